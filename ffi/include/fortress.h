@@ -0,0 +1,54 @@
+/*
+ * C interface to libfortress.  See src/lib.rs for the conventions: handles, UTF-8 strings, JSON, and how errors are reported.
+ */
+#ifndef FORTRESS_H
+#define FORTRESS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+	FORTRESS_OK = 0,
+	FORTRESS_ERROR = 1,
+	FORTRESS_INVALID_ARGUMENT = 2,
+	FORTRESS_WRONG_PASSWORD = 3,
+	FORTRESS_DATABASE_LOCKED = 4,
+	FORTRESS_NOT_FOUND = 5,
+} FortressStatus;
+
+typedef struct FortressDatabase FortressDatabase;
+
+/* Opening, saving and closing */
+FortressDatabase *fortress_database_create(const char *path, const char *username, const char *password);
+FortressDatabase *fortress_database_open(const char *path, const char *password);
+FortressStatus fortress_database_save(FortressDatabase *db);
+void fortress_database_free(FortressDatabase *db);
+
+/* Entries; returned strings are JSON, freed with fortress_string_free */
+char *fortress_database_list_entries(FortressDatabase *db);
+char *fortress_database_get_entry(FortressDatabase *db, const char *id);
+char *fortress_database_search(FortressDatabase *db, const char *query);
+char *fortress_database_add_entry(FortressDatabase *db, const char *fields);
+FortressStatus fortress_database_edit_entry(FortressDatabase *db, const char *id, const char *fields);
+
+/* Sync */
+FortressStatus fortress_database_set_sync_url(FortressDatabase *db, const char *url);
+char *fortress_database_sync(FortressDatabase *db);
+char *fortress_database_estimate_sync_size(FortressDatabase *db);
+
+/* Passwords */
+char *fortress_generate_password(size_t length, bool uppercase, bool lowercase, bool numbers, const char *others);
+
+/* Strings and errors */
+void fortress_string_free(char *string);
+const char *fortress_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
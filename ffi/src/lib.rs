@@ -0,0 +1,455 @@
+// C interface to libfortress, for apps that can't use the Rust API directly (the iOS and Android wrappers).  include/fortress.h
+// declares everything exported here.
+//
+// Conventions:
+//  * An open database is an opaque FortressDatabase handle, made by `fortress_database_create` or `fortress_database_open` and
+//    freed with `fortress_database_free`.  A handle remembers the file it was opened from, and must not be used from two threads
+//    at once.
+//  * Strings passed in are NUL-terminated UTF-8, and only borrowed for the length of the call.  Strings returned are
+//    NUL-terminated UTF-8 that the caller owns and frees with `fortress_string_free`, which wipes them (they can hold passwords).
+//  * Structured data (entries, search results, sync reports) is JSON, in the same shapes the desktop app's frontend gets it.
+//    Entry fields passed in are a JSON object of field names to values.  IDs are hex strings.
+//  * Functions that fail return NULL, or a status other than FORTRESS_OK; `fortress_last_error` then says what went wrong.
+//    Panics are caught before they reach the caller and reported as FORTRESS_ERROR.
+use libfortress::{
+	fortresscrypto::{CryptoError, Zeroizing},
+	Database, Entry, FortressError, HistoryOrderError, ID,
+};
+use serde::Serialize;
+use std::{
+	cell::RefCell,
+	collections::HashMap,
+	ffi::{c_char, CStr, CString},
+	panic::{self, AssertUnwindSafe},
+	path::PathBuf,
+	ptr,
+};
+use url::Url;
+
+
+/// Returned by functions that don't return anything else.  The values are part of the C interface and must not change.
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum FortressStatus {
+	Ok = 0,
+	/// Anything without a status of its own; see `fortress_last_error`.
+	Error = 1,
+	/// An argument was NULL, not valid UTF-8, or not in the expected format.
+	InvalidArgument = 2,
+	/// The password was wrong, or the file isn't a Fortress database.
+	WrongPassword = 3,
+	/// The database file is open in another process.
+	DatabaseLocked = 4,
+	/// No entry has the given ID.
+	NotFound = 5,
+}
+
+
+/// An open database, and the file it's saved to.
+pub struct FortressDatabase {
+	database: Database,
+	path: PathBuf,
+}
+
+
+thread_local! {
+	static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+
+// Why a call failed
+struct Failure {
+	status: FortressStatus,
+	message: String,
+}
+
+impl Failure {
+	fn new<S: Into<String>>(status: FortressStatus, message: S) -> Failure {
+		Failure {
+			status,
+			message: message.into(),
+		}
+	}
+}
+
+impl From<FortressError> for Failure {
+	fn from(err: FortressError) -> Failure {
+		let status = match &err {
+			FortressError::CryptoError(CryptoError::DecryptionError | CryptoError::BadChecksum) => FortressStatus::WrongPassword,
+			FortressError::DatabaseLocked { .. } => FortressStatus::DatabaseLocked,
+			_ => FortressStatus::Error,
+		};
+
+		Failure::new(status, err.to_string())
+	}
+}
+
+impl From<HistoryOrderError> for Failure {
+	fn from(err: HistoryOrderError) -> Failure {
+		FortressError::from(err).into()
+	}
+}
+
+impl From<serde_json::Error> for Failure {
+	fn from(err: serde_json::Error) -> Failure {
+		Failure::new(FortressStatus::InvalidArgument, format!("Malformed JSON: {err}"))
+	}
+}
+
+
+// Runs the body of an exported function, keeping panics from unwinding into the caller.  Failures are recorded for
+// `fortress_last_error`.
+fn call<T>(body: impl FnOnce() -> Result<T, Failure>) -> Result<T, FortressStatus> {
+	let result = panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|_| Err(Failure::new(FortressStatus::Error, "Internal error")));
+
+	result.map_err(|failure| {
+		// Messages never contain NUL bytes, but if one did it'd be better to lose the message than panic
+		LAST_ERROR.with(|last_error| *last_error.borrow_mut() = CString::new(failure.message).ok());
+		failure.status
+	})
+}
+
+
+fn status(result: Result<(), FortressStatus>) -> FortressStatus {
+	result.err().unwrap_or(FortressStatus::Ok)
+}
+
+
+// A string argument
+unsafe fn str_arg<'a>(arg: *const c_char, name: &str) -> Result<&'a str, Failure> {
+	if arg.is_null() {
+		return Err(Failure::new(FortressStatus::InvalidArgument, format!("{name} is NULL")));
+	}
+
+	CStr::from_ptr(arg)
+		.to_str()
+		.map_err(|_| Failure::new(FortressStatus::InvalidArgument, format!("{name} is not valid UTF-8")))
+}
+
+
+unsafe fn id_arg(arg: *const c_char) -> Result<ID, Failure> {
+	ID::from_hex(str_arg(arg, "id")?).ok_or_else(|| Failure::new(FortressStatus::InvalidArgument, "id is not a valid ID"))
+}
+
+
+unsafe fn fields_arg(arg: *const c_char) -> Result<HashMap<String, String>, Failure> {
+	Ok(serde_json::from_str(str_arg(arg, "fields")?)?)
+}
+
+
+unsafe fn handle<'a>(db: *mut FortressDatabase) -> Result<&'a mut FortressDatabase, Failure> {
+	db.as_mut().ok_or_else(|| Failure::new(FortressStatus::InvalidArgument, "db is NULL"))
+}
+
+
+// Hands a string over to the caller, who frees it with `fortress_string_free`
+fn string_out(string: Vec<u8>) -> Result<*mut c_char, Failure> {
+	CString::new(string)
+		.map(CString::into_raw)
+		.map_err(|_| Failure::new(FortressStatus::Error, "String contains a NUL byte"))
+}
+
+
+fn json_out<T: Serialize + ?Sized>(value: &T) -> Result<*mut c_char, Failure> {
+	string_out(serde_json::to_vec(value).map_err(|err| Failure::new(FortressStatus::Error, err.to_string()))?)
+}
+
+
+/// Creates a database for `username` and `password`, and saves it to `path`, which must not exist yet.  Returns NULL on failure.
+///
+/// # Safety
+/// `path`, `username` and `password` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_create(path: *const c_char, username: *const c_char, password: *const c_char) -> *mut FortressDatabase {
+	call(|| {
+		let path = PathBuf::from(str_arg(path, "path")?);
+		if path.exists() {
+			return Err(Failure::new(FortressStatus::InvalidArgument, "A file already exists at path"));
+		}
+
+		let database = Database::new_with_password(str_arg(username, "username")?, str_arg(password, "password")?);
+		database.save_to_path(&path)?;
+
+		Ok(Box::into_raw(Box::new(FortressDatabase { database, path })))
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Opens the database file at `path`.  Returns NULL on failure (FORTRESS_WRONG_PASSWORD if `password` is wrong).
+///
+/// # Safety
+/// `path` and `password` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_open(path: *const c_char, password: *const c_char) -> *mut FortressDatabase {
+	call(|| {
+		let path = PathBuf::from(str_arg(path, "path")?);
+		let database = Database::load_from_path(&path, str_arg(password, "password")?)?;
+
+		Ok(Box::into_raw(Box::new(FortressDatabase { database, path })))
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Saves the database to the file it was created at or opened from.  Edits aren't saved until this is called.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_save(db: *mut FortressDatabase) -> FortressStatus {
+	status(call(|| {
+		let db = handle(db)?;
+		Ok(db.database.save_incremental(&db.path)?)
+	}))
+}
+
+
+/// Closes the database without saving it.  Does nothing if `db` is NULL.
+///
+/// # Safety
+/// `db` must be NULL or a handle that hasn't been freed, and mustn't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_free(db: *mut FortressDatabase) {
+	if !db.is_null() {
+		drop(Box::from_raw(db));
+	}
+}
+
+
+/// All entries that aren't in the trash, as a JSON array.  Returns NULL on failure.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_list_entries(db: *mut FortressDatabase) -> *mut c_char {
+	call(|| {
+		let database = &handle(db)?.database;
+		let trashed = database.list_trash_recursive();
+
+		json_out(&database.list_entries().filter(|entry| !trashed.contains(entry.get_id())).collect::<Vec<_>>())
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Entry `id` as JSON.  Returns NULL on failure (FORTRESS_NOT_FOUND if there's no such entry).
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed, and `id` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_get_entry(db: *mut FortressDatabase, id: *const c_char) -> *mut c_char {
+	call(|| {
+		let database = &handle(db)?.database;
+		let entry = database
+			.get_entry_by_id(&id_arg(id)?)
+			.ok_or_else(|| Failure::new(FortressStatus::NotFound, "No entry with that ID"))?;
+
+		json_out(entry)
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Searches entries (see libfortress's search module for the query syntax), returning a JSON array of hits, best match first.
+/// Returns NULL on failure.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed, and `query` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_search(db: *mut FortressDatabase, query: *const c_char) -> *mut c_char {
+	call(|| {
+		let database = &handle(db)?.database;
+		json_out(&database.search(str_arg(query, "query")?))
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Adds an entry with `fields` (a JSON object of field names to values) to the root directory, returning its ID.  Returns NULL
+/// on failure.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed, and `fields` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_add_entry(db: *mut FortressDatabase, fields: *const c_char) -> *mut c_char {
+	call(|| {
+		let database = &mut handle(db)?.database;
+		let fields = fields_arg(fields)?;
+
+		let mut entry = Entry::new();
+		let id = *entry.get_id();
+		entry.try_edit(entry.new_edit(fields))?;
+		database.add_entry(entry);
+
+		string_out(id.to_hex().into_bytes())
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Edits entry `id`, setting the fields in `fields` (a JSON object of field names to values); other fields are left alone.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed, and `id` and `fields` NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_edit_entry(db: *mut FortressDatabase, id: *const c_char, fields: *const c_char) -> FortressStatus {
+	status(call(|| {
+		let database = &mut handle(db)?.database;
+		let id = id_arg(id)?;
+		let fields = fields_arg(fields)?;
+
+		let entry = database
+			.get_entry_by_id_mut(&id)
+			.ok_or_else(|| Failure::new(FortressStatus::NotFound, "No entry with that ID"))?;
+		entry.try_edit(entry.new_edit(fields))?;
+
+		Ok(())
+	}))
+}
+
+
+/// Sets the sync server's URL, or turns sync off if `url` is NULL.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed, and `url` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_set_sync_url(db: *mut FortressDatabase, url: *const c_char) -> FortressStatus {
+	status(call(|| {
+		let database = &mut handle(db)?.database;
+		let url = match url.is_null() {
+			true => None,
+			false => Some(Url::parse(str_arg(url, "url")?).map_err(|err| Failure::new(FortressStatus::InvalidArgument, format!("Bad URL: {err}")))?),
+		};
+
+		database.set_sync_url(url);
+		Ok(())
+	}))
+}
+
+
+/// Syncs with the sync server, returning a JSON report of what changed.  Returns NULL on failure.  Save the database afterwards.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_sync(db: *mut FortressDatabase) -> *mut c_char {
+	call(|| {
+		let database = &mut handle(db)?.database;
+		json_out(&database.sync()?)
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Predicts what the next sync would transfer, as JSON (see `Database::estimate_sync_size`), fetching only the server's object
+/// list.  Useful on metered connections.  Returns NULL on failure.
+///
+/// # Safety
+/// `db` must be a handle that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_database_estimate_sync_size(db: *mut FortressDatabase) -> *mut c_char {
+	call(|| {
+		let database = &handle(db)?.database;
+		json_out(&database.estimate_sync_size()?)
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+/// A random password of `length` characters drawn from the chosen character sets and the characters in `others` (which may be
+/// NULL).  Returns NULL on failure.
+///
+/// # Safety
+/// `others` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_generate_password(length: usize, uppercase: bool, lowercase: bool, numbers: bool, others: *const c_char) -> *mut c_char {
+	call(|| {
+		let others = if others.is_null() { "" } else { str_arg(others, "others")? };
+		string_out(libfortress::random_string(length, uppercase, lowercase, numbers, others).into_bytes())
+	})
+	.unwrap_or(ptr::null_mut())
+}
+
+
+/// Wipes and frees a string returned by one of these functions.  Does nothing if `string` is NULL.
+///
+/// # Safety
+/// `string` must be NULL or a string returned by one of these functions that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn fortress_string_free(string: *mut c_char) {
+	if !string.is_null() {
+		drop(Zeroizing::new(CString::from_raw(string).into_bytes()));
+	}
+}
+
+
+/// Why the last call on this thread that failed did, or NULL if none has.  The string belongs to the library and stays valid
+/// until the next failure on this thread; don't free it.
+#[no_mangle]
+pub extern "C" fn fortress_last_error() -> *const c_char {
+	LAST_ERROR.with(|last_error| last_error.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tempfile::tempdir;
+
+	// Takes ownership of a string returned by the library
+	unsafe fn take(string: *mut c_char) -> String {
+		assert!(!string.is_null(), "{:?}", CStr::from_ptr(fortress_last_error()));
+		let owned = CStr::from_ptr(string).to_str().unwrap().to_owned();
+		fortress_string_free(string);
+		owned
+	}
+
+	fn c(string: &str) -> CString {
+		CString::new(string).unwrap()
+	}
+
+	#[test]
+	fn ffi() {
+		let tmp_dir = tempdir().unwrap();
+		let path = c(tmp_dir.path().join("test.fortressdb").to_str().unwrap());
+
+		unsafe {
+			let db = fortress_database_create(path.as_ptr(), c("username").as_ptr(), c("password").as_ptr());
+			assert!(!db.is_null());
+			assert!(fortress_database_create(path.as_ptr(), c("username").as_ptr(), c("password").as_ptr()).is_null());
+
+			let id = take(fortress_database_add_entry(db, c(r#"{"title": "Bank", "username": "me"}"#).as_ptr()));
+			let id = c(&id);
+			assert_eq!(
+				fortress_database_edit_entry(db, id.as_ptr(), c(r#"{"password": "hunter2"}"#).as_ptr()),
+				FortressStatus::Ok
+			);
+			assert_eq!(
+				fortress_database_edit_entry(db, id.as_ptr(), c("not json").as_ptr()),
+				FortressStatus::InvalidArgument
+			);
+			assert_eq!(fortress_database_save(db), FortressStatus::Ok);
+			fortress_database_free(db);
+
+			assert!(fortress_database_open(path.as_ptr(), c("wrong").as_ptr()).is_null());
+			assert!(CStr::from_ptr(fortress_last_error()).to_str().unwrap().contains("Cryptography"));
+			let db = fortress_database_open(path.as_ptr(), c("password").as_ptr());
+			assert!(!db.is_null());
+
+			let entries: serde_json::Value = serde_json::from_str(&take(fortress_database_list_entries(db))).unwrap();
+			assert_eq!(entries.as_array().unwrap().len(), 1);
+			let entry: serde_json::Value = serde_json::from_str(&take(fortress_database_get_entry(db, id.as_ptr()))).unwrap();
+			assert_eq!(entry, entries[0]);
+			assert!(take(fortress_database_search(db, c("bank").as_ptr())).contains(id.to_str().unwrap()));
+			assert!(fortress_database_get_entry(db, c(&"00".repeat(32)).as_ptr()).is_null());
+			assert!(fortress_database_get_entry(ptr::null_mut(), id.as_ptr()).is_null());
+
+			assert_eq!(fortress_database_set_sync_url(db, c("not a url").as_ptr()), FortressStatus::InvalidArgument);
+			assert_eq!(fortress_database_set_sync_url(db, ptr::null()), FortressStatus::Ok);
+			fortress_database_free(db);
+
+			assert_eq!(take(fortress_generate_password(16, false, false, true, ptr::null())).len(), 16);
+			assert_eq!(take(fortress_generate_password(16, false, false, false, ptr::null())), "");
+		}
+	}
+}
@@ -0,0 +1,7 @@
+// Prints the reference test vectors (see fortresscrypto::test_vectors) as JSON.
+//
+// Run with: cargo run -p fortresscrypto --example generate_test_vectors > fortresscrypto/test-vectors.json
+fn main() {
+	let vectors = fortresscrypto::test_vectors::generate();
+	println!("{}", serde_json::to_string_pretty(&vectors).expect("internal error"));
+}
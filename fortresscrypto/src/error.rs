@@ -14,6 +14,14 @@ pub enum CryptoError {
 	BadChecksum,
 	/// Unsupported version.
 	UnsupportedVersion,
+	/// The file needs a hardware key's response, and none was given.
+	HardwareKeyRequired,
+	/// A signature didn't match the message or key.
+	BadSignature,
+	/// Files with keyslots can't also need a hardware key (see the keyslot module).
+	HardwareKeyWithKeyslots,
+	/// The file already has MAX_KEYSLOTS keyslots.
+	TooManyKeyslots,
 }
 
 impl From<std::io::Error> for CryptoError {
@@ -33,6 +41,10 @@ impl std::fmt::Display for CryptoError {
 			CryptoError::IOError(e) => write!(f, "IO error: {e}"),
 			CryptoError::BadChecksum => write!(f, "Bad checksum"),
 			CryptoError::UnsupportedVersion => write!(f, "Unsupported version"),
+			CryptoError::HardwareKeyRequired => write!(f, "A hardware key is required"),
+			CryptoError::BadSignature => write!(f, "Bad signature"),
+			CryptoError::HardwareKeyWithKeyslots => write!(f, "Files with more than one password can't also need a hardware key"),
+			CryptoError::TooManyKeyslots => write!(f, "The file already has as many passwords as it can"),
 		}
 	}
 }
@@ -0,0 +1,308 @@
+// Files that more than one password can open, like LUKS's keyslots.  Such a file is encrypted with random keys instead of keys
+// derived from a password, and its header has a keyslot for each password: the random keys, SivEncrypted with the keys derived
+// from that password using the slot's own KDF parameters (which are the AAD).  Opening the file derives keys from the password
+// with each slot's parameters in turn, until one of them decrypts its slot.
+//
+// A file gets keyslots when a second password is added (`FileKeySuite::add_keyslot`).  Its current password becomes keyslot 0,
+// wrapping new random keys with the keys already derived from it, so the password itself isn't needed.  Keyslot 0 is the file's
+// own password, the one `FileKeySuite::rederive` changes; the others can only be added and removed.  Files with keyslots can't
+// also need a hardware key.
+//
+// Header, after "fortress2ks\0":
+//   keyslot count (u8)
+//   for each keyslot: scrypt log_n (u8), r (u32 little endian), p (u32 little endian), salt (32 bytes),
+//                     SIV || encrypted keys (32 + 256 bytes)
+use crate::{siv::SivEncryptionKeys, CryptoError, FileKdfParameters, FileKeySuite, Protected, SIV};
+use byteorder::{LittleEndian, ReadBytesExt};
+use rand::{rngs::OsRng, Rng};
+use std::io::{Cursor, Read};
+use zeroize::Zeroizing;
+
+
+/// The most keyslots a file can have.
+pub const MAX_KEYSLOTS: usize = 8;
+
+pub(crate) const KEYSLOT_HEADER: &[u8] = b"fortress2ks\0";
+
+const WRAPPED_KEYS_LEN: usize = 32 + 256;
+
+
+/// One of the passwords that can open a file with keyslots (see the keyslot module).
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Keyslot {
+	kdf_params: FileKdfParameters,
+	/// SIV || the file's keys, encrypted with the keys derived from the slot's password
+	wrapped_keys: Vec<u8>,
+}
+
+impl Keyslot {
+	fn wrap(file_keys: &SivEncryptionKeys, kdf_params: FileKdfParameters, password_keys: &SivEncryptionKeys) -> Keyslot {
+		let (siv, ciphertext) = password_keys.encrypt(&params_bytes(&kdf_params), &file_keys.to_bytes());
+
+		Keyslot {
+			kdf_params,
+			wrapped_keys: [siv.as_ref(), ciphertext.as_slice()].concat(),
+		}
+	}
+
+	fn unwrap(&self, password_keys: &SivEncryptionKeys) -> Option<SivEncryptionKeys> {
+		let (siv, ciphertext) = self.wrapped_keys.split_at(32);
+		let raw_keys = password_keys.decrypt(&params_bytes(&self.kdf_params), &SIV::from_slice(siv)?, ciphertext)?;
+
+		SivEncryptionKeys::from_slice(&raw_keys)
+	}
+
+	pub fn get_kdf_params(&self) -> &FileKdfParameters {
+		&self.kdf_params
+	}
+}
+
+
+impl FileKeySuite {
+	/// The same keys, but for a file that `password` can open as well as the passwords that already can, with the KDF
+	/// parameters `params` (see the keyslot module).  Also returns the new keyslot's number.  Fails with
+	/// CryptoError::HardwareKeyWithKeyslots if the file needs a hardware key or `params` has a hardware challenge, and
+	/// CryptoError::TooManyKeyslots if it already has MAX_KEYSLOTS.
+	pub fn add_keyslot(&self, password: &[u8], params: &FileKdfParameters) -> Result<(FileKeySuite, usize), CryptoError> {
+		if self.kdf_params.hardware_challenge.is_some() || params.hardware_challenge.is_some() {
+			return Err(CryptoError::HardwareKeyWithKeyslots);
+		}
+
+		let mut keys = if self.keyslots.is_empty() { self.with_random_keys() } else { self.clone() };
+
+		if keys.keyslots.len() >= MAX_KEYSLOTS {
+			return Err(CryptoError::TooManyKeyslots);
+		}
+
+		let password_keys = FileKeySuite::derive(password, params)?;
+		keys.keyslots
+			.push(Keyslot::wrap(&keys.encryption_keys, params.clone(), &password_keys.encryption_keys));
+
+		let slot = keys.keyslots.len() - 1;
+		Ok((keys, slot))
+	}
+
+	/// The same keys, without keyslot `slot`.  Later keyslots move down by one.  None if there's no such keyslot, or it's
+	/// keyslot 0, the file's own password.
+	pub fn remove_keyslot(&self, slot: usize) -> Option<FileKeySuite> {
+		if slot == 0 || slot >= self.keyslots.len() {
+			return None;
+		}
+
+		let mut keys = self.clone();
+		keys.keyslots.remove(slot);
+		keys.keyslot = match self.keyslot {
+			keyslot if keyslot > slot => keyslot - 1,
+			keyslot if keyslot == slot => 0,
+			keyslot => keyslot,
+		};
+
+		Some(keys)
+	}
+
+	/// The file's keyslots (see the keyslot module), or nothing if its keys are derived from its one password.
+	pub fn get_keyslots(&self) -> &[Keyslot] {
+		&self.keyslots
+	}
+
+	/// The keyslot whose password opened the keys.  0 for files without keyslots, and for keys that weren't opened with a
+	/// password (e.g. `from_bytes`).
+	pub fn get_keyslot(&self) -> usize {
+		self.keyslot
+	}
+
+	// The same file, but encrypted with new random keys, held in keyslot 0 for the current password
+	fn with_random_keys(&self) -> FileKeySuite {
+		let raw_keys = Zeroizing::new((0..256).map(|_| OsRng.gen()).collect::<Vec<u8>>());
+		let file_keys = SivEncryptionKeys::from_slice(&raw_keys).expect("internal error");
+		let keyslot = Keyslot::wrap(&file_keys, self.kdf_params.clone(), &self.encryption_keys);
+
+		FileKeySuite {
+			encryption_keys: Protected::new(file_keys),
+			kdf_params: self.kdf_params.clone(),
+			hardware_key: None,
+			keyslots: vec![keyslot],
+			keyslot: 0,
+		}
+	}
+
+	/// Keys for a file with `keyslots`, which `password` opens.  Fails with CryptoError::DecryptionError if it doesn't open any
+	/// of them.
+	pub(crate) fn open_keyslots(keyslots: Vec<Keyslot>, password: &[u8]) -> Result<FileKeySuite, CryptoError> {
+		for (i, keyslot) in keyslots.iter().enumerate() {
+			let password_keys = FileKeySuite::derive(password, &keyslot.kdf_params)?;
+
+			if let Some(file_keys) = keyslot.unwrap(&password_keys.encryption_keys) {
+				return Ok(FileKeySuite::with_keyslots(file_keys, keyslots, i));
+			}
+		}
+
+		Err(CryptoError::DecryptionError)
+	}
+
+	/// Keys for a file with `keyslots`, opened with keyslot `keyslot`'s password.
+	pub(crate) fn with_keyslots(file_keys: SivEncryptionKeys, keyslots: Vec<Keyslot>, keyslot: usize) -> FileKeySuite {
+		FileKeySuite {
+			encryption_keys: Protected::new(file_keys),
+			kdf_params: keyslots[0].kdf_params.clone(),
+			hardware_key: None,
+			keyslots,
+			keyslot,
+		}
+	}
+
+	/// Replaces keyslot 0 with one for `password_keys`, derived from a new password (see `rederive`).
+	pub(crate) fn rewrap_keyslot(&self, password_keys: FileKeySuite) -> FileKeySuite {
+		let mut keys = self.clone();
+		keys.keyslots[0] = Keyslot::wrap(&self.encryption_keys, password_keys.kdf_params.clone(), &password_keys.encryption_keys);
+		keys.kdf_params = password_keys.kdf_params;
+		keys.keyslot = 0;
+		keys
+	}
+}
+
+
+/// The header for a file with `keyslots`.
+pub(crate) fn build_keyslot_header(keyslots: &[Keyslot]) -> Vec<u8> {
+	let mut result = KEYSLOT_HEADER.to_vec();
+
+	result.push(u8::try_from(keyslots.len()).expect("internal error"));
+	for keyslot in keyslots {
+		result.extend_from_slice(&params_bytes(&keyslot.kdf_params));
+		result.extend_from_slice(&keyslot.wrapped_keys);
+	}
+
+	result
+}
+
+
+/// Parses the keyslots from the header of a file with keyslots, after its header string.
+pub(crate) fn parse_keyslots(reader: &mut Cursor<&[u8]>) -> Result<Vec<Keyslot>, CryptoError> {
+	let count = reader.read_u8()? as usize;
+
+	if count == 0 || count > MAX_KEYSLOTS {
+		return Err(CryptoError::DecryptionError);
+	}
+
+	(0..count)
+		.map(|_| {
+			let log_n = reader.read_u8()?;
+			let r = reader.read_u32::<LittleEndian>()?;
+			let p = reader.read_u32::<LittleEndian>()?;
+			let mut salt = [0u8; 32];
+			reader.read_exact(&mut salt)?;
+			let mut wrapped_keys = vec![0u8; WRAPPED_KEYS_LEN];
+			reader.read_exact(&mut wrapped_keys)?;
+
+			Ok(Keyslot {
+				kdf_params: FileKdfParameters {
+					log_n,
+					r,
+					p,
+					salt,
+					hardware_challenge: None,
+				},
+				wrapped_keys,
+			})
+		})
+		.collect()
+}
+
+
+/// The scrypt parameters, as they're written in file headers.
+pub(crate) fn params_bytes(params: &FileKdfParameters) -> Vec<u8> {
+	let mut result = Vec::new();
+	result.extend_from_slice(&params.log_n.to_le_bytes());
+	result.extend_from_slice(&params.r.to_le_bytes());
+	result.extend_from_slice(&params.p.to_le_bytes());
+	result.extend_from_slice(&params.salt);
+	result
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::MAX_KEYSLOTS;
+	use crate::{decrypt_from_file, decrypt_from_file_with_keys, encrypt_to_file, CryptoError, FileKdfParameters, FileKeySuite};
+	use rand::{rngs::OsRng, Rng};
+	use std::io::Cursor;
+
+	fn encrypt(keys: &FileKeySuite) -> Vec<u8> {
+		let mut file = Vec::new();
+		encrypt_to_file(&mut file, b"payload", keys).unwrap();
+		file
+	}
+
+	#[test]
+	fn keyslots() {
+		let keys = FileKeySuite::derive(b"alice", &Default::default()).unwrap();
+		let bob_params = FileKdfParameters {
+			log_n: 9,
+			..Default::default()
+		};
+		let (shared, slot) = keys.add_keyslot(b"bob", &bob_params).unwrap();
+		assert_eq!(slot, 1);
+		assert_ne!(shared.encryption_keys, keys.encryption_keys);
+		assert_eq!(shared.get_kdf_params(), keys.get_kdf_params());
+		assert_eq!(shared.get_keyslots()[1].get_kdf_params(), &bob_params);
+
+		// Either password opens the file, with the same keys
+		let file = encrypt(&shared);
+		assert!(file.starts_with(b"fortress2ks\0"));
+		let (plaintext, alice_keys) = decrypt_from_file(&mut Cursor::new(&file), b"alice").unwrap();
+		let (_, bob_keys) = decrypt_from_file(&mut Cursor::new(&file), b"bob").unwrap();
+		assert_eq!(&plaintext[..], b"payload");
+		assert_eq!((alice_keys.get_keyslot(), bob_keys.get_keyslot()), (0, 1));
+		assert_eq!(alice_keys, shared);
+		assert_eq!(bob_keys.encryption_keys, shared.encryption_keys);
+		assert!(matches!(decrypt_from_file(&mut Cursor::new(&file), b"eve"), Err(CryptoError::DecryptionError)));
+
+		// Stored keys only open the file while it has the same keyslots
+		let stored = FileKeySuite::from_bytes(&bob_keys.to_bytes()).unwrap();
+		assert_eq!(stored.encryption_keys, shared.encryption_keys);
+		assert_eq!(&decrypt_from_file_with_keys(&mut Cursor::new(&file), &stored).unwrap()[..], b"payload");
+		let (carol, _) = shared.add_keyslot(b"carol", &Default::default()).unwrap();
+		assert_eq!(carol.encryption_keys, shared.encryption_keys);
+		assert!(decrypt_from_file_with_keys(&mut Cursor::new(encrypt(&carol)), &stored).is_err());
+
+		// Changing keyslot 0's password leaves the others alone
+		let changed = bob_keys.rederive(b"alice2", &Default::default()).unwrap();
+		let file = encrypt(&changed);
+		assert!(decrypt_from_file(&mut Cursor::new(&file), b"alice").is_err());
+		assert_eq!(decrypt_from_file(&mut Cursor::new(&file), b"alice2").unwrap().1, changed);
+		assert!(decrypt_from_file(&mut Cursor::new(&file), b"bob").is_ok());
+
+		// Removing
+		assert!(changed.remove_keyslot(0).is_none());
+		assert!(changed.remove_keyslot(2).is_none());
+		let removed = changed.remove_keyslot(1).unwrap();
+		assert_eq!(removed.get_keyslots().len(), 1);
+		let file = encrypt(&removed);
+		assert!(decrypt_from_file(&mut Cursor::new(&file), b"bob").is_err());
+		assert!(decrypt_from_file(&mut Cursor::new(&file), b"alice2").is_ok());
+	}
+
+	#[test]
+	fn keyslot_limits() {
+		let mut keys = FileKeySuite::derive(b"password", &Default::default()).unwrap();
+		for i in 1..MAX_KEYSLOTS {
+			keys = keys.add_keyslot(format!("password {i}").as_bytes(), &Default::default()).unwrap().0;
+		}
+		assert!(matches!(
+			keys.add_keyslot(b"one too many", &Default::default()),
+			Err(CryptoError::TooManyKeyslots)
+		));
+
+		let hardware_params = FileKdfParameters {
+			hardware_challenge: Some(OsRng.gen()),
+			..Default::default()
+		};
+		let response = |challenge: &[u8]| Some(challenge.to_vec().into());
+		let hardware_keys = FileKeySuite::derive_with_hardware_key(b"password", &hardware_params, &response).unwrap();
+		assert!(matches!(
+			hardware_keys.add_keyslot(b"bob", &Default::default()),
+			Err(CryptoError::HardwareKeyWithKeyslots)
+		));
+		assert!(matches!(keys.add_keyslot(b"bob", &hardware_params), Err(CryptoError::HardwareKeyWithKeyslots)));
+	}
+}
@@ -0,0 +1,125 @@
+// Reading of the legacy fortress1-scrypt-chacha20 file format (see ENCRYPTION.md).
+// Only decryption is supported; fortress1 files should be converted and re-saved in the current format.
+use crate::CryptoError;
+use byteorder::{LittleEndian, ReadBytesExt};
+use chacha20::{
+	cipher::{KeyIvInit, StreamCipher},
+	ChaCha20Legacy,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{Cursor, Read};
+use zeroize::Zeroizing;
+
+
+const LEGACY_HEADER: &[u8] = b"fortress1-scrypt-chacha20\0";
+
+
+/// Returns true if `data` (the start of a file is enough) looks like a legacy fortress1 file.
+pub fn is_legacy_file(data: &[u8]) -> bool {
+	data.starts_with(LEGACY_HEADER)
+}
+
+
+/// Decrypts a legacy fortress1 file.  Returns the plaintext.
+pub fn decrypt_legacy_from_file<R: Read>(reader: &mut R, password: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+	let mut filedata = Vec::new();
+	reader.read_to_end(&mut filedata)?;
+
+	if !is_legacy_file(&filedata) {
+		return Err(CryptoError::UnsupportedVersion);
+	}
+
+	// Parse header
+	let mut reader = Cursor::new(&filedata[LEGACY_HEADER.len()..]);
+	let log_n = reader.read_u8()?;
+	let r = reader.read_u32::<LittleEndian>()?;
+	let p = reader.read_u32::<LittleEndian>()?;
+	let mut salt = [0u8; 32];
+	reader.read_exact(&mut salt)?;
+	let header_len = LEGACY_HEADER.len() + reader.position() as usize;
+
+	if filedata.len() < header_len + 32 {
+		return Err(CryptoError::TruncatedData);
+	}
+
+	let (authenticated, mac) = filedata.split_at(filedata.len() - 32);
+	let ciphertext = &authenticated[header_len..];
+
+	// Derive keys
+	let mut raw_keys = Zeroizing::new([0u8; 64]);
+	let scrypt_params = scrypt::Params::new(log_n, r, p, 32).map_err(|_| CryptoError::BadScryptParameters)?;
+	scrypt::scrypt(password, &salt, &scrypt_params, &mut raw_keys[..]).expect("internal error");
+	let (cipher_key, mac_key) = raw_keys.split_at(32);
+
+	// Verify mac
+	let mut hmac = Hmac::<Sha256>::new_from_slice(mac_key).expect("unexpected");
+	hmac.update(authenticated);
+	hmac.verify_slice(mac).map_err(|_| CryptoError::DecryptionError)?;
+
+	// Decrypt.  Every save used a fresh salt, and thus fresh keys, so the nonce is always zero.
+	let mut plaintext = Zeroizing::new(ciphertext.to_vec());
+	let mut cipher = ChaCha20Legacy::new_from_slices(cipher_key, &[0u8; 8]).expect("unexpected");
+	cipher.apply_keystream(&mut plaintext);
+
+	Ok(plaintext)
+}
+
+
+#[cfg(test)]
+pub(crate) fn encrypt_legacy_to_vec(data: &[u8], password: &[u8], log_n: u8, salt: [u8; 32]) -> Vec<u8> {
+	let mut raw_keys = [0u8; 64];
+	let scrypt_params = scrypt::Params::new(log_n, 8, 1, 32).unwrap();
+	scrypt::scrypt(password, &salt, &scrypt_params, &mut raw_keys[..]).unwrap();
+	let (cipher_key, mac_key) = raw_keys.split_at(32);
+
+	let mut result = LEGACY_HEADER.to_vec();
+	result.push(log_n);
+	result.extend_from_slice(&8u32.to_le_bytes());
+	result.extend_from_slice(&1u32.to_le_bytes());
+	result.extend_from_slice(&salt);
+
+	let mut ciphertext = data.to_vec();
+	ChaCha20Legacy::new_from_slices(cipher_key, &[0u8; 8]).unwrap().apply_keystream(&mut ciphertext);
+	result.extend_from_slice(&ciphertext);
+
+	let mut hmac = Hmac::<Sha256>::new_from_slice(mac_key).unwrap();
+	hmac.update(&result);
+	result.extend_from_slice(&hmac.finalize().into_bytes());
+	result
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{decrypt_legacy_from_file, encrypt_legacy_to_vec, is_legacy_file};
+	use crate::CryptoError;
+	use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+	use std::io::Cursor;
+
+	#[test]
+	fn test_decrypt_legacy() {
+		let plaintext = (0..1034).map(|_| OsRng.gen()).collect::<Vec<u8>>();
+		let encrypted = encrypt_legacy_to_vec(&plaintext, b"password", 8, OsRng.gen());
+
+		assert!(is_legacy_file(&encrypted));
+		assert!(!is_legacy_file(b"fortress2\0"));
+		assert_eq!(*decrypt_legacy_from_file(&mut Cursor::new(&encrypted), b"password").unwrap(), plaintext);
+
+		// Wrong password
+		assert!(matches!(
+			decrypt_legacy_from_file(&mut Cursor::new(&encrypted), b"wrong"),
+			Err(CryptoError::DecryptionError)
+		));
+
+		// Tampering with the salt, ciphertext, or mac is detected
+		let mut tampered = encrypted.clone();
+		*tampered[26 + 9..].choose_mut(&mut OsRng).unwrap() ^= 1;
+		assert!(decrypt_legacy_from_file(&mut Cursor::new(&tampered), b"password").is_err());
+
+		// Truncation
+		for len in [0, 26, 40, 26 + 9 + 32 + 31] {
+			assert!(decrypt_legacy_from_file(&mut Cursor::new(&encrypted[..len]), b"password").is_err());
+		}
+	}
+}
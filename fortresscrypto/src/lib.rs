@@ -1,26 +1,49 @@
+// The older tests predate some of clippy's style lints
+#![cfg_attr(test, allow(clippy::clone_on_copy, clippy::needless_borrow, clippy::option_map_unit_fn))]
+
 #[macro_use]
 mod newtype_macros;
 mod error;
+mod keyslot;
+mod legacy;
+mod memlock;
+mod share;
+mod signing;
 mod siv;
+pub mod test_vectors;
+mod totp;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 pub use error::CryptoError;
 use hmac::{digest::CtOutput, Hmac, Mac};
+use keyslot::{build_keyslot_header, params_bytes, parse_keyslots, KEYSLOT_HEADER};
+pub use keyslot::{Keyslot, MAX_KEYSLOTS};
+pub use legacy::{decrypt_legacy_from_file, is_legacy_file};
+pub use memlock::Protected;
 use rand::{rngs::OsRng, Rng};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha512};
+pub use share::{decrypt_share, encrypt_share, SharePublicKey, ShareSecretKey};
+pub use signing::{Signature, SigningKey, VerifyingKey};
 use siv::SivEncryptionKeys;
 pub use siv::SIV;
 use std::{
 	io::{self, BufRead, Cursor, Read, Write},
 	str,
+	time::{Duration, Instant},
 };
+pub use totp::{totp, TotpAlgorithm, TOTP_MAX_DIGITS};
+pub use zeroize::{Zeroize, Zeroizing};
 
 
+// Secret newtypes (Key, LoginKey, and the SIV module's HmacKey) are zeroized when dropped, which covers all key material held by
+// NetworkKeySuite and FileKeySuite.  Decrypted plaintext is returned wrapped in Zeroizing, and intermediate buffers holding raw
+// key material are zeroized before they go out of scope.
 new_type!(secret Key(32););
 new_type!(public MacTag(32););
 new_type!(secret LoginKey(32););
 new_type!(public LoginId(32););
+new_type!(public DerivationId(32););
 
 
 // For cloud storage, we want the user's password to be _extremely_ hard to crack.
@@ -41,6 +64,9 @@ const NETWORK_SCRYPT_P: u32 = 1;
 #[cfg(not(debug_assertions))]
 const NETWORK_SCRYPT_P: u32 = 128;
 
+// Derivation time is estimated by timing a derivation with N reduced by this many powers of two (see estimate_scrypt_time)
+const ESTIMATE_LOG_N_REDUCTION: u8 = 3;
+
 // Fixed key used to derive salt from username
 const NETWORK_USERNAME_SALT: Key = Key([
 	0x51, 0xc3, 0xd0, 0x0b, 0xde, 0x2b, 0x32, 0x58, 0xca, 0x17, 0x92, 0x72, 0x15, 0x3e, 0xd0, 0xfd, 0x2e, 0x47, 0x56, 0x04, 0xda, 0x14, 0xba, 0xc2, 0xb7, 0xa3,
@@ -55,6 +81,13 @@ const LOGIN_USERNAME_SALT: Key = Key([
 ]);
 
 
+// Mixed into the network keys to derive the signing key
+const SIGNING_KEY_CONTEXT: &[u8] = b"fortress-signing-key1";
+
+// Mixed into the login key to derive the read-only login key
+const READONLY_LOGIN_KEY_CONTEXT: &[u8] = b"fortress-readonly-login-key1";
+
+
 pub fn hash_username_for_login(username: &[u8]) -> LoginId {
 	LoginId::from_slice(&hmac_512(&LOGIN_USERNAME_SALT, username).into_bytes()[..32]).expect("internal error")
 }
@@ -68,26 +101,55 @@ pub struct EncryptedObject {
 
 #[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkKeySuite {
-	encryption_keys: SivEncryptionKeys,
-	pub login_key: LoginKey,
+	encryption_keys: Protected<SivEncryptionKeys>,
+	pub login_key: Protected<LoginKey>,
 }
 
 impl NetworkKeySuite {
 	/// Derive from username and password using a very aggressive KDF.
 	/// This function call will take a long time to finish (5 minutes or more).
 	pub fn derive(username: &[u8], password: &[u8]) -> NetworkKeySuite {
+		NetworkKeySuite::derive_with_params(username, password, NETWORK_SCRYPT_LOG_N, NETWORK_SCRYPT_R, NETWORK_SCRYPT_P)
+	}
+
+	/// Roughly how long `derive` takes on this machine, for progress reporting.  Measured by running a much smaller derivation,
+	/// which takes a fraction of a second.
+	pub fn estimate_derive_time() -> Duration {
+		estimate_scrypt_time(NETWORK_SCRYPT_LOG_N, NETWORK_SCRYPT_R, NETWORK_SCRYPT_P)
+	}
+
+	/// Identifies `derive(username, password)`, including the scrypt parameters it uses, so its result can be looked up in a cache
+	/// of derived keys without storing the password.  Keyed with the cache's own random `salt`, so IDs can't be matched against
+	/// any other cache's.
+	/// NOTE: This is fast to compute, so anyone who has the salt and an ID can test password guesses against it far more quickly
+	/// than against the keys.  Keep caches of IDs in memory only.
+	pub fn derivation_id(salt: &Key, username: &[u8], password: &[u8]) -> DerivationId {
+		let mut data = Zeroizing::new(vec![NETWORK_SCRYPT_LOG_N]);
+		data.extend_from_slice(&NETWORK_SCRYPT_R.to_le_bytes());
+		data.extend_from_slice(&NETWORK_SCRYPT_P.to_le_bytes());
+
+		for part in [username, password] {
+			data.extend_from_slice(&(part.len() as u64).to_le_bytes());
+			data.extend_from_slice(part);
+		}
+
+		DerivationId::from_slice(&hmac_512(salt, &data).into_bytes()[..32]).expect("internal error")
+	}
+
+	// Used directly by the test vectors, which can't afford the real parameters
+	fn derive_with_params(username: &[u8], password: &[u8], log_n: u8, r: u32, p: u32) -> NetworkKeySuite {
 		// Hide username behind hmac so salt is unique to this application.
 		let salt = &hmac_512(&NETWORK_USERNAME_SALT, username).into_bytes()[..32];
-		let mut raw_keys = [0u8; 256 + 32];
-		let scrypt_params = scrypt::Params::new(NETWORK_SCRYPT_LOG_N, NETWORK_SCRYPT_R, NETWORK_SCRYPT_P, 32).expect("scrypt parameters should be valid");
-		scrypt::scrypt(password, salt, &scrypt_params, &mut raw_keys).expect("internal error");
+		let mut raw_keys = Zeroizing::new([0u8; 256 + 32]);
+		let scrypt_params = scrypt::Params::new(log_n, r, p, 32).expect("scrypt parameters should be valid");
+		scrypt::scrypt(password, salt, &scrypt_params, &mut raw_keys[..]).expect("internal error");
 
 		let (siv_keys, raw_keys) = raw_keys.split_at(256);
 		let (login_key, _) = raw_keys.split_at(32);
 
 		NetworkKeySuite {
-			encryption_keys: SivEncryptionKeys::from_slice(siv_keys).expect("internal error"),
-			login_key: LoginKey::from_slice(login_key).expect("internal error"),
+			encryption_keys: Protected::new(SivEncryptionKeys::from_slice(siv_keys).expect("internal error")),
+			login_key: Protected::new(LoginKey::from_slice(login_key).expect("internal error")),
 		}
 	}
 
@@ -97,39 +159,187 @@ impl NetworkKeySuite {
 	}
 
 	// Deterministically decrypt payload, after validating mac.  Returns plaintext.
-	pub fn decrypt_object(&self, id: &[u8], encrypted_object: &EncryptedObject) -> Result<Vec<u8>, CryptoError> {
+	pub fn decrypt_object(&self, id: &[u8], encrypted_object: &EncryptedObject) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
 		self.encryption_keys
 			.decrypt(id, &encrypted_object.siv, &encrypted_object.ciphertext)
 			.ok_or(CryptoError::DecryptionError)
 	}
+
+	/// An Ed25519 key for signing the database's contents (see the signing module).  Derived from the encryption keys, so it's
+	/// the same for everyone with this username and password: HMAC-SHA-512 (encryption keys, "fortress-signing-key1")
+	/// truncated to 256 bits.
+	pub fn signing_key(&self) -> SigningKey {
+		let mut hmac = Hmac::<Sha512>::new_from_slice(&self.encryption_keys.to_bytes()).expect("unexpected");
+		hmac.update(SIGNING_KEY_CONTEXT);
+
+		SigningKey::from_slice(&hmac.finalize().into_bytes()[..32]).expect("internal error")
+	}
 }
 
 
+impl LoginKey {
+	/// The key that logs in to the same account with read-only access.  Servers derive it from the login key they know, so it
+	/// doesn't need registering, and it can't be turned back into this key: HMAC-SHA-512 (login key,
+	/// "fortress-readonly-login-key1") truncated to 256 bits.
+	pub fn readonly_login_key(&self) -> LoginKey {
+		let mut hmac = Hmac::<Sha512>::new_from_slice(&self[..]).expect("unexpected");
+		hmac.update(READONLY_LOGIN_KEY_CONTEXT);
+
+		LoginKey::from_slice(&hmac.finalize().into_bytes()[..32]).expect("internal error")
+	}
+}
+
+
+/// Asks a hardware key for its response to a challenge, or returns None if it can't (e.g. the key isn't plugged in).
+pub type HardwareKeyFn<'a> = dyn Fn(&[u8]) -> Option<Zeroizing<Vec<u8>>> + 'a;
+
+
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub struct FileKeySuite {
-	encryption_keys: SivEncryptionKeys,
+	encryption_keys: Protected<SivEncryptionKeys>,
 	kdf_params: FileKdfParameters,
+	/// Derived from the hardware key's response to `kdf_params.hardware_challenge`, if there is one.  Kept so the keys can be
+	/// derived again for a new password (see `rederive`) without asking the hardware key again.
+	hardware_key: Option<Key>,
+	/// Empty if the keys are derived from the file's password.  Otherwise the keys are random, and these hold them for each
+	/// password that can open the file, keyslot 0 being the one `kdf_params` are for (see the keyslot module).
+	keyslots: Vec<Keyslot>,
+	/// The keyslot whose password opened the keys (see `get_keyslot`)
+	keyslot: usize,
 }
 
 impl FileKeySuite {
+	/// Fails with CryptoError::HardwareKeyRequired if `params` has a hardware challenge; see `derive_with_hardware_key`.
 	pub fn derive(password: &[u8], params: &FileKdfParameters) -> Result<FileKeySuite, CryptoError> {
-		let mut raw_keys = [0u8; 256];
+		FileKeySuite::derive_with_hardware_key(password, params, &|_| None)
+	}
+
+	/// Same as `derive`, but if `params` has a hardware challenge, `hardware_key` is asked for its response (e.g. from a
+	/// YubiKey's HMAC-SHA1 challenge-response, or a FIDO2 key's hmac-secret), which is mixed into the keys.  Fails with
+	/// CryptoError::HardwareKeyRequired if it returns None.  `hardware_key` isn't called for parameters without a challenge.
+	pub fn derive_with_hardware_key(password: &[u8], params: &FileKdfParameters, hardware_key: &HardwareKeyFn) -> Result<FileKeySuite, CryptoError> {
+		let hardware_key = match &params.hardware_challenge {
+			Some(challenge) => {
+				let response = hardware_key(challenge).ok_or(CryptoError::HardwareKeyRequired)?;
+				Some(Key::from_slice(&Sha512::digest(&response)[..32]).expect("internal error"))
+			},
+			None => None,
+		};
+
+		FileKeySuite::derive_inner(password, params, hardware_key)
+	}
+
+	/// Derives keys for a new password or parameters, keeping this suite's hardware challenge and key, if it has them, in place
+	/// of any in `params`.  Files with keyslots keep their keys, and only keyslot 0 changes (see the keyslot module).
+	pub fn rederive(&self, password: &[u8], params: &FileKdfParameters) -> Result<FileKeySuite, CryptoError> {
+		let params = FileKdfParameters {
+			hardware_challenge: self.kdf_params.hardware_challenge,
+			..params.clone()
+		};
+		let derived = FileKeySuite::derive_inner(password, &params, self.hardware_key.clone())?;
+
+		if self.keyslots.is_empty() {
+			Ok(derived)
+		} else {
+			Ok(self.rewrap_keyslot(derived))
+		}
+	}
+
+	fn derive_inner(password: &[u8], params: &FileKdfParameters, hardware_key: Option<Key>) -> Result<FileKeySuite, CryptoError> {
+		let mut raw_keys = Zeroizing::new([0u8; 256]);
 
 		let scrypt_params = scrypt::Params::new(params.log_n, params.r, params.p, 32).map_err(|_| CryptoError::BadScryptParameters)?;
-		scrypt::scrypt(password, &params.salt, &scrypt_params, &mut raw_keys).expect("internal error");
+		scrypt::scrypt(password, &params.salt, &scrypt_params, &mut raw_keys[..]).expect("internal error");
+
+		// Mixed in after scrypt, so the password alone is no longer enough: keys = HMAC-SHA-512 (hardware_key, scrypt || i) for
+		// i in 0..4
+		if let Some(hardware_key) = &hardware_key {
+			let mut mixed = Zeroizing::new([0u8; 256]);
+
+			for (i, block) in mixed.chunks_mut(64).enumerate() {
+				let mut hmac = Hmac::<Sha512>::new_from_slice(&hardware_key[..]).expect("unexpected");
+				hmac.update(&raw_keys[..]);
+				hmac.update(&[i as u8]);
+				block.copy_from_slice(&hmac.finalize().into_bytes());
+			}
+
+			raw_keys = mixed;
+		}
 
 		Ok(FileKeySuite {
-			encryption_keys: SivEncryptionKeys::from_slice(&raw_keys).expect("internal error"),
+			encryption_keys: Protected::new(SivEncryptionKeys::from_slice(&raw_keys[..]).expect("internal error")),
 			kdf_params: params.clone(),
+			hardware_key,
+			keyslots: Vec::new(),
+			keyslot: 0,
+		})
+	}
+
+	/// The parameters the keys were derived with, or keyslot 0's for files with keyslots.
+	pub fn get_kdf_params(&self) -> &FileKdfParameters {
+		&self.kdf_params
+	}
+
+	/// The derived keys and the parameters they were derived with, for keeping somewhere safe (like the OS keychain) so the file
+	/// can be decrypted later without the password or hardware key.  Anyone with these bytes can decrypt the file.
+	pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+		let mut result = Zeroizing::new(build_header(self));
+		result.extend_from_slice(&self.encryption_keys.to_bytes());
+		if let Some(hardware_key) = &self.hardware_key {
+			result.extend_from_slice(&hardware_key[..]);
+		}
+		result
+	}
+
+	/// Parses the output of `to_bytes`.
+	pub fn from_bytes(bytes: &[u8]) -> Option<FileKeySuite> {
+		let (kdf_params, raw_keys) = match parse_header(bytes).ok()? {
+			(FileHeader::Password(kdf_params), raw_keys) => (kdf_params, raw_keys),
+			(FileHeader::Keyslots(keyslots), raw_keys) => return Some(FileKeySuite::with_keyslots(SivEncryptionKeys::from_slice(raw_keys)?, keyslots, 0)),
+		};
+		let (raw_keys, hardware_key) = match kdf_params.hardware_challenge {
+			Some(_) => {
+				let (raw_keys, hardware_key) = raw_keys.split_at_checked(256)?;
+				(raw_keys, Some(Key::from_slice(hardware_key)?))
+			},
+			None => (raw_keys, None),
+		};
+
+		Some(FileKeySuite {
+			encryption_keys: Protected::new(SivEncryptionKeys::from_slice(raw_keys)?),
+			kdf_params,
+			hardware_key,
+			keyslots: Vec::new(),
+			keyslot: 0,
 		})
 	}
 
+	/// Encrypts `data` on its own, bound to `associated_data`, for things kept alongside the database file (like libfortress's
+	/// journal of unsaved edits).  The result is SIV || ciphertext.
+	pub fn encrypt_record(&self, associated_data: &[u8], data: &[u8]) -> Vec<u8> {
+		let (siv, ciphertext) = self.encryption_keys.encrypt(associated_data, data);
+		[siv.as_ref(), ciphertext.as_slice()].concat()
+	}
+
+	/// Decrypts the output of `encrypt_record`, which must have been given the same `associated_data`.
+	pub fn decrypt_record(&self, associated_data: &[u8], data: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+		if data.len() < 32 {
+			return Err(CryptoError::TruncatedData);
+		}
+
+		let (siv, ciphertext) = data.split_at(32);
+		let siv = SIV::from_slice(siv).expect("internal error");
+		self.encryption_keys
+			.decrypt(associated_data, &siv, ciphertext)
+			.ok_or(CryptoError::DecryptionError)
+	}
+
 	fn encrypt_object(&self, data: &[u8]) -> Vec<u8> {
 		let (siv, ciphertext) = self.encryption_keys.encrypt(&[], data);
 		[siv.as_ref(), ciphertext.as_slice()].concat()
 	}
 
-	fn decrypt_object(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+	fn decrypt_object(&self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
 		if data.len() < 32 {
 			return Err(CryptoError::DecryptionError);
 		}
@@ -142,12 +352,62 @@ impl FileKeySuite {
 
 
 /// Decrypts a database stored on disk.  Returns the plaintext and the FileKeySuite that was used.
-pub fn decrypt_from_file<R: Read>(reader: &mut R, password: &[u8]) -> Result<(Vec<u8>, FileKeySuite), CryptoError> {
+/// Fails with CryptoError::HardwareKeyRequired if the file was encrypted with a hardware key; see
+/// `decrypt_from_file_with_hardware_key`.
+pub fn decrypt_from_file<R: Read>(reader: &mut R, password: &[u8]) -> Result<(Zeroizing<Vec<u8>>, FileKeySuite), CryptoError> {
+	decrypt_from_file_with_hardware_key(reader, password, &|_| None)
+}
+
+
+/// Same as `decrypt_from_file`, but `hardware_key` is asked for the hardware key's response if the file needs one (see
+/// `FileKeySuite::derive_with_hardware_key`).
+pub fn decrypt_from_file_with_hardware_key<R: Read>(
+	reader: &mut R,
+	password: &[u8],
+	hardware_key: &HardwareKeyFn,
+) -> Result<(Zeroizing<Vec<u8>>, FileKeySuite), CryptoError> {
 	// Read file
 	let mut filedata = Vec::new();
 	reader.read_to_end(&mut filedata)?;
 
-	// Check checksum
+	let (header, payload) = check_file(&filedata)?;
+
+	// Derive keys
+	let file_key_suite = match header {
+		FileHeader::Password(params) => FileKeySuite::derive_with_hardware_key(password, &params, hardware_key)?,
+		FileHeader::Keyslots(keyslots) => FileKeySuite::open_keyslots(keyslots, password)?,
+	};
+
+	// Decrypt
+	let plaintext = file_key_suite.decrypt_object(payload)?;
+
+	Ok((plaintext, file_key_suite))
+}
+
+
+/// Same as `decrypt_from_file`, but with keys that were already derived (see `FileKeySuite::to_bytes`), skipping the KDF.  Fails
+/// with CryptoError::DecryptionError if the file was encrypted with different keys, e.g. because the password was changed, or
+/// has different keyslots.
+pub fn decrypt_from_file_with_keys<R: Read>(reader: &mut R, file_key_suite: &FileKeySuite) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+	let mut filedata = Vec::new();
+	reader.read_to_end(&mut filedata)?;
+
+	let (header, payload) = check_file(&filedata)?;
+	let same_keys = match header {
+		FileHeader::Password(params) => file_key_suite.keyslots.is_empty() && params == file_key_suite.kdf_params,
+		FileHeader::Keyslots(keyslots) => keyslots == file_key_suite.keyslots,
+	};
+
+	if !same_keys {
+		return Err(CryptoError::DecryptionError);
+	}
+
+	file_key_suite.decrypt_object(payload)
+}
+
+
+// Checks the file's checksum and parses its header, returning the header and the encrypted payload
+fn check_file(filedata: &[u8]) -> Result<(FileHeader, &[u8]), CryptoError> {
 	if filedata.len() < 32 {
 		return Err(CryptoError::TruncatedData);
 	}
@@ -159,23 +419,14 @@ pub fn decrypt_from_file<R: Read>(reader: &mut R, password: &[u8]) -> Result<(Ve
 		return Err(CryptoError::BadChecksum);
 	}
 
-	// Parse header
-	let (params, payload) = parse_header(filedata)?;
-
-	// Derive keys
-	let file_key_suite = FileKeySuite::derive(password, &params)?;
-
-	// Decrypt
-	let plaintext = file_key_suite.decrypt_object(payload)?;
-
-	Ok((plaintext, file_key_suite))
+	parse_header(filedata)
 }
 
 
 /// Encrypts a database to disk.  Resulting file will contain a header, ciphertext, mac, and checksum.
 pub fn encrypt_to_file<W: Write>(writer: &mut W, data: &[u8], key_suite: &FileKeySuite) -> io::Result<()> {
 	let ciphertext = key_suite.encrypt_object(data);
-	let header = build_header(&key_suite.kdf_params);
+	let header = build_header(key_suite);
 	let checksum = calculate_checksum([header.as_slice(), ciphertext.as_slice()]);
 
 	writer.write_all(&header)?;
@@ -184,49 +435,101 @@ pub fn encrypt_to_file<W: Write>(writer: &mut W, data: &[u8], key_suite: &FileKe
 }
 
 
-fn build_header(params: &FileKdfParameters) -> Vec<u8> {
+// How a file's keys are found, according to its header
+enum FileHeader {
+	/// Derived from the password with these parameters
+	Password(FileKdfParameters),
+	/// Random, and held in these keyslots (see the keyslot module)
+	Keyslots(Vec<Keyslot>),
+}
+
+
+// Files that need a hardware key have their own version string, followed by the same parameters and then the challenge, so older
+// versions of Fortress refuse them as unsupported rather than failing to decrypt them.  So do files with keyslots.
+fn build_header(key_suite: &FileKeySuite) -> Vec<u8> {
+	if !key_suite.keyslots.is_empty() {
+		return build_keyslot_header(&key_suite.keyslots);
+	}
+
+	let params = &key_suite.kdf_params;
 	let mut result = Vec::new();
 
-	result.extend_from_slice(b"fortress2\0");
-	result.extend_from_slice(&params.log_n.to_le_bytes());
-	result.extend_from_slice(&params.r.to_le_bytes());
-	result.extend_from_slice(&params.p.to_le_bytes());
-	result.extend_from_slice(&params.salt);
+	result.extend_from_slice(if params.hardware_challenge.is_some() {
+		b"fortress2hw\0"
+	} else {
+		b"fortress2\0"
+	});
+	result.extend_from_slice(&params_bytes(params));
+	if let Some(challenge) = &params.hardware_challenge {
+		result.extend_from_slice(challenge);
+	}
 	result
 }
 
 
-fn parse_header(data: &[u8]) -> Result<(FileKdfParameters, &[u8]), CryptoError> {
+fn parse_header(data: &[u8]) -> Result<(FileHeader, &[u8]), CryptoError> {
 	let mut reader = Cursor::new(data);
 
 	let mut header_string = Vec::new();
 	reader.read_until(0, &mut header_string)?;
 
-	// Only v2 is supported
-	if str::from_utf8(&header_string).map_err(|_| CryptoError::UnsupportedVersion)? != "fortress2\0" {
-		return Err(CryptoError::UnsupportedVersion);
+	if header_string == KEYSLOT_HEADER {
+		let keyslots = parse_keyslots(&mut reader)?;
+		let pos = reader.position() as usize;
+		return Ok((FileHeader::Keyslots(keyslots), &reader.into_inner()[pos..]));
 	}
 
+	// Only v2 is supported
+	let has_hardware_challenge = match str::from_utf8(&header_string).map_err(|_| CryptoError::UnsupportedVersion)? {
+		"fortress2\0" => false,
+		"fortress2hw\0" => true,
+		_ => return Err(CryptoError::UnsupportedVersion),
+	};
+
 	let log_n = reader.read_u8()?;
 	let r = reader.read_u32::<LittleEndian>()?;
 	let p = reader.read_u32::<LittleEndian>()?;
 	let mut scrypt_salt = [0u8; 32];
 	reader.read_exact(&mut scrypt_salt)?;
+	let hardware_challenge = if has_hardware_challenge {
+		let mut challenge = [0u8; 32];
+		reader.read_exact(&mut challenge)?;
+		Some(challenge)
+	} else {
+		None
+	};
 
 	let pos = reader.position() as usize;
 
 	Ok((
-		FileKdfParameters {
+		FileHeader::Password(FileKdfParameters {
 			log_n,
 			r,
 			p,
 			salt: scrypt_salt,
-		},
+			hardware_challenge,
+		}),
 		&reader.into_inner()[pos..],
 	))
 }
 
 
+/// Estimates how long scrypt takes with the given parameters by timing a single lane (p = 1) with a smaller N.  scrypt's time is
+/// linear in N and p, so the result is scaled back up.  The smaller N fits in cache better, so this tends to underestimate.
+fn estimate_scrypt_time(log_n: u8, r: u32, p: u32) -> Duration {
+	let sample_log_n = log_n.saturating_sub(ESTIMATE_LOG_N_REDUCTION);
+	let mut output = [0u8; 32];
+	let start = Instant::now();
+
+	match scrypt::Params::new(sample_log_n, r, 1, 32) {
+		Ok(params) => scrypt::scrypt(b"estimate", b"estimate", &params, &mut output).expect("internal error"),
+		Err(_) => return Duration::ZERO,
+	}
+
+	start.elapsed() * (1 << (log_n - sample_log_n)) * p
+}
+
+
 /// Calculates the SHA-512-256 hash of the given inputs (SHA-512 output is truncated to 32 bytes).
 fn calculate_checksum(inputs: impl IntoIterator<Item = impl AsRef<[u8]>>) -> [u8; 32] {
 	use sha2::digest::generic_array::{sequence::Split, typenum::U32, GenericArray};
@@ -253,6 +556,16 @@ pub struct FileKdfParameters {
 	pub r: u32,
 	pub p: u32,
 	pub salt: [u8; 32],
+	/// Sent to the hardware key (e.g. a YubiKey) whose response is mixed into the keys.  None if the file only needs the password.
+	pub hardware_challenge: Option<[u8; 32]>,
+}
+
+impl FileKdfParameters {
+	/// Roughly how long `FileKeySuite::derive` takes with these parameters on this machine.  See
+	/// `NetworkKeySuite::estimate_derive_time`.
+	pub fn estimate_derive_time(&self) -> Duration {
+		estimate_scrypt_time(self.log_n, self.r, self.p)
+	}
 }
 
 // Default is N=18, r=8, p=1 (less N when in debug mode)
@@ -264,6 +577,7 @@ impl Default for FileKdfParameters {
 			r: 8,
 			p: 1,
 			salt: OsRng.gen(),
+			hardware_challenge: None,
 		}
 	}
 }
@@ -271,9 +585,13 @@ impl Default for FileKdfParameters {
 
 #[cfg(test)]
 mod tests {
-	use super::{calculate_checksum, decrypt_from_file, encrypt_to_file, FileKeySuite, NetworkKeySuite};
+	use super::{
+		calculate_checksum, decrypt_from_file, decrypt_from_file_with_hardware_key, decrypt_from_file_with_keys, encrypt_to_file, CryptoError,
+		FileKdfParameters, FileKeySuite, Key, NetworkKeySuite,
+	};
 	use rand::{rngs::OsRng, seq::SliceRandom, Rng};
 	use std::io::Cursor;
+	use zeroize::Zeroizing;
 
 	// Basic santiy checks on NetworkKeySuite (the underlying SIV encryption is tested in the siv module)
 	#[test]
@@ -292,7 +610,7 @@ mod tests {
 		let bad_id: [u8; 32] = OsRng.gen();
 		let ciphertext = keys.encrypt_object(&id, &plaintext);
 
-		assert_eq!(plaintext, keys.decrypt_object(&id, &ciphertext).unwrap());
+		assert_eq!(plaintext, *keys.decrypt_object(&id, &ciphertext).unwrap());
 		assert!(keys.decrypt_object(&bad_id, &ciphertext).is_err());
 		assert!(bad_keys.decrypt_object(&id, &ciphertext).is_err());
 
@@ -303,6 +621,19 @@ mod tests {
 		assert_ne!(keys, NetworkKeySuite::derive("differentuser".as_bytes(), password.as_bytes()));
 	}
 
+	#[test]
+	fn derivation_id() {
+		let salt: Key = OsRng.gen();
+		let id = NetworkKeySuite::derivation_id(&salt, b"username", b"password");
+
+		assert_eq!(id, NetworkKeySuite::derivation_id(&salt, b"username", b"password"));
+		assert_ne!(id, NetworkKeySuite::derivation_id(&OsRng.gen(), b"username", b"password"));
+		assert_ne!(id, NetworkKeySuite::derivation_id(&salt, b"username", b"password2"));
+		assert_ne!(id, NetworkKeySuite::derivation_id(&salt, b"username2", b"password"));
+		// Usernames and passwords can't run into each other
+		assert_ne!(id, NetworkKeySuite::derivation_id(&salt, b"usernamep", b"assword"));
+	}
+
 
 	// Basic santiy checks on FileKeySuite (the underlying SIV encryption is tested in the siv module)
 	#[test]
@@ -319,13 +650,95 @@ mod tests {
 		let plaintext = (0..2017).map(|_| OsRng.gen()).collect::<Vec<u8>>();
 		let ciphertext = keys.encrypt_object(&plaintext);
 
-		assert_eq!(plaintext, keys.decrypt_object(&ciphertext).unwrap());
+		assert_eq!(plaintext, *keys.decrypt_object(&ciphertext).unwrap());
 		assert!(bad_keys.decrypt_object(&ciphertext).is_err());
 
 		// Check that the same keys are derived from the same password
 		assert_eq!(keys, FileKeySuite::derive(password.as_bytes(), &params).unwrap());
 	}
 
+	#[test]
+	fn file_key_suite_bytes() {
+		let keys = FileKeySuite::derive(b"password", &Default::default()).unwrap();
+		let other_keys = FileKeySuite::derive(b"password", &Default::default()).unwrap();
+		let bytes = keys.to_bytes();
+
+		assert_eq!(FileKeySuite::from_bytes(&bytes), Some(keys.clone()));
+		assert_eq!(FileKeySuite::from_bytes(&bytes[..bytes.len() - 1]), None);
+		assert_eq!(FileKeySuite::from_bytes(b"garbage"), None);
+
+		let mut file = Vec::new();
+		encrypt_to_file(&mut file, b"payload", &keys).unwrap();
+		assert_eq!(&decrypt_from_file_with_keys(&mut Cursor::new(&file), &keys).unwrap()[..], b"payload");
+		// Same password, but a different salt
+		assert!(decrypt_from_file_with_keys(&mut Cursor::new(&file), &other_keys).is_err());
+	}
+
+	#[test]
+	fn hardware_key() {
+		let params = FileKdfParameters {
+			hardware_challenge: Some(OsRng.gen()),
+			..Default::default()
+		};
+		// Stands in for a YubiKey's HMAC-SHA1 challenge-response
+		let response = |challenge: &[u8]| Some(Zeroizing::new(challenge.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>()));
+		let wrong_response = |_: &[u8]| Some(Zeroizing::new(vec![0u8; 20]));
+
+		let keys = FileKeySuite::derive_with_hardware_key(b"password", &params, &response).unwrap();
+		let password_only = FileKeySuite::derive(
+			b"password",
+			&FileKdfParameters {
+				hardware_challenge: None,
+				..params.clone()
+			},
+		)
+		.unwrap();
+		assert!(matches!(FileKeySuite::derive(b"password", &params), Err(CryptoError::HardwareKeyRequired)));
+		assert_ne!(keys.encryption_keys, password_only.encryption_keys);
+		assert_ne!(keys, FileKeySuite::derive_with_hardware_key(b"password", &params, &wrong_response).unwrap());
+
+		let mut file = Vec::new();
+		encrypt_to_file(&mut file, b"payload", &keys).unwrap();
+		assert!(file.starts_with(b"fortress2hw\0"));
+		let (plaintext, loaded_keys) = decrypt_from_file_with_hardware_key(&mut Cursor::new(&file), b"password", &response).unwrap();
+		assert_eq!(&plaintext[..], b"payload");
+		assert_eq!(loaded_keys, keys);
+		assert!(matches!(
+			decrypt_from_file(&mut Cursor::new(&file), b"password"),
+			Err(CryptoError::HardwareKeyRequired)
+		));
+		assert!(matches!(
+			decrypt_from_file_with_hardware_key(&mut Cursor::new(&file), b"password", &wrong_response),
+			Err(CryptoError::DecryptionError)
+		));
+		assert!(decrypt_from_file_with_hardware_key(&mut Cursor::new(&file), b"wrong", &response).is_err());
+
+		// Stored keys and rederived keys keep the hardware key
+		assert_eq!(FileKeySuite::from_bytes(&keys.to_bytes()), Some(keys.clone()));
+		let new_params = FileKdfParameters::default();
+		let rederived = keys.rederive(b"new password", &new_params).unwrap();
+		assert_eq!(rederived.kdf_params.hardware_challenge, params.hardware_challenge);
+		assert_eq!(rederived.kdf_params.salt, new_params.salt);
+		assert_eq!(
+			rederived,
+			FileKeySuite::derive_with_hardware_key(b"new password", &rederived.kdf_params, &response).unwrap()
+		);
+	}
+
+	#[test]
+	fn records() {
+		let keys = FileKeySuite::derive(b"password", &Default::default()).unwrap();
+		let record = keys.encrypt_record(b"record 1", b"payload");
+
+		assert_eq!(&keys.decrypt_record(b"record 1", &record).unwrap()[..], b"payload");
+		assert!(matches!(keys.decrypt_record(b"record 2", &record), Err(CryptoError::DecryptionError)));
+		assert!(matches!(keys.decrypt_record(b"record 1", &record[..31]), Err(CryptoError::TruncatedData)));
+		assert!(FileKeySuite::derive(b"password", &Default::default())
+			.unwrap()
+			.decrypt_record(b"record 1", &record)
+			.is_err());
+	}
+
 	// Make sure errors are thrown for the various kinds of file corruption
 	#[test]
 	fn file_corruption() {
@@ -370,12 +783,14 @@ mod tests {
 			if mutation_byte == 0 {
 				assert_eq!(
 					decrypt_from_file(&mut Cursor::new(corrupted_checksum), password)
-						.map(|(pt, _)| pt)
+						.map(|(pt, _)| pt.to_vec())
 						.map_err(|_| ()),
 					Ok(payload.to_vec())
 				);
 				assert_eq!(
-					decrypt_from_file(&mut Cursor::new(corrupted_mac), password).map(|(pt, _)| pt).map_err(|_| ()),
+					decrypt_from_file(&mut Cursor::new(corrupted_mac), password)
+						.map(|(pt, _)| pt.to_vec())
+						.map_err(|_| ()),
 					Ok(payload.to_vec())
 				);
 			} else {
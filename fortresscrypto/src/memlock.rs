@@ -0,0 +1,266 @@
+// Storage for key material.
+//
+// With the `memlock` feature enabled, Protected<T> places its value in its own page-aligned allocation which is locked into RAM
+// (so it never gets written to swap) and excluded from core dumps (mlock + MADV_DONTDUMP on Linux, VirtualLock on Windows).
+// If the OS refuses to lock the memory (e.g. RLIMIT_MEMLOCK is too low) the value is still stored, just without the lock.
+// Without the feature, Protected<T> is simply a Box<T>.
+//
+// NOTE: This is best-effort.  Values are built on the stack before being moved into a Protected, and those temporary copies
+// are not covered.
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+
+
+pub struct Protected<T> {
+	inner: imp::Allocation<T>,
+}
+
+impl<T> Protected<T> {
+	pub fn new(value: T) -> Protected<T> {
+		Protected {
+			inner: imp::Allocation::new(value),
+		}
+	}
+
+	/// Returns true if the value is actually held in locked memory.
+	/// Always false when the `memlock` feature is disabled or the OS refused the lock.
+	pub fn is_locked(&self) -> bool {
+		self.inner.is_locked()
+	}
+}
+
+impl<T> Deref for Protected<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		self.inner.get()
+	}
+}
+
+impl<T: Clone> Clone for Protected<T> {
+	fn clone(&self) -> Protected<T> {
+		Protected::new((**self).clone())
+	}
+}
+
+impl<T: PartialEq> PartialEq for Protected<T> {
+	fn eq(&self, other: &Protected<T>) -> bool {
+		**self == **other
+	}
+}
+
+impl<T: Eq> Eq for Protected<T> {}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Protected<T> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		(**self).fmt(f)
+	}
+}
+
+// Serialization is transparent, so wrapping a field in Protected doesn't change any on-disk formats.
+impl<T: Serialize> Serialize for Protected<T> {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		(**self).serialize(serializer)
+	}
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Protected<T> {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: serde::Deserializer<'de>,
+	{
+		T::deserialize(deserializer).map(Protected::new)
+	}
+}
+
+
+#[cfg(not(feature = "memlock"))]
+mod imp {
+	pub(super) struct Allocation<T>(Box<T>);
+
+	impl<T> Allocation<T> {
+		pub(super) fn new(value: T) -> Allocation<T> {
+			Allocation(Box::new(value))
+		}
+
+		pub(super) fn get(&self) -> &T {
+			&self.0
+		}
+
+		pub(super) fn is_locked(&self) -> bool {
+			false
+		}
+	}
+}
+
+
+#[cfg(feature = "memlock")]
+mod imp {
+	use std::{
+		alloc::{self, Layout},
+		ptr::NonNull,
+	};
+	use zeroize::Zeroize;
+
+	pub(super) struct Allocation<T> {
+		ptr: NonNull<T>,
+		layout: Layout,
+		locked: bool,
+	}
+
+	// Allocation uniquely owns its value, just like a Box.
+	unsafe impl<T: Send> Send for Allocation<T> {}
+	unsafe impl<T: Sync> Sync for Allocation<T> {}
+
+	impl<T> Allocation<T> {
+		pub(super) fn new(value: T) -> Allocation<T> {
+			// Give every value its own pages, so that unlocking one allocation never unlocks another's memory.
+			let page_size = platform::page_size();
+			let size = std::mem::size_of::<T>().max(1).div_ceil(page_size) * page_size;
+			let layout = Layout::from_size_align(size, page_size.max(std::mem::align_of::<T>())).expect("invalid memlock layout");
+
+			// SAFETY: layout has a non-zero size.
+			let ptr = NonNull::new(unsafe { alloc::alloc_zeroed(layout) } as *mut T).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+
+			// Lock before writing so the value never sits in swappable memory at this address.
+			// SAFETY: ptr points to an allocation of layout.size() bytes.
+			let locked = unsafe { platform::lock(ptr.as_ptr() as *mut u8, layout.size()) };
+
+			// SAFETY: ptr is valid and suitably aligned for T.
+			unsafe { ptr.as_ptr().write(value) };
+
+			Allocation { ptr, layout, locked }
+		}
+
+		pub(super) fn get(&self) -> &T {
+			// SAFETY: ptr was initialized in new and is only freed in drop.
+			unsafe { self.ptr.as_ref() }
+		}
+
+		pub(super) fn is_locked(&self) -> bool {
+			self.locked
+		}
+	}
+
+	impl<T> Drop for Allocation<T> {
+		fn drop(&mut self) {
+			// SAFETY: ptr is valid, initialized, and not used again after this.
+			unsafe {
+				std::ptr::drop_in_place(self.ptr.as_ptr());
+				std::slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut u8, self.layout.size()).zeroize();
+
+				if self.locked {
+					platform::unlock(self.ptr.as_ptr() as *mut u8, self.layout.size());
+				}
+
+				alloc::dealloc(self.ptr.as_ptr() as *mut u8, self.layout);
+			}
+		}
+	}
+
+	#[cfg(unix)]
+	mod platform {
+		pub(super) fn page_size() -> usize {
+			// SAFETY: sysconf has no preconditions.
+			match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+				size if size > 0 => size as usize,
+				_ => 4096,
+			}
+		}
+
+		/// Returns true if the memory was locked.
+		pub(super) unsafe fn lock(ptr: *mut u8, len: usize) -> bool {
+			// Keep key material out of core dumps, regardless of whether locking works.
+			#[cfg(target_os = "linux")]
+			libc::madvise(ptr as *mut libc::c_void, len, libc::MADV_DONTDUMP);
+
+			libc::mlock(ptr as *const libc::c_void, len) == 0
+		}
+
+		pub(super) unsafe fn unlock(ptr: *mut u8, len: usize) {
+			libc::munlock(ptr as *const libc::c_void, len);
+		}
+	}
+
+	#[cfg(windows)]
+	mod platform {
+		use windows_sys::Win32::System::{
+			Memory::{VirtualLock, VirtualUnlock},
+			SystemInformation::{GetSystemInfo, SYSTEM_INFO},
+		};
+
+		pub(super) fn page_size() -> usize {
+			// SAFETY: GetSystemInfo fills in the provided struct and cannot fail.
+			let info = unsafe {
+				let mut info: SYSTEM_INFO = std::mem::zeroed();
+				GetSystemInfo(&mut info);
+				info
+			};
+
+			match info.dwPageSize {
+				0 => 4096,
+				size => size as usize,
+			}
+		}
+
+		/// Returns true if the memory was locked.
+		pub(super) unsafe fn lock(ptr: *mut u8, len: usize) -> bool {
+			VirtualLock(ptr as *const std::ffi::c_void, len) != 0
+		}
+
+		pub(super) unsafe fn unlock(ptr: *mut u8, len: usize) {
+			VirtualUnlock(ptr as *const std::ffi::c_void, len);
+		}
+	}
+
+	#[cfg(not(any(unix, windows)))]
+	mod platform {
+		pub(super) fn page_size() -> usize {
+			4096
+		}
+
+		pub(super) unsafe fn lock(_ptr: *mut u8, _len: usize) -> bool {
+			false
+		}
+
+		pub(super) unsafe fn unlock(_ptr: *mut u8, _len: usize) {}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::Protected;
+	use crate::LoginKey;
+	use rand::{rngs::OsRng, Rng};
+
+	#[test]
+	fn protected_behaves_like_its_value() {
+		let key: LoginKey = OsRng.gen();
+		let protected = Protected::new(key.clone());
+
+		assert_eq!(*protected, key);
+		assert_eq!(protected.clone(), protected);
+		assert_ne!(Protected::new(OsRng.gen::<LoginKey>()), protected);
+
+		// Serialization is transparent
+		let serialized = serde_json::to_string(&protected).unwrap();
+		assert_eq!(serialized, serde_json::to_string(&key).unwrap());
+		assert_eq!(serde_json::from_str::<Protected<LoginKey>>(&serialized).unwrap(), protected);
+	}
+
+	#[test]
+	fn zero_sized_values() {
+		let protected = Protected::new(());
+		assert_eq!(*protected, ());
+	}
+
+	#[cfg(not(feature = "memlock"))]
+	#[test]
+	fn not_locked_without_feature() {
+		assert!(!Protected::new(0u8).is_locked());
+	}
+}
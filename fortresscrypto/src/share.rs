@@ -0,0 +1,149 @@
+// Public key encryption, for sending entries to another Fortress user.  Each recipient has an X25519 key pair and hands out the
+// public half.  Every share generates an ephemeral key pair; the Diffie-Hellman secret between it and the recipient's key is
+// expanded into a one-off set of SivEncryptionKeys, and the ephemeral public key is sent along with the ciphertext.
+//
+// X25519 comes from x25519-dalek rather than ring, since ring only supports ephemeral keys and the recipient's key is
+// long-lived.
+use crate::{siv::SivEncryptionKeys, CryptoError, SIV};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, Rng};
+use sha2::Sha512;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+use zeroize::Zeroizing;
+
+
+new_type!(secret ShareSecretKey(32););
+new_type!(public SharePublicKey(32););
+
+
+const SHARE_HEADER: &[u8] = b"fortress-share1\0";
+
+
+impl ShareSecretKey {
+	pub fn generate() -> ShareSecretKey {
+		OsRng.gen()
+	}
+
+	pub fn public_key(&self) -> SharePublicKey {
+		SharePublicKey::from_slice(PublicKey::from(&self.private_key()).as_bytes()).expect("internal error")
+	}
+
+	fn private_key(&self) -> StaticSecret {
+		let mut bytes = Zeroizing::new([0u8; 32]);
+		bytes.copy_from_slice(&self[..]);
+		StaticSecret::from(*bytes)
+	}
+}
+
+
+/// Encrypts `plaintext` so only the holder of `recipient`'s secret key can decrypt it.
+pub fn encrypt_share(recipient: &SharePublicKey, plaintext: &[u8]) -> Vec<u8> {
+	let ephemeral_key = EphemeralSecret::random_from_rng(OsRng);
+	let ephemeral_public = SharePublicKey::from_slice(PublicKey::from(&ephemeral_key).as_bytes()).expect("internal error");
+	let shared_secret = ephemeral_key.diffie_hellman(&public_key(recipient));
+	let keys = share_keys(&shared_secret, &ephemeral_public, recipient).expect("internal error");
+	let (siv, ciphertext) = keys.encrypt(&[], plaintext);
+
+	[SHARE_HEADER, &ephemeral_public[..], &siv[..], &ciphertext].concat()
+}
+
+
+/// Decrypts data made by `encrypt_share` for `secret_key`'s public key.
+pub fn decrypt_share(secret_key: &ShareSecretKey, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+	let data = data.strip_prefix(SHARE_HEADER).ok_or(CryptoError::UnsupportedVersion)?;
+
+	if data.len() < 64 {
+		return Err(CryptoError::TruncatedData);
+	}
+
+	let (ephemeral_public, data) = data.split_at(32);
+	let (siv, ciphertext) = data.split_at(32);
+	let ephemeral_public = SharePublicKey::from_slice(ephemeral_public).expect("internal error");
+	let shared_secret = secret_key.private_key().diffie_hellman(&public_key(&ephemeral_public));
+	let keys = share_keys(&shared_secret, &ephemeral_public, &secret_key.public_key()).ok_or(CryptoError::DecryptionError)?;
+
+	keys.decrypt(&[], &SIV::from_slice(siv).expect("internal error"), ciphertext)
+		.ok_or(CryptoError::DecryptionError)
+}
+
+
+fn public_key(key: &SharePublicKey) -> PublicKey {
+	let mut bytes = [0u8; 32];
+	bytes.copy_from_slice(&key[..]);
+	PublicKey::from(bytes)
+}
+
+
+// The encryption keys for a share from `ephemeral_public` to `recipient`, given the Diffie-Hellman secret between them.  Both
+// public keys are mixed in so a share can't be passed off as being for a different key pair.
+// None if the two didn't agree on a secret that depends on both keys (e.g. one is a low order point).
+fn share_keys(shared_secret: &SharedSecret, ephemeral_public: &SharePublicKey, recipient: &SharePublicKey) -> Option<SivEncryptionKeys> {
+	if !shared_secret.was_contributory() {
+		return None;
+	}
+
+	// HKDF-Expand style, with HMAC-SHA-512 keyed by the shared secret, to get the 256 bytes SivEncryptionKeys needs
+	let mut raw_keys = Zeroizing::new(Vec::with_capacity(256));
+
+	for counter in 1..=4u8 {
+		let mut hmac = Hmac::<Sha512>::new_from_slice(shared_secret.as_bytes()).expect("unexpected");
+		hmac.update(SHARE_HEADER);
+		hmac.update(&ephemeral_public[..]);
+		hmac.update(&recipient[..]);
+		hmac.update(&[counter]);
+		raw_keys.extend_from_slice(&hmac.finalize().into_bytes());
+	}
+
+	Some(SivEncryptionKeys::from_slice(&raw_keys).expect("internal error"))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{decrypt_share, encrypt_share, public_key, SharePublicKey, ShareSecretKey, SHARE_HEADER};
+	use crate::CryptoError;
+
+	// The Diffie-Hellman test vector from RFC 7748 section 6.1
+	#[test]
+	fn rfc7748_vector() {
+		let alice = ShareSecretKey::from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a").unwrap();
+		let bob = ShareSecretKey::from_hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb").unwrap();
+
+		assert_eq!(alice.public_key().to_hex(), "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a");
+		assert_eq!(bob.public_key().to_hex(), "de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f");
+
+		let shared = alice.private_key().diffie_hellman(&public_key(&bob.public_key()));
+		assert_eq!(
+			SharePublicKey::from_slice(shared.as_bytes()).unwrap().to_hex(),
+			"4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742"
+		);
+		assert_eq!(shared.as_bytes(), bob.private_key().diffie_hellman(&public_key(&alice.public_key())).as_bytes());
+	}
+
+	#[test]
+	fn share_round_trip() {
+		let recipient = ShareSecretKey::generate();
+		let share = encrypt_share(&recipient.public_key(), b"hello");
+
+		assert_eq!(&decrypt_share(&recipient, &share).unwrap()[..], b"hello");
+		// Every share uses a new ephemeral key
+		assert_ne!(share, encrypt_share(&recipient.public_key(), b"hello"));
+
+		// Only the recipient can decrypt it
+		assert!(matches!(decrypt_share(&ShareSecretKey::generate(), &share), Err(CryptoError::DecryptionError)));
+
+		// Tampering with any part of it is detected
+		for i in 16..share.len() {
+			let mut tampered = share.clone();
+			tampered[i] ^= 1;
+			assert!(decrypt_share(&recipient, &tampered).is_err(), "byte {i}");
+		}
+
+		assert!(matches!(decrypt_share(&recipient, &share[..70]), Err(CryptoError::TruncatedData)));
+		assert!(matches!(decrypt_share(&recipient, b"garbage"), Err(CryptoError::UnsupportedVersion)));
+
+		// An ephemeral key of low order gives a shared secret the sender chose, so it's refused
+		let low_order = [SHARE_HEADER, &[0; 32], &share[48..]].concat();
+		assert!(matches!(decrypt_share(&recipient, &low_order), Err(CryptoError::DecryptionError)));
+	}
+}
@@ -0,0 +1,90 @@
+// Ed25519 signatures, for making a database's contents tamper-evident (see libfortress's signature module).  The signing key is
+// derived from the network keys (`NetworkKeySuite::signing_key`), so every device that knows the username and password signs
+// with the same key, and anyone holding the public half can check a signature without being able to make one.
+use crate::CryptoError;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+
+
+new_type!(secret SigningKey(32););
+new_type!(public VerifyingKey(32););
+new_type!(public Signature(64););
+
+
+impl SigningKey {
+	pub fn verifying_key(&self) -> VerifyingKey {
+		VerifyingKey::from_slice(self.key_pair().public_key().as_ref()).expect("internal error")
+	}
+
+	pub fn sign(&self, message: &[u8]) -> Signature {
+		Signature::from_slice(self.key_pair().sign(message).as_ref()).expect("internal error")
+	}
+
+	// The key is the RFC 8032 seed, which ring expands each time
+	fn key_pair(&self) -> Ed25519KeyPair {
+		Ed25519KeyPair::from_seed_unchecked(&self[..]).expect("internal error")
+	}
+}
+
+
+impl VerifyingKey {
+	/// Fails with CryptoError::BadSignature unless `signature` was made over `message` by this key's signing key.
+	pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), CryptoError> {
+		UnparsedPublicKey::new(&ED25519, &self[..])
+			.verify(message, &signature[..])
+			.map_err(|_| CryptoError::BadSignature)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{Signature, SigningKey, VerifyingKey};
+	use crate::CryptoError;
+
+	// Test 1 and Test 3 from RFC 8032 section 7.1
+	#[test]
+	fn rfc8032_vectors() {
+		let vectors = [
+			(
+				"9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+				"d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+				"",
+				"e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+			),
+			(
+				"c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+				"fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+				"af82",
+				"6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+			),
+		];
+
+		for (secret, public, message, signature) in vectors {
+			let signing_key = SigningKey::from_hex(secret).unwrap();
+			let message = data_encoding::HEXLOWER.decode(message.as_bytes()).unwrap();
+
+			assert_eq!(signing_key.verifying_key().to_hex(), public);
+			assert_eq!(signing_key.sign(&message).to_hex(), signature);
+			signing_key.verifying_key().verify(&message, &Signature::from_hex(signature).unwrap()).unwrap();
+		}
+	}
+
+	#[test]
+	fn verify() {
+		let signing_key: SigningKey = rand::random();
+		let verifying_key = signing_key.verifying_key();
+		let signature = signing_key.sign(b"hello");
+
+		verifying_key.verify(b"hello", &signature).unwrap();
+		assert!(matches!(verifying_key.verify(b"hellO", &signature), Err(CryptoError::BadSignature)));
+		assert!(matches!(
+			rand::random::<SigningKey>().verifying_key().verify(b"hello", &signature),
+			Err(CryptoError::BadSignature)
+		));
+
+		let mut tampered = signature;
+		tampered.0[10] ^= 1;
+		assert!(matches!(verifying_key.verify(b"hello", &tampered), Err(CryptoError::BadSignature)));
+		assert!(matches!(VerifyingKey([0xff; 32]).verify(b"hello", &signature), Err(CryptoError::BadSignature)));
+	}
+}
@@ -5,6 +5,7 @@ use chacha20::{
 use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
 use sha2::Sha512;
+use zeroize::{Zeroize, Zeroizing};
 
 new_type!(secret HmacKey(128););
 
@@ -27,8 +28,9 @@ impl SivEncryptionKeys {
 		(siv, ciphertext)
 	}
 
-	pub(crate) fn decrypt(&self, aad: &[u8], siv: &SIV, ciphertext: &[u8]) -> Option<Vec<u8>> {
-		let plaintext = self.cipher(siv, ciphertext);
+	pub(crate) fn decrypt(&self, aad: &[u8], siv: &SIV, ciphertext: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+		// Wrapped immediately so the plaintext is wiped even when authentication fails
+		let plaintext = Zeroizing::new(self.cipher(siv, ciphertext));
 		let expected_siv = self.calculate_siv(aad, &plaintext);
 
 		if siv != &expected_siv {
@@ -44,7 +46,7 @@ impl SivEncryptionKeys {
 	fn cipher(&self, nonce: &SIV, data: &[u8]) -> Vec<u8> {
 		let mut result = data.to_vec();
 
-		let big_key = {
+		let mut big_key = {
 			let mut hmac = Hmac::<Sha512>::new_from_slice(&self.cipher_key[..]).expect("unexpected");
 			hmac.update(&nonce[..]);
 			hmac.finalize().into_bytes()
@@ -54,6 +56,7 @@ impl SivEncryptionKeys {
 		// Using slice notation here so this code panics in case we accidentally didn't derive the right size big_key
 		let mut cipher = ChaCha20::new_from_slices(&chacha_key[..32], &chacha_nonce[..12]).expect("unexpected");
 		cipher.apply_keystream(&mut result);
+		big_key.as_mut_slice().zeroize();
 		result
 	}
 
@@ -70,6 +73,11 @@ impl SivEncryptionKeys {
 		SIV::from_slice(&hmac.finalize().into_bytes()[..32]).expect("unexpected")
 	}
 
+	/// The inverse of `from_slice`.
+	pub(crate) fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+		Zeroizing::new([&self.siv_key[..], &self.cipher_key[..]].concat())
+	}
+
 	pub(crate) fn from_slice(bs: &[u8]) -> Option<Self> {
 		if bs.len() != 256 {
 			return None;
@@ -105,7 +113,7 @@ mod tests {
 
 		let plaintext = keys.decrypt(&id, &siv1, &ciphertext1).expect("decryption failed");
 
-		assert_eq!(plaintext, data);
+		assert_eq!(*plaintext, data);
 		assert_eq!(ciphertext1, ciphertext2);
 		assert_eq!(siv1, siv2);
 	}
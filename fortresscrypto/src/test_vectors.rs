@@ -0,0 +1,238 @@
+// Reference test vectors for Fortress's cryptography, so other implementations (mobile apps, the sync server, auditors) can check
+// that they interoperate without reverse-engineering this crate.
+// The vectors are published as test-vectors.json in this crate's directory, which is generated by
+// `cargo run -p fortresscrypto --example generate_test_vectors > fortresscrypto/test-vectors.json`.  The tests below check that
+// this crate still produces exactly the published vectors, so any change to the formats shows up as a test failure.
+//
+// All byte strings are lowercase hex.  The `notes` in the file describe each construction.
+use crate::{hash_username_for_login, siv::SivEncryptionKeys, FileKdfParameters, FileKeySuite, NetworkKeySuite, LOGIN_USERNAME_SALT, NETWORK_USERNAME_SALT};
+use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+
+
+pub const TEST_VECTORS_VERSION: u32 = 1;
+
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct TestVectors {
+	pub version: u32,
+	pub notes: Vec<String>,
+	pub network_username_salt: String,
+	pub login_username_salt: String,
+	pub siv: Vec<SivVector>,
+	pub login_id: Vec<LoginIdVector>,
+	pub network: Vec<NetworkVector>,
+	pub file: Vec<FileVector>,
+}
+
+/// SIV encryption with explicit keys.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct SivVector {
+	pub siv_key: String,
+	pub cipher_key: String,
+	pub aad: String,
+	pub plaintext: String,
+	pub siv: String,
+	pub ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct LoginIdVector {
+	pub username: String,
+	pub login_id: String,
+}
+
+/// Network key derivation (with small scrypt parameters), checked by encrypting an object with the derived keys.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct NetworkVector {
+	pub username: String,
+	pub password: String,
+	pub scrypt_log_n: u8,
+	pub scrypt_r: u32,
+	pub scrypt_p: u32,
+	pub login_key: String,
+	pub object_id: String,
+	pub plaintext: String,
+	pub siv: String,
+	pub ciphertext: String,
+}
+
+/// A complete encrypted file, as written by `encrypt_to_file`.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct FileVector {
+	pub password: String,
+	pub scrypt_log_n: u8,
+	pub scrypt_r: u32,
+	pub scrypt_p: u32,
+	pub scrypt_salt: String,
+	pub plaintext: String,
+	pub file: String,
+}
+
+
+const NOTES: &[&str] = &[
+	"All byte strings are lowercase hex.  HMAC-SHA-512-256 means HMAC-SHA-512 truncated to its first 32 bytes.",
+	"siv: keys are siv_key (128 bytes) and cipher_key (128 bytes).  siv = HMAC-SHA-512-256(siv_key, aad || plaintext || le64(len(aad)) || le64(len(plaintext))).  k = HMAC-SHA-512(cipher_key, siv); ciphertext = ChaCha20(key = k[0..32], nonce = k[32..44], counter = 0) XOR plaintext.  Decryption recomputes the siv from the decrypted plaintext and rejects the ciphertext if it doesn't match.",
+	"login_id: HMAC-SHA-512-256(login_username_salt, username).  Sent to the sync server in place of the username.",
+	"network: salt = HMAC-SHA-512-256(network_username_salt, username); k = scrypt(password, salt, N = 2^scrypt_log_n, r, p, 288 bytes); siv_key = k[0..128], cipher_key = k[128..256], login_key = k[256..288].  Objects are encrypted with siv using the object's ID as aad; the server stores ciphertext || siv.  The vectors use small scrypt parameters so they're quick to check; the real parameters are log_n = 20, r = 8, p = 128.",
+	"file: \"fortress2\\0\" || u8(scrypt_log_n) || le32(scrypt_r) || le32(scrypt_p) || scrypt_salt (32 bytes) || siv || ciphertext || checksum.  Keys are scrypt(password, scrypt_salt, N = 2^scrypt_log_n, r, p, 256 bytes), split into siv_key and cipher_key as for network keys; the aad is empty.  checksum = SHA-512-256 (SHA-512 truncated to 32 bytes) of everything before it.",
+];
+
+
+/// Generates the test vectors.  This is deterministic: every input is derived from a fixed label.
+pub fn generate() -> TestVectors {
+	let siv = [("empty", 0, 0), ("id", 32, 100), ("long", 32, 4096), ("no aad", 0, 65)]
+		.into_iter()
+		.map(|(label, aad_len, plaintext_len)| {
+			let raw_keys = sample(&format!("siv keys {label}"), 256);
+			let keys = SivEncryptionKeys::from_slice(&raw_keys).expect("internal error");
+			let aad = sample(&format!("siv aad {label}"), aad_len);
+			let plaintext = sample(&format!("siv plaintext {label}"), plaintext_len);
+			let (siv, ciphertext) = keys.encrypt(&aad, &plaintext);
+
+			SivVector {
+				siv_key: HEXLOWER.encode(&raw_keys[..128]),
+				cipher_key: HEXLOWER.encode(&raw_keys[128..]),
+				aad: HEXLOWER.encode(&aad),
+				plaintext: HEXLOWER.encode(&plaintext),
+				siv: HEXLOWER.encode(&siv[..]),
+				ciphertext: HEXLOWER.encode(&ciphertext),
+			}
+		})
+		.collect();
+
+	let login_id = ["", "alice@example.com", "ünïcödé"]
+		.into_iter()
+		.map(|username| LoginIdVector {
+			username: HEXLOWER.encode(username.as_bytes()),
+			login_id: HEXLOWER.encode(&hash_username_for_login(username.as_bytes())[..]),
+		})
+		.collect();
+
+	let network = [("alice@example.com", "correct horse battery staple", 10, 8, 1), ("bob", "", 8, 4, 2)]
+		.into_iter()
+		.map(|(username, password, log_n, r, p)| {
+			let keys = NetworkKeySuite::derive_with_params(username.as_bytes(), password.as_bytes(), log_n, r, p);
+			let object_id = sample(&format!("network object id {username}"), 32);
+			let plaintext = sample(&format!("network plaintext {username}"), 200);
+			let encrypted = keys.encrypt_object(&object_id, &plaintext);
+
+			NetworkVector {
+				username: HEXLOWER.encode(username.as_bytes()),
+				password: HEXLOWER.encode(password.as_bytes()),
+				scrypt_log_n: log_n,
+				scrypt_r: r,
+				scrypt_p: p,
+				login_key: HEXLOWER.encode(&keys.login_key[..]),
+				object_id: HEXLOWER.encode(&object_id),
+				plaintext: HEXLOWER.encode(&plaintext),
+				siv: HEXLOWER.encode(&encrypted.siv[..]),
+				ciphertext: HEXLOWER.encode(&encrypted.ciphertext),
+			}
+		})
+		.collect();
+
+	let file = [("password", 8, 8, 1, 0), ("another password", 9, 2, 3, 1000)]
+		.into_iter()
+		.map(|(password, log_n, r, p, plaintext_len)| {
+			let salt = sample(&format!("file salt {password}"), 32).try_into().expect("internal error");
+			let params = FileKdfParameters {
+				log_n,
+				r,
+				p,
+				salt,
+				hardware_challenge: None,
+			};
+			let keys = FileKeySuite::derive(password.as_bytes(), &params).expect("internal error");
+			let plaintext = sample(&format!("file plaintext {password}"), plaintext_len);
+			let mut file = Vec::new();
+			crate::encrypt_to_file(&mut file, &plaintext, &keys).expect("internal error");
+
+			FileVector {
+				password: HEXLOWER.encode(password.as_bytes()),
+				scrypt_log_n: log_n,
+				scrypt_r: r,
+				scrypt_p: p,
+				scrypt_salt: HEXLOWER.encode(&salt),
+				plaintext: HEXLOWER.encode(&plaintext),
+				file: HEXLOWER.encode(&file),
+			}
+		})
+		.collect();
+
+	TestVectors {
+		version: TEST_VECTORS_VERSION,
+		notes: NOTES.iter().map(|note| note.to_string()).collect(),
+		network_username_salt: HEXLOWER.encode(&NETWORK_USERNAME_SALT[..]),
+		login_username_salt: HEXLOWER.encode(&LOGIN_USERNAME_SALT[..]),
+		siv,
+		login_id,
+		network,
+		file,
+	}
+}
+
+
+// Deterministic bytes for vector inputs: SHA-512(label || le32(counter)) for counter = 0, 1, ..., concatenated and truncated.
+fn sample(label: &str, len: usize) -> Vec<u8> {
+	(0u32..)
+		.flat_map(|counter| Sha512::new().chain_update(label).chain_update(counter.to_le_bytes()).finalize())
+		.take(len)
+		.collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{generate, TestVectors};
+	use crate::{decrypt_from_file, siv::SivEncryptionKeys, NetworkKeySuite, SIV};
+	use data_encoding::HEXLOWER;
+	use std::io::Cursor;
+
+	fn hex(s: &str) -> Vec<u8> {
+		HEXLOWER.decode(s.as_bytes()).unwrap()
+	}
+
+	// The published vectors must match what this crate produces
+	#[test]
+	fn published_vectors() {
+		let published: TestVectors = serde_json::from_str(include_str!("../test-vectors.json")).unwrap();
+		assert_eq!(published, generate());
+	}
+
+	// The vectors decrypt, using only what's in the file
+	#[test]
+	fn vectors_decrypt() {
+		let vectors = generate();
+
+		for vector in &vectors.siv {
+			let keys = SivEncryptionKeys::from_slice(&[hex(&vector.siv_key), hex(&vector.cipher_key)].concat()).unwrap();
+			let siv = SIV::from_slice(&hex(&vector.siv)).unwrap();
+			assert_eq!(
+				*keys.decrypt(&hex(&vector.aad), &siv, &hex(&vector.ciphertext)).unwrap(),
+				hex(&vector.plaintext)
+			);
+		}
+
+		for vector in &vectors.network {
+			let keys = NetworkKeySuite::derive_with_params(
+				&hex(&vector.username),
+				&hex(&vector.password),
+				vector.scrypt_log_n,
+				vector.scrypt_r,
+				vector.scrypt_p,
+			);
+			let encrypted = crate::EncryptedObject {
+				siv: SIV::from_slice(&hex(&vector.siv)).unwrap(),
+				ciphertext: hex(&vector.ciphertext),
+			};
+			assert_eq!(*keys.decrypt_object(&hex(&vector.object_id), &encrypted).unwrap(), hex(&vector.plaintext));
+		}
+
+		for vector in &vectors.file {
+			let (plaintext, _) = decrypt_from_file(&mut Cursor::new(hex(&vector.file)), &hex(&vector.password)).unwrap();
+			assert_eq!(*plaintext, hex(&vector.plaintext));
+		}
+	}
+}
@@ -0,0 +1,26 @@
+// Feeds arbitrary database files, and arbitrary decrypted file contents, into the database file parser.
+// A corrupted or tampered file must be rejected with an error, never panic.
+//
+// Run with: cargo +nightly fuzz run database_file
+#![no_main]
+
+use libfortress::fuzzing;
+use libfuzzer_sys::fuzz_target;
+
+
+fuzz_target!(|data: &[u8]| {
+	let Some((&selector, data)) = data.split_first() else {
+		return;
+	};
+
+	match selector % 2 {
+		// A whole file, header and checksum included
+		0 => {
+			let _ = fuzzing::parse_database_file(data, "password");
+		},
+		// The JSON inside a file, as if it had decrypted correctly
+		_ => {
+			let _ = fuzzing::parse_database_payload(data);
+		},
+	}
+});
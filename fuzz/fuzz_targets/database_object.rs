@@ -0,0 +1,13 @@
+// Feeds arbitrary JSON into the parser for database objects (entries, directories, and settings), the way objects from the
+// sync server are parsed once decrypted.  Anything that parses must pass the checks in libfortress::fuzzing::parse_object.
+//
+// Run with: cargo +nightly fuzz run database_object
+#![no_main]
+
+use libfortress::fuzzing;
+use libfuzzer_sys::fuzz_target;
+
+
+fuzz_target!(|data: &[u8]| {
+	fuzzing::parse_object(data);
+});
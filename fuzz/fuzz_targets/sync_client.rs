@@ -0,0 +1,51 @@
+// Feeds arbitrary server responses into the sync client's parsing paths.
+// The client must never panic on network-controlled data; malformed responses are either rejected
+// with an error or ignored.
+//
+// Run with: cargo +nightly fuzz run sync_client
+#![no_main]
+
+use libfortress::{fortresscrypto::NetworkKeySuite, fuzzing, FortressError, ID};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+
+fn network_key_suite() -> &'static NetworkKeySuite {
+	// Deriving is expensive, so only do it once
+	static KEYS: OnceLock<NetworkKeySuite> = OnceLock::new();
+	KEYS.get_or_init(|| NetworkKeySuite::derive(b"username", b"password"))
+}
+
+
+fn check_decoded(result: Result<Option<ID>, FortressError>, id: &ID) {
+	match result {
+		Ok(Some(decoded_id)) => assert_eq!(&decoded_id, id),
+		Ok(None) | Err(FortressError::SyncMalformedResponse) | Err(FortressError::SyncInconsistentServer) | Err(FortressError::SyncLimitExceeded { .. }) => (),
+		Err(err) => panic!("Unexpected error classification: {:?}", err),
+	}
+}
+
+
+fuzz_target!(|data: &[u8]| {
+	let Some((&selector, data)) = data.split_first() else {
+		return;
+	};
+	let id = ID::from_slice(&[0x42; 32]).unwrap();
+
+	match selector % 3 {
+		// A listing from GET /objects
+		0 => match fuzzing::parse_object_list(data) {
+			Ok(_) | Err(FortressError::SyncMalformedResponse) | Err(FortressError::SyncLimitExceeded { .. }) => (),
+			Err(err) => panic!("Unexpected error classification: {:?}", err),
+		},
+		// A raw object body from GET /object/:id
+		1 => check_decoded(fuzzing::decode_server_object(network_key_suite(), &id, data), &id),
+		// A correctly encrypted object body with arbitrary contents, to get past authentication
+		_ => {
+			let encrypted = network_key_suite().encrypt_object(&id[..], data);
+			let body = [&encrypted.ciphertext, encrypted.siv.as_ref()].concat();
+
+			check_decoded(fuzzing::decode_server_object(network_key_suite(), &id, &body), &id);
+		},
+	}
+});
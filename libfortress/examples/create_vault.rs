@@ -0,0 +1,73 @@
+// Creates a new database, fills it with a few entries, saves it to disk, and loads it back.
+//
+// Run with: cargo run -p libfortress --example create_vault
+use libfortress::{Database, Directory, Entry, EntryHistory, FieldKind, FieldMetadata};
+use std::collections::HashMap;
+
+
+fn main() {
+	let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+	let path = tmp_dir.path().join("database.fortress");
+
+	// The username and password are used for both file encryption and sync.
+	// NOTE: This derives the sync keys, which takes a while (especially in release builds, where the parameters are much stronger).
+	let mut db = Database::new_with_password("alice@example.com", "correct horse battery staple");
+
+	// Directories form a tree under the root directory
+	let mut work = Directory::new();
+	work.rename("Work");
+	let work_id = *work.get_id();
+	db.add_directory(work);
+
+	// Entries are a set of fields, and every edit is kept in the entry's history
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new(fields(&[
+		("title", "Email"),
+		("username", "alice@example.com"),
+		("password", "hunter2"),
+		("url", "https://mail.example.com"),
+	])));
+	entry.edit(EntryHistory::new(fields(&[("password", "hunter3")])));
+	let email_id = *entry.get_id();
+	db.add_entry(entry);
+	db.move_object(&email_id, &work_id);
+
+	// Fields can carry metadata, such as whether they should be masked when displayed
+	let mut entry = Entry::new();
+	entry.edit(
+		EntryHistory::new(fields(&[("title", "Bank"), ("username", "alice"), ("PIN", "1234")])).with_metadata(
+			"PIN",
+			FieldMetadata {
+				kind: FieldKind::Secret,
+				protected: true,
+				derived: None,
+			},
+		),
+	);
+	db.add_entry(entry);
+
+	db.save_to_path(&path).expect("Failed to save database");
+
+	// Load it back
+	let db = Database::load_from_path(&path, "correct horse battery staple").expect("Failed to load database");
+
+	for directory in db.list_directories() {
+		println!("Directory: {}", directory.get_name().unwrap_or("(unnamed)"));
+
+		for entry in directory.entries.iter().filter_map(|id| db.get_entry_by_id(id)) {
+			println!("  Entry: {}", entry["title"]);
+
+			for field in entry.get_fields() {
+				let value = if field.protected { "********" } else { field.value };
+				println!("    {}: {}", field.name, value);
+			}
+
+			println!("    ({} edits)", entry.get_history().len());
+		}
+	}
+}
+
+
+fn fields(data: &[(&str, &str)]) -> HashMap<String, String> {
+	data.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
@@ -0,0 +1,48 @@
+// Exports a database as JSON (see EXPORT.md).
+//
+// Run with: cargo run -p libfortress --example export_json [-- <path> <password>]
+// Without arguments, a small example database is built in memory and exported with secrets left out.
+use libfortress::{Database, Entry, EntryHistory, ExportOptions};
+use std::{env, io};
+
+
+fn main() {
+	let args = env::args().skip(1).collect::<Vec<_>>();
+
+	let (db, options) = match args.as_slice() {
+		[path, password] => (
+			Database::load_from_path(path, password).expect("Failed to load database"),
+			ExportOptions::default(),
+		),
+		[] => (
+			example_database(),
+			ExportOptions {
+				include_history: false,
+				include_secrets: false,
+			},
+		),
+		_ => {
+			eprintln!("Usage: export_json [<path> <password>]");
+			std::process::exit(1);
+		},
+	};
+
+	db.export_json(io::stdout().lock(), &options).expect("Export failed");
+	println!();
+}
+
+
+fn example_database() -> Database {
+	let mut db = Database::new_with_password("alice@example.com", "correct horse battery staple");
+
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new(
+		[("title", "Email"), ("username", "alice@example.com"), ("password", "hunter2")]
+			.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect(),
+	));
+	db.add_entry(entry);
+
+	db
+}
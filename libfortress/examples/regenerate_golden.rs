@@ -0,0 +1,27 @@
+// Writes the golden database file for the current format version to tests/data/golden (see src/golden.rs).  Only run this
+// when adding a new format version: golden files for older versions must never change.
+//
+// Run with: cargo run -p libfortress --example regenerate_golden --features testing
+use libfortress::{
+	golden::{golden_database, golden_file_name},
+	CURRENT_FORMAT_VERSION,
+};
+use std::path::Path;
+
+
+fn main() {
+	let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/golden");
+	std::fs::create_dir_all(&dir).expect("Failed to create tests/data/golden");
+
+	// Saved elsewhere first, so the lock file that comes with saving (see src/file_lock.rs) doesn't end up next to it
+	let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+	let tmp_path = tmp_dir.path().join("golden.fortress");
+	golden_database(CURRENT_FORMAT_VERSION)
+		.save_to_path(&tmp_path)
+		.expect("Failed to save golden database");
+
+	let path = dir.join(golden_file_name(CURRENT_FORMAT_VERSION));
+	std::fs::copy(&tmp_path, &path).expect("Failed to write golden file");
+
+	println!("Wrote {}", path.display());
+}
@@ -0,0 +1,52 @@
+// Syncs two copies of a database, as if on two devices, through the in-memory sync server used by the integration tests.
+//
+// Run with: cargo run -p libfortress --example sync
+#[path = "../tests/sync_server/mod.rs"]
+#[allow(dead_code)]
+mod sync_server;
+
+use libfortress::{Database, Entry, EntryHistory};
+use reqwest::Url;
+
+
+fn main() {
+	// Both devices log in with the same username and password, so they derive the same sync keys.
+	let mut laptop = Database::new_with_password("alice@example.com", "correct horse battery staple");
+	let mut phone = Database::new_with_password("alice@example.com", "correct horse battery staple");
+
+	// A real deployment would point at a fortress server instead
+	let sync_url = Url::parse(&sync_server::server(laptop.get_login_key().clone())).expect("Bad sync URL");
+	laptop.set_sync_url(Some(sync_url.clone()));
+	phone.set_sync_url(Some(sync_url));
+
+	// Add an entry on the laptop and push it to the server
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new(
+		[("title", "Email"), ("username", "alice@example.com"), ("password", "hunter2")]
+			.iter()
+			.map(|(key, value)| (key.to_string(), value.to_string()))
+			.collect(),
+	));
+	let entry_id = *entry.get_id();
+	laptop.add_entry(entry);
+	laptop.sync().expect("Laptop sync failed");
+
+	// The phone pulls it down
+	phone.sync().expect("Phone sync failed");
+	println!("Phone received: {}", phone.get_entry_by_id(&entry_id).expect("Entry missing on phone")["title"]);
+
+	// Edits on the phone make their way back to the laptop
+	phone
+		.get_entry_by_id_mut(&entry_id)
+		.unwrap()
+		.edit(EntryHistory::new([("password".to_string(), "hunter3".to_string())].into_iter().collect()));
+	phone.sync().expect("Phone sync failed");
+	laptop.sync().expect("Laptop sync failed");
+
+	let entry = laptop.get_entry_by_id(&entry_id).unwrap();
+	println!(
+		"Laptop sees {} edits; password updated: {}",
+		entry.get_history().len(),
+		entry["password"] == "hunter3"
+	);
+}
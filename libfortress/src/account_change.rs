@@ -0,0 +1,39 @@
+// Guarding against merging two accounts into one database.
+// Changing the username (see `Database::change_password`) switches the database to a different account on the sync server.
+// If that account already has objects, perhaps because the user typed the credentials of another account they own, the next
+// sync would merge the two accounts' objects together, which can't be undone.  So the database remembers which account it
+// belongs to (the one it was created with or last synced with), and sync refuses with FortressError::SyncAccountChanged if the
+// account changed and the new account's server copy isn't empty.  Once the user has confirmed that merging is what they want,
+// `confirm_account_change` lets the sync go ahead.
+//
+// Switching to an account that has nothing on the server yet is allowed, since nothing foreign gets merged in.
+use crate::{Database, FortressError};
+
+
+/// Passed to `Database::confirm_account_change` to confirm that merging the objects of two accounts is intended.
+#[derive(Copy, Clone, Debug)]
+pub struct AccountChangeConfirmed;
+
+
+impl Database {
+	/// True if the database belongs to a different account than the one its current username refers to; i.e. the username was
+	/// changed since it was created or last synced.
+	pub fn account_changed(&self) -> bool {
+		self.account_login_id.as_ref().is_some_and(|id| id != self.get_login_id())
+	}
+
+	/// Lets the next sync merge the current account's objects into this database even though it belongs to a different account.
+	/// See the account_change module.
+	pub fn confirm_account_change(&mut self, _confirmation: AccountChangeConfirmed) {
+		self.account_login_id = Some(*self.get_login_id());
+	}
+
+	/// Called by sync with the server's object list before anything is merged.
+	pub(crate) fn check_account(&self, server_is_empty: bool) -> Result<(), FortressError> {
+		if self.account_changed() && !server_is_empty {
+			Err(FortressError::SyncAccountChanged)
+		} else {
+			Ok(())
+		}
+	}
+}
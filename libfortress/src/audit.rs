@@ -0,0 +1,200 @@
+// Password health audits, for a frontend's "security check" screen and the CLI's `audit` command.  `Database::audit` looks at
+// the current state of every entry outside the trash and lists the ones worth the user's attention: weak, reused and old
+// passwords, entries with a password but no URL (which autofill can't match), and entries that have never been used (see
+// the usage module).  Secure notes (see the secure_note module) have no password, so they're left out.
+//
+// Password strength is estimated from the password's length and the kinds of characters in it, as if each character were
+// picked at random from those classes.  That overestimates human-chosen passwords, which are rarely random, so a password
+// that's reported as weak certainly is, but one that isn't may still be guessable.
+//
+// The health score summarizes the password problems: the percentage of entries with a password that are neither weak, reused
+// nor old.  Missing URLs and unused entries are only reported, since they don't make an account any less safe.
+use crate::{unix_timestamp, Database, ID, WEAK_ENTROPY_BITS};
+use serde::Serialize;
+use std::collections::HashMap;
+
+
+/// How long a password can go unchanged before an audit reports it as old, by default (nanoseconds).
+pub const OLD_PASSWORD_AGE: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditOptions {
+	/// Passwords estimated to have fewer bits of entropy than this are weak (see the audit module).
+	pub weak_bits: f64,
+	/// Passwords that haven't been changed for longer than this are old (nanoseconds).
+	pub max_password_age: u64,
+}
+
+impl Default for AuditOptions {
+	fn default() -> AuditOptions {
+		AuditOptions {
+			weak_bits: WEAK_ENTROPY_BITS,
+			max_password_age: OLD_PASSWORD_AGE,
+		}
+	}
+}
+
+
+/// Returned by `Database::audit`.  Entries are listed by ID, in ascending order.
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct AuditReport {
+	/// Entries outside the trash that aren't secure notes.
+	pub entries: usize,
+	/// Of those, the entries with a password.
+	pub entries_with_password: usize,
+	pub weak_passwords: Vec<ID>,
+	/// Groups of entries that share a password.
+	pub reused_passwords: Vec<Vec<ID>>,
+	pub old_passwords: Vec<ID>,
+	/// Entries with a password but no URL.
+	pub missing_urls: Vec<ID>,
+	pub never_used: Vec<ID>,
+	/// Percentage of entries with a password that aren't weak, reused, or old.  100 if no entry has a password.
+	pub health_score: u8,
+}
+
+
+impl Database {
+	/// Checks the passwords in the database (see the audit module).
+	pub fn audit(&self, options: &AuditOptions) -> AuditReport {
+		let now = unix_timestamp();
+		let trash = self.list_trash_recursive();
+		let mut report = AuditReport::default();
+		let mut by_password: HashMap<&str, Vec<ID>> = HashMap::new();
+
+		let mut entries = self
+			.list_entries()
+			.filter(|entry| !entry.is_note() && !trash.contains(entry.get_id()))
+			.collect::<Vec<_>>();
+		entries.sort_by_key(|entry| *entry.get_id());
+
+		for entry in entries {
+			let id = *entry.get_id();
+			report.entries += 1;
+
+			if entry.get_use_count() == 0 {
+				report.never_used.push(id);
+			}
+
+			let Some(password) = entry.get("password").filter(|password| !password.is_empty()) else {
+				continue;
+			};
+			report.entries_with_password += 1;
+			by_password.entry(password).or_default().push(id);
+
+			if estimate_password_strength(password) < options.weak_bits {
+				report.weak_passwords.push(id);
+			}
+
+			if entry
+				.password_changed_at()
+				.is_some_and(|changed| now.saturating_sub(changed) > options.max_password_age)
+			{
+				report.old_passwords.push(id);
+			}
+
+			if entry.get("url").is_none_or(|url| url.trim().is_empty()) {
+				report.missing_urls.push(id);
+			}
+		}
+
+		report.reused_passwords = by_password.into_values().filter(|ids| ids.len() > 1).collect();
+		report.reused_passwords.sort();
+
+		let mut unhealthy = report
+			.weak_passwords
+			.iter()
+			.chain(report.old_passwords.iter())
+			.chain(report.reused_passwords.iter().flatten())
+			.collect::<Vec<_>>();
+		unhealthy.sort();
+		unhealthy.dedup();
+		report.health_score = match report.entries_with_password {
+			0 => 100,
+			n => ((n - unhealthy.len()) * 100 / n) as u8,
+		};
+
+		report
+	}
+}
+
+
+/// Estimated bits of entropy in `password`, as if each character were picked at random from the classes (lowercase, uppercase,
+/// digits, and everything else) it uses.  Only an upper bound for passwords a person chose (see the audit module).
+pub fn estimate_password_strength(password: &str) -> f64 {
+	let has = |is_class: fn(&char) -> bool| password.chars().any(|c| is_class(&c));
+	let pool = [
+		(has(char::is_ascii_lowercase), 26.0),
+		(has(char::is_ascii_uppercase), 26.0),
+		(has(char::is_ascii_digit), 10.0),
+		// Printable ASCII symbols, and anything else counts the same
+		(password.chars().any(|c| !c.is_ascii_alphanumeric()), 33.0),
+	]
+	.into_iter()
+	.filter(|(used, _)| *used)
+	.map(|(_, size)| size)
+	.sum::<f64>();
+
+	if pool == 0.0 {
+		0.0
+	} else {
+		password.chars().count() as f64 * pool.log2()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{estimate_password_strength, AuditOptions};
+	use crate::{testing::add_entry, Database, Entry, NoteFormat};
+
+	#[test]
+	fn audit() {
+		let mut db = Database::new_with_password("username", "password");
+		assert_eq!(db.audit(&AuditOptions::default()).health_score, 100);
+
+		let strong = add_entry(&mut db, &[("password", "nXq4Vb9TmR2kLp7WzH3c"), ("url", "https://example.com")]);
+		let weak = add_entry(&mut db, &[("password", "hunter2"), ("url", "https://example.org")]);
+		let reused = [
+			add_entry(&mut db, &[("password", "Gq8rW2mZtK5vNb7XpL4d"), ("url", "https://a.example")]),
+			add_entry(&mut db, &[("password", "Gq8rW2mZtK5vNb7XpL4d")]),
+		];
+		let no_password = add_entry(&mut db, &[("title", "Wifi")]);
+		db.add_entry(Entry::new_note("Note", "hunter2", NoteFormat::Plain));
+		let trashed = add_entry(&mut db, &[("password", "123")]);
+		assert!(db.trash_object(&trashed));
+		assert!(db.record_use(&strong));
+
+		let report = db.audit(&AuditOptions::default());
+		assert_eq!(report.entries, 5);
+		assert_eq!(report.entries_with_password, 4);
+		assert_eq!(report.weak_passwords, [weak]);
+		let mut reused_group = reused.to_vec();
+		reused_group.sort();
+		assert_eq!(report.reused_passwords, [reused_group]);
+		assert!(report.old_passwords.is_empty());
+		assert_eq!(report.missing_urls, [reused[1]]);
+		assert_eq!(report.never_used.len(), 4);
+		assert!(!report.never_used.contains(&strong) && report.never_used.contains(&no_password));
+		// Only the strong password is healthy
+		assert_eq!(report.health_score, 25);
+
+		// Every password is old if any age is too old
+		let report = db.audit(&AuditOptions {
+			max_password_age: 0,
+			..AuditOptions::default()
+		});
+		assert_eq!(report.old_passwords.len(), 4);
+		assert_eq!(report.health_score, 0);
+	}
+
+	#[test]
+	fn password_strength() {
+		assert_eq!(estimate_password_strength(""), 0.0);
+		assert_eq!(estimate_password_strength("aaaa"), 4.0 * 26f64.log2());
+		assert_eq!(estimate_password_strength("aA1!"), 4.0 * 95f64.log2());
+		assert!(estimate_password_strength("hunter2") < crate::WEAK_ENTROPY_BITS);
+		assert!(estimate_password_strength("nXq4Vb9TmR2kLp7WzH3c") > crate::WEAK_ENTROPY_BITS);
+	}
+}
@@ -0,0 +1,181 @@
+// Rotating backups of the database file.  With a backup policy set, every full save (`save_to_path`, and the full saves
+// `save_incremental` falls back to) first copies the file being replaced to `<path>.1`, after moving the older copies along to
+// `<path>.2`, `<path>.3`, and so on, and deleting those past the policy's limit.  That gives a local way back from a corrupted
+// save or a mistaken bulk deletion without needing the sync server.
+//
+// Journal appends (see the journal module) don't make backups, but a backup's journal is kept with it (`<path>.1.journal`), so
+// each backup opens with `load_from_path` to the state it was in when it was replaced.  So is its signed digest, if saves are
+// signed (`<path>.1.sig`; see the signature module).  The policy is local to this device.
+use crate::{Database, FortressError};
+use serde::{Deserialize, Serialize};
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+
+// Files kept next to the database file that belong to the version of it they're next to: its journal, and its signed digest (see
+// the signature module).  Each backup keeps its own.
+const SIDECARS: &[&str] = &[".journal", ".sig"];
+
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct BackupPolicy {
+	/// How many previous versions of the database file to keep.  0 turns backups off (existing backups are left alone).
+	pub keep: usize,
+}
+
+impl BackupPolicy {
+	pub fn new(keep: usize) -> BackupPolicy {
+		BackupPolicy { keep }
+	}
+
+	/// Used to leave the policy out of the saved database unless backups were turned on.
+	pub(crate) fn is_off(&self) -> bool {
+		self.keep == 0
+	}
+}
+
+
+impl Database {
+	pub fn get_backup_policy(&self) -> BackupPolicy {
+		self.backup_policy
+	}
+
+	/// Sets how many backups are kept from the next full save on.  Lowering the limit deletes the extra backups on that save.
+	pub fn set_backup_policy(&mut self, policy: BackupPolicy) {
+		self.backup_policy = policy;
+	}
+
+	/// The backups of the database file at `path` that exist, newest first.  Each one can be opened with `load_from_path`.
+	pub fn list_backups<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
+		(1..).map(|n| numbered_path(path.as_ref(), n)).take_while(|backup| backup.exists()).collect()
+	}
+}
+
+
+/// Moves the backups of `path` along by one, copies `path` (and its sidecar files) to the first backup, and deletes the backups
+/// past `policy.keep`.  Does nothing if backups are off.
+pub(crate) fn rotate(path: &Path, policy: BackupPolicy) -> Result<(), FortressError> {
+	if policy.is_off() {
+		return Ok(());
+	}
+
+	if path.exists() {
+		remove_backup(path, policy.keep)?;
+
+		for n in (1..policy.keep).rev() {
+			ignore_missing(fs::rename(numbered_path(path, n), numbered_path(path, n + 1)))?;
+			for suffix in SIDECARS {
+				ignore_missing(fs::rename(sidecar_of(path, n, suffix), sidecar_of(path, n + 1, suffix)))?;
+			}
+		}
+
+		fs::copy(path, numbered_path(path, 1))?;
+		for suffix in SIDECARS {
+			ignore_missing(fs::copy(sidecar_of(path, 0, suffix), sidecar_of(path, 1, suffix)).map(|_| ()))?;
+		}
+	}
+
+	// Prune backups left from a higher limit
+	let mut n = policy.keep + 1;
+	while numbered_path(path, n).exists() || SIDECARS.iter().any(|suffix| sidecar_of(path, n, suffix).exists()) {
+		remove_backup(path, n)?;
+		n += 1;
+	}
+
+	Ok(())
+}
+
+
+fn remove_backup(path: &Path, n: usize) -> Result<(), FortressError> {
+	ignore_missing(fs::remove_file(numbered_path(path, n)))?;
+	for suffix in SIDECARS {
+		ignore_missing(fs::remove_file(sidecar_of(path, n, suffix)))?;
+	}
+	Ok(())
+}
+
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+	match result {
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+		result => result,
+	}
+}
+
+
+// `path` with a `.n` suffix
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+	let mut numbered_path = path.as_os_str().to_owned();
+	numbered_path.push(format!(".{n}"));
+	PathBuf::from(numbered_path)
+}
+
+
+// Sidecar file `suffix` of backup `n` of `path`, or of `path` itself if `n` is 0
+fn sidecar_of(path: &Path, n: usize, suffix: &str) -> PathBuf {
+	let mut sidecar_path = if n == 0 {
+		path.as_os_str().to_owned()
+	} else {
+		numbered_path(path, n).into_os_string()
+	};
+	sidecar_path.push(suffix);
+	PathBuf::from(sidecar_path)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::BackupPolicy;
+	use crate::{testing::new_entry, Database};
+	use tempfile::tempdir;
+
+	#[test]
+	fn backups() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		// Large enough that a small edit is journaled rather than saved in full
+		db.add_entries((0..100).map(|i| new_entry(&format!("Filler {i}"))));
+
+		// Off by default
+		db.save_to_path(&path).unwrap();
+		db.save_to_path(&path).unwrap();
+		assert!(Database::list_backups(&path).is_empty());
+
+		db.set_backup_policy(BackupPolicy::new(3));
+		let mut saved = Vec::new();
+		for i in 0..5 {
+			saved.push(db.clone());
+			db.add_entry(new_entry(&format!("Entry {i}")));
+			db.save_to_path(&path).unwrap();
+		}
+
+		// Newest first, each one what the file held before a save
+		let backups = Database::list_backups(&path);
+		assert_eq!(backups.len(), 3);
+		assert_eq!(backups[0], tmp_dir.path().join("test.fortressdb.1"));
+		for (backup, expected) in backups.iter().zip(saved.iter().rev()) {
+			assert_eq!(
+				Database::load_from_path(backup, "password").unwrap().list_entries().count(),
+				expected.list_entries().count()
+			);
+		}
+
+		// A backup keeps its journal, so it opens with the edits journaled since its last full save
+		db.save_incremental(&path).unwrap();
+		db.add_entry(new_entry("Journaled"));
+		db.save_incremental(&path).unwrap();
+		assert!(tmp_dir.path().join("test.fortressdb.journal").exists());
+		let journaled = db.clone();
+		db.save_to_path(&path).unwrap();
+		assert_eq!(Database::load_from_path(&backups[0], "password").unwrap(), journaled);
+
+		// Lowering the limit prunes the extra backups
+		db.set_backup_policy(BackupPolicy::new(1));
+		db.save_to_path(&path).unwrap();
+		assert_eq!(Database::list_backups(&path), vec![backups[0].clone()]);
+		assert_eq!(Database::load_from_path(&path, "password").unwrap().get_backup_policy(), BackupPolicy::new(1));
+	}
+}
@@ -0,0 +1,404 @@
+// Checking entry passwords against known data breaches.  Only available with the `breach` feature.
+//
+// Online, passwords are checked with Have I Been Pwned's range API using k-anonymity: only the first 5 hex digits of each
+// password's SHA-1 hash are sent, and the server answers with the rest of every breached hash starting with them (padded with
+// fake ones, so the response size doesn't give anything away either), which are compared locally.  Requests go through the
+// sync proxy, if one is set (see `Database::set_sync_proxy`).
+//
+// Offline, passwords are checked against a bloom filter file instead, which can be built from HIBP's downloadable list of
+// hashes with `build_breach_bloom_filter`.  Bloom filters can have false positives (about one in a million, as built here), and
+// don't know how often a password was seen.  The file is:
+//
+// * the magic bytes `FTBLOOM1`,
+// * the number of bits in the filter, m, as a little-endian u64,
+// * the number of hash functions, k, as a little-endian u32,
+// * the m bits, packed 8 to a byte starting with the least significant bit.
+//
+// A password's SHA-1 hash is in the filter if bits `(h1 + i * h2) mod m` are set for every i below k, where h1 and h2 are the
+// hash's first and second 8 bytes as little-endian u64s (h2 with its lowest bit set).
+use crate::{sync_options::proxy_url, ApiError, Database, FieldKind, FortressError, ID};
+use reqwest::blocking::Client;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::{
+	collections::{BTreeMap, HashSet},
+	fs::File,
+	io::{self, BufRead, Read, Seek, SeekFrom, Write},
+	path::PathBuf,
+};
+use url::Url;
+
+
+/// Have I Been Pwned's range API; the first 5 hex digits of a hash are appended to it.
+pub const HIBP_RANGE_API: &str = "https://api.pwnedpasswords.com/range/";
+
+const BLOOM_FILTER_MAGIC: &[u8; 8] = b"FTBLOOM1";
+const BLOOM_FILTER_HEADER_SIZE: u64 = 8 + 8 + 4;
+// About one false positive in a million
+const BLOOM_FILTER_BITS_PER_HASH: u64 = 29;
+const BLOOM_FILTER_HASH_FUNCTIONS: u32 = 20;
+
+
+/// Options for `Database::check_breached`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BreachCheckOptions {
+	pub source: BreachSource,
+}
+
+impl Default for BreachCheckOptions {
+	fn default() -> BreachCheckOptions {
+		BreachCheckOptions {
+			source: BreachSource::Online {
+				api_url: Url::parse(HIBP_RANGE_API).expect("internal error"),
+			},
+		}
+	}
+}
+
+
+/// Where `Database::check_breached` looks passwords up (see the breach module).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BreachSource {
+	/// A server with Have I Been Pwned's range API, at `api_url` (with a trailing slash).
+	Online { api_url: Url },
+	/// A bloom filter file.
+	Offline { bloom_filter: PathBuf },
+}
+
+
+/// A breached password found by `Database::check_breached`.
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct BreachHit {
+	/// The entry the password is in.
+	pub id: ID,
+	/// The field the password is in.
+	pub field: String,
+	/// How many times the password was seen in breaches, if known (bloom filters don't say).
+	pub count: Option<u64>,
+}
+
+
+impl Database {
+	/// Checks the secret fields (such as passwords) of every entry outside the trash against known breaches (see the breach
+	/// module).  Returns the breached ones, ordered by entry ID and field.
+	/// Fails with FortressError::BreachCheckError if the range API can't be reached, or FortressError::MalformedBloomFilter if
+	/// the bloom filter isn't one.
+	pub fn check_breached(&self, options: &BreachCheckOptions) -> Result<Vec<BreachHit>, FortressError> {
+		let trash = self.list_trash_recursive();
+		let mut secrets = BTreeMap::new();
+
+		for entry in self.list_entries().filter(|entry| !trash.contains(entry.get_id())) {
+			for field in entry.get_fields() {
+				if field.kind == FieldKind::Secret && !field.value.is_empty() {
+					let hash: [u8; 20] = Sha1::digest(field.value.as_bytes()).into();
+					secrets.insert((*entry.get_id(), field.name.to_string()), hash);
+				}
+			}
+		}
+
+		let breached = match &options.source {
+			BreachSource::Online { api_url } => self.check_range_api(api_url, secrets.values().copied().collect())?,
+			BreachSource::Offline { bloom_filter } => {
+				let mut filter = BloomFilter::open(File::open(bloom_filter)?)?;
+				let mut breached = BTreeMap::new();
+
+				for hash in secrets.values().copied().collect::<HashSet<_>>() {
+					if filter.contains(&hash)? {
+						breached.insert(hash, None);
+					}
+				}
+
+				breached
+			},
+		};
+
+		Ok(secrets
+			.into_iter()
+			.filter_map(|((id, field), hash)| {
+				Some(BreachHit {
+					id,
+					field,
+					count: *breached.get(&hash)?,
+				})
+			})
+			.collect())
+	}
+
+	/// Looks up `hashes` with the range API at `api_url`, one request per prefix.  Returns the breached ones and how often they
+	/// were seen.
+	fn check_range_api(&self, api_url: &Url, hashes: HashSet<[u8; 20]>) -> Result<BTreeMap<[u8; 20], Option<u64>>, FortressError> {
+		let mut builder = Client::builder().https_only(!cfg!(debug_assertions));
+		if let Some(proxy) = &self.sync_proxy {
+			builder = builder.proxy(reqwest::Proxy::all(proxy_url(proxy)?).map_err(|_| FortressError::SyncBadProxy)?);
+		}
+		let client = builder.build().expect("Failed to build HTTP client");
+
+		let mut by_prefix = BTreeMap::<String, Vec<[u8; 20]>>::new();
+		for hash in hashes {
+			by_prefix.entry(hex_upper(&hash)[..5].to_string()).or_default().push(hash);
+		}
+
+		let mut breached = BTreeMap::new();
+
+		for (prefix, hashes) in by_prefix {
+			let response = client
+				.get(format!("{api_url}{prefix}"))
+				.header("Add-Padding", "true")
+				.send()
+				.map_err(ApiError::from)
+				.map_err(FortressError::BreachCheckError)?;
+			let status = response.status();
+			let text = response.text().map_err(ApiError::from).map_err(FortressError::BreachCheckError)?;
+			if !status.is_success() {
+				return Err(FortressError::BreachCheckError(ApiError::ApiError(status.into(), text)));
+			}
+
+			// Lines of `SUFFIX:COUNT`; padding has a count of 0
+			let counts = text
+				.lines()
+				.filter_map(|line| {
+					let (suffix, count) = line.trim().split_once(':')?;
+					Some((suffix.to_ascii_uppercase(), count.parse::<u64>().ok()?))
+				})
+				.filter(|(_, count)| *count > 0)
+				.collect::<BTreeMap<_, _>>();
+
+			for hash in hashes {
+				if let Some(count) = counts.get(&hex_upper(&hash)[5..]) {
+					breached.insert(hash, Some(*count));
+				}
+			}
+		}
+
+		Ok(breached)
+	}
+}
+
+
+/// Builds a bloom filter file for `BreachSource::Offline` from `hash_list`, which has a hex SHA-1 hash at the start of each
+/// line, like HIBP's downloadable list (anything after a `:` is ignored).  `expected_count` is about how many hashes there are,
+/// to size the filter; the filter gets less accurate if there are many more.  Lines that don't start with a hash are skipped.
+pub fn build_breach_bloom_filter<R: BufRead, W: Write>(hash_list: R, expected_count: u64, mut output: W) -> io::Result<()> {
+	let mut filter = BloomFilter::new(expected_count.max(1) * BLOOM_FILTER_BITS_PER_HASH, BLOOM_FILTER_HASH_FUNCTIONS);
+
+	for line in hash_list.lines() {
+		let line = line?;
+		let hex = line.split(':').next().unwrap_or_default().trim();
+		let mut hash = [0u8; 20];
+
+		if hex.len() == 40 && data_encoding::HEXUPPER_PERMISSIVE.decode_mut(hex.as_bytes(), &mut hash).is_ok() {
+			filter.insert(&hash);
+		}
+	}
+
+	output.write_all(BLOOM_FILTER_MAGIC)?;
+	output.write_all(&filter.bits.to_le_bytes())?;
+	output.write_all(&filter.hash_functions.to_le_bytes())?;
+	output.write_all(&filter.data)
+}
+
+
+/// A bloom filter, either built in memory or read from a file one byte at a time as needed (the file can be gigabytes).
+struct BloomFilter {
+	bits: u64,
+	hash_functions: u32,
+	data: Vec<u8>,
+	file: Option<File>,
+}
+
+impl BloomFilter {
+	fn new(bits: u64, hash_functions: u32) -> BloomFilter {
+		BloomFilter {
+			bits,
+			hash_functions,
+			data: vec![0; bits.div_ceil(8) as usize],
+			file: None,
+		}
+	}
+
+	fn open(mut file: File) -> Result<BloomFilter, FortressError> {
+		let mut header = [0u8; BLOOM_FILTER_HEADER_SIZE as usize];
+		file.read_exact(&mut header).map_err(|_| FortressError::MalformedBloomFilter)?;
+
+		let bits = u64::from_le_bytes(header[8..16].try_into().expect("internal error"));
+		let hash_functions = u32::from_le_bytes(header[16..20].try_into().expect("internal error"));
+		if &header[..8] != BLOOM_FILTER_MAGIC || bits == 0 || file.metadata()?.len() != BLOOM_FILTER_HEADER_SIZE + bits.div_ceil(8) {
+			return Err(FortressError::MalformedBloomFilter);
+		}
+
+		Ok(BloomFilter {
+			bits,
+			hash_functions,
+			data: Vec::new(),
+			file: Some(file),
+		})
+	}
+
+	fn bit_indexes(&self, hash: &[u8; 20]) -> impl Iterator<Item = u64> {
+		let h1 = u64::from_le_bytes(hash[..8].try_into().expect("internal error"));
+		let h2 = u64::from_le_bytes(hash[8..16].try_into().expect("internal error")) | 1;
+		let bits = self.bits;
+
+		(0..u64::from(self.hash_functions)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+	}
+
+	fn insert(&mut self, hash: &[u8; 20]) {
+		for index in self.bit_indexes(hash).collect::<Vec<_>>() {
+			self.data[(index / 8) as usize] |= 1 << (index % 8);
+		}
+	}
+
+	fn contains(&mut self, hash: &[u8; 20]) -> io::Result<bool> {
+		for index in self.bit_indexes(hash).collect::<Vec<_>>() {
+			let byte = match &mut self.file {
+				Some(file) => {
+					let mut byte = [0u8];
+					file.seek(SeekFrom::Start(BLOOM_FILTER_HEADER_SIZE + index / 8))?;
+					file.read_exact(&mut byte)?;
+					byte[0]
+				},
+				None => self.data[(index / 8) as usize],
+			};
+
+			if byte & (1 << (index % 8)) == 0 {
+				return Ok(false);
+			}
+		}
+
+		Ok(true)
+	}
+}
+
+
+fn hex_upper(hash: &[u8; 20]) -> String {
+	data_encoding::HEXUPPER.encode(hash)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{build_breach_bloom_filter, hex_upper, BreachCheckOptions, BreachHit, BreachSource};
+	use crate::{Database, Entry, EntryHistory, FortressError};
+	use sha1::{Digest, Sha1};
+	use std::{collections::HashMap, io::Write, thread};
+	use url::Url;
+
+	fn hash_hex(password: &str) -> String {
+		hex_upper(&Sha1::digest(password.as_bytes()).into())
+	}
+
+	// A database with entries using "password", "hunter2" and an unbreached password, and one without a password.  Returns the
+	// IDs of the first two.
+	fn test_database() -> (Database, [crate::ID; 2]) {
+		let mut db = Database::new_with_password("username", "password");
+		let mut add = |fields: &[(&str, &str)]| {
+			let mut entry = Entry::new();
+			entry.edit(EntryHistory::new(
+				fields
+					.iter()
+					.map(|(key, value)| (key.to_string(), value.to_string()))
+					.collect::<HashMap<_, _>>(),
+			));
+			let id = *entry.get_id();
+			db.add_entry(entry);
+			id
+		};
+
+		let first = add(&[("title", "Forum"), ("password", "password")]);
+		let second = add(&[("title", "Router"), ("password", "hunter2"), ("notes", "password")]);
+		// Not breached, or not a password
+		let _others = [
+			add(&[("title", "Bank"), ("password", "correct horse battery staple 8f2e")]),
+			add(&[("title", "Note"), ("notes", "hunter2")]),
+		];
+
+		let mut ids = [first, second];
+		ids.sort();
+		(db, ids)
+	}
+
+	#[test]
+	fn online() {
+		let (db, ids) = test_database();
+		let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+		let api_url = Url::parse(&format!("http://{}/range/", server.server_addr().to_ip().unwrap())).unwrap();
+		let breached = [(hash_hex("password"), 100), (hash_hex("hunter2"), 7)];
+
+		let server_thread = thread::spawn(move || {
+			let mut prefixes = Vec::new();
+
+			// One request for each of the three passwords
+			for _ in 0..3 {
+				let request = server.recv().unwrap();
+				let prefix = request.url().strip_prefix("/range/").unwrap().to_string();
+				assert_eq!(prefix.len(), 5);
+				assert!(request.headers().iter().any(|header| header.field.equiv("Add-Padding")));
+
+				let mut body = format!("{}:0\r\n", "0".repeat(35));
+				for (hash, count) in &breached {
+					if hash.starts_with(&prefix) {
+						body += &format!("{}:{count}\r\n", &hash[5..]);
+					}
+				}
+				request.respond(tiny_http::Response::from_string(body)).unwrap();
+				prefixes.push(prefix);
+			}
+
+			prefixes
+		});
+
+		let hits = db
+			.check_breached(&BreachCheckOptions {
+				source: BreachSource::Online { api_url },
+			})
+			.unwrap();
+		let prefixes = server_thread.join().unwrap();
+
+		// Only prefixes were sent
+		assert!(prefixes.contains(&hash_hex("password")[..5].to_string()));
+		let count_of = |id| if db.get_entry_by_id(id).unwrap()["password"] == "password" { 100 } else { 7 };
+		assert_eq!(
+			hits,
+			ids.iter()
+				.map(|id| BreachHit {
+					id: *id,
+					field: "password".to_string(),
+					count: Some(count_of(id)),
+				})
+				.collect::<Vec<_>>()
+		);
+	}
+
+	#[test]
+	fn offline() {
+		let (db, ids) = test_database();
+		let hash_list = format!("{}:100\n{}:7\nnot a hash\n", hash_hex("password"), hash_hex("hunter2").to_lowercase());
+		let mut file = tempfile::NamedTempFile::new().unwrap();
+		build_breach_bloom_filter(hash_list.as_bytes(), 2, &mut file).unwrap();
+		file.flush().unwrap();
+
+		let hits = db
+			.check_breached(&BreachCheckOptions {
+				source: BreachSource::Offline {
+					bloom_filter: file.path().to_owned(),
+				},
+			})
+			.unwrap();
+		assert_eq!(
+			hits.iter().map(|hit| (hit.id, hit.field.as_str(), hit.count)).collect::<Vec<_>>(),
+			[(ids[0], "password", None), (ids[1], "password", None)]
+		);
+
+		// Not a bloom filter
+		let mut file = tempfile::NamedTempFile::new().unwrap();
+		file.write_all(b"FTBLOOM1 but not really").unwrap();
+		assert!(matches!(
+			db.check_breached(&BreachCheckOptions {
+				source: BreachSource::Offline {
+					bloom_filter: file.path().to_owned(),
+				},
+			}),
+			Err(FortressError::MalformedBloomFilter)
+		));
+	}
+}
@@ -0,0 +1,235 @@
+// Importing passwords exported by browsers (see the import module).  Chrome, Edge, and Firefox all export CSV with a header row
+// and a row per saved login: Chrome's columns are name, url, username, password, and note, and Firefox's are url, username,
+// password, and some of its own bookkeeping (httpRealm, guid, timeCreated, ...), which is left out.  Columns are matched by name,
+// ignoring case, and unknown columns become fields named after them.
+//
+// Browsers save a login per page they saw it on, so one account often shows up several times, e.g. for the sign-in page and
+// the password change page.  Rows for the same site (the host and port of their URL, after `normalize_url`) and username are
+// merged into one entry, with the first row's URL.  If those rows disagree about the password, the entry keeps all of them,
+// the first as `password` and the rest as `password 2` and so on, gets the tag IMPORT_CONFLICT_TAG, and is listed in
+// `ImportPlan::conflicts` for the user to sort out, rather than guessing which one is current.  Firefox records when each
+// password was last changed, so its most recent password comes first; otherwise they're in the order of the file.
+//
+// Rows without a URL, or with one that can't be parsed, aren't merged with anything.  Everything goes in the root
+// directory.
+use crate::{
+	import::{parse_csv, ImportPlan, ImportedEntry},
+	normalize_url, FieldKind, FortressError,
+};
+use std::{cmp::Reverse, collections::HashMap};
+
+
+/// Tag given to imported entries that were saved with more than one password (see the browser_csv module).
+pub const IMPORT_CONFLICT_TAG: &str = "import-conflict";
+
+
+#[derive(Default)]
+struct Login {
+	title: String,
+	url: String,
+	username: String,
+	password: String,
+	otpauth: String,
+	notes: String,
+	/// When the password was last changed, in milliseconds since the Unix epoch (Firefox only).
+	password_changed: Option<u64>,
+	other_fields: Vec<(String, String)>,
+}
+
+
+impl ImportPlan {
+	/// Parses passwords exported from a browser as CSV, merging duplicate logins (see the browser_csv module).  Fails with
+	/// FortressError::MalformedImport if it isn't CSV with a header row.
+	pub fn from_browser_csv(data: &[u8]) -> Result<ImportPlan, FortressError> {
+		let text = std::str::from_utf8(data).map_err(|_| FortressError::MalformedImport("CSV isn't UTF-8".to_string()))?;
+		let mut rows = parse_csv(text)?.into_iter();
+		let header = rows.next().ok_or_else(|| FortressError::MalformedImport("CSV has no header row".to_string()))?;
+		let mut sites: Vec<(Option<String>, Vec<Login>)> = Vec::new();
+		let mut site_index: HashMap<(String, String), usize> = HashMap::new();
+
+		for row in rows {
+			let login = parse_login(&header, row);
+			let site = normalize_url(&login.url).and_then(|url| {
+				let host = url.host_str()?;
+				Some(url.port().map_or_else(|| host.to_string(), |port| format!("{host}:{port}")))
+			});
+
+			match site.clone().map(|site| (site, login.username.clone())) {
+				Some(key) if site_index.contains_key(&key) => sites[site_index[&key]].1.push(login),
+				Some(key) => {
+					site_index.insert(key, sites.len());
+					sites.push((site, vec![login]));
+				},
+				None => sites.push((None, vec![login])),
+			}
+		}
+
+		let mut plan = ImportPlan::default();
+
+		for (site, mut logins) in sites {
+			logins.sort_by_key(|login| Reverse(login.password_changed));
+			let (entry, conflict) = merge_logins(&logins, site.as_deref());
+
+			if conflict {
+				let site = site.unwrap_or_default();
+				plan.conflicts.push(match logins[0].username.as_str() {
+					"" => format!("Different passwords at {site}"),
+					username => format!("Different passwords for '{username}' at {site}"),
+				});
+			}
+
+			plan.entries.push(entry);
+		}
+
+		Ok(plan)
+	}
+}
+
+
+fn parse_login(header: &[String], row: Vec<String>) -> Login {
+	let mut login = Login::default();
+
+	for (column, value) in header.iter().zip(row) {
+		let column = column.trim();
+
+		match column.to_lowercase().as_str() {
+			"name" | "title" => login.title = value,
+			"url" | "login_uri" | "website" => login.url = value,
+			"username" | "login_username" => login.username = value,
+			"password" | "login_password" => login.password = value,
+			"otpauth" | "login_totp" => login.otpauth = value,
+			"note" | "notes" | "extra" => login.notes = value,
+			"timepasswordchanged" => login.password_changed = value.trim().parse().ok(),
+			"httprealm" | "formactionorigin" | "guid" | "timecreated" | "timelastused" => (),
+			_ => login.other_fields.push((column.to_string(), value)),
+		}
+	}
+
+	login
+}
+
+
+/// One entry holding every login in `logins`, which are for the same account, and whether they had different passwords.
+fn merge_logins(logins: &[Login], site: Option<&str>) -> (ImportedEntry, bool) {
+	let mut entry = ImportedEntry::default();
+	let first = |value: fn(&Login) -> &str| logins.iter().map(value).find(|value| !value.is_empty()).unwrap_or_default();
+
+	// Chrome names logins after their site, but not every browser names them at all
+	entry.push_field("title", or(first(|login| &login.title), site.unwrap_or_default()), None);
+	entry.push_field("url", &logins[0].url, None);
+	entry.push_field("username", &logins[0].username, None);
+
+	let passwords = distinct(logins.iter().map(|login| login.password.as_str()).filter(|password| !password.is_empty()));
+	for password in &passwords {
+		entry.push_field("password", password, Some(FieldKind::Secret));
+	}
+
+	entry.push_field("otpauth", first(|login| &login.otpauth), Some(FieldKind::Totp));
+
+	let notes = distinct(logins.iter().map(|login| login.notes.as_str()).filter(|notes| !notes.is_empty()));
+	entry.push_field("notes", &notes.join("\n\n"), None);
+
+	let other_fields = distinct(logins.iter().flat_map(|login| &login.other_fields));
+	for (name, value) in other_fields {
+		entry.push_field(name, value, None);
+	}
+
+	let conflict = passwords.len() > 1;
+	if conflict {
+		entry.tags.insert(IMPORT_CONFLICT_TAG.to_string());
+	}
+
+	(entry, conflict)
+}
+
+
+// `items` without repeats, in order
+fn distinct<T: PartialEq>(items: impl Iterator<Item = T>) -> Vec<T> {
+	let mut distinct = Vec::new();
+	for item in items {
+		if !distinct.contains(&item) {
+			distinct.push(item);
+		}
+	}
+	distinct
+}
+
+
+fn or<'a>(value: &'a str, default: &'a str) -> &'a str {
+	if value.is_empty() {
+		default
+	} else {
+		value
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::IMPORT_CONFLICT_TAG;
+	use crate::{import::ImportPlan, Database};
+
+	#[test]
+	fn chrome() {
+		let csv = "name,url,username,password,note\n\
+			example.com,https://www.example.com/login,me,hunter2,\n\
+			example.com,https://example.com/settings/password,me,hunter2,Work account\n\
+			example.com,https://example.com/login,someone,letmein,\n\
+			mail,https://mail.example.com,me,old,\n\
+			mail,https://mail.example.com/#inbox,me,new,\n\
+			Router,,admin,hunter2,\n\
+			Router,,admin,hunter2,\n";
+		let plan = ImportPlan::from_browser_csv(csv.as_bytes()).unwrap();
+		assert_eq!(plan.entries.len(), 5);
+		assert_eq!(plan.conflicts, ["Different passwords for 'me' at mail.example.com"]);
+		assert_eq!(plan.summary(), "5 entries, 1 conflict");
+
+		// Same site and username, same password
+		let example = &plan.entries[0];
+		assert_eq!(example.get("title"), Some("example.com"));
+		assert_eq!(example.get("url"), Some("https://www.example.com/login"));
+		assert_eq!(example.get("password"), Some("hunter2"));
+		assert!(example.get("password 2").is_none());
+		assert_eq!(example.get("notes"), Some("Work account"));
+		assert!(example.tags.is_empty());
+
+		// Same site, different username
+		assert_eq!(plan.entries[1].get("username"), Some("someone"));
+
+		// Same site and username, different password
+		let mail = &plan.entries[2];
+		assert_eq!(mail.get("password"), Some("old"));
+		assert_eq!(mail.get("password 2"), Some("new"));
+		assert!(mail.tags.contains(IMPORT_CONFLICT_TAG));
+
+		// Logins without a URL are never merged
+		assert_eq!(plan.entries[3].get("title"), Some("Router"));
+		assert_eq!(plan.entries[4].get("title"), Some("Router"));
+
+		let mut db = Database::new_with_password("username", "password");
+		let report = db.import(&plan).unwrap();
+		let mail = db.get_entry_by_id(&report.entries[2]).unwrap();
+		assert!(mail.is_field_protected("password 2"));
+		assert!(mail.has_tag(IMPORT_CONFLICT_TAG));
+	}
+
+	#[test]
+	fn firefox() {
+		let csv = "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+			\"https://example.com:8443\",\"me\",\"old\",,\"https://example.com:8443\",\"{1}\",\"1\",\"1\",\"1000\"\n\
+			\"https://example.com:8443\",\"me\",\"new\",,\"https://example.com:8443\",\"{2}\",\"1\",\"1\",\"2000\"\n\
+			\"https://example.com\",\"me\",\"other\",,\"https://example.com\",\"{3}\",\"1\",\"1\",\"1000\"\n";
+		let plan = ImportPlan::from_browser_csv(csv.as_bytes()).unwrap();
+		assert_eq!(plan.entries.len(), 2);
+		assert_eq!(plan.conflicts, ["Different passwords for 'me' at example.com:8443"]);
+
+		// The most recently changed password comes first, and Firefox's own columns are left out
+		let entry = &plan.entries[0];
+		assert_eq!(entry.get("title"), Some("example.com:8443"));
+		assert_eq!(entry.get("password"), Some("new"));
+		assert_eq!(entry.get("password 2"), Some("old"));
+		assert_eq!(entry.fields.len(), 5);
+
+		assert!(ImportPlan::from_browser_csv(b"").is_err());
+	}
+}
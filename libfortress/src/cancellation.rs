@@ -0,0 +1,187 @@
+// Cancelling long-running operations (key derivation, sync, imports) from another thread, e.g. a frontend's Cancel button.
+// Cancelled operations return FortressError::Cancelled and leave the database as it was before they started.
+//
+// Key derivation can't be interrupted part way through (scrypt doesn't stop between rounds), so it runs on a worker thread
+// which is abandoned if the operation is cancelled: cancelling only gives control back early, and the worker keeps the CPU and
+// memory busy until it finishes, when its result is dropped.  So that cancelling and retrying doesn't pile up derivations
+// nobody is waiting for, the next operation to start waits for abandoned workers first (cancellably, and reporting progress
+// as it goes).  Sync checks for cancellation between requests to the server.
+use crate::FortressError;
+use std::{
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		mpsc::{self, RecvTimeoutError},
+		Arc, Mutex, PoisonError,
+	},
+	thread::{self, JoinHandle},
+	time::{Duration, Instant},
+};
+
+
+// How often a cancellable wait checks whether it has been cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Workers of cancelled operations, which may still be running (see the cancellation module).
+static ABANDONED: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+
+/// Shared flag for cancelling an operation.  Clones share the same flag, so one clone can be handed to the operation and another
+/// kept to cancel it.  Once cancelled, a token stays cancelled; use a new token for the next operation.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+	pub fn new() -> CancellationToken {
+		CancellationToken::default()
+	}
+
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+
+	/// Returns FortressError::Cancelled if the token has been cancelled.
+	pub fn check(&self) -> Result<(), FortressError> {
+		if self.is_cancelled() {
+			Err(FortressError::Cancelled)
+		} else {
+			Ok(())
+		}
+	}
+}
+
+
+/// Runs `f` on a worker thread, returning its result, or FortressError::Cancelled as soon as `cancel` is cancelled, in which case
+/// the worker is left to finish on its own.  `f` doesn't start until workers abandoned like that have finished (see the
+/// cancellation module).  Panics in `f` are propagated.
+pub(crate) fn run_cancellable<T, F>(cancel: &CancellationToken, f: F) -> Result<T, FortressError>
+where
+	T: Send + 'static,
+	F: FnOnce() -> T + Send + 'static,
+{
+	run_cancellable_with_ticks(cancel, f, &mut |_| {})
+}
+
+
+/// Same as `run_cancellable`, but calls `tick` with the time elapsed so far every POLL_INTERVAL while waiting, on the calling
+/// thread.  Used for progress reporting.
+pub(crate) fn run_cancellable_with_ticks<T, F>(cancel: &CancellationToken, f: F, tick: &mut dyn FnMut(Duration)) -> Result<T, FortressError>
+where
+	T: Send + 'static,
+	F: FnOnce() -> T + Send + 'static,
+{
+	cancel.check()?;
+
+	let start = Instant::now();
+	wait_for_abandoned(cancel, &mut || tick(start.elapsed()))?;
+
+	let (sender, receiver) = mpsc::channel();
+	let worker = thread::spawn(move || {
+		// The receiver is gone if we were cancelled, in which case the result is just dropped
+		let _ = sender.send(f());
+	});
+
+	loop {
+		match receiver.recv_timeout(POLL_INTERVAL) {
+			Ok(result) => return Ok(result),
+			Err(RecvTimeoutError::Timeout) => {
+				if let Err(err) = cancel.check() {
+					ABANDONED.lock().unwrap_or_else(PoisonError::into_inner).push(worker);
+					return Err(err);
+				}
+				tick(start.elapsed());
+			},
+			Err(RecvTimeoutError::Disconnected) => match worker.join() {
+				Err(panic) => std::panic::resume_unwind(panic),
+				Ok(()) => unreachable!(),
+			},
+		}
+	}
+}
+
+
+/// Waits until every abandoned worker has finished, calling `tick` every POLL_INTERVAL.  An abandoned worker that itself runs
+/// something cancellable doesn't wait for itself.
+fn wait_for_abandoned(cancel: &CancellationToken, tick: &mut dyn FnMut()) -> Result<(), FortressError> {
+	loop {
+		{
+			let mut abandoned = ABANDONED.lock().unwrap_or_else(PoisonError::into_inner);
+			abandoned.retain(|worker| !worker.is_finished());
+			if abandoned.iter().all(|worker| worker.thread().id() == thread::current().id()) {
+				return Ok(());
+			}
+		}
+
+		cancel.check()?;
+		tick();
+		thread::sleep(POLL_INTERVAL);
+	}
+}
+
+
+/// Sleeps for `duration`, returning FortressError::Cancelled as soon as `cancel` is cancelled.
+pub(crate) fn sleep_cancellable(cancel: &CancellationToken, duration: Duration) -> Result<(), FortressError> {
+	let end = Instant::now() + duration;
+
+	loop {
+		cancel.check()?;
+		let remaining = end.saturating_duration_since(Instant::now());
+		if remaining.is_zero() {
+			return Ok(());
+		}
+		thread::sleep(remaining.min(POLL_INTERVAL));
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{run_cancellable, CancellationToken};
+	use crate::FortressError;
+	use std::{
+		sync::{
+			atomic::{AtomicBool, Ordering},
+			Arc,
+		},
+		thread,
+		time::Duration,
+	};
+
+	#[test]
+	fn cancellation() {
+		let cancel = CancellationToken::new();
+		assert!(cancel.check().is_ok());
+		assert_eq!(run_cancellable(&cancel, || 42).unwrap(), 42);
+
+		// Cancelled while running
+		let canceller = cancel.clone();
+		thread::spawn(move || {
+			thread::sleep(Duration::from_millis(100));
+			canceller.cancel();
+		});
+		let finished = Arc::new(AtomicBool::new(false));
+		let worker_finished = finished.clone();
+		let result = run_cancellable(&cancel, move || {
+			thread::sleep(Duration::from_millis(500));
+			worker_finished.store(true, Ordering::Relaxed);
+		});
+		assert!(matches!(result, Err(FortressError::Cancelled)));
+		assert!(cancel.is_cancelled());
+		assert!(!finished.load(Ordering::Relaxed));
+
+		// The abandoned worker finishes before anything else starts
+		assert!(run_cancellable(&CancellationToken::new(), move || finished.load(Ordering::Relaxed)).unwrap());
+
+		// Already cancelled
+		assert!(matches!(run_cancellable(&cancel, || 42), Err(FortressError::Cancelled)));
+	}
+
+	#[test]
+	#[should_panic(expected = "worker panicked")]
+	fn panics_propagate() {
+		let _ = run_cancellable(&CancellationToken::new(), || -> u32 { panic!("worker panicked") });
+	}
+}
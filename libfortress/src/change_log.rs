@@ -0,0 +1,355 @@
+// A chronological feed of every change made to the database's entries and directories, flattened out of their histories, for
+// auditing a shared vault.  Changes are attributed to the device that made them, when that's known (see the device module);
+// histories don't record which user made a change, so neither can the log.
+//
+// Values of protected fields (see `Entry::is_field_protected`) are always redacted; the log is meant to be handed to other people.
+// Like `Entry::diff`, whether a field is protected is decided by its current metadata, so a field that is protected now is
+// redacted throughout its history.  So are the fields a double-locked entry's lock holds (see the double_lock module), while it's
+// locked.
+use crate::{
+	database_object::{DatabaseObject, DirectoryHistoryAction},
+	selection_export::escape_csv,
+	Database, DeviceId, Directory, Entry, FortressError, SyncObjectKind, ID, REDACTED_VALUE,
+};
+use fortresscrypto::Zeroize;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	io,
+};
+
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct ChangeRecord {
+	/// When the change was made (nanoseconds).
+	pub time: u64,
+	/// The entry or directory that was changed.
+	pub id: ID,
+	pub kind: SyncObjectKind,
+	pub action: ChangeAction,
+	/// The device that made the change, if known.
+	pub device: Option<DeviceId>,
+}
+
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeAction {
+	/// A field of an entry was set.  `old` is None if the field didn't exist before.
+	Field {
+		key: String,
+		old: Option<String>,
+		new: String,
+	},
+	/// The metadata of an entry's field (its kind, protection, or derivation) changed.
+	FieldMetadata {
+		key: String,
+	},
+	/// An entry's fields were reordered.
+	FieldOrder,
+	TagAdded {
+		tag: String,
+	},
+	TagRemoved {
+		tag: String,
+	},
+	/// An entry or directory was added to a directory.
+	Added {
+		child: ID,
+	},
+	/// An entry or directory was removed from a directory (moved elsewhere, or trashed).
+	Removed {
+		child: ID,
+	},
+	/// A directory was renamed.  `old` is None for its first name.
+	Renamed {
+		old: Option<String>,
+		new: String,
+	},
+	/// A directory was made local-only, or allowed to sync again (see the selective_sync module).
+	LocalOnly {
+		local_only: bool,
+	},
+	/// An entry or directory was deleted for good, leaving a tombstone (see the gc module).
+	Deleted,
+}
+
+impl Drop for ChangeAction {
+	fn drop(&mut self) {
+		if let ChangeAction::Field { old, new, .. } = self {
+			old.zeroize();
+			new.zeroize();
+		}
+	}
+}
+
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeLogFormat {
+	/// RFC 4180 CSV with the columns time, id, kind, action, subject, old, new, and device.  `subject` is the field, tag, or
+	/// child the change was to, and `old` and `new` are field values or directory names.
+	Csv,
+	/// A JSON array of ChangeRecords.
+	Json,
+}
+
+
+impl Database {
+	/// Every change made at or after `since` (nanoseconds) to the database's entries and directories, oldest first.  Changes made
+	/// at the same time are ordered by object ID, then as they appear in the object's history.
+	pub fn change_log(&self, since: u64) -> Vec<ChangeRecord> {
+		let mut records = self
+			.objects
+			.values()
+			.flat_map(|object| match object {
+				DatabaseObject::Entry(entry) => entry_changes(entry, since),
+				DatabaseObject::Directory(directory) => directory_changes(directory, since),
+				DatabaseObject::Tombstone(tombstone) if tombstone.get_time() >= since => vec![ChangeRecord {
+					time: tombstone.get_time(),
+					id: *tombstone.get_id(),
+					kind: SyncObjectKind::Tombstone,
+					action: ChangeAction::Deleted,
+					device: None,
+				}],
+				DatabaseObject::Settings(_) | DatabaseObject::Tombstone(_) => Vec::new(),
+			})
+			.collect::<Vec<_>>();
+
+		records.sort_by_key(|record| (record.time, record.id));
+		records
+	}
+
+	/// Writes `change_log(since)` to `writer` as `format`.
+	pub fn export_change_log<W: io::Write>(&self, mut writer: W, since: u64, format: ChangeLogFormat) -> Result<(), FortressError> {
+		let records = self.change_log(since);
+
+		match format {
+			ChangeLogFormat::Csv => {
+				writer.write_all(b"time,id,kind,action,subject,old,new,device\r\n")?;
+
+				for record in &records {
+					let (action, subject, old, new) = csv_columns(&record.action);
+					let kind = match record.kind {
+						SyncObjectKind::Entry => "entry",
+						SyncObjectKind::Directory => "directory",
+						SyncObjectKind::Settings => "settings",
+						SyncObjectKind::Tombstone => "tombstone",
+					};
+					let device = record.device.map(|device| device.to_hex()).unwrap_or_default();
+					let cells = [&record.time.to_string(), &record.id.to_hex(), kind, action, &subject, old, new, &device];
+					let mut line = cells.iter().map(|cell| escape_csv(cell)).collect::<Vec<_>>().join(",");
+					line.push_str("\r\n");
+					writer.write_all(line.as_bytes())?;
+					line.zeroize();
+				}
+			},
+			ChangeLogFormat::Json => serde_json::to_writer_pretty(&mut writer, &records)?,
+		}
+
+		writer.flush()?;
+		Ok(())
+	}
+}
+
+
+fn entry_changes(entry: &Entry, since: u64) -> Vec<ChangeRecord> {
+	let mut records = Vec::new();
+	let mut state = HashMap::new();
+	let mut metadata = HashMap::new();
+	let mut field_order = None;
+	let mut tags = BTreeSet::new();
+
+	for item in entry.get_history() {
+		let mut actions = Vec::new();
+
+		for (key, value) in item.data.iter().collect::<BTreeMap<_, _>>() {
+			let old = state.insert(key, value);
+
+			if old != Some(value) {
+				let protected = entry.is_field_protected(key) || entry.is_field_double_locked(key);
+				let visible = |value: &String| {
+					if protected {
+						REDACTED_VALUE.to_owned()
+					} else {
+						value.clone()
+					}
+				};
+
+				actions.push(ChangeAction::Field {
+					key: key.clone(),
+					old: old.map(visible),
+					new: visible(value),
+				});
+			}
+		}
+
+		for (key, field_metadata) in item.metadata.iter().collect::<BTreeMap<_, _>>() {
+			if metadata.insert(key, field_metadata) != Some(field_metadata) {
+				actions.push(ChangeAction::FieldMetadata { key: key.clone() });
+			}
+		}
+
+		if let Some(order) = &item.field_order {
+			if field_order.replace(order) != Some(order) {
+				actions.push(ChangeAction::FieldOrder);
+			}
+		}
+
+		for (tag, present) in item.tags.iter().collect::<BTreeMap<_, _>>() {
+			if *present && tags.insert(tag) {
+				actions.push(ChangeAction::TagAdded { tag: tag.clone() });
+			} else if !*present && tags.remove(tag) {
+				actions.push(ChangeAction::TagRemoved { tag: tag.clone() });
+			}
+		}
+
+		if item.time >= since {
+			records.extend(actions.into_iter().map(|action| ChangeRecord {
+				time: item.time,
+				id: *entry.get_id(),
+				kind: SyncObjectKind::Entry,
+				action,
+				device: item.device,
+			}));
+		}
+	}
+
+	records
+}
+
+
+fn directory_changes(directory: &Directory, since: u64) -> Vec<ChangeRecord> {
+	let mut name: Option<&str> = None;
+
+	directory
+		.get_history()
+		.iter()
+		.filter_map(|item| {
+			let action = match &item.action {
+				DirectoryHistoryAction::Add(child) => ChangeAction::Added { child: *child },
+				DirectoryHistoryAction::Remove(child) => ChangeAction::Removed { child: *child },
+				DirectoryHistoryAction::Rename(new) => ChangeAction::Renamed {
+					old: name.replace(new).map(str::to_owned),
+					new: new.clone(),
+				},
+				DirectoryHistoryAction::SetLocalOnly(local_only) => ChangeAction::LocalOnly { local_only: *local_only },
+			};
+
+			(item.time >= since).then(|| ChangeRecord {
+				time: item.time,
+				id: *directory.get_id(),
+				kind: SyncObjectKind::Directory,
+				action,
+				device: item.device,
+			})
+		})
+		.collect()
+}
+
+
+// (action, subject, old, new)
+fn csv_columns(action: &ChangeAction) -> (&'static str, String, &str, &str) {
+	match action {
+		ChangeAction::Field { key, old, new } => ("field", key.clone(), old.as_deref().unwrap_or(""), new),
+		ChangeAction::FieldMetadata { key } => ("field_metadata", key.clone(), "", ""),
+		ChangeAction::FieldOrder => ("field_order", String::new(), "", ""),
+		ChangeAction::TagAdded { tag } => ("tag_added", tag.clone(), "", ""),
+		ChangeAction::TagRemoved { tag } => ("tag_removed", tag.clone(), "", ""),
+		ChangeAction::Added { child } => ("added", child.to_hex(), "", ""),
+		ChangeAction::Removed { child } => ("removed", child.to_hex(), "", ""),
+		ChangeAction::Renamed { old, new } => ("renamed", String::new(), old.as_deref().unwrap_or(""), new),
+		ChangeAction::LocalOnly { local_only } => ("local_only", String::new(), "", if *local_only { "true" } else { "false" }),
+		ChangeAction::Deleted => ("deleted", String::new(), "", ""),
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{ChangeAction, ChangeLogFormat};
+	use crate::{testing::fields, Database, Directory, Entry, EntryHistory, SyncObjectKind, REDACTED_VALUE};
+
+	#[test]
+	fn change_log() {
+		let mut db = Database::new_with_password("username", "password");
+		let mut directory = Directory::new();
+		directory.rename("Work");
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[("title", "Mail, etc."), ("password", "hunter2")])));
+		let entry_id = *entry.get_id();
+		db.add_entry(entry);
+
+		let entry = db.get_entry_by_id_mut(&entry_id).unwrap();
+		// Edits that don't change a field aren't logged
+		entry.edit(entry.new_edit(fields(&[("title", "Mail"), ("password", "hunter2")])));
+		entry.add_tag("shared");
+		let since = entry.get_history().last().unwrap().time;
+		db.get_directory_by_id_mut(&directory_id).unwrap().rename("Team");
+		db.move_object(&entry_id, &directory_id);
+
+		let log = db.change_log(0);
+		let entry_log = log
+			.iter()
+			.filter(|record| record.id == entry_id)
+			.map(|record| &record.action)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			entry_log,
+			[
+				&ChangeAction::Field {
+					key: "password".to_owned(),
+					old: None,
+					new: REDACTED_VALUE.to_owned(),
+				},
+				&ChangeAction::Field {
+					key: "title".to_owned(),
+					old: None,
+					new: "Mail, etc.".to_owned(),
+				},
+				&ChangeAction::Field {
+					key: "title".to_owned(),
+					old: Some("Mail, etc.".to_owned()),
+					new: "Mail".to_owned(),
+				},
+				&ChangeAction::TagAdded { tag: "shared".to_owned() },
+			]
+		);
+		assert!(log.windows(2).all(|pair| pair[0].time <= pair[1].time));
+		assert!(log
+			.iter()
+			.any(|record| record.kind == SyncObjectKind::Directory && record.action == ChangeAction::Added { child: entry_id }));
+
+		let recent = db.change_log(since);
+		assert_eq!(recent[0].action, ChangeAction::TagAdded { tag: "shared".to_owned() });
+		assert!(recent.contains(&super::ChangeRecord {
+			time: recent[1].time,
+			id: directory_id,
+			kind: SyncObjectKind::Directory,
+			action: ChangeAction::Renamed {
+				old: Some("Work".to_owned()),
+				new: "Team".to_owned(),
+			},
+			device: None,
+		}));
+		assert_eq!(recent.len(), 4);
+
+		let mut csv = Vec::new();
+		db.export_change_log(&mut csv, since, ChangeLogFormat::Csv).unwrap();
+		let csv = String::from_utf8(csv).unwrap();
+		let lines = csv.lines().collect::<Vec<_>>();
+		assert_eq!(lines.len(), 5);
+		assert_eq!(lines[0], "time,id,kind,action,subject,old,new,device");
+		assert_eq!(lines[1], format!("{},{},entry,tag_added,shared,,,", recent[0].time, entry_id.to_hex()));
+
+		let mut json = Vec::new();
+		db.export_change_log(&mut json, since, ChangeLogFormat::Json).unwrap();
+		let json = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+		assert_eq!(json[0]["action"], serde_json::json!({"type": "tag_added", "tag": "shared"}));
+		assert_eq!(json.as_array().unwrap().len(), 4);
+		assert!(!serde_json::to_string(&db.change_log(0)).unwrap().contains("hunter2"));
+	}
+}
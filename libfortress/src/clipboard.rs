@@ -0,0 +1,198 @@
+// Copying secrets to the clipboard and wiping them again after a timeout, for frontends that don't have a clipboard of their own
+// (the CLI) or want the same behavior as it.  Only available with the `clipboard` feature.
+//
+// The system clipboard is accessed through the platform's command line tools: wl-copy/wl-paste (Wayland) or xclip (X11) on
+// Linux and the BSDs, pbcopy/pbpaste on macOS, and PowerShell on Windows.  This avoids linking against any windowing system, and
+// on X11 and Wayland the tools keep serving the clipboard after our process exits.
+use crate::FortressError;
+use fortresscrypto::Zeroizing;
+use std::{
+	io::{Read, Write},
+	process::{Command, Stdio},
+	thread::{self, JoinHandle},
+	time::Duration,
+};
+
+
+/// How long copied secrets stay on the clipboard by default.
+pub const DEFAULT_CLIPBOARD_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// A clipboard that text can be copied to and read back from.
+pub trait Clipboard: Send + 'static {
+	fn get(&mut self) -> Result<Zeroizing<String>, FortressError>;
+	/// Setting the clipboard to an empty string clears it.
+	fn set(&mut self, text: &str) -> Result<(), FortressError>;
+}
+
+
+/// The system clipboard, accessed through the platform's command line tools (see the clipboard module).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClipboard;
+
+impl SystemClipboard {
+	fn commands() -> ClipboardCommands {
+		if cfg!(target_os = "macos") {
+			ClipboardCommands {
+				set: &["pbcopy"],
+				clear: &["pbcopy"],
+				get: &["pbpaste"],
+			}
+		} else if cfg!(windows) {
+			ClipboardCommands {
+				set: &["powershell", "-NoProfile", "-Command", "Set-Clipboard -Value $([Console]::In.ReadToEnd())"],
+				clear: &["powershell", "-NoProfile", "-Command", "Set-Clipboard -Value $null"],
+				get: &["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"],
+			}
+		} else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+			ClipboardCommands {
+				set: &["wl-copy"],
+				clear: &["wl-copy", "--clear"],
+				get: &["wl-paste", "--no-newline"],
+			}
+		} else {
+			ClipboardCommands {
+				set: &["xclip", "-selection", "clipboard"],
+				clear: &["xclip", "-selection", "clipboard"],
+				get: &["xclip", "-selection", "clipboard", "-out"],
+			}
+		}
+	}
+}
+
+impl Clipboard for SystemClipboard {
+	fn get(&mut self) -> Result<Zeroizing<String>, FortressError> {
+		let command = SystemClipboard::commands().get;
+		let unavailable = || FortressError::ClipboardUnavailable(command[0].to_string());
+		let mut child = Command::new(command[0])
+			.args(&command[1..])
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::null())
+			.spawn()
+			.map_err(|_| unavailable())?;
+
+		let mut text = Zeroizing::new(String::new());
+		let read = child.stdout.take().expect("internal error").read_to_string(&mut text);
+
+		// The clipboard may be empty, or hold something other than text, in which case the tools fail
+		match (read, child.wait()) {
+			(Ok(_), Ok(status)) if status.success() => Ok(text),
+			(_, Ok(_)) => Ok(Zeroizing::new(String::new())),
+			(_, Err(_)) => Err(unavailable()),
+		}
+	}
+
+	fn set(&mut self, text: &str) -> Result<(), FortressError> {
+		let commands = SystemClipboard::commands();
+		let command = if text.is_empty() { commands.clear } else { commands.set };
+		let unavailable = || FortressError::ClipboardUnavailable(command[0].to_string());
+		let mut child = Command::new(command[0])
+			.args(&command[1..])
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+			.map_err(|_| unavailable())?;
+
+		// Written on the stdin pipe, rather than passed as an argument, so it doesn't show up in the process list
+		let written = child.stdin.take().expect("internal error").write_all(text.as_bytes());
+
+		match (written, child.wait()) {
+			(Ok(()), Ok(status)) if status.success() => Ok(()),
+			_ => Err(unavailable()),
+		}
+	}
+}
+
+
+struct ClipboardCommands {
+	/// Sets the clipboard to its stdin
+	set: &'static [&'static str],
+	clear: &'static [&'static str],
+	/// Writes the clipboard to its stdout
+	get: &'static [&'static str],
+}
+
+
+/// Returned by `copy_secret`.  The clipboard is cleared in the background whether or not this is kept.
+pub struct PendingClear {
+	thread: JoinHandle<Result<(), FortressError>>,
+}
+
+impl PendingClear {
+	/// Waits for the clipboard to be cleared.  Programs that exit right after copying (like the CLI) should wait, since the
+	/// clipboard isn't cleared if they've exited.
+	pub fn wait(self) -> Result<(), FortressError> {
+		match self.thread.join() {
+			Ok(result) => result,
+			Err(panic) => std::panic::resume_unwind(panic),
+		}
+	}
+}
+
+
+/// Copies `secret` to `clipboard`, and after `timeout` puts back whatever was on the clipboard before, or clears it if that
+/// couldn't be read.  If the clipboard was changed in the meantime (the user copied something else), it's left alone.
+pub fn copy_secret<C: Clipboard>(mut clipboard: C, secret: &str, timeout: Duration) -> Result<PendingClear, FortressError> {
+	let previous = clipboard.get().unwrap_or_default();
+	let secret = Zeroizing::new(secret.to_string());
+	clipboard.set(&secret)?;
+
+	let thread = thread::spawn(move || {
+		thread::sleep(timeout);
+
+		if clipboard.get()? == secret {
+			clipboard.set(&previous)?;
+		}
+
+		Ok(())
+	});
+
+	Ok(PendingClear { thread })
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{copy_secret, Clipboard};
+	use crate::FortressError;
+	use fortresscrypto::Zeroizing;
+	use std::{
+		sync::{Arc, Mutex},
+		thread,
+		time::Duration,
+	};
+
+	#[derive(Clone, Default)]
+	struct MemoryClipboard(Arc<Mutex<String>>);
+
+	impl Clipboard for MemoryClipboard {
+		fn get(&mut self) -> Result<Zeroizing<String>, FortressError> {
+			Ok(Zeroizing::new(self.0.lock().unwrap().clone()))
+		}
+
+		fn set(&mut self, text: &str) -> Result<(), FortressError> {
+			*self.0.lock().unwrap() = text.to_string();
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn copy_secret_restores() {
+		let mut clipboard = MemoryClipboard::default();
+		clipboard.set("previous").unwrap();
+
+		let pending = copy_secret(clipboard.clone(), "hunter2", Duration::from_millis(50)).unwrap();
+		assert_eq!(*clipboard.get().unwrap(), "hunter2");
+		pending.wait().unwrap();
+		assert_eq!(*clipboard.get().unwrap(), "previous");
+
+		// Changed in the meantime, so it's left alone
+		let pending = copy_secret(clipboard.clone(), "hunter2", Duration::from_millis(200)).unwrap();
+		thread::sleep(Duration::from_millis(50));
+		clipboard.set("something else").unwrap();
+		pending.wait().unwrap();
+		assert_eq!(*clipboard.get().unwrap(), "something else");
+	}
+}
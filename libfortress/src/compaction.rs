@@ -0,0 +1,70 @@
+// Explicitly destructive compaction of history.
+// Everything else in this library preserves history (see the methodology note in lib.rs).  This is the one escape hatch, for
+// users whose databases have grown too large, so it is kept separate and requires HistoryLossConfirmed to call.
+//
+// What is lost, for history before the cutoff:
+//  * Entries: every edit is squashed into a single edit, timestamped at the last squashed edit, that sets all fields, field
+//    metadata, field order, and tags to their state at that point.  Old values of fields, and tags that were removed, are gone.
+//  * Directories: replaced by a rename to the name at that point (if any) and an add for each child at that point.  The names
+//    a directory used to have and objects that were added and later removed are gone.
+// Which devices made the squashed changes (see the device module) is lost too.  History after the cutoff, and the current state of
+// every object, are unchanged.
+//
+// On the next sync, the server's copies of compacted objects are compacted the same way before being merged, and the result
+// replaces them; edits made on other devices after the cutoff are kept.  Other devices still have the full history, though, and
+// a device that hasn't compacted with the same cutoff will merge the full history back in when it syncs.  To reclaim the space
+// everywhere, compact every device with the same cutoff before syncing them.
+use crate::{database_object::DatabaseObject, Database};
+use serde::Serialize;
+
+
+/// Passed to `Database::compact_history` to confirm that permanently losing history is intended.
+#[derive(Copy, Clone, Debug)]
+pub struct HistoryLossConfirmed;
+
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct CompactionReport {
+	pub entries_compacted: usize,
+	pub directories_compacted: usize,
+	/// Total history items removed across all objects.
+	pub history_items_removed: usize,
+}
+
+
+impl Database {
+	/// PERMANENTLY deletes history older than `before` (a Unix timestamp in nanoseconds), keeping every object's state as of then.
+	/// See the compaction module for exactly what is lost and how this interacts with sync.
+	pub fn compact_history(&mut self, before: u64, _confirmation: HistoryLossConfirmed) -> CompactionReport {
+		let mut report = CompactionReport::default();
+
+		let compacted = self
+			.objects
+			.values()
+			.filter_map(|object| {
+				let compacted = object.compact_history(before)?;
+
+				match (object, &compacted) {
+					(DatabaseObject::Entry(old), DatabaseObject::Entry(new)) => {
+						report.entries_compacted += 1;
+						report.history_items_removed += old.get_history().len() - new.get_history().len();
+					},
+					(DatabaseObject::Directory(old), DatabaseObject::Directory(new)) => {
+						report.directories_compacted += 1;
+						report.history_items_removed += old.get_history().len() - new.get_history().len();
+					},
+					_ => unreachable!(),
+				}
+
+				Some(compacted)
+			})
+			.collect::<Vec<_>>();
+
+		for object in compacted {
+			self.compacted_objects.insert(*object.get_id(), before);
+			self.objects.replace_destructively(object);
+		}
+
+		report
+	}
+}
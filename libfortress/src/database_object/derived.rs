@@ -0,0 +1,188 @@
+// Derived fields, whose values are computed from an entry's other fields instead of being stored, e.g. the current TOTP code for
+// a stored secret, or an email address built from the username.  A field is derived if its FieldMetadata says how to derive it;
+// whatever value the field has stored is ignored.  See `Entry::resolved_state`.
+//
+// Derivations only ever read the stored values of non-derived fields, so derived fields can't depend on each other and resolving
+// them never loops.
+use fortresscrypto::{TotpAlgorithm, Zeroizing};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use url::Url;
+
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DerivedField {
+	/// The current code for the TOTP secret in the field `source`, either an otpauth:// URI or a bare base32 secret.
+	Totp { source: String },
+	/// `value` with each `{name}` replaced by the field `name`.  `{{` and `}}` are literal braces, and placeholders that don't
+	/// name a stored field are left as they are.
+	Template { value: String },
+}
+
+impl DerivedField {
+	/// The field's value given the entry's stored, non-derived, fields, at `now` (Unix time, in nanoseconds).  None if it can't be
+	/// derived, e.g. because the TOTP secret is missing or isn't valid.
+	pub(crate) fn derive(&self, fields: &HashMap<&str, &str>, now: u64) -> Option<String> {
+		match self {
+			DerivedField::Totp { source } => {
+				let params = TotpParams::parse(fields.get(source.as_str())?)?;
+				fortresscrypto::totp(&params.secret, params.algorithm, params.digits, params.period, now / 1_000_000_000)
+			},
+			DerivedField::Template { value } => Some(expand_template(value, fields)),
+		}
+	}
+}
+
+
+struct TotpParams {
+	secret: Zeroizing<Vec<u8>>,
+	algorithm: TotpAlgorithm,
+	digits: u32,
+	period: u64,
+}
+
+impl TotpParams {
+	fn parse(source: &str) -> Option<TotpParams> {
+		let source = source.trim();
+
+		if !source.get(..10).is_some_and(|scheme| scheme.eq_ignore_ascii_case("otpauth://")) {
+			return Some(TotpParams {
+				secret: decode_base32(source)?,
+				algorithm: TotpAlgorithm::Sha1,
+				digits: 6,
+				period: 30,
+			});
+		}
+
+		let url = Url::parse(source).ok()?;
+		if url.host_str() != Some("totp") {
+			return None;
+		}
+
+		let mut params = TotpParams {
+			secret: Zeroizing::new(Vec::new()),
+			algorithm: TotpAlgorithm::Sha1,
+			digits: 6,
+			period: 30,
+		};
+
+		for (key, value) in url.query_pairs() {
+			match &*key {
+				"secret" => params.secret = decode_base32(&value)?,
+				"algorithm" => {
+					params.algorithm = match value.to_ascii_uppercase().as_str() {
+						"SHA1" => TotpAlgorithm::Sha1,
+						"SHA256" => TotpAlgorithm::Sha256,
+						"SHA512" => TotpAlgorithm::Sha512,
+						_ => return None,
+					}
+				},
+				"digits" => params.digits = value.parse().ok()?,
+				"period" => params.period = value.parse().ok()?,
+				_ => (),
+			}
+		}
+
+		(!params.secret.is_empty()).then_some(params)
+	}
+}
+
+
+// Secrets are often shown in groups with spaces or dashes between them, in lowercase, and with or without padding
+fn decode_base32(secret: &str) -> Option<Zeroizing<Vec<u8>>> {
+	let secret = Zeroizing::new(
+		secret
+			.chars()
+			.filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+			.map(|c| c.to_ascii_uppercase())
+			.collect::<String>(),
+	);
+
+	data_encoding::BASE32_NOPAD
+		.decode(secret.as_bytes())
+		.ok()
+		.filter(|secret| !secret.is_empty())
+		.map(Zeroizing::new)
+}
+
+
+fn expand_template(template: &str, fields: &HashMap<&str, &str>) -> String {
+	let mut result = String::with_capacity(template.len());
+	let mut rest = template;
+
+	while let Some(start) = rest.find(['{', '}']) {
+		result.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		if rest.starts_with("{{") || rest.starts_with("}}") {
+			result.push_str(&rest[..1]);
+			rest = &rest[2..];
+		} else if let Some((name, value)) = placeholder(rest, fields) {
+			result.push_str(value);
+			rest = &rest[name.len() + 2..];
+		} else {
+			result.push_str(&rest[..1]);
+			rest = &rest[1..];
+		}
+	}
+
+	result.push_str(rest);
+	result
+}
+
+
+// The field named by the placeholder at the start of `template`, if there is one and the field exists
+fn placeholder<'a>(template: &'a str, fields: &HashMap<&str, &'a str>) -> Option<(&'a str, &'a str)> {
+	let (name, _) = template.strip_prefix('{')?.split_once('}')?;
+
+	if name.contains('{') {
+		return None;
+	}
+
+	Some((name, fields.get(name)?))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{expand_template, DerivedField, TotpParams};
+	use fortresscrypto::TotpAlgorithm;
+	use std::collections::HashMap;
+
+	#[test]
+	fn derive() {
+		// The RFC 6238 SHA1 secret, "12345678901234567890"
+		let secret = "gezd gnbv gy3t qojq-gezd gnbv gy3t qojq";
+		let fields = HashMap::from([
+			("secret", secret),
+			(
+				"uri",
+				"otpauth://totp/Example:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8&issuer=Example",
+			),
+			("username", "alice"),
+			("notes", "not a secret"),
+		]);
+		let totp = |source: &str, now: u64| DerivedField::Totp { source: source.to_owned() }.derive(&fields, now * 1_000_000_000);
+
+		assert_eq!(totp("secret", 59).as_deref(), Some("287082"));
+		assert_eq!(totp("uri", 59).as_deref(), Some("94287082"));
+		assert_eq!(totp("uri", 1111111109).as_deref(), Some("07081804"));
+		assert_eq!(totp("notes", 59), None);
+		assert_eq!(totp("missing", 59), None);
+
+		let params = TotpParams::parse("OTPAUTH://totp/x?secret=GEZDGNBV&algorithm=sha512&period=60").unwrap();
+		assert_eq!((params.algorithm, params.digits, params.period), (TotpAlgorithm::Sha512, 6, 60));
+		assert!(TotpParams::parse("otpauth://hotp/x?secret=GEZDGNBV").is_none());
+		assert!(TotpParams::parse("otpauth://totp/x?secret=GEZDGNBV&algorithm=md5").is_none());
+		assert!(TotpParams::parse("otpauth://totp/x").is_none());
+
+		assert_eq!(expand_template("{username}@corp.com", &fields), "alice@corp.com");
+		assert_eq!(
+			expand_template("{{username}} {username}}} {unknown} {user{username}", &fields),
+			"{username} alice} {unknown} {useralice"
+		);
+		assert_eq!(expand_template("}{", &fields), "}{");
+		assert_eq!(expand_template("é{username}ü", &fields), "éaliceü");
+	}
+}
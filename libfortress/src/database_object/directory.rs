@@ -1,11 +1,21 @@
-use crate::ROOT_DIRECTORY_ID;
-
-use super::super::{unix_timestamp, Database, ID};
-use rand::{rngs::OsRng, Rng};
+use crate::{ROOT_DIRECTORY_ID, TRASH_DIRECTORY_ID};
+
+use super::super::{
+	device::{DeviceId, EditingDevice},
+	limits::{self, SyncLimit},
+	time_source::{SystemClock, TimeSource},
+	Database, HistoryOrderError, ID,
+};
+use rand::{rngs::OsRng, Rng, RngCore};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
 
+/// Renames made independently on two devices within this long of each other (nanoseconds) are reported as a conflict when
+/// syncing, rather than the later one silently winning.
+pub const RENAME_CONFLICT_WINDOW: u64 = 5 * 60 * 1_000_000_000;
+
+
 /// A directory is a list of references to Entries and Directories, much like a filesystem directory.
 /// History is always ordered (by timestamp) and consistent (no double adds or removes of non-existant IDs).
 #[derive(Serialize, Eq, PartialEq, Debug, Clone)]
@@ -18,16 +28,29 @@ pub struct Directory {
 
 	#[serde(skip_serializing)]
 	pub name: Option<String>,
+
+	#[serde(skip_serializing)]
+	local_only: bool,
+
+	#[serde(skip_serializing)]
+	editing_device: EditingDevice,
 }
 
 impl Directory {
 	#[allow(clippy::new_without_default)]
 	pub fn new() -> Directory {
+		Directory::new_with(&mut OsRng)
+	}
+
+	/// Same as `new`, but with an ID from `rng` (see the time_source module).
+	pub fn new_with(rng: &mut dyn RngCore) -> Directory {
 		Directory {
-			id: OsRng.gen(),
+			id: rng.gen(),
 			history: Vec::new(),
 			entries: HashSet::new(),
 			name: None,
+			local_only: false,
+			editing_device: EditingDevice::default(),
 		}
 	}
 
@@ -37,6 +60,19 @@ impl Directory {
 			history: Vec::new(),
 			entries: HashSet::new(),
 			name: None,
+			local_only: false,
+			editing_device: EditingDevice::default(),
+		}
+	}
+
+	pub(crate) fn new_trash() -> Directory {
+		Directory {
+			id: TRASH_DIRECTORY_ID,
+			history: Vec::new(),
+			entries: HashSet::new(),
+			name: None,
+			local_only: false,
+			editing_device: EditingDevice::default(),
 		}
 	}
 
@@ -46,6 +82,7 @@ impl Directory {
 		let mut entries = HashSet::new();
 		let mut min_next_timestamp = 0;
 		let mut name = None;
+		let mut local_only = false;
 
 		for history_item in &history {
 			// History must be ordered
@@ -68,10 +105,20 @@ impl Directory {
 				DirectoryHistoryAction::Rename(ref new_name) => {
 					name = Some(new_name.clone());
 				},
+				DirectoryHistoryAction::SetLocalOnly(value) => {
+					local_only = value;
+				},
 			};
 		}
 
-		Some(Directory { id, entries, history, name })
+		Some(Directory {
+			id,
+			entries,
+			history,
+			name,
+			local_only,
+			editing_device: EditingDevice::default(),
+		})
 	}
 
 	pub fn get_id(&self) -> &ID {
@@ -86,64 +133,120 @@ impl Directory {
 		self.name.as_deref()
 	}
 
+	/// True if what's in the directory is kept off the sync server (see the selective_sync module).
+	pub fn is_local_only(&self) -> bool {
+		self.local_only
+	}
+
 	pub fn add(&mut self, id: ID) {
-		self.add_with_time(id, unix_timestamp())
+		self.add_with(id, &SystemClock)
 	}
 
+	/// Same as `add`, timestamped by `time_source` (see the time_source module).
+	pub fn add_with(&mut self, id: ID, time_source: &dyn TimeSource) {
+		self.add_with_time(id, self.next_time(time_source))
+	}
+
+	/// Same as `try_add_with_time`, but panics if `time` is before the latest change.
 	pub fn add_with_time(&mut self, id: ID, time: u64) {
-		if !self.entries.insert(id) {
+		self.try_add_with_time(id, time).unwrap_or_else(|err| panic!("{err}"))
+	}
+
+	/// Adds `id` at `time`, or just after the latest change if that was at the very same time.  Fails, changing nothing, if
+	/// `time` is before the latest change, since history must be ordered.  Panics if `id` is already in the directory.
+	pub fn try_add_with_time(&mut self, id: ID, time: u64) -> Result<(), HistoryOrderError> {
+		if self.entries.contains(&id) {
 			panic!("Cannot add duplicate ID to directory.");
 		}
 
-		if let Some(last) = self.history.last() {
-			if time <= last.time {
-				panic!("Directory history must be ordered");
-			}
-		}
+		let time = self.ordered_time(time)?;
+		self.entries.insert(id);
 
 		self.history.push(DirectoryHistory {
 			action: DirectoryHistoryAction::Add(id),
 			time,
+			device: self.editing_device.0,
 		});
+
+		Ok(())
 	}
 
 	pub fn remove(&mut self, id: ID) {
-		self.remove_with_time(id, unix_timestamp())
+		self.remove_with(id, &SystemClock)
 	}
 
+	/// Same as `remove`, timestamped by `time_source` (see the time_source module).
+	pub fn remove_with(&mut self, id: ID, time_source: &dyn TimeSource) {
+		self.remove_with_time(id, self.next_time(time_source))
+	}
+
+	/// Same as `try_remove_with_time`, but panics if `time` is before the latest change.
 	pub fn remove_with_time(&mut self, id: ID, time: u64) {
-		if !self.entries.remove(&id) {
+		self.try_remove_with_time(id, time).unwrap_or_else(|err| panic!("{err}"))
+	}
+
+	/// Like `try_add_with_time`, but removes `id`.  Panics if `id` isn't in the directory.
+	pub fn try_remove_with_time(&mut self, id: ID, time: u64) -> Result<(), HistoryOrderError> {
+		if !self.entries.contains(&id) {
 			panic!("Cannot remove non-existant ID from directory.");
 		}
 
-		if let Some(last) = self.history.last() {
-			if time <= last.time {
-				panic!("Directory history must be ordered");
-			}
-		}
+		let time = self.ordered_time(time)?;
+		self.entries.remove(&id);
 
 		self.history.push(DirectoryHistory {
 			action: DirectoryHistoryAction::Remove(id),
 			time,
+			device: self.editing_device.0,
 		});
+
+		Ok(())
 	}
 
 	pub fn rename<S: Into<String>>(&mut self, name: S) {
-		self.rename_with_time(name, unix_timestamp())
+		self.rename_with(name, &SystemClock)
 	}
 
-	pub fn rename_with_time<S: Into<String>>(&mut self, name: S, time: u64) {
-		let name = name.into();
+	/// Same as `rename`, timestamped by `time_source` (see the time_source module).
+	pub fn rename_with<S: Into<String>>(&mut self, name: S, time_source: &dyn TimeSource) {
+		self.rename_with_time(name, self.next_time(time_source))
+	}
 
-		if let Some(last) = self.history.last() {
-			if time <= last.time {
-				panic!("Directory history must be ordered");
-			}
+	// The time for a change made now.  History merged in from another device may be timestamped slightly ahead of our clock.
+	fn next_time(&self, time_source: &dyn TimeSource) -> u64 {
+		match self.history.last() {
+			Some(last) => time_source.now().max(last.time + 1),
+			None => time_source.now(),
 		}
+	}
+
+	// `time`, or just after the latest change if that was at the very same time (e.g. on a coarse clock).  An error if `time` is
+	// before the latest change, since history must be ordered.
+	fn ordered_time(&self, time: u64) -> Result<u64, HistoryOrderError> {
+		match self.history.last() {
+			Some(last) if time < last.time => Err(HistoryOrderError {
+				id: self.id,
+				time,
+				latest: last.time,
+			}),
+			Some(last) if time == last.time => Ok(time + 1),
+			_ => Ok(time),
+		}
+	}
+
+	/// Same as `try_rename_with_time`, but panics if `time` is before the latest change.
+	pub fn rename_with_time<S: Into<String>>(&mut self, name: S, time: u64) {
+		self.try_rename_with_time(name, time).unwrap_or_else(|err| panic!("{err}"))
+	}
+
+	/// Like `try_add_with_time`, but renames the directory.
+	pub fn try_rename_with_time<S: Into<String>>(&mut self, name: S, time: u64) -> Result<(), HistoryOrderError> {
+		let name = name.into();
+		let time = self.ordered_time(time)?;
 
 		// Don't add a rename if the name is the same
 		if self.name.as_deref() == Some(&name) {
-			return;
+			return Ok(());
 		}
 
 		self.name = Some(name.clone());
@@ -151,7 +254,91 @@ impl Directory {
 		self.history.push(DirectoryHistory {
 			action: DirectoryHistoryAction::Rename(name),
 			time,
+			device: self.editing_device.0,
+		});
+
+		Ok(())
+	}
+
+	/// Keeps what's in the directory off the sync server, or lets it sync again (see the selective_sync module).
+	pub fn set_local_only(&mut self, local_only: bool) {
+		self.set_local_only_with(local_only, &SystemClock)
+	}
+
+	/// Same as `set_local_only`, timestamped by `time_source` (see the time_source module).
+	pub fn set_local_only_with(&mut self, local_only: bool, time_source: &dyn TimeSource) {
+		self.set_local_only_with_time(local_only, self.next_time(time_source))
+	}
+
+	/// Same as `try_set_local_only_with_time`, but panics if `time` is before the latest change.
+	pub fn set_local_only_with_time(&mut self, local_only: bool, time: u64) {
+		self.try_set_local_only_with_time(local_only, time).unwrap_or_else(|err| panic!("{err}"))
+	}
+
+	/// Like `try_add_with_time`, but sets whether the directory is local-only.
+	pub fn try_set_local_only_with_time(&mut self, local_only: bool, time: u64) -> Result<(), HistoryOrderError> {
+		let time = self.ordered_time(time)?;
+
+		if self.local_only == local_only {
+			return Ok(());
+		}
+
+		self.local_only = local_only;
+
+		self.history.push(DirectoryHistory {
+			action: DirectoryHistoryAction::SetLocalOnly(local_only),
+			time,
+			device: self.editing_device.0,
 		});
+
+		Ok(())
+	}
+
+	/// Returns a copy of this directory with all of its history before `before` replaced by the minimum needed to reproduce the
+	/// state as of then (a rename, whether it's local-only, and an add for each child), or None if that wouldn't be any shorter.
+	/// This permanently loses history; see `Database::compact_history`.
+	pub(crate) fn compact_history(&self, before: u64) -> Option<Directory> {
+		let split = self.history.partition_point(|item| item.time < before);
+		let old = Directory::from_history(self.id, self.history[..split].to_vec())?;
+
+		let mut children = old.entries.into_iter().collect::<Vec<_>>();
+		children.sort_unstable();
+		let actions = old
+			.name
+			.map(DirectoryHistoryAction::Rename)
+			.into_iter()
+			.chain(old.local_only.then_some(DirectoryHistoryAction::SetLocalOnly(true)))
+			.chain(children.into_iter().map(DirectoryHistoryAction::Add))
+			.collect::<Vec<_>>();
+
+		if actions.len() >= split {
+			return None;
+		}
+
+		// Squashed actions get consecutive timestamps ending at the last squashed item, so they stay before the cutoff
+		let last_time = self.history[split - 1].time;
+		let first_time = last_time.checked_sub(actions.len().saturating_sub(1) as u64)?;
+		let squashed = actions.into_iter().enumerate().map(|(i, action)| DirectoryHistory {
+			action,
+			time: first_time + i as u64,
+			device: None,
+		});
+		let history = squashed.chain(self.history[split..].iter().cloned()).collect();
+
+		Directory::from_history(self.id, history)
+	}
+
+	/// The directory as it was at `time` (inclusive), with only the history up to then, or None if it had no history yet.
+	/// Directories don't record when they were created, so a directory is taken to exist from its first change (it's named or
+	/// has something added when created).  The root directory always exists.
+	pub(crate) fn as_of(&self, time: u64) -> Option<Directory> {
+		let split = self.history.partition_point(|item| item.time <= time);
+
+		if split == 0 && self.id != ROOT_DIRECTORY_ID {
+			return None;
+		}
+
+		Directory::from_history(self.id, self.history[..split].to_vec())
 	}
 
 	/// List all Entry entries in this directory.
@@ -159,6 +346,28 @@ impl Directory {
 		self.entries.iter().filter(|id| database.get_entry_by_id(id).is_some()).collect()
 	}
 
+	/// List all Entry entries in this directory and, recursively, all of its subdirectories.
+	/// IDs that don't refer to an existing object are skipped, and each directory is only visited once even if the tree is malformed.
+	/// Entries reachable through more than one directory are only listed once.
+	pub fn list_entries_recursive<'a>(&'a self, database: &'a Database) -> Vec<&'a ID> {
+		let mut entries = Vec::new();
+		let mut listed = HashSet::new();
+		let mut visited = HashSet::from([self.id]);
+		let mut pending = vec![self];
+
+		while let Some(directory) = pending.pop() {
+			entries.extend(directory.list_entries(database).into_iter().filter(|id| listed.insert(*id)));
+
+			for id in directory.list_directories(database) {
+				if visited.insert(*id) {
+					pending.extend(database.get_directory_by_id(id));
+				}
+			}
+		}
+
+		entries
+	}
+
 	/// List all Directory entries in this directory.
 	pub fn list_directories<'a>(&'a self, database: &'a Database) -> impl Iterator<Item = &'a ID> + 'a {
 		self.entries.iter().filter(|id| database.get_directory_by_id(id).is_some())
@@ -179,10 +388,11 @@ impl Directory {
 		let mut merged_history = [&self.history[..], &other.history[..]].concat();
 
 		// Sort by timestamp
-		merged_history.sort_unstable_by(|a, b| a.time.cmp(&b.time));
+		merged_history.sort_unstable_by_key(|a| (a.time, a.device));
 
-		// Remove duplicates (the same timestamp and operation)
-		merged_history.dedup();
+		// Remove duplicates (the same timestamp and operation).  When the same change was made on two devices, the lower device ID
+		// is kept, so every device merges to the same history.
+		merged_history.dedup_by(|a, b| a.same_change(b));
 
 		// Re-build state and validate
 		// If we are unable to re-build state that means the merged history was
@@ -190,6 +400,36 @@ impl Directory {
 		Directory::from_history(self.id, merged_history)
 	}
 
+	/// Checks whether self and other were renamed independently of each other (neither has the other's latest rename), to
+	/// different names, within RENAME_CONFLICT_WINDOW.  If so, returns the (later, earlier) names.
+	/// Merging keeps the later name, so this is used to let the user choose instead.
+	pub fn concurrent_rename<'a>(&'a self, other: &'a Directory) -> Option<(&'a str, &'a str)> {
+		let last_rename = |directory: &'a Directory| {
+			directory.history.iter().rev().find_map(|item| match &item.action {
+				DirectoryHistoryAction::Rename(name) => Some((item, name.as_str())),
+				_ => None,
+			})
+		};
+		let (ours, our_name) = last_rename(self)?;
+		let (theirs, their_name) = last_rename(other)?;
+
+		let has = |directory: &Directory, item: &DirectoryHistory| directory.history.iter().any(|other| other.same_change(item));
+
+		if our_name == their_name || has(other, ours) || has(self, theirs) {
+			return None;
+		}
+
+		if ours.time.abs_diff(theirs.time) > RENAME_CONFLICT_WINDOW {
+			return None;
+		}
+
+		if ours.time > theirs.time {
+			Some((our_name, their_name))
+		} else {
+			Some((their_name, our_name))
+		}
+	}
+
 	/// Returns true only if it is safe to replace self with other in the Database.
 	/// This is only true if doing so is a non-destructive operation (i.e. history is perserved).
 	pub fn safe_to_replace_with(&self, other: &Directory) -> bool {
@@ -201,13 +441,39 @@ impl Directory {
 
 		// Sequentially search other's history for our history.
 		for item in &self.history {
-			if !other_iter.any(|other_item| other_item == item) {
+			if !other_iter.any(|other_item| other_item.same_change(item)) {
 				return false;
 			}
 		}
 
 		true
 	}
+
+	pub(crate) fn set_editing_device(&mut self, device: Option<DeviceId>) {
+		self.editing_device = EditingDevice(device);
+	}
+
+	/// Same as `Entry::attribute_to`.
+	pub(crate) fn attribute_to(&mut self, device: Option<DeviceId>) {
+		for item in self.history.iter_mut().filter(|item| item.device.is_none()) {
+			item.device = device;
+		}
+
+		self.set_editing_device(device);
+	}
+
+	/// Checks the directory against the limits on objects from the sync server (see the limits module).
+	pub(crate) fn check_limits(&self) -> Result<(), SyncLimit> {
+		limits::check_history_length(self.history.len())?;
+
+		for item in &self.history {
+			if let DirectoryHistoryAction::Rename(name) = &item.action {
+				limits::check_field(name)?;
+			}
+		}
+
+		Ok(())
+	}
 }
 
 impl<'de> serde::Deserialize<'de> for Directory {
@@ -233,6 +499,17 @@ pub struct DirectoryHistory {
 	pub action: DirectoryHistoryAction,
 	/// Unix timestamp for when this edit occurred (in nanoseconds).
 	pub time: u64,
+	/// The device that made the edit, if known (see the device module).  Left out of the serialized form when unknown, so
+	/// edits serialize exactly as they did before devices were recorded.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub device: Option<DeviceId>,
+}
+
+impl DirectoryHistory {
+	// Whether the two are the same change, whichever devices made them
+	fn same_change(&self, other: &DirectoryHistory) -> bool {
+		self.time == other.time && self.action == other.action
+	}
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -240,13 +517,14 @@ pub enum DirectoryHistoryAction {
 	Add(ID),
 	Remove(ID),
 	Rename(String),
+	/// See `Directory::set_local_only`.
+	SetLocalOnly(bool),
 }
 
 
 #[cfg(test)]
 mod tests {
-	use super::{Directory, DirectoryHistory, DirectoryHistoryAction};
-	use crate::tests::quick_sleep;
+	use super::{Directory, DirectoryHistory, DirectoryHistoryAction, RENAME_CONFLICT_WINDOW};
 	use rand::{rngs::OsRng, Rng};
 	use serde_json;
 
@@ -257,10 +535,12 @@ mod tests {
 			DirectoryHistory {
 				action: DirectoryHistoryAction::Add(OsRng.gen()),
 				time: 50,
+				device: None,
 			},
 			DirectoryHistory {
 				action: DirectoryHistoryAction::Add(OsRng.gen()),
 				time: 0,
+				device: None,
 			},
 		];
 
@@ -278,10 +558,12 @@ mod tests {
 				DirectoryHistory {
 					action: DirectoryHistoryAction::Add(id1),
 					time: 0,
+					device: None,
 				},
 				DirectoryHistory {
 					action: DirectoryHistoryAction::Add(id1),
 					time: 5,
+					device: None,
 				},
 			];
 
@@ -294,6 +576,7 @@ mod tests {
 			bad_directory.history = vec![DirectoryHistory {
 				action: DirectoryHistoryAction::Remove(OsRng.gen()),
 				time: 0,
+				device: None,
 			}];
 
 			let serialized = serde_json::to_string(&bad_directory).unwrap();
@@ -307,7 +590,6 @@ mod tests {
 		let mut directory = Directory::new();
 		let id = OsRng.gen();
 		directory.add(id);
-		quick_sleep();
 		directory.add(id);
 	}
 
@@ -335,6 +617,49 @@ mod tests {
 		directory.remove_with_time(id, 999);
 	}
 
+	// History merged in from a device whose clock is ahead
+	#[test]
+	fn changes_after_future_history() {
+		let mut directory = Directory::new();
+		let id = OsRng.gen();
+		directory.add_with_time(id, u64::MAX - 10);
+
+		directory.remove(id);
+		directory.add(id);
+		directory.rename("Later");
+		assert_eq!(directory.history.last().unwrap().time, u64::MAX - 7);
+		assert!(Directory::from_history(directory.id, directory.history.clone()).is_some());
+	}
+
+	#[test]
+	fn same_time_changes_are_bumped() {
+		let mut directory = Directory::new();
+		let id = OsRng.gen();
+		directory.add_with_time(id, 1000);
+		directory.remove_with_time(id, 1000);
+		directory.rename_with_time("Name", 1001);
+		assert_eq!(directory.history.iter().map(|item| item.time).collect::<Vec<_>>(), [1000, 1001, 1002]);
+	}
+
+	#[test]
+	fn out_of_order_changes_fail() {
+		let mut directory = Directory::new();
+		let (id1, id2) = (OsRng.gen(), OsRng.gen());
+		directory.add_with_time(id1, 1000);
+
+		let err = directory.try_add_with_time(id2, 999).unwrap_err();
+		assert_eq!((err.id, err.time, err.latest), (directory.id, 999, 1000));
+		assert!(directory.try_remove_with_time(id1, 999).is_err());
+		assert!(directory.try_rename_with_time("Name", 999).is_err());
+		assert!(directory.try_set_local_only_with_time(true, 999).is_err());
+
+		// Nothing changed
+		assert_eq!(directory.history.len(), 1);
+		assert!(directory.entries.contains(&id1) && !directory.entries.contains(&id2));
+		assert!(directory.name.is_none() && !directory.local_only);
+		directory.try_rename_with_time("Name", 1001).unwrap();
+	}
+
 	#[test]
 	#[should_panic]
 	fn bad_rename_should_panic1() {
@@ -360,11 +685,9 @@ mod tests {
 		{
 			let mut directory1 = Directory::new();
 			directory1.add(OsRng.gen());
-			quick_sleep();
 			let mut directory2 = directory1.clone();
 			let id = OsRng.gen();
 			directory1.add(id);
-			quick_sleep();
 			directory2.add(id);
 			assert!(directory1.merge(&directory2).is_none());
 			assert!(!directory1.safe_to_replace_with(&directory2));
@@ -375,7 +698,6 @@ mod tests {
 			let mut directory1 = Directory::new();
 			let mut directory2 = directory1.clone();
 			directory1.add(OsRng.gen());
-			quick_sleep();
 			directory2.add(OsRng.gen());
 			assert!(!directory1.safe_to_replace_with(&directory2));
 		}
@@ -384,17 +706,12 @@ mod tests {
 		{
 			let mut directory1 = Directory::new();
 			directory1.add(OsRng.gen());
-			quick_sleep();
 			let id = OsRng.gen();
 			directory1.add(id);
-			quick_sleep();
 			directory1.add(OsRng.gen());
-			quick_sleep();
 			let mut directory2 = directory1.clone();
 			directory2.add(OsRng.gen());
-			quick_sleep();
 			directory2.remove(id);
-			quick_sleep();
 			directory1.add(OsRng.gen());
 
 			assert_eq!(directory1.safe_to_replace_with(&directory2), false);
@@ -420,4 +737,73 @@ mod tests {
 		let deserialized: Directory = serde_json::from_str(&serialized).unwrap();
 		assert_eq!(deserialized.get_name(), Some("Name2"));
 	}
+
+	#[test]
+	fn concurrent_rename() {
+		let mut directory = Directory::new();
+		directory.rename_with_time("Original", 1000);
+
+		// Renames on two devices close together
+		let mut directory1 = directory.clone();
+		let mut directory2 = directory.clone();
+		directory1.rename_with_time("Mine", 2000);
+		directory2.rename_with_time("Theirs", 3000);
+		assert_eq!(directory1.concurrent_rename(&directory2), Some(("Theirs", "Mine")));
+		assert_eq!(directory2.concurrent_rename(&directory1), Some(("Theirs", "Mine")));
+		assert_eq!(directory1.merge(&directory2).unwrap().get_name(), Some("Theirs"));
+
+		// Once merged, both renames are known and there's no conflict anymore
+		let merged = directory1.merge(&directory2).unwrap();
+		assert_eq!(merged.concurrent_rename(&directory1), None);
+		assert_eq!(directory2.concurrent_rename(&merged), None);
+
+		// Renaming to the same name isn't a conflict
+		let mut directory3 = directory.clone();
+		directory3.rename_with_time("Mine", 2500);
+		assert_eq!(directory1.concurrent_rename(&directory3), None);
+
+		// Renames far apart aren't a conflict, and only one side being renamed isn't either
+		let mut directory4 = directory.clone();
+		directory4.rename_with_time("Later", 2000 + RENAME_CONFLICT_WINDOW + 1);
+		assert_eq!(directory1.concurrent_rename(&directory4), None);
+		assert_eq!(directory1.concurrent_rename(&directory), None);
+		assert_eq!(Directory::new().concurrent_rename(&directory1), None);
+	}
+
+	#[test]
+	fn compact_history() {
+		let (id1, id2, id3): (_, _, _) = (OsRng.gen(), OsRng.gen(), OsRng.gen());
+		let mut directory = Directory::new();
+		directory.rename_with_time("Old", 100);
+		directory.add_with_time(id1, 110);
+		directory.add_with_time(id2, 120);
+		directory.remove_with_time(id1, 130);
+		directory.rename_with_time("New", 140);
+		directory.add_with_time(id3, 200);
+
+		// Nothing to squash, or squashing wouldn't make it shorter
+		assert_eq!(directory.compact_history(50), None);
+		assert_eq!(directory.compact_history(115), None);
+
+		let compacted = directory.compact_history(150).unwrap();
+		assert_eq!(compacted.get_id(), directory.get_id());
+		assert_eq!(compacted.get_name(), Some("New"));
+		assert!(compacted.contains(&id2) && compacted.contains(&id3) && !compacted.contains(&id1));
+		assert_eq!(
+			compacted.get_history(),
+			[
+				DirectoryHistory {
+					action: DirectoryHistoryAction::Rename("New".to_string()),
+					time: 139,
+					device: None,
+				},
+				DirectoryHistory {
+					action: DirectoryHistoryAction::Add(id2),
+					time: 140,
+					device: None,
+				},
+				directory.get_history()[5].clone(),
+			]
+		);
+	}
 }
@@ -1,9 +1,19 @@
-use super::super::{unix_timestamp, ID};
-use rand::{rngs::OsRng, Rng};
+use super::{
+	super::{
+		device::{DeviceId, EditingDevice},
+		limits::{self, SyncLimit},
+		time_source::{SystemClock, TimeSource},
+		HistoryOrderError, ID,
+	},
+	derived::DerivedField,
+	field::{Field, FieldMetadata},
+};
+use fortresscrypto::Zeroize;
+use rand::{rngs::OsRng, Rng, RngCore};
 use serde::{Deserialize, Serialize};
 use std::{
 	borrow::Borrow,
-	collections::{BTreeMap, HashMap},
+	collections::{BTreeMap, BTreeSet, HashMap},
 	hash::Hash,
 	ops::Index,
 };
@@ -15,25 +25,47 @@ pub struct Entry {
 	id: ID,
 	history: Vec<EntryHistory>,
 	time_created: u64, // Unix timestamp for when this entry was created (nanoseconds)
+	// When the entry was used (nanoseconds), kept apart from history so uses don't add edits to merge.  Left out when empty so
+	// entries that were never used serialize as they did before this existed.
+	#[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+	uses: BTreeSet<u64>,
 
 	// The current state of the entry
 	#[serde(skip_serializing, skip_deserializing)]
 	state: HashMap<String, String>,
+	#[serde(skip_serializing, skip_deserializing)]
+	metadata: HashMap<String, FieldMetadata>,
+	#[serde(skip_serializing, skip_deserializing)]
+	field_order: Vec<String>,
+	#[serde(skip_serializing, skip_deserializing)]
+	tags: BTreeSet<String>,
+	#[serde(skip_serializing, skip_deserializing)]
+	editing_device: EditingDevice,
 }
 
 impl Entry {
 	#[allow(clippy::new_without_default)]
 	pub fn new() -> Entry {
-		Entry::inner_new(OsRng.gen(), Vec::new(), unix_timestamp()).unwrap()
+		Entry::new_with(&SystemClock, &mut OsRng)
 	}
 
-	fn inner_new(id: ID, history: Vec<EntryHistory>, time_created: u64) -> Option<Entry> {
+	/// Same as `new`, but created at `time_source`'s time with an ID from `rng` (see the time_source module).
+	pub fn new_with(time_source: &dyn TimeSource, rng: &mut dyn RngCore) -> Entry {
+		Entry::inner_new(rng.gen(), Vec::new(), time_source.now()).unwrap()
+	}
+
+	pub(crate) fn inner_new(id: ID, history: Vec<EntryHistory>, time_created: u64) -> Option<Entry> {
 		let mut entry = Entry {
 			id,
 			history: history.clone(),
 			time_created,
+			uses: BTreeSet::new(),
 
 			state: HashMap::new(),
+			metadata: HashMap::new(),
+			field_order: Vec::new(),
+			tags: BTreeSet::new(),
+			editing_device: EditingDevice::default(),
 		};
 		let mut min_next_timestamp = 0;
 
@@ -71,24 +103,348 @@ impl Entry {
 		self.state.get(key)
 	}
 
+	/// The entry's state with its derived fields (see the derived module) computed as of `now` (Unix time, in nanoseconds).
+	/// Derived fields that can't be computed, e.g. because their TOTP secret is missing, are left out.
+	pub fn resolved_state(&self, now: u64) -> HashMap<String, String> {
+		let derived = self
+			.metadata
+			.iter()
+			.filter_map(|(name, metadata)| Some((name, metadata.derived.as_ref()?)))
+			.collect::<HashMap<_, _>>();
+		let stored = self
+			.state
+			.iter()
+			.filter(|(name, _)| !derived.contains_key(name))
+			.map(|(name, value)| (name.as_str(), value.as_str()))
+			.collect::<HashMap<_, _>>();
+
+		let mut state = stored
+			.iter()
+			.map(|(name, value)| (name.to_string(), value.to_string()))
+			.collect::<HashMap<_, _>>();
+		for (name, derived) in derived {
+			if let Some(value) = derived.derive(&stored, now) {
+				state.insert(name.clone(), value);
+			}
+		}
+
+		state
+	}
+
 	pub fn get_history(&self) -> &[EntryHistory] {
 		&self.history
 	}
 
-	pub fn edit(&mut self, mut new_data: EntryHistory) {
+	/// Metadata for the field `key`.  Fields that never had metadata set get defaults based on their name.
+	pub fn get_field_metadata(&self, key: &str) -> FieldMetadata {
+		self.metadata.get(key).cloned().unwrap_or_else(|| FieldMetadata::default_for(key))
+	}
+
+	pub fn is_field_protected(&self, key: &str) -> bool {
+		self.get_field_metadata(key).protected
+	}
+
+	/// The order fields should be displayed in, if one was ever set.
+	pub fn get_field_order(&self) -> &[String] {
+		&self.field_order
+	}
+
+	/// All of the entry's current fields along with their metadata, in display order.
+	/// Fields in the field order come first, followed by any others ordered by name.
+	pub fn get_fields(&self) -> Vec<Field<'_>> {
+		let ordered = self.field_order.iter().filter(|name| self.state.contains_key(*name));
+		let mut rest = self.state.keys().filter(|name| !self.field_order.contains(name)).collect::<Vec<_>>();
+		rest.sort_unstable();
+
+		ordered
+			.chain(rest)
+			.map(|name| {
+				let metadata = self.get_field_metadata(name);
+
+				Field {
+					name,
+					value: &self.state[name],
+					kind: metadata.kind,
+					protected: metadata.protected,
+				}
+			})
+			.collect()
+	}
+
+	/// Number of times `record_use` was called for this entry, on any device.
+	pub fn get_use_count(&self) -> usize {
+		self.uses.len()
+	}
+
+	/// When the entry was last used (nanoseconds), if ever.
+	pub fn get_last_used(&self) -> Option<u64> {
+		self.uses.last().copied()
+	}
+
+	/// Records that the entry was used (e.g. its password was copied).  Uses sync, but aren't edits: they don't show up in the
+	/// history and merge by combining both sides' uses.
+	pub fn record_use(&mut self) {
+		self.record_use_with(&SystemClock)
+	}
+
+	/// Same as `record_use`, timestamped by `time_source` (see the time_source module).
+	pub fn record_use_with(&mut self, time_source: &dyn TimeSource) {
+		let mut time = time_source.now();
+
+		// Uses are told apart by their time
+		while !self.uses.insert(time) {
+			time += 1;
+		}
+	}
+
+	pub fn get_tags(&self) -> &BTreeSet<String> {
+		&self.tags
+	}
+
+	pub fn has_tag(&self, tag: &str) -> bool {
+		self.tags.contains(tag)
+	}
+
+	/// Tags the entry with `tag`.  Like any other edit, this is recorded in the entry's history.
+	pub fn add_tag<T: Into<String>>(&mut self, tag: T) {
+		self.edit(self.new_edit(HashMap::new()).with_tag(tag, true));
+	}
+
+	pub fn remove_tag<T: Into<String>>(&mut self, tag: T) {
+		self.edit(self.new_edit(HashMap::new()).with_tag(tag, false));
+	}
+
+	/// Same as `EntryHistory::new`, but timestamped just after the entry's latest edit if that's ahead of our clock (edits merged
+	/// in from another device can be), so it's always safe to pass to `edit`.
+	pub fn new_edit(&self, data: HashMap<String, String>) -> EntryHistory {
+		self.new_edit_with(data, &SystemClock)
+	}
+
+	/// Same as `new_edit`, but timestamped by `time_source` (see the time_source module).
+	pub fn new_edit_with(&self, data: HashMap<String, String>, time_source: &dyn TimeSource) -> EntryHistory {
+		let mut edit = EntryHistory::new_with(data, time_source);
+
 		if let Some(last) = self.history.last() {
-			if new_data.time <= last.time {
-				panic!("Entry history must be ordered");
+			edit.time = edit.time.max(last.time + 1);
+		}
+
+		edit
+	}
+
+	/// An edit (see `new_edit`) that sets the field `key` back to its value as of `time` (inclusive), so an overwritten value can
+	/// be recovered without losing history.  None if the field had no value then, or still has that value, or if it's one the
+	/// entry's double lock holds (see `is_field_double_locked`), since reverting it would bring its old value back into the clear.
+	pub fn revert_field(&self, key: &str, time: u64) -> Option<EntryHistory> {
+		if self.is_field_double_locked(key) {
+			return None;
+		}
+
+		let value = self
+			.history
+			.iter()
+			.take_while(|item| item.time <= time)
+			.filter_map(|item| item.get(key))
+			.last()?;
+
+		if self.get(key) == Some(value) {
+			return None;
+		}
+
+		Some(self.new_edit([(key.to_string(), value.clone())].into()))
+	}
+
+	/// Undoes the entry's latest edit if it was made at `expected_time`, e.g. to roll back `rotate_password` after the website
+	/// refused the new password.  History is never removed, so the undo is a new edit setting back what the latest one changed;
+	/// fields it added are set to empty.  Returns false, changing nothing, if the latest edit isn't the expected one (another
+	/// edit was made since, or merged in).
+	pub fn undo_last_edit_if_matches(&mut self, expected_time: u64) -> bool {
+		let Some(last) = self.history.last().filter(|last| last.time == expected_time) else {
+			return false;
+		};
+		let previous = expected_time
+			.checked_sub(1)
+			.and_then(|time| self.as_of(time))
+			.unwrap_or_else(|| Entry::inner_new(self.id, Vec::new(), self.time_created).expect("internal error"));
+
+		let mut undo = self.new_edit(
+			last.data
+				.keys()
+				.map(|key| (key.clone(), previous.get(key).cloned().unwrap_or_default()))
+				.collect(),
+		);
+		undo.metadata = last.metadata.keys().map(|key| (key.clone(), previous.get_field_metadata(key))).collect();
+		undo.field_order = last.field_order.as_ref().map(|_| previous.field_order.clone());
+		undo.tags = last.tags.keys().map(|tag| (tag.clone(), previous.has_tag(tag))).collect();
+
+		self.edit(undo);
+		true
+	}
+
+	/// Returns a copy of this entry with all of its history before `before` squashed into a single edit that sets the state as of
+	/// then, or None if there's nothing to squash.  This permanently loses history; see `Database::compact_history`.
+	pub(crate) fn compact_history(&self, before: u64) -> Option<Entry> {
+		let split = self.history.partition_point(|item| item.time < before);
+
+		if split < 2 {
+			return None;
+		}
+
+		let old = Entry::inner_new(self.id, self.history[..split].to_vec(), self.time_created)?;
+		let squashed = EntryHistory {
+			time: self.history[split - 1].time,
+			data: old.state.clone(),
+			metadata: old.metadata.clone(),
+			field_order: (!old.field_order.is_empty()).then(|| old.field_order.clone()),
+			tags: old.tags.iter().map(|tag| (tag.clone(), true)).collect(),
+			device: None,
+		};
+		let history = std::iter::once(squashed).chain(self.history[split..].iter().cloned()).collect();
+		let mut compacted = Entry::inner_new(self.id, history, self.time_created)?;
+		compacted.uses = self.uses.clone();
+
+		Some(compacted)
+	}
+
+	/// The entry as it was at `time` (inclusive), with only the history up to then, or None if it didn't exist yet.
+	pub(crate) fn as_of(&self, time: u64) -> Option<Entry> {
+		if self.time_created > time {
+			return None;
+		}
+
+		let split = self.history.partition_point(|item| item.time <= time);
+		let mut entry = Entry::inner_new(self.id, self.history[..split].to_vec(), self.time_created)?;
+		entry.uses = self.uses.range(..=time).copied().collect();
+
+		Some(entry)
+	}
+
+	/// A copy of the entry with the non-empty values `redact` picks out (by field name) replaced with `REDACTED_VALUE` throughout
+	/// its history.
+	pub(crate) fn redacted(&self, redact: impl Fn(&str) -> bool) -> Entry {
+		let history = self
+			.history
+			.iter()
+			.map(|item| {
+				let mut item = item.clone();
+				for (_, value) in item.data.iter_mut().filter(|(key, value)| !value.is_empty() && redact(key)) {
+					value.zeroize();
+					*value = REDACTED_VALUE.to_string();
+				}
+				item
+			})
+			.collect();
+		let mut entry = Entry::inner_new(self.id, history, self.time_created).expect("internal error");
+		entry.uses = self.uses.clone();
+
+		entry
+	}
+
+	/// Reconstructs the entry's state as it was at `time` (inclusive).
+	fn state_at(&self, time: u64) -> HashMap<String, String> {
+		let mut state = HashMap::new();
+
+		for history_item in self.history.iter().take_while(|item| item.time <= time) {
+			for (key, value) in &history_item.data {
+				state.insert(key.to_string(), value.to_string());
 			}
 		}
 
-		// Remove any fields from the EntryHistory if they don't actually cause any changes to our state
+		state
+	}
+
+	/// Lists the fields that changed between the entry's state at `from_time` and its state at `to_time` (both inclusive), ordered by key.
+	/// Values of fields that are currently protected (see `is_field_protected`) or held by the entry's double lock (see
+	/// `is_field_double_locked`) are replaced with `REDACTED_VALUE`.
+	pub fn diff(&self, from_time: u64, to_time: u64) -> Vec<FieldChange> {
+		let mut changes = self.diff_unredacted(from_time, to_time);
+
+		for change in changes
+			.iter_mut()
+			.filter(|change| self.is_field_protected(&change.key) || self.is_field_double_locked(&change.key))
+		{
+			for value in [&mut change.old, &mut change.new].into_iter().flatten() {
+				value.zeroize();
+				*value = REDACTED_VALUE.to_string();
+			}
+		}
+
+		changes
+	}
+
+	/// Same as `diff`, but protected fields are returned as-is.
+	pub fn diff_unredacted(&self, from_time: u64, to_time: u64) -> Vec<FieldChange> {
+		let mut old_state = self.state_at(from_time);
+		let mut new_state = self.state_at(to_time);
+
+		let keys: BTreeSet<&String> = old_state.keys().chain(new_state.keys()).collect();
+		let changes = keys
+			.into_iter()
+			.filter(|key| old_state.get(*key) != new_state.get(*key))
+			.map(|key| FieldChange {
+				key: key.to_string(),
+				old: old_state.get(key).cloned(),
+				new: new_state.get(key).cloned(),
+			})
+			.collect();
+
+		zeroize_map(&mut old_state);
+		zeroize_map(&mut new_state);
+
+		changes
+	}
+
+	/// Same as `try_edit`, but panics if `new_data` is from before the latest edit.
+	pub fn edit(&mut self, new_data: EntryHistory) {
+		self.try_edit(new_data).unwrap_or_else(|err| panic!("{err}"))
+	}
+
+	/// Applies `new_data`.  If it isn't attributed to a device, it's attributed to the device the entry is being edited on, if
+	/// any (see the device module).  If it's timestamped at the very same time as the latest edit (e.g. on a coarse clock), it's
+	/// moved just after it.  Fails, changing nothing, if it's from before the latest edit, since history must be ordered; edits
+	/// from `new_edit` never are.
+	pub fn try_edit(&mut self, mut new_data: EntryHistory) -> Result<(), HistoryOrderError> {
+		if let Some(last) = self.history.last() {
+			if new_data.time < last.time {
+				return Err(HistoryOrderError {
+					id: self.id,
+					time: new_data.time,
+					latest: last.time,
+				});
+			} else if new_data.time == last.time {
+				new_data.time += 1;
+			}
+		}
+
+		new_data.device = new_data.device.or(self.editing_device.0);
+
+		// Remove anything from the EntryHistory that doesn't actually cause any changes to our state
 		new_data.data.retain(|k, v| self.state.get(k) != Some(v));
+		new_data.metadata.retain(|k, v| self.metadata.get(k) != Some(v));
+		if new_data.field_order.as_ref() == Some(&self.field_order) {
+			new_data.field_order = None;
+		}
+		new_data.tags.retain(|tag, present| self.tags.contains(tag) != *present);
 
-		if !new_data.data.is_empty() {
+		if !new_data.is_empty() {
 			self.apply_history(&new_data);
 			self.history.push(new_data);
 		}
+
+		Ok(())
+	}
+
+	pub(crate) fn set_editing_device(&mut self, device: Option<DeviceId>) {
+		self.editing_device = EditingDevice(device);
+	}
+
+	/// Attributes edits that aren't attributed to any device to `device`, and makes it the device future edits are attributed to.
+	/// Only for entries that are new to the database (see `Database::add_entry`).
+	pub(crate) fn attribute_to(&mut self, device: Option<DeviceId>) {
+		for item in self.history.iter_mut().filter(|item| item.device.is_none()) {
+			item.device = device;
+		}
+
+		self.set_editing_device(device);
 	}
 
 	// Used internally to apply an EntryHistory on top of this object's current state.
@@ -96,6 +452,22 @@ impl Entry {
 		for (key, value) in &new_data.data {
 			self.state.insert(key.to_string(), value.to_string());
 		}
+
+		for (key, metadata) in &new_data.metadata {
+			self.metadata.insert(key.to_string(), metadata.clone());
+		}
+
+		if let Some(field_order) = &new_data.field_order {
+			self.field_order = field_order.clone();
+		}
+
+		for (tag, present) in &new_data.tags {
+			if *present {
+				self.tags.insert(tag.to_string());
+			} else {
+				self.tags.remove(tag);
+			}
+		}
 	}
 
 	/// Attempts to merge self and other and return a new Entry.
@@ -106,24 +478,37 @@ impl Entry {
 		}
 
 		// Concat histories
-		let mut merged_history = [&self.history[..], &other.history[..]].concat();
+		let mut all_history = [&self.history[..], &other.history[..]].concat();
 
 		// Sort by timestamp
-		merged_history.sort_unstable_by(|a, b| a.time.cmp(&b.time));
+		all_history.sort_by_key(|a| a.time);
+
+		// Remove duplicates (edits made at the same time that have the same effect)
+		let mut merged_history: Vec<EntryHistory> = Vec::with_capacity(all_history.len());
+		let mut merged = Entry::inner_new(self.id, Vec::new(), self.time_created)?;
 
-		// Remove duplicates (the same timestamp and operation)
-		merged_history.dedup();
+		for group in all_history.chunk_by(|a, b| a.time == b.time) {
+			for item in dedup_simultaneous_edits(group, &merged) {
+				merged.apply_history(&item);
+				merged_history.push(item);
+			}
+		}
 
 		// Re-build state and validate
 		// If we are unable to re-build state that means the merged history was
 		// invalid due to a conflict (two edits at the same time).
-		Entry::inner_new(self.id, merged_history, self.time_created)
+		let mut result = Entry::inner_new(self.id, merged_history, self.time_created)?;
+		result.uses = self.uses.union(&other.uses).copied().collect();
+
+		Some(result)
 	}
 
 	/// Returns true only if it is non-destructive to replace self with other in a Database.
-	/// This is true only if all of our history is contained within other.
+	/// This is true only if all of our history (and uses) is contained within other.
+	/// An edit is contained in other if other has an edit at the same time with (at least) the same fields, values, and metadata;
+	/// this is the case for edits that merge combined with an equivalent edit.
 	pub fn safe_to_replace_with(&self, other: &Entry) -> bool {
-		if self.id != other.id {
+		if self.id != other.id || !self.uses.is_subset(&other.uses) {
 			return false;
 		}
 
@@ -131,13 +516,39 @@ impl Entry {
 
 		// Sequentially search other's history for our history.
 		for item in &self.history {
-			if !other_iter.any(|other_item| other_item == item) {
+			if !other_iter.any(|other_item| other_item.contains(item)) {
 				return false;
 			}
 		}
 
 		true
 	}
+
+	/// Checks the entry against the limits on objects from the sync server (see the limits module).
+	pub(crate) fn check_limits(&self) -> Result<(), SyncLimit> {
+		limits::check_history_length(self.history.len())?;
+		limits::check_history_length(self.uses.len())?;
+
+		for item in &self.history {
+			for (key, value) in &item.data {
+				limits::check_field(key)?;
+				limits::check_field(value)?;
+			}
+
+			let field_order = item.field_order.iter().flatten();
+			for field in item.metadata.keys().chain(field_order).chain(item.tags.keys()) {
+				limits::check_field(field)?;
+			}
+
+			for derived in item.metadata.values().filter_map(|metadata| metadata.derived.as_ref()) {
+				match derived {
+					DerivedField::Totp { source: text } | DerivedField::Template { value: text } => limits::check_field(text)?,
+				}
+			}
+		}
+
+		Ok(())
+	}
 }
 
 impl<Q: ?Sized> Index<&Q> for Entry
@@ -153,6 +564,12 @@ where
 	}
 }
 
+impl Drop for Entry {
+	fn drop(&mut self) {
+		zeroize_map(&mut self.state);
+	}
+}
+
 impl<'de> serde::Deserialize<'de> for Entry {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
 	where
@@ -163,26 +580,86 @@ impl<'de> serde::Deserialize<'de> for Entry {
 			id: ID,
 			history: Vec<EntryHistory>,
 			time_created: u64,
+			#[serde(default)]
+			uses: BTreeSet<u64>,
 		}
 
 		let entry: PartialDeserialized = serde::Deserialize::deserialize(deserializer)?;
+		let mut result = Entry::inner_new(entry.id, entry.history, entry.time_created).ok_or_else(|| serde::de::Error::custom("Invalid history"))?;
+		result.uses = entry.uses;
 
-		Entry::inner_new(entry.id, entry.history, entry.time_created).ok_or_else(|| serde::de::Error::custom("Invalid history"))
+		Ok(result)
 	}
 }
 
 
+// metadata, field_order, and device are left out of the serialized form when unused, so edits that don't use them serialize
+// exactly as they did before those fields existed.
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug)]
 pub struct EntryHistory {
 	/// Unix timestamp for when this edit occured (nanoseconds)
 	pub time: u64,
 	#[serde(serialize_with = "ordered_map")]
 	pub data: HashMap<String, String>,
+	/// Changes to the metadata of fields
+	#[serde(default, skip_serializing_if = "HashMap::is_empty", serialize_with = "ordered_map")]
+	pub metadata: HashMap<String, FieldMetadata>,
+	/// A new display order for the entry's fields
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub field_order: Option<Vec<String>>,
+	/// Tags added (true) or removed (false)
+	#[serde(default, skip_serializing_if = "HashMap::is_empty", serialize_with = "ordered_map")]
+	pub tags: HashMap<String, bool>,
+	/// The device that made the edit, if known (see the device module).  Not part of the edit's effect, so it's ignored when
+	/// deciding whether edits conflict.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub device: Option<DeviceId>,
 }
 
 impl EntryHistory {
 	pub fn new(data: HashMap<String, String>) -> EntryHistory {
-		EntryHistory { time: unix_timestamp(), data }
+		EntryHistory::new_with(data, &SystemClock)
+	}
+
+	/// Same as `new`, but timestamped with `time_source`'s time (see the time_source module).
+	pub fn new_with(data: HashMap<String, String>, time_source: &dyn TimeSource) -> EntryHistory {
+		EntryHistory {
+			time: time_source.now(),
+			data,
+			metadata: HashMap::new(),
+			field_order: None,
+			tags: HashMap::new(),
+			device: None,
+		}
+	}
+
+	pub fn with_metadata<K: Into<String>>(mut self, key: K, metadata: FieldMetadata) -> EntryHistory {
+		self.metadata.insert(key.into(), metadata);
+		self
+	}
+
+	pub fn with_field_order(mut self, field_order: Vec<String>) -> EntryHistory {
+		self.field_order = Some(field_order);
+		self
+	}
+
+	/// Adds (`present` is true) or removes `tag`.
+	pub fn with_tag<T: Into<String>>(mut self, tag: T, present: bool) -> EntryHistory {
+		self.tags.insert(tag.into(), present);
+		self
+	}
+
+	fn is_empty(&self) -> bool {
+		self.data.is_empty() && self.metadata.is_empty() && self.field_order.is_none() && self.tags.is_empty()
+	}
+
+	/// True if other is at the same time and everything it sets is also set by self.
+	fn contains(&self, other: &EntryHistory) -> bool {
+		self.time == other.time
+			&& other.data.iter().all(|(key, value)| self.data.get(key) == Some(value))
+			&& other.metadata.iter().all(|(key, value)| self.metadata.get(key) == Some(value))
+			&& (other.field_order.is_none() || other.field_order == self.field_order)
+			&& other.tags.iter().all(|(tag, present)| self.tags.get(tag) == Some(present))
 	}
 
 	pub fn get<Q>(&self, key: &Q) -> Option<&String>
@@ -194,6 +671,12 @@ impl EntryHistory {
 	}
 }
 
+impl Drop for EntryHistory {
+	fn drop(&mut self) {
+		zeroize_map(&mut self.data);
+	}
+}
+
 impl<Q: ?Sized> Index<&Q> for EntryHistory
 where
 	Q: Eq + Hash,
@@ -207,6 +690,91 @@ where
 	}
 }
 
+/// Placeholder used in place of a protected field's value when redacting.
+pub const REDACTED_VALUE: &str = "********";
+
+/// Whether fields named `key` are protected by default.  Use `Entry::is_field_protected` to take an entry's metadata into account.
+pub fn is_protected_field(key: &str) -> bool {
+	FieldMetadata::default_for(key).protected
+}
+
+
+/// A single field's change between two points in an Entry's history.
+#[derive(Serialize, Eq, PartialEq, Debug, Clone)]
+pub struct FieldChange {
+	pub key: String,
+	/// None if the field did not exist yet.
+	pub old: Option<String>,
+	/// None if the field does not exist at the later point.
+	pub new: Option<String>,
+}
+
+impl Drop for FieldChange {
+	fn drop(&mut self) {
+		self.old.zeroize();
+		self.new.zeroize();
+	}
+}
+
+
+/// Collapses a group of edits that all have the same timestamp (e.g. the same edit imported on two devices) into a single edit
+/// if they're equivalent: applied on top of `entry` they make the same changes, differing only in things that didn't change
+/// anything.  The combined edit contains everything from the group, so none of the original edits are lost.
+/// Edits that aren't equivalent are returned as-is, which makes them a conflict.
+fn dedup_simultaneous_edits(group: &[EntryHistory], entry: &Entry) -> Vec<EntryHistory> {
+	type Effect<'a> = (
+		BTreeMap<&'a String, &'a String>,
+		BTreeMap<&'a String, &'a FieldMetadata>,
+		Option<&'a Vec<String>>,
+		BTreeMap<&'a String, &'a bool>,
+	);
+
+	fn effect<'a>(item: &'a EntryHistory, entry: &Entry) -> Effect<'a> {
+		(
+			item.data.iter().filter(|(key, value)| entry.state.get(*key) != Some(value)).collect(),
+			item.metadata.iter().filter(|(key, value)| entry.metadata.get(*key) != Some(value)).collect(),
+			item.field_order.as_ref().filter(|field_order| **field_order != entry.field_order),
+			item.tags.iter().filter(|(tag, present)| entry.tags.contains(*tag) != **present).collect(),
+		)
+	}
+
+	let first_effect = effect(&group[0], entry);
+
+	if group[1..].iter().any(|item| effect(item, entry) != first_effect) {
+		return group.to_vec();
+	}
+
+	let mut combined = EntryHistory {
+		time: group[0].time,
+		data: HashMap::new(),
+		metadata: HashMap::new(),
+		field_order: None,
+		tags: HashMap::new(),
+		// The same on every device, whichever order the edits were merged in
+		device: group.iter().filter_map(|item| item.device).min(),
+	};
+
+	for item in group {
+		combined.data.extend(item.data.iter().map(|(key, value)| (key.clone(), value.clone())));
+		combined.metadata.extend(item.metadata.iter().map(|(key, value)| (key.clone(), value.clone())));
+		combined.tags.extend(item.tags.iter().map(|(tag, present)| (tag.clone(), *present)));
+		if combined.field_order.is_none() {
+			combined.field_order = item.field_order.clone();
+		}
+	}
+
+	vec![combined]
+}
+
+
+// Field keys and values may be secrets, so wipe them before the memory is freed.
+fn zeroize_map(map: &mut HashMap<String, String>) {
+	for (mut key, mut value) in map.drain() {
+		key.zeroize();
+		value.zeroize();
+	}
+}
+
 // We have to use this so that the serialization for EntryHistory is deterministic (always the same for the same input).
 // If we didn't, the serialized form would change each time, which would cause problems for synchronization.
 fn ordered_map<S, K, V>(value: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
@@ -224,8 +792,12 @@ where
 mod tests {
 	use std::collections::HashMap;
 
-	use super::{Entry, EntryHistory};
-	use crate::{tests::random_uniform_string, unix_timestamp};
+	use super::{Entry, EntryHistory, FieldChange, REDACTED_VALUE};
+	use crate::{
+		database_object::{DerivedField, Field, FieldKind, FieldMetadata},
+		tests::random_uniform_string,
+		unix_timestamp,
+	};
 	use rand::{rngs::OsRng, thread_rng, Rng};
 	use serde_json;
 
@@ -233,6 +805,10 @@ mod tests {
 		let mut history = EntryHistory {
 			time: time.unwrap_or_else(unix_timestamp),
 			data: HashMap::new(),
+			metadata: HashMap::new(),
+			field_order: None,
+			tags: HashMap::new(),
+			device: None,
 		};
 
 		for _ in 0..thread_rng().gen_range(1..10) {
@@ -259,6 +835,400 @@ mod tests {
 		entry.edit(random_entry_history(Some(0)));
 	}
 
+	#[test]
+	fn out_of_order_edit_fails() {
+		let mut entry = Entry::new();
+		entry.edit(random_entry_history(Some(42)));
+
+		let err = entry.try_edit(random_entry_history(Some(41))).unwrap_err();
+		assert_eq!((err.id, err.time, err.latest), (entry.id, 41, 42));
+		assert_eq!(entry.get_history().len(), 1);
+		assert!(entry.try_edit(random_entry_history(Some(50))).is_ok());
+	}
+
+	#[test]
+	fn same_time_edit_is_bumped() {
+		let mut entry = Entry::new();
+		entry.edit(random_entry_history(Some(42)));
+		entry.edit(random_entry_history(Some(42)));
+		assert_eq!(entry.get_history().iter().map(|item| item.time).collect::<Vec<_>>(), [42, 43]);
+	}
+
+	// Edits merged in from a device whose clock is ahead
+	#[test]
+	fn new_edit_after_future_history() {
+		let mut entry = Entry::new();
+		entry.edit(random_entry_history(Some(u64::MAX - 10)));
+
+		entry.edit(entry.new_edit([("title".to_string(), "Later".to_string())].into()));
+		entry.add_tag("later");
+		assert_eq!(entry["title"], "Later");
+		assert!(entry.has_tag("later"));
+		assert_eq!(entry.get_history().last().unwrap().time, u64::MAX - 8);
+	}
+
+	#[test]
+	fn revert_field() {
+		let mut entry = Entry::new();
+		let mut edit = |data: &[(&str, &str)]| {
+			entry.edit(entry.new_edit(data.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()));
+			entry.get_history().last().unwrap().time
+		};
+
+		let first = edit(&[("title", "Bank"), ("password", "hunter2")]);
+		let second = edit(&[("password", "letmein")]);
+		edit(&[("title", "My Bank")]);
+
+		let revert = entry.revert_field("password", first).unwrap();
+		assert_eq!(revert.data, [("password".to_string(), "hunter2".to_string())].into());
+		entry.edit(revert);
+		assert_eq!(entry["password"], "hunter2");
+		assert_eq!(entry["title"], "My Bank");
+		assert_eq!(entry.get_history().len(), 4);
+
+		// Nothing to revert to, or nothing that would change
+		assert_eq!(entry.revert_field("password", first - 1), None);
+		assert_eq!(entry.revert_field("username", second), None);
+		assert_eq!(entry.revert_field("password", first), None);
+		assert!(entry.revert_field("password", second).is_some());
+	}
+
+	#[test]
+	fn compact_history() {
+		let data = |data: &[(&str, &str)]| data.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
+		let edit = |time, data| {
+			let mut edit = EntryHistory::new(data);
+			edit.time = time;
+			edit
+		};
+
+		let mut entry = Entry::new();
+		entry.edit(edit(10, data(&[("title", "Old"), ("password", "hunter2")])).with_tag("old", true));
+		entry.edit(edit(20, data(&[("title", "Bank"), ("username", "alice"), ("password", "letmein")])).with_tag("finance", true));
+		entry.edit(edit(30, HashMap::new()).with_tag("old", false).with_field_order(vec!["username".to_string()]));
+		entry.edit(edit(40, data(&[("password", "correct horse")])));
+
+		// Nothing to squash
+		assert_eq!(entry.compact_history(5), None);
+		assert_eq!(entry.compact_history(15), None);
+
+		let compacted = entry.compact_history(35).unwrap();
+		assert_eq!(compacted.get_id(), entry.get_id());
+		assert_eq!(compacted.get_time_created(), entry.get_time_created());
+		assert_eq!(compacted.get_history().len(), 2);
+		assert_eq!(compacted.get_history()[0].time, 30);
+		assert_eq!(compacted.get_history()[1], entry.get_history()[3]);
+		assert_eq!(compacted.get_state(), entry.get_state());
+		assert_eq!(compacted.get_tags(), entry.get_tags());
+		assert_eq!(compacted.get_field_order(), entry.get_field_order());
+		assert_eq!(compacted.get_history()[0].get("title"), Some(&"Bank".to_string()));
+		// Old values are gone
+		assert!(!compacted.get_history().iter().any(|item| item.get("password") == Some(&"hunter2".to_string())));
+		assert!(!compacted.get_history().iter().any(|item| item.tags.contains_key("old")));
+
+		// Compacting the same way on two copies gives the same result, so they still merge
+		let mut other = entry.clone();
+		other.edit(edit(50, data(&[("notes", "Later")])));
+		let other_compacted = other.compact_history(35).unwrap();
+		let merged = compacted.merge(&other_compacted).unwrap();
+		assert_eq!(merged.get_history().len(), 3);
+		assert_eq!(merged.get_state(), other.get_state());
+	}
+
+	#[test]
+	fn field_metadata() {
+		let fields = |data: &[(&str, &str)]| data.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
+		let pin = FieldMetadata {
+			kind: FieldKind::Secret,
+			protected: true,
+			derived: None,
+		};
+
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[
+			("title", "Bank"),
+			("password", "hunter2"),
+			("url", "https://bank.example"),
+			("PIN", "1234"),
+		])));
+		let created = entry.get_history()[0].time;
+
+		// Defaults are based on field names
+		assert!(entry.is_field_protected("password"));
+		assert_eq!(entry.get_field_metadata("url").kind, FieldKind::Url);
+		assert_eq!(entry.get_field_metadata("PIN"), FieldMetadata::default());
+		assert_eq!(
+			entry.get_fields().iter().map(|field| field.name).collect::<Vec<_>>(),
+			["PIN", "password", "title", "url"]
+		);
+
+		// Metadata and ordering are versioned alongside the data
+		entry.edit(
+			EntryHistory::new(HashMap::new())
+				.with_metadata("PIN", pin.clone())
+				.with_field_order(vec!["title".to_string(), "url".to_string()]),
+		);
+		assert_eq!(entry.get_history().len(), 2);
+		assert!(entry.is_field_protected("PIN"));
+		assert_eq!(
+			entry.get_fields()[..3],
+			[
+				Field {
+					name: "title",
+					value: "Bank",
+					kind: FieldKind::Text,
+					protected: false,
+				},
+				Field {
+					name: "url",
+					value: "https://bank.example",
+					kind: FieldKind::Url,
+					protected: false,
+				},
+				Field {
+					name: "PIN",
+					value: "1234",
+					kind: FieldKind::Secret,
+					protected: true,
+				},
+			]
+		);
+
+		// Protected fields are redacted in diffs
+		entry.edit(EntryHistory::new(fields(&[("PIN", "4321")])));
+		let change = entry.diff(created, u64::MAX).into_iter().find(|change| change.key == "PIN").unwrap();
+		assert_eq!(change.new.as_deref(), Some(REDACTED_VALUE));
+
+		// Edits that don't change anything are dropped
+		entry.edit(
+			EntryHistory::new(HashMap::new())
+				.with_metadata("PIN", pin.clone())
+				.with_field_order(entry.get_field_order().to_vec()),
+		);
+		assert_eq!(entry.get_history().len(), 3);
+
+		// Round trips, and edits without metadata serialize the same way they did before metadata existed
+		let serialized = serde_json::to_value(&entry).unwrap();
+		assert!(serialized["history"][0].get("metadata").is_none());
+		assert!(serialized["history"][0].get("field_order").is_none());
+		assert_eq!(serialized["history"][1]["metadata"]["PIN"]["kind"], "secret");
+		assert_eq!(serde_json::from_value::<Entry>(serialized).unwrap(), entry);
+
+		// Same-time metadata edits must agree
+		let mut entry1 = entry.clone();
+		let mut entry2 = entry.clone();
+		let edit = EntryHistory::new(HashMap::new()).with_metadata("title", pin.clone());
+		entry1.edit(edit.clone());
+		entry2.edit(edit.clone());
+		assert_eq!(entry1.merge(&entry2).unwrap(), entry1);
+		let mut entry3 = entry.clone();
+		let mut conflicting_edit = EntryHistory::new(HashMap::new()).with_metadata("title", FieldMetadata::default_for("url"));
+		conflicting_edit.time = edit.time;
+		entry3.edit(conflicting_edit);
+		assert!(entry1.merge(&entry3).is_none());
+	}
+
+	#[test]
+	fn resolved_state() {
+		let derived = |derived: DerivedField| FieldMetadata {
+			derived: Some(derived),
+			..FieldMetadata::default()
+		};
+		let mut entry = Entry::new();
+		entry.edit(
+			EntryHistory::new(
+				[
+					("username", "alice"),
+					("otpauth_secret", "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8"),
+					// Stored values of derived fields are ignored
+					("email", "old@example.com"),
+				]
+				.iter()
+				.map(|(k, v)| (k.to_string(), v.to_string()))
+				.collect(),
+			)
+			.with_metadata(
+				"code",
+				derived(DerivedField::Totp {
+					source: "otpauth_secret".to_owned(),
+				}),
+			)
+			.with_metadata(
+				"email",
+				derived(DerivedField::Template {
+					value: "{username}@corp.com".to_owned(),
+				}),
+			)
+			.with_metadata(
+				"broken",
+				derived(DerivedField::Totp {
+					source: "username_typo".to_owned(),
+				}),
+			)
+			// Derived fields only see stored fields
+			.with_metadata("nested", derived(DerivedField::Template { value: "{email}".to_owned() })),
+		);
+
+		let state = entry.resolved_state(59_000_000_000);
+		assert_eq!(state["code"], "94287082");
+		assert_eq!(state["email"], "alice@corp.com");
+		assert_eq!(state["nested"], "{email}");
+		assert!(!state.contains_key("broken"));
+		assert_eq!(state["username"], "alice");
+		assert_eq!(entry.resolved_state(1_111_111_109_000_000_000)["code"], "07081804");
+		assert_eq!(entry["email"], "old@example.com");
+
+		// The derivation round trips
+		let serialized = serde_json::to_value(&entry).unwrap();
+		assert_eq!(
+			serialized["history"][0]["metadata"]["email"]["derived"],
+			serde_json::json!({"kind": "template", "value": "{username}@corp.com"})
+		);
+		assert_eq!(serde_json::from_value::<Entry>(serialized).unwrap(), entry);
+	}
+
+	#[test]
+	fn tags() {
+		let mut entry = Entry::new();
+		entry.add_tag("banking");
+		entry.add_tag("work");
+		entry.remove_tag("work");
+		assert_eq!(entry.get_tags().iter().collect::<Vec<_>>(), ["banking"]);
+		assert!(entry.has_tag("banking"));
+		assert!(!entry.has_tag("work"));
+		assert_eq!(entry.get_history().len(), 3);
+
+		// Tag changes that don't change anything are dropped
+		entry.add_tag("banking");
+		entry.remove_tag("personal");
+		assert_eq!(entry.get_history().len(), 3);
+
+		// Round trips
+		let serialized = serde_json::to_value(&entry).unwrap();
+		assert_eq!(serialized["history"][2]["tags"]["work"], false);
+		assert_eq!(serde_json::from_value::<Entry>(serialized).unwrap(), entry);
+
+		// Tag changes on two devices merge like any other edit
+		let mut entry1 = entry.clone();
+		let mut entry2 = entry.clone();
+		entry1.add_tag("work");
+		entry2.remove_tag("banking");
+		let merged = entry1.merge(&entry2).unwrap();
+		assert_eq!(merged.get_tags().iter().collect::<Vec<_>>(), ["work"]);
+		assert!(entry1.safe_to_replace_with(&merged));
+		assert!(entry2.safe_to_replace_with(&merged));
+
+		// Same-time tag changes must agree
+		let edit = EntryHistory::new(HashMap::new()).with_tag("travel", true);
+		let mut entry3 = entry.clone();
+		entry3.edit(edit.clone());
+		let mut entry4 = entry.clone();
+		entry4.edit(edit.clone().with_tag("banking", true));
+		assert_eq!(entry3.merge(&entry4).unwrap().get_tags(), entry3.get_tags());
+		let mut entry5 = entry.clone();
+		let mut conflicting_edit = EntryHistory::new(HashMap::new()).with_tag("travel", true).with_tag("banking", false);
+		conflicting_edit.time = edit.time;
+		entry5.edit(conflicting_edit);
+		assert!(entry3.merge(&entry5).is_none());
+	}
+
+	#[test]
+	fn uses() {
+		let mut entry = Entry::new();
+		entry.edit(random_entry_history(None));
+		assert_eq!(entry.get_use_count(), 0);
+		assert_eq!(entry.get_last_used(), None);
+
+		// Unused entries serialize without uses
+		let serialized = serde_json::to_value(&entry).unwrap();
+		assert!(serialized.get("uses").is_none());
+
+		entry.record_use();
+		entry.record_use();
+		assert_eq!(entry.get_use_count(), 2);
+		assert!(entry.get_last_used().unwrap() >= entry.get_time_created());
+		assert_eq!(entry.get_history().len(), 1);
+
+		// Round trips
+		let serialized = serde_json::to_string(&entry).unwrap();
+		assert_eq!(serde_json::from_str::<Entry>(&serialized).unwrap(), entry);
+
+		// Uses on two devices are combined
+		let mut entry1 = entry.clone();
+		let mut entry2 = entry.clone();
+		entry1.record_use();
+		entry2.record_use();
+		entry2.edit(entry2.new_edit([("title".to_string(), "Bank".to_string())].into()));
+		let merged = entry1.merge(&entry2).unwrap();
+		assert_eq!(merged.get_use_count(), 4);
+		assert_eq!(
+			merged.get_last_used(),
+			Some(entry1.get_last_used().unwrap().max(entry2.get_last_used().unwrap()))
+		);
+		assert!(entry1.safe_to_replace_with(&merged));
+		assert!(entry2.safe_to_replace_with(&merged));
+		assert!(!entry1.safe_to_replace_with(&entry2));
+		assert_eq!(merged, entry2.merge(&entry1).unwrap());
+	}
+
+	#[test]
+	fn diff() {
+		let history = |time: u64, data: &[(&str, &str)]| EntryHistory {
+			time,
+			data: data.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+			metadata: HashMap::new(),
+			field_order: None,
+			tags: HashMap::new(),
+			device: None,
+		};
+
+		let mut entry = Entry::new();
+		entry.edit(history(10, &[("title", "Title"), ("username", "Foo"), ("password", "hunter2")]));
+		entry.edit(history(20, &[("username", "Bar")]));
+		entry.edit(history(30, &[("password", "hunter3"), ("url", "https://example.com")]));
+
+		let change = |key: &str, old: Option<&str>, new: Option<&str>| FieldChange {
+			key: key.to_string(),
+			old: old.map(str::to_string),
+			new: new.map(str::to_string),
+		};
+
+		// No changes between identical points
+		assert_eq!(entry.diff(20, 20), vec![]);
+		assert_eq!(entry.diff(20, 29), vec![]);
+
+		assert_eq!(entry.diff(10, 20), vec![change("username", Some("Foo"), Some("Bar"))]);
+
+		// Fields are ordered by key and protected fields are redacted
+		assert_eq!(
+			entry.diff(0, 30),
+			vec![
+				change("password", None, Some(REDACTED_VALUE)),
+				change("title", None, Some("Title")),
+				change("url", None, Some("https://example.com")),
+				change("username", None, Some("Bar")),
+			]
+		);
+		assert_eq!(
+			entry.diff(20, 30),
+			vec![
+				change("password", Some(REDACTED_VALUE), Some(REDACTED_VALUE)),
+				change("url", None, Some("https://example.com")),
+			]
+		);
+
+		assert_eq!(
+			entry.diff_unredacted(20, 30),
+			vec![
+				change("password", Some("hunter2"), Some("hunter3")),
+				change("url", None, Some("https://example.com")),
+			]
+		);
+
+		// Diffing backwards reverses old and new
+		assert_eq!(entry.diff(20, 10), vec![change("username", Some("Bar"), Some("Foo"))]);
+	}
+
 	// Tests merge and safe_to_replace_with
 	#[test]
 	fn merge_and_supersets() {
@@ -285,6 +1255,84 @@ mod tests {
 			assert!(!entry1.safe_to_replace_with(&entry2));
 		}
 
+		// Identical edits made at the same time (e.g. imported on two devices) are not a conflict
+		{
+			let mut entry1 = Entry::new();
+			entry1.edit(random_entry_history(Some(1)));
+			let mut entry2 = entry1.clone();
+			let edit = random_entry_history(Some(2));
+			entry1.edit(edit.clone());
+			entry2.edit(EntryHistory {
+				time: edit.time,
+				// Same contents, built in a different order
+				data: edit
+					.data
+					.iter()
+					.collect::<Vec<_>>()
+					.into_iter()
+					.rev()
+					.map(|(key, value)| (key.clone(), value.clone()))
+					.collect(),
+				metadata: HashMap::new(),
+				field_order: None,
+				tags: HashMap::new(),
+				device: None,
+			});
+
+			let merged = entry1.merge(&entry2).unwrap();
+			assert_eq!(merged, entry1);
+			assert_eq!(merged, entry2.merge(&entry1).unwrap());
+			assert!(entry1.safe_to_replace_with(&merged));
+			assert!(entry2.safe_to_replace_with(&merged));
+		}
+
+		// Same-time edits that differ only by fields that didn't change anything are combined
+		{
+			let mut entry1 = Entry::new();
+			entry1.edit(EntryHistory {
+				time: 1,
+				data: [("title".to_string(), "Title".to_string())].into_iter().collect(),
+				metadata: HashMap::new(),
+				field_order: None,
+				tags: HashMap::new(),
+				device: None,
+			});
+			let mut entry2 = entry1.clone();
+			// An import that kept the unchanged title in its edit
+			entry1.history.push(EntryHistory {
+				time: 2,
+				data: [("title".to_string(), "Title".to_string()), ("password".to_string(), "hunter2".to_string())]
+					.into_iter()
+					.collect(),
+				metadata: HashMap::new(),
+				field_order: None,
+				tags: HashMap::new(),
+				device: None,
+			});
+			entry2.edit(EntryHistory {
+				time: 2,
+				data: [("password".to_string(), "hunter2".to_string())].into_iter().collect(),
+				metadata: HashMap::new(),
+				field_order: None,
+				tags: HashMap::new(),
+				device: None,
+			});
+
+			let merged = entry1.merge(&entry2).unwrap();
+			assert_eq!(merged.get_history().len(), 2);
+			assert_eq!(merged["password"], "hunter2");
+			assert_eq!(merged.get_history(), entry2.merge(&entry1).unwrap().get_history());
+			assert!(entry1.safe_to_replace_with(&merged));
+			assert!(entry2.safe_to_replace_with(&merged));
+			assert!(merged.safe_to_replace_with(&merged.merge(&entry1).unwrap()));
+
+			// But a same-time edit with a different effect is still a conflict
+			let mut entry3 = entry2.clone();
+			entry3.history[1].data.insert("password".to_string(), "hunter3".to_string());
+			assert!(entry1.merge(&entry3).is_none());
+			assert!(entry2.merge(&entry3).is_none());
+		}
+
 		// Always safe to replace after merging
 		{
 			let mut entry1 = Entry::new();
@@ -0,0 +1,59 @@
+// Typed metadata for an Entry's fields.
+// Field values are still stored in EntryHistory::data as plain strings; metadata lives alongside them in each EntryHistory and
+// is versioned the same way.  Fields without any metadata (e.g. everything written before metadata existed) get defaults based
+// on their name, so older databases behave exactly as they did before.
+use super::derived::DerivedField;
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Copy, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+	#[default]
+	Text,
+	Url,
+	Secret,
+	/// A TOTP secret or otpauth:// URI
+	Totp,
+}
+
+
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Hash, Debug, Default)]
+pub struct FieldMetadata {
+	pub kind: FieldKind,
+	/// Whether the field's value should be masked when displayed, and left out of redacted diffs and exports.
+	pub protected: bool,
+	/// How the field's value is computed, for derived fields (see the derived module).  Versions from before derived fields
+	/// existed ignore this and show the field's stored value instead.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub derived: Option<DerivedField>,
+}
+
+impl FieldMetadata {
+	/// Metadata used for a field that doesn't have any set.
+	pub fn default_for(name: &str) -> FieldMetadata {
+		match name {
+			"password" => FieldMetadata {
+				kind: FieldKind::Secret,
+				protected: true,
+				derived: None,
+			},
+			"url" => FieldMetadata {
+				kind: FieldKind::Url,
+				protected: false,
+				derived: None,
+			},
+			_ => FieldMetadata::default(),
+		}
+	}
+}
+
+
+/// A field of an Entry along with its metadata, as returned by `Entry::get_fields`.
+#[derive(Serialize, Eq, PartialEq, Debug, Clone)]
+pub struct Field<'a> {
+	pub name: &'a str,
+	pub value: &'a str,
+	pub kind: FieldKind,
+	pub protected: bool,
+}
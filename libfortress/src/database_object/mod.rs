@@ -1,14 +1,22 @@
+mod derived;
 mod directory;
 mod entry;
+mod field;
+mod settings;
+mod tombstone;
 
 use serde::{Deserialize, Serialize};
 
 pub use self::{
-	directory::Directory,
-	entry::{Entry, EntryHistory},
+	derived::DerivedField,
+	directory::{Directory, DirectoryHistoryAction, RENAME_CONFLICT_WINDOW},
+	entry::{is_protected_field, Entry, EntryHistory, FieldChange, REDACTED_VALUE},
+	field::{Field, FieldKind, FieldMetadata},
+	settings::{SettingsObject, SETTINGS_ID},
+	tombstone::Tombstone,
 };
 
-use super::ID;
+use super::{device::DeviceId, limits::SyncLimit, ID};
 
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -16,6 +24,8 @@ use super::ID;
 pub enum DatabaseObject {
 	Entry(Entry),
 	Directory(Directory),
+	Settings(SettingsObject),
+	Tombstone(Tombstone),
 }
 
 impl DatabaseObject {
@@ -23,6 +33,8 @@ impl DatabaseObject {
 		match *self {
 			DatabaseObject::Entry(ref e) => e.get_id(),
 			DatabaseObject::Directory(ref d) => d.get_id(),
+			DatabaseObject::Settings(ref s) => s.get_id(),
+			DatabaseObject::Tombstone(ref t) => t.get_id(),
 		}
 	}
 
@@ -53,4 +65,72 @@ impl DatabaseObject {
 			_ => None,
 		}
 	}
+
+	pub fn as_tombstone(&self) -> Option<&Tombstone> {
+		match self {
+			DatabaseObject::Tombstone(t) => Some(t),
+			_ => None,
+		}
+	}
+
+	/// See `Entry::compact_history` and `Directory::compact_history`.  Settings and tombstones don't keep history, so are never
+	/// compacted.
+	pub(crate) fn compact_history(&self, before: u64) -> Option<DatabaseObject> {
+		match self {
+			DatabaseObject::Entry(e) => e.compact_history(before).map(DatabaseObject::Entry),
+			DatabaseObject::Directory(d) => d.compact_history(before).map(DatabaseObject::Directory),
+			DatabaseObject::Settings(_) | DatabaseObject::Tombstone(_) => None,
+		}
+	}
+
+	/// See `Entry::as_of` and `Directory::as_of`.  Settings don't keep history, so have no past versions, and neither do
+	/// tombstones.
+	pub(crate) fn as_of(&self, time: u64) -> Option<DatabaseObject> {
+		match self {
+			DatabaseObject::Entry(e) => e.as_of(time).map(DatabaseObject::Entry),
+			DatabaseObject::Directory(d) => d.as_of(time).map(DatabaseObject::Directory),
+			DatabaseObject::Settings(_) | DatabaseObject::Tombstone(_) => None,
+		}
+	}
+
+	/// See `Entry::set_editing_device` and `Directory::set_editing_device`.  Settings and tombstones aren't attributed to devices.
+	pub(crate) fn set_editing_device(&mut self, device: Option<DeviceId>) {
+		match self {
+			DatabaseObject::Entry(e) => e.set_editing_device(device),
+			DatabaseObject::Directory(d) => d.set_editing_device(device),
+			DatabaseObject::Settings(_) | DatabaseObject::Tombstone(_) => (),
+		}
+	}
+
+	/// True if this is an entry or directory that was edited after `tombstone` deleted it, so it wins over the tombstone when
+	/// they're merged (see Tombstone).
+	pub(crate) fn revived_after(&self, tombstone: &Tombstone) -> bool {
+		self.last_edit().is_some_and(|last_edit| last_edit > tombstone.get_time())
+	}
+
+	/// When an entry or directory was last edited, or created if it never was.  None for other objects.
+	pub(crate) fn last_edit(&self) -> Option<u64> {
+		match self {
+			DatabaseObject::Entry(e) => Some(e.get_history().last().map_or(e.get_time_created(), |item| item.time)),
+			DatabaseObject::Directory(d) => Some(d.get_history().last().map_or(0, |item| item.time)),
+			DatabaseObject::Settings(_) | DatabaseObject::Tombstone(_) => None,
+		}
+	}
+
+	pub fn as_settings(&self) -> Option<&SettingsObject> {
+		match self {
+			DatabaseObject::Settings(s) => Some(s),
+			_ => None,
+		}
+	}
+
+	/// Checks the object against the limits on objects from the sync server (see the limits module).
+	pub(crate) fn check_limits(&self) -> Result<(), SyncLimit> {
+		match self {
+			DatabaseObject::Entry(e) => e.check_limits(),
+			DatabaseObject::Directory(d) => d.check_limits(),
+			DatabaseObject::Settings(s) => s.check_limits(),
+			DatabaseObject::Tombstone(_) => Ok(()),
+		}
+	}
 }
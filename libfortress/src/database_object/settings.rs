@@ -0,0 +1,210 @@
+use super::super::{
+	limits::{self, SyncLimit},
+	unix_timestamp, HistoryOrderError, ID,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+
+/// Frontend settings (generator presets, display preferences, etc) that are synced along with the rest of the database.
+/// Settings are arbitrary JSON values under string keys.  Unlike Entries and Directories, settings don't keep a history: merging
+/// keeps the most recently set value of each key (last writer wins).
+/// Each database has at most one SettingsObject, which always has the ID SETTINGS_ID so that separately created copies merge.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct SettingsObject {
+	id: ID,
+	// BTreeMap (and serde_json::Value's own maps) keep serialization deterministic
+	values: BTreeMap<String, SettingsValue>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct SettingsValue {
+	/// Unix timestamp for when this value was set (nanoseconds)
+	pub time: u64,
+	/// Null for settings that were removed
+	pub value: serde_json::Value,
+}
+
+pub const SETTINGS_ID: ID = ID([0xff; 32]);
+
+impl SettingsObject {
+	#[allow(clippy::new_without_default)]
+	pub fn new() -> SettingsObject {
+		SettingsObject {
+			id: SETTINGS_ID,
+			values: BTreeMap::new(),
+		}
+	}
+
+	pub fn get_id(&self) -> &ID {
+		&self.id
+	}
+
+	/// Returns None for settings that were never set or were removed.
+	pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+		self.values.get(key).map(|value| &value.value).filter(|value| !value.is_null())
+	}
+
+	/// All current settings, ordered by key.
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &serde_json::Value)> {
+		self.values
+			.iter()
+			.filter(|(_, value)| !value.value.is_null())
+			.map(|(key, value)| (key.as_str(), &value.value))
+	}
+
+	pub fn set<K: Into<String>>(&mut self, key: K, value: serde_json::Value) {
+		let key = key.into();
+		// A value merged in from another device may be timestamped slightly ahead of our clock
+		let time = match self.values.get(&key) {
+			Some(existing) => unix_timestamp().max(existing.time.saturating_add(1)),
+			None => unix_timestamp(),
+		};
+
+		self.insert(key, value, time)
+	}
+
+	/// Sets `key` to `value` as of `time`.  Fails, changing nothing, if the setting was last set after `time`, since a setting's
+	/// value is always its most recent one.  Setting it again at the very same time replaces the value.
+	pub fn set_with_time<K: Into<String>>(&mut self, key: K, value: serde_json::Value, time: u64) -> Result<(), HistoryOrderError> {
+		let key = key.into();
+
+		if let Some(existing) = self.values.get(&key).filter(|existing| time < existing.time) {
+			return Err(HistoryOrderError {
+				id: self.id,
+				time,
+				latest: existing.time,
+			});
+		}
+
+		self.insert(key, value, time);
+		Ok(())
+	}
+
+	// Sets `key` without checking `time` comes after its current value
+	fn insert(&mut self, key: String, value: serde_json::Value, time: u64) {
+		if self.values.get(&key).is_some_and(|existing| existing.value == value) {
+			return;
+		}
+
+		self.values.insert(key, SettingsValue { time, value });
+	}
+
+	/// Removed settings are remembered (as null) so the removal syncs.
+	pub fn remove(&mut self, key: &str) {
+		if self.values.contains_key(key) {
+			self.set(key, serde_json::Value::Null);
+		}
+	}
+
+	/// Merges self and other, keeping the most recent value of each setting.
+	/// Returns None if the IDs don't match or a setting was set to two different values at the same time.
+	pub fn merge(&self, other: &SettingsObject) -> Option<SettingsObject> {
+		if self.id != other.id {
+			return None;
+		}
+
+		let mut merged = self.clone();
+
+		for (key, theirs) in &other.values {
+			match merged.values.get(key) {
+				Some(ours) if ours.time > theirs.time => (),
+				Some(ours) if ours.time == theirs.time && ours.value != theirs.value => return None,
+				_ => {
+					merged.values.insert(key.clone(), theirs.clone());
+				},
+			}
+		}
+
+		Some(merged)
+	}
+
+	/// Returns true only if replacing self with other doesn't lose any of our settings; i.e. other has every setting at least as
+	/// recently as we do.
+	pub fn safe_to_replace_with(&self, other: &SettingsObject) -> bool {
+		if self.id != other.id {
+			return false;
+		}
+
+		self.values.iter().all(|(key, ours)| match other.values.get(key) {
+			Some(theirs) => theirs.time > ours.time || theirs == ours,
+			None => false,
+		})
+	}
+
+	/// Checks the settings against the limits on objects from the sync server (see the limits module).
+	pub(crate) fn check_limits(&self) -> Result<(), SyncLimit> {
+		limits::check_history_length(self.values.len())?;
+
+		for (key, value) in &self.values {
+			limits::check_field(key)?;
+			limits::check_field(value.value.to_string())?;
+		}
+
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{SettingsObject, SETTINGS_ID};
+	use serde_json::json;
+
+	#[test]
+	fn last_writer_wins() {
+		let mut settings = SettingsObject::new();
+		settings.set_with_time("theme", json!("dark"), 10).unwrap();
+		settings.set_with_time("generator", json!({ "length": 20, "symbols": true }), 20).unwrap();
+		assert_eq!(settings.get("theme"), Some(&json!("dark")));
+		assert_eq!(settings.iter().map(|(key, _)| key).collect::<Vec<_>>(), ["generator", "theme"]);
+
+		let mut settings1 = settings.clone();
+		let mut settings2 = settings.clone();
+		settings1.set_with_time("theme", json!("light"), 30).unwrap();
+		settings2.set_with_time("theme", json!("solarized"), 40).unwrap();
+		settings2.set_with_time("generator", serde_json::Value::Null, 50).unwrap();
+		settings1.set_with_time("font_size", json!(14), 60).unwrap();
+
+		let merged = settings1.merge(&settings2).unwrap();
+		assert_eq!(merged, settings2.merge(&settings1).unwrap());
+		assert_eq!(merged.get("theme"), Some(&json!("solarized")));
+		assert_eq!(merged.get("generator"), None);
+		assert_eq!(merged.get("font_size"), Some(&json!(14)));
+		assert!(settings1.safe_to_replace_with(&merged));
+		assert!(settings2.safe_to_replace_with(&merged));
+		assert!(!settings1.safe_to_replace_with(&settings2));
+		assert!(!merged.safe_to_replace_with(&settings));
+
+		// Same time, different values
+		let mut settings3 = settings.clone();
+		settings3.set_with_time("theme", json!("light"), 30).unwrap();
+		assert!(settings1.merge(&settings3).is_some());
+		settings3.set_with_time("font_size", json!(12), 60).unwrap();
+		assert!(settings1.merge(&settings3).is_none());
+
+		// Deterministic serialization
+		let serialized = serde_json::to_string(&merged).unwrap();
+		let deserialized: SettingsObject = serde_json::from_str(&serialized).unwrap();
+		assert_eq!(serde_json::to_string(&deserialized).unwrap(), serialized);
+	}
+
+	#[test]
+	fn must_be_ordered() {
+		let mut settings = SettingsObject::new();
+		settings.set_with_time("theme", json!("dark"), 10).unwrap();
+		let err = settings.set_with_time("theme", json!("light"), 5).unwrap_err();
+		assert_eq!((err.id, err.time, err.latest), (SETTINGS_ID, 5, 10));
+		assert_eq!(settings.get("theme"), Some(&json!("dark")));
+
+		// Other settings have their own order, and a setting set twice at once keeps the second value
+		settings.set_with_time("font_size", json!(14), 5).unwrap();
+		settings.set_with_time("theme", json!("light"), 10).unwrap();
+		assert_eq!(settings.get("theme"), Some(&json!("light")));
+
+		// Even a value from the very end of time can be replaced
+		settings.set_with_time("theme", json!("solarized"), u64::MAX).unwrap();
+		settings.set("theme", json!("dark"));
+		assert_eq!(settings.get("theme"), Some(&json!("dark")));
+	}
+}
@@ -0,0 +1,41 @@
+use super::super::ID;
+use serde::{Deserialize, Serialize};
+
+
+/// What's left of an entry or directory that was deleted for good (see the gc module): its ID, and when it was deleted, so the
+/// deletion syncs like any other change.  Merging a tombstone with the object it replaced keeps whichever is more recent: the
+/// tombstone, unless the object was edited after it was deleted (e.g. on a device that hadn't synced the deletion yet), in which
+/// case the object comes back.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct Tombstone {
+	id: ID,
+	/// Unix timestamp for when the object was deleted (nanoseconds)
+	time: u64,
+}
+
+impl Tombstone {
+	pub(crate) fn new(id: ID, time: u64) -> Tombstone {
+		Tombstone { id, time }
+	}
+
+	pub fn get_id(&self) -> &ID {
+		&self.id
+	}
+
+	pub fn get_time(&self) -> u64 {
+		self.time
+	}
+
+	/// An object deleted on two devices keeps the later deletion.  Returns None if the IDs don't match.
+	pub fn merge(&self, other: &Tombstone) -> Option<Tombstone> {
+		if self.id != other.id {
+			return None;
+		}
+
+		Some(if other.time > self.time { other.clone() } else { self.clone() })
+	}
+
+	pub fn safe_to_replace_with(&self, other: &Tombstone) -> bool {
+		self.id == other.id && other.time >= self.time
+	}
+}
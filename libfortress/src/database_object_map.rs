@@ -1,5 +1,15 @@
-use super::{database_object::DatabaseObject, ID};
-use std::{self, borrow::Borrow, collections::HashMap, hash::Hash};
+use super::{database_object::DatabaseObject, device::DeviceId, id_policy, object_index::ObjectIndex, FortressError, ID};
+use fortresscrypto::Zeroizing;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::{
+	self,
+	borrow::Borrow,
+	collections::{HashMap, HashSet},
+	fmt,
+	hash::Hash,
+	sync::OnceLock,
+};
 
 
 // We wrap HashMap to enforce some invariants.
@@ -17,14 +27,53 @@ use std::{self, borrow::Borrow, collections::HashMap, hash::Hash};
 // serializing a DatabaseObject, modifying the serialized representation, and then Deserializing,
 // but the point is to make it difficult and unnatural to bypass the invariants; it shouldn't
 // happen accidentally.
-#[derive(Eq, PartialEq, Debug, Clone, Default)]
+// Funnelling every modification through here also lets us keep the object index (see the object_index module) up to date, and
+// keep track of which objects changed since the database was last saved to its journal (see the journal module).
+//
+// Entries can also be loaded lazily (see `deserialize_lazily`): they're kept as the JSON they were saved as, and only decoded
+// when they're first used.  Once an entry is handed out mutably or replaced, it's decoded for good.
+#[derive(Debug, Clone, Default)]
 pub struct DatabaseObjectMap {
 	inner: HashMap<ID, DatabaseObject>,
+	/// Lazily loaded entries that haven't been modified since they were loaded
+	encoded: HashMap<ID, EncodedObject>,
+	index: ObjectIndex,
+	/// Edits to objects handed out mutably are attributed to this device (see the device module)
+	device: Option<DeviceId>,
+	/// Objects handed out mutably or replaced since `clear_changed`
+	changed: HashSet<ID>,
+}
+
+
+#[derive(Clone)]
+struct EncodedObject {
+	json: Zeroizing<String>,
+	decoded: OnceLock<DatabaseObject>,
+}
+
+impl EncodedObject {
+	fn get(&self) -> &DatabaseObject {
+		// The JSON came out of an authenticated database file, written by us, so this only fails if there's a bug
+		self.decoded
+			.get_or_init(|| serde_json::from_str(&self.json).expect("Internal error: A lazily loaded object failed to decode."))
+	}
+
+	fn into_decoded(self) -> DatabaseObject {
+		self.get();
+		self.decoded.into_inner().expect("Internal error")
+	}
+}
+
+// Secrets are only shown once decoded, as they would be for any other object
+impl fmt::Debug for EncodedObject {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("EncodedObject").field("decoded", &self.decoded.get()).finish_non_exhaustive()
+	}
 }
 
 impl DatabaseObjectMap {
 	pub fn new() -> DatabaseObjectMap {
-		DatabaseObjectMap { inner: HashMap::new() }
+		DatabaseObjectMap::default()
 	}
 
 	pub fn get<Q>(&self, key: &Q) -> Option<&DatabaseObject>
@@ -32,7 +81,7 @@ impl DatabaseObjectMap {
 		Q: Hash + Eq + ?Sized,
 		ID: Borrow<Q>,
 	{
-		self.inner.get(key)
+		self.inner.get(key).or_else(|| self.encoded.get(key).map(EncodedObject::get))
 	}
 
 	pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut DatabaseObject>
@@ -40,56 +89,218 @@ impl DatabaseObjectMap {
 		Q: Hash + Eq + ?Sized,
 		ID: Borrow<Q>,
 	{
-		self.inner.get_mut(key)
+		self.refresh_index();
+		if let Some(encoded) = self.encoded.remove(key) {
+			let object = encoded.into_decoded();
+			self.inner.insert(*object.get_id(), object);
+		}
+		let object = self.inner.get_mut(key)?;
+		self.index.mark_stale(*object.get_id());
+		self.changed.insert(*object.get_id());
+		object.set_editing_device(self.device);
+		Some(object)
 	}
 
 	pub fn len(&self) -> usize {
-		self.inner.len()
+		self.inner.len() + self.encoded.len()
 	}
 
 	/// Update an object in the map (or insert if it didn't already exist)
 	/// NOTE: Does not allow you to overwrite an existing object if that operation would be destructive (e.g. older version, conflicting history, etc).
 	pub fn update(&mut self, object: DatabaseObject) {
-		match (self.inner.get(object.get_id()), &object) {
-			(Some(DatabaseObject::Entry(existing)), DatabaseObject::Entry(new_object)) => {
-				if !existing.safe_to_replace_with(new_object) {
-					panic!("Attempted to overwrite an existing DatabaseObject with an older version.");
-				}
-			},
-			(Some(DatabaseObject::Directory(existing)), DatabaseObject::Directory(new_object)) => {
-				if !existing.safe_to_replace_with(new_object) {
-					panic!("Attempted to overwrite an existing DatabaseObject with an older version.");
-				}
+		match self.try_update(object) {
+			Ok(()) => (),
+			Err(FortressError::SyncObjectTypeMismatch(_)) => panic!("Attempted to overwrite an existing DatabaseObject with a different type object."),
+			Err(_) => panic!("Attempted to overwrite an existing DatabaseObject with an older version."),
+		}
+	}
+
+	/// Same as `update`, but returns an error instead of panicking, for objects that came from the sync server.
+	/// Fails with FortressError::SyncHistoryLost if the update would be destructive, or FortressError::SyncObjectTypeMismatch if
+	/// the existing object is a different type.  An entry or directory and its tombstone replace each other if they would win
+	/// a merge (see Tombstone).
+	pub fn try_update(&mut self, object: DatabaseObject) -> Result<(), FortressError> {
+		let id = *object.get_id();
+		let safe = match (self.get(&id), &object) {
+			(Some(DatabaseObject::Entry(existing)), DatabaseObject::Entry(new_object)) => existing.safe_to_replace_with(new_object),
+			(Some(DatabaseObject::Directory(existing)), DatabaseObject::Directory(new_object)) => existing.safe_to_replace_with(new_object),
+			(Some(DatabaseObject::Settings(existing)), DatabaseObject::Settings(new_object)) => existing.safe_to_replace_with(new_object),
+			(Some(DatabaseObject::Tombstone(existing)), DatabaseObject::Tombstone(new_object)) => existing.safe_to_replace_with(new_object),
+			(Some(existing @ (DatabaseObject::Entry(_) | DatabaseObject::Directory(_))), DatabaseObject::Tombstone(tombstone)) => {
+				!existing.revived_after(tombstone)
 			},
-			(None, _) => {},
-			_ => {
-				panic!("Attempted to overwrite an existing DatabaseObject with a different type object.");
+			(Some(DatabaseObject::Tombstone(tombstone)), new_object @ (DatabaseObject::Entry(_) | DatabaseObject::Directory(_))) => {
+				new_object.revived_after(tombstone)
 			},
+			(None, _) => true,
+			_ => return Err(FortressError::SyncObjectTypeMismatch(id)),
+		};
+
+		if !safe {
+			return Err(FortressError::SyncHistoryLost(id));
 		}
 
+		self.refresh_index();
+		self.index.insert(&object);
+		self.encoded.remove(&id);
+		self.changed.insert(id);
+		self.inner.insert(id, object);
+		Ok(())
+	}
+
+	/// Replace an existing object even if doing so loses data.  Only for explicitly destructive operations like history compaction.
+	pub fn replace_destructively(&mut self, object: DatabaseObject) {
+		match (self.get(object.get_id()), &object) {
+			(Some(DatabaseObject::Entry(_)), DatabaseObject::Entry(_)) => (),
+			(Some(DatabaseObject::Directory(_)), DatabaseObject::Directory(_)) => (),
+			(Some(DatabaseObject::Settings(_)), DatabaseObject::Settings(_)) => (),
+			(Some(DatabaseObject::Entry(_) | DatabaseObject::Directory(_)), DatabaseObject::Tombstone(_)) => (),
+			_ => panic!("Attempted to destructively replace a DatabaseObject that doesn't exist or with a different type object."),
+		}
+
+		self.refresh_index();
+		self.index.insert(&object);
+		self.encoded.remove(object.get_id());
+		self.changed.insert(*object.get_id());
 		self.inner.insert(*object.get_id(), object);
 	}
 
+	/// Takes tombstone `id` out of the map for good (see the gc module).  Returns false, changing nothing, if `id` isn't a
+	/// tombstone.
+	pub(crate) fn remove_tombstone(&mut self, id: &ID) -> bool {
+		if self.inner.get(id).and_then(DatabaseObject::as_tombstone).is_none() {
+			return false;
+		}
+
+		self.refresh_index();
+		self.index.forget(id);
+		self.changed.remove(id);
+		self.inner.remove(id);
+		true
+	}
+
 	pub fn values(&self) -> impl Iterator<Item = &DatabaseObject> {
-		self.inner.values()
+		self.inner.values().chain(self.encoded.values().map(EncodedObject::get))
 	}
 
 	pub fn values_mut(&mut self) -> impl Iterator<Item = &mut DatabaseObject> {
+		for (id, encoded) in self.encoded.drain() {
+			self.inner.insert(id, encoded.into_decoded());
+		}
+		self.refresh_index();
+		for (id, object) in self.inner.iter_mut() {
+			self.index.mark_stale(*id);
+			self.changed.insert(*id);
+			object.set_editing_device(self.device);
+		}
 		self.inner.values_mut()
 	}
+
+	pub(crate) fn set_device(&mut self, device: Option<DeviceId>) {
+		self.device = device;
+	}
+
+	/// Objects that were handed out mutably or replaced since the last `clear_changed` (or since the map was deserialized).
+	pub(crate) fn changed(&self) -> impl Iterator<Item = &DatabaseObject> {
+		self.changed.iter().filter_map(|id| self.get(id))
+	}
+
+	pub(crate) fn clear_changed(&mut self) {
+		self.changed.clear();
+	}
+
+	pub(crate) fn get_index(&self) -> &ObjectIndex {
+		&self.index
+	}
+
+	// Indexes objects that were handed out mutably again.  Any method taking &mut self can call this, since the references it
+	// handed out must be gone by then.
+	fn refresh_index(&mut self) {
+		for id in self.index.take_stale() {
+			if let Some(object) = self.inner.get(&id) {
+				self.index.insert(object);
+			}
+		}
+	}
+
+	/// Deserializes a map saved by `serialize`, leaving entries encoded until they're used.  Directories and settings are
+	/// decoded right away, since finding anything needs them.
+	pub(crate) fn deserialize_lazily<'de, D>(deserializer: D) -> Result<DatabaseObjectMap, D::Error>
+	where
+		D: serde::Deserializer<'de>,
+	{
+		#[derive(Deserialize)]
+		struct ObjectHeader {
+			#[serde(rename = "type")]
+			kind: String,
+			id: ID,
+		}
+
+		let mut map = DatabaseObjectMap::new();
+
+		for json in Vec::<Box<RawValue>>::deserialize(deserializer)? {
+			let header: ObjectHeader = serde_json::from_str(json.get()).map_err(serde::de::Error::custom)?;
+
+			if header.kind == "Entry" {
+				id_policy::check_entry_id(&header.id).map_err(serde::de::Error::custom)?;
+				let json = Zeroizing::new(String::from(Box::<str>::from(json)));
+				map.index.mark_unindexed_entry(header.id);
+				map.encoded.insert(
+					header.id,
+					EncodedObject {
+						json,
+						decoded: OnceLock::new(),
+					},
+				);
+			} else {
+				let object: DatabaseObject = serde_json::from_str(json.get()).map_err(serde::de::Error::custom)?;
+				id_policy::check_object_id(&object).map_err(serde::de::Error::custom)?;
+				map.index.insert(&object);
+				map.inner.insert(*object.get_id(), object);
+			}
+		}
+
+		Ok(map)
+	}
 }
 
+// The index is derived from the objects, and lazily loaded objects are equal to their decoded selves
+impl PartialEq for DatabaseObjectMap {
+	fn eq(&self, other: &DatabaseObjectMap) -> bool {
+		self.len() == other.len() && self.into_iter().all(|(id, object)| other.get(id) == Some(object))
+	}
+}
+
+impl Eq for DatabaseObjectMap {}
+
 impl serde::Serialize for DatabaseObjectMap {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
 	where
 		S: serde::Serializer,
 	{
+		#[derive(serde::Serialize)]
+		#[serde(untagged)]
+		enum Serialized<'a> {
+			Decoded(&'a DatabaseObject),
+			// Lazily loaded entries are saved as they were loaded, without decoding them
+			Encoded(&'a RawValue),
+		}
+
 		// Deterministic serialization of the hashmap by ordering keys
 		// Also, we serialize to a Vec since we already have the IDs in the objects themselves
-		let mut ordered = self.inner.values().collect::<Vec<_>>();
-		ordered.sort_unstable_by(|a, b| a.get_id().cmp(b.get_id()));
+		let encoded = self.encoded.iter().map(|(id, encoded)| {
+			let raw = serde_json::from_str(&encoded.json).map_err(serde::ser::Error::custom)?;
+			Ok((id, Serialized::Encoded(raw)))
+		});
+		let mut ordered = self
+			.inner
+			.iter()
+			.map(|(id, object)| Ok((id, Serialized::Decoded(object))))
+			.chain(encoded)
+			.collect::<Result<Vec<_>, S::Error>>()?;
+		ordered.sort_unstable_by(|a, b| a.0.cmp(b.0));
 
-		ordered.serialize(serializer)
+		ordered.into_iter().map(|(_, object)| object).collect::<Vec<_>>().serialize(serializer)
 	}
 }
 
@@ -98,30 +309,38 @@ impl<'de> serde::Deserialize<'de> for DatabaseObjectMap {
 	where
 		D: serde::Deserializer<'de>,
 	{
-		Ok(DatabaseObjectMap {
-			inner: Vec::deserialize(deserializer)?
-				.into_iter()
-				.map(|object: DatabaseObject| (*object.get_id(), object))
-				.collect(),
-		})
+		let mut map = DatabaseObjectMap::new();
+
+		for object in Vec::<DatabaseObject>::deserialize(deserializer)? {
+			id_policy::check_object_id(&object).map_err(serde::de::Error::custom)?;
+			map.index.insert(&object);
+			map.inner.insert(*object.get_id(), object);
+		}
+
+		Ok(map)
 	}
 }
 
 impl<'a> IntoIterator for &'a DatabaseObjectMap {
 	type Item = (&'a ID, &'a DatabaseObject);
-	type IntoIter = std::collections::hash_map::Iter<'a, ID, DatabaseObject>;
+	type IntoIter = Box<dyn Iterator<Item = (&'a ID, &'a DatabaseObject)> + 'a>;
 
-	fn into_iter(self) -> std::collections::hash_map::Iter<'a, ID, DatabaseObject> {
-		self.inner.iter()
+	fn into_iter(self) -> Self::IntoIter {
+		Box::new(self.inner.iter().chain(self.encoded.iter().map(|(id, encoded)| (id, encoded.get()))))
 	}
 }
 
 
 #[cfg(test)]
 mod tests {
-	use rand::{rngs::OsRng, Rng};
+	use rand::{
+		rngs::{OsRng, StdRng},
+		seq::SliceRandom,
+		Rng, SeedableRng,
+	};
+	use std::collections::{HashMap, HashSet};
 
-	use crate::Directory;
+	use crate::{database_object::SettingsObject, tests::for_each_seed, unix_timestamp, Directory, FortressError, ID, ROOT_DIRECTORY_ID};
 
 	use super::{
 		super::{DatabaseObject, Entry, EntryHistory},
@@ -160,10 +379,335 @@ mod tests {
 		object_map.update(DatabaseObject::Directory(old_directory));
 	}
 
-	// TODO
-	/*#[test]
-	#[should_panic]
-	fn cannot_overwrite_with_different_type() {
+	// Objects from the sync server go through try_update, which reports these cases instead of panicking
+	#[test]
+	fn try_update_errors() {
 		let mut object_map = DatabaseObjectMap::new();
-	}*/
+
+		let mut directory = Directory::new();
+		let old_directory = directory.clone();
+		directory.add(OsRng.gen());
+		let id = *directory.get_id();
+		object_map.update(DatabaseObject::Directory(directory.clone()));
+
+		assert!(matches!(
+			object_map.try_update(DatabaseObject::Directory(old_directory)),
+			Err(FortressError::SyncHistoryLost(error_id)) if error_id == id
+		));
+
+		// An entry with the directory's ID
+		let entry: Entry = serde_json::from_value(serde_json::json!({ "id": id, "history": [], "time_created": 0 })).unwrap();
+		assert!(matches!(
+			object_map.try_update(DatabaseObject::Entry(entry)),
+			Err(FortressError::SyncObjectTypeMismatch(error_id)) if error_id == id
+		));
+
+		assert_eq!(object_map.get(&id), Some(&DatabaseObject::Directory(directory)));
+	}
+
+	// Generative tests: devices make random changes to their own copies of a database and sync with each other in random
+	// pairs, checking after every full sync that nothing was lost, nothing was orphaned, and every device ended up the same.
+	// Each case is generated from a seed (see `for_each_seed`).
+	//
+	// Concurrent moves of the same object don't merge (see concurrent_moves_conflict), so a device only moves an entry that no
+	// other device has moved since the last full sync.  Directories are never moved, since concurrently moving two directories
+	// into each other would leave both unreachable.
+	const DEVICES: usize = 4;
+	const CASES: u64 = 32;
+	const STEPS: usize = 200;
+	const FIELDS: &[&str] = &["title", "username", "password", "notes"];
+
+	#[test]
+	fn random_device_histories() {
+		for_each_seed("random_device_histories", CASES, run_devices);
+	}
+
+	fn run_devices(seed: u64) {
+		let mut rng = StdRng::seed_from_u64(seed);
+		let mut root = DatabaseObjectMap::new();
+		root.update(DatabaseObject::Directory(Directory::new_root()));
+		let mut devices = vec![root; DEVICES];
+		// Objects in the order they were created, so choices don't depend on their (random) IDs
+		let mut entries = Vec::new();
+		let mut directories = vec![ROOT_DIRECTORY_ID];
+		let mut moved_by = HashMap::new();
+		let mut time = unix_timestamp();
+		let mut synced = devices[0].clone();
+
+		for _ in 0..STEPS {
+			time += rng.gen_range(1..1_000_000);
+			let device_index = rng.gen_range(0..DEVICES);
+			let device = &mut devices[device_index];
+			let existing = |ids: &[ID], device: &DatabaseObjectMap| ids.iter().copied().filter(|id| device.get(id).is_some()).collect::<Vec<_>>();
+			let device_entries = existing(&entries, device);
+			let device_directories = existing(&directories, device);
+
+			match rng.gen_range(0..8) {
+				0 => {
+					let entry = Entry::new();
+					let parent = *device_directories.choose(&mut rng).unwrap();
+					directory_mut(device, &parent).add_with_time(*entry.get_id(), time);
+					entries.push(*entry.get_id());
+					device.update(DatabaseObject::Entry(entry));
+				},
+				1 => {
+					let directory = Directory::new();
+					let parent = *device_directories.choose(&mut rng).unwrap();
+					directory_mut(device, &parent).add_with_time(*directory.get_id(), time);
+					directories.push(*directory.get_id());
+					device.update(DatabaseObject::Directory(directory));
+				},
+				2 | 3 => {
+					if let Some(id) = device_entries.choose(&mut rng) {
+						let mut history = EntryHistory::new(
+							[(FIELDS.choose(&mut rng).unwrap().to_string(), rng.gen_range(0..4).to_string())]
+								.into_iter()
+								.collect(),
+						);
+						history.time = time;
+						device.get_mut(id).and_then(DatabaseObject::as_entry_mut).unwrap().edit(history);
+					}
+				},
+				4 => {
+					let id = *device_directories.choose(&mut rng).unwrap();
+					directory_mut(device, &id).rename_with_time(format!("Folder {}", rng.gen_range(0..4)), time);
+				},
+				5 => {
+					let movable = device_entries
+						.iter()
+						.filter(|id| moved_by.get(*id).is_none_or(|mover| *mover == device_index))
+						.collect::<Vec<_>>();
+
+					if let Some(&&id) = movable.choose(&mut rng) {
+						let old_parent = parent_of(device, &id);
+						let new_parent = *device_directories.choose(&mut rng).unwrap();
+
+						if old_parent != new_parent {
+							directory_mut(device, &new_parent).add_with_time(id, time);
+							directory_mut(device, &old_parent).remove_with_time(id, time + 1);
+							time += 1;
+							moved_by.insert(id, device_index);
+						}
+					}
+				},
+				6 => {
+					let mut settings = device
+						.get(&crate::database_object::SETTINGS_ID)
+						.and_then(DatabaseObject::as_settings)
+						.cloned()
+						.unwrap_or_else(SettingsObject::new);
+					settings
+						.set_with_time(format!("setting {}", rng.gen_range(0..3)), rng.gen_range(0..4).into(), time)
+						.unwrap();
+					device.update(DatabaseObject::Settings(settings));
+				},
+				_ => {
+					let (a, b) = two_devices(&mut devices, &mut rng);
+					sync(a, b).expect("merge failed");
+				},
+			}
+
+			if rng.gen_ratio(1, 25) {
+				synced = full_sync(&mut devices, &synced);
+				moved_by.clear();
+			}
+		}
+
+		full_sync(&mut devices, &synced);
+	}
+
+	// Syncs every device until they're all the same, checking the invariants, and returns the result
+	fn full_sync(devices: &mut [DatabaseObjectMap], previous: &DatabaseObjectMap) -> DatabaseObjectMap {
+		let before = devices.to_vec();
+
+		// A chain of pairwise syncs there and back reaches everyone
+		for i in (1..devices.len()).chain((1..devices.len()).rev()) {
+			let (left, right) = devices.split_at_mut(i);
+			sync(&mut left[i - 1], &mut right[0]).expect("merge failed");
+		}
+
+		let result = devices[0].clone();
+		for device in devices.iter() {
+			assert_eq!(*device, result, "devices didn't converge");
+		}
+
+		// Nothing any device had is lost, including what was there at the last full sync
+		for old in before.iter().chain([previous]) {
+			for (id, object) in old {
+				assert!(safe_to_replace(object, result.get(id).expect("object lost")), "history lost");
+			}
+		}
+
+		// Every object but the root is in exactly one directory, and reachable from the root
+		let mut parents = HashMap::<ID, usize>::new();
+		for directory in result.values().filter_map(DatabaseObject::as_directory) {
+			for child in &directory.entries {
+				*parents.entry(*child).or_default() += 1;
+			}
+		}
+		let mut reachable = HashSet::from([ROOT_DIRECTORY_ID]);
+		let mut pending = vec![ROOT_DIRECTORY_ID];
+		while let Some(id) = pending.pop() {
+			for child in &result.get(&id).and_then(DatabaseObject::as_directory).unwrap().entries {
+				if reachable.insert(*child) && matches!(result.get(child), Some(DatabaseObject::Directory(_))) {
+					pending.push(*child);
+				}
+			}
+		}
+		for object in result.values().filter(|object| !matches!(object, DatabaseObject::Settings(_))) {
+			let id = object.get_id();
+			if *id != ROOT_DIRECTORY_ID {
+				assert_eq!(parents.get(id), Some(&1), "object should have exactly one parent");
+				assert!(reachable.contains(id), "object orphaned");
+			}
+		}
+
+		result
+	}
+
+	// Merges two devices' objects, leaving both with the result, like syncing them through a server.  Fails with the ID of an
+	// object that doesn't merge, leaving both devices unchanged.
+	fn sync(a: &mut DatabaseObjectMap, b: &mut DatabaseObjectMap) -> Result<(), ID> {
+		let mut merged = Vec::new();
+
+		for (id, theirs) in &*b {
+			let object = match a.get(id) {
+				Some(ours) => {
+					let object = merge(ours, theirs).ok_or(*id)?;
+					assert_eq!(merge(theirs, ours).as_ref(), Some(&object), "merge isn't commutative");
+					object
+				},
+				None => theirs.clone(),
+			};
+			merged.push(object);
+		}
+
+		for object in merged {
+			a.update(object);
+		}
+
+		for (_, object) in &*a {
+			b.update(object.clone());
+		}
+
+		Ok(())
+	}
+
+	fn merge(a: &DatabaseObject, b: &DatabaseObject) -> Option<DatabaseObject> {
+		match (a, b) {
+			(DatabaseObject::Entry(a), DatabaseObject::Entry(b)) => a.merge(b).map(DatabaseObject::Entry),
+			(DatabaseObject::Directory(a), DatabaseObject::Directory(b)) => a.merge(b).map(DatabaseObject::Directory),
+			(DatabaseObject::Settings(a), DatabaseObject::Settings(b)) => a.merge(b).map(DatabaseObject::Settings),
+			_ => None,
+		}
+	}
+
+	fn safe_to_replace(old: &DatabaseObject, new: &DatabaseObject) -> bool {
+		match (old, new) {
+			(DatabaseObject::Entry(old), DatabaseObject::Entry(new)) => old.safe_to_replace_with(new),
+			(DatabaseObject::Directory(old), DatabaseObject::Directory(new)) => old.safe_to_replace_with(new),
+			(DatabaseObject::Settings(old), DatabaseObject::Settings(new)) => old.safe_to_replace_with(new),
+			_ => false,
+		}
+	}
+
+	fn directory_mut<'a>(device: &'a mut DatabaseObjectMap, id: &ID) -> &'a mut Directory {
+		device.get_mut(id).and_then(DatabaseObject::as_directory_mut).unwrap()
+	}
+
+	fn parent_of(device: &DatabaseObjectMap, id: &ID) -> ID {
+		*device
+			.values()
+			.filter_map(DatabaseObject::as_directory)
+			.find(|directory| directory.contains(id))
+			.unwrap()
+			.get_id()
+	}
+
+	fn two_devices<'a>(devices: &'a mut [DatabaseObjectMap], rng: &mut StdRng) -> (&'a mut DatabaseObjectMap, &'a mut DatabaseObjectMap) {
+		let mut indexes = rand::seq::index::sample(rng, devices.len(), 2).into_vec();
+		indexes.sort_unstable();
+		let (left, right) = devices.split_at_mut(indexes[1]);
+		(&mut left[indexes[0]], &mut right[0])
+	}
+
+	// Lazily loaded entries are only decoded when used, and are saved without being decoded
+	#[test]
+	fn lazy_loading() {
+		let tmp_dir = tempfile::tempdir().unwrap();
+		let (path, resaved_path) = (tmp_dir.path().join("database.fortress"), tmp_dir.path().join("resaved.fortress"));
+		let mut db = crate::Database::new_with_password("username", "password");
+		let (mut used, mut unused) = (Entry::new(), Entry::new());
+		used.edit(EntryHistory::new(HashMap::from([("title".to_owned(), "Used".to_owned())])));
+		unused.edit(EntryHistory::new(HashMap::from([("title".to_owned(), "Unused".to_owned())])));
+		let (used_id, unused_id) = (*used.get_id(), *unused.get_id());
+		let directory = Directory::new();
+		let directory_id = *directory.get_id();
+		db.add_entry(used);
+		db.add_entry(unused);
+		db.add_directory(directory);
+		db.move_object(&unused_id, &directory_id);
+		db.save_to_path(&path).unwrap();
+
+		let mut lazy = crate::Database::load_from_path_lazily(&path, "password").unwrap();
+		let decoded = |lazy: &crate::Database, id: &ID| lazy.objects.encoded.get(id).map(|encoded| encoded.decoded.get().is_some());
+		assert_eq!(decoded(&lazy, &used_id), Some(false));
+		assert_eq!(decoded(&lazy, &unused_id), Some(false));
+
+		assert_eq!(lazy.get_entry_by_id(&used_id).unwrap().get("title").unwrap(), "Used");
+		assert_eq!(lazy.get_parent_directory(&unused_id).unwrap().get_id(), &directory_id);
+		assert_eq!(decoded(&lazy, &used_id), Some(true));
+		assert_eq!(decoded(&lazy, &unused_id), Some(false));
+
+		lazy.save_to_path(&resaved_path).unwrap();
+		assert_eq!(decoded(&lazy, &unused_id), Some(false));
+		assert_eq!(crate::Database::load_from_path(&resaved_path, "password").unwrap(), db);
+
+		// Editing an entry decodes it for good, and it's indexed like any other
+		let entry = lazy.get_entry_by_id_mut(&unused_id).unwrap();
+		entry.edit(EntryHistory::new(HashMap::from([("title".to_owned(), "Renamed".to_owned())])));
+		assert_eq!(decoded(&lazy, &unused_id), None);
+		lazy.move_object(&used_id, &directory_id);
+		assert_eq!(
+			lazy.find_entries("renamed").iter().map(|entry| *entry.get_id()).collect::<Vec<_>>(),
+			[unused_id]
+		);
+		assert_eq!(lazy.objects.len(), db.objects.len());
+	}
+
+	// Two devices moving the same entry at about the same time don't merge: both remove it from the same directory.  Syncing fails
+	// (as FortressError::SyncConflict) without losing either device's changes.
+	#[test]
+	fn concurrent_moves_conflict() {
+		let entry = Entry::new();
+		let id = *entry.get_id();
+		let (first, second) = (Directory::new(), Directory::new());
+		let (first_id, second_id) = (*first.get_id(), *second.get_id());
+		let time = unix_timestamp();
+
+		let mut root = Directory::new_root();
+		root.add_with_time(id, time);
+		root.add_with_time(first_id, time + 1);
+		root.add_with_time(second_id, time + 2);
+		let mut a = DatabaseObjectMap::new();
+		for object in [
+			DatabaseObject::Directory(root),
+			DatabaseObject::Directory(first),
+			DatabaseObject::Directory(second),
+			DatabaseObject::Entry(entry),
+		] {
+			a.update(object);
+		}
+		let mut b = a.clone();
+
+		for (device, parent, time) in [(&mut a, first_id, time + 10), (&mut b, second_id, time + 20)] {
+			directory_mut(device, &parent).add_with_time(id, time);
+			directory_mut(device, &ROOT_DIRECTORY_ID).remove_with_time(id, time + 1);
+		}
+		let (original_a, original_b) = (a.clone(), b.clone());
+
+		assert_eq!(sync(&mut a, &mut b), Err(ROOT_DIRECTORY_ID));
+		assert_eq!(a, original_a);
+		assert_eq!(b, original_b);
+	}
 }
@@ -0,0 +1,162 @@
+// Remembers the network keys derived for the current username and password and the ones they replaced, so undoing a password
+// change is instant instead of re-running the very slow network key derivation.  Older keys are forgotten as soon as the
+// password changes again.  Entries are looked up by `NetworkKeySuite::derivation_id`, a keyed hash of the username and
+// password, so passwords are never stored.
+//
+// The cache only lives in memory.  A derivation ID is quick to compute, so a saved cache would let anyone who can open the
+// database file (e.g. another user with a keyslot) test guesses at the passwords in it without paying for the derivation.
+// Only whether it's turned on is saved.
+//
+// The cache is on by default.  Turning it off (`Database::set_derivation_cache_enabled`) also clears it.
+use fortresscrypto::{DerivationId, Key, NetworkKeySuite};
+use rand::{rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+
+
+/// Number of derivations remembered, the current one and the one it replaced; the least recently used is forgotten first.
+pub const DERIVATION_CACHE_LIMIT: usize = 2;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct DerivationCache {
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	disabled: bool,
+	#[serde(skip, default = "random_salt")]
+	salt: Key,
+	/// Most recently used first
+	#[serde(skip)]
+	entries: Vec<CachedDerivation>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedDerivation {
+	id: DerivationId,
+	network_key_suite: NetworkKeySuite,
+}
+
+impl Default for DerivationCache {
+	fn default() -> DerivationCache {
+		DerivationCache {
+			disabled: false,
+			salt: random_salt(),
+			entries: Vec::new(),
+		}
+	}
+}
+
+// Only whether the cache is on is part of the database's value, since that's all that's saved
+impl PartialEq for DerivationCache {
+	fn eq(&self, other: &DerivationCache) -> bool {
+		self.disabled == other.disabled
+	}
+}
+
+impl Eq for DerivationCache {}
+
+fn random_salt() -> Key {
+	OsRng.gen()
+}
+
+impl DerivationCache {
+	/// The keys previously derived for `username` and `password`, if they're in the cache.
+	pub(crate) fn get(&mut self, username: &str, password: &str) -> Option<NetworkKeySuite> {
+		let id = NetworkKeySuite::derivation_id(&self.salt, username.as_bytes(), password.as_bytes());
+		let index = self.entries.iter().position(|entry| entry.id == id)?;
+		let entry = self.entries.remove(index);
+		let network_key_suite = entry.network_key_suite.clone();
+		self.entries.insert(0, entry);
+
+		Some(network_key_suite)
+	}
+
+	/// Remembers the keys derived for `username` and `password`.  Does nothing if the cache is turned off.
+	pub(crate) fn insert(&mut self, username: &str, password: &str, network_key_suite: &NetworkKeySuite) {
+		if self.disabled {
+			return;
+		}
+
+		let id = NetworkKeySuite::derivation_id(&self.salt, username.as_bytes(), password.as_bytes());
+		self.entries.retain(|entry| entry.id != id);
+		self.entries.insert(
+			0,
+			CachedDerivation {
+				id,
+				network_key_suite: network_key_suite.clone(),
+			},
+		);
+		self.entries.truncate(DERIVATION_CACHE_LIMIT);
+	}
+
+	pub(crate) fn clear(&mut self) {
+		self.entries.clear();
+		// Forget which IDs were in use too
+		self.salt = random_salt();
+	}
+
+	pub(crate) fn is_enabled(&self) -> bool {
+		!self.disabled
+	}
+
+	pub(crate) fn set_enabled(&mut self, enabled: bool) {
+		self.disabled = !enabled;
+
+		if !enabled {
+			self.clear();
+		}
+	}
+
+	/// True if there's nothing worth saving: the cache is on.
+	pub(crate) fn is_default(&self) -> bool {
+		!self.disabled
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{DerivationCache, DERIVATION_CACHE_LIMIT};
+	use fortresscrypto::NetworkKeySuite;
+
+	#[test]
+	fn derivation_cache() {
+		let keys = NetworkKeySuite::derive(b"username", b"password");
+		let mut cache = DerivationCache::default();
+		assert_eq!(cache.get("username", "password"), None);
+
+		cache.insert("username", "password", &keys);
+		cache.insert("username", "password", &keys);
+		assert_eq!(cache.entries.len(), 1);
+		assert_eq!(cache.get("username", "password"), Some(keys.clone()));
+		assert_eq!(cache.get("username", "password2"), None);
+		assert_eq!(cache.get("username2", "password"), None);
+
+		// Only kept in memory
+		assert_eq!(serde_json::to_string(&cache).unwrap(), "{}");
+		let mut saved: DerivationCache = serde_json::from_str(r#"{"disabled": true}"#).unwrap();
+		assert!(!saved.is_enabled());
+		assert_eq!(saved.get("username", "password"), None);
+
+		// Least recently used goes first
+		for i in 1..DERIVATION_CACHE_LIMIT {
+			cache.insert("username", &format!("password {i}"), &keys);
+		}
+		assert!(cache.get("username", "password").is_some());
+		cache.insert("username", "one too many", &keys);
+		assert_eq!(cache.entries.len(), DERIVATION_CACHE_LIMIT);
+		assert!(cache.get("username", "password").is_some());
+		assert!(cache.get("username", "password 1").is_none());
+
+		cache.clear();
+		assert_eq!(cache.entries.len(), 0);
+		assert!(cache.is_default());
+
+		cache.set_enabled(false);
+		cache.insert("username", "password", &keys);
+		assert_eq!(cache.get("username", "password"), None);
+		assert!(!cache.is_default());
+		assert_eq!(serde_json::to_string(&cache).unwrap(), r#"{"disabled":true}"#);
+		cache.set_enabled(true);
+		cache.insert("username", "password", &keys);
+		assert_eq!(cache.get("username", "password"), Some(keys));
+	}
+}
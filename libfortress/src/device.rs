@@ -0,0 +1,195 @@
+// Attributing edits to the devices that made them.
+// Naming a database's device with `Database::set_device_name` gives it a random DeviceId, which is kept in the local database
+// file; it isn't synced and has nothing to do with the account's keys.  From then on, every entry and directory edit made
+// through the database records the device's ID, so once histories are synced every device can tell where each change came from
+// (see `EntryHistory::device`, `DirectoryHistory::device`, and the change_log module).  Device names are shared through the
+// synced settings, under DEVICE_NAME_SETTING_PREFIX followed by the device ID in hex, so frontends can show them instead of IDs.
+//
+// The device ID is opaque metadata: it's never used to decide whether edits conflict or are the same edit, and identical
+// edits made at the same time on two devices merge into one attributed to the lower of the device IDs, so every device ends up
+// with the same history.  Edits made before a device was named, on devices that were never named, or by older versions have no
+// device.  A database file copied to another device keeps the original's device ID until `reset_device_id` is called.
+use crate::Database;
+use rand::{rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+
+new_type! {
+	public DeviceId(16);
+}
+
+
+/// Device names are synced as settings with this prefix followed by the device ID in hex.  Each device only ever sets its own
+/// name, so renaming two devices at once can't lose either name.
+pub const DEVICE_NAME_SETTING_PREFIX: &str = "device_name.";
+
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub(crate) struct DeviceIdentity {
+	id: DeviceId,
+	name: String,
+}
+
+
+// The device that edits made to an object through a database are attributed to, set by DatabaseObjectMap whenever it hands out a
+// mutable object.  It's not part of the object's value: it's never serialized, and objects compare equal whatever it is.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct EditingDevice(pub(crate) Option<DeviceId>);
+
+impl PartialEq for EditingDevice {
+	fn eq(&self, _other: &EditingDevice) -> bool {
+		true
+	}
+}
+
+impl Eq for EditingDevice {}
+
+
+impl Database {
+	/// Names this device, giving it a device ID first if it doesn't have one yet, and shares the name with the account's other
+	/// devices on the next sync.  Edits made from now on are attributed to this device.
+	/// NOTE: Device names are synced as settings, which older versions of Fortress can't sync (see `set_setting`).
+	pub fn set_device_name<S: Into<String>>(&mut self, name: S) {
+		let name = name.into();
+		let id = self.device.as_ref().map_or_else(|| OsRng.gen(), |device| device.id);
+
+		self.set_setting(format!("{DEVICE_NAME_SETTING_PREFIX}{}", id.to_hex()), serde_json::Value::from(name.as_str()));
+		self.device = Some(DeviceIdentity { id, name });
+		self.objects.set_device(Some(id));
+	}
+
+	/// Gives this device a new device ID, keeping its name, so a database file copied from another device stops being mistaken
+	/// for it.  The old ID's name is kept too, so past edits stay attributed.  Does nothing if the device was never named.
+	pub fn reset_device_id(&mut self) {
+		if let Some(device) = self.device.take() {
+			self.set_device_name(device.name);
+		}
+	}
+
+	/// None until the device is named with `set_device_name`.
+	pub fn get_device_id(&self) -> Option<&DeviceId> {
+		self.device.as_ref().map(|device| &device.id)
+	}
+
+	pub fn get_device_name(&self) -> Option<&str> {
+		self.device.as_ref().map(|device| device.name.as_str())
+	}
+
+	/// The names of every device that has been named, as synced so far.
+	pub fn get_device_names(&self) -> HashMap<DeviceId, &str> {
+		self.get_settings()
+			.into_iter()
+			.flat_map(|settings| settings.iter())
+			.filter_map(|(key, value)| {
+				let id = DeviceId::from_hex(key.strip_prefix(DEVICE_NAME_SETTING_PREFIX)?)?;
+				Some((id, value.as_str()?))
+			})
+			.collect()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, Directory, Entry, EntryHistory};
+	use std::collections::HashMap;
+	use tempfile::tempdir;
+
+	#[test]
+	fn device_attribution() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+
+		// Nothing is attributed until the device is named
+		db.new_entry();
+		assert!(db.get_root().get_history().iter().all(|item| item.device.is_none()));
+		assert!(db.get_device_id().is_none());
+
+		db.set_device_name("Laptop");
+		let laptop = *db.get_device_id().unwrap();
+		assert_eq!(db.get_device_name(), Some("Laptop"));
+		assert_eq!(db.get_device_names(), HashMap::from([(laptop, "Laptop")]));
+
+		// New objects, edits through the database, and directory changes are all attributed
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(HashMap::from([("title".to_owned(), "Mail".to_owned())])));
+		let entry_id = *entry.get_id();
+		db.add_entry(entry);
+		let mut directory = Directory::new();
+		directory.rename("Work");
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+		db.move_object(&entry_id, &directory_id);
+		let entry = db.get_entry_by_id_mut(&entry_id).unwrap();
+		entry.edit(entry.new_edit(HashMap::from([("username".to_owned(), "alice".to_owned())])));
+
+		let entry = db.get_entry_by_id(&entry_id).unwrap();
+		assert!(entry.get_history().iter().all(|item| item.device == Some(laptop)));
+		assert!(db
+			.get_directory_by_id(&directory_id)
+			.unwrap()
+			.get_history()
+			.iter()
+			.all(|item| item.device == Some(laptop)));
+		assert_eq!(db.get_root().get_history().last().unwrap().device, Some(laptop));
+		assert!(db.change_log(0).iter().any(|record| record.id == entry_id && record.device == Some(laptop)));
+
+		// The identity is kept in the database file, and edits after loading are still attributed
+		db.save_to_path(&path).unwrap();
+		let mut db2 = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(db2.get_device_id(), Some(&laptop));
+		assert_eq!(db2.get_device_name(), Some("Laptop"));
+		db2.get_directory_by_id_mut(&directory_id).unwrap().rename("Personal");
+		assert_eq!(
+			db2.get_directory_by_id(&directory_id).unwrap().get_history().last().unwrap().device,
+			Some(laptop)
+		);
+
+		// A copy given its own ID is a different device, and the old ID keeps its name
+		db2.reset_device_id();
+		let copy = *db2.get_device_id().unwrap();
+		assert_ne!(copy, laptop);
+		assert_eq!(db2.get_device_names(), HashMap::from([(laptop, "Laptop"), (copy, "Laptop")]));
+		db2.set_device_name("Phone");
+		assert_eq!(db2.get_device_names()[&copy], "Phone");
+	}
+
+	#[test]
+	fn device_is_ignored_when_merging() {
+		let mut laptop = Database::new_with_password("username", "password");
+		laptop.set_device_name("Laptop");
+		let mut phone = Database::new_with_password("username", "password");
+		phone.set_device_name("Phone");
+
+		// The same edit, made on both devices at the same time, merges into one edit attributed to the lower device ID
+		let mut entry = Entry::new();
+		let edit = EntryHistory::new(HashMap::from([("title".to_owned(), "Mail".to_owned())]));
+		let mut entry2 = entry.clone();
+		entry.attribute_to(laptop.get_device_id().copied());
+		entry.edit(edit.clone());
+		entry2.attribute_to(phone.get_device_id().copied());
+		entry2.edit(edit);
+
+		let merged = entry.merge(&entry2).unwrap();
+		assert_eq!(merged, entry2.merge(&entry).unwrap());
+		assert_eq!(merged.get_history().len(), 1);
+		assert_eq!(merged.get_history()[0].device, laptop.get_device_id().min(phone.get_device_id()).copied());
+		assert!(entry.safe_to_replace_with(&merged) && entry2.safe_to_replace_with(&merged));
+
+		let mut directory = Directory::new();
+		let mut directory2 = directory.clone();
+		directory.set_editing_device(laptop.get_device_id().copied());
+		directory.rename_with_time("Work", 1);
+		directory2.set_editing_device(phone.get_device_id().copied());
+		directory2.rename_with_time("Work", 1);
+
+		let merged = directory.merge(&directory2).unwrap();
+		assert_eq!(merged, directory2.merge(&directory).unwrap());
+		assert_eq!(merged.get_history().len(), 1);
+		assert_eq!(merged.get_history()[0].device, laptop.get_device_id().min(phone.get_device_id()).copied());
+		assert!(directory.safe_to_replace_with(&merged) && directory2.safe_to_replace_with(&merged));
+		assert!(directory.concurrent_rename(&directory2).is_none());
+	}
+}
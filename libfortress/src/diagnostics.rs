@@ -0,0 +1,110 @@
+// Diagnostics for frontends and other embedders.  With the `tracing` feature, problems that libfortress works around instead
+// of failing (like server objects it can't read during sync) are reported as `tracing` events with the object's ID and the
+// kind of error as fields, and sync, each of its rounds and phases, and saving and loading a database run inside spans.  An
+// embedder can install a subscriber to log them or show them to the user.
+// Without the feature, the warnings are printed to stderr and there are no spans.
+use crate::{SyncPhase, ID};
+use std::{fmt, path::Path};
+
+
+/// Why sync skipped one of the server's objects.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) enum SkippedObject {
+	/// The server listed the object but didn't return it
+	Missing,
+	/// Probably encrypted with an old password
+	Undecryptable,
+	/// Decrypted, but isn't an object we understand
+	Malformed,
+}
+
+impl SkippedObject {
+	fn as_str(self) -> &'static str {
+		match self {
+			SkippedObject::Missing => "missing",
+			SkippedObject::Undecryptable => "undecryptable",
+			SkippedObject::Malformed => "malformed",
+		}
+	}
+}
+
+
+pub(crate) fn skipped_server_object(id: &ID, kind: SkippedObject, error: Option<&dyn fmt::Display>) {
+	#[cfg(feature = "tracing")]
+	match error {
+		Some(error) => tracing::warn!(object_id = %id.to_hex(), error_kind = kind.as_str(), %error, "skipped server object"),
+		None => tracing::warn!(object_id = %id.to_hex(), error_kind = kind.as_str(), "skipped server object"),
+	}
+
+	#[cfg(not(feature = "tracing"))]
+	match error {
+		Some(error) => eprintln!("WARNING: Skipped server object {} ({}): {}", id.to_hex(), kind.as_str(), error),
+		None => eprintln!("WARNING: Skipped server object {} ({})", id.to_hex(), kind.as_str()),
+	}
+}
+
+
+/// Leaves its span when dropped.
+#[must_use]
+pub(crate) struct SpanGuard {
+	#[cfg(feature = "tracing")]
+	_span: tracing::span::EnteredSpan,
+}
+
+
+pub(crate) fn sync_span() -> SpanGuard {
+	SpanGuard {
+		#[cfg(feature = "tracing")]
+		_span: tracing::info_span!("sync").entered(),
+	}
+}
+
+
+pub(crate) fn sync_round_span(round: usize) -> SpanGuard {
+	let _ = round;
+
+	SpanGuard {
+		#[cfg(feature = "tracing")]
+		_span: tracing::info_span!("sync_round", round).entered(),
+	}
+}
+
+
+pub(crate) fn sync_phase_span(phase: SyncPhase) -> SpanGuard {
+	let _ = phase;
+
+	SpanGuard {
+		#[cfg(feature = "tracing")]
+		_span: tracing::info_span!("sync_phase", ?phase).entered(),
+	}
+}
+
+
+pub(crate) fn save_span(path: &Path) -> SpanGuard {
+	let _ = path;
+
+	SpanGuard {
+		#[cfg(feature = "tracing")]
+		_span: tracing::info_span!("save", path = %path.display()).entered(),
+	}
+}
+
+
+pub(crate) fn load_span() -> SpanGuard {
+	SpanGuard {
+		#[cfg(feature = "tracing")]
+		_span: tracing::info_span!("load").entered(),
+	}
+}
+
+
+/// Reports that sync failed.  The error's Debug form names its kind.  Cancellation isn't reported, since the user asked for it.
+pub(crate) fn sync_failed(error: &crate::FortressError) {
+	#[cfg(feature = "tracing")]
+	if !matches!(error, crate::FortressError::Cancelled) {
+		tracing::warn!(error = ?error, "sync failed");
+	}
+
+	#[cfg(not(feature = "tracing"))]
+	let _ = error;
+}
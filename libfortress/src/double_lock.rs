@@ -0,0 +1,246 @@
+// Double-locked entries: entries whose secrets need a second password or PIN to see, even while the database is open, for the
+// few entries worth the trouble (a bank login, a recovery key) on a screen others might see.  An entry is double-locked when its
+// `double_lock` field is set: it holds every field apart from the title and URL (and a note's type and format; see the
+// secure_note module), encrypted with the PIN in the database file format, so it carries its own KDF parameters, in base64.
+// The fields it holds are emptied in the same edit, so listings, search, and exports only see the title and URL, and
+// `Entry::unlock_fields` gets the rest back on demand.
+//
+// The lock is an ordinary field, so it syncs and merges like any other, and older versions show a double-locked entry with an
+// unreadable `double_lock` field.  Locking an entry doesn't touch its history: edits from before it was locked still hold the
+// values it had then.  While it's locked, those values are redacted wherever history is shown (`Entry::get_visible_history`,
+// `Entry::diff`, `Database::change_log`, `Database::snapshot_at`), and `Entry::revert_field` won't bring them back.  They're
+// still in the database file and on the sync server, though, so secrets that matter should be changed after locking the entry
+// that holds them.
+use crate::{Entry, EntryHistory, FieldKind, FieldMetadata, FortressError, ENTRY_TYPE_FIELD, NOTE_FORMAT_FIELD};
+use data_encoding::BASE64;
+use fortresscrypto::{CryptoError, FileKeySuite, Zeroizing};
+use std::{
+	collections::{BTreeMap, HashMap},
+	io::Cursor,
+};
+
+
+/// Name of the field a double-locked entry's other fields are kept in (see the double_lock module).
+pub const DOUBLE_LOCK_FIELD: &str = "double_lock";
+/// Fields that double-locking leaves as they are.
+pub const DOUBLE_LOCK_CLEAR_FIELDS: &[&str] = &["title", "url", ENTRY_TYPE_FIELD, NOTE_FORMAT_FIELD];
+
+
+impl Entry {
+	pub fn is_double_locked(&self) -> bool {
+		self.get(DOUBLE_LOCK_FIELD).is_some_and(|locked| !locked.is_empty())
+	}
+
+	/// True if the entry is double-locked and `key` is one of the fields its lock holds, whose past values stay hidden while it's
+	/// locked (see the double_lock module).
+	pub fn is_field_double_locked(&self, key: &str) -> bool {
+		key != DOUBLE_LOCK_FIELD && !DOUBLE_LOCK_CLEAR_FIELDS.contains(&key) && self.is_double_locked()
+	}
+
+	/// The entry's history, with the past values of the fields its double lock holds replaced with `REDACTED_VALUE` while it's
+	/// locked.  The same as `get_history` for entries that aren't double-locked.
+	pub fn get_visible_history(&self) -> Vec<EntryHistory> {
+		self.redact_double_locked().get_history().to_vec()
+	}
+
+	/// A copy of the entry with the past values of the fields its double lock holds redacted (see `get_visible_history`).
+	pub(crate) fn redact_double_locked(&self) -> Entry {
+		self.redacted(|key| self.is_field_double_locked(key))
+	}
+
+	/// The entry's fields with the ones the double lock holds filled in (see the double_lock module).  Entries that aren't
+	/// double-locked just give their fields.  Fails with CryptoError::DecryptionError if `pin` is wrong.
+	pub fn unlock_fields(&self, pin: &str) -> Result<HashMap<String, String>, FortressError> {
+		let mut fields = self
+			.get_state()
+			.iter()
+			.filter(|(name, _)| *name != DOUBLE_LOCK_FIELD)
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect::<HashMap<_, _>>();
+
+		if let Some(locked) = self.get(DOUBLE_LOCK_FIELD).filter(|locked| !locked.is_empty()) {
+			fields.extend(open(locked, pin)?);
+		}
+
+		Ok(fields)
+	}
+
+	/// An edit (see `new_edit`) that double-locks the entry with `pin`.  Entries that are already double-locked have to have been
+	/// locked with `pin`, and stay as they are.
+	pub fn double_lock_edit(&self, pin: &str) -> Result<EntryHistory, FortressError> {
+		self.double_locked_edit(pin, HashMap::new())
+	}
+
+	/// An edit (see `new_edit`) that sets the fields in `data` and leaves the entry double-locked with `pin`, whether or not it
+	/// was before.  If it was, it has to have been locked with `pin` (CryptoError::DecryptionError otherwise).
+	pub fn double_locked_edit(&self, pin: &str, data: HashMap<String, String>) -> Result<EntryHistory, FortressError> {
+		let mut fields = self.unlock_fields(pin)?;
+		fields.extend(data.clone());
+
+		let locked = fields
+			.iter()
+			.filter(|(name, value)| !DOUBLE_LOCK_CLEAR_FIELDS.contains(&name.as_str()) && *name != DOUBLE_LOCK_FIELD && !value.is_empty())
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect::<BTreeMap<_, _>>();
+
+		let mut edit = data
+			.into_iter()
+			.filter(|(name, _)| DOUBLE_LOCK_CLEAR_FIELDS.contains(&name.as_str()))
+			.collect::<HashMap<_, _>>();
+		for name in self.get_state().keys().filter(|name| !DOUBLE_LOCK_CLEAR_FIELDS.contains(&name.as_str())) {
+			edit.insert(name.clone(), String::new());
+		}
+		edit.insert(DOUBLE_LOCK_FIELD.to_string(), lock(&locked, pin));
+
+		let metadata = FieldMetadata {
+			kind: FieldKind::Secret,
+			protected: true,
+			derived: None,
+		};
+
+		Ok(self.new_edit(edit).with_metadata(DOUBLE_LOCK_FIELD, metadata))
+	}
+
+	/// An edit (see `new_edit`) that puts the fields the double lock holds back in the entry, so it's no longer double-locked.
+	/// Fails with CryptoError::DecryptionError if `pin` is wrong.
+	pub fn remove_double_lock_edit(&self, pin: &str) -> Result<EntryHistory, FortressError> {
+		let mut fields = self.unlock_fields(pin)?;
+		fields.retain(|name, value| self.get(name) != Some(value));
+		if self.get(DOUBLE_LOCK_FIELD).is_some() {
+			fields.insert(DOUBLE_LOCK_FIELD.to_string(), String::new());
+		}
+
+		Ok(self.new_edit(fields))
+	}
+}
+
+
+fn lock(fields: &BTreeMap<String, String>, pin: &str) -> String {
+	let payload = Zeroizing::new(serde_json::to_vec(fields).expect("internal error"));
+	let keys = FileKeySuite::derive(pin.as_bytes(), &Default::default()).expect("internal error");
+	let mut file = Vec::new();
+	fortresscrypto::encrypt_to_file(&mut file, &payload, &keys).expect("internal error");
+
+	BASE64.encode(&file)
+}
+
+
+fn open(locked: &str, pin: &str) -> Result<BTreeMap<String, String>, FortressError> {
+	let file = BASE64.decode(locked.as_bytes()).map_err(|_| CryptoError::DecryptionError)?;
+	let (payload, _) = fortresscrypto::decrypt_from_file(&mut Cursor::new(file), pin.as_bytes())?;
+
+	Ok(serde_json::from_slice(&payload)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::DOUBLE_LOCK_FIELD;
+	use crate::{testing::fields, ChangeAction, Database, Entry, EntryHistory, FortressError, REDACTED_VALUE};
+	use fortresscrypto::CryptoError;
+
+	#[test]
+	fn double_lock() {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[
+			("title", "Bank"),
+			("url", "https://bank.example.com"),
+			("username", "alice"),
+			("password", "hunter2"),
+		])));
+		let unlocked = entry.get_state().clone();
+		assert!(!entry.is_double_locked());
+		assert_eq!(entry.unlock_fields("1234").unwrap(), unlocked);
+
+		entry.edit(entry.double_lock_edit("1234").unwrap());
+		assert!(entry.is_double_locked());
+		assert!(entry.is_field_protected(DOUBLE_LOCK_FIELD));
+		assert_eq!(entry["title"], "Bank");
+		assert_eq!(entry["url"], "https://bank.example.com");
+		assert_eq!(entry["username"], "");
+		assert_eq!(entry["password"], "");
+		assert!(!entry[DOUBLE_LOCK_FIELD].contains("hunter2"));
+
+		assert!(matches!(
+			entry.unlock_fields("4321"),
+			Err(FortressError::CryptoError(CryptoError::DecryptionError))
+		));
+		assert!(entry.double_lock_edit("4321").is_err());
+		assert_eq!(entry.unlock_fields("1234").unwrap(), unlocked);
+
+		// Edits keep the entry locked, and the title in the clear
+		entry.edit(
+			entry
+				.double_locked_edit("1234", fields(&[("title", "My Bank"), ("password", "correct horse"), ("pin", "0000")]))
+				.unwrap(),
+		);
+		assert!(entry.is_double_locked());
+		assert_eq!(entry["title"], "My Bank");
+		assert!(entry.get("pin").is_none());
+		let unlocked = entry.unlock_fields("1234").unwrap();
+		assert_eq!((unlocked["password"].as_str(), unlocked["pin"].as_str()), ("correct horse", "0000"));
+
+		entry.edit(entry.remove_double_lock_edit("1234").unwrap());
+		assert!(!entry.is_double_locked());
+		assert_eq!(entry["username"], "alice");
+		assert_eq!(entry["password"], "correct horse");
+		assert_eq!(entry["pin"], "0000");
+		assert_eq!(entry.unlock_fields("").unwrap()["password"], "correct horse");
+	}
+
+	// Searching a double-locked entry only finds it by what's left in the clear
+	#[test]
+	fn double_locked_search() {
+		let mut db = Database::new_with_password("username", "password");
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[("title", "Recovery key"), ("notes", "vault-7731")])));
+		entry.edit(entry.double_lock_edit("1234").unwrap());
+		db.add_entry(entry);
+
+		assert_eq!(db.search("recovery").len(), 1);
+		assert!(db.search("vault").is_empty());
+	}
+
+	// History from before the entry was locked doesn't give its secrets away while it's locked
+	#[test]
+	fn double_locked_history() {
+		let mut db = Database::new_with_password("username", "password");
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[("title", "Bank"), ("password", "hunter2")])));
+		let before = entry.get_history()[0].time;
+		entry.edit(entry.double_lock_edit("1234").unwrap());
+		let locked = entry.get_history()[1].time;
+		let id = *entry.get_id();
+		db.add_entry(entry.clone());
+
+		let history = entry.get_visible_history();
+		assert_eq!(history[0]["title"], "Bank");
+		assert_eq!(history[0]["password"], REDACTED_VALUE);
+		assert_eq!(history[1]["password"], "");
+		assert_eq!(history[1][DOUBLE_LOCK_FIELD], entry[DOUBLE_LOCK_FIELD]);
+
+		let change = entry.diff(0, before).into_iter().find(|change| change.key == "password").unwrap();
+		assert_eq!(change.new.as_deref(), Some(REDACTED_VALUE));
+
+		let changes = db.change_log(0);
+		assert!(changes
+			.iter()
+			.any(|record| matches!(&record.action, ChangeAction::Field { key, new, .. } if key == "title" && new == "Bank")));
+		for record in &changes {
+			if let ChangeAction::Field { key, old, new } = &record.action {
+				assert!(key != "password" || (new != "hunter2" && old.as_deref() != Some("hunter2")));
+			}
+		}
+
+		let snapshot = db.snapshot_at(before);
+		assert_eq!(snapshot.get_entry_by_id(&id).unwrap()["title"], "Bank");
+		assert_eq!(snapshot.get_entry_by_id(&id).unwrap()["password"], REDACTED_VALUE);
+
+		assert!(entry.revert_field("password", before).is_none());
+
+		// Once the lock is removed, the history is all there again
+		entry.edit(entry.remove_double_lock_edit("1234").unwrap());
+		assert_eq!(entry.get_visible_history(), entry.get_history());
+		assert_eq!(entry.revert_field("password", locked).unwrap()["password"], "");
+	}
+}
@@ -1,6 +1,6 @@
 use fortresscrypto::CryptoError;
 
-use crate::ApiError;
+use crate::{ApiError, SyncLimit, ID};
 
 
 #[derive(Debug)]
@@ -12,6 +12,83 @@ pub enum FortressError {
 	SyncApiError(ApiError),
 	SyncInconsistentServer,
 	SyncConflict,
+	SyncMalformedResponse,
+	SyncObjectTypeMismatch(ID),
+	/// Merging the server's copy of an object would have lost some of the local copy's history
+	SyncHistoryLost(ID),
+	SyncNotConverging,
+	ReencryptionMismatch,
+	LegacyDatabase,
+	LegacyImportError,
+	MalformedPaperBackup,
+	/// Paper backups can't be made of databases that need a hardware key or have more than one user (see the paper_backup module)
+	PaperBackupUnsupported,
+	Cancelled,
+	ClipboardUnavailable(String),
+	SyncAccountChanged,
+	/// The sync server won't accept an object this large (see the sync_capabilities module).  `title` and `field` name the entry
+	/// and its largest field, if the object is an entry.  `max` is None if the server didn't say what its limit is.
+	SyncObjectTooLarge {
+		id: ID,
+		title: Option<String>,
+		field: Option<String>,
+		size: usize,
+		max: Option<usize>,
+	},
+	/// An object or listing from the sync server, or one of our objects about to be uploaded, is over one of the limits in the
+	/// limits module.  `id` is None for listings.
+	SyncLimitExceeded {
+		id: Option<ID>,
+		limit: SyncLimit,
+	},
+	/// The database can't be opened without its password, because its sync keys have to be derived again (they were still being
+	/// derived when it was saved).
+	PasswordRequired,
+	/// The OS keychain couldn't be used; holds the name of the tool that failed (see the keychain module).
+	KeychainUnavailable(String),
+	/// An entry in a share couldn't be merged into this database's object with the same ID (see the share module).
+	ShareConflict(ID),
+	/// The database file is open in another process (see the file_lock module).  `pid` is that process's ID, if it could be read.
+	DatabaseLocked {
+		pid: Option<u32>,
+	},
+	/// A signed digest was made with another database's key, or of different contents (see the signature module).
+	SignatureMismatch,
+	/// The sync server already has an account for these credentials (see the sync_account module).
+	SyncAccountExists,
+	/// The sync server doesn't have an account for these credentials, or rejected them.
+	SyncCredentialsRejected,
+	/// The sync server doesn't support registering, deleting, or querying accounts.
+	SyncAccountManagementUnsupported,
+	/// The sync server doesn't support dropping old tombstones (see the gc module).
+	SyncGcUnsupported,
+	/// A sync took longer than its `SyncOptions::timeout`.
+	SyncTimedOut,
+	/// The sync proxy isn't a SOCKS5 URL with a host and port (see `Database::set_sync_proxy`).
+	SyncBadProxy,
+	/// The breach check's range API couldn't be reached or answered with an error (see the breach module).
+	BreachCheckError(ApiError),
+	/// The breach check's bloom filter file isn't one (see the breach module).
+	MalformedBloomFilter,
+	/// No password meets the generator's options (see the generator module).
+	GeneratorUnsatisfiable,
+	/// An object from the sync server or a database file has an ID reserved for a different kind of object (see the id_policy
+	/// module).
+	ReservedIdViolation(ID),
+	/// The database syncs with a read-only login key (see the readonly_sync module), so it can't change anything on the server.
+	SyncReadOnly,
+	/// The database already syncs with the server being added as a sync target (see the sync_targets module).
+	SyncTargetExists,
+	/// An export being imported isn't in the format its importer expects (see the import module).
+	MalformedImport(String),
+	/// An edit was timestamped before the object's latest edit.
+	HistoryOrder(HistoryOrderError),
+	/// The database file has no user with this number (see the users module).
+	NoSuchUser(usize),
+	/// User 0's password is the database's own, and can't be removed (see the users module).
+	CannotRemoveDatabasePassword,
+	/// Only user 0, whose password is the database's own, can change it (see the users module).
+	NotDatabasePassword,
 }
 
 impl From<std::io::Error> for FortressError {
@@ -41,6 +118,12 @@ impl From<ApiError> for FortressError {
 	}
 }
 
+impl From<HistoryOrderError> for FortressError {
+	fn from(error: HistoryOrderError) -> FortressError {
+		FortressError::HistoryOrder(error)
+	}
+}
+
 impl std::error::Error for FortressError {}
 
 impl std::fmt::Display for FortressError {
@@ -53,6 +136,92 @@ impl std::fmt::Display for FortressError {
 			FortressError::SyncApiError(e) => write!(f, "Sync API error: {e}"),
 			FortressError::SyncInconsistentServer => write!(f, "Sync server is inconsistent"),
 			FortressError::SyncConflict => write!(f, "Sync Conflict"),
+			FortressError::SyncMalformedResponse => write!(f, "Sync server sent a malformed response"),
+			FortressError::SyncObjectTypeMismatch(id) => write!(f, "Sync server sent an object of the wrong type (ID: {})", id.to_hex()),
+			FortressError::SyncHistoryLost(id) => write!(f, "Merging the sync server's copy of an object would lose history (ID: {})", id.to_hex()),
+			FortressError::SyncNotConverging => write!(f, "Sync did not settle; the sync server may be misbehaving"),
+			FortressError::ReencryptionMismatch => write!(f, "Re-encrypted database did not match the original"),
+			FortressError::LegacyDatabase => write!(f, "Database is in the legacy fortress1 format and must be upgraded first"),
+			FortressError::LegacyImportError => write!(f, "Legacy database contains invalid entries"),
+			FortressError::MalformedPaperBackup => write!(f, "Paper backup is incomplete or malformed"),
+			FortressError::PaperBackupUnsupported => write!(
+				f,
+				"Paper backups can't be made of databases that need a hardware key or have more than one user"
+			),
+			FortressError::Cancelled => write!(f, "Operation was cancelled"),
+			FortressError::SyncAccountChanged => write!(
+				f,
+				"Database was last synced with a different account; syncing would merge the two accounts' entries"
+			),
+			FortressError::SyncObjectTooLarge { id, title, field, size, max } => {
+				match title {
+					Some(title) => write!(f, "Entry '{title}' is too large to sync")?,
+					None => write!(f, "Object {} is too large to sync", id.to_hex())?,
+				}
+				match max {
+					Some(max) => write!(f, " ({size} bytes; the sync server's limit is {max})")?,
+					None => write!(f, " ({size} bytes)")?,
+				}
+				match field {
+					Some(field) => write!(f, "; most of it is the '{field}' field and its history"),
+					None => Ok(()),
+				}
+			},
+			FortressError::SyncLimitExceeded { id: Some(id), limit } => write!(f, "Object is over the sync limit on {limit} (ID: {})", id.to_hex()),
+			FortressError::SyncLimitExceeded { id: None, limit } => write!(f, "Sync server's object listing is over the limit on {limit}"),
+			FortressError::ShareConflict(id) => write!(f, "A shared entry conflicts with this database's copy (ID: {})", id.to_hex()),
+			FortressError::PasswordRequired => write!(f, "The database's password is needed to open it"),
+			FortressError::KeychainUnavailable(tool) => write!(f, "Couldn't access the OS keychain using {tool}; is it installed and unlocked?"),
+			FortressError::DatabaseLocked { pid: Some(pid) } => write!(f, "Database is open in another process (PID {pid}); close it there first"),
+			FortressError::DatabaseLocked { pid: None } => write!(f, "Database is open in another process; close it there first"),
+			FortressError::SignatureMismatch => write!(f, "Database doesn't match its signature"),
+			FortressError::SyncAccountExists => write!(f, "The sync server already has an account for this username and password"),
+			FortressError::SyncCredentialsRejected => write!(f, "The sync server doesn't have an account for this username and password"),
+			FortressError::SyncAccountManagementUnsupported => write!(f, "The sync server doesn't support managing accounts"),
+			FortressError::SyncGcUnsupported => write!(f, "The sync server doesn't support dropping deleted objects"),
+			FortressError::SyncTimedOut => write!(f, "Sync took too long and was stopped"),
+			FortressError::SyncBadProxy => write!(f, "Bad sync proxy; it should be a URL like socks5://127.0.0.1:9050"),
+			FortressError::BreachCheckError(e) => write!(f, "Couldn't check for breached passwords: {e}"),
+			FortressError::MalformedBloomFilter => write!(f, "Breached password list is not a valid bloom filter"),
+			FortressError::GeneratorUnsatisfiable => write!(f, "No password can meet these generator options"),
+			FortressError::ReservedIdViolation(id) => write!(f, "An object has an ID reserved for a different kind of object (ID: {})", id.to_hex()),
+			FortressError::SyncReadOnly => write!(f, "This database's sync credentials are read-only, so it can't upload changes"),
+			FortressError::SyncTargetExists => write!(f, "This database already syncs with that server"),
+			FortressError::MalformedImport(reason) => write!(f, "The file can't be imported: {reason}"),
+			FortressError::ClipboardUnavailable(tool) => write!(f, "Couldn't access the clipboard using {tool}; is it installed?"),
+			FortressError::HistoryOrder(e) => write!(f, "{e}"),
+			FortressError::NoSuchUser(user) => write!(f, "The database has no user {user}"),
+			FortressError::CannotRemoveDatabasePassword => write!(f, "The database's own password can't be removed; change it instead"),
+			FortressError::NotDatabasePassword => write!(f, "Only the database's own password can be changed; other users are removed and added again"),
 		}
 	}
 }
+
+
+/// An edit to an entry or directory (`Entry::try_edit`, `Directory::try_add_with_time`, ...) was timestamped before its latest
+/// edit, which would put its history out of order.  Nothing was changed.  Edits made now (`Entry::new_edit`, `Directory::add`,
+/// ...) never are; this only happens to edits given an explicit time, e.g. from a clock that was set back.  Settings set with
+/// an explicit time (`SettingsObject::set_with_time`) fail the same way if the setting was last set later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryOrderError {
+	/// The entry, directory, or settings object.
+	pub id: ID,
+	/// When the edit was timestamped.
+	pub time: u64,
+	/// When the latest edit was.
+	pub latest: u64,
+}
+
+impl std::error::Error for HistoryOrderError {}
+
+impl std::fmt::Display for HistoryOrderError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(
+			f,
+			"History must be ordered: an edit at {} is before the latest edit at {} (ID: {})",
+			self.time,
+			self.latest,
+			self.id.to_hex()
+		)
+	}
+}
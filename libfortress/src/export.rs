@@ -0,0 +1,273 @@
+// Exports a Database to a stable, documented JSON format (see EXPORT.md).
+// Unlike the internal serialization, this format doesn't include any sync parameters or keys, and
+// changes to it must remain backwards compatible (bump EXPORT_VERSION when adding to it).
+use crate::{
+	database_object::{DirectoryHistoryAction, Entry},
+	Database, Directory, FortressError, ID,
+};
+use serde::Serialize;
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	io,
+};
+
+
+pub const EXPORT_FORMAT: &str = "fortress-export";
+pub const EXPORT_VERSION: u32 = 3;
+
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ExportOptions {
+	/// Include the full edit history of every object, not just its current state.
+	pub include_history: bool,
+	/// Include the values of protected fields (e.g. passwords).  When false, those fields are omitted entirely.
+	pub include_secrets: bool,
+}
+
+impl Default for ExportOptions {
+	fn default() -> ExportOptions {
+		ExportOptions {
+			include_history: true,
+			include_secrets: true,
+		}
+	}
+}
+
+
+#[derive(Serialize)]
+struct ExportedDatabase<'a> {
+	format: &'static str,
+	version: u32,
+	root: ID,
+	directories: Vec<ExportedDirectory<'a>>,
+	entries: Vec<ExportedEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct ExportedDirectory<'a> {
+	id: ID,
+	name: Option<&'a str>,
+	local_only: bool,
+	children: Vec<ID>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	history: Option<Vec<ExportedDirectoryHistory<'a>>>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ExportedDirectoryHistory<'a> {
+	Add { time: u64, id: ID },
+	Remove { time: u64, id: ID },
+	Rename { time: u64, name: &'a str },
+	SetLocalOnly { time: u64, local_only: bool },
+}
+
+#[derive(Serialize)]
+struct ExportedEntry<'a> {
+	id: ID,
+	parent: Option<ID>,
+	time_created: u64,
+	fields: BTreeMap<&'a str, &'a str>,
+	tags: &'a BTreeSet<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	history: Option<Vec<ExportedEntryHistory<'a>>>,
+}
+
+#[derive(Serialize)]
+struct ExportedEntryHistory<'a> {
+	time: u64,
+	fields: BTreeMap<&'a str, &'a str>,
+}
+
+
+impl Database {
+	/// Writes the database to `writer` as JSON in the export format described in EXPORT.md.
+	/// Output is deterministic: objects are ordered by ID and fields by name.
+	pub fn export_json<W: io::Write>(&self, writer: W, options: &ExportOptions) -> Result<(), FortressError> {
+		let mut directories = self
+			.list_directories()
+			.map(|directory| export_directory(directory, options))
+			.collect::<Vec<_>>();
+		directories.sort_unstable_by_key(|directory| directory.id);
+
+		let mut entries = self.list_entries().map(|entry| self.export_entry(entry, options)).collect::<Vec<_>>();
+		entries.sort_unstable_by_key(|entry| entry.id);
+
+		let exported = ExportedDatabase {
+			format: EXPORT_FORMAT,
+			version: EXPORT_VERSION,
+			root: *self.get_root().get_id(),
+			directories,
+			entries,
+		};
+
+		serde_json::to_writer_pretty(writer, &exported)?;
+		Ok(())
+	}
+
+	fn export_entry<'a>(&self, entry: &'a Entry, options: &ExportOptions) -> ExportedEntry<'a> {
+		let filter_fields = |fields: &'a HashMap<String, String>| -> BTreeMap<&'a str, &'a str> {
+			fields
+				.iter()
+				.filter(|(key, _)| options.include_secrets || !entry.is_field_protected(key))
+				.map(|(key, value)| (key.as_str(), value.as_str()))
+				.collect()
+		};
+
+		let history = options.include_history.then(|| {
+			entry
+				.get_history()
+				.iter()
+				.map(|item| ExportedEntryHistory {
+					time: item.time,
+					fields: filter_fields(&item.data),
+				})
+				.filter(|item| !item.fields.is_empty())
+				.collect()
+		});
+
+		ExportedEntry {
+			id: *entry.get_id(),
+			parent: self.get_parent_directory(entry.get_id()).map(|directory| *directory.get_id()),
+			time_created: entry.get_time_created(),
+			fields: filter_fields(entry.get_state()),
+			tags: entry.get_tags(),
+			history,
+		}
+	}
+}
+
+fn export_directory<'a>(directory: &'a Directory, options: &ExportOptions) -> ExportedDirectory<'a> {
+	let mut children = directory.entries.iter().copied().collect::<Vec<_>>();
+	children.sort_unstable();
+
+	let history = options.include_history.then(|| {
+		directory
+			.get_history()
+			.iter()
+			.map(|item| match &item.action {
+				DirectoryHistoryAction::Add(id) => ExportedDirectoryHistory::Add { time: item.time, id: *id },
+				DirectoryHistoryAction::Remove(id) => ExportedDirectoryHistory::Remove { time: item.time, id: *id },
+				DirectoryHistoryAction::Rename(name) => ExportedDirectoryHistory::Rename { time: item.time, name },
+				DirectoryHistoryAction::SetLocalOnly(local_only) => ExportedDirectoryHistory::SetLocalOnly {
+					time: item.time,
+					local_only: *local_only,
+				},
+			})
+			.collect()
+	});
+
+	ExportedDirectory {
+		id: *directory.get_id(),
+		name: directory.get_name(),
+		local_only: directory.is_local_only(),
+		children,
+		history,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::ExportOptions;
+	use crate::{Database, Directory, Entry, EntryHistory};
+	use serde_json::Value;
+
+	fn build_database() -> Database {
+		let mut db = Database::new_with_password("username", "password");
+		db.get_root_mut().rename("My Passwords");
+
+		let mut directory = Directory::new();
+		directory.rename("Work");
+		directory.set_local_only(true);
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+
+		let mut entry = Entry::new();
+		let entry_id = *entry.get_id();
+		entry.edit(EntryHistory::new(
+			[("title".to_string(), "Example".to_string()), ("password".to_string(), "hunter2".to_string())]
+				.iter()
+				.cloned()
+				.collect(),
+		));
+		entry.edit(EntryHistory::new([("password".to_string(), "hunter3".to_string())].iter().cloned().collect()));
+		entry.add_tag("work");
+		db.add_entry(entry);
+		db.move_object(&entry_id, &directory_id);
+
+		db
+	}
+
+	fn export(db: &Database, options: &ExportOptions) -> Value {
+		let mut buffer = Vec::new();
+		db.export_json(&mut buffer, options).unwrap();
+		serde_json::from_slice(&buffer).unwrap()
+	}
+
+	#[test]
+	fn export_json() {
+		let db = build_database();
+		let exported = export(&db, &ExportOptions::default());
+
+		assert_eq!(exported["format"], "fortress-export");
+		assert_eq!(exported["version"], 3);
+		assert_eq!(exported["root"], db.get_root().get_id().to_hex());
+		assert_eq!(exported["directories"].as_array().unwrap().len(), 2);
+
+		let work = exported["directories"].as_array().unwrap().iter().find(|d| d["name"] == "Work").unwrap();
+		let entry = &exported["entries"][0];
+		assert_eq!(entry["parent"], work["id"]);
+		assert_eq!(work["children"][0], entry["id"]);
+		assert_eq!(entry["fields"]["title"], "Example");
+		assert_eq!(entry["fields"]["password"], "hunter3");
+		assert_eq!(entry["tags"], serde_json::json!(["work"]));
+		assert_eq!(entry["history"].as_array().unwrap().len(), 2);
+		assert_eq!(entry["history"][0]["fields"]["password"], "hunter2");
+		assert_eq!(work["history"][0]["action"], "rename");
+		assert_eq!(work["history"][0]["name"], "Work");
+		assert_eq!(work["local_only"], true);
+		assert_eq!(work["history"][1]["action"], "set_local_only");
+		assert_eq!(work["history"][1]["local_only"], true);
+
+		// Nothing sync related should leak into the export
+		assert!(exported.get("sync_parameters").is_none());
+
+		// Output is deterministic
+		let mut a = Vec::new();
+		let mut b = Vec::new();
+		db.export_json(&mut a, &ExportOptions::default()).unwrap();
+		db.clone().export_json(&mut b, &ExportOptions::default()).unwrap();
+		assert_eq!(a, b);
+	}
+
+	#[test]
+	fn export_json_options() {
+		let db = build_database();
+		let exported = export(
+			&db,
+			&ExportOptions {
+				include_history: false,
+				include_secrets: false,
+			},
+		);
+
+		let entry = &exported["entries"][0];
+		assert_eq!(entry["fields"]["title"], "Example");
+		assert!(entry["fields"].get("password").is_none());
+		assert!(entry.get("history").is_none());
+		assert!(exported["directories"][0].get("history").is_none());
+
+		// Secrets are also removed from history, along with edits that only touched secrets
+		let exported = export(
+			&db,
+			&ExportOptions {
+				include_history: true,
+				include_secrets: false,
+			},
+		);
+		let history = exported["entries"][0]["history"].as_array().unwrap();
+		assert_eq!(history.len(), 1);
+		assert!(history[0]["fields"].get("password").is_none());
+	}
+}
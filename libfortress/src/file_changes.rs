@@ -0,0 +1,178 @@
+// Noticing when the database file was changed by something else, such as Dropbox or Syncthing bringing in another device's copy,
+// so that frontends can merge the other copy in instead of overwriting it on the next save.  A database remembers the version
+// of the file it was last loaded from or saved to: the checksum at the end of the file, and the length of its journal (see the
+// journal module).
+//
+// `reload_and_merge` merges the file's objects the same way sync merges the server's copies; nothing is ever lost, since objects
+// are only added to and their histories combined.  Everything else (sync settings, local settings) stays as it is here.
+use crate::{
+	journal::{journal_path, read_checksum},
+	Database, FortressError, SyncActivity, SyncReport,
+};
+use std::{
+	fs, io,
+	path::{self, Path, PathBuf},
+	sync::{Mutex, PoisonError},
+};
+
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub(crate) struct FileVersion {
+	path: PathBuf,
+	checksum: [u8; 32],
+	journal_len: u64,
+}
+
+impl FileVersion {
+	pub(crate) fn new(path: &Path, checksum: [u8; 32], journal_len: u64) -> FileVersion {
+		FileVersion {
+			path: path::absolute(path).unwrap_or_else(|_| path.to_owned()),
+			checksum,
+			journal_len,
+		}
+	}
+
+	// The version of the database file at `path` on disk now, or None if there's no file
+	fn read(path: &Path) -> Result<Option<FileVersion>, FortressError> {
+		let Some(checksum) = read_checksum(path)? else {
+			return Ok(None);
+		};
+		let journal_len = match fs::metadata(journal_path(path)) {
+			Ok(metadata) => metadata.len(),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+			Err(err) => return Err(err.into()),
+		};
+
+		Ok(Some(FileVersion::new(path, checksum, journal_len)))
+	}
+}
+
+
+// The version of the file a database was last loaded from or saved to.  Not part of the database's value: it's never
+// serialized, and databases compare equal whatever it is.  `save_to_path` only borrows the database, hence the Mutex.
+#[derive(Debug, Default)]
+pub(crate) struct FileVersionState(Mutex<Option<FileVersion>>);
+
+impl FileVersionState {
+	pub(crate) fn get(&self) -> Option<FileVersion> {
+		self.0.lock().unwrap_or_else(PoisonError::into_inner).clone()
+	}
+
+	pub(crate) fn set(&self, version: FileVersion) {
+		*self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(version);
+	}
+}
+
+impl Clone for FileVersionState {
+	fn clone(&self) -> FileVersionState {
+		FileVersionState(Mutex::new(self.get()))
+	}
+}
+
+impl PartialEq for FileVersionState {
+	fn eq(&self, _other: &FileVersionState) -> bool {
+		true
+	}
+}
+
+impl Eq for FileVersionState {}
+
+
+impl Database {
+	/// True if the database file at `path` (or its journal) isn't what this database last loaded from or saved to it, including
+	/// if the file is gone or can't be read.  Saving now would overwrite whatever changed it; see `reload_and_merge`.
+	pub fn file_changed_on_disk<P: AsRef<Path>>(&self, path: P) -> bool {
+		match FileVersion::read(path.as_ref()) {
+			Ok(Some(version)) => self.file_version.get() != Some(version),
+			Ok(None) | Err(_) => true,
+		}
+	}
+
+	/// Loads the database file at `path` and merges its objects into this database, the way sync merges the server's copies.
+	/// Returns what the file added or changed (as Added and Updated changes), and any conflicts.  If an object can't be merged,
+	/// nothing is changed.  Afterwards `file_changed_on_disk` is false until the file changes again, and the next save writes
+	/// the merged database over it.
+	pub fn reload_and_merge<P: AsRef<Path>, A: AsRef<str>>(&mut self, path: P, password: A) -> Result<SyncReport, FortressError> {
+		let on_disk = Database::load_from_path(path, password)?;
+		let mut objects = self.objects.clone();
+		let mut report = SyncReport::default();
+
+		for disk_object in on_disk.objects.values() {
+			match self.objects.get(disk_object.get_id()) {
+				Some(local_object) => {
+					let merged = self.merge_server_object(local_object, disk_object.clone(), &mut report)?;
+
+					if merged != *local_object {
+						report.record(SyncActivity::Updated, &merged);
+						objects.try_update(merged)?;
+					}
+				},
+				None => {
+					report.record(SyncActivity::Added, disk_object);
+					objects.try_update(disk_object.clone())?;
+				},
+			}
+		}
+
+		self.objects = objects;
+		if let Some(version) = on_disk.file_version.get() {
+			self.file_version.set(version);
+		}
+
+		Ok(report)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		testing::{fields, new_entry},
+		Database, SyncActivity,
+	};
+	use tempfile::tempdir;
+
+	#[test]
+	fn reload_and_merge() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		assert!(db.file_changed_on_disk(&path));
+		db.add_entry(new_entry("Shared"));
+		db.save_to_path(&path).unwrap();
+		assert!(!db.file_changed_on_disk(&path));
+
+		// Another device's copy arrives through a file syncing service, with an edit and a new entry
+		let mut other = Database::load_from_path(&path, "password").unwrap();
+		assert!(!other.file_changed_on_disk(&path));
+		let shared_id = *db.list_entries().next().unwrap().get_id();
+		let shared = other.get_entry_by_id_mut(&shared_id).unwrap();
+		shared.edit(shared.new_edit(fields(&[("username", "them")])));
+		let added = new_entry("Theirs");
+		let added_id = *added.get_id();
+		other.add_entry(added);
+		other.save_to_path(&path).unwrap();
+
+		// Meanwhile this device edited the same entry
+		let shared = db.get_entry_by_id_mut(&shared_id).unwrap();
+		shared.edit(shared.new_edit(fields(&[("password", "mine")])));
+		assert!(db.file_changed_on_disk(&path));
+
+		let report = db.reload_and_merge(&path, "password").unwrap();
+		assert!(!db.file_changed_on_disk(&path));
+		let changes = report.changes.iter().map(|change| (change.activity, change.id)).collect::<Vec<_>>();
+		assert!(changes.contains(&(SyncActivity::Added, added_id)));
+		assert!(changes.contains(&(SyncActivity::Updated, shared_id)));
+		let shared = db.get_entry_by_id(&shared_id).unwrap();
+		assert_eq!(shared.get("username").map(String::as_str), Some("them"));
+		assert_eq!(shared.get("password").map(String::as_str), Some("mine"));
+		assert!(db.get_entry_by_id(&added_id).is_some());
+
+		// Saving writes the merged copy, which the other device's copy merges into the same way
+		db.save_to_path(&path).unwrap();
+		assert!(!db.file_changed_on_disk(&path));
+		assert!(other.file_changed_on_disk(&path));
+		other.reload_and_merge(&path, "password").unwrap();
+		assert_eq!(other.get_entry_by_id(&shared_id), db.get_entry_by_id(&shared_id));
+	}
+}
@@ -0,0 +1,162 @@
+// Advisory locking of database files, so that two processes (the GTK and Tauri apps, or two instances of either) can't both have
+// the same file open and silently overwrite each other's saves.  A database loaded from or saved to a path holds an OS file lock
+// (flock on *nix, LockFileEx on Windows) on `<path>.lock` until it's dropped, and loading or saving the file in another process
+// fails with FortressError::DatabaseLocked.  The database file itself isn't locked, because saving replaces it.
+//
+// Databases in the same process share the lock, so a process can still open the same file more than once.  Stale locks recover
+// by themselves: the OS releases a lock when the process holding it exits, crashes included, and a leftover `.lock` file is just
+// locked again by the next process.  Where the lock file can't be created or locked (read-only locations, some network
+// filesystems), databases are opened without a lock.
+use crate::FortressError;
+use std::{
+	collections::HashMap,
+	fs::{File, OpenOptions, TryLockError},
+	io::{self, Read, Write},
+	path::{self, Path, PathBuf},
+	process,
+	sync::{Arc, Mutex, PoisonError, Weak},
+};
+
+
+// The locks held by this process, by the absolute path of the database file
+static LOCKS: Mutex<Option<HashMap<PathBuf, Weak<FileLock>>>> = Mutex::new(None);
+
+
+#[derive(Debug)]
+pub(crate) struct FileLock {
+	path: PathBuf,
+	/// None if the file couldn't be locked; see the module comment.
+	_file: Option<File>,
+}
+
+impl FileLock {
+	/// Locks the database file at `path` for this process, or returns this process's existing lock on it.
+	pub(crate) fn acquire(path: &Path) -> Result<Arc<FileLock>, FortressError> {
+		let path = path::absolute(path)?;
+		let mut locks = LOCKS.lock().unwrap_or_else(PoisonError::into_inner);
+		let locks = locks.get_or_insert_with(HashMap::new);
+
+		if let Some(lock) = locks.get(&path).and_then(Weak::upgrade) {
+			return Ok(lock);
+		}
+
+		let lock = Arc::new(FileLock {
+			_file: lock_file(&path)?,
+			path: path.clone(),
+		});
+		locks.retain(|_, lock| lock.strong_count() > 0);
+		locks.insert(path, Arc::downgrade(&lock));
+
+		Ok(lock)
+	}
+}
+
+
+// The lock on the database file a database was last loaded from or saved to.  Not part of the database's value: it's never
+// serialized, and databases compare equal whatever it is.  Saving (`save_to_path` only borrows the database) can move it to
+// another file, hence the Mutex.
+#[derive(Debug, Default)]
+pub(crate) struct FileLockState(Mutex<Option<Arc<FileLock>>>);
+
+impl FileLockState {
+	pub(crate) fn held(lock: Arc<FileLock>) -> FileLockState {
+		FileLockState(Mutex::new(Some(lock)))
+	}
+
+	/// Locks the database file at `path`, unless this already holds the lock on it, and releases the lock on any other file.
+	pub(crate) fn lock(&self, path: &Path) -> Result<(), FortressError> {
+		let mut held = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+		let absolute_path = path::absolute(path)?;
+
+		if held.as_ref().is_none_or(|lock| lock.path != absolute_path) {
+			*held = Some(FileLock::acquire(path)?);
+		}
+
+		Ok(())
+	}
+}
+
+impl Clone for FileLockState {
+	fn clone(&self) -> FileLockState {
+		FileLockState(Mutex::new(self.0.lock().unwrap_or_else(PoisonError::into_inner).clone()))
+	}
+}
+
+impl PartialEq for FileLockState {
+	fn eq(&self, _other: &FileLockState) -> bool {
+		true
+	}
+}
+
+impl Eq for FileLockState {}
+
+
+// Opens and locks the lock file of the database file at `path`
+fn lock_file(path: &Path) -> Result<Option<File>, FortressError> {
+	let mut lock_path = path.as_os_str().to_owned();
+	lock_path.push(".lock");
+
+	let mut file = match OpenOptions::new().read(true).write(true).create(true).truncate(false).open(lock_path) {
+		Ok(file) => file,
+		Err(err) if matches!(err.kind(), io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem) => return Ok(None),
+		Err(err) => return Err(err.into()),
+	};
+
+	match file.try_lock() {
+		Ok(()) => {
+			// Only so whoever finds the file locked can tell which process has it
+			file.set_len(0)?;
+			file.write_all(process::id().to_string().as_bytes())?;
+			Ok(Some(file))
+		},
+		Err(TryLockError::WouldBlock) => {
+			let mut pid = String::new();
+			let pid = file.read_to_string(&mut pid).ok().and_then(|_| pid.trim().parse().ok());
+			Err(FortressError::DatabaseLocked { pid })
+		},
+		Err(TryLockError::Error(err)) if err.kind() == io::ErrorKind::Unsupported => Ok(None),
+		Err(TryLockError::Error(err)) => Err(err.into()),
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, FortressError};
+	use std::{fs::File, process};
+	use tempfile::tempdir;
+
+	#[test]
+	fn file_lock() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let db = Database::new_with_password("username", "password");
+		db.save_to_path(&path).unwrap();
+
+		// The same process can open the file as often as it likes
+		let loaded = Database::load_from_path(&path, "password").unwrap();
+		loaded.save_to_path(&path).unwrap();
+		db.save_to_path(&path).unwrap();
+		drop((db, loaded));
+
+		// Another process holding the lock stands in for another instance of the app
+		let other_process = File::options()
+			.read(true)
+			.write(true)
+			.open(tmp_dir.path().join("test.fortressdb.lock"))
+			.unwrap();
+		other_process.try_lock().unwrap();
+		assert!(matches!(
+			Database::load_from_path(&path, "password"),
+			Err(FortressError::DatabaseLocked { pid: Some(pid) }) if pid == process::id()
+		));
+		let db = Database::new_with_password("username", "password");
+		assert!(matches!(db.save_to_path(&path), Err(FortressError::DatabaseLocked { .. })));
+
+		// Locks are released when the process holding them exits, leaving the lock file behind
+		drop(other_process);
+		let mut loaded = Database::load_from_path(&path, "password").unwrap();
+		loaded.save_incremental(&path).unwrap();
+		assert!(tmp_dir.path().join("test.fortressdb.lock").exists());
+	}
+}
@@ -0,0 +1,232 @@
+// Deleting objects for good.  Trashing an object (see the trash module) keeps it, history and all, since anything less would be
+// brought back by the next sync with a device that still has it.  `delete_forever` and `empty_trash` instead replace trashed
+// objects with tombstones (see Tombstone), which sync like any other object, so the deletion reaches every device.  A tombstone
+// stays in the trash directory where its object was, and an object edited after it was deleted wins over its tombstone, so an
+// edit made on a device that hadn't heard of the deletion yet isn't lost: the object comes back, in the trash.
+//
+// Tombstones are small, but they'd still pile up on the sync server forever.  `sync_gc` asks the server to drop tombstones older
+// than a given number of days, with `POST /gc`.  The body is a JSON list of `[id, siv]` pairs in hex, and the server drops each
+// object whose SIV still matches, answering with a JSON list of the IDs it dropped.  Servers without the endpoint answer 404 or
+// 405, reported as FortressError::SyncGcUnsupported.  The dropped tombstones are removed locally too, and the cutoff is saved as
+// the GC_HORIZON_SETTING synced setting, so other devices drop their own copies of tombstones from before it instead of
+// uploading them again.
+//
+// A device that doesn't sync until after its objects' tombstones have been dropped never hears of the deletion, and uploads
+// those objects again the next time it syncs, so the number of days should be longer than any device goes without syncing.
+// Older versions of Fortress can't sync or load databases that have tombstones.
+use crate::{
+	api_request,
+	compaction::HistoryLossConfirmed,
+	database_object::{DatabaseObject, Tombstone},
+	sync_endpoint, unix_timestamp, ApiError, Database, FortressError, ID, TRASH_DIRECTORY_ID,
+};
+use fortresscrypto::SIV;
+use reqwest::Method;
+use std::collections::{HashMap, HashSet};
+
+
+/// Synced setting holding the time (nanoseconds) before which tombstones have been dropped from the sync server (see the gc
+/// module).  Only ever raised.
+pub const GC_HORIZON_SETTING: &str = "sync_gc_before";
+
+const DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+
+impl Database {
+	/// PERMANENTLY deletes the trashed entry or directory `id`, along with everything in it, leaving tombstones in its place
+	/// (see the gc module).  Returns false if `id` isn't directly in the trash, or was already deleted.
+	pub fn delete_forever(&mut self, id: &ID, _confirmation: HistoryLossConfirmed) -> bool {
+		if !self.get_trash().is_some_and(|trash| trash.contains(id)) {
+			return false;
+		}
+
+		let mut deleted = HashSet::new();
+		let mut pending = vec![*id];
+
+		while let Some(id) = pending.pop() {
+			let Some(object) = self.objects.get(&id).filter(|object| object.as_tombstone().is_none()) else {
+				continue;
+			};
+
+			let Some(last_edit) = object.last_edit() else {
+				continue;
+			};
+
+			if let DatabaseObject::Directory(directory) = object {
+				pending.extend(directory.entries.iter().filter(|child| !deleted.contains(*child)));
+			}
+
+			// Deleted after the last edit, even one merged in from a device whose clock is ahead, so the object stays deleted
+			let tombstone = Tombstone::new(id, unix_timestamp().max(last_edit + 1));
+
+			self.objects.replace_destructively(DatabaseObject::Tombstone(tombstone));
+			deleted.insert(id);
+		}
+
+		!deleted.is_empty()
+	}
+
+	/// PERMANENTLY deletes everything in the trash (see `delete_forever`).  Returns how many objects were directly in it.
+	pub fn empty_trash(&mut self, confirmation: HistoryLossConfirmed) -> usize {
+		self.list_trash()
+			.into_iter()
+			.filter(|object| self.delete_forever(&object.id, confirmation))
+			.count()
+	}
+
+	pub fn list_tombstones(&self) -> impl Iterator<Item = &Tombstone> {
+		self.objects.values().filter_map(DatabaseObject::as_tombstone)
+	}
+
+	/// Syncs, then asks the sync server to drop tombstones from more than `days` days ago, and drops them locally too (see the
+	/// gc module).  Syncs again afterwards, so other devices learn where the server's tombstones stop.  Returns how many
+	/// tombstones were dropped.  Fails with FortressError::SyncGcUnsupported if the server can't drop objects.
+	pub fn sync_gc(&mut self, days: u64) -> Result<usize, FortressError> {
+		self.sync()?;
+		self.check_sync_writable()?;
+
+		let url = self.sync_url.clone().ok_or(FortressError::SyncBadUrl)?;
+		let client = self.sync_client()?;
+		let before = unix_timestamp().saturating_sub(days.saturating_mul(DAY));
+
+		// Only tombstones the server has exactly as we do, so nothing that changed since is dropped
+		let server_objects = self.sync_api_list_objects(&client, &url)?.into_iter().collect::<HashMap<_, _>>();
+		let candidates = self
+			.list_tombstones()
+			.filter(|tombstone| tombstone.get_time() < before)
+			.filter_map(|tombstone| {
+				let siv = server_objects.get(tombstone.get_id())?;
+				let encrypted = self.encrypt_object(&DatabaseObject::Tombstone(tombstone.clone()));
+				(encrypted.siv == *siv).then_some((*tombstone.get_id(), *siv))
+			})
+			.collect::<Vec<(ID, SIV)>>();
+
+		if candidates.is_empty() {
+			return Ok(0);
+		}
+
+		let response = api_request(
+			&client,
+			self.sync_parameters.get_login_id(),
+			self.sync_parameters.get_login_key().expect("internal error"),
+			Method::POST,
+			sync_endpoint(&url, "/gc")?,
+			serde_json::to_vec(&candidates).expect("internal error"),
+		)
+		.map_err(|err| match err {
+			FortressError::SyncApiError(ApiError::ApiError(404 | 405, _)) => FortressError::SyncGcUnsupported,
+			err => err,
+		})?;
+		let dropped: Vec<ID> = serde_json::from_slice(&response.bytes().map_err(ApiError::from)?).map_err(|_| FortressError::SyncMalformedResponse)?;
+
+		let candidates = candidates.into_iter().map(|(id, _)| id).collect::<HashSet<_>>();
+		let dropped = dropped
+			.into_iter()
+			.filter(|id| candidates.contains(id))
+			.filter(|id| self.drop_tombstone(id))
+			.count();
+
+		if self.get_gc_horizon() < before {
+			self.set_setting(GC_HORIZON_SETTING, serde_json::Value::from(before));
+		}
+
+		self.sync()?;
+
+		Ok(dropped)
+	}
+
+	/// The time before which tombstones have been dropped from the sync server (see GC_HORIZON_SETTING).  0 if they never have.
+	pub fn get_gc_horizon(&self) -> u64 {
+		self.get_settings()
+			.and_then(|settings| settings.get(GC_HORIZON_SETTING))
+			.and_then(serde_json::Value::as_u64)
+			.unwrap_or(0)
+	}
+
+	/// Removes tombstone `id` from the database and the trash directory.  Returns false if `id` isn't a tombstone.
+	pub(crate) fn drop_tombstone(&mut self, id: &ID) -> bool {
+		if !self.objects.remove_tombstone(id) {
+			return false;
+		}
+
+		if let Some(trash) = self.get_directory_by_id_mut(&TRASH_DIRECTORY_ID).filter(|trash| trash.contains(id)) {
+			trash.remove(*id);
+		}
+
+		// The journal can only add objects, so the next incremental save has to save everything
+		self.journal = Default::default();
+		true
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		database_object::{DatabaseObject, Tombstone},
+		Database, Directory, Entry, EntryHistory, HistoryLossConfirmed,
+	};
+	use std::collections::HashMap;
+
+	#[test]
+	fn delete_forever() {
+		let mut db = Database::new_with_password("username", "password");
+		let directory = Directory::new();
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+		let (entry, nested) = (Entry::new(), Entry::new());
+		let (entry_id, nested_id) = (*entry.get_id(), *nested.get_id());
+		db.add_entry(entry);
+		db.add_entry(nested);
+		db.move_object(&nested_id, &directory_id);
+
+		// Only what's directly in the trash
+		assert!(!db.delete_forever(&entry_id, HistoryLossConfirmed));
+		assert!(db.trash_object(&directory_id));
+		assert!(!db.delete_forever(&nested_id, HistoryLossConfirmed));
+		assert!(db.trash_object(&entry_id));
+
+		assert!(db.delete_forever(&directory_id, HistoryLossConfirmed));
+		assert!(!db.delete_forever(&directory_id, HistoryLossConfirmed));
+		assert!(db.get_directory_by_id(&directory_id).is_none());
+		assert!(db.get_entry_by_id(&nested_id).is_none());
+		assert_eq!(db.list_tombstones().count(), 2);
+		assert_eq!(db.list_trash().iter().map(|object| object.id).collect::<Vec<_>>(), [entry_id]);
+		assert!(!db.restore_object(&directory_id));
+		assert!(db.check_integrity().is_ok());
+
+		assert_eq!(db.empty_trash(HistoryLossConfirmed), 1);
+		assert!(db.get_entry_by_id(&entry_id).is_none());
+		assert!(db.list_trash().is_empty());
+		assert_eq!(db.list_tombstones().count(), 3);
+
+		// Dropping a tombstone takes it out of the trash
+		let root_id = *db.get_root().get_id();
+		assert!(!db.drop_tombstone(&root_id));
+		assert!(db.drop_tombstone(&entry_id));
+		assert!(!db.get_trash().unwrap().contains(&entry_id));
+		assert_eq!(db.list_tombstones().count(), 2);
+		assert!(db.check_integrity().is_ok());
+	}
+
+	// A deletion wins over the object it deleted, unless the object was edited after it
+	#[test]
+	fn tombstone_merges() {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(HashMap::from([("title".to_string(), "Old".to_string())])));
+		let object = DatabaseObject::Entry(entry.clone());
+		let tombstone = Tombstone::new(*entry.get_id(), entry.get_history().last().unwrap().time + 1);
+		assert!(!object.revived_after(&tombstone));
+
+		let mut edited = entry.clone();
+		edited.edit(EntryHistory::new(HashMap::from([("title".to_string(), "New".to_string())])));
+		assert!(DatabaseObject::Entry(edited).revived_after(&tombstone));
+
+		let later = Tombstone::new(*entry.get_id(), tombstone.get_time() + 10);
+		assert_eq!(tombstone.merge(&later), Some(later.clone()));
+		assert_eq!(later.merge(&tombstone), Some(later.clone()));
+		assert!(tombstone.safe_to_replace_with(&later));
+		assert!(!later.safe_to_replace_with(&tombstone));
+		assert_eq!(tombstone.merge(&Tombstone::new(*Entry::new().get_id(), 0)), None);
+	}
+}
@@ -0,0 +1,342 @@
+// Options for generating random passwords, so callers like `Entry::rotate_password` can take them as one value.  Frontends keep
+// their presets in the synced settings, in whatever shape they like.
+//
+// Passwords are drawn from the union of the selected character classes (uppercase, lowercase, numbers, and `others`), with
+// duplicates removed so characters in `others` that are also in a selected class aren't more likely.  Constraints such as
+// `require_each_selected_class` are met by drawing again until a password meets them, rather than placing characters, so every
+// password that meets them is equally likely.  Options that no password can meet fail with FortressError::GeneratorUnsatisfiable.
+use crate::FortressError;
+use rand::{rngs::OsRng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+
+/// Characters left out by `GeneratorOptions::exclude_ambiguous`, since they're easily mistaken for each other.
+pub const AMBIGUOUS_CHARACTERS: &str = "0O1lI";
+/// Generated passwords with fewer bits of entropy than this (see `GeneratorOptions::estimate_entropy`) are worth warning about.
+pub const WEAK_ENTROPY_BITS: f64 = 64.0;
+/// Longest password frontends should ask for, so a request can't have the generator allocate without limit.  The app's length
+/// field goes up to this too.
+pub const MAX_GENERATED_LENGTH: usize = 1000;
+
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const NUMBERS: &str = "0123456789";
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct GeneratorOptions {
+	pub length: usize,
+	pub uppercase: bool,
+	pub lowercase: bool,
+	pub numbers: bool,
+	/// Other characters to use, such as symbols.
+	pub others: String,
+	/// Leave out `AMBIGUOUS_CHARACTERS`, including from `others`.
+	#[serde(default)]
+	pub exclude_ambiguous: bool,
+	/// Use at least one character from each selected class.  Characters in `others` that are in a selected class count as that
+	/// class, and the rest (if any) as a class of their own.
+	#[serde(default)]
+	pub require_each_selected_class: bool,
+	/// Use each character at most once.
+	#[serde(default)]
+	pub no_repeated_characters: bool,
+}
+
+impl Default for GeneratorOptions {
+	fn default() -> GeneratorOptions {
+		GeneratorOptions {
+			length: 20,
+			uppercase: true,
+			lowercase: true,
+			numbers: true,
+			others: String::new(),
+			exclude_ambiguous: false,
+			require_each_selected_class: false,
+			no_repeated_characters: false,
+		}
+	}
+}
+
+impl GeneratorOptions {
+	/// A random password with these options (see the generator module).  Fails with FortressError::GeneratorUnsatisfiable if
+	/// there's no such password, e.g. because `length` is shorter than the number of classes required.  A `length` of 0 gives
+	/// an empty password.
+	pub fn generate(&self) -> Result<String, FortressError> {
+		if self.length == 0 {
+			return Ok(String::new());
+		}
+
+		let classes = self.satisfiable_classes()?;
+		let alphabet = classes.concat();
+
+		loop {
+			let password: Vec<char> = if self.no_repeated_characters {
+				alphabet.choose_multiple(&mut OsRng, self.length).copied().collect()
+			} else {
+				(0..self.length).map(|_| *alphabet.choose(&mut OsRng).expect("internal error")).collect()
+			};
+
+			if !self.require_each_selected_class || classes.iter().all(|class| class.iter().any(|c| password.contains(c))) {
+				return Ok(password.into_iter().collect());
+			}
+		}
+	}
+
+	/// Bits of entropy in the passwords `generate` gives with these options: log2 of how many different passwords it can give,
+	/// since they're all equally likely.  Characters in `others` that are repeated or already selected only count once.
+	/// Fails like `generate` does.
+	pub fn estimate_entropy(&self) -> Result<f64, FortressError> {
+		if self.length == 0 {
+			return Ok(0.0);
+		}
+
+		let classes = self.satisfiable_classes()?;
+		let alphabet_size = classes.iter().map(Vec::len).sum::<usize>();
+
+		// log2 of how many passwords there are from an alphabet of `size` characters, ignoring the required classes
+		let log2_count = |size: usize| -> f64 {
+			if self.no_repeated_characters {
+				(0..self.length).map(|i| ((size - i) as f64).log2()).sum()
+			} else {
+				self.length as f64 * (size as f64).log2()
+			}
+		};
+
+		if !self.require_each_selected_class {
+			return Ok(log2_count(alphabet_size));
+		}
+
+		// Inclusion-exclusion over the classes a password might be missing, with each term relative to the total, since the
+		// counts themselves don't fit in any number type
+		let mut fraction = 0.0;
+		for missing in 0..(1u32 << classes.len()) {
+			let missing_size = classes
+				.iter()
+				.enumerate()
+				.filter(|(i, _)| missing & (1 << i) != 0)
+				.map(|(_, class)| class.len())
+				.sum::<usize>();
+			let size = alphabet_size - missing_size;
+			let term = if size == 0 || (self.no_repeated_characters && size < self.length) {
+				0.0
+			} else {
+				(log2_count(size) - log2_count(alphabet_size)).exp2()
+			};
+
+			fraction += if missing.count_ones() % 2 == 0 { term } else { -term };
+		}
+
+		Ok(log2_count(alphabet_size) + fraction.log2())
+	}
+
+	/// The selected classes (see `classes`), or FortressError::GeneratorUnsatisfiable if no password of `length` can meet the
+	/// options.
+	fn satisfiable_classes(&self) -> Result<Vec<Vec<char>>, FortressError> {
+		let classes = self.classes();
+		let alphabet_size = classes.iter().map(Vec::len).sum::<usize>();
+
+		if alphabet_size == 0
+			|| (self.no_repeated_characters && self.length > alphabet_size)
+			|| (self.require_each_selected_class && self.length < classes.len())
+		{
+			return Err(FortressError::GeneratorUnsatisfiable);
+		}
+
+		Ok(classes)
+	}
+
+	/// The selected classes' characters, without ambiguous characters if they're excluded.  They don't overlap: `others` only
+	/// has the characters that aren't in another selected class, and is left out if that's none of them.
+	fn classes(&self) -> Vec<Vec<char>> {
+		let keep = |c: &char| !(self.exclude_ambiguous && AMBIGUOUS_CHARACTERS.contains(*c));
+		let mut classes = [(self.uppercase, UPPERCASE), (self.lowercase, LOWERCASE), (self.numbers, NUMBERS)]
+			.into_iter()
+			.filter(|(selected, _)| *selected)
+			.map(|(_, class)| class.chars().filter(keep).collect::<Vec<_>>())
+			.collect::<Vec<_>>();
+
+		let mut others = Vec::new();
+		for c in self.others.chars().filter(keep) {
+			if !others.contains(&c) && !classes.iter().any(|class| class.contains(&c)) {
+				others.push(c);
+			}
+		}
+		if !others.is_empty() {
+			classes.push(others);
+		}
+
+		classes
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{GeneratorOptions, AMBIGUOUS_CHARACTERS};
+	use crate::FortressError;
+
+	fn selected(length: usize, uppercase: bool, lowercase: bool, numbers: bool, others: &str) -> GeneratorOptions {
+		GeneratorOptions {
+			length,
+			uppercase,
+			lowercase,
+			numbers,
+			others: others.to_string(),
+			..GeneratorOptions::default()
+		}
+	}
+
+	#[test]
+	fn exclude_ambiguous() {
+		let options = GeneratorOptions {
+			exclude_ambiguous: true,
+			..selected(10000, true, true, true, "!|")
+		};
+		let password = options.generate().unwrap();
+		assert!(!password.contains(|c| AMBIGUOUS_CHARACTERS.contains(c)));
+		assert!(password.contains('!') && password.contains('|') && password.contains('L'));
+
+		// Nothing left to use
+		let options = GeneratorOptions {
+			exclude_ambiguous: true,
+			..selected(5, false, false, false, "0O")
+		};
+		assert!(matches!(options.generate(), Err(FortressError::GeneratorUnsatisfiable)));
+	}
+
+	#[test]
+	fn require_each_selected_class() {
+		let options = GeneratorOptions {
+			require_each_selected_class: true,
+			..selected(4, true, true, true, "#")
+		};
+
+		for _ in 0..100 {
+			let password = options.generate().unwrap();
+			assert!(password.contains(|c: char| c.is_ascii_uppercase()));
+			assert!(password.contains(|c: char| c.is_ascii_lowercase()));
+			assert!(password.contains(|c: char| c.is_ascii_digit()));
+			assert!(password.contains('#'));
+		}
+
+		// Too short for every class
+		let options = GeneratorOptions { length: 3, ..options };
+		assert!(matches!(options.generate(), Err(FortressError::GeneratorUnsatisfiable)));
+
+		// Others that are all in another class aren't a class of their own
+		let options = GeneratorOptions {
+			require_each_selected_class: true,
+			..selected(2, true, true, false, "abc")
+		};
+		assert!(options.generate().is_ok());
+
+		// Nor are others that are all ambiguous, when those are excluded
+		let options = GeneratorOptions {
+			require_each_selected_class: true,
+			exclude_ambiguous: true,
+			..selected(1, true, false, false, "0")
+		};
+		assert!(options.generate().unwrap().chars().all(|c| c.is_ascii_uppercase()));
+	}
+
+	#[test]
+	fn no_repeated_characters() {
+		let options = GeneratorOptions {
+			no_repeated_characters: true,
+			..selected(10, false, false, true, "")
+		};
+
+		for _ in 0..100 {
+			let mut password = options.generate().unwrap().chars().collect::<Vec<_>>();
+			password.sort();
+			assert_eq!(password.into_iter().collect::<String>(), "0123456789");
+		}
+
+		let options = GeneratorOptions { length: 11, ..options };
+		assert!(matches!(options.generate(), Err(FortressError::GeneratorUnsatisfiable)));
+
+		// Duplicates in others don't count twice
+		let options = GeneratorOptions {
+			no_repeated_characters: true,
+			..selected(3, false, false, false, "aab")
+		};
+		assert!(matches!(options.generate(), Err(FortressError::GeneratorUnsatisfiable)));
+	}
+
+	#[test]
+	fn estimate_entropy() {
+		let assert_bits = |options: GeneratorOptions, expected: f64| {
+			let bits = options.estimate_entropy().unwrap();
+			assert!((bits - expected).abs() < 1e-6, "{bits} != {expected}");
+		};
+
+		// 62 characters; "a" and the repeated "$" only count once
+		assert_bits(selected(20, true, true, true, ""), 20.0 * 62f64.log2());
+		assert_bits(selected(20, true, true, true, "a$$"), 20.0 * 63f64.log2());
+		assert_bits(
+			GeneratorOptions {
+				exclude_ambiguous: true,
+				..selected(20, true, true, true, "")
+			},
+			20.0 * 57f64.log2(),
+		);
+		assert_bits(
+			GeneratorOptions {
+				no_repeated_characters: true,
+				..selected(3, false, false, true, "")
+			},
+			720f64.log2(),
+		);
+
+		// 2 characters long, with one of each of "a" and "0": a0 and 0a
+		assert_bits(
+			GeneratorOptions {
+				require_each_selected_class: true,
+				..selected(2, false, false, true, "a")
+			},
+			(10.0f64 * 2.0).log2(),
+		);
+		// 3 long from the numbers and "ab": 1728 passwords, less the 1000 without "a" or "b" and the 8 without a number
+		assert_bits(
+			GeneratorOptions {
+				require_each_selected_class: true,
+				..selected(3, false, false, true, "ab")
+			},
+			720f64.log2(),
+		);
+		assert_bits(
+			GeneratorOptions {
+				require_each_selected_class: true,
+				no_repeated_characters: true,
+				length: 2,
+				..selected(2, false, false, false, "ab")
+			},
+			2f64.log2(),
+		);
+
+		// Requiring every class costs little for long passwords
+		let bits = GeneratorOptions {
+			require_each_selected_class: true,
+			..selected(100, true, true, true, "#")
+		}
+		.estimate_entropy()
+		.unwrap();
+		assert!(bits < 100.0 * 63f64.log2() && bits > 100.0 * 63f64.log2() - 1.0);
+
+		assert_eq!(selected(0, true, true, true, "").estimate_entropy().unwrap(), 0.0);
+		assert!(matches!(
+			selected(1, false, false, false, "").estimate_entropy(),
+			Err(FortressError::GeneratorUnsatisfiable)
+		));
+	}
+
+	#[test]
+	fn empty() {
+		assert_eq!(selected(0, false, false, false, "").generate().unwrap(), "");
+		assert!(matches!(
+			selected(1, false, false, false, "").generate(),
+			Err(FortressError::GeneratorUnsatisfiable)
+		));
+	}
+}
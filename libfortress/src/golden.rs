@@ -0,0 +1,188 @@
+// Golden databases: database files saved by earlier versions of this crate, kept in tests/data/golden, that every later
+// version has to keep loading unchanged.  A golden file's contents come from `golden_database`, which builds the same database
+// every time from a fixed seed, clock, username and password (see the time_source module), so the golden test can check that loading
+// the file gives back exactly the objects that were saved, instead of a few spot checks.
+//
+// Every format version from fortress2 on has a golden file, named after it (see `golden_file_name`).  When the format changes,
+// add the new version to GOLDEN_VERSIONS, add whatever the new version can hold to `golden_database` for it, and run
+//
+//     cargo run -p libfortress --example regenerate_golden --features testing
+//
+// to write its file.  Once committed, a version's file is never regenerated and what `golden_database` builds for it never
+// changes, since the file stands in for databases users already have.  Regenerating writes different bytes each time (the
+// database's share key is random), but the same contents.
+//
+// fortress1 predates golden files; tests/data/legacy.fortress1 covers it (see the legacy module).
+//
+// Built for this crate's tests, and for others with the `testing` feature.
+use crate::{
+	database_object::DatabaseObject, testing::fields, Database, Directory, Entry, EntryHistory, FieldKind, FieldMetadata, FormatVersion, SettingsObject,
+	SteppingClock, SyncParameters, TimeSource, ENTRY_TYPE_FIELD, NOTE_ENTRY_TYPE, NOTE_FORMAT_FIELD,
+};
+use fortresscrypto::{FileKdfParameters, FileKeySuite};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::collections::HashMap;
+
+
+pub const GOLDEN_USERNAME: &str = "golden";
+pub const GOLDEN_PASSWORD: &str = "golden password";
+/// Format versions that have a golden file, oldest first.
+pub const GOLDEN_VERSIONS: &[FormatVersion] = &[FormatVersion::Fortress2];
+const GOLDEN_SEED: u64 = 0x676f6c64656e;
+/// 2020-01-01, in nanoseconds since the Unix epoch.
+const GOLDEN_START_TIME: u64 = 1_577_836_800_000_000_000;
+
+
+/// The golden file's name for `version`, e.g. "fortress2.fortress".
+pub fn golden_file_name(version: FormatVersion) -> String {
+	format!("{}.fortress", format!("{version:?}").to_lowercase())
+}
+
+
+/// The database saved in `version`'s golden file (see the golden module): a few directories, entries with several edits,
+/// field metadata and tags, a secure note, and a setting.  Panics if `version` has no golden file.
+pub fn golden_database(version: FormatVersion) -> Database {
+	assert!(GOLDEN_VERSIONS.contains(&version), "{version:?} has no golden file");
+
+	let clock = SteppingClock::new(GOLDEN_START_TIME, 1_000_000_000);
+	let mut rng = ChaCha20Rng::seed_from_u64(GOLDEN_SEED);
+	let file_kdf_parameters = FileKdfParameters {
+		log_n: 8,
+		r: 8,
+		p: 1,
+		salt: rng.gen(),
+		hardware_challenge: None,
+	};
+	let file_key_suite = FileKeySuite::derive(GOLDEN_PASSWORD.as_bytes(), &file_kdf_parameters).expect("internal error");
+	let mut database = Database::new_with_keys(file_key_suite, SyncParameters::new(GOLDEN_USERNAME, GOLDEN_PASSWORD));
+
+	let mut root = Directory::new_root();
+	root.rename_with_time("My Passwords", clock.now());
+	let mut work = Directory::new_with(&mut rng);
+	work.rename_with_time("Work", clock.now());
+	let mut servers = Directory::new_with(&mut rng);
+	servers.rename_with_time("Servers", clock.now());
+	servers.rename_with_time("Infrastructure", clock.now());
+
+	let mut email = Entry::new_with(&clock, &mut rng);
+	email.edit(
+		EntryHistory::new_with(
+			fields(&[
+				("title", "Email"),
+				("username", "alice@example.com"),
+				("password", "hunter2"),
+				("url", "https://mail.example.com"),
+				("recovery codes", "1234-5678\n9012-3456"),
+			]),
+			&clock,
+		)
+		.with_metadata("recovery codes", metadata(FieldKind::Secret, true))
+		.with_field_order(["title", "username", "password", "url", "recovery codes"].map(String::from).to_vec()),
+	);
+	email.edit(EntryHistory::new_with(fields(&[("password", "correct horse battery staple")]), &clock));
+	email.edit(EntryHistory::new_with(HashMap::new(), &clock).with_tag("work", true).with_tag("email", true));
+	email.edit(EntryHistory::new_with(HashMap::new(), &clock).with_tag("email", false));
+
+	let mut server = Entry::new_with(&clock, &mut rng);
+	server.edit(
+		EntryHistory::new_with(
+			fields(&[
+				("title", "Build server"),
+				("username", "root"),
+				("password", "p@ss\"word'\\"),
+				("otpauth", "otpauth://totp/build?secret=JBSWY3DPEHPK3PXP"),
+				("host", "build.internal:2222"),
+			]),
+			&clock,
+		)
+		.with_metadata("otpauth", metadata(FieldKind::Totp, true))
+		.with_metadata("host", metadata(FieldKind::Url, false)),
+	);
+
+	let mut note = Entry::new_with(&clock, &mut rng);
+	note.edit(EntryHistory::new_with(
+		fields(&[
+			("title", "Wi-Fi — Café ☕"),
+			("notes", "# Network\n\nSSID: `café`\nPassword: ünïcödé"),
+			(ENTRY_TYPE_FIELD, NOTE_ENTRY_TYPE),
+			(NOTE_FORMAT_FIELD, "markdown"),
+		]),
+		&clock,
+	));
+
+	let mut settings = SettingsObject::new();
+	settings.set_with_time("generator_length", 24.into(), clock.now()).expect("internal error");
+	settings.set_with_time("generator_length", 32.into(), clock.now()).expect("internal error");
+
+	// The email entry starts out in the root directory and moves to Work
+	root.add_with_time(*email.get_id(), clock.now());
+	root.add_with_time(*note.get_id(), clock.now());
+	root.add_with_time(*work.get_id(), clock.now());
+	work.add_with_time(*servers.get_id(), clock.now());
+	servers.add_with_time(*server.get_id(), clock.now());
+	root.remove_with_time(*email.get_id(), clock.now());
+	work.add_with_time(*email.get_id(), clock.now());
+
+	for object in [
+		DatabaseObject::Directory(root),
+		DatabaseObject::Directory(work),
+		DatabaseObject::Directory(servers),
+		DatabaseObject::Entry(email),
+		DatabaseObject::Entry(server),
+		DatabaseObject::Entry(note),
+		DatabaseObject::Settings(settings),
+	] {
+		database.objects.update(object);
+	}
+
+	database
+}
+
+
+fn metadata(kind: FieldKind, protected: bool) -> FieldMetadata {
+	FieldMetadata {
+		kind,
+		protected,
+		derived: None,
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{golden_database, golden_file_name, GOLDEN_PASSWORD, GOLDEN_VERSIONS};
+	use crate::{Database, CURRENT_FORMAT_VERSION};
+	use std::{fs, io::Cursor, path::Path};
+
+	#[test]
+	fn golden_files() {
+		assert!(
+			GOLDEN_VERSIONS.contains(&CURRENT_FORMAT_VERSION),
+			"the current format version needs a golden file (see the golden module)"
+		);
+
+		for &version in GOLDEN_VERSIONS {
+			let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/golden").join(golden_file_name(version));
+			// Not load_from_path, which would leave a lock file next to it
+			let data = fs::read(&path).unwrap_or_else(|err| panic!("failed to read {}: {err}", path.display()));
+			let loaded = Database::load_from_reader(GOLDEN_PASSWORD, &mut Cursor::new(data)).unwrap();
+			let expected = golden_database(version);
+
+			assert_eq!(loaded.get_original_format_version(), version);
+			assert_eq!(loaded.objects.len(), expected.objects.len(), "{version:?}");
+			for object in expected.objects.values() {
+				assert_eq!(loaded.objects.get(object.get_id()), Some(object), "{version:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn golden_database_is_reproducible() {
+		for &version in GOLDEN_VERSIONS {
+			let (a, b) = (golden_database(version), golden_database(version));
+			assert_eq!(a.objects.len(), b.objects.len());
+			assert!(a.objects.values().all(|object| b.objects.get(object.get_id()) == Some(object)));
+		}
+	}
+}
@@ -0,0 +1,123 @@
+// Databases that need a hardware key, like a YubiKey, as well as the password to open.  The file's header holds a random challenge,
+// and the key's response to it (HMAC-SHA1 challenge-response, or a FIDO2 key's hmac-secret) is mixed into the file keys (see
+// fortresscrypto's `FileKeySuite::derive_with_hardware_key`).  There is no way to open such a file with the password alone; losing
+// the hardware key means losing the file, unless it's restored from the sync server.
+//
+// Talking to the hardware key is left to the caller, which passes a callback that's given the challenge.  The hardware key only
+// protects the local file: the sync keys are still derived from the username and password alone.
+use crate::{journal, Database, FortressError};
+use fortresscrypto::{CryptoError, FileKdfParameters, FileKeySuite, HardwareKeyFn};
+use rand::{rngs::OsRng, Rng};
+use std::{
+	io::{self, Cursor},
+	path::Path,
+};
+
+
+impl Database {
+	/// Same as `load_from_reader`, but `hardware_key` is asked for the hardware key's response if the file needs one.  Files
+	/// that need one fail to load with CryptoError::HardwareKeyRequired if `hardware_key` returns None.
+	pub fn load_from_reader_with_hardware_key<P: AsRef<str>, R: io::Read>(
+		password: P,
+		reader: &mut R,
+		hardware_key: &HardwareKeyFn,
+	) -> Result<Database, FortressError> {
+		crate::load_from_reader(password.as_ref(), reader, false, hardware_key)
+	}
+
+	/// Same as `load_from_path`, but with a hardware key; see `load_from_reader_with_hardware_key`.
+	pub fn load_from_path_with_hardware_key<P: AsRef<Path>, A: AsRef<str>>(
+		path: P,
+		password: A,
+		hardware_key: &HardwareKeyFn,
+	) -> Result<Database, FortressError> {
+		let password = password.as_ref();
+
+		journal::load_with_journal(path.as_ref(), Some(password), |data| {
+			Self::load_from_reader_with_hardware_key(password, &mut Cursor::new(data), hardware_key)
+		})
+	}
+
+	/// True if the database file needs a hardware key to open.
+	pub fn needs_hardware_key(&self) -> bool {
+		self.file_key_suite.get_kdf_params().hardware_challenge.is_some()
+	}
+
+	/// Makes the database file need the hardware key that answers `hardware_key` (with a new challenge) as well as `password`
+	/// from the next save on, or only `password` if `hardware_key` is None.  `password` must be the database's current password.
+	pub fn set_hardware_key<P: AsRef<str>>(&mut self, password: P, hardware_key: Option<&HardwareKeyFn>) -> Result<(), FortressError> {
+		let password = password.as_ref().as_bytes();
+
+		// See the users module
+		if !self.file_key_suite.get_keyslots().is_empty() {
+			return Err(CryptoError::HardwareKeyWithKeyslots.into());
+		}
+
+		if self.file_key_suite.rederive(password, self.file_key_suite.get_kdf_params())? != self.file_key_suite {
+			return Err(FortressError::CryptoError(CryptoError::DecryptionError));
+		}
+
+		self.file_key_suite = match hardware_key {
+			Some(hardware_key) => {
+				let params = FileKdfParameters {
+					hardware_challenge: Some(OsRng.gen()),
+					..Default::default()
+				};
+				FileKeySuite::derive_with_hardware_key(password, &params, hardware_key)?
+			},
+			None => FileKeySuite::derive(password, &Default::default())?,
+		};
+
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, FortressError};
+	use fortresscrypto::{CryptoError, Zeroizing};
+	use tempfile::tempdir;
+
+	// Stands in for a YubiKey's HMAC-SHA1 challenge-response
+	fn yubikey(secret: u8) -> impl Fn(&[u8]) -> Option<Zeroizing<Vec<u8>>> {
+		move |challenge| Some(Zeroizing::new(challenge.iter().take(20).map(|b| b ^ secret).collect()))
+	}
+
+	#[test]
+	fn hardware_key() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		assert!(!db.needs_hardware_key());
+
+		assert!(matches!(
+			db.set_hardware_key("wrong", Some(&yubikey(1))),
+			Err(FortressError::CryptoError(CryptoError::DecryptionError))
+		));
+		db.set_hardware_key("password", Some(&yubikey(1))).unwrap();
+		assert!(db.needs_hardware_key());
+		db.save_to_path(&path).unwrap();
+
+		// The password alone isn't enough, and neither is the wrong hardware key
+		assert!(matches!(
+			Database::load_from_path(&path, "password"),
+			Err(FortressError::CryptoError(CryptoError::HardwareKeyRequired))
+		));
+		assert!(Database::load_from_path_with_hardware_key(&path, "password", &yubikey(2)).is_err());
+		assert!(Database::load_from_path_with_hardware_key(&path, "password", &|_| None).is_err());
+		let mut loaded = Database::load_from_path_with_hardware_key(&path, "password", &yubikey(1)).unwrap();
+		assert_eq!(loaded, db);
+
+		// Changing the password keeps the hardware key, without asking it again
+		loaded.change_password("username", "new password").unwrap();
+		loaded.save_to_path(&path).unwrap();
+		assert!(Database::load_from_path(&path, "new password").is_err());
+		let mut loaded = Database::load_from_path_with_hardware_key(&path, "new password", &yubikey(1)).unwrap();
+		assert!(loaded.needs_hardware_key());
+
+		loaded.set_hardware_key("new password", None).unwrap();
+		loaded.save_to_path(&path).unwrap();
+		assert!(!Database::load_from_path(&path, "new password").unwrap().needs_hardware_key());
+	}
+}
@@ -0,0 +1,137 @@
+// Which IDs objects may have.  Object IDs are random, except for the objects a database has at most one of, which have fixed
+// IDs so that separately created copies merge: the root directory, the trash, and the settings.  IDs whose bytes are all 0x00,
+// or all the same byte from 0xf0 up, are reserved for such objects, the unused ones for objects to come.  (Other repeated bytes
+// aren't, since older test databases use IDs like that.)  Random IDs land there with negligible probability, so they aren't
+// checked when objects are created.
+//
+// A reserved ID may only be used by the kind of object it's reserved for, and the settings object only by its own ID.  Objects
+// from the sync server and database files are checked against this, so a malicious server can't pass off, say, an entry as
+// the root directory.  Objects whose ID is already used by a different kind of object fail with
+// FortressError::SyncObjectTypeMismatch when they're merged (see `DatabaseObjectMap::try_update`).
+//
+// Objects from anywhere else outside the database (imports, shared bundles, paper backups) are checked the same way when
+// they're added with `Database::insert_imported_object`, since a database holding one would fail to load once saved.
+use crate::{database_object::DatabaseObject, Database, FortressError, ID, ROOT_DIRECTORY_ID, SETTINGS_ID, TRASH_DIRECTORY_ID};
+
+
+/// True if `id` is in the reserved namespace (see the id_policy module).
+pub fn is_reserved_id(id: &ID) -> bool {
+	let bytes = &id[..];
+	(bytes[0] == 0x00 || bytes[0] >= 0xf0) && bytes.iter().all(|byte| *byte == bytes[0])
+}
+
+
+/// Fails with FortressError::ReservedIdViolation if `object` has a reserved ID that isn't its kind's, or is the settings
+/// object without its reserved ID.
+pub(crate) fn check_object_id(object: &DatabaseObject) -> Result<(), FortressError> {
+	let id = *object.get_id();
+	let allowed = match object {
+		DatabaseObject::Entry(_) => !is_reserved_id(&id),
+		DatabaseObject::Directory(_) => !is_reserved_id(&id) || id == ROOT_DIRECTORY_ID || id == TRASH_DIRECTORY_ID,
+		DatabaseObject::Settings(_) => id == SETTINGS_ID,
+		DatabaseObject::Tombstone(_) => !is_reserved_id(&id),
+	};
+
+	if allowed {
+		Ok(())
+	} else {
+		Err(FortressError::ReservedIdViolation(id))
+	}
+}
+
+
+/// Fails with FortressError::ReservedIdViolation if an entry can't have `id`, for entries that are checked before they're
+/// decoded.
+pub(crate) fn check_entry_id(id: &ID) -> Result<(), FortressError> {
+	if is_reserved_id(id) {
+		Err(FortressError::ReservedIdViolation(*id))
+	} else {
+		Ok(())
+	}
+}
+
+
+impl Database {
+	/// Adds, or merges into its existing copy, an object that came from outside the database, without adding it to a directory.
+	/// Fails with FortressError::ReservedIdViolation if `check_object_id` rejects it, or like `DatabaseObjectMap::try_update`.
+	/// Every importer adds its objects through this.
+	pub(crate) fn insert_imported_object(&mut self, object: DatabaseObject) -> Result<(), FortressError> {
+		check_object_id(&object)?;
+		self.objects.try_update(object)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{check_object_id, is_reserved_id};
+	use crate::{
+		database_object::DatabaseObject, database_object_map::DatabaseObjectMap, Database, Directory, Entry, FortressError, SettingsObject, ID,
+		ROOT_DIRECTORY_ID, SETTINGS_ID, TRASH_DIRECTORY_ID,
+	};
+
+	// `object` serialized with its ID replaced by `id`
+	fn with_id(object: DatabaseObject, id: ID) -> serde_json::Value {
+		let mut json = serde_json::to_value(object).unwrap();
+		json["id"] = serde_json::to_value(id).unwrap();
+		json
+	}
+
+	fn check(json: &serde_json::Value) -> Result<(), FortressError> {
+		check_object_id(&serde_json::from_value(json.clone()).unwrap())
+	}
+
+	#[test]
+	fn reserved_ids() {
+		for id in [ROOT_DIRECTORY_ID, TRASH_DIRECTORY_ID, SETTINGS_ID, ID([0xf7; 32])] {
+			assert!(is_reserved_id(&id));
+		}
+		assert!(!is_reserved_id(&ID([0x11; 32])));
+		let mut bytes = [0; 32];
+		bytes[31] = 1;
+		assert!(!is_reserved_id(&ID(bytes)));
+
+		let entry = DatabaseObject::Entry(Entry::new());
+		let directory = DatabaseObject::Directory(Directory::new());
+		let settings = DatabaseObject::Settings(SettingsObject::new());
+
+		assert!(check_object_id(&entry).is_ok());
+		assert!(check_object_id(&directory).is_ok());
+		assert!(check_object_id(&settings).is_ok());
+		assert!(check_object_id(&DatabaseObject::Directory(Directory::new_root())).is_ok());
+		assert!(check(&with_id(directory.clone(), TRASH_DIRECTORY_ID)).is_ok());
+
+		for (object, id) in [
+			(entry.clone(), ROOT_DIRECTORY_ID),
+			(entry.clone(), SETTINGS_ID),
+			(directory.clone(), SETTINGS_ID),
+			(directory, ID([0xf7; 32])),
+			(settings.clone(), ROOT_DIRECTORY_ID),
+			(settings, *Entry::new().get_id()),
+		] {
+			assert!(matches!(check(&with_id(object, id)), Err(FortressError::ReservedIdViolation(bad)) if bad == id));
+		}
+
+		// Database files are checked too, including entries that are loaded lazily
+		let json = serde_json::Value::Array(vec![with_id(entry, TRASH_DIRECTORY_ID)]);
+		assert!(serde_json::from_value::<DatabaseObjectMap>(json.clone()).is_err());
+		assert!(DatabaseObjectMap::deserialize_lazily(json).is_err());
+	}
+
+	#[test]
+	fn imported_objects() {
+		let mut db = Database::new_with_password("username", "password");
+		let before = db.clone();
+		let entry = serde_json::from_value(with_id(DatabaseObject::Entry(Entry::new()), ID([0xf1; 32]))).unwrap();
+		let directory = serde_json::from_value(with_id(DatabaseObject::Directory(Directory::new()), SETTINGS_ID)).unwrap();
+
+		assert!(matches!(db.insert_imported_object(entry), Err(FortressError::ReservedIdViolation(_))));
+		assert!(matches!(db.insert_imported_object(directory), Err(FortressError::ReservedIdViolation(_))));
+		assert_eq!(db, before);
+
+		let entry = Entry::new();
+		let id = *entry.get_id();
+		db.insert_imported_object(DatabaseObject::Entry(entry)).unwrap();
+		assert!(db.get_entry_by_id(&id).is_some());
+	}
+}
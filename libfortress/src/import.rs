@@ -0,0 +1,298 @@
+// Importing entries from other password managers.  Each importer (e.g. the onepassword module) parses an export into an
+// `ImportPlan` without touching the database, so a frontend can show what would be created first (a dry run).
+// `Database::import` then adds the plan's directories to the root directory and its entries to them.
+//
+// Imported entries are new entries with a single edit holding their fields, so importing the same export twice creates
+// everything twice.  Whatever an importer can't bring over (e.g. attachments, which Fortress doesn't have) is listed in
+// `ImportPlan::skipped` rather than failing the import.
+use crate::{database_object::DatabaseObject, Database, Directory, Entry, EntryHistory, FieldKind, FieldMetadata, FortressError, ID};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+
+/// What an importer found in an export (see the import module).
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct ImportPlan {
+	pub directories: Vec<ImportedDirectory>,
+	/// Entries that go directly in the root directory.
+	pub entries: Vec<ImportedEntry>,
+	/// Descriptions of what wasn't imported, e.g. "Attachment 'scan.pdf' of 'Passport'".
+	pub skipped: Vec<String>,
+	/// Descriptions of what the user should review after importing, e.g. "Different passwords for 'me' at example.com" (see
+	/// the browser_csv module).
+	pub conflicts: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct ImportedDirectory {
+	pub name: String,
+	pub entries: Vec<ImportedEntry>,
+}
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct ImportedEntry {
+	/// In the order they're shown.
+	pub fields: Vec<ImportedField>,
+	pub tags: BTreeSet<String>,
+}
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct ImportedField {
+	pub name: String,
+	pub value: String,
+	pub metadata: FieldMetadata,
+}
+
+
+/// Returned by `Database::import`.
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct ImportReport {
+	/// The directories created, in the plan's order.
+	pub directories: Vec<ID>,
+	/// The entries created, in the plan's order (the root directory's entries first).
+	pub entries: Vec<ID>,
+}
+
+
+impl ImportPlan {
+	pub fn entry_count(&self) -> usize {
+		self.entries.len() + self.directories.iter().map(|directory| directory.entries.len()).sum::<usize>()
+	}
+
+	/// A short description of what importing would do, e.g. "12 entries in 2 directories, 1 item skipped, 1 conflict".
+	pub fn summary(&self) -> String {
+		let plural = |count: usize, singular: &str, plural: &str| format!("{count} {}", if count == 1 { singular } else { plural });
+		let mut summary = plural(self.entry_count(), "entry", "entries");
+
+		if !self.directories.is_empty() {
+			summary += &format!(" in {}", plural(self.directories.len(), "directory", "directories"));
+		}
+
+		if !self.skipped.is_empty() {
+			summary += &format!(", {} skipped", plural(self.skipped.len(), "item", "items"));
+		}
+
+		if !self.conflicts.is_empty() {
+			summary += &format!(", {}", plural(self.conflicts.len(), "conflict", "conflicts"));
+		}
+
+		summary
+	}
+}
+
+
+impl ImportedEntry {
+	/// Adds a field, unless `value` is empty.  Fields get default metadata for their name (see `FieldMetadata::default_for`),
+	/// unless `kind` is given.  Names the entry already has get a number, e.g. "url 2".
+	pub fn push_field(&mut self, name: &str, value: &str, kind: Option<FieldKind>) {
+		if value.is_empty() {
+			return;
+		}
+
+		let mut unique_name = name.to_string();
+		for n in 2.. {
+			if !self.fields.iter().any(|field| field.name == unique_name) {
+				break;
+			}
+			unique_name = format!("{name} {n}");
+		}
+
+		let metadata = match kind {
+			Some(kind) => FieldMetadata {
+				kind,
+				protected: matches!(kind, FieldKind::Secret | FieldKind::Totp),
+				derived: None,
+			},
+			None => FieldMetadata::default_for(&unique_name),
+		};
+
+		self.fields.push(ImportedField {
+			name: unique_name,
+			value: value.to_string(),
+			metadata,
+		});
+	}
+
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.fields.iter().find(|field| field.name == name).map(|field| field.value.as_str())
+	}
+
+	fn to_entry(&self) -> Entry {
+		let data = self
+			.fields
+			.iter()
+			.map(|field| (field.name.clone(), field.value.clone()))
+			.collect::<HashMap<_, _>>();
+		let mut edit = EntryHistory::new(data).with_field_order(self.fields.iter().map(|field| field.name.clone()).collect());
+
+		for field in self.fields.iter().filter(|field| field.metadata != FieldMetadata::default_for(&field.name)) {
+			edit = edit.with_metadata(field.name.clone(), field.metadata.clone());
+		}
+
+		for tag in &self.tags {
+			edit = edit.with_tag(tag.clone(), true);
+		}
+
+		let mut entry = Entry::new();
+		entry.edit(edit);
+		entry
+	}
+}
+
+
+impl Database {
+	/// Adds everything in `plan` (see the import module).  Nothing is imported if any entry is over the limits in the limits
+	/// module.
+	pub fn import(&mut self, plan: &ImportPlan) -> Result<ImportReport, FortressError> {
+		let root_entries = plan.entries.iter().map(ImportedEntry::to_entry).collect::<Vec<_>>();
+		let directories = plan
+			.directories
+			.iter()
+			.map(|imported| {
+				let mut directory = Directory::new();
+				directory.rename(imported.name.clone());
+				(directory, imported.entries.iter().map(ImportedEntry::to_entry).collect::<Vec<_>>())
+			})
+			.collect::<Vec<_>>();
+
+		for entry in root_entries.iter().chain(directories.iter().flat_map(|(_, entries)| entries)) {
+			entry.check_limits().map_err(|limit| FortressError::SyncLimitExceeded {
+				id: Some(*entry.get_id()),
+				limit,
+			})?;
+		}
+
+		let mut report = ImportReport::default();
+
+		for entry in root_entries {
+			let id = *entry.get_id();
+			self.add_imported_entry(entry)?;
+			self.get_root_mut().add(id);
+			report.entries.push(id);
+		}
+
+		for (mut directory, entries) in directories {
+			for entry in entries {
+				directory.add(*entry.get_id());
+				report.entries.push(*entry.get_id());
+				self.add_imported_entry(entry)?;
+			}
+
+			let id = *directory.get_id();
+			directory.attribute_to(self.get_device_id().copied());
+			self.insert_imported_object(DatabaseObject::Directory(directory))?;
+			self.get_root_mut().add(id);
+			report.directories.push(id);
+		}
+
+		Ok(report)
+	}
+
+	/// Same as `add_entry`, for entries from outside the database (see `insert_imported_object`), but leaves it to the caller
+	/// to add the entry to a directory.
+	pub(crate) fn add_imported_entry(&mut self, mut entry: Entry) -> Result<(), FortressError> {
+		entry.attribute_to(self.get_device_id().copied());
+		self.insert_imported_object(DatabaseObject::Entry(entry))
+	}
+}
+
+
+/// Parses RFC 4180 CSV: commas between cells, and double quotes around cells that contain commas, quotes (doubled), or line
+/// breaks.  Lines may end with CRLF or LF, and blank lines are skipped.  Fails with FortressError::MalformedImport on an
+/// unterminated quote.
+pub(crate) fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, FortressError> {
+	let mut rows = Vec::new();
+	let mut row = Vec::new();
+	let mut cell = String::new();
+	let mut chars = text.strip_prefix('\u{feff}').unwrap_or(text).chars().peekable();
+	let mut quoted = false;
+
+	while let Some(c) = chars.next() {
+		match (quoted, c) {
+			(true, '"') if chars.peek() == Some(&'"') => {
+				chars.next();
+				cell.push('"');
+			},
+			(true, '"') => quoted = false,
+			(true, c) => cell.push(c),
+			(false, '"') if cell.is_empty() => quoted = true,
+			(false, ',') => row.push(std::mem::take(&mut cell)),
+			(false, '\r') if chars.peek() == Some(&'\n') => (),
+			(false, '\n') => {
+				row.push(std::mem::take(&mut cell));
+				if row.len() > 1 || !row[0].is_empty() {
+					rows.push(std::mem::take(&mut row));
+				}
+				row.clear();
+			},
+			(false, c) => cell.push(c),
+		}
+	}
+
+	if quoted {
+		return Err(FortressError::MalformedImport("unterminated quote in CSV".to_string()));
+	}
+
+	if !cell.is_empty() || !row.is_empty() {
+		row.push(cell);
+		rows.push(row);
+	}
+
+	Ok(rows)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_csv, ImportPlan, ImportedDirectory, ImportedEntry};
+	use crate::{Database, FieldKind};
+
+	#[test]
+	fn import() {
+		let mut db = Database::new_with_password("username", "password");
+		let mut entry = ImportedEntry::default();
+		entry.push_field("title", "Email", None);
+		entry.push_field("password", "hunter2", None);
+		entry.push_field("url", "https://mail.example.com", None);
+		entry.push_field("url", "https://example.com", None);
+		entry.push_field("pin", "1234", Some(FieldKind::Secret));
+		entry.push_field("notes", "", None);
+		entry.tags.insert("work".to_string());
+		let plan = ImportPlan {
+			directories: vec![ImportedDirectory {
+				name: "Personal".to_string(),
+				entries: vec![entry.clone()],
+			}],
+			entries: vec![ImportedEntry::default()],
+			skipped: vec!["Attachment 'scan.pdf' of 'Passport'".to_string()],
+			conflicts: Vec::new(),
+		};
+		assert_eq!(plan.summary(), "2 entries in 1 directory, 1 item skipped");
+		assert_eq!(ImportPlan::default().summary(), "0 entries");
+
+		let report = db.import(&plan).unwrap();
+		assert_eq!(report.entries.len(), 2);
+		let directory = db.get_directory_by_id(&report.directories[0]).unwrap();
+		assert_eq!(directory.get_name(), Some("Personal"));
+		assert!(db.get_root().contains(directory.get_id()));
+		assert!(directory.contains(&report.entries[1]));
+		assert!(!db.get_root().contains(&report.entries[1]));
+
+		let imported = db.get_entry_by_id(&report.entries[1]).unwrap();
+		assert_eq!(imported["title"], "Email");
+		assert_eq!(imported["url 2"], "https://example.com");
+		assert!(imported.get("notes").is_none());
+		assert!(imported.is_field_protected("password") && imported.is_field_protected("pin"));
+		assert_eq!(imported.get_field_metadata("url 2").kind, FieldKind::Text);
+		assert!(imported.has_tag("work"));
+		assert_eq!(imported.get_history().len(), 1);
+	}
+
+	#[test]
+	fn csv() {
+		let rows = parse_csv("\u{feff}a,b\r\n\"c,\"\"d\"\"\",\"line\nbreak\"\n\n,\nlast").unwrap();
+		assert_eq!(rows, [vec!["a", "b"], vec!["c,\"d\"", "line\nbreak"], vec!["", ""], vec!["last"]]);
+		assert!(parse_csv("\"unterminated").is_err());
+		assert!(parse_csv("").unwrap().is_empty());
+	}
+}
@@ -0,0 +1,283 @@
+// Integrity checks on the directory tree.
+// Every entry and directory should be reachable from the root directory (or the trash), and be in exactly one directory;
+// directories should only refer to objects that exist.  Merging concurrent moves from different devices, bugs in older
+// versions, or hand-edited files can break this, leaving entries that no frontend shows.  `Database::check_integrity` finds
+// these problems, and `Database::repair` fixes them the way any other edit would, by adding to directories' history, so
+// nothing is lost and the repair syncs like any other change.
+use crate::{
+	database_object::{DatabaseObject, DirectoryHistoryAction},
+	unix_timestamp, Database, Directory, ID, ROOT_DIRECTORY_ID, SETTINGS_ID, TRASH_DIRECTORY_ID,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+
+/// History timestamped more than this far ahead of our clock (nanoseconds) is reported by `Database::check_integrity`.
+pub const FUTURE_HISTORY_TOLERANCE: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct IntegrityReport {
+	/// The root directory is missing.  `repair` creates a new, empty one.
+	pub missing_root: bool,
+	/// Entries and directories that can't be reached from the root directory: they aren't in any directory, or only in
+	/// directories that are themselves unreachable.  `repair` moves them into the root directory.
+	pub orphans: Vec<ID>,
+	/// Directories referring to objects that don't exist.  `repair` removes the references.
+	pub missing_references: Vec<MissingReference>,
+	/// Objects in more than one directory.  `repair` keeps each in the directory it was most recently added to.
+	pub duplicate_references: Vec<DuplicateReference>,
+	/// Objects with history timestamped more than FUTURE_HISTORY_TOLERANCE ahead of our clock, usually from a device whose clock
+	/// is wrong.  Not repaired, since history is never rewritten; edits made after it are timestamped after it.
+	pub future_history: Vec<FutureHistory>,
+}
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct MissingReference {
+	pub directory: ID,
+	pub missing: ID,
+}
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct DuplicateReference {
+	pub object: ID,
+	/// Every directory the object is in, sorted by ID.
+	pub directories: Vec<ID>,
+}
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+pub struct FutureHistory {
+	pub object: ID,
+	/// The object's latest history timestamp (nanoseconds).
+	pub time: u64,
+}
+
+impl IntegrityReport {
+	/// True if no problems were found.
+	pub fn is_ok(&self) -> bool {
+		*self == IntegrityReport::default()
+	}
+}
+
+
+impl Database {
+	/// Checks the directory tree and history for problems, without changing anything.  See the integrity module.
+	pub fn check_integrity(&self) -> IntegrityReport {
+		let mut report = IntegrityReport {
+			missing_root: self.get_directory_by_id(&ROOT_DIRECTORY_ID).is_none(),
+			orphans: self.list_orphans(),
+			..IntegrityReport::default()
+		};
+
+		for (object, mut directories) in self.parents() {
+			if directories.len() > 1 {
+				directories.sort_unstable();
+				report.duplicate_references.push(DuplicateReference { object, directories });
+			}
+		}
+
+		for directory in self.list_directories() {
+			for missing in directory.entries.iter().filter(|id| self.objects.get(*id).is_none()) {
+				report.missing_references.push(MissingReference {
+					directory: *directory.get_id(),
+					missing: *missing,
+				});
+			}
+		}
+
+		let now = unix_timestamp();
+		for object in self.objects.values() {
+			let time = match object {
+				DatabaseObject::Entry(entry) => entry.get_history().last().map(|item| item.time),
+				DatabaseObject::Directory(directory) => directory.get_history().last().map(|item| item.time),
+				DatabaseObject::Settings(_) | DatabaseObject::Tombstone(_) => None,
+			};
+
+			if let Some(time) = time.filter(|time| *time > now.saturating_add(FUTURE_HISTORY_TOLERANCE)) {
+				report.future_history.push(FutureHistory {
+					object: *object.get_id(),
+					time,
+				});
+			}
+		}
+
+		report.duplicate_references.sort_unstable_by_key(|duplicate| duplicate.object);
+		report
+			.missing_references
+			.sort_unstable_by_key(|reference| (reference.directory, reference.missing));
+		report.future_history.sort_unstable_by_key(|future| future.object);
+		report
+	}
+
+	/// Fixes the problems `check_integrity` finds (except for future history), returning what was found.
+	pub fn repair(&mut self) -> IntegrityReport {
+		let report = self.check_integrity();
+
+		if report.missing_root {
+			self.objects.update(DatabaseObject::Directory(Directory::new_root()));
+		}
+
+		for reference in &report.missing_references {
+			if let Some(directory) = self.get_directory_by_id_mut(&reference.directory) {
+				directory.remove(reference.missing);
+			}
+		}
+
+		for duplicate in &report.duplicate_references {
+			let keep = duplicate
+				.directories
+				.iter()
+				.max_by_key(|id| self.get_directory_by_id(id).map(|directory| last_added(directory, &duplicate.object)))
+				.copied();
+
+			for id in duplicate.directories.iter().filter(|id| Some(**id) != keep) {
+				if let Some(directory) = self.get_directory_by_id_mut(id) {
+					directory.remove(duplicate.object);
+				}
+			}
+		}
+
+		// Orphans that aren't in any directory go straight into the root.  What's left are directories that contain each other,
+		// so one at a time is moved out of its cycle and into the root, until everything is reachable.
+		let parents = self.parents();
+		for orphan in self.list_orphans().into_iter().filter(|id| !parents.contains_key(id)) {
+			self.get_root_mut().add(orphan);
+		}
+
+		while let Some(orphan) = self.list_orphans().first().copied() {
+			self.move_object(&orphan, &ROOT_DIRECTORY_ID);
+		}
+
+		report
+	}
+
+	/// Entries and directories that can't be reached from the root directory or the trash, sorted by ID.  Tombstones (see the gc
+	/// module) don't count.  See `check_integrity`.
+	pub fn list_orphans(&self) -> Vec<ID> {
+		let mut reachable = HashSet::from([ROOT_DIRECTORY_ID, TRASH_DIRECTORY_ID]);
+		let mut pending = vec![ROOT_DIRECTORY_ID, TRASH_DIRECTORY_ID];
+
+		while let Some(id) = pending.pop() {
+			if let Some(directory) = self.get_directory_by_id(&id) {
+				pending.extend(directory.entries.iter().filter(|child| reachable.insert(**child)));
+			}
+		}
+
+		let mut orphans = self
+			.objects
+			.values()
+			.filter(|object| object.as_tombstone().is_none())
+			.map(DatabaseObject::get_id)
+			.filter(|id| **id != SETTINGS_ID && !reachable.contains(*id))
+			.copied()
+			.collect::<Vec<_>>();
+		orphans.sort_unstable();
+		orphans
+	}
+
+	// The directories each object is in
+	fn parents(&self) -> HashMap<ID, Vec<ID>> {
+		let mut parents = HashMap::<ID, Vec<ID>>::new();
+
+		for directory in self.list_directories() {
+			for child in &directory.entries {
+				parents.entry(*child).or_default().push(*directory.get_id());
+			}
+		}
+
+		parents
+	}
+}
+
+
+// When `id` was last added to `directory`
+fn last_added(directory: &Directory, id: &ID) -> Option<u64> {
+	directory
+		.get_history()
+		.iter()
+		.rev()
+		.find(|item| item.action == DirectoryHistoryAction::Add(*id))
+		.map(|item| item.time)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{DuplicateReference, FutureHistory, IntegrityReport, MissingReference, FUTURE_HISTORY_TOLERANCE};
+	use crate::{database_object::DatabaseObject, unix_timestamp, Database, Directory, Entry, ID};
+	use rand::{rngs::OsRng, Rng};
+
+	#[test]
+	fn check_and_repair() {
+		let mut db = Database::new_with_password("username", "password");
+		let root = *db.get_root().get_id();
+		let directory = Directory::new();
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+		db.add_entry(Entry::new());
+		assert!(db.check_integrity().is_ok());
+		assert_eq!(db.repair(), IntegrityReport::default());
+
+		// An entry that isn't in any directory
+		let orphan = Entry::new();
+		let orphan_id = *orphan.get_id();
+		db.objects.update(DatabaseObject::Entry(orphan));
+
+		// Two directories that only contain each other
+		let (mut first, mut second) = (Directory::new(), Directory::new());
+		let (first_id, second_id) = (*first.get_id(), *second.get_id());
+		first.add(second_id);
+		second.add(first_id);
+		db.objects.update(DatabaseObject::Directory(first));
+		db.objects.update(DatabaseObject::Directory(second));
+
+		// A reference to an object that doesn't exist
+		let missing: ID = OsRng.gen();
+		db.get_root_mut().add(missing);
+
+		// An entry in two directories, most recently added to `directory`
+		let duplicate = Entry::new();
+		let duplicate_id = *duplicate.get_id();
+		db.add_entry(duplicate);
+		db.get_directory_by_id_mut(&directory_id).unwrap().add(duplicate_id);
+
+		// A directory renamed by a device whose clock is days ahead
+		let future = unix_timestamp() + 2 * FUTURE_HISTORY_TOLERANCE;
+		db.get_directory_by_id_mut(&directory_id).unwrap().rename_with_time("Future", future);
+
+		let mut orphans = vec![orphan_id, first_id, second_id];
+		orphans.sort_unstable();
+		let mut directories = vec![root, directory_id];
+		directories.sort_unstable();
+		let expected = IntegrityReport {
+			missing_root: false,
+			orphans,
+			missing_references: vec![MissingReference { directory: root, missing }],
+			duplicate_references: vec![DuplicateReference {
+				object: duplicate_id,
+				directories,
+			}],
+			future_history: vec![FutureHistory {
+				object: directory_id,
+				time: future,
+			}],
+		};
+		assert_eq!(db.check_integrity(), expected);
+		assert_eq!(db.list_orphans(), expected.orphans);
+
+		// Everything but the future history is repaired
+		assert_eq!(db.repair(), expected);
+		let report = db.check_integrity();
+		assert_eq!(report.future_history, expected.future_history);
+		assert!(IntegrityReport {
+			future_history: Vec::new(),
+			..report
+		}
+		.is_ok());
+
+		assert!(!db.get_root().contains(&missing));
+		assert_eq!(db.get_parent_directory(&duplicate_id).map(Directory::get_id), Some(&directory_id));
+		assert_eq!(db.get_parent_directory(&orphan_id).map(Directory::get_id), Some(&root));
+		assert!(db.get_root().contains(&first_id) != db.get_root().contains(&second_id));
+	}
+}
@@ -0,0 +1,353 @@
+// Saving only what changed.  `save_to_path` writes the whole database, which gets slow for large databases when it's done after
+// every edit.  `Database::save_incremental` instead appends the objects that changed since its last save, along with the rest of
+// the database's (small) state, as a record in a journal kept next to the database file (`path` with a `.journal` suffix).  The
+// path loaders replay the journal after loading the file.  Once the journal grows larger than the file, the next
+// `save_incremental` saves the whole database instead, which starts a new journal.
+//
+// The journal starts with the checksum of the database file it belongs to, so it's ignored once the file is saved in full (e.g.
+// by `save_to_path`, or by another copy of Fortress).  Each record is encrypted with the file's keys, and bound to the file's
+// checksum and its position in the journal.  A record that's cut short, e.g. by a crash while it was being written, ends the
+// replay, and is overwritten by the next record; records are only ever lost that way, never applied partially.
+//
+// File format:
+//   "fortress-journal1\0"
+//   database file checksum (32 bytes)
+//   records: length (u64, little endian) || FileKeySuite::encrypt_record of the record
+// Records are serialized like the database file's payload, but only hold the objects that changed.
+use crate::{
+	database_object_map::DatabaseObjectMap,
+	file_changes::FileVersion,
+	file_lock::{FileLock, FileLockState},
+	migration, persist_atomically, signature, Database, FortressError,
+};
+use fortresscrypto::{FileKeySuite, Zeroizing};
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{self, Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+};
+
+
+const JOURNAL_HEADER: &[u8] = b"fortress-journal1\0";
+
+
+// The journal this database was last loaded with or saved to.  Not part of the database's value: it's never serialized, and
+// databases compare equal whatever it is.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct JournalState(Option<Journal>);
+
+impl PartialEq for JournalState {
+	fn eq(&self, _other: &JournalState) -> bool {
+		true
+	}
+}
+
+impl Eq for JournalState {}
+
+
+#[derive(Clone, Debug)]
+struct Journal {
+	/// The database file the journal belongs to
+	path: PathBuf,
+	snapshot_checksum: [u8; 32],
+	snapshot_len: u64,
+	/// The file's keys; records have to be encrypted with the same keys as the file, and a change to its keyslots (see the users
+	/// module) needs the whole file saved
+	file_key_suite: FileKeySuite,
+	/// Length of the journal up to the end of its last good record.  0 if there's no journal for this file yet.
+	len: u64,
+	records: u64,
+}
+
+impl Journal {
+	fn new(path: &Path, snapshot: &[u8], file_key_suite: &FileKeySuite) -> Journal {
+		Journal {
+			path: path.to_owned(),
+			snapshot_checksum: snapshot[snapshot.len() - 32..].try_into().expect("internal error"),
+			snapshot_len: snapshot.len() as u64,
+			file_key_suite: file_key_suite.clone(),
+			len: 0,
+			records: 0,
+		}
+	}
+
+	// Record `index` is bound to the file it belongs to and its position, so records can't be replayed against another file or
+	// out of order
+	fn associated_data(&self, index: u64) -> Vec<u8> {
+		[JOURNAL_HEADER, &self.snapshot_checksum, &index.to_le_bytes()].concat()
+	}
+}
+
+
+impl Database {
+	/// Saves the changes made since the database was loaded from or saved to `path` (with `save_incremental`), as a record in
+	/// the file's journal (see the journal module).  Saves the whole database like `save_to_path` instead when there's no
+	/// journal to add to: the first time the database is saved to `path` this way, after the file was saved some other way or
+	/// its keys were changed, and when the journal has grown larger than the file.
+	pub fn save_incremental<P: AsRef<Path>>(&mut self, path: P) -> Result<(), FortressError> {
+		let path = path.as_ref();
+		self.file_lock.lock(path)?;
+		let journal = match &self.journal.0 {
+			Some(journal)
+				if journal.path == path && journal.file_key_suite == self.file_key_suite && read_checksum(path)? == Some(journal.snapshot_checksum) =>
+			{
+				journal.clone()
+			},
+			_ => return self.save_snapshot(path),
+		};
+
+		let record = self.journal_record(&journal)?;
+		let start = if journal.len == 0 { (JOURNAL_HEADER.len() + 32) as u64 } else { journal.len };
+		let end = start + 8 + record.len() as u64;
+
+		if end > journal.snapshot_len {
+			return self.save_snapshot(path);
+		}
+
+		if journal.len == 0 {
+			persist_atomically(journal_path(path), |writer| {
+				writer.write_all(JOURNAL_HEADER)?;
+				writer.write_all(&journal.snapshot_checksum)?;
+				write_record(writer, &record)
+			})?;
+		} else {
+			// Anything after the last good record is left over from a record that was cut short
+			let mut file = OpenOptions::new().write(true).open(journal_path(path))?;
+			file.set_len(journal.len)?;
+			file.seek(SeekFrom::Start(journal.len))?;
+			write_record(&mut file, &record)?;
+			file.flush()?;
+		}
+		signature::write_save_signature(self, path)?;
+
+		self.file_version.set(FileVersion::new(path, journal.snapshot_checksum, end));
+		self.journal = JournalState(Some(Journal {
+			len: end,
+			records: journal.records + 1,
+			..journal
+		}));
+		self.objects.clear_changed();
+
+		Ok(())
+	}
+
+	fn save_snapshot(&mut self, path: &Path) -> Result<(), FortressError> {
+		let snapshot = self.write_snapshot(path)?;
+
+		self.journal = JournalState(Some(Journal::new(path, &snapshot, &self.file_key_suite)));
+		self.objects.clear_changed();
+
+		Ok(())
+	}
+
+	// The changed objects, and everything else, serialized like the file's payload and encrypted as the journal's next record
+	fn journal_record(&mut self, journal: &Journal) -> Result<Vec<u8>, FortressError> {
+		let mut changed = DatabaseObjectMap::new();
+		for object in self.objects.changed() {
+			changed.update(object.clone());
+		}
+
+		let objects = std::mem::replace(&mut self.objects, changed);
+		let payload = serde_json::to_vec(&self).map(Zeroizing::new);
+		self.objects = objects;
+
+		Ok(self.file_key_suite.encrypt_record(&journal.associated_data(journal.records), &payload?))
+	}
+}
+
+
+/// Reads the database file at `path` and loads it with `load`, then replays its journal, if it has one.  `password` is needed
+/// if a record was saved while the sync keys were still being derived (see `migration::deserialize_fortress2`).
+pub(crate) fn load_with_journal<F>(path: &Path, password: Option<&str>, load: F) -> Result<Database, FortressError>
+where
+	F: FnOnce(&[u8]) -> Result<Database, FortressError>,
+{
+	let lock = FileLock::acquire(path)?;
+	let snapshot = fs::read(path)?;
+	let mut database = load(&snapshot)?;
+	let mut journal = Journal::new(path, &snapshot, &database.file_key_suite);
+
+	let data = match fs::read(journal_path(path)) {
+		Ok(data) => data,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+		Err(err) => return Err(err.into()),
+	};
+
+	// A journal for some other version of the file is left alone until the next `save_incremental` replaces it
+	if let Some(mut records) = data
+		.strip_prefix(JOURNAL_HEADER)
+		.and_then(|data| data.strip_prefix(&journal.snapshot_checksum[..]))
+	{
+		journal.len = (JOURNAL_HEADER.len() + 32) as u64;
+
+		while let Some((record, rest)) = read_record(records) {
+			let Ok(plaintext) = database.file_key_suite.decrypt_record(&journal.associated_data(journal.records), record) else {
+				break;
+			};
+
+			database = apply_record(database, &plaintext, password)?;
+			journal.len += 8 + record.len() as u64;
+			journal.records += 1;
+			records = rest;
+		}
+	}
+
+	database.file_version.set(FileVersion::new(path, journal.snapshot_checksum, data.len() as u64));
+	database.journal = JournalState(Some(journal));
+	database.file_lock = FileLockState::held(lock);
+	database.objects.clear_changed();
+
+	Ok(database)
+}
+
+
+/// Removes the journal of the database file at `path`, after the file was saved in full.  The journal is ignored from then on
+/// anyway, so failing to remove it isn't an error.
+pub(crate) fn remove_journal(path: &Path) {
+	let _ = fs::remove_file(journal_path(path));
+}
+
+
+// The record's state replaces the database's, except for its objects, which are added to the database's objects
+fn apply_record(database: Database, plaintext: &[u8], password: Option<&str>) -> Result<Database, FortressError> {
+	let mut replayed = migration::deserialize_fortress2::<DatabaseObjectMap>(plaintext, database.file_key_suite.clone(), password)?;
+	let mut objects = database.objects;
+
+	// Records hold the objects as they were saved, which may be compacted (see the compaction module)
+	for object in replayed.objects.values() {
+		match objects.get(object.get_id()) {
+			Some(_) => objects.replace_destructively(object.clone()),
+			None => objects.update(object.clone()),
+		}
+	}
+
+	objects.set_device(replayed.get_device_id().copied());
+	replayed.objects = objects;
+	replayed.original_format_version = database.original_format_version;
+
+	Ok(replayed)
+}
+
+
+pub(crate) fn journal_path(path: &Path) -> PathBuf {
+	let mut journal_path = path.as_os_str().to_owned();
+	journal_path.push(".journal");
+	PathBuf::from(journal_path)
+}
+
+
+// The checksum at the end of the database file at `path`, which identifies its contents
+pub(crate) fn read_checksum(path: &Path) -> Result<Option<[u8; 32]>, FortressError> {
+	let mut file = match File::open(path) {
+		Ok(file) => file,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(err) => return Err(err.into()),
+	};
+
+	if file.metadata()?.len() < 32 {
+		return Ok(None);
+	}
+
+	let mut checksum = [0u8; 32];
+	file.seek(SeekFrom::End(-32))?;
+	file.read_exact(&mut checksum)?;
+	Ok(Some(checksum))
+}
+
+
+fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> io::Result<()> {
+	writer.write_all(&(record.len() as u64).to_le_bytes())?;
+	writer.write_all(record)
+}
+
+
+// The next record in `data` and what follows it, or None if there isn't a complete record
+fn read_record(data: &[u8]) -> Option<(&[u8], &[u8])> {
+	let (len, rest) = data.split_first_chunk::<8>()?;
+	let len = usize::try_from(u64::from_le_bytes(*len)).ok()?;
+
+	(rest.len() >= len).then(|| rest.split_at(len))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::journal_path;
+	use crate::{
+		testing::{fields, new_entry},
+		Database,
+	};
+	use std::fs;
+	use tempfile::tempdir;
+	use url::Url;
+
+	#[test]
+	fn journal() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		for i in 0..100 {
+			db.add_entry(new_entry(&format!("Entry {i}")));
+		}
+
+		// The first save is in full
+		db.save_incremental(&path).unwrap();
+		assert!(!journal_path(&path).exists());
+		let snapshot = fs::read(&path).unwrap();
+
+		// Later ones only add to the journal, including changes that aren't to objects
+		let journaled = new_entry("Journaled");
+		let id = *journaled.get_id();
+		db.add_entry(journaled);
+		db.save_incremental(&path).unwrap();
+		let entry = db.get_entry_by_id_mut(&id).unwrap();
+		entry.edit(entry.new_edit(fields(&[("username", "me")])));
+		db.set_sync_url(Some(Url::parse("https://example.com").unwrap()));
+		db.save_incremental(&path).unwrap();
+		assert_eq!(fs::read(&path).unwrap(), snapshot);
+		let loaded = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(loaded, db);
+		assert_eq!(loaded.get_entry_by_id(&id).unwrap()["username"], "me");
+		assert_eq!(Database::load_from_path_lazily(&path, "password").unwrap(), db);
+
+		// A record that was cut short is skipped, and replaced by the next one
+		let journal = fs::read(journal_path(&path)).unwrap();
+		let mut db = loaded;
+		let before = db.clone();
+		db.add_entry(new_entry("Cut short"));
+		db.save_incremental(&path).unwrap();
+		let full_journal = fs::read(journal_path(&path)).unwrap();
+		fs::write(journal_path(&path), &full_journal[..full_journal.len() - 10]).unwrap();
+		let mut db = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(db, before);
+		let after_crash = new_entry("After the crash");
+		let id = *after_crash.get_id();
+		db.add_entry(after_crash);
+		db.save_incremental(&path).unwrap();
+		let loaded = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(loaded, db);
+		assert!(loaded.get_entry_by_id(&id).is_some());
+
+		// Saving in full removes the journal, and an old journal isn't replayed against a newer file
+		db.save_to_path(&path).unwrap();
+		assert!(!journal_path(&path).exists());
+		fs::write(journal_path(&path), &journal).unwrap();
+		assert_eq!(Database::load_from_path(&path, "password").unwrap(), db);
+
+		// The journal is compacted into the file once it's larger than the file
+		let mut db = Database::load_from_path(&path, "password").unwrap();
+		for i in 0..100 {
+			let entry = db.get_entry_by_id_mut(&id).unwrap();
+			entry.edit(entry.new_edit(fields(&[("notes", &"x".repeat(100 + i))])));
+			db.save_incremental(&path).unwrap();
+		}
+		assert!(fs::metadata(journal_path(&path)).map_or(0, |metadata| metadata.len()) <= fs::metadata(&path).unwrap().len());
+		assert_eq!(Database::load_from_path(&path, "password").unwrap(), db);
+
+		// Changing the password saves in full, since the journal is encrypted with the file's keys
+		db.change_password("username", "new password").unwrap();
+		db.save_incremental(&path).unwrap();
+		assert!(!journal_path(&path).exists());
+		assert_eq!(Database::load_from_path(&path, "new password").unwrap(), db);
+	}
+}
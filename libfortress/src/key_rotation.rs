@@ -0,0 +1,105 @@
+// Rotating the network keys, e.g. if they're suspected to have leaked.  `change_password` already gets new keys for the same
+// account, but leaves the objects on the server encrypted under the old ones until a sync happens to replace them, and sync
+// skips objects it can't decrypt, so edits another device uploaded under the old keys would be overwritten rather than merged.
+// `rotate_network_keys` instead re-encrypts every object on the server straight away, in two phases coordinated through the
+// server:
+//
+// 1. The server is told the new login key, as after any password change.  From then on it only accepts the new credentials, so
+//    devices still using the old keys can't upload anything more under them.
+// 2. A sync downloads every object on the server, decrypting it with whichever keys it's under, merges it into our copy, and
+//    uploads it under the new keys.  Uploads name the SIV of the copy they replace, so the server rejects them if the object
+//    changed in the meantime.  Objects keep their IDs and history.
+//
+// The old keys are kept, and saved with the database, until a sync finishes phase 2, so a rotation that's interrupted (e.g. by
+// a network error) carries on with the next sync.  They're dropped from the derivation cache straight away, though, so the
+// old password can't be changed back to without deriving its keys again.
+use crate::{Database, FortressError, SyncReport, ID};
+use fortresscrypto::{EncryptedObject, NetworkKeySuite, SIV};
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub(crate) struct KeyRotation {
+	/// The keys objects on the server may still be encrypted under
+	old_network_key_suite: NetworkKeySuite,
+}
+
+
+impl Database {
+	/// Changes the network keys (and file keys) to ones derived from `new_password`, and re-encrypts every object on the sync
+	/// server under them with a sync (see the key_rotation module).  Returns that sync's report.  `new_password` must be
+	/// different from the current password, since the keys are derived from it.  Other devices need the new password to sync
+	/// again.
+	/// Fails with FortressError::SyncBadUrl, leaving the keys alone, if there's no sync URL, and likewise with
+	/// FortressError::SyncReadOnly if the database syncs with read-only credentials.  If the sync fails, the database keeps the
+	/// new keys and the next sync finishes the rotation, so it should be saved either way.  An earlier rotation or password
+	/// change that hasn't been synced yet is synced first.
+	pub fn rotate_network_keys<P: AsRef<str>>(&mut self, new_password: P) -> Result<SyncReport, FortressError> {
+		if self.sync_url.is_none() {
+			return Err(FortressError::SyncBadUrl);
+		}
+		self.check_sync_writable()?;
+
+		if self.key_rotation.is_some() || self.old_sync_parameters.is_some() {
+			self.sync()?;
+		}
+
+		let old_network_key_suite = self.sync_parameters.get_network_key_suite().expect("internal error").clone();
+		let username = self.get_username().to_string();
+		self.derivation_cache.clear();
+		self.change_password(username, new_password)?;
+		self.key_rotation = Some(KeyRotation { old_network_key_suite });
+
+		self.sync()
+	}
+
+	/// True while objects on the sync server may still be encrypted under the keys from before `rotate_network_keys`.
+	pub fn key_rotation_pending(&self) -> bool {
+		self.key_rotation.is_some()
+	}
+
+	/// The keys an object `body` from the server is encrypted under: ours, unless a rotation is in progress and only the old
+	/// keys can decrypt it.
+	pub(crate) fn server_object_keys(&self, id: &ID, body: &[u8]) -> &NetworkKeySuite {
+		let network_key_suite = self.sync_parameters.get_network_key_suite().expect("internal error");
+		let Some(rotation) = &self.key_rotation else {
+			return network_key_suite;
+		};
+		// Malformed bodies are reported by decode_server_object
+		let Some((ciphertext, siv)) = body.len().checked_sub(32).map(|mid| body.split_at(mid)) else {
+			return network_key_suite;
+		};
+		let encrypted_object = EncryptedObject {
+			ciphertext: ciphertext.to_vec(),
+			siv: SIV::from_slice(siv).expect("internal error"),
+		};
+
+		if network_key_suite.decrypt_object(&id[..], &encrypted_object).is_err()
+			&& rotation.old_network_key_suite.decrypt_object(&id[..], &encrypted_object).is_ok()
+		{
+			&rotation.old_network_key_suite
+		} else {
+			network_key_suite
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::Database;
+	use url::Url;
+
+	#[test]
+	fn forgets_old_keys() {
+		let mut db = Database::new_with_password("username", "foobar");
+		db.set_sync_url(Some(Url::parse("http://127.0.0.1:1").unwrap()));
+		assert!(db.derivation_cache.get("username", "foobar").is_some());
+
+		// The rotation itself is left for the next sync, but the old keys are gone either way
+		assert!(db.rotate_network_keys("barfoo").is_err());
+		assert!(db.key_rotation_pending());
+		assert!(db.derivation_cache.get("username", "foobar").is_none());
+		assert!(db.derivation_cache.get("username", "barfoo").is_some());
+	}
+}
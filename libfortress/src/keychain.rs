@@ -0,0 +1,281 @@
+// Unlocking a database without typing its password, by keeping its derived file keys in the OS keychain, which the OS only
+// unlocks for the logged in user.  Only available with the `keychain` feature.
+//
+// Like the clipboard module, the keychain is accessed through the platform's command line tools rather than linked libraries:
+// secret-tool (libsecret) on Linux and the BSDs, security (Keychain) on macOS, and PowerShell on Windows, which encrypts the keys
+// with DPAPI and keeps them in the registry.  Keys are stored per database file, under the hex of its canonical path.
+//
+// The keys are the FileKeySuite, not the password, so they can't be used to log in to the sync server or to open other files
+// that use the same password.  The password always works too: `unlock_with_keychain` returns None whenever the keychain can't
+// open the file, e.g. after the password is changed, and frontends should then ask for the password and call
+// `remember_in_keychain` again.  TPMs aren't supported.
+//
+// For databases that need a hardware key (see the hardware_key module), the stored keys already have the hardware key's response
+// mixed in, so unlocking from the keychain doesn't ask for the hardware key either.
+use crate::{journal, migration, Database, FortressError};
+use data_encoding::HEXLOWER;
+use fortresscrypto::{CryptoError, FileKeySuite, Zeroizing};
+use std::{
+	fs,
+	io::{Read, Write},
+	path::Path,
+	process::{Command, Stdio},
+};
+
+
+/// The service name the keys are stored under.
+pub const KEYCHAIN_SERVICE: &str = "fortress";
+
+
+/// A store of secrets, each under an account name.
+pub trait Keychain {
+	/// None if there is no secret for `account`.
+	fn get(&mut self, account: &str) -> Result<Option<Zeroizing<String>>, FortressError>;
+	fn set(&mut self, account: &str, secret: &str) -> Result<(), FortressError>;
+	/// Does nothing if there is no secret for `account`.
+	fn delete(&mut self, account: &str) -> Result<(), FortressError>;
+}
+
+
+/// The OS keychain, accessed through the platform's command line tools (see the keychain module).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemKeychain;
+
+impl SystemKeychain {
+	// `account` is hex, so it's safe to put in arguments and in the commands `security -i` reads.  PowerShell gets it from the
+	// environment instead.
+	fn command(operation: KeychainOperation, account: &str) -> (Command, Option<String>) {
+		if cfg!(target_os = "macos") {
+			let mut command = Command::new("security");
+			let stdin = match operation {
+				KeychainOperation::Get => {
+					command.args(["find-generic-password", "-s", KEYCHAIN_SERVICE, "-a", account, "-w"]);
+					None
+				},
+				// The secret is written to the interactive mode's stdin, so it doesn't show up in the process list
+				KeychainOperation::Set => {
+					command.arg("-i");
+					Some(format!("add-generic-password -U -s {KEYCHAIN_SERVICE} -a {account} -w "))
+				},
+				KeychainOperation::Delete => {
+					command.args(["delete-generic-password", "-s", KEYCHAIN_SERVICE, "-a", account]);
+					None
+				},
+			};
+			(command, stdin)
+		} else if cfg!(windows) {
+			let script = match operation {
+				KeychainOperation::Get => WINDOWS_GET_SCRIPT,
+				KeychainOperation::Set => WINDOWS_SET_SCRIPT,
+				KeychainOperation::Delete => WINDOWS_DELETE_SCRIPT,
+			};
+			let mut command = Command::new("powershell");
+			command.args(["-NoProfile", "-Command", script]).env("FORTRESS_KEYCHAIN_ACCOUNT", account);
+			(command, None)
+		} else {
+			let mut command = Command::new("secret-tool");
+			match operation {
+				KeychainOperation::Get => command.arg("lookup"),
+				KeychainOperation::Set => command.args(["store", "--label", "Fortress database keys"]),
+				KeychainOperation::Delete => command.arg("clear"),
+			};
+			command.args(["service", KEYCHAIN_SERVICE, "account", account]);
+			(command, None)
+		}
+	}
+
+	fn unavailable(command: &Command) -> FortressError {
+		FortressError::KeychainUnavailable(command.get_program().to_string_lossy().into_owned())
+	}
+}
+
+impl Keychain for SystemKeychain {
+	fn get(&mut self, account: &str) -> Result<Option<Zeroizing<String>>, FortressError> {
+		let (mut command, _) = SystemKeychain::command(KeychainOperation::Get, account);
+		let mut child = command
+			.stdin(Stdio::null())
+			.stdout(Stdio::piped())
+			.stderr(Stdio::null())
+			.spawn()
+			.map_err(|_| SystemKeychain::unavailable(&command))?;
+
+		let mut secret = Zeroizing::new(String::new());
+		let read = child.stdout.take().expect("internal error").read_to_string(&mut secret);
+
+		// The tools fail when there's no such secret
+		match (read, child.wait()) {
+			(Ok(_), Ok(status)) if status.success() && !secret.trim().is_empty() => Ok(Some(Zeroizing::new(secret.trim().to_owned()))),
+			(_, Ok(_)) => Ok(None),
+			(_, Err(_)) => Err(SystemKeychain::unavailable(&command)),
+		}
+	}
+
+	fn set(&mut self, account: &str, secret: &str) -> Result<(), FortressError> {
+		let (mut command, prefix) = SystemKeychain::command(KeychainOperation::Set, account);
+		let mut child = command
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+			.map_err(|_| SystemKeychain::unavailable(&command))?;
+
+		let input = Zeroizing::new(format!("{}{secret}\n", prefix.unwrap_or_default()));
+		let written = child.stdin.take().expect("internal error").write_all(input.as_bytes());
+
+		match (written, child.wait()) {
+			(Ok(()), Ok(status)) if status.success() => Ok(()),
+			_ => Err(SystemKeychain::unavailable(&command)),
+		}
+	}
+
+	fn delete(&mut self, account: &str) -> Result<(), FortressError> {
+		let (mut command, _) = SystemKeychain::command(KeychainOperation::Delete, account);
+
+		// The tools fail when there's no such secret, which is fine
+		command
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.status()
+			.map(|_| ())
+			.map_err(|_| SystemKeychain::unavailable(&command))
+	}
+}
+
+
+#[derive(Clone, Copy)]
+enum KeychainOperation {
+	Get,
+	Set,
+	Delete,
+}
+
+
+const WINDOWS_SET_SCRIPT: &str = "$ErrorActionPreference = 'Stop'; Add-Type -AssemblyName System.Security; \
+	$secret = [Text.Encoding]::UTF8.GetBytes([Console]::In.ReadToEnd().Trim()); \
+	$blob = [Security.Cryptography.ProtectedData]::Protect($secret, $null, 'CurrentUser'); \
+	$key = 'HKCU:\\Software\\Fortress\\Keychain'; \
+	if (-not (Test-Path $key)) { New-Item -Path $key -Force | Out-Null }; \
+	Set-ItemProperty -Path $key -Name $env:FORTRESS_KEYCHAIN_ACCOUNT -Value ([Convert]::ToBase64String($blob))";
+
+const WINDOWS_GET_SCRIPT: &str = "$ErrorActionPreference = 'Stop'; Add-Type -AssemblyName System.Security; \
+	$item = Get-ItemProperty -Path 'HKCU:\\Software\\Fortress\\Keychain' -Name $env:FORTRESS_KEYCHAIN_ACCOUNT -ErrorAction SilentlyContinue; \
+	if ($item) { $blob = [Convert]::FromBase64String($item.($env:FORTRESS_KEYCHAIN_ACCOUNT)); \
+	[Console]::Out.Write([Text.Encoding]::UTF8.GetString([Security.Cryptography.ProtectedData]::Unprotect($blob, $null, 'CurrentUser'))) }";
+
+const WINDOWS_DELETE_SCRIPT: &str =
+	"Remove-ItemProperty -Path 'HKCU:\\Software\\Fortress\\Keychain' -Name $env:FORTRESS_KEYCHAIN_ACCOUNT -ErrorAction SilentlyContinue";
+
+
+impl Database {
+	/// Keeps this database's file keys in `keychain`, so the file at `path` can be opened with `unlock_with_keychain`.
+	pub fn remember_in_keychain<K: Keychain, P: AsRef<Path>>(&self, keychain: &mut K, path: P) -> Result<(), FortressError> {
+		let secret = Zeroizing::new(HEXLOWER.encode(&self.file_key_suite.to_bytes()));
+
+		keychain.set(&keychain_account(path.as_ref()), &secret)
+	}
+
+	/// Opens the database file at `path` with the keys `remember_in_keychain` kept in `keychain`.  Returns None if there are no
+	/// keys for it or they no longer open it (they're removed from the keychain then), in which case the password has to be used.
+	pub fn unlock_with_keychain<K: Keychain, P: AsRef<Path>>(keychain: &mut K, path: P) -> Result<Option<Database>, FortressError> {
+		let path = path.as_ref();
+		let account = keychain_account(path);
+		let Some(secret) = keychain.get(&account)? else {
+			return Ok(None);
+		};
+		let file_key_suite = HEXLOWER
+			.decode(secret.as_bytes())
+			.ok()
+			.map(Zeroizing::new)
+			.and_then(|bytes| FileKeySuite::from_bytes(&bytes));
+		let load = |file_key_suite| journal::load_with_journal(path, None, |data| migration::load_with_file_keys(data, file_key_suite));
+
+		match file_key_suite.map(load) {
+			Some(Ok(database)) => Ok(Some(database)),
+			Some(Err(FortressError::PasswordRequired)) => Ok(None),
+			None | Some(Err(FortressError::CryptoError(CryptoError::DecryptionError | CryptoError::UnsupportedVersion))) => {
+				keychain.delete(&account)?;
+				Ok(None)
+			},
+			Some(Err(err)) => Err(err),
+		}
+	}
+
+	/// Removes the keys for the database file at `path` from `keychain`, so it can only be opened with its password.
+	pub fn forget_in_keychain<K: Keychain, P: AsRef<Path>>(keychain: &mut K, path: P) -> Result<(), FortressError> {
+		keychain.delete(&keychain_account(path.as_ref()))
+	}
+}
+
+
+fn keychain_account(path: &Path) -> String {
+	let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+	HEXLOWER.encode(path.as_os_str().as_encoded_bytes())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::Keychain;
+	use crate::{Database, FortressError};
+	use fortresscrypto::Zeroizing;
+	use std::collections::HashMap;
+	use tempfile::tempdir;
+
+	#[derive(Default)]
+	struct MemoryKeychain(HashMap<String, String>);
+
+	impl Keychain for MemoryKeychain {
+		fn get(&mut self, account: &str) -> Result<Option<Zeroizing<String>>, FortressError> {
+			Ok(self.0.get(account).map(|secret| Zeroizing::new(secret.clone())))
+		}
+
+		fn set(&mut self, account: &str, secret: &str) -> Result<(), FortressError> {
+			self.0.insert(account.to_owned(), secret.to_owned());
+			Ok(())
+		}
+
+		fn delete(&mut self, account: &str) -> Result<(), FortressError> {
+			self.0.remove(account);
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn keychain_unlock() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let other_path = tmp_dir.path().join("other.fortressdb");
+		let mut keychain = MemoryKeychain::default();
+		let mut db = Database::new_with_password("username", "password");
+		db.new_entry();
+		db.save_to_path(&path).unwrap();
+		db.save_to_path(&other_path).unwrap();
+
+		assert!(Database::unlock_with_keychain(&mut keychain, &path).unwrap().is_none());
+
+		db.remember_in_keychain(&mut keychain, &path).unwrap();
+		assert!(!keychain.0.values().any(|secret| secret.contains("password")));
+		let unlocked = Database::unlock_with_keychain(&mut keychain, &path).unwrap().unwrap();
+		assert_eq!(unlocked.list_entries().count(), 1);
+		assert_eq!(unlocked.get_login_key(), db.get_login_key());
+		// Saving with the keys from the keychain still opens with the password
+		unlocked.save_to_path(&path).unwrap();
+		Database::load_from_path(&path, "password").unwrap();
+
+		// Keys are per file
+		assert!(Database::unlock_with_keychain(&mut keychain, &other_path).unwrap().is_none());
+
+		// Keys that no longer open the file are forgotten
+		let mut changed = Database::load_from_path(&path, "password").unwrap();
+		changed.change_password("username", "password2").unwrap();
+		changed.save_to_path(&path).unwrap();
+		assert!(Database::unlock_with_keychain(&mut keychain, &path).unwrap().is_none());
+		assert!(keychain.0.is_empty());
+
+		changed.remember_in_keychain(&mut keychain, &path).unwrap();
+		assert!(Database::unlock_with_keychain(&mut keychain, &path).unwrap().is_some());
+		Database::forget_in_keychain(&mut keychain, &path).unwrap();
+		assert!(Database::unlock_with_keychain(&mut keychain, &path).unwrap().is_none());
+	}
+}
@@ -0,0 +1,299 @@
+// Import of databases saved in the legacy fortress1-scrypt-chacha20 format (see ENCRYPTION.md).
+// fortress1 databases were a flat list of entries, each with a history of edits to a fixed set of fields.  Each edit holds only
+// some of the fields, so they're converted to EntryHistory holding only the fields that actually changed; edits that didn't
+// change anything are dropped.  fortress1 had no directories (everything ends up in the root) and no sync, so a username must
+// be supplied.
+//
+// The decrypted payload is gzip compressed JSON; tools/fortress_v1_to_fortress_v2.py converts the same JSON, and
+// tools/fortress_v1_encrypt.py made tests/data/legacy.fortress1 from tests/data/legacy.fortress1.json.
+use crate::{
+	migration::{self, MigrationContext},
+	CancellationToken, Database, Entry, EntryHistory, FortressError, ID,
+};
+use flate2::read::GzDecoder;
+use fortresscrypto::{Zeroize, Zeroizing};
+use serde::Deserialize;
+use std::{
+	collections::{HashMap, HashSet},
+	fs::{self, File},
+	io::{self, BufReader, Cursor, Read},
+	path::{Path, PathBuf},
+};
+
+
+#[derive(Deserialize)]
+pub(crate) struct LegacyDatabase {
+	entries: Vec<LegacyEntry>,
+}
+
+#[derive(Deserialize)]
+struct LegacyEntry {
+	id: ID,
+	history: Vec<LegacyEntryData>,
+}
+
+#[derive(Deserialize)]
+struct LegacyEntryData {
+	#[serde(default)]
+	title: Option<String>,
+	#[serde(default)]
+	username: Option<String>,
+	#[serde(default)]
+	password: Option<String>,
+	#[serde(default)]
+	url: Option<String>,
+	#[serde(default)]
+	notes: Option<String>,
+	time_created: u64, // Unix timestamp (seconds)
+}
+
+impl Drop for LegacyEntryData {
+	fn drop(&mut self) {
+		for field in [&mut self.title, &mut self.username, &mut self.password, &mut self.url, &mut self.notes]
+			.into_iter()
+			.flatten()
+		{
+			field.zeroize();
+		}
+	}
+}
+
+impl LegacyEntry {
+	fn convert(self) -> Result<Entry, FortressError> {
+		// fortress1 entries were created along with their first edit
+		let time_created = self.history.first().ok_or(FortressError::LegacyImportError)?.time_created;
+		let mut history = Vec::with_capacity(self.history.len());
+		let mut state = HashMap::new();
+		let mut min_next_timestamp = 0;
+
+		for data in self.history {
+			// fortress1 timestamps only had one second resolution, so history items may need nudging to stay strictly ordered.
+			let time = data.time_created.saturating_mul(1_000_000_000).max(min_next_timestamp);
+			let fields = [
+				("title", &data.title),
+				("username", &data.username),
+				("password", &data.password),
+				("url", &data.url),
+				("notes", &data.notes),
+			];
+			let changed: HashMap<String, String> = fields
+				.into_iter()
+				.filter_map(|(key, value)| Some((key, value.as_deref()?)))
+				.filter(|(key, value)| state.get(*key).map_or(!value.is_empty(), |old| old != value))
+				.map(|(key, value)| (key.to_string(), value.to_string()))
+				.collect();
+
+			if changed.is_empty() {
+				continue;
+			}
+
+			state.extend(changed.iter().map(|(key, value)| (key.clone(), value.clone())));
+			history.push(EntryHistory {
+				time,
+				data: changed,
+				metadata: HashMap::new(),
+				field_order: None,
+				tags: HashMap::new(),
+				device: None,
+			});
+			min_next_timestamp = time + 1;
+		}
+
+		Entry::inner_new(self.id, history, time_created.saturating_mul(1_000_000_000)).ok_or(FortressError::LegacyImportError)
+	}
+}
+
+
+impl LegacyDatabase {
+	pub(crate) fn parse(data: &[u8], password: &str) -> Result<LegacyDatabase, FortressError> {
+		let compressed = fortresscrypto::decrypt_legacy_from_file(&mut Cursor::new(data), password.as_bytes())?;
+		let mut plaintext = Zeroizing::new(Vec::new());
+		GzDecoder::new(&compressed[..])
+			.read_to_end(&mut plaintext)
+			.map_err(|_| FortressError::LegacyImportError)?;
+
+		Ok(serde_json::from_slice(&plaintext)?)
+	}
+
+	/// Converts to the current model.  fortress1 databases have no notion of a username, so one must be provided for sync.
+	pub(crate) fn upgrade(self, username: &str, password: &str) -> Result<Database, FortressError> {
+		let mut entries = self.entries;
+		entries.sort_unstable_by_key(|entry| entry.id);
+
+		let mut database = Database::new_with_password(username, password);
+		let mut seen = HashSet::new();
+
+		for entry in entries {
+			let id = entry.id;
+			if !seen.insert(id) {
+				return Err(FortressError::LegacyImportError);
+			}
+
+			database.add_imported_entry(entry.convert()?)?;
+			database.get_root_mut().add(id);
+		}
+
+		Ok(database)
+	}
+}
+
+
+impl Database {
+	/// Reads a database saved in the legacy fortress1 format (or any later format) and converts it to a new Database.
+	/// fortress1 databases have no notion of a username, so one must be provided for sync.
+	pub fn import_legacy_from_reader<U: AsRef<str>, P: AsRef<str>, R: Read>(username: U, password: P, reader: &mut R) -> Result<Database, FortressError> {
+		let mut data = Vec::new();
+		reader.read_to_end(&mut data)?;
+
+		migration::load(
+			&data,
+			&MigrationContext {
+				password: password.as_ref(),
+				username: Some(username.as_ref()),
+				lazy_entries: false,
+				hardware_key: &|_| None,
+			},
+		)
+	}
+
+	pub fn import_legacy_from_path<Q: AsRef<Path>, U: AsRef<str>, P: AsRef<str>>(path: Q, username: U, password: P) -> Result<Database, FortressError> {
+		let file = File::open(path)?;
+		let mut reader = BufReader::new(file);
+
+		Self::import_legacy_from_reader(username, password, &mut reader)
+	}
+
+	/// Same as `import_legacy_from_path`, but returns FortressError::Cancelled as soon as `cancel` is cancelled.
+	pub fn import_legacy_from_path_cancellable<Q: AsRef<Path>, U: AsRef<str>, P: AsRef<str>>(
+		path: Q,
+		username: U,
+		password: P,
+		cancel: &CancellationToken,
+	) -> Result<Database, FortressError> {
+		let data = fs::read(path)?;
+
+		crate::load_cancellable(data, password.as_ref(), Some(username.as_ref()), cancel)
+	}
+
+	/// Converts the legacy fortress1 database at `path` and saves it back to `path` in the current format.
+	/// The original file is kept alongside the new one with a `.bak` suffix; the path of that backup is returned.
+	pub fn upgrade_legacy_file<Q: AsRef<Path>, U: AsRef<str>, P: AsRef<str>>(path: Q, username: U, password: P) -> Result<PathBuf, FortressError> {
+		let path = path.as_ref();
+		let database = Database::import_legacy_from_path(path, username, password)?;
+
+		let backup_path = crate::backup_path(path);
+		fs::copy(path, &backup_path)?;
+		database.save_to_path(path)?;
+
+		Ok(backup_path)
+	}
+}
+
+
+/// Returns true if the file at `path` is in the legacy fortress1 format.
+pub fn is_legacy_database<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+	let mut header = Vec::new();
+	File::open(path)?.take(64).read_to_end(&mut header)?;
+
+	Ok(fortresscrypto::is_legacy_file(&header))
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::LegacyDatabase;
+	use crate::{is_legacy_database, Database, FortressError, ID};
+	use flate2::read::GzDecoder;
+	use serde_json::json;
+	use std::{
+		fs,
+		io::{Cursor, Read},
+	};
+	use tempfile::tempdir;
+
+	const LEGACY_DATABASE: &[u8] = include_bytes!("../tests/data/legacy.fortress1");
+	const LEGACY_JSON: &str = include_str!("../tests/data/legacy.fortress1.json");
+
+	#[test]
+	fn import_legacy() {
+		let db = Database::import_legacy_from_reader("username", "password", &mut Cursor::new(LEGACY_DATABASE)).unwrap();
+		assert_eq!(db.get_username(), "username");
+		assert_eq!(db.list_entries().count(), 2);
+
+		let entry = db.get_entry_by_id(&ID::from_slice(&[0x11; 32]).unwrap()).unwrap();
+		assert_eq!(db.get_parent_directory(entry.get_id()).unwrap().get_id(), db.get_root().get_id());
+		assert_eq!(entry.get_time_created(), 1_500_000_000_000_000_000);
+		assert_eq!(entry["title"], "Example");
+		assert_eq!(entry["username"], "alice");
+		assert_eq!(entry["password"], "hunter3");
+		assert_eq!(entry["url"], "");
+		assert_eq!(entry["notes"], "Moved");
+
+		// Edits keep only the fields that changed; edits that changed nothing are dropped and same-second edits stay ordered
+		let history = entry.get_history();
+		assert_eq!(history.len(), 3);
+		assert_eq!(history[0].data.len(), 4);
+		assert_eq!(history[1].time, history[0].time + 1);
+		assert_eq!(history[1].data.keys().collect::<Vec<_>>(), ["password"]);
+		assert_eq!(history[2].time, 1_500_000_200_000_000_000);
+		assert_eq!(history[2].data.len(), 2);
+
+		let entry = db.get_entry_by_id(&ID::from_slice(&[0x22; 32]).unwrap()).unwrap();
+		assert_eq!(entry["password"], "correct horse");
+		assert_eq!(entry.get("url"), None);
+
+		assert!(matches!(
+			Database::import_legacy_from_reader("username", "wrong", &mut Cursor::new(LEGACY_DATABASE)),
+			Err(FortressError::CryptoError(_))
+		));
+	}
+
+	#[test]
+	fn legacy_schema() {
+		// The fixture is the JSON next to it, compressed and encrypted
+		let compressed = fortresscrypto::decrypt_legacy_from_file(&mut Cursor::new(LEGACY_DATABASE), b"password").unwrap();
+		let mut plaintext = String::new();
+		GzDecoder::new(&compressed[..]).read_to_string(&mut plaintext).unwrap();
+		assert_eq!(
+			serde_json::from_str::<serde_json::Value>(&plaintext).unwrap(),
+			serde_json::from_str::<serde_json::Value>(LEGACY_JSON).unwrap()
+		);
+
+		let parse = |json: serde_json::Value| serde_json::from_value::<LegacyDatabase>(json)?.upgrade("username", "password");
+		let entry = |id: &str, history: serde_json::Value| json!({"id": id.repeat(32), "history": history});
+
+		// Entries with no history, or listed twice, can't be converted
+		assert!(matches!(
+			parse(json!({"entries": [entry("33", json!([]))]})),
+			Err(FortressError::LegacyImportError)
+		));
+		let history = json!([{"time_created": 1, "title": "Twice"}]);
+		assert!(matches!(
+			parse(json!({"entries": [entry("33", history.clone()), entry("33", history.clone())]})),
+			Err(FortressError::LegacyImportError)
+		));
+		let db = parse(json!({"entries": [entry("33", history)]})).unwrap();
+		assert_eq!(db.list_entries().count(), 1);
+	}
+
+	#[test]
+	fn upgrade_legacy_file() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("database.fortress");
+		fs::write(&path, LEGACY_DATABASE).unwrap();
+
+		assert!(is_legacy_database(&path).unwrap());
+		assert!(matches!(Database::load_from_path(&path, "password"), Err(FortressError::LegacyDatabase)));
+
+		let backup_path = Database::upgrade_legacy_file(&path, "username", "password").unwrap();
+		assert_eq!(fs::read(&backup_path).unwrap(), LEGACY_DATABASE);
+		assert!(!is_legacy_database(&path).unwrap());
+
+		let db = Database::load_from_path(&path, "password").unwrap();
+		let imported = Database::import_legacy_from_reader("username", "password", &mut Cursor::new(LEGACY_DATABASE)).unwrap();
+		assert_eq!(db.list_entries().count(), 2);
+		for entry in imported.list_entries() {
+			assert_eq!(db.get_entry_by_id(entry.get_id()), Some(entry));
+		}
+	}
+}
@@ -18,36 +18,177 @@
 // NOTE: Changing any of these structs which derive Serialize/Deserialize requires
 // bumping the database format version.
 //
+// NOTE: Secrets are wiped from memory when they are dropped, on a best-effort basis.
+// Key material is protected by fortresscrypto's secret newtypes; Entry and EntryHistory
+// zeroize their field keys and values; and serialized/decrypted database payloads are held
+// in Zeroizing buffers.  Copies made by HashMap reallocation, serde_json, or callers of this
+// library are not covered.
+//
 // NOTE: No versioning is currently included for cloud objects.  The plan is to
 // add versioning the next time the format changes, and to change the way the
 // network and login keys are calculated to prevent old versions from syncing.
 // We can then have a plan for more graceful versioning going forward.
+// The older tests predate some of clippy's style lints
+#![cfg_attr(
+	test,
+	allow(
+		clippy::bool_assert_comparison,
+		clippy::clone_on_copy,
+		clippy::for_kv_map,
+		clippy::needless_borrow,
+		clippy::partialeq_to_none,
+		clippy::single_component_path_imports
+	)
+)]
+
 #[macro_use]
 mod newtype_macros;
+mod account_change;
+mod audit;
+mod backups;
+#[cfg(feature = "breach")]
+pub mod breach;
+mod browser_csv;
+mod cancellation;
+mod change_log;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+mod compaction;
 mod database_object;
 mod database_object_map;
+mod derivation_cache;
+mod device;
+mod diagnostics;
+mod double_lock;
 mod errors;
+mod export;
+mod file_changes;
+mod file_lock;
+mod gc;
+mod generator;
+#[cfg(any(test, feature = "testing"))]
+pub mod golden;
+mod hardware_key;
+mod id_policy;
+mod import;
+mod integrity;
+mod journal;
+mod key_rotation;
+#[cfg(feature = "keychain")]
+pub mod keychain;
+mod legacy;
+mod limits;
+mod local_settings;
+mod login_key_rotation;
+mod migration;
+mod object_index;
+mod onepassword;
+mod paper_backup;
+mod password_expiry;
+mod password_rotation;
+mod progress;
+mod readonly_sync;
+mod recent;
+mod search;
+mod secure_note;
+mod selection_export;
+mod selective_sync;
+mod share;
+mod signature;
+mod snapshot;
+mod stats;
+mod sync_account;
+mod sync_capabilities;
+mod sync_estimate;
+mod sync_options;
 pub mod sync_parameters;
+mod sync_report;
+mod sync_targets;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod time_source;
+mod transaction;
+mod trash;
+mod url_match;
+mod usage;
+mod users;
+
+pub use crate::database_object::{
+	is_protected_field, DerivedField, Directory, Entry, EntryHistory, Field, FieldChange, FieldKind, FieldMetadata, SettingsObject, Tombstone, REDACTED_VALUE,
+	RENAME_CONFLICT_WINDOW, SETTINGS_ID,
+};
 
-pub use crate::database_object::{Directory, Entry, EntryHistory};
-
-use crate::{database_object::DatabaseObject, database_object_map::DatabaseObjectMap, sync_parameters::SyncParameters};
-pub use errors::FortressError;
+use crate::{database_object::DatabaseObject, database_object_map::DatabaseObjectMap, migration::MigrationContext, sync_parameters::SyncParameters};
+pub use account_change::AccountChangeConfirmed;
+pub use audit::{estimate_password_strength, AuditOptions, AuditReport, OLD_PASSWORD_AGE};
+pub use backups::BackupPolicy;
+pub use browser_csv::IMPORT_CONFLICT_TAG;
+pub use cancellation::CancellationToken;
+pub use change_log::{ChangeAction, ChangeLogFormat, ChangeRecord};
+pub use compaction::{CompactionReport, HistoryLossConfirmed};
+use derivation_cache::DerivationCache;
+pub use derivation_cache::DERIVATION_CACHE_LIMIT;
+use device::DeviceIdentity;
+pub use device::{DeviceId, DEVICE_NAME_SETTING_PREFIX};
+use diagnostics::SkippedObject;
+pub use double_lock::{DOUBLE_LOCK_CLEAR_FIELDS, DOUBLE_LOCK_FIELD};
+pub use errors::{FortressError, HistoryOrderError};
+pub use export::{ExportOptions, EXPORT_FORMAT, EXPORT_VERSION};
+use file_changes::{FileVersion, FileVersionState};
+use file_lock::FileLockState;
 pub use fortresscrypto;
-use fortresscrypto::{EncryptedObject, FileKeySuite, LoginId, LoginKey, SIV};
-use rand::{rngs::OsRng, seq::SliceRandom, Rng};
-use reqwest::{IntoUrl, Method};
-use serde::{Deserialize, Serialize};
+use fortresscrypto::{
+	CryptoError, EncryptedObject, FileKdfParameters, FileKeySuite, HardwareKeyFn, LoginId, LoginKey, NetworkKeySuite, ShareSecretKey, Zeroizing, SIV,
+};
+pub use gc::GC_HORIZON_SETTING;
+pub use generator::{GeneratorOptions, AMBIGUOUS_CHARACTERS, MAX_GENERATED_LENGTH, WEAK_ENTROPY_BITS};
+pub use id_policy::is_reserved_id;
+pub use import::{ImportPlan, ImportReport, ImportedDirectory, ImportedEntry, ImportedField};
+pub use integrity::{DuplicateReference, FutureHistory, IntegrityReport, MissingReference, FUTURE_HISTORY_TOLERANCE};
+use journal::JournalState;
+use key_rotation::KeyRotation;
+pub use legacy::is_legacy_database;
+pub use limits::{SyncLimit, MAX_FIELD_SIZE, MAX_HISTORY_LENGTH, MAX_OBJECT_COUNT, MAX_OBJECT_SIZE};
+pub use local_settings::{LocalSettings, WindowSize, LOCAL_SETTINGS_VERSION};
+pub use migration::{FormatVersion, CURRENT_FORMAT_VERSION};
+pub use onepassword::ARCHIVED_TAG;
+pub use paper_backup::{PaperBackupEntries, PaperBackupOptions, PAPER_BACKUP_FORMAT, PAPER_BACKUP_TAG, PAPER_BACKUP_VERSION};
+pub use password_expiry::{PasswordExpiry, PASSWORD_EXPIRY_FIELD};
+pub use password_rotation::PasswordRotation;
+pub use progress::{DeriveProgress, SyncPhase, SyncProgress};
+use rand::Rng;
+pub use readonly_sync::ReadonlySyncKeys;
+use recent::RecentEntries;
+pub use recent::RECENT_ENTRIES_LIMIT;
+use reqwest::Method;
+pub use search::{SearchHit, SearchMatch, SearchTarget};
+pub use secure_note::{NoteFormat, ENTRY_TYPE_FIELD, NOTE_ENTRY_TYPE, NOTE_FORMAT_FIELD};
+pub use selection_export::SelectionFormat;
+use serde::Serialize;
+pub use signature::{CanonicalDigest, SignedDigest, SIGNATURE_FORMAT, SIGNATURE_VERSION};
+pub use snapshot::DatabaseSnapshot;
+pub use stats::DatabaseStats;
 use std::{
-	collections::{HashMap, HashSet},
-	fs::File,
-	io::{self, BufReader, BufWriter},
-	path::Path,
+	collections::{BTreeSet, HashMap, HashSet},
+	fs,
+	io::{self, BufWriter, Cursor, Write},
+	path::{Path, PathBuf},
 	str,
+	time::{Duration, Instant},
 };
+pub use sync_account::{AccountDeletionConfirmed, SyncAccountInfo};
+pub use sync_capabilities::SyncCapabilities;
+pub use sync_estimate::SyncSizeEstimate;
+pub use sync_options::SyncOptions;
+use sync_options::{api_request, SyncClient};
 use sync_parameters::FrozenSyncParameters;
+pub use sync_report::{SyncActivity, SyncChange, SyncConflict, SyncObjectKind, SyncReport};
+pub use sync_targets::SyncTarget;
 use tempfile::NamedTempFile;
+pub use time_source::{SteppingClock, SystemClock, TimeSource};
+pub use trash::TrashedObject;
 use url::Url;
+pub use url_match::{normalize_url, URL_MATCH_EXACT, URL_MATCH_FIELD};
 
 
 new_type! {
@@ -56,6 +197,11 @@ new_type! {
 
 
 const ROOT_DIRECTORY_ID: ID = ID([0; 32]);
+// Created the first time something is trashed (see the trash module)
+const TRASH_DIRECTORY_ID: ID = ID([0xfe; 32]);
+
+// Maximum number of download/upload rounds in a single sync, so a misbehaving server can't keep us syncing forever.
+const MAX_SYNC_ROUNDS: usize = 16;
 
 
 // TODO: Not sure if we want this to be cloneable?
@@ -63,26 +209,78 @@ const ROOT_DIRECTORY_ID: ID = ID([0; 32]);
 pub struct Database {
 	objects: DatabaseObjectMap,
 
-	/// This can be None while it is being generated in the background.
+	/// Its keys may still be being derived in the background (see `new_with_password_async`).
 	sync_parameters: SyncParameters,
 	sync_url: Option<Url>,
+	/// SOCKS5 proxy to sync through, e.g. Tor (see the sync_options module).  Local to this device.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	sync_proxy: Option<Url>,
 	/// If password is changed, this is set to the old sync parameters until the server is successfully told about the change.
 	old_sync_parameters: Option<FrozenSyncParameters>,
+	/// Servers synced with besides the one at sync_url (see the sync_targets module).
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	sync_targets: Vec<SyncTarget>,
+	/// The old network keys while objects on the server are re-encrypted under new ones (see the key_rotation module).
+	#[serde(skip_serializing_if = "Option::is_none")]
+	key_rotation: Option<KeyRotation>,
+	/// Objects whose history was compacted (and the cutoff used), whose server copies need compacting the same way on the next sync.
+	#[serde(skip_serializing_if = "HashMap::is_empty")]
+	compacted_objects: HashMap<ID, u64>,
+	/// The account this database belongs to: the one it was created with or last synced with (see the account_change module).
+	/// None for databases saved before this was recorded, until their next sync.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	account_login_id: Option<LoginId>,
+	/// Local to this device, and only saved if persistence was turned on (see the recent module).
+	#[serde(skip_serializing_if = "RecentEntries::is_transient")]
+	recent_entries: RecentEntries,
+	/// Network keys derived for the current and previous username and password (see the derivation_cache module).  Only whether
+	/// it's turned on is saved.
+	#[serde(skip_serializing_if = "DerivationCache::is_default")]
+	derivation_cache: DerivationCache,
+	/// This device's identity, once it has been named (see the device module).  Local to this device.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	device: Option<DeviceIdentity>,
+	/// The secret half of this database's share key pair (see the share module).  Local to this device.
+	share_key: ShareSecretKey,
+	/// How many previous versions of the file are kept when saving (see the backups module).  Local to this device.
+	#[serde(skip_serializing_if = "BackupPolicy::is_off")]
+	backup_policy: BackupPolicy,
+	/// Whether saves also write a signed digest of the database (see the signature module).  Local to this device.
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	sign_saves: bool,
 
 	#[serde(skip_serializing, skip_deserializing)]
 	file_key_suite: FileKeySuite,
+	#[serde(skip_serializing, skip_deserializing)]
+	original_format_version: FormatVersion,
+	#[serde(skip_serializing, skip_deserializing)]
+	journal: JournalState,
+	#[serde(skip_serializing, skip_deserializing)]
+	file_lock: FileLockState,
+	#[serde(skip_serializing, skip_deserializing)]
+	file_version: FileVersionState,
 }
 
 impl Database {
 	pub fn new_with_password<U: AsRef<str>, P: AsRef<str>>(username: U, password: P) -> Database {
-		let username = username.as_ref();
-		let password = password.as_ref();
+		let (file_key_suite, sync_parameters) = derive_keys(username.as_ref(), password.as_ref(), None, None);
+		let mut database = Database::new_with_keys(file_key_suite, sync_parameters);
+		database.cache_sync_keys(password.as_ref());
+		database
+	}
 
-		let encryption_parameters = Default::default();
-		let file_key_suite = FileKeySuite::derive(password.as_bytes(), &encryption_parameters).expect("Internal error: Scrypt parameters were invalid.");
+	/// Same as `new_with_password`, but only derives the (much quicker) file keys before returning.  The sync keys are derived
+	/// on a worker thread, and the database picks them up once they're done.  Until then, anything that needs them blocks (see
+	/// `sync_keys_pending`), except sync, which waits for them cancellably.
+	/// If the database is saved before the sync keys are done, they're derived again when it's next opened.
+	pub fn new_with_password_async<U: AsRef<str>, P: AsRef<str>>(username: U, password: P) -> Database {
+		let file_key_suite = FileKeySuite::derive(password.as_ref().as_bytes(), &Default::default()).expect("Internal error: Scrypt parameters were invalid.");
+		let sync_parameters = SyncParameters::pending(SyncParameters::derive_async(username, password));
+		Database::new_with_keys(file_key_suite, sync_parameters)
+	}
 
-		// TODO: Derive in a background thread
-		let sync_parameters = SyncParameters::new(username, password);
+	fn new_with_keys(file_key_suite: FileKeySuite, sync_parameters: SyncParameters) -> Database {
+		let account_login_id = Some(*sync_parameters.get_login_id());
 
 		let root = Directory::new_root();
 		let mut objects = DatabaseObjectMap::new();
@@ -93,25 +291,143 @@ impl Database {
 			sync_parameters,
 			file_key_suite,
 			sync_url: None,
+			sync_proxy: None,
 			old_sync_parameters: None,
+			sync_targets: Vec::new(),
+			key_rotation: None,
+			compacted_objects: HashMap::new(),
+			account_login_id,
+			recent_entries: RecentEntries::default(),
+			derivation_cache: DerivationCache::default(),
+			device: None,
+			share_key: ShareSecretKey::generate(),
+			backup_policy: BackupPolicy::default(),
+			sign_saves: false,
+			original_format_version: CURRENT_FORMAT_VERSION,
+			journal: JournalState::default(),
+			file_lock: FileLockState::default(),
+			file_version: FileVersionState::default(),
 		}
 	}
 
-	pub fn change_password<A: AsRef<str>, B: AsRef<str>>(&mut self, username: A, password: B) {
-		let username = username.as_ref();
-		let password = password.as_ref();
+	/// Same as `new_with_password`, but returns FortressError::Cancelled as soon as `cancel` is cancelled.
+	pub fn new_with_password_cancellable<U: AsRef<str>, P: AsRef<str>>(
+		username: U,
+		password: P,
+		cancel: &CancellationToken,
+	) -> Result<Database, FortressError> {
+		Database::new_with_password_and_progress(username, password, cancel, &mut |_| {})
+	}
+
+	/// Same as `new_with_password_cancellable`, reporting the progress of the key derivation to `progress`.
+	pub fn new_with_password_and_progress<U: AsRef<str>, P: AsRef<str>>(
+		username: U,
+		password: P,
+		cancel: &CancellationToken,
+		progress: &mut dyn FnMut(DeriveProgress),
+	) -> Result<Database, FortressError> {
+		let username = Zeroizing::new(username.as_ref().to_string());
+		let password = Zeroizing::new(password.as_ref().to_string());
+
+		progress::derive_with_progress(
+			cancel,
+			estimate_derive_keys_time(true),
+			move || Database::new_with_password(username.as_str(), password.as_str()),
+			progress,
+		)
+	}
 
-		let encryption_parameters = Default::default();
-		self.file_key_suite = FileKeySuite::derive(password.as_bytes(), &encryption_parameters).expect("Internal error: Scrypt parameters were invalid.");
+	/// Keys for the previous username and password are taken from the derivation cache, which skips the slow network key
+	/// derivation (see the derivation_cache module), and any older ones are forgotten.  Databases that need a hardware key keep
+	/// needing the same one.
+	/// Fails with FortressError::NotDatabasePassword if the database was opened with another user's password (see the users
+	/// module), since the password is the database's own.
+	pub fn change_password<A: AsRef<str>, B: AsRef<str>>(&mut self, username: A, password: B) -> Result<(), FortressError> {
+		self.check_database_password()?;
+		let cached = self.derivation_cache.get(username.as_ref(), password.as_ref());
+		let (file_key_suite, sync_parameters) = derive_keys(username.as_ref(), password.as_ref(), cached, Some(&self.file_key_suite));
+		self.set_keys(file_key_suite, sync_parameters);
+		self.cache_sync_keys(password.as_ref());
+
+		Ok(())
+	}
+
+	/// Same as `change_password`, but returns FortressError::Cancelled as soon as `cancel` is cancelled, in which case the password
+	/// is left unchanged.
+	pub fn change_password_cancellable<A: AsRef<str>, B: AsRef<str>>(
+		&mut self,
+		username: A,
+		password: B,
+		cancel: &CancellationToken,
+	) -> Result<(), FortressError> {
+		self.change_password_with_progress(username, password, cancel, &mut |_| {})
+	}
+
+	/// Same as `change_password_cancellable`, reporting the progress of the key derivation to `progress`.
+	pub fn change_password_with_progress<A: AsRef<str>, B: AsRef<str>>(
+		&mut self,
+		username: A,
+		password: B,
+		cancel: &CancellationToken,
+		progress: &mut dyn FnMut(DeriveProgress),
+	) -> Result<(), FortressError> {
+		self.check_database_password()?;
+		let cached = self.derivation_cache.get(username.as_ref(), password.as_ref());
+		let estimated = estimate_derive_keys_time(cached.is_none());
+		let worker_username = Zeroizing::new(username.as_ref().to_string());
+		let worker_password = Zeroizing::new(password.as_ref().to_string());
+		let current_file_key_suite = self.file_key_suite.clone();
+
+		let (file_key_suite, sync_parameters) = progress::derive_with_progress(
+			cancel,
+			estimated,
+			move || derive_keys(&worker_username, &worker_password, cached, Some(&current_file_key_suite)),
+			progress,
+		)?;
+		self.set_keys(file_key_suite, sync_parameters);
+		self.cache_sync_keys(password.as_ref());
+
+		Ok(())
+	}
+
+	fn set_keys(&mut self, file_key_suite: FileKeySuite, sync_parameters: SyncParameters) {
+		self.file_key_suite = file_key_suite;
 
 		// Don't need to inform the server if we're changing username
-		if username == self.sync_parameters.get_username() {
-			// TODO: When we implement background generation, this should instead return an error
+		if sync_parameters.get_username() == self.sync_parameters.get_username() {
+			// A database's sync keys are always either derived or being derived (files saved without them derive them again on
+			// load), and freeze waits for a pending derivation, so it can't return None here
 			self.old_sync_parameters = Some(self.sync_parameters.freeze().expect("internal error"));
 		}
 
-		// TODO: Derive in a background thread
-		self.sync_parameters = SyncParameters::new(username, password);
+		self.sync_parameters = sync_parameters;
+	}
+
+	/// Remembers the current sync keys, derived from `password`, in the derivation cache.  Keys still being derived in the
+	/// background are left out.
+	pub(crate) fn cache_sync_keys(&mut self, password: &str) {
+		if self.sync_parameters.is_pending() {
+			return;
+		}
+
+		if let Some(network_key_suite) = self.sync_parameters.get_network_key_suite() {
+			self.derivation_cache.insert(self.sync_parameters.get_username(), password, network_key_suite);
+		}
+	}
+
+	/// Forgets the keys remembered for the previous username and password, and the current ones until the next time the database
+	/// is opened or its password changes.
+	pub fn clear_derivation_cache(&mut self) {
+		self.derivation_cache.clear();
+	}
+
+	/// Turns the derivation cache on or off (it's on by default).  Turning it off clears it.
+	pub fn set_derivation_cache_enabled(&mut self, enabled: bool) {
+		self.derivation_cache.set_enabled(enabled);
+	}
+
+	pub fn is_derivation_cache_enabled(&self) -> bool {
+		self.derivation_cache.is_enabled()
 	}
 
 	pub fn get_username(&self) -> &str {
@@ -122,10 +438,16 @@ impl Database {
 		self.sync_parameters.get_login_id()
 	}
 
+	/// Blocks until the sync keys are derived if they're still pending.
 	pub fn get_login_key(&self) -> &LoginKey {
 		self.sync_parameters.get_login_key().expect("internal error")
 	}
 
+	/// True while the sync keys are still being derived in the background (see `new_with_password_async`).
+	pub fn sync_keys_pending(&self) -> bool {
+		self.sync_parameters.is_pending()
+	}
+
 	pub fn get_sync_url(&self) -> Option<&Url> {
 		self.sync_url.as_ref()
 	}
@@ -147,12 +469,17 @@ impl Database {
 		self.add_entry(entry);
 	}
 
-	pub fn add_entry(&mut self, entry: Entry) {
+	/// Adds a new entry to the root directory.  Any of its edits that aren't attributed to a device are attributed to this one
+	/// (see the device module).
+	pub fn add_entry(&mut self, mut entry: Entry) {
+		entry.attribute_to(self.get_device_id().copied());
 		self.get_root_mut().add(*entry.get_id());
 		self.objects.update(DatabaseObject::Entry(entry));
 	}
 
-	pub fn add_directory(&mut self, directory: Directory) {
+	/// Same as `add_entry`, for directories.
+	pub fn add_directory(&mut self, mut directory: Directory) {
+		directory.attribute_to(self.get_device_id().copied());
 		self.get_root_mut().add(*directory.get_id());
 		self.objects.update(DatabaseObject::Directory(directory));
 	}
@@ -201,12 +528,102 @@ impl Database {
 		self.objects.values_mut().filter_map(|obj| obj.as_entry_mut())
 	}
 
+	/// The synced frontend settings, if any have ever been set.
+	pub fn get_settings(&self) -> Option<&SettingsObject> {
+		self.objects.get(&SETTINGS_ID)?.as_settings()
+	}
+
+	/// Sets a synced frontend setting.  The settings object is created the first time this is called.
+	/// NOTE: Older versions of Fortress can't sync databases that have a settings object.
+	pub fn set_setting<K: Into<String>>(&mut self, key: K, value: serde_json::Value) {
+		let mut settings = self.get_settings().cloned().unwrap_or_else(SettingsObject::new);
+		settings.set(key, value);
+		self.objects.update(DatabaseObject::Settings(settings));
+	}
+
+	pub fn remove_setting(&mut self, key: &str) {
+		if let Some(mut settings) = self.get_settings().cloned() {
+			settings.remove(key);
+			self.objects.update(DatabaseObject::Settings(settings));
+		}
+	}
+
+	/// Number of entries in directory `id`, including those in subdirectories if `recursive` is true.
+	/// Returns None if `id` isn't a directory.
+	pub fn count_entries_in(&self, id: &ID, recursive: bool) -> Option<usize> {
+		let directory = self.get_directory_by_id(id)?;
+
+		if recursive {
+			Some(directory.list_entries_recursive(self).len())
+		} else {
+			Some(directory.list_entries(self).len())
+		}
+	}
+
+	/// All tags used by any entry, in sorted order.
+	pub fn list_tags(&self) -> BTreeSet<&str> {
+		let index = self.objects.get_index();
+		let indexed = index
+			.tags()
+			.filter(|(tag, ids)| ids.iter().filter_map(|id| self.get_entry_by_id(id)).any(|entry| entry.has_tag(tag)))
+			.map(|(tag, _)| tag);
+		let stale = index
+			.stale()
+			.filter_map(|id| self.get_entry_by_id(id))
+			.flat_map(|entry| entry.get_tags())
+			.map(String::as_str);
+
+		indexed.chain(stale).collect()
+	}
+
+	pub fn find_by_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Entry> {
+		self.objects
+			.get_index()
+			.tag_candidates(tag)
+			.into_iter()
+			.filter_map(|id| self.get_entry_by_id(&id))
+			.filter(move |entry| entry.has_tag(tag))
+	}
+
+	/// Entries whose hex ID starts with `query`, or whose title is `query` (ignoring case), for looking entries up by something a
+	/// person can type.  Results are ordered by ID.
+	pub fn find_entries(&self, query: &str) -> Vec<&Entry> {
+		let index = self.objects.get_index();
+		let candidates = index
+			.ids_with_prefix(query)
+			.into_iter()
+			.chain(index.title_candidates(query))
+			.collect::<BTreeSet<_>>();
+
+		candidates
+			.iter()
+			.filter_map(|id| self.get_entry_by_id(id))
+			.filter(|entry| matches_id_or_name(entry.get_id(), entry.get("title").map(String::as_str), query))
+			.collect()
+	}
+
+	/// Same as `find_entries`, but for directories and their names.
+	pub fn find_directories(&self, query: &str) -> Vec<&Directory> {
+		let mut directories = self
+			.list_directories()
+			.filter(|directory| matches_id_or_name(directory.get_id(), directory.get_name(), query))
+			.collect::<Vec<_>>();
+		directories.sort_unstable_by_key(|directory| *directory.get_id());
+		directories
+	}
+
 	pub fn get_parent_directory(&self, id: &ID) -> Option<&Directory> {
-		self.list_directories().find(move |dir| dir.contains(id))
+		self.objects
+			.get_index()
+			.parent_candidates(id)
+			.iter()
+			.filter_map(|candidate| self.get_directory_by_id(candidate))
+			.find(|directory| directory.contains(id))
 	}
 
 	pub fn get_parent_directory_mut(&mut self, id: &ID) -> Option<&mut Directory> {
-		self.list_directories_mut().find(move |dir| dir.contains(id))
+		let parent = *self.get_parent_directory(id)?.get_id();
+		self.get_directory_by_id_mut(&parent)
 	}
 
 	pub fn move_object(&mut self, id: &ID, new_parent: &ID) {
@@ -217,7 +634,8 @@ impl Database {
 		}
 
 		// Add to new parent first (so the entry isn't dangling during the operation)
-		if let Some(parent) = self.get_directory_by_id_mut(new_parent) {
+		// Concurrent moves on different devices can leave an object in more than one directory, including the new parent
+		if let Some(parent) = self.get_directory_by_id_mut(new_parent).filter(|parent| !parent.contains(id)) {
 			parent.add(*id);
 		}
 
@@ -227,199 +645,549 @@ impl Database {
 		}
 	}
 
+	/// Saves the whole database to `path`, replacing its journal if it had one (see `save_incremental`).
 	pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), FortressError> {
-		// Create a temporary file to write to
-		let mut temp_file = {
-			let parent_directory = path.as_ref().parent().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Bad path"))?;
-			NamedTempFile::new_in(parent_directory)?
-		};
+		self.write_snapshot(path.as_ref()).map(|_| ())
+	}
+
+	// Returns what was written, which the journal needs the checksum of
+	fn write_snapshot(&self, path: &Path) -> Result<Vec<u8>, FortressError> {
+		let _span = diagnostics::save_span(path);
+		self.file_lock.lock(path)?;
 
 		// Serialized payload
-		let payload = serde_json::to_vec(&self)?;
+		let payload = Zeroizing::new(serde_json::to_vec(&self)?);
+
+		// Encrypt and write to the destination
+		let mut file = Vec::new();
+		fortresscrypto::encrypt_to_file(&mut file, &payload, &self.file_key_suite)?;
+		backups::rotate(path, self.backup_policy)?;
+		persist_atomically(path, |writer| writer.write_all(&file))?;
+		journal::remove_journal(path);
+		signature::write_save_signature(self, path)?;
+		self.file_version
+			.set(FileVersion::new(path, file[file.len() - 32..].try_into().expect("internal error"), 0));
+
+		Ok(file)
+	}
+
+	/// Re-encrypts the database file at `path` using new file KDF parameters.
+	/// The file is decrypted using whatever parameters are in its existing header, encrypted again using `params`, and the result is
+	/// decrypted and compared against the original before anything on disk is touched.
+	/// The original file is kept alongside the new one with a `.bak` suffix; the path of that backup is returned.
+	/// Files that need a hardware key can't be re-encrypted (CryptoError::HardwareKeyRequired).  Files with more than one user (see
+	/// the users module) only get `params` for the database's own password, and can't be re-encrypted with another user's
+	/// (CryptoError::DecryptionError).
+	pub fn reencrypt<P: AsRef<Path>, A: AsRef<str>>(path: P, password: A, params: &FileKdfParameters) -> Result<PathBuf, FortressError> {
+		let path = path.as_ref();
+		let password = password.as_ref();
+
+		let mut database = Database::load_from_path(path, password)?;
+		if database.get_current_user() != 0 {
+			return Err(FortressError::CryptoError(CryptoError::DecryptionError));
+		}
+		database.file_key_suite = database.file_key_suite.rederive(password.as_bytes(), params)?;
+
+		// Encrypt to memory first so we can verify the result
+		let payload = Zeroizing::new(serde_json::to_vec(&database)?);
+		let mut encrypted = Vec::new();
+		fortresscrypto::encrypt_to_file(&mut encrypted, &payload, &database.file_key_suite)?;
+
+		// Round-trip: the new file must decrypt to exactly the same payload and objects
+		let (plaintext, _) = fortresscrypto::decrypt_from_file(&mut Cursor::new(&encrypted), password.as_bytes())?;
+		let reloaded = Database::load_from_reader(password, &mut Cursor::new(&encrypted))?;
+
+		if plaintext != payload || reloaded.objects != database.objects {
+			return Err(FortressError::ReencryptionMismatch);
+		}
+
+		// Keep a backup of the original before replacing it
+		let backup_path = backup_path(path);
+		fs::copy(path, &backup_path)?;
+
+		persist_atomically(path, |writer| writer.write_all(&encrypted))?;
 
-		// Encrypt and write to the temporary file
-		fortresscrypto::encrypt_to_file(&mut BufWriter::new(&mut temp_file), &payload, &self.file_key_suite)?;
+		Ok(backup_path)
+	}
+
+	/// Writes a copy of the database, encrypted under `password` instead of ours, to `path`; for example to hand to a family
+	/// member or keep in escrow.  The copy has all of our entries and directories, but keys of its own for the same username,
+	/// and no sync URL, so it can't be used to get into our sync account.
+	pub fn export_encrypted_copy<P: AsRef<Path>, A: AsRef<str>>(&self, path: P, password: A) -> Result<(), FortressError> {
+		// Saving only needs the file keys; the copy's sync keys are derived when it's next opened
+		let mut copy = Database::new_with_password_async(self.get_username(), password);
+		copy.objects = self.objects.clone();
 
-		// Now close the temp file and move it to the destination.
-		// Moving a temporary file is atomic (at least on *nix), so doing it this way
-		// instead of writing directly to the destination file helps prevent data loss.
-		let temp_path = temp_file.into_temp_path();
-		temp_path.persist(path).map_err(|e| e.error).map_err(FortressError::from)
+		copy.save_to_path(path)
 	}
 
+	/// Loads a database, upgrading it from an older format if needed (see `needs_resave`).
+	/// Legacy fortress1 databases can't be loaded this way, since they need a username; use `import_legacy_from_reader` instead.
 	pub fn load_from_reader<P: AsRef<str>, R: io::Read>(password: P, reader: &mut R) -> Result<Database, FortressError> {
+		load_from_reader(password.as_ref(), reader, false, &|_| None)
+	}
+
+	/// Also replays the file's journal (see `save_incremental`), as do the other path loaders.
+	pub fn load_from_path<P: AsRef<Path>, A: AsRef<str>>(path: P, password: A) -> Result<Database, FortressError> {
 		let password = password.as_ref();
 
-		// This struct is needed because Database has fields that aren't part of
-		// serialization, but can't implement Default.
-		#[derive(Deserialize)]
-		struct SerializableDatabase {
-			objects: DatabaseObjectMap,
-			sync_parameters: SyncParameters,
-			sync_url: Option<Url>,
-			old_sync_parameters: Option<FrozenSyncParameters>,
-		}
+		journal::load_with_journal(path.as_ref(), Some(password), |data| Self::load_from_reader(password, &mut Cursor::new(data)))
+	}
 
-		// Read file and decrypt
-		let (plaintext, file_key_suite) = fortresscrypto::decrypt_from_file(reader, password.as_bytes())?;
+	/// Same as `load_from_reader`, but entries are only decoded when they're first used, which makes opening very large
+	/// databases quicker and keeps rarely used entries out of memory.  Entries are decoded as needed by anything that looks at
+	/// them (listing or searching entries decodes all of them), and saving doesn't decode them.
+	/// NOTE: Entries aren't checked until they're decoded, so a damaged entry panics when it's first used instead of failing
+	/// the load.  Database files are authenticated, so this only happens if a bug saved an invalid entry.
+	pub fn load_from_reader_lazily<P: AsRef<str>, R: io::Read>(password: P, reader: &mut R) -> Result<Database, FortressError> {
+		load_from_reader(password.as_ref(), reader, true, &|_| None)
+	}
 
-		// Deserialize
-		let mut db: SerializableDatabase = serde_json::from_slice(&plaintext)?;
+	/// Same as `load_from_path`, but loads lazily like `load_from_reader_lazily`.
+	pub fn load_from_path_lazily<P: AsRef<Path>, A: AsRef<str>>(path: P, password: A) -> Result<Database, FortressError> {
+		let password = password.as_ref();
 
-		// TODO: Background derive
-		if db.sync_parameters.get_network_key_suite().is_none() {
-			db.sync_parameters.derive(password);
-		}
+		journal::load_with_journal(path.as_ref(), Some(password), |data| {
+			Self::load_from_reader_lazily(password, &mut Cursor::new(data))
+		})
+	}
 
-		// Keep encryption keys for quicker saving later
-		Ok(Database {
-			objects: db.objects,
-			sync_parameters: db.sync_parameters,
+	/// Same as `load_from_reader`, but returns FortressError::Cancelled as soon as `cancel` is cancelled.
+	pub fn load_from_reader_cancellable<P: AsRef<str>, R: io::Read>(
+		password: P,
+		reader: &mut R,
+		cancel: &CancellationToken,
+	) -> Result<Database, FortressError> {
+		let _span = diagnostics::load_span();
+		let mut data = Vec::new();
+		reader.read_to_end(&mut data)?;
+
+		load_cancellable(data, password.as_ref(), None, cancel)
+	}
 
-			file_key_suite,
-			sync_url: db.sync_url,
-			old_sync_parameters: db.old_sync_parameters,
+	pub fn load_from_path_cancellable<P: AsRef<Path>, A: AsRef<str>>(path: P, password: A, cancel: &CancellationToken) -> Result<Database, FortressError> {
+		let password = password.as_ref();
+
+		journal::load_with_journal(path.as_ref(), Some(password), |data| {
+			Self::load_from_reader_cancellable(password, &mut Cursor::new(data), cancel)
 		})
 	}
 
-	pub fn load_from_path<P: AsRef<Path>, A: AsRef<str>>(path: P, password: A) -> Result<Database, FortressError> {
-		let file = File::open(path)?;
-		let mut reader = BufReader::new(file);
+	/// The format version the database was originally loaded from.  Databases created in memory are always the current version.
+	pub fn get_original_format_version(&self) -> FormatVersion {
+		self.original_format_version
+	}
 
-		Self::load_from_reader(password, &mut reader)
+	/// Returns true if the database was upgraded from an older format when it was loaded, in which case it should be saved
+	/// so the file on disk is in the current format.
+	pub fn needs_resave(&self) -> bool {
+		self.original_format_version != CURRENT_FORMAT_VERSION
 	}
 
 	// TODO: Sync should be performed in a separate background thread
 	// TODO: Instead of having library users call sync themselves, we should just have an init method which sets up a continuous automatic
 	// background sync.
-	pub fn sync(&mut self) -> Result<(), FortressError> {
-		let url = self.sync_url.as_ref().ok_or(FortressError::SyncBadUrl)?;
+	pub fn sync(&mut self) -> Result<SyncReport, FortressError> {
+		self.sync_cancellable(&CancellationToken::new())
+	}
 
-		// Force SSL on release builds
-		let client = if cfg!(debug_assertions) {
-			reqwest::blocking::Client::new()
-		} else {
-			reqwest::blocking::Client::builder()
-				.https_only(true)
-				.build()
-				.expect("Failed to build HTTPS-only client")
-		};
+	/// Same as `sync`, but stops with FortressError::Cancelled soon after `cancel` is cancelled.  A cancelled sync leaves the
+	/// database's objects as they were before it started; anything already uploaded is merged back in on the next sync.
+	pub fn sync_cancellable(&mut self, cancel: &CancellationToken) -> Result<SyncReport, FortressError> {
+		self.sync_with_progress(cancel, &mut |_| {})
+	}
+
+	/// Same as `sync_cancellable`, reporting to `progress` as each object is compared with the server's copy.
+	pub fn sync_with_progress(&mut self, cancel: &CancellationToken, progress: &mut dyn FnMut(SyncProgress)) -> Result<SyncReport, FortressError> {
+		self.sync_with_options(&SyncOptions::default(), cancel, progress)
+	}
+
+	/// Same as `sync_with_progress`, retrying failed requests and limiting how long the sync takes according to `options` (see
+	/// the sync_options module).  A sync that takes too long fails with FortressError::SyncTimedOut.
+	pub fn sync_with_options(
+		&mut self,
+		options: &SyncOptions,
+		cancel: &CancellationToken,
+		progress: &mut dyn FnMut(SyncProgress),
+	) -> Result<SyncReport, FortressError> {
+		let _span = diagnostics::sync_span();
+		let objects = self.objects.clone();
+		let result = SyncClient::new(options, self.sync_proxy.as_ref(), cancel).and_then(|client| {
+			let mut report = self.sync_inner(&client, cancel, progress)?;
+			report.add_traffic(client.take_traffic());
+			Ok(report)
+		});
+
+		match &result {
+			Err(FortressError::Cancelled) => self.objects = objects,
+			Err(err) => diagnostics::sync_failed(err),
+			Ok(_) => (),
+		}
+
+		result
+	}
+
+	fn sync_inner(&mut self, client: &SyncClient, cancel: &CancellationToken, progress: &mut dyn FnMut(SyncProgress)) -> Result<SyncReport, FortressError> {
+		// Cloned, since dropping tombstones below needs the database mutably
+		let url = &self.sync_url.clone().ok_or(FortressError::SyncBadUrl)?;
+		self.sync_parameters.wait_cancellable(cancel)?;
 
 		// If password was previously changed, tell the server first
 		if let Some(old_sync_parameters) = &self.old_sync_parameters {
-			self.sync_api_update_login_key(&client, url, old_sync_parameters)?;
+			self.sync_api_update_login_key(client, url, old_sync_parameters)?;
 			self.old_sync_parameters = None;
 		}
 
-		loop {
+		let capabilities = self.sync_api_get_capabilities(client, url)?;
+		let mut report = SyncReport::default();
+		// Objects whose upload the server refused because it had a different copy (see `sync_api_update_object`)
+		let mut contested = HashSet::new();
+
+		for round in 1..=MAX_SYNC_ROUNDS {
 			// Get list of objects from server
-			let server_objects = self.sync_api_list_objects(&client, url)?.into_iter().collect::<HashMap<_, _>>();
+			let _round_span = diagnostics::sync_round_span(round);
+			cancel.check()?;
+			let server_objects = self.sync_api_list_objects(client, url)?.into_iter().collect::<HashMap<_, _>>();
 			let mut loop_again = false;
+			let mut report_progress = |phase, done, total| progress(SyncProgress { round, phase, done, total });
+
+			// Don't merge in the objects of an account we haven't synced with before
+			self.check_account(server_objects.is_empty())?;
+			self.account_login_id = Some(*self.sync_parameters.get_login_id());
 
 			// Download any objects that we're missing or that differ
-			for (server_id, server_siv) in &server_objects {
+			let phase_span = diagnostics::sync_phase_span(SyncPhase::Downloading);
+			for (done, (server_id, server_siv)) in server_objects.iter().enumerate() {
+				cancel.check()?;
+				report_progress(SyncPhase::Downloading, done, server_objects.len());
+
 				if let Some(local_object) = self.objects.get(server_id) {
 					let encrypted_object = self.encrypt_object(local_object);
 
 					if encrypted_object.siv != *server_siv {
 						// Object is different, download it and merge
-						let server_object = match self.sync_api_get_object(&client, url, server_id)? {
+						let server_object = match self.sync_api_get_object(client, url, server_id)? {
 							Some(object) => object,
 							None => {
 								// We couldn't get the object from the server (could be a changed password).  Ignore.
-								println!("WARNING: Couldn't get object {} from server, ignoring", server_id.to_hex());
+								diagnostics::skipped_server_object(server_id, SkippedObject::Missing, None);
 								continue;
 							},
 						};
 
-						let new_object = match (local_object, server_object) {
-							(DatabaseObject::Directory(local_directory), DatabaseObject::Directory(server_directory)) => {
-								let new_directory = local_directory.merge(&server_directory).ok_or(FortressError::SyncConflict)?;
-								DatabaseObject::Directory(new_directory)
-							},
-							(DatabaseObject::Entry(local_entry), DatabaseObject::Entry(server_entry)) => {
-								let new_entry = local_entry.merge(&server_entry).ok_or(FortressError::SyncConflict)?;
-								DatabaseObject::Entry(new_entry)
-							},
-							_ => panic!("Object type mismatch, this should never happen"),
-						};
+						let new_object = self.merge_server_object(local_object, server_object, &mut report)?;
 
-						self.objects.update(new_object);
+						if new_object != *local_object {
+							report.record(SyncActivity::Updated, &new_object);
+						}
+
+						self.objects.try_update(new_object)?;
+					} else if contested.remove(server_id) {
+						// The server has our copy after all; an earlier attempt at the upload got there
+						report.record(SyncActivity::Uploaded, local_object);
 					}
 				} else {
-					let object = self
-						.sync_api_get_object(&client, url, server_id)?
-						.ok_or(FortressError::SyncInconsistentServer)?;
-					self.objects.update(object);
+					let object = self.sync_api_get_object(client, url, server_id)?.ok_or(FortressError::SyncInconsistentServer)?;
+					report.record(SyncActivity::Added, &object);
+					self.objects.try_update(object)?;
 				}
 			}
+			report_progress(SyncPhase::Downloading, server_objects.len(), server_objects.len());
+			drop(phase_span);
+
+			// Computed after merging, since that may have changed which directories are local-only
+			let local_only = self.list_local_only_objects();
+			report.local_only_on_server.extend(server_objects.keys().filter(|id| local_only.contains(id)));
 
 			// Upload any objects the server doesn't know about or that differ
 			// Objects will differ here if the server had an older version or the merge above resulted in a change
-			for (local_id, local_object) in &self.objects {
+			let phase_span = diagnostics::sync_phase_span(SyncPhase::Uploading);
+			let gc_horizon = self.get_gc_horizon();
+			let mut gc_dropped = Vec::new();
+			for (done, (local_id, local_object)) in (&self.objects).into_iter().enumerate() {
+				cancel.check()?;
+				report_progress(SyncPhase::Uploading, done, self.objects.len());
+				if local_only.contains(local_id) {
+					continue;
+				}
 				let encrypted_object = self.encrypt_object(local_object);
 
 				if let Some(server_siv) = server_objects.get(local_id) {
 					if encrypted_object.siv != *server_siv {
 						// Object is different, upload it
-						self.sync_api_update_object(&client, url, local_object, server_siv)?;
+						self.check_sync_writable()?;
+						limits::check_upload(local_object, &encrypted_object)?;
+						capabilities.check_object_size(local_object, &encrypted_object)?;
+						if self.sync_api_update_object(client, url, local_object, server_siv)? {
+							report.record(SyncActivity::Uploaded, local_object);
+						} else {
+							contested.insert(*local_id);
+						}
 						loop_again = true;
 					}
+				} else if local_object.as_tombstone().is_some_and(|tombstone| tombstone.get_time() < gc_horizon) {
+					// The server dropped it already (see the gc module)
+					gc_dropped.push(*local_id);
 				} else {
 					// Object is missing from server, upload it
-					self.sync_api_update_object(&client, url, local_object, &SIV([0; 32]))?;
+					self.check_sync_writable()?;
+					limits::check_upload(local_object, &encrypted_object)?;
+					capabilities.check_object_size(local_object, &encrypted_object)?;
+					if self.sync_api_update_object(client, url, local_object, &SIV([0; 32]))? {
+						report.record(SyncActivity::Uploaded, local_object);
+					} else {
+						contested.insert(*local_id);
+						loop_again = true;
+					}
 				}
 			}
+			report_progress(SyncPhase::Uploading, self.objects.len(), self.objects.len());
+			drop(phase_span);
+
+			// Which changes the trash directory, so it needs uploading again
+			for id in gc_dropped {
+				loop_again |= self.drop_tombstone(&id);
+			}
 
 			if !loop_again {
-				break;
+				self.compacted_objects.clear();
+				// Everything on the server is under the current keys now
+				self.key_rotation = None;
+				return Ok(report);
 			}
 		}
 
-		Ok(())
+		// A well-behaved server accepts our uploads, so we should have settled after a round or two
+		Err(FortressError::SyncNotConverging)
+	}
+
+	/// Shows what `sync` would do, without changing anything in the database or on the server.  The report lists the objects
+	/// a sync would add or update from the server and upload to it (an object that's both updated and uploaded had changes on
+	/// both sides, which are merged), and the conflicts it would report.
+	/// Objects that differ from the server's copy are downloaded to work this out.  Fails like `sync` does, including with
+	/// FortressError::SyncAccountChanged.  If the password was changed since the last sync, the server won't accept the new
+	/// credentials until a sync has told it about the change, so the preview fails until then.
+	pub fn sync_preview(&self) -> Result<SyncReport, FortressError> {
+		let url = self.sync_url.as_ref().ok_or(FortressError::SyncBadUrl)?;
+		let client = self.sync_client()?;
+		let mut report = SyncReport::default();
+
+		let capabilities = self.sync_api_get_capabilities(&client, url)?;
+		let server_objects = self.sync_api_list_objects(&client, url)?.into_iter().collect::<HashMap<_, _>>();
+		self.check_account(server_objects.is_empty())?;
+		let local_only = self.list_local_only_objects();
+		report.local_only_on_server.extend(server_objects.keys().filter(|id| local_only.contains(id)));
+
+		for (server_id, server_siv) in &server_objects {
+			if let Some(local_object) = self.objects.get(server_id) {
+				if self.encrypt_object(local_object).siv == *server_siv {
+					continue;
+				}
+
+				// Ignored by sync too
+				let Some(server_object) = self.sync_api_get_object(&client, url, server_id)? else {
+					continue;
+				};
+				let new_object = self.merge_server_object(local_object, server_object, &mut report)?;
+
+				if new_object != *local_object {
+					report.record(SyncActivity::Updated, &new_object);
+				}
+
+				let encrypted_object = self.encrypt_object(&new_object);
+
+				if encrypted_object.siv != *server_siv && !local_only.contains(server_id) {
+					limits::check_upload(&new_object, &encrypted_object)?;
+					capabilities.check_object_size(&new_object, &encrypted_object)?;
+					report.record(SyncActivity::Uploaded, &new_object);
+				}
+			} else {
+				let object = self
+					.sync_api_get_object(&client, url, server_id)?
+					.ok_or(FortressError::SyncInconsistentServer)?;
+				report.record(SyncActivity::Added, &object);
+			}
+		}
+
+		for (local_id, local_object) in &self.objects {
+			if !server_objects.contains_key(local_id) && !local_only.contains(local_id) {
+				capabilities.check_object_size(local_object, &self.encrypt_object(local_object))?;
+				report.record(SyncActivity::Uploaded, local_object);
+			}
+		}
+
+		// What the preview itself transferred
+		report.add_traffic(client.take_traffic());
+
+		Ok(report)
+	}
+
+	/// Merges the server's copy of an object into ours, recording any conflicts in `report`.
+	fn merge_server_object(
+		&self,
+		local_object: &DatabaseObject,
+		server_object: DatabaseObject,
+		report: &mut SyncReport,
+	) -> Result<DatabaseObject, FortressError> {
+		let id = *server_object.get_id();
+
+		// If we compacted this object's history, compact the server's copy the same way so the merge doesn't bring
+		// the old history back.  The result is then uploaded, replacing the server's copy.
+		let server_object = match self.compacted_objects.get(&id) {
+			Some(before) => server_object.compact_history(*before).unwrap_or(server_object),
+			None => server_object,
+		};
+
+		match (local_object, server_object) {
+			(DatabaseObject::Directory(local_directory), DatabaseObject::Directory(server_directory)) => {
+				if let Some((kept, discarded)) = local_directory.concurrent_rename(&server_directory) {
+					report.conflicts.push(SyncConflict::DirectoryRename {
+						directory: id,
+						kept: kept.to_string(),
+						discarded: discarded.to_string(),
+					});
+				}
+
+				let new_directory = local_directory.merge(&server_directory).ok_or(FortressError::SyncConflict)?;
+				Ok(DatabaseObject::Directory(new_directory))
+			},
+			(DatabaseObject::Entry(local_entry), DatabaseObject::Entry(server_entry)) => {
+				let new_entry = local_entry.merge(&server_entry).ok_or(FortressError::SyncConflict)?;
+				Ok(DatabaseObject::Entry(new_entry))
+			},
+			(DatabaseObject::Settings(local_settings), DatabaseObject::Settings(server_settings)) => {
+				let new_settings = local_settings.merge(&server_settings).ok_or(FortressError::SyncConflict)?;
+				Ok(DatabaseObject::Settings(new_settings))
+			},
+			(DatabaseObject::Tombstone(local_tombstone), DatabaseObject::Tombstone(server_tombstone)) => {
+				let new_tombstone = local_tombstone.merge(&server_tombstone).ok_or(FortressError::SyncConflict)?;
+				Ok(DatabaseObject::Tombstone(new_tombstone))
+			},
+			// An object deleted on one side wins over the deletion only if it was edited since (see Tombstone)
+			(DatabaseObject::Tombstone(tombstone), server_object @ (DatabaseObject::Entry(_) | DatabaseObject::Directory(_))) => {
+				Ok(if server_object.revived_after(tombstone) {
+					server_object
+				} else {
+					local_object.clone()
+				})
+			},
+			(DatabaseObject::Entry(_) | DatabaseObject::Directory(_), DatabaseObject::Tombstone(tombstone)) => Ok(if local_object.revived_after(&tombstone) {
+				local_object.clone()
+			} else {
+				DatabaseObject::Tombstone(tombstone)
+			}),
+			_ => Err(FortressError::SyncObjectTypeMismatch(id)),
+		}
+	}
+
+	/// Checks that the sync server at `url` is reachable and accepts our credentials, without changing anything on the server or
+	/// in the database.  This lets a frontend validate a newly entered sync URL before syncing with it; pass `get_sync_url()` to
+	/// check the current configuration.
+	/// Rejected credentials are reported in the result; anything else that goes wrong (the server is unreachable, or doesn't
+	/// respond like a Fortress server) is an error.
+	pub fn test_sync_connection(&self, url: &Url) -> Result<SyncConnectionStatus, FortressError> {
+		let client = self.sync_client()?;
+
+		let start = Instant::now();
+		let result = self.sync_api_list_objects(&client, url);
+		let latency = start.elapsed();
+
+		let authenticated = match result {
+			Ok(_) => true,
+			Err(FortressError::SyncApiError(ApiError::ApiError(401, _))) => match &self.old_sync_parameters {
+				// The server may not have been told about a password change yet; the next sync takes care of that
+				Some(old_sync_parameters) => api_request(
+					&client,
+					old_sync_parameters.get_login_id(),
+					old_sync_parameters.get_login_key(),
+					Method::GET,
+					sync_endpoint(url, "/objects")?,
+					"",
+				)
+				.is_ok(),
+				None => false,
+			},
+			Err(err) => return Err(err),
+		};
+
+		Ok(SyncConnectionStatus { latency, authenticated })
 	}
 
 	/// List all objects on the server
-	fn sync_api_list_objects(&self, client: &reqwest::blocking::Client, url: &Url) -> Result<Vec<(ID, SIV)>, ApiError> {
-		api_request(
+	fn sync_api_list_objects(&self, client: &SyncClient, url: &Url) -> Result<Vec<(ID, SIV)>, FortressError> {
+		let response = api_request(
+			client,
+			self.sync_parameters.get_login_id(),
+			self.sync_parameters.get_login_key().expect("internal error"),
+			Method::GET,
+			sync_endpoint(url, "/objects")?,
+			"",
+		)?;
+
+		let body = limits::read_listing(response)?;
+		client.record_download(None, body.len());
+		parse_object_list(&body)
+	}
+
+	/// Fetch the server's limits.  Servers without the capabilities endpoint don't have any we know of.
+	fn sync_api_get_capabilities(&self, client: &SyncClient, url: &Url) -> Result<SyncCapabilities, FortressError> {
+		let response = api_request(
 			client,
 			self.sync_parameters.get_login_id(),
-			self.sync_parameters.get_login_key().expect("TODO: Update when background derive is in"),
+			self.sync_parameters.get_login_key().expect("internal error"),
 			Method::GET,
-			url.join("/objects").expect("internal error"),
+			sync_endpoint(url, "/capabilities")?,
 			"",
-		)?
-		.json()
-		.map_err(ApiError::from)
+		);
+
+		match response {
+			Ok(response) => {
+				let body = response.bytes().map_err(ApiError::from)?;
+				client.record_download(None, body.len());
+				serde_json::from_slice(&body).map_err(|_| FortressError::SyncMalformedResponse)
+			},
+			Err(FortressError::SyncApiError(ApiError::ApiError(404, _))) => Ok(SyncCapabilities::default()),
+			Err(err) => Err(err),
+		}
 	}
 
 	/// Upload object to fortress server
-	fn sync_api_update_object(&self, client: &reqwest::blocking::Client, url: &Url, object: &DatabaseObject, old_mac: &SIV) -> Result<(), ApiError> {
+	/// Uploads `object` in place of the server's copy with SIV `old_mac`.  Returns false if the server has a different copy
+	/// (409 Conflict), which happens when another device uploaded the object since we listed it, or when an earlier attempt at
+	/// this upload got there but its response was lost and the request was retried (see the sync_options module).  Either way
+	/// the next sync round downloads the server's copy and sorts it out.
+	fn sync_api_update_object(&self, client: &SyncClient, url: &Url, object: &DatabaseObject, old_mac: &SIV) -> Result<bool, FortressError> {
 		// Encrypt
 		let encrypted_object = self.encrypt_object(object);
 
 		let body = [&encrypted_object.ciphertext, encrypted_object.siv.as_ref()].concat();
-		let url = url
-			.join(&format!("/object/{}/{}", object.get_id().to_hex(), old_mac.to_hex()))
-			.expect("internal error");
+		let size = body.len();
+		let url = sync_endpoint(url, &format!("/object/{}/{}", object.get_id().to_hex(), old_mac.to_hex()))?;
 
-		api_request(
+		match api_request(
 			client,
 			self.sync_parameters.get_login_id(),
-			self.sync_parameters.get_login_key().expect("TODO: Update when background derive is in"),
+			self.sync_parameters.get_login_key().expect("internal error"),
 			Method::POST,
 			url,
 			body,
-		)
-		.map(|_| ())
+		) {
+			Ok(_) => {
+				client.record_upload(object.get_id(), size);
+				Ok(true)
+			},
+			Err(FortressError::SyncApiError(ApiError::ApiError(409, _))) => Ok(false),
+			// Payload Too Large, from a server that didn't advertise its limit
+			Err(FortressError::SyncApiError(ApiError::ApiError(413, _))) => Err(sync_capabilities::object_too_large(object, size, None)),
+			Err(err) => Err(err),
+		}
 	}
 
 	/// Fetch an object from the server.
 	/// If the object doesn't exist on the server or could not be decrypted then None is returned.
-	fn sync_api_get_object(&self, client: &reqwest::blocking::Client, url: &Url, id: &ID) -> Result<Option<DatabaseObject>, FortressError> {
-		let url = url.join(&format!("/object/{}", id.to_hex())).expect("internal error");
+	fn sync_api_get_object(&self, client: &SyncClient, url: &Url, id: &ID) -> Result<Option<DatabaseObject>, FortressError> {
+		let url = sync_endpoint(url, &format!("/object/{}", id.to_hex()))?;
 
-		// TODO: Update when background derive is in
 		let response = api_request(
 			client,
 			self.sync_parameters.get_login_id(),
@@ -427,51 +1195,18 @@ impl Database {
 			Method::GET,
 			url,
 			"",
-		)?
-		.bytes()
-		.map_err(ApiError::from)?;
-
-		if response.len() < 32 {
-			println!("WARNING: Server returned invalid response for object");
-			return Ok(None);
-		}
+		)?;
+		let response = limits::read_object(id, response)?;
+		client.record_download(Some(id), response.len());
 
-		let (ciphertext, siv) = response.split_at(response.len() - 32);
-		let siv = SIV::from_slice(siv).expect("internal error");
-		let encrypted_object = EncryptedObject {
-			ciphertext: ciphertext.to_vec(),
-			siv,
-		};
-
-		// TODO: Update when background derive is in
-		let plaintext = match self
-			.sync_parameters
-			.get_network_key_suite()
-			.expect("internal error")
-			.decrypt_object(&id[..], &encrypted_object)
-		{
-			Ok(plaintext) => plaintext,
-			Err(err) => {
-				println!("WARNING: Error while decrypting server object(ID: {}): {}", id.to_hex(), err);
-				return Ok(None);
-			},
-		};
-
-		match serde_json::from_slice(&plaintext) {
-			Ok(object) => Ok(Some(object)),
-			Err(err) => {
-				println!("WARNING: Error while deserializing server object(ID: {}): {}", id.to_hex(), err);
-				Ok(None)
-			},
-		}
+		decode_server_object(self.server_object_keys(id, &response), id, &response)
 	}
 
 	/// Tell the server about a change in our LoginKey
-	fn sync_api_update_login_key(&self, client: &reqwest::blocking::Client, url: &Url, old_sync_parameters: &FrozenSyncParameters) -> Result<(), ApiError> {
-		// TODO: Update when background derive is in
+	fn sync_api_update_login_key(&self, client: &SyncClient, url: &Url, old_sync_parameters: &FrozenSyncParameters) -> Result<(), FortressError> {
 		let body = self.sync_parameters.get_login_key().expect("internal error").0.to_vec();
-		let url = url.join("/user/login_key").expect("internal error");
-		let test_url = url.join("/objects").expect("internal error");
+		let test_url = sync_endpoint(url, "/objects")?;
+		let url = sync_endpoint(url, "/user/login_key")?;
 
 		match api_request(
 			client,
@@ -482,12 +1217,12 @@ impl Database {
 			body,
 		) {
 			Ok(_) => Ok(()),
-			Err(ApiError::ApiError(401, _)) => {
+			Err(FortressError::SyncApiError(ApiError::ApiError(401, _))) => {
 				// It's possible the server already knows about the new key, let's check by doing a test request
 				api_request(
 					client,
 					self.sync_parameters.get_login_id(),
-					self.sync_parameters.get_login_key().expect("internal error"), // TODO: Update once background derive is in
+					self.sync_parameters.get_login_key().expect("internal error"),
 					Method::GET,
 					test_url,
 					"",
@@ -499,8 +1234,7 @@ impl Database {
 	}
 
 	fn encrypt_object(&self, object: &DatabaseObject) -> EncryptedObject {
-		let payload = serde_json::to_vec(&object).expect("internal error");
-		// TODO: Update once background derive is in
+		let payload = Zeroizing::new(serde_json::to_vec(&object).expect("internal error"));
 		self.sync_parameters
 			.get_network_key_suite()
 			.expect("internal error")
@@ -509,6 +1243,125 @@ impl Database {
 }
 
 
+/// Returned by `Database::test_sync_connection`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SyncConnectionStatus {
+	/// How long the server took to answer the test request.
+	pub latency: Duration,
+	/// False if the server rejected our credentials.
+	pub authenticated: bool,
+}
+
+
+/// The URL of one of the sync server's API endpoints.  Fails with FortressError::SyncBadUrl if the sync URL can't have a path
+/// (e.g. a `mailto:` URL).
+fn sync_endpoint(url: &Url, path: &str) -> Result<Url, FortressError> {
+	url.join(path).map_err(|_| FortressError::SyncBadUrl)
+}
+
+
+/// Parse the server's response to GET /objects: a list of (ID, SIV) pairs.
+fn parse_object_list(body: &[u8]) -> Result<Vec<(ID, SIV)>, FortressError> {
+	let objects: Vec<(ID, SIV)> = serde_json::from_slice(body).map_err(|_| FortressError::SyncMalformedResponse)?;
+	limits::check_object_count(objects.len())?;
+	Ok(objects)
+}
+
+
+/// Decrypt and deserialize an object body returned by the server (ciphertext followed by its SIV).
+/// Returns None if the object could not be decrypted or deserialized (e.g. it was encrypted using an old password).
+/// Objects over the limits in the limits module are an error.
+fn decode_server_object(network_key_suite: &NetworkKeySuite, id: &ID, body: &[u8]) -> Result<Option<DatabaseObject>, FortressError> {
+	limits::check_object_size(id, body.len())?;
+	let (ciphertext, siv) = body
+		.len()
+		.checked_sub(32)
+		.map(|mid| body.split_at(mid))
+		.ok_or(FortressError::SyncMalformedResponse)?;
+	let siv = SIV::from_slice(siv).ok_or(FortressError::SyncMalformedResponse)?;
+	let encrypted_object = EncryptedObject {
+		ciphertext: ciphertext.to_vec(),
+		siv,
+	};
+
+	let plaintext = match network_key_suite.decrypt_object(&id[..], &encrypted_object) {
+		Ok(plaintext) => plaintext,
+		Err(err) => {
+			diagnostics::skipped_server_object(id, SkippedObject::Undecryptable, Some(&err));
+			return Ok(None);
+		},
+	};
+
+	match serde_json::from_slice::<DatabaseObject>(&plaintext) {
+		Ok(object) if object.get_id() == id => {
+			limits::check_object(&object)?;
+			id_policy::check_object_id(&object)?;
+			Ok(Some(object))
+		},
+		// The ID is authenticated, so this can only be an object that was uploaded under the wrong ID
+		Ok(_) => Err(FortressError::SyncInconsistentServer),
+		Err(err) => {
+			diagnostics::skipped_server_object(id, SkippedObject::Malformed, Some(&err));
+			Ok(None)
+		},
+	}
+}
+
+
+/// Entry points into the parsing of server responses and database files, for the fuzz targets in /fuzz.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+	use crate::{DatabaseObject, FortressError, ID};
+	use fortresscrypto::{NetworkKeySuite, SIV};
+	use std::io::Cursor;
+
+	pub fn parse_object_list(body: &[u8]) -> Result<Vec<(ID, SIV)>, FortressError> {
+		crate::parse_object_list(body)
+	}
+
+	/// Returns the decoded object's ID, if decoding succeeded.
+	pub fn decode_server_object(network_key_suite: &NetworkKeySuite, id: &ID, body: &[u8]) -> Result<Option<ID>, FortressError> {
+		Ok(crate::decode_server_object(network_key_suite, id, body)?
+			.as_ref()
+			.map(DatabaseObject::get_id)
+			.copied())
+	}
+
+	/// Parses a whole database file, without deriving any keys from the password.
+	pub fn parse_database_file(data: &[u8], password: &str) -> Result<(), FortressError> {
+		let (plaintext, _) = fortresscrypto::decrypt_from_file(&mut Cursor::new(data), password.as_bytes())?;
+		crate::migration::parse_fortress2_payload(&plaintext)
+	}
+
+	/// Parses the decrypted contents of a database file.
+	pub fn parse_database_payload(plaintext: &[u8]) -> Result<(), FortressError> {
+		crate::migration::parse_fortress2_payload(plaintext)
+	}
+
+	/// Deserializes an object the way objects from the server are and checks it against the sync limits.  Returns whether it
+	/// deserialized.
+	/// Panics if merging the object with itself, or checking whether it can replace itself, doesn't leave it unchanged.
+	pub fn parse_object(json: &[u8]) -> bool {
+		let Ok(object) = serde_json::from_slice::<DatabaseObject>(json) else {
+			return false;
+		};
+		let _ = object.check_limits();
+
+		let (merged, safe) = match &object {
+			DatabaseObject::Entry(e) => (e.merge(e).map(DatabaseObject::Entry), e.safe_to_replace_with(e)),
+			DatabaseObject::Directory(d) => (d.merge(d).map(DatabaseObject::Directory), d.safe_to_replace_with(d)),
+			DatabaseObject::Settings(s) => (s.merge(s).map(DatabaseObject::Settings), s.safe_to_replace_with(s)),
+			DatabaseObject::Tombstone(t) => (t.merge(t).map(DatabaseObject::Tombstone), t.safe_to_replace_with(t)),
+		};
+		assert_eq!(merged.as_ref(), Some(&object), "merging an object with itself changed it");
+		assert!(safe, "an object can't safely replace itself");
+
+		true
+	}
+}
+
+
 #[derive(Debug)]
 pub enum ApiError {
 	ReqwestError(reqwest::Error),
@@ -531,95 +1384,170 @@ impl std::fmt::Display for ApiError {
 }
 
 
-fn api_request<U, B>(
-	client: &reqwest::blocking::Client,
-	login_id: &LoginId,
-	login_key: &LoginKey,
-	method: Method,
-	url: U,
-	body: B,
-) -> Result<reqwest::blocking::Response, ApiError>
-where
-	U: IntoUrl,
-	B: Into<reqwest::blocking::Body>,
-{
-	let auth_token = login_id.to_hex() + login_key.to_hex().as_str();
-	let response = client.request(method, url).bearer_auth(auth_token).body(body).send()?;
+fn matches_id_or_name(id: &ID, name: Option<&str>, query: &str) -> bool {
+	let is_id_prefix = !query.is_empty() && id.to_hex().starts_with(&query.to_lowercase());
+	let is_name = name.is_some_and(|name| name.to_lowercase() == query.to_lowercase());
+
+	is_id_prefix || is_name
+}
+
+
+/// Derives the file keys and sync keys for a new password.  This is slow on purpose, unless the network keys were `cached`.
+/// The hardware key of the `current` file keys, if they have one, is kept (see `FileKeySuite::rederive`).
+fn derive_keys(username: &str, password: &str, cached: Option<NetworkKeySuite>, current: Option<&FileKeySuite>) -> (FileKeySuite, SyncParameters) {
+	let encryption_parameters = Default::default();
+	let file_key_suite = match current {
+		Some(current) => current.rederive(password.as_bytes(), &encryption_parameters),
+		None => FileKeySuite::derive(password.as_bytes(), &encryption_parameters),
+	}
+	.expect("Internal error: Scrypt parameters were invalid.");
+	let sync_parameters = match cached {
+		Some(network_key_suite) => SyncParameters::with_network_key_suite(username, network_key_suite),
+		None => SyncParameters::new(username, password),
+	};
+
+	(file_key_suite, sync_parameters)
+}
 
-	if response.status().is_success() {
-		Ok(response)
+
+/// How long `derive_keys` is expected to take on this machine, with or without deriving the network keys.
+fn estimate_derive_keys_time(network_keys: bool) -> Duration {
+	let file_time = FileKdfParameters::default().estimate_derive_time();
+
+	if network_keys {
+		NetworkKeySuite::estimate_derive_time() + file_time
 	} else {
-		let status = response.status();
-		let error = response.text()?;
-		Err(ApiError::ApiError(status.into(), error))
+		file_time
 	}
 }
 
 
-pub fn random_string(length: usize, uppercase: bool, lowercase: bool, numbers: bool, others: &str) -> String {
-	let alphabet_uppercase = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-	let alphabet_lowercase = "abcdefghijklmnopqrstuvwxyz";
-	let alphabet_numbers = "0123456789";
+/// Loads (or, given a username, imports a legacy) database from `data` on a worker thread, so it can be cancelled during key derivation.
+fn load_from_reader<R: io::Read>(password: &str, reader: &mut R, lazy_entries: bool, hardware_key: &HardwareKeyFn) -> Result<Database, FortressError> {
+	let _span = diagnostics::load_span();
+	let mut data = Vec::new();
+	reader.read_to_end(&mut data)?;
+
+	migration::load(
+		&data,
+		&MigrationContext {
+			password,
+			username: None,
+			lazy_entries,
+			hardware_key,
+		},
+	)
+}
 
-	// Use a hashset to avoid duplicates caused by "others"
-	let mut alphabet = HashSet::new();
 
-	alphabet.extend(others.chars());
+fn load_cancellable(data: Vec<u8>, password: &str, username: Option<&str>, cancel: &CancellationToken) -> Result<Database, FortressError> {
+	let password = Zeroizing::new(password.to_string());
+	let username = username.map(str::to_string);
 
-	if uppercase {
-		alphabet.extend(alphabet_uppercase.chars());
-	}
+	cancellation::run_cancellable(cancel, move || {
+		migration::load(
+			&data,
+			&MigrationContext {
+				password: &password,
+				username: username.as_deref(),
+				lazy_entries: false,
+				hardware_key: &|_| None,
+			},
+		)
+	})?
+}
 
-	if lowercase {
-		alphabet.extend(alphabet_lowercase.chars());
-	}
 
-	if numbers {
-		alphabet.extend(alphabet_numbers.chars());
-	}
+/// The path an existing file at `path` is backed up to before being replaced (`path` with a `.bak` suffix).
+fn backup_path(path: &Path) -> PathBuf {
+	let mut backup_path = path.as_os_str().to_owned();
+	backup_path.push(".bak");
+	PathBuf::from(backup_path)
+}
 
-	if alphabet.is_empty() {
-		return String::new();
-	}
 
-	let alphabet: Vec<char> = alphabet.into_iter().collect();
-	let mut result = String::new();
+/// Writes to a temporary file next to `path` and then moves it into place.
+/// Moving a temporary file is atomic (at least on *nix), so doing it this way
+/// instead of writing directly to the destination file helps prevent data loss.
+fn persist_atomically<P, F>(path: P, write: F) -> Result<(), FortressError>
+where
+	P: AsRef<Path>,
+	F: FnOnce(&mut BufWriter<&mut NamedTempFile>) -> io::Result<()>,
+{
+	// Create a temporary file to write to
+	let mut temp_file = {
+		let parent_directory = path.as_ref().parent().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Bad path"))?;
+		NamedTempFile::new_in(parent_directory)?
+	};
 
-	for _ in 0..length {
-		result.push(*alphabet.choose(&mut OsRng).expect("internal error"));
+	{
+		let mut writer = BufWriter::new(&mut temp_file);
+		write(&mut writer)?;
+		writer.flush()?;
 	}
 
-	result
+	// Now close the temp file and move it to the destination.
+	let temp_path = temp_file.into_temp_path();
+	temp_path.persist(path).map_err(|e| e.error).map_err(FortressError::from)
 }
 
 
-// Returns the current unix timestamp in nanoseconds.
+/// A random string of `length` characters drawn from the selected classes and `others`, or an empty string if nothing is
+/// selected.  See `GeneratorOptions` for more options.
+pub fn random_string(length: usize, uppercase: bool, lowercase: bool, numbers: bool, others: &str) -> String {
+	GeneratorOptions {
+		length,
+		uppercase,
+		lowercase,
+		numbers,
+		others: others.to_string(),
+		..GeneratorOptions::default()
+	}
+	.generate()
+	.unwrap_or_default()
+}
+
+
+// Returns the current unix timestamp in nanoseconds, never the same one twice (see the time_source module).
 // Our library won't handle time before the unix epoch, so we return u64.
 // NOTE: This will panic if used past ~2500 C.E. (Y2K taught me nothing).
 fn unix_timestamp() -> u64 {
-	let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).expect("internal error");
-	timestamp
-		.as_secs()
-		.checked_mul(1000000000)
-		.expect("internal error")
-		.checked_add(timestamp.subsec_nanos() as u64)
-		.expect("internal error")
+	SystemClock.now()
 }
 
 
 #[cfg(test)]
 mod tests {
-	use super::{random_string, Database, DatabaseObject, Directory, Entry, EntryHistory, ID};
+	use super::{
+		decode_server_object, parse_object_list, random_string, CancellationToken, Database, DatabaseObject, Directory, Entry, EntryHistory, FileKdfParameters,
+		FortressError, NetworkKeySuite, SyncLimit, ID, MAX_HISTORY_LENGTH, MAX_OBJECT_SIZE, SIV,
+	};
 	use rand::{
 		distributions::{uniform::SampleRange, Standard},
 		rngs::OsRng,
 		thread_rng, Rng,
 	};
-	use std::{collections::HashMap, io::Cursor};
+	use std::{
+		collections::HashMap,
+		io::Cursor,
+		panic::{self, AssertUnwindSafe},
+	};
 	use tempfile::tempdir;
 
-	pub(crate) fn quick_sleep() {
-		std::thread::sleep(std::time::Duration::from_nanos(1));
+	/// Runs `run` with each seed from 0 to `cases`, or just FORTRESS_TEST_SEED if it's set.  A failing seed is printed so the
+	/// case can be replayed with FORTRESS_TEST_SEED=<seed> cargo test -p libfortress <test>.
+	pub(crate) fn for_each_seed(test: &str, cases: u64, run: impl Fn(u64)) {
+		let seeds = match std::env::var("FORTRESS_TEST_SEED") {
+			Ok(seed) => vec![seed.parse().expect("FORTRESS_TEST_SEED must be a number")],
+			Err(_) => (0..cases).collect(),
+		};
+
+		for seed in seeds {
+			if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| run(seed))) {
+				eprintln!("{test} failed with seed {seed}; replay with FORTRESS_TEST_SEED={seed}");
+				panic::resume_unwind(panic);
+			}
+		}
 	}
 
 	pub(crate) fn random_uniform_string<R: SampleRange<usize>>(range: R) -> String {
@@ -663,20 +1591,20 @@ mod tests {
 		db.save_to_path(tmp_dir.path().join("test.fortressdb")).unwrap();
 
 		// Password change should change file encryption keys, even if using the same password
-		db.change_password("username", "password");
+		db.change_password("username", "password").unwrap();
 		assert_ne!(db.file_key_suite, old_file_key_suite);
 
 		// Password change should not change network keys if using the same password
 		assert_eq!(db.sync_parameters, old_sync_parameters);
 
 		// Changing username should change network keys even if using the same password
-		db.change_password("username2", "password");
+		db.change_password("username2", "password").unwrap();
 		assert_ne!(db.sync_parameters.get_login_key(), old_sync_parameters.get_login_key());
 		assert_ne!(db.sync_parameters.get_login_id(), old_sync_parameters.get_login_id());
 		assert_ne!(db.sync_parameters.get_network_key_suite(), old_sync_parameters.get_network_key_suite());
 
 		// Password change should change all keys if username and/or password are different
-		db.change_password("username", "password2");
+		db.change_password("username", "password2").unwrap();
 		assert_ne!(db.file_key_suite, old_file_key_suite);
 		assert_ne!(db.sync_parameters, old_sync_parameters);
 
@@ -692,6 +1620,258 @@ mod tests {
 		assert_eq!(db.objects, db3.objects);
 	}
 
+	#[test]
+	fn derivation_cache() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		let original_keys = db.get_login_key().clone();
+
+		// Changing back comes from the cache, which gives the same keys as deriving them
+		db.change_password("username", "password2").unwrap();
+		assert!(db.derivation_cache.get("username", "password2").is_some());
+		db.change_password("username", "password").unwrap();
+		assert_eq!(*db.get_login_key(), original_keys);
+
+		// Only the keys that were just replaced are kept
+		db.change_password("username", "password3").unwrap();
+		assert!(db.derivation_cache.get("username", "password").is_some());
+		assert!(db.derivation_cache.get("username", "password2").is_none());
+		db.change_password("username", "password").unwrap();
+
+		// Not saved with the database, apart from the current keys being remembered on load
+		db.save_to_path(&path).unwrap();
+		assert!(!serde_json::to_string(&db).unwrap().contains("derivation_cache"));
+		let mut db2 = Database::load_from_path(&path, "password").unwrap();
+		assert!(db2.derivation_cache.get("username", "password").is_some());
+		assert!(db2.derivation_cache.get("username", "password3").is_none());
+		db2.change_password_cancellable("username", "password2", &CancellationToken::new()).unwrap();
+		db.change_password("username", "password2").unwrap();
+		assert_eq!(db2.sync_parameters, db.sync_parameters);
+
+		// Turned off, nothing is remembered, even across saves
+		db.clear_derivation_cache();
+		assert!(db.derivation_cache.get("username", "password2").is_none());
+		db.set_derivation_cache_enabled(false);
+		assert!(!db.is_derivation_cache_enabled());
+		db.change_password("username", "password3").unwrap();
+		db.save_to_path(&path).unwrap();
+		let mut db3 = Database::load_from_path(&path, "password3").unwrap();
+		assert!(!db3.is_derivation_cache_enabled());
+		assert!(db3.derivation_cache.get("username", "password3").is_none());
+	}
+
+	#[test]
+	fn cancellable_key_derivation() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let cancel = CancellationToken::new();
+
+		let mut db = Database::new_with_password_cancellable("username", "password", &cancel).unwrap();
+		db.new_entry();
+		db.save_to_path(&path).unwrap();
+		assert_eq!(Database::load_from_path_cancellable(&path, "password", &cancel).unwrap().objects, db.objects);
+		assert!(matches!(
+			Database::load_from_path_cancellable(&path, "wrong", &cancel),
+			Err(FortressError::CryptoError(_))
+		));
+
+		db.change_password_cancellable("username", "password2", &cancel).unwrap();
+		assert!(db.old_sync_parameters.is_some());
+
+		// Cancelling leaves everything as it was
+		let original = db.clone();
+		cancel.cancel();
+		assert!(matches!(
+			db.change_password_cancellable("username", "password3", &cancel),
+			Err(FortressError::Cancelled)
+		));
+		assert_eq!(db, original);
+		assert!(matches!(
+			Database::load_from_path_cancellable(&path, "password", &cancel),
+			Err(FortressError::Cancelled)
+		));
+		assert!(matches!(
+			Database::new_with_password_cancellable("username", "password", &cancel),
+			Err(FortressError::Cancelled)
+		));
+	}
+
+	#[test]
+	fn background_key_derivation() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let expected = Database::new_with_password("username", "password");
+
+		let mut db = Database::new_with_password_async("username", "password");
+		db.new_entry();
+		db.save_to_path(&path).unwrap();
+		assert_eq!(db.get_login_id(), expected.get_login_id());
+		assert_eq!(db.get_login_key(), expected.get_login_key());
+		assert!(!db.sync_keys_pending());
+
+		// Loads whether or not the keys were done when it was saved
+		let db2 = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(db2.objects, db.objects);
+		assert_eq!(db2.sync_parameters, db.sync_parameters);
+	}
+
+	#[test]
+	fn reencrypt() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+
+		let mut db = Database::new_with_password("username", "password");
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new([("title".to_string(), "Reencrypt".to_string())].iter().cloned().collect()));
+		db.add_entry(entry);
+		db.save_to_path(&path).unwrap();
+
+		let params = FileKdfParameters {
+			log_n: 9,
+			r: 4,
+			p: 2,
+			salt: OsRng.gen(),
+			hardware_challenge: None,
+		};
+
+		// Wrong password should fail and leave the file alone
+		Database::reencrypt(&path, "wrong", &params).expect_err("Shouldn't be able to reencrypt with the wrong password");
+		assert!(!tmp_dir.path().join("test.fortressdb.bak").exists());
+
+		let backup_path = Database::reencrypt(&path, "password", &params).unwrap();
+		assert_eq!(backup_path, tmp_dir.path().join("test.fortressdb.bak"));
+
+		// New file uses the new parameters and holds the same data
+		let db2 = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(db2.file_key_suite.get_kdf_params(), &params);
+		assert_eq!(db.objects, db2.objects);
+		assert_eq!(db.sync_parameters, db2.sync_parameters);
+
+		// Backup is the original file
+		let backup = Database::load_from_path(&backup_path, "password").unwrap();
+		assert_eq!(backup, db);
+	}
+
+	#[test]
+	fn export_encrypted_copy() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("export.fortressdb");
+
+		let mut db = Database::new_with_password("username", "password");
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new([("title".to_string(), "Exported".to_string())].into()));
+		db.add_entry(entry);
+		db.set_sync_url(Some(url::Url::parse("https://example.com").unwrap()));
+		db.export_encrypted_copy(&path, "export password").unwrap();
+
+		assert!(matches!(
+			Database::load_from_path(&path, "password"),
+			Err(FortressError::CryptoError(fortresscrypto::CryptoError::DecryptionError))
+		));
+		let copy = Database::load_from_path(&path, "export password").unwrap();
+		assert_eq!(copy.objects, db.objects);
+		assert_eq!(copy.get_username(), "username");
+		assert_eq!(copy.get_sync_url(), None);
+		assert_ne!(copy.get_login_key(), db.get_login_key());
+		// The sync keys weren't saved, since the copy was written before they were derived, so opening it derived them again
+		assert_eq!(copy.get_login_key(), Database::new_with_password("username", "export password").get_login_key());
+	}
+
+	// Malformed server responses should be rejected without panicking
+	#[test]
+	fn malformed_server_responses() {
+		// Object listings
+		let id: ID = OsRng.gen();
+		let siv: SIV = OsRng.gen();
+		let valid_listing = format!("[[\"{}\",\"{}\"]]", id.to_hex(), siv.to_hex());
+		assert_eq!(parse_object_list(valid_listing.as_bytes()).unwrap(), vec![(id, siv)]);
+		assert_eq!(parse_object_list(b"[]").unwrap(), vec![]);
+
+		let truncated_siv = format!("[[\"{}\",\"{}\"]]", id.to_hex(), &siv.to_hex()[..62]);
+		let long_id = format!("[[\"{}00\",\"{}\"]]", id.to_hex(), siv.to_hex());
+		for listing in [
+			b"".as_slice(),
+			b"{}",
+			b"[[]]",
+			b"[[\"zz\", \"zz\"]]",
+			&valid_listing.as_bytes()[..valid_listing.len() - 1],
+			truncated_siv.as_bytes(),
+			long_id.as_bytes(),
+			&[0xff; 64],
+		] {
+			assert!(matches!(parse_object_list(listing), Err(FortressError::SyncMalformedResponse)));
+		}
+
+		// Object bodies
+		let keys = NetworkKeySuite::derive(b"username", b"password");
+		let entry = DatabaseObject::Entry(Entry::new());
+		let id = *entry.get_id();
+		let encode = |id: &ID, plaintext: &[u8]| {
+			let encrypted = keys.encrypt_object(&id[..], plaintext);
+			[&encrypted.ciphertext, encrypted.siv.as_ref()].concat()
+		};
+		let valid_body = encode(&id, &serde_json::to_vec(&entry).unwrap());
+		assert_eq!(decode_server_object(&keys, &id, &valid_body).unwrap(), Some(entry.clone()));
+
+		// Too short to even contain a SIV
+		for body in [Vec::new(), vec![0; 31]] {
+			assert!(matches!(decode_server_object(&keys, &id, &body), Err(FortressError::SyncMalformedResponse)));
+		}
+
+		// Validly encrypted under our ID, but a different object
+		let other_entry = DatabaseObject::Entry(Entry::new());
+		assert!(matches!(
+			decode_server_object(&keys, &id, &encode(&id, &serde_json::to_vec(&other_entry).unwrap())),
+			Err(FortressError::SyncInconsistentServer)
+		));
+
+		// Can't be decrypted or deserialized; these are ignored
+		let mut corrupted = valid_body.clone();
+		corrupted[0] ^= 1;
+		for body in [
+			vec![0; 32],
+			valid_body[1..].to_vec(),
+			valid_body[..valid_body.len() - 1].to_vec(),
+			corrupted,
+			[valid_body.as_slice(), &[0; 32]].concat(),
+			encode(&id, b"not json"),
+			encode(&id, b"{\"type\":\"Entry\"}"),
+		] {
+			assert_eq!(decode_server_object(&keys, &id, &body).unwrap(), None);
+		}
+
+		// Valid, but over the limits
+		let history = (0..=MAX_HISTORY_LENGTH)
+			.map(|time| serde_json::json!({ "time": time, "data": {} }))
+			.collect::<Vec<_>>();
+		let huge_entry = serde_json::json!({ "type": "Entry", "id": id, "history": history, "time_created": 0 });
+		assert!(matches!(
+			decode_server_object(&keys, &id, &encode(&id, &serde_json::to_vec(&huge_entry).unwrap())),
+			Err(FortressError::SyncLimitExceeded {
+				limit: SyncLimit::HistoryLength,
+				..
+			})
+		));
+		assert!(matches!(
+			decode_server_object(&keys, &id, &vec![0; MAX_OBJECT_SIZE + 1]),
+			Err(FortressError::SyncLimitExceeded {
+				limit: SyncLimit::ObjectSize,
+				..
+			})
+		));
+	}
+
+	// A sync URL that can't have a path is rejected instead of panicking
+	#[test]
+	fn bad_sync_url() {
+		let mut db = Database::new_with_password("username", "password");
+		db.set_sync_url(Some(url::Url::parse("mailto:fortress@example.com").unwrap()));
+
+		assert!(matches!(db.sync(), Err(FortressError::SyncBadUrl)));
+		assert!(matches!(db.sync_preview(), Err(FortressError::SyncBadUrl)));
+	}
+
 	// Just some sanity checks on our keys
 	#[test]
 	fn key_sanity_checks() {
@@ -957,6 +2137,117 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn recursive_listing() {
+		let mut db = Database::new_with_password("username", "password");
+		let root_id = *db.get_root().get_id();
+		let work = Directory::new();
+		let sub = Directory::new();
+		let (work_id, sub_id) = (*work.get_id(), *sub.get_id());
+		db.add_directory(work);
+		db.add_directory(sub);
+		db.move_object(&sub_id, &work_id);
+
+		let mut add_entry = |parent: &ID| {
+			let entry = Entry::new();
+			let id = *entry.get_id();
+			db.add_entry(entry);
+			db.move_object(&id, parent);
+			id
+		};
+		let entry1 = add_entry(&root_id);
+		let entry2 = add_entry(&work_id);
+		let entry3 = add_entry(&sub_id);
+		let entry4 = add_entry(&sub_id);
+
+		// Dangling IDs are ignored, a malformed tree with a cycle doesn't loop forever, and an entry in two directories is
+		// only counted once
+		db.get_directory_by_id_mut(&work_id).unwrap().add(OsRng.gen());
+		db.get_directory_by_id_mut(&sub_id).unwrap().add(work_id);
+		db.get_directory_by_id_mut(&sub_id).unwrap().add(entry2);
+		db.get_root_mut().add(entry3);
+
+		let sorted = |mut ids: Vec<&ID>| {
+			ids.sort_unstable();
+			ids.into_iter().copied().collect::<Vec<_>>()
+		};
+		let mut expected = vec![entry2, entry3, entry4];
+		expected.sort_unstable();
+		assert_eq!(sorted(db.get_directory_by_id(&work_id).unwrap().list_entries_recursive(&db)), expected);
+		assert_eq!(db.get_root().list_entries_recursive(&db).len(), 4);
+		assert!(db.get_root().list_entries_recursive(&db).contains(&&entry1));
+
+		assert_eq!(db.count_entries_in(&root_id, false), Some(2));
+		assert_eq!(db.count_entries_in(&root_id, true), Some(4));
+		assert_eq!(db.count_entries_in(&work_id, false), Some(1));
+		assert_eq!(db.count_entries_in(&work_id, true), Some(3));
+		assert_eq!(db.count_entries_in(&sub_id, true), Some(3));
+		assert_eq!(db.count_entries_in(&entry1, true), None);
+		assert_eq!(db.count_entries_in(&OsRng.gen(), false), None);
+	}
+
+	#[test]
+	fn find_entries() {
+		let mut db = Database::new_with_password("username", "password");
+		let mut email = Entry::new();
+		email.edit(EntryHistory::new([("title".to_string(), "Email".to_string())].into_iter().collect()));
+		let mut untitled = Entry::new();
+		untitled.edit(EntryHistory::new([("username".to_string(), "Email".to_string())].into_iter().collect()));
+		let (email_id, untitled_id) = (*email.get_id(), *untitled.get_id());
+		db.add_entry(email);
+		db.add_entry(untitled);
+		let mut work = Directory::new();
+		work.rename("Work");
+		let work_id = *work.get_id();
+		db.add_directory(work);
+
+		let ids = |entries: Vec<&Entry>| entries.into_iter().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+		assert_eq!(ids(db.find_entries("email")), [email_id]);
+		assert_eq!(ids(db.find_entries("EMAIL")), [email_id]);
+		assert_eq!(ids(db.find_entries("Emai")), []);
+		assert_eq!(ids(db.find_entries(&untitled_id.to_hex()[..8].to_uppercase())), [untitled_id]);
+		assert_eq!(ids(db.find_entries("")), []);
+		assert_eq!(ID::from_hex(&email_id.to_hex()), Some(email_id));
+		assert_eq!(ID::from_hex("zz"), None);
+		assert_eq!(ID::from_hex(&email_id.to_hex()[..62]), None);
+
+		assert_eq!(
+			db.find_directories("work").iter().map(|directory| *directory.get_id()).collect::<Vec<_>>(),
+			[work_id]
+		);
+		assert_eq!(db.find_directories(&db.get_root().get_id().to_hex()).len(), 1);
+		assert!(db.find_directories("email").is_empty());
+	}
+
+	#[test]
+	fn tags() {
+		let mut db = Database::new_with_password("username", "password");
+		assert!(db.list_tags().is_empty());
+
+		let mut entry1 = Entry::new();
+		entry1.add_tag("banking");
+		entry1.add_tag("work");
+		let mut entry2 = Entry::new();
+		entry2.add_tag("banking");
+		let entry3 = Entry::new();
+		let (id1, id2) = (*entry1.get_id(), *entry2.get_id());
+		db.add_entry(entry1);
+		db.add_entry(entry2);
+		db.add_entry(entry3);
+
+		assert_eq!(db.list_tags().into_iter().collect::<Vec<_>>(), ["banking", "work"]);
+		let mut banking = db.find_by_tag("banking").map(|entry| *entry.get_id()).collect::<Vec<_>>();
+		banking.sort_unstable();
+		let mut expected = vec![id1, id2];
+		expected.sort_unstable();
+		assert_eq!(banking, expected);
+		assert_eq!(db.find_by_tag("work").map(|entry| *entry.get_id()).collect::<Vec<_>>(), [id1]);
+		assert_eq!(db.find_by_tag("missing").count(), 0);
+
+		db.get_entry_by_id_mut(&id1).unwrap().remove_tag("work");
+		assert_eq!(db.list_tags().into_iter().collect::<Vec<_>>(), ["banking"]);
+	}
+
 	// Test to make sure serialization is fully deterministic (the same database object serializes to the same string every time)
 	#[test]
 	fn entry_deterministic_serialization() {
@@ -980,6 +2271,12 @@ mod tests {
 				.cloned()
 				.collect(),
 		));
+		entry.edit(
+			EntryHistory::new(HashMap::new())
+				.with_tag("work", true)
+				.with_tag("banking", true)
+				.with_tag("travel", false),
+		);
 
 		let object = DatabaseObject::Entry(entry);
 
@@ -1008,15 +2305,10 @@ mod tests {
 		let id3: ID = OsRng.gen();
 
 		directory.add(id1.clone());
-		quick_sleep();
 		directory.add(id2.clone());
-		quick_sleep();
 		directory.add(id3.clone());
-		quick_sleep();
 		directory.remove(id2.clone());
-		quick_sleep();
 		directory.remove(id3.clone());
-		quick_sleep();
 		directory.add(id2.clone());
 
 		let object = DatabaseObject::Directory(directory);
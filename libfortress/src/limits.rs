@@ -0,0 +1,198 @@
+// Limits on what the sync server can send us, so a malicious or broken server can't exhaust our memory with pathological
+// objects (gigantic histories, huge strings, endless listings).  Responses are read only up to a size limit, and every object
+// downloaded is checked against these limits before it's merged, failing the sync with FortressError::SyncLimitExceeded.
+// Our own objects are checked the same way before they're uploaded, so other devices won't reject them.
+//
+// Database files aren't limited, since they're authenticated with the user's password.  JSON nesting depth (only settings
+// values can nest) is bounded by serde_json's recursion limit.
+use crate::{database_object::DatabaseObject, FortressError, ID};
+use fortresscrypto::EncryptedObject;
+use std::{fmt, io::Read};
+
+
+/// Largest object body we'll download (the encrypted object and its SIV), in bytes.
+pub const MAX_OBJECT_SIZE: usize = 32 << 20;
+/// Most objects a listing from the server can have.
+pub const MAX_OBJECT_COUNT: usize = 250_000;
+/// Most edits an object's history can have (or values, for the settings object, and uses, for entries).
+pub const MAX_HISTORY_LENGTH: usize = 100_000;
+/// Largest field name or value, directory name, tag, or setting, in bytes.
+pub const MAX_FIELD_SIZE: usize = 4 << 20;
+
+// Generous room for one `["<id>","<siv>"]` item in a listing, including whitespace
+const MAX_LISTING_ITEM_SIZE: usize = 256;
+
+
+/// Which of the limits above was exceeded.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SyncLimit {
+	ObjectSize,
+	ObjectCount,
+	HistoryLength,
+	FieldSize,
+}
+
+impl fmt::Display for SyncLimit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SyncLimit::ObjectSize => write!(f, "object size ({MAX_OBJECT_SIZE} bytes)"),
+			SyncLimit::ObjectCount => write!(f, "number of objects ({MAX_OBJECT_COUNT})"),
+			SyncLimit::HistoryLength => write!(f, "history length ({MAX_HISTORY_LENGTH} edits)"),
+			SyncLimit::FieldSize => write!(f, "field size ({MAX_FIELD_SIZE} bytes)"),
+		}
+	}
+}
+
+
+/// Checks that `object`, about to be uploaded, will be accepted when other devices download it.  `encrypted` is its encryption.
+pub(crate) fn check_upload(object: &DatabaseObject, encrypted: &EncryptedObject) -> Result<(), FortressError> {
+	check_object(object)?;
+	check_object_size(object.get_id(), encrypted.ciphertext.len() + encrypted.siv.as_ref().len())
+}
+
+
+/// Checks `object` against the history length and field size limits.
+pub(crate) fn check_object(object: &DatabaseObject) -> Result<(), FortressError> {
+	object.check_limits().map_err(|limit| FortressError::SyncLimitExceeded {
+		id: Some(*object.get_id()),
+		limit,
+	})
+}
+
+
+pub(crate) fn check_history_length(length: usize) -> Result<(), SyncLimit> {
+	if length > MAX_HISTORY_LENGTH {
+		return Err(SyncLimit::HistoryLength);
+	}
+
+	Ok(())
+}
+
+
+pub(crate) fn check_field<S: AsRef<str>>(field: S) -> Result<(), SyncLimit> {
+	if field.as_ref().len() > MAX_FIELD_SIZE {
+		return Err(SyncLimit::FieldSize);
+	}
+
+	Ok(())
+}
+
+
+/// Checks the number of objects in a listing from the server.
+pub(crate) fn check_object_count(count: usize) -> Result<(), FortressError> {
+	if count > MAX_OBJECT_COUNT {
+		return Err(FortressError::SyncLimitExceeded {
+			id: None,
+			limit: SyncLimit::ObjectCount,
+		});
+	}
+
+	Ok(())
+}
+
+
+/// Checks the size of an object body from the server.
+pub(crate) fn check_object_size(id: &ID, size: usize) -> Result<(), FortressError> {
+	if size > MAX_OBJECT_SIZE {
+		return Err(FortressError::SyncLimitExceeded {
+			id: Some(*id),
+			limit: SyncLimit::ObjectSize,
+		});
+	}
+
+	Ok(())
+}
+
+
+/// Reads an object body from the server, stopping as soon as it's over MAX_OBJECT_SIZE.
+pub(crate) fn read_object<R: Read>(id: &ID, response: R) -> Result<Vec<u8>, FortressError> {
+	let body = read_at_most(response, MAX_OBJECT_SIZE)?;
+	check_object_size(id, body.len())?;
+	Ok(body)
+}
+
+
+/// Reads an object listing from the server, stopping as soon as it's too large to be within MAX_OBJECT_COUNT.
+pub(crate) fn read_listing<R: Read>(response: R) -> Result<Vec<u8>, FortressError> {
+	let max = MAX_OBJECT_COUNT * MAX_LISTING_ITEM_SIZE;
+	let body = read_at_most(response, max)?;
+
+	if body.len() > max {
+		return Err(FortressError::SyncLimitExceeded {
+			id: None,
+			limit: SyncLimit::ObjectCount,
+		});
+	}
+
+	Ok(body)
+}
+
+
+// Reads up to one byte past `max`, so callers can tell the limit was exceeded
+fn read_at_most<R: Read>(reader: R, max: usize) -> Result<Vec<u8>, FortressError> {
+	let mut body = Vec::new();
+	reader.take(max as u64 + 1).read_to_end(&mut body)?;
+	Ok(body)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{read_listing, read_object, SyncLimit, MAX_FIELD_SIZE, MAX_HISTORY_LENGTH, MAX_OBJECT_SIZE};
+	use crate::{
+		database_object::{DatabaseObject, SettingsObject},
+		Directory, Entry, EntryHistory, FortressError, ID,
+	};
+	use rand::{rngs::OsRng, Rng};
+	use std::io::{self, Read};
+
+	#[test]
+	fn object_limits() {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new([("title".to_string(), "x".repeat(MAX_FIELD_SIZE))].into()));
+		assert_eq!(DatabaseObject::Entry(entry.clone()).check_limits(), Ok(()));
+		entry.edit(entry.new_edit([("y".repeat(MAX_FIELD_SIZE + 1), String::new())].into()));
+		assert_eq!(DatabaseObject::Entry(entry).check_limits(), Err(SyncLimit::FieldSize));
+
+		let mut entry = Entry::new();
+		entry.add_tag("x".repeat(MAX_FIELD_SIZE + 1));
+		assert_eq!(DatabaseObject::Entry(entry).check_limits(), Err(SyncLimit::FieldSize));
+
+		let mut directory = Directory::new();
+		for time in 0..=MAX_HISTORY_LENGTH as u64 {
+			directory.rename_with_time(time.to_string(), time);
+		}
+		assert_eq!(DatabaseObject::Directory(directory).check_limits(), Err(SyncLimit::HistoryLength));
+
+		let mut settings = SettingsObject::new();
+		settings.set("key", serde_json::Value::String("x".repeat(MAX_FIELD_SIZE)));
+		assert_eq!(DatabaseObject::Settings(settings.clone()).check_limits(), Err(SyncLimit::FieldSize));
+		settings.set("key", serde_json::Value::Bool(true));
+		assert_eq!(DatabaseObject::Settings(settings).check_limits(), Ok(()));
+	}
+
+	// An endless response is cut off instead of being read into memory
+	#[test]
+	fn response_limits() {
+		let id: ID = OsRng.gen();
+		let endless = || io::repeat(b' ');
+
+		assert!(matches!(
+			read_object(&id, endless()),
+			Err(FortressError::SyncLimitExceeded {
+				id: Some(error_id),
+				limit: SyncLimit::ObjectSize,
+			}) if error_id == id
+		));
+		assert!(matches!(
+			read_listing(endless()),
+			Err(FortressError::SyncLimitExceeded {
+				id: None,
+				limit: SyncLimit::ObjectCount,
+			})
+		));
+
+		assert_eq!(read_object(&id, endless().take(MAX_OBJECT_SIZE as u64)).unwrap().len(), MAX_OBJECT_SIZE);
+		assert_eq!(read_listing(&b"[]"[..]).unwrap(), b"[]");
+	}
+}
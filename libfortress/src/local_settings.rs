@@ -0,0 +1,131 @@
+// Preferences that stay on this device, kept as plaintext JSON next to the database file (`path` with a `.settings.json`
+// suffix), so frontends can read them before the database is unlocked.  Nothing secret belongs here.  Preferences that should
+// follow the user to their other devices, like the auto-lock time, generator presets, and the backup policy, are kept in the
+// database instead (see `Database::set_setting` and `Database::set_backup_policy`).
+//
+// Schema (every key is optional):
+//   "version": LOCAL_SETTINGS_VERSION, the schema the file was written with
+//   "window_size": { "width": <u32>, "height": <u32> }, the main window's last size in logical pixels
+//   "generator": GeneratorOptions, the options the generator starts with on this device
+// Any other keys are the frontends' own, and are kept as they are, so older versions don't drop what newer ones wrote.
+use crate::{persist_atomically, FortressError, GeneratorOptions};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::BTreeMap,
+	fs,
+	io::{self, Write},
+	path::{Path, PathBuf},
+};
+
+
+/// The schema version `LocalSettings::save` writes.
+pub const LOCAL_SETTINGS_VERSION: u32 = 1;
+
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LocalSettings {
+	#[serde(default = "default_version")]
+	pub version: u32,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub window_size: Option<WindowSize>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub generator: Option<GeneratorOptions>,
+	/// Keys this version doesn't know about.
+	#[serde(flatten)]
+	pub other: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct WindowSize {
+	pub width: u32,
+	pub height: u32,
+}
+
+impl Default for LocalSettings {
+	fn default() -> LocalSettings {
+		LocalSettings {
+			version: LOCAL_SETTINGS_VERSION,
+			window_size: None,
+			generator: None,
+			other: BTreeMap::new(),
+		}
+	}
+}
+
+impl LocalSettings {
+	/// Where the settings for the database at `database_path` are kept.
+	pub fn path_for<P: AsRef<Path>>(database_path: P) -> PathBuf {
+		let mut path = database_path.as_ref().as_os_str().to_owned();
+		path.push(".settings.json");
+		PathBuf::from(path)
+	}
+
+	/// The settings for the database at `database_path`, or the defaults if none have been saved.
+	pub fn load<P: AsRef<Path>>(database_path: P) -> Result<LocalSettings, FortressError> {
+		match fs::read(LocalSettings::path_for(database_path)) {
+			Ok(json) => Ok(serde_json::from_slice(&json)?),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(LocalSettings::default()),
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	/// Saves the settings for the database at `database_path`, as the current schema version.
+	pub fn save<P: AsRef<Path>>(&self, database_path: P) -> Result<(), FortressError> {
+		let settings = LocalSettings {
+			version: LOCAL_SETTINGS_VERSION,
+			..self.clone()
+		};
+		let json = serde_json::to_vec_pretty(&settings)?;
+
+		persist_atomically(LocalSettings::path_for(database_path), |writer| writer.write_all(&json))
+	}
+}
+
+
+fn default_version() -> u32 {
+	LOCAL_SETTINGS_VERSION
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{LocalSettings, WindowSize, LOCAL_SETTINGS_VERSION};
+	use crate::GeneratorOptions;
+	use std::fs;
+
+	#[test]
+	fn load_and_save() {
+		let dir = tempfile::tempdir().unwrap();
+		let database_path = dir.path().join("database.fortress");
+
+		// Nothing saved yet
+		assert_eq!(LocalSettings::load(&database_path).unwrap(), LocalSettings::default());
+
+		let settings = LocalSettings {
+			window_size: Some(WindowSize { width: 800, height: 600 }),
+			generator: Some(GeneratorOptions {
+				length: 32,
+				..GeneratorOptions::default()
+			}),
+			..LocalSettings::default()
+		};
+		settings.save(&database_path).unwrap();
+		assert_eq!(LocalSettings::load(&database_path).unwrap(), settings);
+		assert!(dir.path().join("database.fortress.settings.json").exists());
+
+		// Keys from other versions and frontends are kept
+		let path = LocalSettings::path_for(&database_path);
+		fs::write(&path, r#"{"version": 7, "theme": "dark", "window_size": {"width": 1, "height": 2}}"#).unwrap();
+		let settings = LocalSettings::load(&database_path).unwrap();
+		assert_eq!(settings.version, 7);
+		assert_eq!(settings.window_size, Some(WindowSize { width: 1, height: 2 }));
+		assert_eq!(settings.generator, None);
+		settings.save(&database_path).unwrap();
+		let saved: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+		assert_eq!(saved["theme"], "dark");
+		assert_eq!(saved["version"], LOCAL_SETTINGS_VERSION);
+
+		fs::write(&path, "not json").unwrap();
+		assert!(LocalSettings::load(&database_path).is_err());
+	}
+}
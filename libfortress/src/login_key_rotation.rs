@@ -0,0 +1,82 @@
+// Rotating the login key on its own, e.g. after it was pasted somewhere it shouldn't have been.  The login key is normally
+// derived from the username and password along with the encryption keys, so it only changes when they do.  `rotate_login_key`
+// instead replaces it with a random one, which is saved with the database like the rest of the network keys, and tells the
+// server with the same `/user/login_key` request a password change uses.  The encryption keys stay the same, so nothing on the
+// server needs re-encrypting.
+//
+// Since the new key can't be derived from the password, other devices syncing with the same account are rejected until they're
+// given it with `set_login_key`.  A later password change derives the login key from the new password again.
+use crate::{Database, FortressError};
+use fortresscrypto::LoginKey;
+use rand::rngs::OsRng;
+
+
+impl Database {
+	/// Replaces the login key with a random one and tells the sync server (see the login_key_rotation module).  Fails with
+	/// FortressError::SyncBadUrl, leaving the key alone, if there's no sync URL, and with FortressError::SyncReadOnly if the
+	/// database syncs with read-only credentials.  If the server can't be told, the database
+	/// keeps the new key and the next sync tells it, so it should be saved either way.  A password change the server hasn't
+	/// been told about yet is sent first.
+	pub fn rotate_login_key(&mut self) -> Result<(), FortressError> {
+		let url = self.sync_url.clone().ok_or(FortressError::SyncBadUrl)?;
+		self.check_sync_writable()?;
+		let client = self.sync_client()?;
+
+		if let Some(old_sync_parameters) = &self.old_sync_parameters {
+			self.sync_api_update_login_key(&client, &url, old_sync_parameters)?;
+			self.old_sync_parameters = None;
+		}
+
+		let old_sync_parameters = self.sync_parameters.freeze().expect("internal error");
+		self.sync_parameters.set_login_key(LoginKey::from_rng(&mut OsRng));
+		self.old_sync_parameters = Some(old_sync_parameters.clone());
+		self.sync_api_update_login_key(&client, &url, &old_sync_parameters)?;
+		self.old_sync_parameters = None;
+
+		Ok(())
+	}
+
+	/// Uses `login_key`, rotated on another device with `rotate_login_key` (see `get_login_key`), to log in to the sync server.
+	/// The server already knows it, so it isn't told.  A database that syncs with read-only credentials gets full access again.
+	pub fn set_login_key(&mut self, login_key: LoginKey) {
+		self.sync_parameters.set_login_key(login_key);
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, FortressError};
+	use reqwest::Url;
+
+	#[test]
+	fn rotate_login_key() {
+		let mut db = Database::new_with_password("username", "password");
+		let login_key = db.get_login_key().clone();
+		assert!(matches!(db.rotate_login_key(), Err(FortressError::SyncBadUrl)));
+		assert_eq!(db.get_login_key(), &login_key);
+
+		// The server can't be told; the next sync tells it, with the key from before
+		db.set_sync_url(Some(Url::parse("http://127.0.0.1:1").unwrap()));
+		assert!(db.rotate_login_key().is_err());
+		assert_ne!(db.get_login_key(), &login_key);
+		assert_eq!(db.old_sync_parameters.as_ref().unwrap().get_login_key(), &login_key);
+
+		// Kept when saved, along with the encryption keys
+		let encryption_keys = db.sync_parameters.get_network_key_suite().unwrap().encrypt_object(b"id", b"data").ciphertext;
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("database.fortress");
+		db.save_to_path(&path).unwrap();
+		let saved = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(saved.get_login_key(), db.get_login_key());
+		assert_eq!(
+			saved.sync_parameters.get_network_key_suite().unwrap().encrypt_object(b"id", b"data").ciphertext,
+			encryption_keys
+		);
+
+		let mut other = Database::new_with_password("username", "password");
+		other.set_login_key(db.get_login_key().clone());
+		assert_eq!(other.get_login_key(), db.get_login_key());
+		assert!(other.old_sync_parameters.is_none());
+	}
+}
@@ -0,0 +1,291 @@
+// Upgrades databases saved in older on-disk formats.
+// Every format version has a parser and an upgrade step to the next version.  Loading a file parses it with the parser for its
+// own version and then runs upgrade steps until it reaches CURRENT_FORMAT_VERSION, so each step only ever has to know about
+// two adjacent versions.
+//
+// To add a new version: add it to FormatVersion, detect it in FormatVersion::detect, give it a variant in ParsedDatabase, and add
+// the upgrade step from the previous version to ParsedDatabase::upgrade.
+use crate::{
+	database_object_map::DatabaseObjectMap,
+	derivation_cache::DerivationCache,
+	device::DeviceIdentity,
+	file_changes::FileVersionState,
+	file_lock::FileLockState,
+	journal::JournalState,
+	key_rotation::KeyRotation,
+	legacy::LegacyDatabase,
+	recent::RecentEntries,
+	sync_parameters::{FrozenSyncParameters, SyncParameters},
+	BackupPolicy, Database, FortressError, SyncTarget, ID,
+};
+use fortresscrypto::{FileKeySuite, HardwareKeyFn, LoginId, ShareSecretKey};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{collections::HashMap, io::Cursor};
+use url::Url;
+
+
+/// On-disk format versions, oldest first.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum FormatVersion {
+	/// The original fortress1-scrypt-chacha20 format (see ENCRYPTION.md)
+	Fortress1,
+	/// fortress2
+	Fortress2,
+}
+
+pub const CURRENT_FORMAT_VERSION: FormatVersion = FormatVersion::Fortress2;
+
+impl FormatVersion {
+	fn detect(data: &[u8]) -> FormatVersion {
+		if fortresscrypto::is_legacy_file(data) {
+			FormatVersion::Fortress1
+		} else {
+			// Anything else is handed to the current parser, which will reject unknown headers
+			FormatVersion::Fortress2
+		}
+	}
+}
+
+
+/// Extra information an upgrade step might need.
+pub(crate) struct MigrationContext<'a> {
+	pub password: &'a str,
+	/// fortress1 databases don't have a username, so one has to be provided to upgrade them.
+	pub username: Option<&'a str>,
+	/// Leave entries encoded until they're used (see `Database::load_from_reader_lazily`).  Only the current format can be
+	/// loaded lazily; older formats are always decoded in full.
+	pub lazy_entries: bool,
+	/// Asked for the hardware key's response if the file needs one (see `Database::load_from_reader_with_hardware_key`).
+	pub hardware_key: &'a HardwareKeyFn<'a>,
+}
+
+
+enum ParsedDatabase {
+	Fortress1(LegacyDatabase),
+	Fortress2(Box<Database>),
+}
+
+impl ParsedDatabase {
+	fn parse(version: FormatVersion, data: &[u8], context: &MigrationContext) -> Result<ParsedDatabase, FortressError> {
+		Ok(match version {
+			FormatVersion::Fortress1 => ParsedDatabase::Fortress1(LegacyDatabase::parse(data, context.password)?),
+			FormatVersion::Fortress2 if context.lazy_entries => ParsedDatabase::Fortress2(Box::new(parse_fortress2::<LazyObjectMap>(data, context)?)),
+			FormatVersion::Fortress2 => ParsedDatabase::Fortress2(Box::new(parse_fortress2::<DatabaseObjectMap>(data, context)?)),
+		})
+	}
+
+	fn version(&self) -> FormatVersion {
+		match self {
+			ParsedDatabase::Fortress1(_) => FormatVersion::Fortress1,
+			ParsedDatabase::Fortress2(_) => FormatVersion::Fortress2,
+		}
+	}
+
+	/// Upgrade to the next version.
+	fn upgrade(self, context: &MigrationContext) -> Result<ParsedDatabase, FortressError> {
+		match self {
+			ParsedDatabase::Fortress1(legacy) => {
+				let username = context.username.ok_or(FortressError::LegacyDatabase)?;
+				Ok(ParsedDatabase::Fortress2(Box::new(legacy.upgrade(username, context.password)?)))
+			},
+			ParsedDatabase::Fortress2(_) => unreachable!("fortress2 is the current version"),
+		}
+	}
+}
+
+
+/// Parses a database file of any supported version, upgrading it to the current version.
+pub(crate) fn load(data: &[u8], context: &MigrationContext) -> Result<Database, FortressError> {
+	let original_version = FormatVersion::detect(data);
+	let mut parsed = ParsedDatabase::parse(original_version, data, context)?;
+
+	while parsed.version() != CURRENT_FORMAT_VERSION {
+		parsed = parsed.upgrade(context)?;
+	}
+
+	match parsed {
+		ParsedDatabase::Fortress2(mut database) => {
+			database.original_format_version = original_version;
+			Ok(*database)
+		},
+		_ => unreachable!(),
+	}
+}
+
+
+// This struct is needed because Database has fields that aren't part of
+// serialization, but can't implement Default.
+#[derive(Deserialize)]
+struct SerializableDatabase<O = DatabaseObjectMap> {
+	objects: O,
+	sync_parameters: SyncParameters,
+	sync_url: Option<Url>,
+	#[serde(default)]
+	sync_proxy: Option<Url>,
+	old_sync_parameters: Option<FrozenSyncParameters>,
+	#[serde(default)]
+	sync_targets: Vec<SyncTarget>,
+	#[serde(default)]
+	key_rotation: Option<KeyRotation>,
+	#[serde(default)]
+	compacted_objects: HashMap<ID, u64>,
+	#[serde(default)]
+	account_login_id: Option<LoginId>,
+	#[serde(default)]
+	recent_entries: RecentEntries,
+	#[serde(default)]
+	derivation_cache: DerivationCache,
+	#[serde(default)]
+	device: Option<DeviceIdentity>,
+	/// Databases saved before sharing was added get a share key when they're next opened.
+	#[serde(default = "ShareSecretKey::generate")]
+	share_key: ShareSecretKey,
+	#[serde(default)]
+	backup_policy: BackupPolicy,
+	#[serde(default)]
+	sign_saves: bool,
+}
+
+
+// Objects deserialized with `DatabaseObjectMap::deserialize_lazily`
+struct LazyObjectMap(DatabaseObjectMap);
+
+impl<'de> Deserialize<'de> for LazyObjectMap {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: serde::Deserializer<'de>,
+	{
+		DatabaseObjectMap::deserialize_lazily(deserializer).map(LazyObjectMap)
+	}
+}
+
+impl From<LazyObjectMap> for DatabaseObjectMap {
+	fn from(objects: LazyObjectMap) -> DatabaseObjectMap {
+		objects.0
+	}
+}
+
+
+/// Loads a current format database file using file keys that were already derived (see the keychain module), rather than its
+/// password.  Older formats aren't supported.
+#[cfg(feature = "keychain")]
+pub(crate) fn load_with_file_keys(data: &[u8], file_key_suite: FileKeySuite) -> Result<Database, FortressError> {
+	let plaintext = fortresscrypto::decrypt_from_file_with_keys(&mut Cursor::new(data), &file_key_suite)?;
+
+	deserialize_fortress2::<DatabaseObjectMap>(&plaintext, file_key_suite, None)
+}
+
+
+fn parse_fortress2<O: DeserializeOwned + Into<DatabaseObjectMap>>(data: &[u8], context: &MigrationContext) -> Result<Database, FortressError> {
+	// Decrypt
+	let (plaintext, file_key_suite) =
+		fortresscrypto::decrypt_from_file_with_hardware_key(&mut Cursor::new(data), context.password.as_bytes(), context.hardware_key)?;
+
+	deserialize_fortress2::<O>(&plaintext, file_key_suite, Some(context.password))
+}
+
+
+// The password is needed to derive the sync keys if they weren't saved, and to remember them in the derivation cache
+pub(crate) fn deserialize_fortress2<O: DeserializeOwned + Into<DatabaseObjectMap>>(
+	plaintext: &[u8],
+	file_key_suite: FileKeySuite,
+	password: Option<&str>,
+) -> Result<Database, FortressError> {
+	let mut db: SerializableDatabase<O> = serde_json::from_slice(plaintext)?;
+	// Other users' passwords only open the file; the sync keys are the database's own password's (see the users module)
+	let password = password.filter(|_| file_key_suite.get_keyslot() == 0);
+
+	// TODO: Background derive
+	if db.sync_parameters.get_network_key_suite().is_none() {
+		db.sync_parameters.derive(password.ok_or(FortressError::PasswordRequired)?);
+	}
+
+	// Keep encryption keys for quicker saving later
+	let mut database = Database {
+		objects: db.objects.into(),
+		sync_parameters: db.sync_parameters,
+
+		file_key_suite,
+		sync_url: db.sync_url,
+		sync_proxy: db.sync_proxy,
+		old_sync_parameters: db.old_sync_parameters,
+		sync_targets: db.sync_targets,
+		key_rotation: db.key_rotation,
+		compacted_objects: db.compacted_objects,
+		account_login_id: db.account_login_id,
+		recent_entries: db.recent_entries,
+		derivation_cache: db.derivation_cache,
+		device: db.device,
+		share_key: db.share_key,
+		backup_policy: db.backup_policy,
+		sign_saves: db.sign_saves,
+		original_format_version: FormatVersion::Fortress2,
+		journal: JournalState::default(),
+		file_lock: FileLockState::default(),
+		file_version: FileVersionState::default(),
+	};
+	database.objects.set_device(database.get_device_id().copied());
+	if let Some(password) = password {
+		database.cache_sync_keys(password);
+	}
+
+	Ok(database)
+}
+
+
+/// Deserializes the decrypted payload of a fortress2 file, without doing anything with it.  For the fuzz targets in /fuzz.
+#[cfg(feature = "fuzzing")]
+pub(crate) fn parse_fortress2_payload(plaintext: &[u8]) -> Result<(), FortressError> {
+	serde_json::from_slice::<SerializableDatabase>(plaintext)?;
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{FormatVersion, CURRENT_FORMAT_VERSION};
+	use crate::{Database, FortressError};
+	use std::{fs, io::Cursor};
+	use tempfile::tempdir;
+
+	const LEGACY_DATABASE: &[u8] = include_bytes!("../tests/data/legacy.fortress1");
+
+	#[test]
+	fn upgrade_chain() {
+		// Current version loads as-is
+		let db = Database::new_with_password("username", "password");
+		assert_eq!(db.get_original_format_version(), CURRENT_FORMAT_VERSION);
+		assert!(!db.needs_resave());
+
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("database.fortress");
+		db.save_to_path(&path).unwrap();
+		let loaded = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(loaded.get_original_format_version(), FormatVersion::Fortress2);
+		assert!(!loaded.needs_resave());
+
+		// fortress1 needs a username to upgrade
+		assert!(matches!(
+			Database::load_from_reader("password", &mut Cursor::new(LEGACY_DATABASE)),
+			Err(FortressError::LegacyDatabase)
+		));
+
+		let upgraded = Database::import_legacy_from_reader("username", "password", &mut Cursor::new(LEGACY_DATABASE)).unwrap();
+		assert_eq!(upgraded.get_original_format_version(), FormatVersion::Fortress1);
+		assert!(upgraded.needs_resave());
+
+		// Once re-saved, it's in the current format
+		upgraded.save_to_path(&path).unwrap();
+		let reloaded = Database::load_from_path(&path, "password").unwrap();
+		assert!(!reloaded.needs_resave());
+		assert_eq!(reloaded.list_entries().count(), 2);
+
+		// Unknown versions are rejected
+		let mut unknown = fs::read(&path).unwrap();
+		unknown[8] = b'9';
+		assert!(matches!(
+			Database::load_from_reader("password", &mut Cursor::new(&unknown)),
+			Err(FortressError::CryptoError(_))
+		));
+	}
+}
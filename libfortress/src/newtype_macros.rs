@@ -67,6 +67,12 @@ macro_rules! newtype_from_slice (($newtype:ident, $len:expr) => (
         use ::data_encoding::HEXLOWER_PERMISSIVE;
         HEXLOWER_PERMISSIVE.encode(&self[..])
     }
+
+	/// Parses the (case-insensitive) hex produced by `to_hex`.  Returns None if it isn't valid hex of the right length.
+	pub fn from_hex(hex: &str) -> Option<$newtype> {
+		use ::data_encoding::HEXLOWER_PERMISSIVE;
+		$newtype::from_slice(&HEXLOWER_PERMISSIVE.decode(hex.as_bytes()).ok()?)
+	}
 ));
 
 macro_rules! newtype_traits (($newtype:ident, $len:expr) => (
@@ -257,9 +263,9 @@ macro_rules! new_type {
         }
         impl Drop for $name {
             fn drop(&mut self) {
-				use crypto::util::secure_memset;
+                use ::zeroize::Zeroize;
                 let &mut $name(ref mut v) = self;
-				secure_memset(v, 0);
+                v.zeroize();
             }
         }
         );
@@ -0,0 +1,364 @@
+// Secondary indexes over a database's objects, so common lookups don't have to look at every object: the directories holding an
+// object, the entries for a URL host, entries by title, entries by tag, and objects by ID prefix.  DatabaseObjectMap keeps the
+// index up to date as objects are added and replaced.
+//
+// Objects can also be changed through mutable references (`DatabaseObjectMap::get_mut`), which the index can't see.  Those
+// objects are marked stale instead: lookups always include them as candidates, and they're indexed again the next time the map
+// is modified, by which point the mutable reference is gone.  Lazily loaded entries are likewise left out of the index, and
+// always included as candidates, until they're decoded for good.  Because of this, and because titles are indexed by trigram,
+// lookups only return candidates, which callers check against the objects themselves.  They never miss a match, though.
+//
+// Fuzzy search (see the search module) matches characters in order anywhere in a field, which these indexes can't narrow
+// down, so it still looks at every entry.
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	fmt,
+};
+
+use crate::{database_object::DatabaseObject, url_match::normalize_url, FieldKind, ID};
+
+
+#[derive(Clone, Default)]
+pub(crate) struct ObjectIndex {
+	/// Object -> the directories containing it (usually just one)
+	parents: HashMap<ID, HashSet<ID>>,
+	/// Host of an entry's URL fields, normalized like `normalize_url` -> entries
+	hosts: HashMap<String, HashSet<ID>>,
+	/// Trigram of an entry's lowercase title -> entries
+	title_trigrams: HashMap<[char; 3], HashSet<ID>>,
+	tags: BTreeMap<String, HashSet<ID>>,
+	ids: BTreeSet<ID>,
+	/// What each object was indexed under, so it can be taken out of the index when it changes
+	keys: HashMap<ID, IndexKeys>,
+	/// Objects that may have changed since they were indexed
+	stale: HashSet<ID>,
+	/// Lazily loaded entries, which aren't indexed until they're decoded for good.  Unlike stale objects, they can't be
+	/// anyone's parent, so parent lookups don't have to decode them.
+	unindexed_entries: HashSet<ID>,
+}
+
+#[derive(Clone, Default)]
+struct IndexKeys {
+	children: Vec<ID>,
+	hosts: Vec<String>,
+	title_trigrams: Vec<[char; 3]>,
+	tags: Vec<String>,
+}
+
+
+impl ObjectIndex {
+	/// Indexes `object`, replacing what was indexed for it before.
+	pub(crate) fn insert(&mut self, object: &DatabaseObject) {
+		let id = *object.get_id();
+		self.remove(&id);
+
+		let keys = match object {
+			DatabaseObject::Directory(directory) => IndexKeys {
+				children: directory.entries.iter().copied().collect(),
+				..Default::default()
+			},
+			DatabaseObject::Entry(entry) => {
+				let mut hosts = entry
+					.get_fields()
+					.iter()
+					.filter(|field| field.kind == FieldKind::Url)
+					.filter_map(|field| normalize_url(field.value)?.host_str().map(str::to_owned))
+					.collect::<Vec<_>>();
+				hosts.sort_unstable();
+				hosts.dedup();
+
+				IndexKeys {
+					children: Vec::new(),
+					hosts,
+					title_trigrams: trigrams(entry.get("title").map(String::as_str).unwrap_or("")),
+					tags: entry.get_tags().iter().cloned().collect(),
+				}
+			},
+			DatabaseObject::Settings(_) | DatabaseObject::Tombstone(_) => IndexKeys::default(),
+		};
+
+		for child in &keys.children {
+			self.parents.entry(*child).or_default().insert(id);
+		}
+		for host in &keys.hosts {
+			self.hosts.entry(host.clone()).or_default().insert(id);
+		}
+		for trigram in &keys.title_trigrams {
+			self.title_trigrams.entry(*trigram).or_default().insert(id);
+		}
+		for tag in &keys.tags {
+			self.tags.entry(tag.clone()).or_default().insert(id);
+		}
+
+		self.ids.insert(id);
+		self.keys.insert(id, keys);
+		self.stale.remove(&id);
+		self.unindexed_entries.remove(&id);
+	}
+
+	// Takes what was indexed for `id` back out, apart from its ID
+	fn remove(&mut self, id: &ID) {
+		let Some(keys) = self.keys.remove(id) else {
+			return;
+		};
+
+		for child in &keys.children {
+			remove_from(&mut self.parents, child, id);
+		}
+		for host in &keys.hosts {
+			remove_from(&mut self.hosts, host, id);
+		}
+		for trigram in &keys.title_trigrams {
+			remove_from(&mut self.title_trigrams, trigram, id);
+		}
+		for tag in &keys.tags {
+			if let Some(ids) = self.tags.get_mut(tag) {
+				ids.remove(id);
+				if ids.is_empty() {
+					self.tags.remove(tag);
+				}
+			}
+		}
+	}
+
+	/// Takes object `id` out of the index altogether, for objects that are removed from the map.
+	pub(crate) fn forget(&mut self, id: &ID) {
+		self.remove(id);
+		self.ids.remove(id);
+		self.stale.remove(id);
+		self.unindexed_entries.remove(id);
+	}
+
+	/// Marks object `id` as possibly changed, until it's indexed again.
+	pub(crate) fn mark_stale(&mut self, id: ID) {
+		self.stale.insert(id);
+	}
+
+	/// Marks lazily loaded entry `id` as not indexed, until it's indexed.
+	pub(crate) fn mark_unindexed_entry(&mut self, id: ID) {
+		self.ids.insert(id);
+		self.unindexed_entries.insert(id);
+	}
+
+	/// Objects marked stale since they were last indexed, leaving none marked.
+	pub(crate) fn take_stale(&mut self) -> HashSet<ID> {
+		std::mem::take(&mut self.stale)
+	}
+
+	/// Candidates for the directories containing `id`.
+	pub(crate) fn parent_candidates(&self, id: &ID) -> BTreeSet<ID> {
+		self.parents.get(id).into_iter().flatten().chain(&self.stale).copied().collect()
+	}
+
+	/// Candidates for the entries with a URL for `host` or one of its parent domains (see `Database::find_entries_for_url`).
+	pub(crate) fn host_candidates(&self, host: &str) -> BTreeSet<ID> {
+		let mut candidates = self.with_stale(self.hosts.get(host));
+
+		for (i, _) in host.match_indices('.') {
+			candidates.extend(self.hosts.get(&host[i + 1..]).into_iter().flatten());
+		}
+
+		candidates
+	}
+
+	/// Candidates for the entries titled `title`, ignoring case.
+	pub(crate) fn title_candidates(&self, title: &str) -> BTreeSet<ID> {
+		// Every trigram of the title has to be there, so only the rarest one needs looking at
+		let rarest = trigrams(title)
+			.iter()
+			.map(|trigram| self.title_trigrams.get(trigram))
+			.min_by_key(|ids| ids.map_or(0, HashSet::len))
+			.flatten();
+
+		self.with_stale(rarest)
+	}
+
+	/// Candidates for the entries tagged `tag`.
+	pub(crate) fn tag_candidates(&self, tag: &str) -> BTreeSet<ID> {
+		self.with_stale(self.tags.get(tag))
+	}
+
+	/// Every tag that was indexed, in sorted order.  Tags that are no longer used may still be listed, and tags of stale entries
+	/// may be missing.
+	pub(crate) fn tags(&self) -> impl Iterator<Item = (&str, &HashSet<ID>)> {
+		self.tags.iter().map(|(tag, ids)| (tag.as_str(), ids))
+	}
+
+	/// Stale objects and unindexed entries, which may be missing from any of the index's lookups.
+	pub(crate) fn stale(&self) -> impl Iterator<Item = &ID> {
+		self.stale.iter().chain(&self.unindexed_entries)
+	}
+
+	/// Objects whose hex ID starts with `prefix` (in any case), in order.
+	pub(crate) fn ids_with_prefix(&self, prefix: &str) -> BTreeSet<ID> {
+		let prefix = prefix.to_lowercase();
+		let Some(start) = (prefix.len() <= 64).then(|| ID::from_hex(&format!("{prefix:0<64}"))).flatten() else {
+			return BTreeSet::new();
+		};
+
+		self.ids.range(start..).take_while(|id| id.to_hex().starts_with(&prefix)).copied().collect()
+	}
+
+	fn with_stale(&self, ids: Option<&HashSet<ID>>) -> BTreeSet<ID> {
+		ids.into_iter().flatten().chain(self.stale()).copied().collect()
+	}
+}
+
+// The index is derived from the objects, so it doesn't need to be shown; it would only swamp the objects
+impl fmt::Debug for ObjectIndex {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ObjectIndex").finish_non_exhaustive()
+	}
+}
+
+
+fn remove_from<K: Eq + std::hash::Hash>(map: &mut HashMap<K, HashSet<ID>>, key: &K, id: &ID) {
+	if let Some(ids) = map.get_mut(key) {
+		ids.remove(id);
+		if ids.is_empty() {
+			map.remove(key);
+		}
+	}
+}
+
+
+// The distinct trigrams of a lowercased title, padded so that even short titles have some
+fn trigrams(title: &str) -> Vec<[char; 3]> {
+	let padded = format!("  {} ", title.to_lowercase()).chars().collect::<Vec<_>>();
+	let mut trigrams = padded.windows(3).map(|window| [window[0], window[1], window[2]]).collect::<Vec<_>>();
+	trigrams.sort_unstable();
+	trigrams.dedup();
+	trigrams
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{matches_id_or_name, normalize_url, Database, Directory, Entry, FieldKind, ID};
+	use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+	use std::collections::{BTreeSet, HashMap};
+
+	const TITLES: &[&str] = &["", "a", "Bank", "bank", "My Bank", "Email"];
+	const URLS: &[&str] = &[
+		"example.com",
+		"https://login.example.com",
+		"www.bank.org/login",
+		"http://127.0.0.1:8080",
+		"not a url",
+	];
+	const TAGS: &[&str] = &["work", "home", "finance"];
+
+	// Changes a database in random ways, through every path that modifies objects, checking after each change that lookups
+	// through the index agree with looking at every object
+	#[test]
+	fn index_matches_objects() {
+		let mut rng = StdRng::seed_from_u64(0);
+		let mut db = Database::new_with_password("username", "password");
+		let mut directories = vec![*db.get_root().get_id()];
+
+		for _ in 0..300 {
+			let entries = db.list_entries().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+
+			match rng.gen_range(0..6) {
+				0 => {
+					let mut entry = Entry::new();
+					entry.edit(entry.new_edit(random_fields(&mut rng)));
+					let id = *entry.get_id();
+					db.add_entry(entry);
+					db.move_object(&id, directories.choose(&mut rng).unwrap());
+				},
+				1 => {
+					let directory = Directory::new();
+					directories.push(*directory.get_id());
+					db.add_directory(directory);
+				},
+				2 => {
+					if let Some(id) = entries.choose(&mut rng) {
+						let entry = db.get_entry_by_id_mut(id).unwrap();
+						entry.edit(entry.new_edit(random_fields(&mut rng)));
+					}
+				},
+				3 => {
+					if let Some(id) = entries.choose(&mut rng) {
+						let tag = *TAGS.choose(&mut rng).unwrap();
+						let entry = db.get_entry_by_id_mut(id).unwrap();
+						if rng.gen() {
+							entry.add_tag(tag);
+						} else {
+							entry.remove_tag(tag);
+						}
+					}
+				},
+				4 => {
+					if let Some(id) = entries.choose(&mut rng) {
+						db.move_object(id, directories.choose(&mut rng).unwrap());
+					}
+				},
+				_ => {
+					// Edits every entry behind the index's back
+					let title = *TITLES.choose(&mut rng).unwrap();
+					for entry in db.list_entries_mut() {
+						entry.edit(entry.new_edit(HashMap::from([("title".to_owned(), title.to_owned())])));
+					}
+				},
+			}
+
+			check(&db);
+		}
+	}
+
+	fn random_fields(rng: &mut StdRng) -> HashMap<String, String> {
+		HashMap::from([
+			("title".to_owned(), TITLES.choose(rng).unwrap().to_string()),
+			("url".to_owned(), URLS.choose(rng).unwrap().to_string()),
+		])
+	}
+
+	fn check(db: &Database) {
+		for object in db.list_entries().map(Entry::get_id).chain(db.list_directories().map(Directory::get_id)) {
+			let expected = db
+				.list_directories()
+				.filter(|directory| directory.contains(object))
+				.map(Directory::get_id)
+				.collect::<BTreeSet<_>>();
+			let parent = db.get_parent_directory(object).map(Directory::get_id);
+			assert_eq!(parent.is_some(), !expected.is_empty());
+			assert!(parent.is_none_or(|parent| expected.contains(parent)));
+		}
+
+		let id_prefixes = db.list_entries().map(|entry| entry.get_id().to_hex()[..3].to_owned()).collect::<Vec<_>>();
+		for query in TITLES.iter().copied().chain(id_prefixes.iter().map(String::as_str)) {
+			let mut expected = db
+				.list_entries()
+				.filter(|entry| matches_id_or_name(entry.get_id(), entry.get("title").map(String::as_str), query))
+				.collect::<Vec<_>>();
+			expected.sort_unstable_by_key(|entry| *entry.get_id());
+			assert_eq!(db.find_entries(query), expected);
+		}
+
+		for tag in TAGS {
+			let expected = db.list_entries().filter(|entry| entry.has_tag(tag)).map(Entry::get_id).collect::<BTreeSet<_>>();
+			assert_eq!(db.find_by_tag(tag).map(Entry::get_id).collect::<BTreeSet<_>>(), expected);
+		}
+		let expected = db.list_entries().flat_map(Entry::get_tags).map(String::as_str).collect::<BTreeSet<_>>();
+		assert_eq!(db.list_tags(), expected);
+
+		for url in ["https://login.example.com/", "https://bank.org/login", "http://127.0.0.1:8080/"] {
+			let host = normalize_url(url).unwrap().host_str().unwrap().to_owned();
+			let expected = db
+				.list_entries()
+				.filter(|entry| {
+					entry
+						.get_fields()
+						.iter()
+						.filter(|field| field.kind == FieldKind::Url)
+						.filter_map(|field| normalize_url(field.value)?.host_str().map(str::to_owned))
+						.any(|entry_host| host == entry_host || host.ends_with(&format!(".{entry_host}")))
+				})
+				.map(Entry::get_id)
+				.collect::<BTreeSet<_>>();
+			let found = db.find_entries_for_url(url).into_iter().map(Entry::get_id).collect::<BTreeSet<&ID>>();
+			assert_eq!(found, expected, "{url}");
+		}
+	}
+}
@@ -0,0 +1,539 @@
+// Importing from 1Password (see the import module).  Two of its export formats are understood:
+//
+// - CSV, which has one row per item with a header row naming the columns (1Password 8 writes Title, Url, Username, Password,
+//   OTPAuth, Favorite, Archived, Tags, and Notes).  Columns are matched by name, ignoring case, and unknown columns become
+//   fields named after them.  Everything goes in the root directory, since the CSV doesn't say which vault an item was in.
+// - 1PUX, a zip file holding the whole account as JSON (`export.data`) along with attachments.  Each vault becomes a directory,
+//   and each item an entry in it with its login fields, URLs, notes, tags, and custom fields, keeping concealed fields
+//   protected.  Secure notes become notes (see the secure_note module).  1PUX needs the `onepassword` feature, for the zip
+//   decompression.
+//
+// Archived items are imported with the tag `archived`.  Fortress has no attachments, so they're listed in
+// `ImportPlan::skipped`, along with custom fields whose values aren't text (e.g. addresses).  Password history isn't imported.
+#[cfg(feature = "onepassword")]
+use crate::{import::ImportedDirectory, secure_note::MARKDOWN, ENTRY_TYPE_FIELD, NOTE_ENTRY_TYPE, NOTE_FORMAT_FIELD};
+use crate::{
+	import::{parse_csv, ImportPlan, ImportedEntry},
+	FieldKind, FortressError,
+};
+#[cfg(feature = "onepassword")]
+use serde::Deserialize;
+#[cfg(feature = "onepassword")]
+use serde_json::Value;
+
+
+/// Tag given to items that were archived in 1Password.
+pub const ARCHIVED_TAG: &str = "archived";
+#[cfg(feature = "onepassword")]
+const SECURE_NOTE_CATEGORY: &str = "003";
+// Larger files in a 1PUX export are refused, so a malicious export can't make us decompress gigabytes
+#[cfg(feature = "onepassword")]
+const MAX_1PUX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+
+impl ImportPlan {
+	/// Parses a CSV export from 1Password (see the onepassword module).  Fails with FortressError::MalformedImport if it isn't
+	/// CSV with a header row.
+	pub fn from_1password_csv(data: &[u8]) -> Result<ImportPlan, FortressError> {
+		let text = std::str::from_utf8(data).map_err(|_| FortressError::MalformedImport("CSV isn't UTF-8".to_string()))?;
+		let mut rows = parse_csv(text)?.into_iter();
+		let header = rows.next().ok_or_else(|| FortressError::MalformedImport("CSV has no header row".to_string()))?;
+		let columns = header
+			.iter()
+			.map(|name| match name.trim().to_lowercase().as_str() {
+				"url" | "website" | "login_uri" => "url".to_string(),
+				"otpauth" | "one-time password" => "otpauth".to_string(),
+				name => name.to_string(),
+			})
+			.collect::<Vec<_>>();
+		let mut plan = ImportPlan::default();
+
+		for row in rows {
+			let mut entry = ImportedEntry::default();
+
+			for (column, value) in columns.iter().zip(&row) {
+				match column.as_str() {
+					"otpauth" => entry.push_field("otpauth", value, Some(FieldKind::Totp)),
+					"tags" => entry
+						.tags
+						.extend(value.split([',', ';']).map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_string)),
+					"archived" if value.eq_ignore_ascii_case("true") => {
+						entry.tags.insert(ARCHIVED_TAG.to_string());
+					},
+					"archived" | "favorite" => (),
+					column => entry.push_field(column, value, None),
+				}
+			}
+
+			plan.entries.push(entry);
+		}
+
+		Ok(plan)
+	}
+
+	/// Parses a 1PUX export from 1Password (see the onepassword module).  Fails with FortressError::MalformedImport if it
+	/// isn't a zip file holding an `export.data` in the format 1Password writes.
+	#[cfg(feature = "onepassword")]
+	pub fn from_1pux(data: &[u8]) -> Result<ImportPlan, FortressError> {
+		let export = zip::read_file(data, "export.data")?;
+		let export: Export = serde_json::from_slice(&export).map_err(|err| FortressError::MalformedImport(format!("export.data: {err}")))?;
+		let mut plan = ImportPlan::default();
+
+		for vault in export.accounts.into_iter().flat_map(|account| account.vaults) {
+			let mut directory = ImportedDirectory {
+				name: vault.attrs.name,
+				entries: Vec::new(),
+			};
+
+			for item in vault.items {
+				directory.entries.push(item.into_entry(&mut plan.skipped));
+			}
+
+			plan.directories.push(directory);
+		}
+
+		Ok(plan)
+	}
+}
+
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize)]
+struct Export {
+	accounts: Vec<Account>,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize)]
+struct Account {
+	#[serde(default)]
+	vaults: Vec<Vault>,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize)]
+struct Vault {
+	attrs: VaultAttrs,
+	#[serde(default)]
+	items: Vec<Item>,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize)]
+struct VaultAttrs {
+	name: String,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct Item {
+	state: String,
+	category_uuid: String,
+	overview: Overview,
+	details: Details,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Overview {
+	title: String,
+	url: String,
+	urls: Vec<ItemUrl>,
+	tags: Vec<String>,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ItemUrl {
+	url: String,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct Details {
+	login_fields: Vec<LoginField>,
+	notes_plain: String,
+	password: String,
+	sections: Vec<Section>,
+	document_attributes: Option<DocumentAttributes>,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct LoginField {
+	value: String,
+	name: String,
+	designation: String,
+	field_type: String,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Section {
+	fields: Vec<SectionField>,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SectionField {
+	title: String,
+	id: String,
+	value: Value,
+}
+
+#[cfg(feature = "onepassword")]
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct DocumentAttributes {
+	file_name: String,
+}
+
+#[cfg(feature = "onepassword")]
+impl Item {
+	fn into_entry(self, skipped: &mut Vec<String>) -> ImportedEntry {
+		let mut entry = ImportedEntry::default();
+		let title = self.overview.title;
+		entry.push_field("title", &title, None);
+
+		if self.category_uuid == SECURE_NOTE_CATEGORY {
+			entry.push_field(ENTRY_TYPE_FIELD, NOTE_ENTRY_TYPE, None);
+			entry.push_field(NOTE_FORMAT_FIELD, MARKDOWN, None);
+		}
+
+		for field in &self.details.login_fields {
+			match field.designation.as_str() {
+				"username" | "password" => entry.push_field(&field.designation, &field.value, None),
+				_ if field.field_type == "P" => entry.push_field(or(&field.name, "password"), &field.value, Some(FieldKind::Secret)),
+				_ => entry.push_field(or(&field.name, "field"), &field.value, None),
+			}
+		}
+		if entry.get("password").is_none() {
+			entry.push_field("password", &self.details.password, None);
+		}
+
+		entry.push_field("url", &self.overview.url, None);
+		for url in &self.overview.urls {
+			if !entry.fields.iter().any(|field| field.value == url.url) {
+				entry.push_field("url", &url.url, Some(FieldKind::Url));
+			}
+		}
+
+		for field in self.details.sections.iter().flat_map(|section| &section.fields) {
+			let name = or(&field.title, &field.id);
+
+			match section_field_value(&field.value) {
+				Some((value, kind)) => entry.push_field(name, &value, Some(kind)),
+				None if field.value.get("file").is_some() => skipped.push(format!("Attachment '{name}' of '{title}'")),
+				None => skipped.push(format!("Field '{name}' of '{title}'")),
+			}
+		}
+
+		entry.push_field("notes", &self.details.notes_plain, None);
+
+		if let Some(document) = &self.details.document_attributes {
+			skipped.push(format!("Attachment '{}' of '{title}'", document.file_name));
+		}
+
+		entry.tags.extend(self.overview.tags);
+		if self.state == "archived" {
+			entry.tags.insert(ARCHIVED_TAG.to_string());
+		}
+
+		entry
+	}
+}
+
+
+#[cfg(feature = "onepassword")]
+fn or<'a>(name: &'a str, default: &'a str) -> &'a str {
+	if name.is_empty() {
+		default
+	} else {
+		name
+	}
+}
+
+
+/// A custom field's value, which 1PUX writes as an object with one member named after the field's type, e.g.
+/// `{"concealed": "hunter2"}`.  None for values that aren't text, such as addresses and attachments.
+#[cfg(feature = "onepassword")]
+fn section_field_value(value: &Value) -> Option<(String, FieldKind)> {
+	let (field_type, value) = value.as_object()?.iter().next()?;
+	let kind = match field_type.as_str() {
+		"concealed" | "creditCardNumber" | "sshKey" => FieldKind::Secret,
+		"totp" => FieldKind::Totp,
+		"url" => FieldKind::Url,
+		_ => FieldKind::Text,
+	};
+
+	let value = match (field_type.as_str(), value) {
+		(_, Value::String(value)) => value.clone(),
+		("monthYear", Value::Number(month_year)) => {
+			let month_year = month_year.as_u64()?;
+			format!("{}-{:02}", month_year / 100, month_year % 100)
+		},
+		(_, Value::Number(number)) => number.to_string(),
+		("email", Value::Object(email)) => email.get("email_address")?.as_str()?.to_string(),
+		("sshKey", Value::Object(key)) => key.get("privateKey")?.as_str()?.to_string(),
+		_ => return None,
+	};
+
+	Some((value, kind))
+}
+
+
+/// Just enough of the zip format to read a file out of a 1PUX export: stored or deflated files, without zip64 or encryption.
+#[cfg(feature = "onepassword")]
+mod zip {
+	use super::MAX_1PUX_FILE_SIZE;
+	use crate::FortressError;
+	use flate2::read::DeflateDecoder;
+	use std::io::Read;
+
+	const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+	const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+	const LOCAL_FILE_HEADER: u32 = 0x04034b50;
+
+	fn malformed() -> FortressError {
+		FortressError::MalformedImport("not a valid 1PUX (zip) file".to_string())
+	}
+
+	fn u16_at(data: &[u8], offset: usize) -> Result<usize, FortressError> {
+		let bytes = data.get(offset..offset + 2).ok_or_else(malformed)?;
+		Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+	}
+
+	fn u32_at(data: &[u8], offset: usize) -> Result<u32, FortressError> {
+		let bytes = data.get(offset..offset + 4).ok_or_else(malformed)?;
+		Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	}
+
+	/// The contents of the file named `name` in the zip file `data`.
+	pub(super) fn read_file(data: &[u8], name: &str) -> Result<Vec<u8>, FortressError> {
+		// The end of central directory record is at the end, followed by a comment of up to 64 KiB
+		let end = (0..data.len().saturating_sub(21))
+			.rev()
+			.take(65536 + 22)
+			.find(|offset| u32_at(data, *offset).is_ok_and(|signature| signature == END_OF_CENTRAL_DIRECTORY))
+			.ok_or_else(malformed)?;
+		let file_count = u16_at(data, end + 10)?;
+		let mut offset = u32_at(data, end + 16)? as usize;
+
+		for _ in 0..file_count {
+			if u32_at(data, offset)? != CENTRAL_DIRECTORY_HEADER {
+				return Err(malformed());
+			}
+
+			let method = u16_at(data, offset + 10)?;
+			let compressed_size = u32_at(data, offset + 20)? as usize;
+			let name_length = u16_at(data, offset + 28)?;
+			let header_length = 46 + name_length + u16_at(data, offset + 30)? + u16_at(data, offset + 32)?;
+			let local_header = u32_at(data, offset + 42)? as usize;
+			let file_name = data.get(offset + 46..offset + 46 + name_length).ok_or_else(malformed)?;
+			offset += header_length;
+
+			if file_name != name.as_bytes() {
+				continue;
+			}
+
+			if u32_at(data, local_header)? != LOCAL_FILE_HEADER {
+				return Err(malformed());
+			}
+			let start = local_header + 30 + u16_at(data, local_header + 26)? + u16_at(data, local_header + 28)?;
+			let compressed = data.get(start..start + compressed_size).ok_or_else(malformed)?;
+			let mut contents = Vec::new();
+
+			match method {
+				0 => contents.extend_from_slice(compressed),
+				8 => {
+					DeflateDecoder::new(compressed)
+						.take(MAX_1PUX_FILE_SIZE + 1)
+						.read_to_end(&mut contents)
+						.map_err(|_| malformed())?;
+				},
+				_ => return Err(FortressError::MalformedImport(format!("{name} uses an unsupported zip compression method"))),
+			}
+
+			if contents.len() as u64 > MAX_1PUX_FILE_SIZE {
+				return Err(FortressError::MalformedImport(format!("{name} is too large")));
+			}
+
+			return Ok(contents);
+		}
+
+		Err(FortressError::MalformedImport(format!("{name} is missing")))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{import::ImportPlan, FieldKind};
+
+	#[test]
+	fn csv() {
+		let csv = "Title,Url,Username,Password,OTPAuth,Favorite,Archived,Tags,Notes,PIN\n\
+			Email,https://mail.example.com,me,hunter2,otpauth://totp/me?secret=ABC,true,false,\"work,mail\",,1234\n\
+			Old,,,,,false,true,,\"Line 1\nLine 2\",\n";
+		let plan = ImportPlan::from_1password_csv(csv.as_bytes()).unwrap();
+		assert!(plan.directories.is_empty());
+		assert_eq!(plan.entries.len(), 2);
+
+		let email = &plan.entries[0];
+		assert_eq!(email.get("title"), Some("Email"));
+		assert_eq!(email.get("url"), Some("https://mail.example.com"));
+		assert_eq!(email.get("password"), Some("hunter2"));
+		assert_eq!(email.get("pin"), Some("1234"));
+		assert!(email
+			.fields
+			.iter()
+			.any(|field| field.name == "otpauth" && field.metadata.kind == FieldKind::Totp));
+		assert_eq!(email.tags.iter().collect::<Vec<_>>(), ["mail", "work"]);
+		assert!(email.get("favorite").is_none() && email.get("notes").is_none());
+
+		let old = &plan.entries[1];
+		assert_eq!(old.get("notes"), Some("Line 1\nLine 2"));
+		assert!(old.tags.contains(super::ARCHIVED_TAG));
+		assert!(old.get("password").is_none());
+
+		assert!(ImportPlan::from_1password_csv(b"").is_err());
+		assert!(ImportPlan::from_1password_csv(b"\xff").is_err());
+	}
+
+	#[cfg(feature = "onepassword")]
+	#[test]
+	fn onepux() {
+		use flate2::{write::DeflateEncoder, Compression};
+		use std::io::Write;
+
+		let export = serde_json::json!({
+			"accounts": [{
+				"attrs": { "name": "Me" },
+				"vaults": [{
+					"attrs": { "uuid": "abc", "name": "Personal" },
+					"items": [
+						{
+							"uuid": "1",
+							"state": "active",
+							"categoryUuid": "001",
+							"overview": {
+								"title": "Email",
+								"url": "https://mail.example.com",
+								"urls": [{ "url": "https://mail.example.com" }, { "url": "https://example.com" }],
+								"tags": ["work"]
+							},
+							"details": {
+								"loginFields": [
+									{ "value": "me", "name": "username", "fieldType": "T", "designation": "username" },
+									{ "value": "hunter2", "name": "password", "fieldType": "P", "designation": "password" }
+								],
+								"notesPlain": "Notes",
+								"sections": [{
+									"title": "Extra",
+									"fields": [
+										{ "title": "PIN", "id": "pin", "value": { "concealed": "1234" } },
+										{ "title": "", "id": "expiry", "value": { "monthYear": 202701 } },
+										{ "title": "Recovery email", "id": "r", "value": { "email": { "email_address": "me@example.com" } } },
+										{ "title": "Home", "id": "a", "value": { "address": { "city": "Nowhere" } } },
+										{ "title": "Scan", "id": "f", "value": { "file": { "fileName": "scan.pdf" } } }
+									]
+								}]
+							}
+						},
+						{
+							"uuid": "2",
+							"state": "archived",
+							"categoryUuid": "003",
+							"overview": { "title": "Note" },
+							"details": { "notesPlain": "# Secret" }
+						},
+						{
+							"uuid": "3",
+							"categoryUuid": "006",
+							"overview": { "title": "Passport" },
+							"details": { "documentAttributes": { "fileName": "passport.pdf" } }
+						}
+					]
+				}]
+			}]
+		});
+		let export = serde_json::to_vec(&export).unwrap();
+
+		// A zip file with a deflated export.data, preceded by an attachment that's stored as-is
+		let mut files = Vec::new();
+		let mut central_directory = Vec::new();
+		let mut deflated = DeflateEncoder::new(Vec::new(), Compression::default());
+		deflated.write_all(&export).unwrap();
+		for (name, method, contents) in [("files/scan.pdf", 0u16, b"%PDF".to_vec()), ("export.data", 8, deflated.finish().unwrap())] {
+			let offset = files.len() as u32;
+			files.extend(0x04034b50u32.to_le_bytes());
+			files.extend([0; 4]);
+			files.extend(method.to_le_bytes());
+			files.extend([0; 16]);
+			files.extend((name.len() as u16).to_le_bytes());
+			files.extend([0; 2]);
+			files.extend(name.as_bytes());
+			files.extend(&contents);
+
+			central_directory.extend(0x02014b50u32.to_le_bytes());
+			central_directory.extend([0; 6]);
+			central_directory.extend(method.to_le_bytes());
+			central_directory.extend([0; 8]);
+			central_directory.extend((contents.len() as u32).to_le_bytes());
+			central_directory.extend([0; 4]);
+			central_directory.extend((name.len() as u16).to_le_bytes());
+			central_directory.extend([0; 12]);
+			central_directory.extend(offset.to_le_bytes());
+			central_directory.extend(name.as_bytes());
+		}
+		let central_directory_offset = files.len() as u32;
+		let mut zip = files;
+		zip.extend(&central_directory);
+		zip.extend(0x06054b50u32.to_le_bytes());
+		zip.extend([0; 6]);
+		zip.extend(2u16.to_le_bytes());
+		zip.extend((central_directory.len() as u32).to_le_bytes());
+		zip.extend(central_directory_offset.to_le_bytes());
+		zip.extend([0; 2]);
+
+		let plan = ImportPlan::from_1pux(&zip).unwrap();
+		assert_eq!(plan.directories.len(), 1);
+		assert_eq!(plan.directories[0].name, "Personal");
+		assert_eq!(
+			plan.skipped,
+			[
+				"Field 'Home' of 'Email'",
+				"Attachment 'Scan' of 'Email'",
+				"Attachment 'passport.pdf' of 'Passport'"
+			]
+		);
+
+		let entries = &plan.directories[0].entries;
+		let email = &entries[0];
+		assert_eq!(email.get("username"), Some("me"));
+		assert_eq!(email.get("password"), Some("hunter2"));
+		assert_eq!(email.get("url"), Some("https://mail.example.com"));
+		assert_eq!(email.get("url 2"), Some("https://example.com"));
+		assert_eq!(email.get("expiry"), Some("2027-01"));
+		assert_eq!(email.get("Recovery email"), Some("me@example.com"));
+		assert_eq!(email.get("notes"), Some("Notes"));
+		let pin = email.fields.iter().find(|field| field.name == "PIN").unwrap();
+		assert!(pin.metadata.protected && pin.metadata.kind == FieldKind::Secret);
+		assert!(email.tags.contains("work"));
+
+		let note = &entries[1];
+		assert_eq!(note.get(crate::ENTRY_TYPE_FIELD), Some(crate::NOTE_ENTRY_TYPE));
+		assert_eq!(note.get("notes"), Some("# Secret"));
+		assert!(note.tags.contains(super::ARCHIVED_TAG));
+
+		assert!(ImportPlan::from_1pux(b"not a zip file").is_err());
+		assert!(ImportPlan::from_1pux(&zip[..zip.len() / 2]).is_err());
+	}
+}
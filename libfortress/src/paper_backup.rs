@@ -0,0 +1,325 @@
+// Printable paper backups, for disaster recovery when every device is lost.
+// A paper backup holds the username and sync URL (enough to recover everything else from the sync server) plus, optionally,
+// the current state of some or all entries, in case the server is gone too.  It is encrypted with keys derived afresh from the
+// database's password with the default KDF parameters (using the same file format as a database, so it carries its own
+// parameters) and split into base64 blocks small enough to fit in a QR code each.
+//
+// The password is also the one restoring derives the sync keys from, so backups can only be made of databases opened with just
+// their password.  Those that need a hardware key couldn't be restored without it, and in those with more than one user (see
+// the users module) any user's password would restore the backup, but only the database's own derives the right sync keys.
+//
+// The written backup is plain text: a line of instructions followed by one line per block, of the form
+// `fortress-paper-backup:<index>/<count>:<base64>`.  Each block line is meant to be printed, or encoded as a QR code, on its
+// own.  Importing ignores anything that isn't a block line, and blocks can be given in any order.
+//
+// Entries are stored with their history squashed into a single edit (see `Entry::compact_history`), which keeps the backup
+// small and still merges cleanly with the full history on the sync server.  They keep their IDs but are restored into the root
+// directory, since the directory tree isn't backed up.
+use crate::{Database, Entry, FortressError};
+use data_encoding::BASE64;
+use fortresscrypto::{CryptoError, FileKdfParameters, FileKeySuite, Zeroizing};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::BTreeMap,
+	io::{self, Cursor},
+};
+use url::Url;
+
+
+pub const PAPER_BACKUP_FORMAT: &str = "fortress-paper-backup";
+pub const PAPER_BACKUP_VERSION: u32 = 1;
+/// Entries with this tag are included in paper backups by default.
+pub const PAPER_BACKUP_TAG: &str = "paper-backup";
+
+// Characters of base64 per block.  Together with the prefix this fits comfortably in a QR code (version 25 at error
+// correction level M holds 1,273 bytes), while leaving the code large enough to scan from paper.
+const BLOCK_SIZE: usize = 1000;
+
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum PaperBackupEntries {
+	/// Only the sync credentials; entries are restored by syncing.
+	None,
+	/// Entries with the given tag.
+	Tagged(String),
+	All,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct PaperBackupOptions {
+	pub entries: PaperBackupEntries,
+}
+
+impl Default for PaperBackupOptions {
+	fn default() -> PaperBackupOptions {
+		PaperBackupOptions {
+			entries: PaperBackupEntries::Tagged(PAPER_BACKUP_TAG.to_string()),
+		}
+	}
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct PaperBackup {
+	format: String,
+	version: u32,
+	username: String,
+	sync_url: Option<Url>,
+	entries: Vec<Entry>,
+}
+
+
+impl Database {
+	/// Writes a paper backup of this database, encrypted under `password`, to `writer`.  See the paper_backup module for the format.
+	/// Returns the number of blocks written.  `password` must be the database's current password (CryptoError::DecryptionError
+	/// otherwise).  Fails with FortressError::PaperBackupUnsupported if the database needs a hardware key or has more than one
+	/// user.
+	/// NOTE: Restoring the backup requires the database's password at the time the backup was made.
+	pub fn export_paper_backup<W: io::Write, P: AsRef<str>>(&self, mut writer: W, password: P, options: &PaperBackupOptions) -> Result<usize, FortressError> {
+		let password = password.as_ref().as_bytes();
+
+		if self.needs_hardware_key() || !self.file_key_suite.get_keyslots().is_empty() {
+			return Err(FortressError::PaperBackupUnsupported);
+		}
+
+		if self.file_key_suite.rederive(password, self.file_key_suite.get_kdf_params())? != self.file_key_suite {
+			return Err(FortressError::CryptoError(CryptoError::DecryptionError));
+		}
+
+		let mut entries = self
+			.list_entries()
+			.filter(|entry| match &options.entries {
+				PaperBackupEntries::None => false,
+				PaperBackupEntries::Tagged(tag) => entry.has_tag(tag),
+				PaperBackupEntries::All => true,
+			})
+			.map(|entry| entry.compact_history(u64::MAX).unwrap_or_else(|| entry.clone()))
+			.collect::<Vec<_>>();
+		entries.sort_unstable_by_key(|entry| *entry.get_id());
+
+		let backup = PaperBackup {
+			format: PAPER_BACKUP_FORMAT.to_string(),
+			version: PAPER_BACKUP_VERSION,
+			username: self.get_username().to_string(),
+			sync_url: self.sync_url.clone(),
+			entries,
+		};
+		let payload = Zeroizing::new(serde_json::to_vec(&backup)?);
+
+		let mut encrypted = Vec::new();
+		fortresscrypto::encrypt_to_file(&mut encrypted, &payload, &FileKeySuite::derive(password, &FileKdfParameters::default())?)?;
+		let encoded = BASE64.encode(&encrypted);
+		let blocks = encoded.as_bytes().chunks(BLOCK_SIZE).collect::<Vec<_>>();
+
+		writeln!(
+			writer,
+			"Fortress paper backup ({} blocks).  Restoring it requires the password the database had when this backup was made.",
+			blocks.len()
+		)?;
+
+		for (index, block) in blocks.iter().enumerate() {
+			writeln!(
+				writer,
+				"{}:{}/{}:{}",
+				PAPER_BACKUP_FORMAT,
+				index + 1,
+				blocks.len(),
+				String::from_utf8_lossy(block)
+			)?;
+		}
+
+		Ok(blocks.len())
+	}
+
+	/// Restores a database from a paper backup written by `export_paper_backup`.  The restored database has the backup's username,
+	/// sync URL, and entries; sync it to recover everything else.
+	pub fn import_paper_backup<R: io::Read, P: AsRef<str>>(reader: &mut R, password: P) -> Result<Database, FortressError> {
+		let mut text = String::new();
+		reader.read_to_string(&mut text)?;
+
+		let encrypted = parse_blocks(&text).ok_or(FortressError::MalformedPaperBackup)?;
+		let (plaintext, _) = fortresscrypto::decrypt_from_file(&mut Cursor::new(encrypted), password.as_ref().as_bytes())?;
+		let backup: PaperBackup = serde_json::from_slice(&plaintext)?;
+
+		if backup.format != PAPER_BACKUP_FORMAT || backup.version > PAPER_BACKUP_VERSION {
+			return Err(FortressError::MalformedPaperBackup);
+		}
+
+		let mut database = Database::new_with_password(&backup.username, password);
+		database.set_sync_url(backup.sync_url);
+
+		for entry in backup.entries {
+			let id = *entry.get_id();
+			database.add_imported_entry(entry)?;
+			database.get_root_mut().add(id);
+		}
+
+		Ok(database)
+	}
+}
+
+
+/// Reassembles the encrypted backup from its block lines.  Returns None if blocks are missing, duplicated with different contents,
+/// or malformed.
+fn parse_blocks(text: &str) -> Option<Vec<u8>> {
+	let mut blocks = BTreeMap::new();
+	let mut count = None;
+
+	for line in text.lines() {
+		let Some(rest) = line.trim().strip_prefix(PAPER_BACKUP_FORMAT).and_then(|rest| rest.strip_prefix(':')) else {
+			continue;
+		};
+		let (position, data) = rest.split_once(':')?;
+		let (index, total) = position.split_once('/')?;
+		let (index, total) = (index.parse::<usize>().ok()?, total.parse::<usize>().ok()?);
+
+		if index == 0 || index > total || *count.get_or_insert(total) != total {
+			return None;
+		}
+
+		if *blocks.entry(index).or_insert(data) != data {
+			return None;
+		}
+	}
+
+	if count? != blocks.len() {
+		return None;
+	}
+
+	BASE64.decode(blocks.into_values().collect::<String>().as_bytes()).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_blocks, PaperBackupEntries, PaperBackupOptions, PAPER_BACKUP_TAG};
+	use crate::{Database, Entry, EntryHistory, FortressError};
+	use fortresscrypto::{CryptoError, Zeroizing};
+	use url::Url;
+
+	fn backup(db: &Database, options: &PaperBackupOptions) -> String {
+		let mut buffer = Vec::new();
+		db.export_paper_backup(&mut buffer, "password", options).unwrap();
+		String::from_utf8(buffer).unwrap()
+	}
+
+	#[test]
+	fn round_trip() {
+		let mut db = Database::new_with_password("username", "password");
+		db.set_sync_url(Some(Url::parse("https://example.com").unwrap()));
+		let mut essential = Entry::new();
+		essential.edit(EntryHistory::new([("title".to_string(), "Email".to_string())].into_iter().collect()));
+		essential.edit(EntryHistory::new([("password".to_string(), "hunter2".to_string())].into_iter().collect()));
+		essential.add_tag(PAPER_BACKUP_TAG);
+		let essential_id = *essential.get_id();
+		db.add_entry(essential);
+		let mut other = Entry::new();
+		other.edit(EntryHistory::new([("title".to_string(), "Forum".to_string())].into_iter().collect()));
+		db.add_entry(other);
+
+		// By default only tagged entries are included, with their history squashed
+		let text = backup(&db, &PaperBackupOptions::default());
+		assert!(!text.contains("hunter2") && !text.contains("username"));
+		let restored = Database::import_paper_backup(&mut text.as_bytes(), "password").unwrap();
+		assert_eq!(restored.get_username(), "username");
+		assert_eq!(restored.get_sync_url(), db.get_sync_url());
+		assert_eq!(restored.list_entries().count(), 1);
+		let restored_entry = restored.get_entry_by_id(&essential_id).unwrap();
+		let original_entry = db.get_entry_by_id(&essential_id).unwrap();
+		assert_eq!(restored_entry.get_state(), original_entry.get_state());
+		assert_eq!(restored_entry.get_tags(), original_entry.get_tags());
+		assert_eq!(restored_entry.get_history().len(), 1);
+		assert_eq!(restored.get_root().list_entries(&restored), [&essential_id]);
+
+		// Restored entries merge cleanly with the originals on the sync server
+		let merged = original_entry.merge(restored_entry).unwrap();
+		assert_eq!(merged.get_state(), original_entry.get_state());
+		assert_eq!(merged.get_history().len(), original_entry.get_history().len());
+
+		let all = PaperBackupOptions {
+			entries: PaperBackupEntries::All,
+		};
+		let restored = Database::import_paper_backup(&mut backup(&db, &all).as_bytes(), "password").unwrap();
+		assert_eq!(restored.list_entries().count(), 2);
+		let none = PaperBackupOptions {
+			entries: PaperBackupEntries::None,
+		};
+		let restored = Database::import_paper_backup(&mut backup(&db, &none).as_bytes(), "password").unwrap();
+		assert_eq!(restored.list_entries().count(), 0);
+
+		assert!(matches!(
+			Database::import_paper_backup(&mut text.as_bytes(), "wrong"),
+			Err(FortressError::CryptoError(_))
+		));
+	}
+
+	#[test]
+	fn only_password_databases() {
+		let options = PaperBackupOptions::default();
+		let mut db = Database::new_with_password("username", "password");
+		assert!(matches!(
+			db.export_paper_backup(Vec::new(), "wrong", &options),
+			Err(FortressError::CryptoError(CryptoError::DecryptionError))
+		));
+
+		// Another user's password would restore the backup with the wrong sync keys
+		db.add_user("other password").unwrap();
+		for password in ["password", "other password"] {
+			assert!(matches!(
+				db.export_paper_backup(Vec::new(), password, &options),
+				Err(FortressError::PaperBackupUnsupported)
+			));
+		}
+
+		// The hardware key isn't part of the backup, so restoring it would need the key too
+		let mut db = Database::new_with_password("username", "password");
+		db.set_hardware_key("password", Some(&|_: &[u8]| Some(Zeroizing::new(vec![1; 20])))).unwrap();
+		assert!(matches!(
+			db.export_paper_backup(Vec::new(), "password", &options),
+			Err(FortressError::PaperBackupUnsupported)
+		));
+		db.set_hardware_key("password", None).unwrap();
+		let text = backup(&db, &options);
+		assert_eq!(
+			Database::import_paper_backup(&mut text.as_bytes(), "password").unwrap().get_login_key(),
+			db.get_login_key()
+		);
+	}
+
+	#[test]
+	fn blocks() {
+		let mut db = Database::new_with_password("username", "password");
+		for i in 0..20 {
+			let mut entry = Entry::new();
+			entry.edit(EntryHistory::new([("notes".to_string(), format!("{i}").repeat(100))].into_iter().collect()));
+			db.add_entry(entry);
+		}
+		let text = backup(
+			&db,
+			&PaperBackupOptions {
+				entries: PaperBackupEntries::All,
+			},
+		);
+		let lines = text.lines().collect::<Vec<_>>();
+		assert!(lines.len() > 3);
+		assert!(lines[1..].iter().all(|line| line.len() <= 1000 + 32));
+
+		// Blocks can be in any order, repeated, and surrounded by other text
+		let mut shuffled = lines[1..].to_vec();
+		shuffled.reverse();
+		shuffled.push(lines[1]);
+		shuffled.insert(1, "Page 2");
+		let restored = Database::import_paper_backup(&mut shuffled.join("\n").as_bytes(), "password").unwrap();
+		assert_eq!(restored.list_entries().count(), 20);
+
+		// Missing or inconsistent blocks
+		assert_eq!(parse_blocks(&lines[..lines.len() - 1].join("\n")), None);
+		assert_eq!(parse_blocks(""), None);
+		assert_eq!(parse_blocks(&format!("{}\nfortress-paper-backup:1/{}:AAAA", text, lines.len() - 1)), None);
+		assert_eq!(parse_blocks(&text.replace(":1/", ":0/")), None);
+		assert!(matches!(
+			Database::import_paper_backup(&mut lines[1..2].join("\n").as_bytes(), "password"),
+			Err(FortressError::MalformedPaperBackup)
+		));
+	}
+}
@@ -0,0 +1,203 @@
+// Password expiry policies, for entries whose passwords have to be changed regularly.  The policy is kept in the entry's
+// `password_expiry` field, so it has history and syncs like any other field, and older versions simply show it as text.  It's
+// either `every <n> days`, counting from when the password was last changed, or `at <time>` for a fixed time (nanoseconds).
+// An empty value means no policy.
+use crate::{Database, Entry, EntryHistory};
+use std::{fmt, str::FromStr};
+
+
+/// Name of the field an entry's password expiry policy is kept in.
+pub const PASSWORD_EXPIRY_FIELD: &str = "password_expiry";
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PasswordExpiry {
+	/// The password should be changed this many days after it was last changed.
+	Interval { days: u32 },
+	/// The password should be changed by this time (nanoseconds).
+	Date(u64),
+}
+
+impl fmt::Display for PasswordExpiry {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PasswordExpiry::Interval { days } => write!(f, "every {days} days"),
+			PasswordExpiry::Date(time) => write!(f, "at {time}"),
+		}
+	}
+}
+
+impl FromStr for PasswordExpiry {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<PasswordExpiry, ()> {
+		if let Some(days) = s.strip_prefix("every ").and_then(|s| s.strip_suffix(" days")) {
+			Ok(PasswordExpiry::Interval {
+				days: days.parse().map_err(|_| ())?,
+			})
+		} else if let Some(time) = s.strip_prefix("at ") {
+			Ok(PasswordExpiry::Date(time.parse().map_err(|_| ())?))
+		} else {
+			Err(())
+		}
+	}
+}
+
+
+impl Entry {
+	/// The entry's password expiry policy, if it has a valid one.
+	pub fn get_password_expiry(&self) -> Option<PasswordExpiry> {
+		self.get(PASSWORD_EXPIRY_FIELD)?.parse().ok()
+	}
+
+	/// An edit (see `new_edit`) that sets the entry's password expiry policy, or removes it if `expiry` is None.
+	pub fn password_expiry_edit(&self, expiry: Option<PasswordExpiry>) -> EntryHistory {
+		let value = expiry.map(|expiry| expiry.to_string()).unwrap_or_default();
+
+		self.new_edit([(PASSWORD_EXPIRY_FIELD.to_string(), value)].into())
+	}
+
+	/// When the password field was last changed (nanoseconds), if the entry has one.
+	pub fn password_changed_at(&self) -> Option<u64> {
+		self.get_history()
+			.iter()
+			.rev()
+			.find(|item| item.get("password").is_some())
+			.map(|item| item.time)
+	}
+
+	/// When the password should be changed by (nanoseconds), according to the entry's policy.  None if there's no policy or no
+	/// password.
+	pub fn password_expires_at(&self) -> Option<u64> {
+		let changed_at = self.password_changed_at()?;
+
+		match self.get_password_expiry()? {
+			PasswordExpiry::Interval { days } => Some(changed_at.saturating_add(u64::from(days) * NANOS_PER_DAY)),
+			PasswordExpiry::Date(time) => Some(time),
+		}
+	}
+
+	/// Adds to `edit`, if it changes the password, a new expiry date as far after the change as the current one was after the
+	/// previous change, so a fixed date policy carries on with the new password.  Interval policies count from the last change
+	/// anyway, so they're left alone, as is `edit` if it doesn't change the password.
+	pub fn bump_password_expiry(&self, mut edit: EntryHistory) -> EntryHistory {
+		let changes_password = edit.get("password").is_some_and(|password| self.get("password") != Some(password));
+
+		if let (true, Some(PasswordExpiry::Date(time)), Some(changed_at)) = (changes_password, self.get_password_expiry(), self.password_changed_at()) {
+			let expiry = PasswordExpiry::Date(edit.time.saturating_add(time.saturating_sub(changed_at)));
+			edit.data.insert(PASSWORD_EXPIRY_FIELD.to_string(), expiry.to_string());
+		}
+
+		edit
+	}
+}
+
+
+impl Database {
+	/// Entries whose password should have been changed by `now` (nanoseconds) according to their expiry policy, most overdue
+	/// first.  Trashed entries are left out.
+	pub fn entries_due_for_rotation(&self, now: u64) -> Vec<&Entry> {
+		let trash = self.list_trash_recursive();
+		let mut due = self
+			.list_entries()
+			.filter(|entry| !trash.contains(entry.get_id()))
+			.filter_map(|entry| Some((entry.password_expires_at().filter(|expires_at| *expires_at <= now)?, entry)))
+			.collect::<Vec<_>>();
+
+		due.sort_by_key(|(expires_at, _)| *expires_at);
+		due.into_iter().map(|(_, entry)| entry).collect()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{PasswordExpiry, NANOS_PER_DAY, PASSWORD_EXPIRY_FIELD};
+	use crate::{Database, Entry, EntryHistory, ID};
+	use std::collections::HashMap;
+
+	fn edit_at(time: u64, data: &[(&str, &str)]) -> EntryHistory {
+		let mut edit = EntryHistory::new(data.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect::<HashMap<_, _>>());
+		edit.time = time;
+		edit
+	}
+
+	#[test]
+	fn policies() {
+		for expiry in [PasswordExpiry::Interval { days: 90 }, PasswordExpiry::Date(1234)] {
+			assert_eq!(expiry.to_string().parse(), Ok(expiry));
+		}
+		assert_eq!("every 90 days".parse(), Ok(PasswordExpiry::Interval { days: 90 }));
+		assert!("".parse::<PasswordExpiry>().is_err());
+		assert!("every week".parse::<PasswordExpiry>().is_err());
+
+		let mut entry = Entry::new();
+		let start = entry.get_time_created();
+		entry.edit(edit_at(start + 1, &[("title", "Bank")]));
+		assert_eq!(entry.password_changed_at(), None);
+
+		// No password, nothing to expire
+		entry.edit(entry.password_expiry_edit(Some(PasswordExpiry::Interval { days: 30 })));
+		assert_eq!(entry.get_password_expiry(), Some(PasswordExpiry::Interval { days: 30 }));
+		assert_eq!(entry.password_expires_at(), None);
+
+		entry.edit(edit_at(start + NANOS_PER_DAY, &[("password", "hunter2")]));
+		assert_eq!(entry.password_changed_at(), Some(start + NANOS_PER_DAY));
+		assert_eq!(entry.password_expires_at(), Some(start + 31 * NANOS_PER_DAY));
+
+		// Interval policies aren't bumped; they follow the password
+		let edit = entry.bump_password_expiry(edit_at(start + 2 * NANOS_PER_DAY, &[("password", "hunter3")]));
+		assert!(edit.get(PASSWORD_EXPIRY_FIELD).is_none());
+		entry.edit(edit);
+		assert_eq!(entry.password_expires_at(), Some(start + 32 * NANOS_PER_DAY));
+
+		// Date policies move forward by the same span
+		let mut edit = entry.password_expiry_edit(Some(PasswordExpiry::Date(start + 12 * NANOS_PER_DAY)));
+		edit.time = start + 3 * NANOS_PER_DAY;
+		entry.edit(edit);
+		assert_eq!(entry.password_expires_at(), Some(start + 12 * NANOS_PER_DAY));
+		let edit = entry.bump_password_expiry(edit_at(start + 5 * NANOS_PER_DAY, &[("password", "hunter4")]));
+		entry.edit(edit);
+		assert_eq!(entry.get_password_expiry(), Some(PasswordExpiry::Date(start + 15 * NANOS_PER_DAY)));
+
+		// Edits that don't change the password don't bump it
+		let edit = entry.bump_password_expiry(edit_at(start + 6 * NANOS_PER_DAY, &[("password", "hunter4"), ("title", "Credit union")]));
+		assert!(edit.get(PASSWORD_EXPIRY_FIELD).is_none());
+
+		entry.edit(entry.password_expiry_edit(None));
+		assert_eq!(entry.get_password_expiry(), None);
+		assert_eq!(entry.password_expires_at(), None);
+	}
+
+	#[test]
+	fn entries_due_for_rotation() {
+		let mut db = Database::new_with_password("username", "password");
+		let now = crate::unix_timestamp();
+		let mut add = |changed_days_ago: u64, expiry: Option<PasswordExpiry>| -> ID {
+			let mut entry = Entry::new();
+			entry.edit(edit_at(now - changed_days_ago * NANOS_PER_DAY, &[("password", "hunter2")]));
+			entry.edit(entry.password_expiry_edit(expiry));
+			let id = *entry.get_id();
+			db.add_entry(entry);
+			id
+		};
+
+		let overdue = add(100, Some(PasswordExpiry::Interval { days: 90 }));
+		let very_overdue = add(100, Some(PasswordExpiry::Date(now - 50 * NANOS_PER_DAY)));
+		let not_due = [
+			add(10, Some(PasswordExpiry::Interval { days: 90 })),
+			add(100, Some(PasswordExpiry::Date(now + NANOS_PER_DAY))),
+			add(1000, None),
+		];
+
+		let due = |db: &Database, now| db.entries_due_for_rotation(now).iter().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+		assert_eq!(due(&db, now), [very_overdue, overdue]);
+		assert_eq!(due(&db, now + 2 * NANOS_PER_DAY).len(), 3);
+		assert!(due(&db, u64::MAX).iter().all(|id| *id != not_due[2]));
+
+		assert!(db.trash_object(&very_overdue));
+		assert_eq!(due(&db, now), [overdue]);
+	}
+}
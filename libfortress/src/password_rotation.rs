@@ -0,0 +1,107 @@
+// Changing an entry's password to a newly generated one, for scripted rotation flows.  The new password is saved in the entry
+// first, so it can't be lost if the script dies while changing it on the website; if the website refuses it,
+// `Entry::undo_last_edit_if_matches` with the rotation's time sets the old one back (as a new edit, like every other change).
+use crate::{Entry, FortressError, GeneratorOptions};
+use fortresscrypto::Zeroize;
+
+
+/// Returned by `Entry::rotate_password`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PasswordRotation {
+	/// The password before the rotation, if the entry had one.
+	pub old: Option<String>,
+	pub new: String,
+	/// Time of the edit that set the new password (nanoseconds), for `Entry::undo_last_edit_if_matches`.
+	pub time: u64,
+}
+
+impl Drop for PasswordRotation {
+	fn drop(&mut self) {
+		self.old.zeroize();
+		self.new.zeroize();
+	}
+}
+
+
+impl Entry {
+	/// Sets the password field to a new password generated with `options`, in a single edit that also carries a fixed date
+	/// expiry policy forward (see `bump_password_expiry`).  The old password stays in the history.  If the new password happens
+	/// to be the old one (only likely with very short passwords or few characters), nothing changes.
+	/// Fails with FortressError::GeneratorUnsatisfiable, changing nothing, if no password meets `options`.
+	pub fn rotate_password(&mut self, options: &GeneratorOptions) -> Result<PasswordRotation, FortressError> {
+		let old = self.get("password").cloned();
+		let new = options.generate()?;
+		let edit = self.bump_password_expiry(self.new_edit([("password".to_string(), new.clone())].into()));
+		let time = edit.time;
+		self.try_edit(edit)?;
+
+		Ok(PasswordRotation { old, new, time })
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Entry, EntryHistory, GeneratorOptions, PasswordExpiry};
+	use std::collections::HashMap;
+
+	#[test]
+	fn rotate_password() {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(HashMap::from([
+			("title".to_string(), "Bank".to_string()),
+			("password".to_string(), "hunter2".to_string()),
+		])));
+		entry.edit(entry.password_expiry_edit(Some(PasswordExpiry::Date(entry.get_time_created() + 1_000_000_000))));
+		let options = GeneratorOptions {
+			length: 32,
+			..GeneratorOptions::default()
+		};
+
+		let rotation = entry.rotate_password(&options).unwrap();
+		assert_eq!(rotation.old.as_deref(), Some("hunter2"));
+		assert_eq!(rotation.new.len(), 32);
+		assert_eq!(entry["password"], rotation.new);
+		assert_eq!(entry.get_history().last().unwrap().time, rotation.time);
+		assert_eq!(entry.get_history().len(), 3);
+		let expires_at = entry.password_expires_at().unwrap();
+		assert!(expires_at > entry.get_time_created() + 1_000_000_000);
+
+		// The website refused it
+		assert!(entry.undo_last_edit_if_matches(rotation.time));
+		assert_eq!(entry["password"], "hunter2");
+		assert_eq!(entry["title"], "Bank");
+		assert_eq!(
+			entry.get_password_expiry(),
+			Some(PasswordExpiry::Date(entry.get_time_created() + 1_000_000_000))
+		);
+		assert_eq!(entry.get_history().len(), 4);
+
+		// Only the latest edit can be undone
+		let rotation = entry.rotate_password(&options).unwrap();
+		entry.edit(entry.new_edit(HashMap::from([("notes".to_string(), "Rotated".to_string())])));
+		assert!(!entry.undo_last_edit_if_matches(rotation.time));
+		assert_eq!(entry["password"], rotation.new);
+
+		// An entry without a password gets one
+		let mut entry = Entry::new();
+		let options = GeneratorOptions {
+			length: 3,
+			uppercase: false,
+			lowercase: false,
+			numbers: false,
+			others: "x".to_string(),
+			..GeneratorOptions::default()
+		};
+		let rotation = entry.rotate_password(&options).unwrap();
+		assert_eq!((rotation.old.as_deref(), rotation.new.as_str()), (None, "xxx"));
+		assert!(entry.undo_last_edit_if_matches(rotation.time));
+		assert_eq!(entry["password"], "");
+
+		// Nothing to undo if the password came out the same
+		entry.edit(entry.new_edit(HashMap::from([("password".to_string(), "xxx".to_string())])));
+		let rotation = entry.rotate_password(&options).unwrap();
+		assert_eq!(rotation.old.as_deref(), Some("xxx"));
+		assert!(!entry.undo_last_edit_if_matches(rotation.time));
+	}
+}
@@ -0,0 +1,137 @@
+// Progress reporting for long-running operations, so frontends can show a progress bar instead of appearing frozen.
+// Progress is reported to a callback, on the thread that started the operation.  To receive it elsewhere, pass a callback that
+// sends it over a channel.
+//
+// Key derivation (creating a database, changing the password) can't report how far along it is, so its progress is an
+// estimate: the time it will take is measured beforehand with a much smaller derivation (see
+// `NetworkKeySuite::estimate_derive_time`), and the time elapsed is reported against that.  Sync reports how many of the
+// objects it has to compare with the server it has gone through.
+use crate::{cancellation, CancellationToken, FortressError};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+
+/// Reported a few times a second while keys are derived from a password, and once more when the derivation is done.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DeriveProgress {
+	pub elapsed: Duration,
+	/// How long the whole derivation is expected to take.  Once it's finished, this is the time it actually took.
+	pub estimated: Duration,
+	pub finished: bool,
+}
+
+impl DeriveProgress {
+	/// How far along the derivation is, from 0 to 1.  Stays just below 1 until it's finished, if it takes longer than estimated.
+	pub fn fraction(&self) -> f64 {
+		if self.finished {
+			1.0
+		} else if self.elapsed >= self.estimated {
+			0.99
+		} else {
+			(self.elapsed.as_secs_f64() / self.estimated.as_secs_f64()).min(0.99)
+		}
+	}
+
+	/// Estimated time left; zero once the estimate has run out.
+	pub fn remaining(&self) -> Duration {
+		self.estimated.saturating_sub(self.elapsed)
+	}
+}
+
+
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPhase {
+	/// Comparing the server's objects with ours, downloading and merging the ones that differ.
+	Downloading,
+	/// Uploading the objects the server doesn't have or has an older version of.
+	Uploading,
+}
+
+/// Reported by sync for each object it goes through.  A sync takes one or more rounds (usually one), each with both phases.
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SyncProgress {
+	/// Counts from 1.
+	pub round: usize,
+	pub phase: SyncPhase,
+	/// Objects gone through so far in this phase.
+	pub done: usize,
+	pub total: usize,
+}
+
+
+/// Runs the key derivation `f`, expected to take about `estimated`, on a worker thread (see `cancellation::run_cancellable`),
+/// reporting progress while it runs.
+pub(crate) fn derive_with_progress<T, F>(
+	cancel: &CancellationToken,
+	estimated: Duration,
+	f: F,
+	progress: &mut dyn FnMut(DeriveProgress),
+) -> Result<T, FortressError>
+where
+	T: Send + 'static,
+	F: FnOnce() -> T + Send + 'static,
+{
+	let start = Instant::now();
+	let report = |elapsed| DeriveProgress {
+		elapsed,
+		estimated,
+		finished: false,
+	};
+	progress(report(Duration::ZERO));
+
+	let result = cancellation::run_cancellable_with_ticks(cancel, f, &mut |elapsed| progress(report(elapsed)))?;
+
+	let elapsed = start.elapsed();
+	progress(DeriveProgress {
+		elapsed,
+		estimated: elapsed,
+		finished: true,
+	});
+	Ok(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{derive_with_progress, DeriveProgress};
+	use crate::CancellationToken;
+	use std::{thread, time::Duration};
+
+	#[test]
+	fn derive_progress() {
+		let mut reports = Vec::new();
+		let result = derive_with_progress(
+			&CancellationToken::new(),
+			Duration::from_millis(200),
+			|| {
+				thread::sleep(Duration::from_millis(300));
+				42
+			},
+			&mut |progress| reports.push(progress),
+		);
+		assert_eq!(result.unwrap(), 42);
+
+		let first = reports.first().unwrap();
+		let last = reports.last().unwrap();
+		assert_eq!(first.fraction(), 0.0);
+		assert_eq!(first.remaining(), Duration::from_millis(200));
+		assert!(reports.len() > 3);
+		assert!(reports.windows(2).all(|pair| pair[0].fraction() <= pair[1].fraction()));
+		// Took longer than estimated
+		assert_eq!(reports[reports.len() - 2].fraction(), 0.99);
+		assert_eq!(reports[reports.len() - 2].remaining(), Duration::ZERO);
+		assert_eq!(last.fraction(), 1.0);
+
+		assert!(last.finished);
+		assert!(last.elapsed >= Duration::from_millis(300));
+
+		let quarter = DeriveProgress {
+			elapsed: Duration::from_secs(1),
+			estimated: Duration::from_secs(4),
+			finished: false,
+		};
+		assert_eq!(quarter.fraction(), 0.25);
+		assert_eq!(quarter.remaining(), Duration::from_secs(3));
+	}
+}
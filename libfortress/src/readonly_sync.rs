@@ -0,0 +1,130 @@
+// Read-only sync credentials, for backup agents and the like that should be able to download the account's objects but never
+// change them.  The read-only login key is derived from the login key (`LoginKey::readonly_login_key`) and goes with the same
+// login ID.  Servers derive it from the login key they know, so it doesn't need registering, and only answer GET requests made
+// with it; anything else fails with 403 Forbidden.
+//
+// `Database::get_readonly_sync_keys` gives the credentials to hand to such an agent, which downloads the encrypted objects with
+// `ReadonlySyncKeys::download_objects` without needing any of the encryption keys.  A database can also be switched to them
+// with `make_sync_read_only`, e.g. a copy on a machine that should only follow along.  Its syncs then fail with
+// FortressError::SyncReadOnly instead of uploading anything, and `set_login_key` gives it full access again.
+//
+// Since it's derived from the login key, changing the password or rotating the login key (see the login_key_rotation module)
+// revokes the read-only key too.
+use crate::{api_request, limits, parse_object_list, sync_endpoint, ApiError, CancellationToken, Database, FortressError, SyncClient, SyncOptions, ID};
+use fortresscrypto::{LoginId, LoginKey};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+
+/// Credentials that can download an account's objects from the sync server but not change them (see the readonly_sync module).
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct ReadonlySyncKeys {
+	login_id: LoginId,
+	login_key: LoginKey,
+}
+
+impl ReadonlySyncKeys {
+	pub fn get_login_id(&self) -> &LoginId {
+		&self.login_id
+	}
+
+	pub fn get_login_key(&self) -> &LoginKey {
+		&self.login_key
+	}
+
+	/// Every object on the sync server at `url`, encrypted as the server stores it, in no particular order.  Objects removed
+	/// from the server while they're downloaded are left out.
+	pub fn download_objects(&self, url: &Url) -> Result<Vec<(ID, Vec<u8>)>, FortressError> {
+		let client = SyncClient::new(&SyncOptions::default(), None, &CancellationToken::new())?;
+		let response = api_request(&client, &self.login_id, &self.login_key, Method::GET, sync_endpoint(url, "/objects")?, "")?;
+		let listing = parse_object_list(&limits::read_listing(response)?)?;
+		let mut objects = Vec::new();
+
+		for (id, _) in listing {
+			let object_url = sync_endpoint(url, &format!("/object/{}", id.to_hex()))?;
+			match api_request(&client, &self.login_id, &self.login_key, Method::GET, object_url, "") {
+				Ok(response) => objects.push((id, limits::read_object(&id, response)?)),
+				Err(FortressError::SyncApiError(ApiError::ApiError(404, _))) => (),
+				Err(err) => return Err(err),
+			}
+		}
+
+		Ok(objects)
+	}
+}
+
+
+impl Database {
+	/// Credentials for read-only access to this database's sync account.  Blocks until the sync keys are derived if they're
+	/// still pending.
+	pub fn get_readonly_sync_keys(&self) -> ReadonlySyncKeys {
+		let login_key = if self.sync_parameters.is_read_only() {
+			self.get_login_key().clone()
+		} else {
+			self.get_login_key().readonly_login_key()
+		};
+
+		ReadonlySyncKeys {
+			login_id: *self.get_login_id(),
+			login_key,
+		}
+	}
+
+	/// Switches to the read-only credentials, so syncs only download (see the readonly_sync module).  Blocks until the sync keys
+	/// are derived if they're still pending.
+	pub fn make_sync_read_only(&mut self) {
+		self.sync_parameters.make_read_only();
+	}
+
+	/// True if the database syncs with read-only credentials.
+	pub fn is_sync_read_only(&self) -> bool {
+		self.sync_parameters.is_read_only()
+	}
+
+	/// Fails with FortressError::SyncReadOnly if the database syncs with read-only credentials.
+	pub(crate) fn check_sync_writable(&self) -> Result<(), FortressError> {
+		if self.sync_parameters.is_read_only() {
+			Err(FortressError::SyncReadOnly)
+		} else {
+			Ok(())
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, FortressError};
+	use reqwest::Url;
+
+	#[test]
+	fn readonly_sync_keys() {
+		let mut db = Database::new_with_password("username", "password");
+		let login_key = db.get_login_key().clone();
+		let keys = db.get_readonly_sync_keys();
+		assert_eq!(keys.get_login_id(), db.get_login_id());
+		assert_ne!(keys.get_login_key(), &login_key);
+		assert_eq!(keys.get_login_key(), &login_key.readonly_login_key());
+		assert!(db.check_sync_writable().is_ok());
+
+		db.make_sync_read_only();
+		assert!(db.is_sync_read_only());
+		assert_eq!(db.get_readonly_sync_keys(), keys);
+		assert!(matches!(db.check_sync_writable(), Err(FortressError::SyncReadOnly)));
+		db.set_sync_url(Some(Url::parse("http://127.0.0.1:1").unwrap()));
+		assert!(matches!(db.rotate_login_key(), Err(FortressError::SyncReadOnly)));
+
+		// Kept when saved
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("database.fortress");
+		db.save_to_path(&path).unwrap();
+		let saved = Database::load_from_path(&path, "password").unwrap();
+		assert!(saved.is_sync_read_only());
+		assert_eq!(saved.get_readonly_sync_keys(), keys);
+
+		db.set_login_key(login_key.clone());
+		assert!(!db.is_sync_read_only());
+		assert_eq!(db.get_login_key(), &login_key);
+	}
+}
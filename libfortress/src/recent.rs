@@ -0,0 +1,124 @@
+// Recently accessed entries, so frontends can offer "recently used" without scanning history.
+// Accesses (viewing an entry, copying a password, ...) are reported by the frontend through `Database::record_access`; reading
+// entries through the API doesn't count.  The list is local to this device and never synced.  By default it only lasts for
+// the session, since it reveals which entries were used; `set_persist_recent` saves it with the database instead.
+use crate::{Database, Entry, ID};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+
+/// Maximum number of recently accessed entries remembered.
+pub const RECENT_ENTRIES_LIMIT: usize = 20;
+
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Default)]
+pub(crate) struct RecentEntries {
+	persist: bool,
+	/// Most recent first, without duplicates.
+	entries: VecDeque<ID>,
+}
+
+impl RecentEntries {
+	/// Used to leave the list out of the saved database unless persistence was asked for.
+	pub(crate) fn is_transient(&self) -> bool {
+		!self.persist
+	}
+
+	fn record(&mut self, id: ID) {
+		self.entries.retain(|existing| *existing != id);
+		self.entries.push_front(id);
+		self.entries.truncate(RECENT_ENTRIES_LIMIT);
+	}
+}
+
+
+impl Database {
+	/// Records that the user accessed entry `id`, moving it to the front of `recent`.  Does nothing if `id` isn't an entry.
+	pub fn record_access(&mut self, id: &ID) {
+		if self.get_entry_by_id(id).is_some() {
+			self.recent_entries.record(*id);
+		}
+	}
+
+	/// Recently accessed entries, most recent first.  Entries that have since been removed from the database are skipped.
+	pub fn recent(&self) -> impl Iterator<Item = &Entry> {
+		self.recent_entries.entries.iter().filter_map(|id| self.get_entry_by_id(id))
+	}
+
+	pub fn clear_recent(&mut self) {
+		self.recent_entries.entries.clear();
+	}
+
+	pub fn get_persist_recent(&self) -> bool {
+		self.recent_entries.persist
+	}
+
+	/// Whether the recently accessed entries are saved with the database (they're kept for the session only by default).
+	pub fn set_persist_recent(&mut self, persist: bool) {
+		self.recent_entries.persist = persist;
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::RECENT_ENTRIES_LIMIT;
+	use crate::{Database, Entry, ID};
+
+	#[test]
+	fn recent() {
+		let mut db = Database::new_with_password("username", "password");
+		let ids = (0..RECENT_ENTRIES_LIMIT + 5)
+			.map(|_| {
+				let entry = Entry::new();
+				let id = *entry.get_id();
+				db.add_entry(entry);
+				id
+			})
+			.collect::<Vec<_>>();
+		let recent = |db: &Database| db.recent().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+		assert_eq!(recent(&db), []);
+
+		db.record_access(&ids[0]);
+		db.record_access(&ids[1]);
+		db.record_access(&ids[0]);
+		assert_eq!(recent(&db), [ids[0], ids[1]]);
+
+		// Directories aren't entries
+		let root = *db.get_root().get_id();
+		db.record_access(&root);
+		assert_eq!(recent(&db), [ids[0], ids[1]]);
+
+		// Only the most recent are kept
+		for id in &ids {
+			db.record_access(id);
+		}
+		assert_eq!(recent(&db), ids.iter().rev().take(RECENT_ENTRIES_LIMIT).copied().collect::<Vec<ID>>());
+
+		db.clear_recent();
+		assert_eq!(recent(&db), []);
+	}
+
+	#[test]
+	fn persistence() {
+		let tmp_dir = tempfile::tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		let entry = Entry::new();
+		let id = *entry.get_id();
+		db.add_entry(entry);
+		db.record_access(&id);
+
+		// Session only by default
+		db.save_to_path(&path).unwrap();
+		let loaded = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(loaded.recent().count(), 0);
+		assert!(!loaded.get_persist_recent());
+
+		db.set_persist_recent(true);
+		db.save_to_path(&path).unwrap();
+		let loaded = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(loaded.recent().map(|entry| *entry.get_id()).collect::<Vec<_>>(), [id]);
+		assert!(loaded.get_persist_recent());
+	}
+}
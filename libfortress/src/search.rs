@@ -0,0 +1,294 @@
+// Searching entries for frontends.
+// A query is split on whitespace into terms, and an entry matches if every term matches at least one of its searchable fields or
+// tags.  Terms can be restricted to one field with a prefix (e.g. `user:alice`).  Matching is case-insensitive and fuzzy: a term
+// matches a field if its characters appear in the field in order, but exact, prefix, and substring matches rank higher.
+//
+// Protected fields are never searched, so search results can't be used to probe for secrets.
+use crate::{Database, Entry, ID};
+use serde::Serialize;
+use std::cmp::Reverse;
+
+
+// Searchable fields and how much a match in each is worth, relative to the others.
+const SEARCH_FIELDS: &[(&str, u32)] = &[("title", 4), ("username", 2), ("url", 2), ("notes", 1)];
+const TAG_WEIGHT: u32 = 3;
+
+
+/// A search result.  Results are ordered best match first.
+#[derive(Serialize, Eq, PartialEq, Debug, Clone)]
+pub struct SearchHit {
+	pub id: ID,
+	/// Higher is better.  Only meaningful relative to other hits from the same search.
+	pub score: u32,
+	/// What matched, ordered by field and then tag, for highlighting.
+	pub matches: Vec<SearchMatch>,
+}
+
+#[derive(Serialize, Eq, PartialEq, Debug, Clone)]
+pub struct SearchMatch {
+	pub target: SearchTarget,
+	/// Indices (in chars, not bytes) of the matched characters, sorted.
+	pub positions: Vec<usize>,
+}
+
+#[derive(Serialize, Eq, PartialEq, Ord, PartialOrd, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchTarget {
+	Field(String),
+	Tag(String),
+}
+
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Scope {
+	Any,
+	Field(&'static str),
+	Tag,
+}
+
+struct Term {
+	scope: Scope,
+	text: Vec<char>,
+}
+
+impl Term {
+	fn parse(term: &str) -> Term {
+		let scope = term.split_once(':').and_then(|(prefix, rest)| {
+			let scope = match prefix.to_lowercase().as_str() {
+				"title" => Scope::Field("title"),
+				"user" | "username" => Scope::Field("username"),
+				"url" => Scope::Field("url"),
+				"notes" => Scope::Field("notes"),
+				"tag" => Scope::Tag,
+				_ => return None,
+			};
+			(!rest.is_empty()).then_some((scope, rest))
+		});
+		// Unknown prefixes (e.g. "https:") are searched for as-is
+		let (scope, text) = scope.unwrap_or((Scope::Any, term));
+
+		Term { scope, text: lowercase(text) }
+	}
+}
+
+
+impl Database {
+	/// Searches entries' titles, usernames, URLs, notes, and tags.  See the search module for the query syntax.
+	/// Returns matching entries, best match first.  An empty query matches nothing.
+	pub fn search(&self, query: &str) -> Vec<SearchHit> {
+		let terms = query.split_whitespace().map(Term::parse).collect::<Vec<_>>();
+
+		if terms.is_empty() {
+			return Vec::new();
+		}
+
+		let mut hits = self.list_entries().filter_map(|entry| search_entry(entry, &terms)).collect::<Vec<_>>();
+
+		// Ties are broken by title and then ID so results are stable
+		hits.sort_by_cached_key(|hit| {
+			let title = self
+				.get_entry_by_id(&hit.id)
+				.and_then(|entry| entry.get("title"))
+				.map(|title| title.to_lowercase());
+			(Reverse(hit.score), title, hit.id)
+		});
+
+		hits
+	}
+}
+
+
+fn search_entry(entry: &Entry, terms: &[Term]) -> Option<SearchHit> {
+	let mut score = 0;
+	let mut matches: Vec<SearchMatch> = Vec::new();
+
+	for term in terms {
+		let fields = SEARCH_FIELDS
+			.iter()
+			.filter(|(name, _)| term.scope == Scope::Any || term.scope == Scope::Field(name))
+			.filter(|(name, _)| !entry.is_field_protected(name))
+			.filter_map(|(name, weight)| Some((SearchTarget::Field(name.to_string()), entry.get(*name)?.as_str(), *weight)));
+		let tags = entry
+			.get_tags()
+			.iter()
+			.filter(|_| term.scope == Scope::Any || term.scope == Scope::Tag)
+			.map(|tag| (SearchTarget::Tag(tag.clone()), tag.as_str(), TAG_WEIGHT));
+
+		// A term counts for its best match, but every match is reported for highlighting
+		let mut best = None;
+
+		for (target, value, weight) in fields.chain(tags) {
+			let Some((quality, positions)) = match_term(&term.text, value) else {
+				continue;
+			};
+			best = best.max(Some(quality * weight));
+
+			match matches.iter_mut().find(|m| m.target == target) {
+				Some(existing) => {
+					existing.positions.extend(positions);
+					existing.positions.sort_unstable();
+					existing.positions.dedup();
+				},
+				None => matches.push(SearchMatch { target, positions }),
+			}
+		}
+
+		score += best?;
+	}
+
+	matches.sort_unstable_by(|a, b| a.target.cmp(&b.target));
+
+	Some(SearchHit {
+		id: *entry.get_id(),
+		score,
+		matches,
+	})
+}
+
+
+/// Case-insensitively matches `term` (already lowercase) against `value`.
+/// Returns the quality of the match and the positions of the matched characters, or None if it doesn't match.
+fn match_term(term: &[char], value: &str) -> Option<(u32, Vec<usize>)> {
+	let value = lowercase(value);
+
+	if term.is_empty() || term.len() > value.len() {
+		return None;
+	}
+
+	// Substring matches, preferring the whole value, then the start of it, then the start of a word
+	let substring = value.windows(term.len()).enumerate().filter(|(_, window)| *window == term).map(|(start, _)| {
+		let quality = if term.len() == value.len() {
+			100
+		} else if start == 0 {
+			80
+		} else if !value[start - 1].is_alphanumeric() {
+			60
+		} else {
+			40
+		};
+		(quality, start)
+	});
+
+	if let Some((quality, start)) = substring.max_by_key(|(quality, start)| (*quality, Reverse(*start))) {
+		return Some((quality, (start..start + term.len()).collect()));
+	}
+
+	// Fuzzy: the term's characters in order, with fewer gaps ranking higher
+	let mut positions = Vec::with_capacity(term.len());
+	let mut chars = value.iter().enumerate();
+
+	for c in term {
+		let (position, _) = chars.find(|(_, v)| *v == c)?;
+		positions.push(position);
+	}
+
+	let span = positions[positions.len() - 1] - positions[0] + 1;
+	let gaps = (span - term.len()) as u32;
+
+	Some((20u32.saturating_sub(gaps).max(1), positions))
+}
+
+
+// Lowercases char by char, so that char positions in the result line up with the original.
+fn lowercase(s: &str) -> Vec<char> {
+	s.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{match_term, SearchTarget};
+	use crate::{testing, Database, EntryHistory, FieldKind, FieldMetadata, ID};
+	use std::collections::HashMap;
+
+	fn add_entry(db: &mut Database, data: &[(&str, &str)], tags: &[&str]) -> ID {
+		let id = testing::add_entry(db, data);
+		for tag in tags {
+			db.get_entry_by_id_mut(&id).unwrap().add_tag(*tag);
+		}
+		id
+	}
+
+	#[test]
+	fn matching() {
+		let term = |s: &str| s.chars().collect::<Vec<_>>();
+
+		assert_eq!(match_term(&term("bank"), "Bank"), Some((100, vec![0, 1, 2, 3])));
+		assert_eq!(match_term(&term("bank"), "Bank of Example"), Some((80, vec![0, 1, 2, 3])));
+		assert_eq!(match_term(&term("example"), "Bank of Example").unwrap().0, 60);
+		assert_eq!(match_term(&term("ample"), "Bank of Example").unwrap().0, 40);
+		assert_eq!(match_term(&term("bnk"), "Bank").unwrap(), (19, vec![0, 2, 3]));
+		assert_eq!(match_term(&term("knab"), "Bank"), None);
+		assert_eq!(match_term(&term("banking"), "Bank"), None);
+
+		// Positions are in chars
+		assert_eq!(match_term(&term("ü"), "Müller").unwrap().1, vec![1]);
+	}
+
+	#[test]
+	fn search() {
+		let mut db = Database::new_with_password("username", "password");
+		let bank = add_entry(
+			&mut db,
+			&[("title", "Bank"), ("username", "alice"), ("password", "bank"), ("url", "https://bank.example")],
+			&["finance"],
+		);
+		let email = add_entry(
+			&mut db,
+			&[("title", "Email"), ("username", "alice@example.com"), ("notes", "Work bank account")],
+			&[],
+		);
+		let forum = add_entry(&mut db, &[("title", "Forum"), ("username", "bob")], &["banking"]);
+
+		let ids = |db: &Database, query: &str| db.search(query).into_iter().map(|hit| hit.id).collect::<Vec<_>>();
+
+		// Ranked by where and how well the term matched
+		assert_eq!(ids(&db, "bank"), [bank, forum, email]);
+		assert_eq!(ids(&db, "BANK"), ids(&db, "bank"));
+
+		// Every term has to match
+		assert_eq!(ids(&db, "alice bank"), [bank, email]);
+		assert_eq!(ids(&db, "alice bob"), []);
+
+		// Prefixes
+		assert_eq!(ids(&db, "user:alice"), [bank, email]);
+		assert_eq!(ids(&db, "user:bob"), [forum]);
+		assert_eq!(ids(&db, "tag:bank"), [forum]);
+		assert_eq!(ids(&db, "title:bank"), [bank]);
+		assert_eq!(ids(&db, "notes:account"), [email]);
+		assert_eq!(ids(&db, "https://bank"), [bank]);
+
+		// Fuzzy
+		assert_eq!(ids(&db, "frm"), [forum]);
+		assert_eq!(ids(&db, ""), []);
+		assert_eq!(ids(&db, "   "), []);
+
+		// Matches are reported for highlighting
+		let hits = db.search("alice bnk");
+		assert_eq!(hits[0].id, bank);
+		let targets = hits[0].matches.iter().map(|m| &m.target).collect::<Vec<_>>();
+		assert_eq!(
+			targets,
+			[
+				&SearchTarget::Field("title".to_string()),
+				&SearchTarget::Field("url".to_string()),
+				&SearchTarget::Field("username".to_string()),
+			]
+		);
+		assert_eq!(hits[0].matches[0].positions, [0, 2, 3]);
+
+		// Protected fields are never searched
+		assert_eq!(ids(&db, "hunter2"), []);
+		let secret = add_entry(&mut db, &[("title", "Secret"), ("notes", "hunter2")], &[]);
+		assert_eq!(ids(&db, "hunter2"), [secret]);
+		db.get_entry_by_id_mut(&secret).unwrap().edit(EntryHistory::new(HashMap::new()).with_metadata(
+			"notes",
+			FieldMetadata {
+				kind: FieldKind::Text,
+				protected: true,
+				derived: None,
+			},
+		));
+		assert_eq!(ids(&db, "hunter2"), []);
+	}
+}
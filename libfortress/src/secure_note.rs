@@ -0,0 +1,113 @@
+// Secure notes: entries that hold a block of text rather than a login.  A note is an ordinary entry whose `entry_type` field is
+// `note`, with its title in `title` and its text in `notes`, so it syncs, has history, and is searched and exported like any
+// other entry, and older versions show it as an entry with a long notes field.  Its `note_format` field is `markdown` if the
+// text should be rendered as Markdown, and empty (or missing) for plain text.  Tags work as they do on any entry.
+use crate::{Database, Entry, EntryHistory};
+use std::collections::HashMap;
+
+
+/// Name of the field that marks an entry as a note (see the secure_note module).
+pub const ENTRY_TYPE_FIELD: &str = "entry_type";
+/// `ENTRY_TYPE_FIELD`'s value for notes.
+pub const NOTE_ENTRY_TYPE: &str = "note";
+/// Name of the field a note's format is kept in.
+pub const NOTE_FORMAT_FIELD: &str = "note_format";
+
+pub(crate) const MARKDOWN: &str = "markdown";
+
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum NoteFormat {
+	#[default]
+	Plain,
+	Markdown,
+}
+
+
+impl Entry {
+	/// A new note.  Add tags with `add_tag`.
+	pub fn new_note(title: &str, text: &str, format: NoteFormat) -> Entry {
+		let mut entry = Entry::new();
+		entry.edit(entry.note_edit(title, text, format));
+		entry
+	}
+
+	/// An edit (see `new_edit`) that sets the note's title, text, and format, or turns the entry into a note with them.
+	pub fn note_edit(&self, title: &str, text: &str, format: NoteFormat) -> EntryHistory {
+		let format = match format {
+			NoteFormat::Plain => "",
+			NoteFormat::Markdown => MARKDOWN,
+		};
+
+		self.new_edit(HashMap::from([
+			(ENTRY_TYPE_FIELD.to_string(), NOTE_ENTRY_TYPE.to_string()),
+			("title".to_string(), title.to_string()),
+			("notes".to_string(), text.to_string()),
+			(NOTE_FORMAT_FIELD.to_string(), format.to_string()),
+		]))
+	}
+
+	pub fn is_note(&self) -> bool {
+		self.get(ENTRY_TYPE_FIELD).is_some_and(|entry_type| entry_type == NOTE_ENTRY_TYPE)
+	}
+
+	/// The note's format, or None if the entry isn't a note.  Formats this version doesn't know are treated as plain text.
+	pub fn get_note_format(&self) -> Option<NoteFormat> {
+		if !self.is_note() {
+			return None;
+		}
+
+		match self.get(NOTE_FORMAT_FIELD).map(String::as_str) {
+			Some(MARKDOWN) => Some(NoteFormat::Markdown),
+			_ => Some(NoteFormat::Plain),
+		}
+	}
+}
+
+
+impl Database {
+	/// Every note outside the trash, in no particular order.
+	pub fn list_notes(&self) -> impl Iterator<Item = &Entry> {
+		let trash = self.list_trash_recursive();
+
+		self.list_entries().filter(move |entry| entry.is_note() && !trash.contains(entry.get_id()))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{NoteFormat, NOTE_FORMAT_FIELD};
+	use crate::{Database, Entry, EntryHistory, ID};
+	use std::collections::HashMap;
+
+	#[test]
+	fn notes() {
+		let mut note = Entry::new_note("Wifi", "# Home\nThe password is on the router", NoteFormat::Markdown);
+		assert!(note.is_note());
+		assert_eq!(note.get_note_format(), Some(NoteFormat::Markdown));
+		assert_eq!(note.get("notes").unwrap(), "# Home\nThe password is on the router");
+
+		note.edit(note.note_edit("Wifi", "On the router", NoteFormat::Plain));
+		assert_eq!(note.get_note_format(), Some(NoteFormat::Plain));
+		note.edit(note.new_edit(HashMap::from([(NOTE_FORMAT_FIELD.to_string(), "rst".to_string())])));
+		assert_eq!(note.get_note_format(), Some(NoteFormat::Plain));
+
+		let mut login = Entry::new();
+		login.edit(EntryHistory::new(HashMap::from([("title".to_string(), "Wifi router".to_string())])));
+		assert!(!login.is_note());
+		assert_eq!(login.get_note_format(), None);
+
+		let mut db = Database::new_with_password("username", "password");
+		let note_id = *note.get_id();
+		let trashed_note = Entry::new_note("Old", "", NoteFormat::Plain);
+		let trashed_id = *trashed_note.get_id();
+		db.add_entry(note);
+		db.add_entry(login);
+		db.add_entry(trashed_note);
+		assert!(db.trash_object(&trashed_id));
+
+		assert_eq!(db.list_notes().map(|entry| *entry.get_id()).collect::<Vec<ID>>(), [note_id]);
+		assert_eq!(db.search("router").len(), 2);
+	}
+}
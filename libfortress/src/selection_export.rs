@@ -0,0 +1,127 @@
+// Exports a few chosen fields of selected entries as text to paste elsewhere, e.g. an inventory of accounts for a ticket or email.
+// Unlike `export_json`, this is meant for people rather than programs, and never includes protected fields: a requested field
+// is left blank for any entry where it's protected (see `Entry::is_field_protected`).
+use crate::{Database, Entry, ID};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SelectionFormat {
+	/// A GitHub-flavored markdown table, with a header row of field names.
+	Markdown,
+	/// RFC 4180 CSV, with a header row of field names.
+	Csv,
+	/// A JSON array with an object per entry, leaving out blank fields.
+	Json,
+}
+
+
+impl Database {
+	/// Exports `fields` of the entries in `ids`, in the given order, as `format`.  IDs that aren't entries are skipped.
+	pub fn export_selection<S: AsRef<str>>(&self, ids: &[ID], fields: &[S], format: SelectionFormat) -> String {
+		let fields = fields.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+		let rows = ids
+			.iter()
+			.filter_map(|id| self.get_entry_by_id(id))
+			.map(|entry| fields.iter().map(|field| visible_value(entry, field)).collect::<Vec<_>>())
+			.collect::<Vec<_>>();
+
+		match format {
+			SelectionFormat::Markdown => {
+				let line = |cells: &[&str]| format!("| {} |\n", cells.iter().map(|cell| escape_markdown(cell)).collect::<Vec<_>>().join(" | "));
+				let mut result = line(&fields);
+				result.push_str(&line(&vec!["---"; fields.len()]));
+				rows.iter().for_each(|row| result.push_str(&line(row)));
+				result
+			},
+			SelectionFormat::Csv => {
+				let line = |cells: &[&str]| format!("{}\r\n", cells.iter().map(|cell| escape_csv(cell)).collect::<Vec<_>>().join(","));
+				let mut result = line(&fields);
+				rows.iter().for_each(|row| result.push_str(&line(row)));
+				result
+			},
+			SelectionFormat::Json => {
+				let objects = rows
+					.iter()
+					.map(|row| {
+						let object = fields
+							.iter()
+							.zip(row)
+							.filter(|(_, value)| !value.is_empty())
+							.map(|(field, value)| (field.to_string(), Value::from(*value)))
+							.collect::<Map<_, _>>();
+						Value::Object(object)
+					})
+					.collect::<Vec<_>>();
+				serde_json::to_string_pretty(&objects).expect("internal error")
+			},
+		}
+	}
+}
+
+
+fn visible_value<'a>(entry: &'a Entry, field: &str) -> &'a str {
+	if entry.is_field_protected(field) {
+		""
+	} else {
+		entry.get_state().get(field).map(String::as_str).unwrap_or("")
+	}
+}
+
+
+fn escape_markdown(cell: &str) -> String {
+	cell.replace('\\', "\\\\")
+		.replace('|', "\\|")
+		.replace("\r\n", "<br>")
+		.replace(['\r', '\n'], "<br>")
+}
+
+
+pub(crate) fn escape_csv(cell: &str) -> String {
+	if cell.contains([',', '"', '\r', '\n']) {
+		format!("\"{}\"", cell.replace('"', "\"\""))
+	} else {
+		cell.to_string()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::SelectionFormat;
+	use crate::{testing::add_entry, Database};
+
+	#[test]
+	fn export_selection() {
+		let mut db = Database::new_with_password("username", "password");
+		let email = add_entry(&mut db, &[("title", "Email"), ("username", "alice"), ("password", "hunter2")]);
+		let forum = add_entry(&mut db, &[("title", "Forum | \"Chat\""), ("notes", "line 1\nline 2, more")]);
+		let _unselected = add_entry(&mut db, &[("title", "Bank")]);
+		let root = *db.get_root().get_id();
+		let ids = [forum, root, email];
+		let fields = ["title", "username", "password", "notes"];
+
+		assert_eq!(
+			db.export_selection(&ids, &fields, SelectionFormat::Markdown),
+			"| title | username | password | notes |\n\
+			 | --- | --- | --- | --- |\n\
+			 | Forum \\| \"Chat\" |  |  | line 1<br>line 2, more |\n\
+			 | Email | alice |  |  |\n"
+		);
+		assert_eq!(
+			db.export_selection(&ids, &fields, SelectionFormat::Csv),
+			"title,username,password,notes\r\n\
+			 \"Forum | \"\"Chat\"\"\",,,\"line 1\nline 2, more\"\r\n\
+			 Email,alice,,\r\n"
+		);
+		assert_eq!(
+			serde_json::from_str::<serde_json::Value>(&db.export_selection(&ids, &fields, SelectionFormat::Json)).unwrap(),
+			serde_json::json!([
+				{ "title": "Forum | \"Chat\"", "notes": "line 1\nline 2, more" },
+				{ "title": "Email", "username": "alice" },
+			])
+		);
+	}
+}
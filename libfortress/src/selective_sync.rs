@@ -0,0 +1,111 @@
+// Selective sync: keeping some entries off the sync server.  A directory can be marked local-only
+// (`Directory::set_local_only`), which is a directory edit like a rename, so it has history and syncs.  Sync then leaves
+// everything inside the directory alone, however deeply nested: it isn't uploaded, even if the server doesn't have it.  The
+// directory itself still syncs, so other devices learn the flag and stop uploading their copies too, though they only see its
+// name and the IDs of what's in it.
+//
+// Objects in a local-only directory that the server already has, e.g. because they were uploaded before the directory was
+// marked, are still downloaded and merged like any other, but listed in `SyncReport::local_only_on_server` so the user can
+// tell the server has them.  Nothing is deleted from the server.
+//
+// Objects trashed from a local-only directory stay local-only.  Moving an object out of one (see `Database::move_object`)
+// lets it sync again.
+//
+// NOTE: Versions from before local-only directories can't read a directory that has ever been marked local-only, so their syncs
+// fail once one reaches the server.
+use crate::{Database, ID, TRASH_DIRECTORY_ID};
+use std::collections::HashSet;
+
+
+impl Database {
+	/// Every object sync keeps off the server (see the selective_sync module): everything inside a local-only directory, but
+	/// not the directory itself, plus anything trashed from one.
+	pub fn list_local_only_objects(&self) -> HashSet<ID> {
+		let mut local_only = HashSet::new();
+		let mut pending = self
+			.list_directories()
+			.filter(|directory| directory.is_local_only())
+			.flat_map(|directory| directory.entries.iter().copied())
+			.collect::<Vec<_>>();
+		let trashed = self
+			.get_trash()
+			.map(|trash| trash.entries.iter().copied().collect::<Vec<_>>())
+			.unwrap_or_default();
+
+		// Trashed objects are local-only if what they were trashed from is, which may only turn out after other trashed
+		// objects have been looked at
+		loop {
+			while let Some(id) = pending.pop() {
+				if id == TRASH_DIRECTORY_ID || !local_only.insert(id) {
+					continue;
+				}
+
+				if let Some(directory) = self.get_directory_by_id(&id) {
+					pending.extend(directory.entries.iter().copied());
+				}
+			}
+
+			pending.extend(trashed.iter().filter(|id| !local_only.contains(*id)).filter(|id| {
+				self.trashed_from(id)
+					.is_some_and(|parent| local_only.contains(&parent) || self.get_directory_by_id(&parent).is_some_and(|parent| parent.is_local_only()))
+			}));
+
+			if pending.is_empty() {
+				return local_only;
+			}
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, Directory, Entry, ROOT_DIRECTORY_ID};
+	use std::collections::HashSet;
+
+	#[test]
+	fn local_only_objects() {
+		let mut db = Database::new_with_password("username", "password");
+		let (mut private, nested) = (Directory::new(), Directory::new());
+		let (private_id, nested_id) = (*private.get_id(), *nested.get_id());
+		private.rename("Private");
+		db.add_directory(private);
+		db.add_directory(nested);
+		db.move_object(&nested_id, &private_id);
+		let (entry, nested_entry, public_entry) = (Entry::new(), Entry::new(), Entry::new());
+		let (entry_id, nested_entry_id) = (*entry.get_id(), *nested_entry.get_id());
+		db.add_entry(entry);
+		db.add_entry(nested_entry);
+		db.add_entry(public_entry);
+		db.move_object(&entry_id, &private_id);
+		db.move_object(&nested_entry_id, &nested_id);
+		assert!(db.list_local_only_objects().is_empty());
+
+		let directory = db.get_directory_by_id_mut(&private_id).unwrap();
+		directory.set_local_only(true);
+		directory.set_local_only(true);
+		assert!(directory.is_local_only());
+		assert_eq!(directory.get_history().len(), 4);
+		assert_eq!(db.list_local_only_objects(), HashSet::from([nested_id, entry_id, nested_entry_id]));
+
+		// Trashed objects stay local-only, even from a trashed directory
+		assert!(db.trash_object(&entry_id));
+		assert!(db.trash_object(&nested_id));
+		assert_eq!(db.list_local_only_objects(), HashSet::from([nested_id, entry_id, nested_entry_id]));
+
+		// Kept across saves and history compaction
+		let json = serde_json::to_string(db.get_directory_by_id(&private_id).unwrap()).unwrap();
+		let directory: Directory = serde_json::from_str(&json).unwrap();
+		assert!(directory.is_local_only());
+		let compacted = directory.compact_history(u64::MAX).unwrap();
+		assert!(compacted.is_local_only());
+		assert_eq!(compacted.get_name(), Some("Private"));
+
+		// Moving out of the directory lets an object sync again
+		assert!(db.restore_object(&entry_id));
+		db.move_object(&entry_id, &ROOT_DIRECTORY_ID);
+		assert_eq!(db.list_local_only_objects(), HashSet::from([nested_id, nested_entry_id]));
+		db.get_directory_by_id_mut(&private_id).unwrap().set_local_only(false);
+		assert!(db.list_local_only_objects().is_empty());
+	}
+}
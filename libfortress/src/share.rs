@@ -0,0 +1,147 @@
+// Sharing entries with another Fortress user, by sending them a file (e.g. by email).  Each database has its own share key pair;
+// the recipient hands out its public key (`get_share_public_key`), and `export_share` encrypts a bundle of entries that only the
+// database holding the matching secret key can open with `import_share` (see fortresscrypto's share module).
+//
+// Bundles hold each entry's current state as a single edit, so the recipient never sees old values from the entry's history.
+// Entries keep their IDs, so sharing an entry again after it's been edited merges the new state into the recipient's copy.  The
+// share key is local to the database file: it isn't synced, so a bundle can only be imported into the database it was made for.
+use crate::{database_object::DatabaseObject, id_policy, Database, Entry, FortressError, ID};
+use fortresscrypto::{SharePublicKey, Zeroizing};
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Serialize, Deserialize)]
+struct ShareBundle {
+	entries: Vec<Entry>,
+}
+
+
+impl Database {
+	/// The public key other users need to share entries with this database.
+	pub fn get_share_public_key(&self) -> SharePublicKey {
+		self.share_key.public_key()
+	}
+
+	/// Encrypts the entries in `ids` for the database whose share public key is `recipient`.  IDs that aren't entries are skipped.
+	pub fn export_share(&self, ids: &[ID], recipient: &SharePublicKey) -> Vec<u8> {
+		let entries = ids
+			.iter()
+			.filter_map(|id| self.get_entry_by_id(id))
+			.map(|entry| entry.compact_history(u64::MAX).unwrap_or_else(|| entry.clone()))
+			.collect();
+		let plaintext = Zeroizing::new(serde_json::to_vec(&ShareBundle { entries }).expect("internal error"));
+
+		fortresscrypto::encrypt_share(recipient, &plaintext)
+	}
+
+	/// Decrypts a bundle made by `export_share` for this database, and adds its entries to the root directory, or merges them into
+	/// the existing copies of entries that were shared before.  Returns the IDs of the entries.
+	/// Nothing is imported if the bundle can't be decrypted, or any of its entries has a reserved ID (see the id_policy module),
+	/// is over the limits in the limits module, or conflicts with the existing copy (FortressError::ShareConflict).
+	pub fn import_share(&mut self, data: &[u8]) -> Result<Vec<ID>, FortressError> {
+		let plaintext = fortresscrypto::decrypt_share(&self.share_key, data)?;
+		let bundle: ShareBundle = serde_json::from_slice(&plaintext)?;
+		let mut imported = Vec::with_capacity(bundle.entries.len());
+
+		for entry in &bundle.entries {
+			let id = *entry.get_id();
+			id_policy::check_entry_id(&id)?;
+			entry.check_limits().map_err(|limit| FortressError::SyncLimitExceeded { id: Some(id), limit })?;
+
+			let merged = match self.objects.get(&id) {
+				None => None,
+				Some(DatabaseObject::Entry(existing)) => Some(existing.merge(entry).ok_or(FortressError::ShareConflict(id))?),
+				Some(_) => return Err(FortressError::ShareConflict(id)),
+			};
+
+			imported.push((id, merged.unwrap_or_else(|| entry.clone())));
+		}
+
+		// Shared edits keep whatever device they were attributed to (none), rather than being attributed to this one
+		for (id, entry) in &imported {
+			let new = self.objects.get(id).is_none();
+			self.insert_imported_object(DatabaseObject::Entry(entry.clone()))?;
+			if new {
+				self.get_root_mut().add(*id);
+			}
+		}
+
+		Ok(imported.into_iter().map(|(id, _)| id).collect())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::ShareBundle;
+	use crate::{testing::fields, Database, Directory, Entry, EntryHistory, FortressError, ID};
+	use tempfile::tempdir;
+
+	#[test]
+	fn share() {
+		let mut alice = Database::new_with_password("alice", "password");
+		let mut bob = Database::new_with_password("bob", "password");
+		let mut eve = Database::new_with_password("eve", "password");
+
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[("title", "Router"), ("password", "old")])));
+		let entry_id = *entry.get_id();
+		alice.add_entry(entry);
+		let entry = alice.get_entry_by_id_mut(&entry_id).unwrap();
+		entry.edit(entry.new_edit(fields(&[("password", "hunter2")])));
+		let unshared = Entry::new();
+		let unshared_id = *unshared.get_id();
+		alice.add_entry(unshared);
+
+		let bundle = alice.export_share(&[entry_id], &bob.get_share_public_key());
+		assert!(matches!(eve.import_share(&bundle), Err(FortressError::CryptoError(_))));
+		assert_eq!(bob.import_share(&bundle).unwrap(), [entry_id]);
+
+		// Only the current state is shared
+		let shared = bob.get_entry_by_id(&entry_id).unwrap();
+		assert_eq!(shared.get_state(), alice.get_entry_by_id(&entry_id).unwrap().get_state());
+		assert_eq!(shared.get_history().len(), 1);
+		assert!(!serde_json::to_string(shared).unwrap().contains("old"));
+		assert!(bob.get_root().list_entries(&bob).contains(&&entry_id));
+		assert!(bob.get_entry_by_id(&unshared_id).is_none());
+
+		// Sharing again after an edit merges into the recipient's copy, even after the recipient reloads its database
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("bob.fortressdb");
+		bob.save_to_path(&path).unwrap();
+		let mut bob = Database::load_from_path(&path, "password").unwrap();
+		let entry = alice.get_entry_by_id_mut(&entry_id).unwrap();
+		entry.edit(entry.new_edit(fields(&[("username", "admin")])));
+		let bundle = alice.export_share(&[entry_id], &bob.get_share_public_key());
+		assert_eq!(bob.import_share(&bundle).unwrap(), [entry_id]);
+		assert_eq!(bob.get_entry_by_id(&entry_id).unwrap()["username"], "admin");
+		assert_eq!(bob.get_root().list_entries(&bob).iter().filter(|id| ***id == entry_id).count(), 1);
+
+		// Nothing is imported if any entry clashes with an object that isn't an entry
+		let directory = Directory::new();
+		let directory_id = *directory.get_id();
+		bob.add_directory(directory);
+		let bundle = crafted_bundle(&bob, &alice, &entry_id, directory_id);
+		let before = bob.clone();
+		assert!(matches!(bob.import_share(&bundle), Err(FortressError::ShareConflict(id)) if id == directory_id));
+		assert_eq!(bob, before);
+
+		// Or has a reserved ID, which would make the database unloadable once saved
+		let reserved = ID([0xf1; 32]);
+		let bundle = crafted_bundle(&bob, &alice, &entry_id, reserved);
+		assert!(matches!(bob.import_share(&bundle), Err(FortressError::ReservedIdViolation(id)) if id == reserved));
+		assert_eq!(bob, before);
+		bob.save_to_path(&path).unwrap();
+		Database::load_from_path(&path, "password").unwrap();
+	}
+
+	// A bundle for `recipient` with a new entry, and a copy of `sender`'s entry `entry_id` whose ID is replaced by `id`
+	fn crafted_bundle(recipient: &Database, sender: &Database, entry_id: &ID, id: ID) -> Vec<u8> {
+		let mut bundle = serde_json::to_value(ShareBundle {
+			entries: vec![Entry::new(), sender.get_entry_by_id(entry_id).unwrap().clone()],
+		})
+		.unwrap();
+		bundle["entries"][1]["id"] = serde_json::to_value(id).unwrap();
+		fortresscrypto::encrypt_share(&recipient.get_share_public_key(), &serde_json::to_vec(&bundle).unwrap())
+	}
+}
@@ -0,0 +1,295 @@
+// Tamper evidence.  `Database::canonical_digest` hashes the database's objects in a form that only depends on what's in them:
+// each object's deterministic serialization, in ID order.  Local state (sync URL, keys, caches) isn't included, so two
+// copies of a database hold the same entries, directories and settings exactly when their digests match, e.g. after a sync.
+//
+// The digest can be signed with an Ed25519 key derived from the network keys (see fortresscrypto's signing module), so every
+// device with the same username and password signs with the same key.  With `set_sign_saves` turned on, every save writes the
+// signed digest next to the database file (`path` with a `.sig` suffix), and backups keep theirs (see the backups module), so a
+// copy restored from somewhere untrusted can be checked with `verify_save_signature`.
+//
+// Digest: SHA-512 ("fortress-canonical-digest1\0" || for each object: le64(length) || serialized object), truncated to 256 bits
+// Signed message: "fortress-digest-signature1\0" || digest
+use crate::{Database, FortressError};
+use fortresscrypto::{Signature, VerifyingKey};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+
+new_type! {
+	public CanonicalDigest(32);
+}
+
+
+pub const SIGNATURE_FORMAT: &str = "fortress-signature";
+pub const SIGNATURE_VERSION: u32 = 1;
+
+const DIGEST_HEADER: &[u8] = b"fortress-canonical-digest1\0";
+const SIGNATURE_HEADER: &[u8] = b"fortress-digest-signature1\0";
+
+
+/// A canonical digest and its signature, as returned by `Database::sign_canonical_digest` and saved next to database files.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct SignedDigest {
+	pub digest: CanonicalDigest,
+	pub public_key: VerifyingKey,
+	pub signature: Signature,
+}
+
+impl SignedDigest {
+	/// Checks that `signature` is `public_key`'s signature of `digest`.  Fails with CryptoError::BadSignature if it isn't.  This
+	/// says nothing about whose key it is; see `Database::verify_signed_digest`.
+	pub fn verify(&self) -> Result<(), FortressError> {
+		Ok(self.public_key.verify(&signed_message(&self.digest), &self.signature)?)
+	}
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct SignatureFile {
+	format: String,
+	version: u32,
+	#[serde(flatten)]
+	signed: SignedDigest,
+}
+
+
+impl Database {
+	/// A hash of all of the database's objects that only depends on their contents (see the signature module).
+	pub fn canonical_digest(&self) -> CanonicalDigest {
+		let mut objects = self.objects.values().collect::<Vec<_>>();
+		objects.sort_unstable_by_key(|object| *object.get_id());
+
+		let mut hasher = Sha512::new();
+		hasher.update(DIGEST_HEADER);
+
+		for object in objects {
+			let serialized = fortresscrypto::Zeroizing::new(serde_json::to_vec(object).expect("internal error"));
+			hasher.update((serialized.len() as u64).to_le_bytes());
+			hasher.update(&serialized[..]);
+		}
+
+		CanonicalDigest::from_slice(&hasher.finalize()[..32]).expect("internal error")
+	}
+
+	/// The key signed digests of this database are checked against.  The same for every database with this username and
+	/// password.  Blocks until the sync keys are derived if they're still pending.
+	pub fn get_signing_public_key(&self) -> VerifyingKey {
+		self.signing_key().verifying_key()
+	}
+
+	/// Signs `canonical_digest`.  Blocks until the sync keys are derived if they're still pending.
+	pub fn sign_canonical_digest(&self) -> SignedDigest {
+		let digest = self.canonical_digest();
+		let signature = self.signing_key().sign(&signed_message(&digest));
+
+		SignedDigest {
+			digest,
+			public_key: self.get_signing_public_key(),
+			signature,
+		}
+	}
+
+	/// Checks that `signed` was signed with this database's key (so by someone with its username and password), and that its
+	/// digest is this database's.  Fails with FortressError::SignatureMismatch if it was signed with another key or is of
+	/// different contents, or CryptoError::BadSignature if the signature itself is bad.
+	pub fn verify_signed_digest(&self, signed: &SignedDigest) -> Result<(), FortressError> {
+		if signed.public_key != self.get_signing_public_key() {
+			return Err(FortressError::SignatureMismatch);
+		}
+
+		signed.verify()?;
+
+		if signed.digest != self.canonical_digest() {
+			return Err(FortressError::SignatureMismatch);
+		}
+
+		Ok(())
+	}
+
+	/// Turns signing saves on or off (it's off by default).  While it's on, every save also writes the signed canonical digest to
+	/// `path` with a `.sig` suffix.  Saves then wait for the sync keys if they're still being derived.  Local to this device.
+	pub fn set_sign_saves(&mut self, enabled: bool) {
+		self.sign_saves = enabled;
+	}
+
+	pub fn signs_saves(&self) -> bool {
+		self.sign_saves
+	}
+
+	/// Reads the signed digest saved next to the database file at `path`.
+	pub fn read_save_signature<P: AsRef<Path>>(path: P) -> Result<SignedDigest, FortressError> {
+		let file: SignatureFile = serde_json::from_slice(&fs::read(signature_path(path.as_ref()))?)?;
+
+		if file.format != SIGNATURE_FORMAT || file.version != SIGNATURE_VERSION {
+			return Err(FortressError::SignatureMismatch);
+		}
+
+		Ok(file.signed)
+	}
+
+	/// Checks this database (e.g. just loaded from `path`) against the signed digest saved next to `path`, like
+	/// `verify_signed_digest`.
+	pub fn verify_save_signature<P: AsRef<Path>>(&self, path: P) -> Result<(), FortressError> {
+		self.verify_signed_digest(&Database::read_save_signature(path)?)
+	}
+
+	fn signing_key(&self) -> fortresscrypto::SigningKey {
+		self.sync_parameters.get_network_key_suite().expect("internal error").signing_key()
+	}
+}
+
+
+/// Writes the signed digest of `database` next to `path`, if it signs its saves.
+pub(crate) fn write_save_signature(database: &Database, path: &Path) -> Result<(), FortressError> {
+	if !database.sign_saves {
+		return Ok(());
+	}
+
+	let file = SignatureFile {
+		format: SIGNATURE_FORMAT.to_string(),
+		version: SIGNATURE_VERSION,
+		signed: database.sign_canonical_digest(),
+	};
+
+	crate::persist_atomically(signature_path(path), |writer| serde_json::to_writer_pretty(writer, &file).map_err(Into::into))
+}
+
+
+fn signed_message(digest: &CanonicalDigest) -> Vec<u8> {
+	[SIGNATURE_HEADER, &digest[..]].concat()
+}
+
+
+// `path` with a `.sig` suffix
+fn signature_path(path: &Path) -> PathBuf {
+	let mut signature_path = path.as_os_str().to_owned();
+	signature_path.push(".sig");
+	PathBuf::from(signature_path)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{signature_path, SignatureFile, SIGNATURE_FORMAT, SIGNATURE_VERSION};
+	use crate::{BackupPolicy, Database, Entry, EntryHistory, FortressError};
+	use fortresscrypto::CryptoError;
+	use std::{collections::HashMap, fs, io::Cursor};
+	use tempfile::tempdir;
+
+	fn new_entry(title: &str) -> Entry {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(HashMap::from([("title".to_string(), title.to_string())])));
+		entry
+	}
+
+	#[test]
+	fn canonical_digest() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		db.add_entry(new_entry("First"));
+		let digest = db.canonical_digest();
+
+		// Only depends on the objects, however they were loaded
+		db.set_sync_url(Some(url::Url::parse("https://example.com").unwrap()));
+		db.set_derivation_cache_enabled(false);
+		assert_eq!(db.canonical_digest(), digest);
+		db.save_to_path(&path).unwrap();
+		let data = fs::read(&path).unwrap();
+		assert_eq!(
+			Database::load_from_reader("password", &mut Cursor::new(&data)).unwrap().canonical_digest(),
+			digest
+		);
+		assert_eq!(
+			Database::load_from_reader_lazily("password", &mut Cursor::new(&data))
+				.unwrap()
+				.canonical_digest(),
+			digest
+		);
+
+		db.add_entry(new_entry("Second"));
+		assert_ne!(db.canonical_digest(), digest);
+	}
+
+	#[test]
+	fn signed_digests() {
+		let mut db = Database::new_with_password("username", "password");
+		db.add_entry(new_entry("First"));
+		let signed = db.sign_canonical_digest();
+		signed.verify().unwrap();
+		db.verify_signed_digest(&signed).unwrap();
+
+		// Same key for the same username and password, but not for anyone else
+		let other = Database::new_with_password("username", "password");
+		assert_eq!(other.get_signing_public_key(), db.get_signing_public_key());
+		let stranger = Database::new_with_password("username", "password2");
+		assert!(matches!(stranger.verify_signed_digest(&signed), Err(FortressError::SignatureMismatch)));
+
+		// Different contents
+		assert!(matches!(other.verify_signed_digest(&signed), Err(FortressError::SignatureMismatch)));
+		db.add_entry(new_entry("Second"));
+		assert!(matches!(db.verify_signed_digest(&signed), Err(FortressError::SignatureMismatch)));
+
+		let mut forged = db.sign_canonical_digest();
+		forged.signature = signed.signature;
+		assert!(matches!(
+			db.verify_signed_digest(&forged),
+			Err(FortressError::CryptoError(CryptoError::BadSignature))
+		));
+	}
+
+	#[test]
+	fn signed_saves() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		// Large enough that a small edit is journaled rather than saved in full
+		db.add_entries((0..100).map(|i| new_entry(&format!("Filler {i}"))));
+
+		// Off by default
+		db.save_to_path(&path).unwrap();
+		assert!(!signature_path(&path).exists());
+
+		db.set_sign_saves(true);
+		db.add_entry(new_entry("First"));
+		db.save_to_path(&path).unwrap();
+		let loaded = Database::load_from_path(&path, "password").unwrap();
+		assert!(loaded.signs_saves());
+		loaded.verify_save_signature(&path).unwrap();
+
+		// Journaled saves are signed too
+		db.save_incremental(&path).unwrap();
+		db.add_entry(new_entry("Second"));
+		db.save_incremental(&path).unwrap();
+		assert!(tmp_dir.path().join("test.fortressdb.journal").exists());
+		assert_eq!(Database::read_save_signature(&path).unwrap().digest, db.canonical_digest());
+		db.verify_save_signature(&path).unwrap();
+
+		// Backups keep theirs
+		db.set_backup_policy(BackupPolicy::new(1));
+		db.save_to_path(&path).unwrap();
+		let backup = &Database::list_backups(&path)[0];
+		Database::load_from_path(backup, "password").unwrap().verify_save_signature(backup).unwrap();
+
+		// Edited since, or tampered with
+		let mut signed = Database::read_save_signature(&path).unwrap();
+		db.add_entry(new_entry("Unsaved"));
+		assert!(matches!(db.verify_save_signature(&path), Err(FortressError::SignatureMismatch)));
+		signed.digest.0[0] ^= 1;
+		let file = SignatureFile {
+			format: SIGNATURE_FORMAT.to_string(),
+			version: SIGNATURE_VERSION,
+			signed,
+		};
+		fs::write(signature_path(&path), serde_json::to_vec(&file).unwrap()).unwrap();
+		assert!(matches!(
+			Database::read_save_signature(&path).unwrap().verify(),
+			Err(FortressError::CryptoError(CryptoError::BadSignature))
+		));
+	}
+}
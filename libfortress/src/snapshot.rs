@@ -0,0 +1,126 @@
+// Read-only views of the whole database as it was at some point in the past, e.g. to see what an entry or directory looked like
+// before a bad sync or edit.  Every entry and directory keeps its full history, so their past states can be rebuilt exactly:
+// entries' fields, metadata, and tags, and directories' names and children (and so where everything was).
+//
+// Only history that's still there can be replayed.  History before a compaction cutoff (see the compaction module) was
+// squashed, so snapshots from before the cutoff are missing whatever the squashed edits did.  Settings don't keep history, so
+// aren't part of snapshots.
+use crate::{database_object::DatabaseObject, Database, Directory, Entry, ID, ROOT_DIRECTORY_ID};
+use std::collections::HashMap;
+
+
+/// The database's entries and directories as they were at a point in time.  See `Database::snapshot_at`.
+#[derive(Debug, Clone)]
+pub struct DatabaseSnapshot {
+	time: u64,
+	objects: HashMap<ID, DatabaseObject>,
+}
+
+
+impl Database {
+	/// Reconstructs the database's entries and directories as they were at `time` (a Unix timestamp in nanoseconds, inclusive).
+	/// Objects that didn't exist yet are left out.  Double-locked entries have the fields their lock holds redacted throughout
+	/// (see `Entry::redact_double_locked`).
+	pub fn snapshot_at(&self, time: u64) -> DatabaseSnapshot {
+		let objects = self
+			.objects
+			.values()
+			.filter_map(|object| match object {
+				DatabaseObject::Entry(entry) if entry.is_double_locked() => entry.redact_double_locked().as_of(time).map(DatabaseObject::Entry),
+				object => object.as_of(time),
+			})
+			.map(|object| (*object.get_id(), object))
+			.collect();
+
+		DatabaseSnapshot { time, objects }
+	}
+}
+
+
+impl DatabaseSnapshot {
+	pub fn get_time(&self) -> u64 {
+		self.time
+	}
+
+	pub fn get_root(&self) -> &Directory {
+		self.get_directory_by_id(&ROOT_DIRECTORY_ID).expect("Internal error")
+	}
+
+	pub fn get_entry_by_id(&self, id: &ID) -> Option<&Entry> {
+		self.objects.get(id)?.as_entry()
+	}
+
+	pub fn get_directory_by_id(&self, id: &ID) -> Option<&Directory> {
+		self.objects.get(id)?.as_directory()
+	}
+
+	pub fn list_entries(&self) -> impl Iterator<Item = &Entry> {
+		self.objects.values().filter_map(|object| object.as_entry())
+	}
+
+	pub fn list_directories(&self) -> impl Iterator<Item = &Directory> {
+		self.objects.values().filter_map(|object| object.as_directory())
+	}
+
+	/// The directory `id` was in at the time, if any.
+	pub fn get_parent_directory(&self, id: &ID) -> Option<&Directory> {
+		self.list_directories().find(|directory| directory.contains(id))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{testing::fields, Database, Directory, Entry, EntryHistory, ROOT_DIRECTORY_ID};
+
+	#[test]
+	fn snapshot_at() {
+		let mut db = Database::new_with_password("username", "password");
+		let before = db.get_root().get_history().last().map_or(0, |item| item.time);
+
+		let mut directory = Directory::new();
+		directory.rename("Work");
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[("title", "Mail"), ("password", "first")])));
+		let entry_id = *entry.get_id();
+		db.add_entry(entry);
+		let created = db.get_root().get_history().last().unwrap().time;
+
+		let entry = db.get_entry_by_id_mut(&entry_id).unwrap();
+		entry.edit(entry.new_edit(fields(&[("password", "second")])));
+		entry.add_tag("old");
+		db.get_directory_by_id_mut(&directory_id).unwrap().rename("Personal");
+		db.move_object(&entry_id, &directory_id);
+
+		// Nothing existed yet
+		let snapshot = db.snapshot_at(before);
+		assert!(snapshot.get_entry_by_id(&entry_id).is_none());
+		assert!(snapshot.get_directory_by_id(&directory_id).is_none());
+		assert_eq!(*snapshot.get_root().get_id(), ROOT_DIRECTORY_ID);
+
+		let snapshot = db.snapshot_at(created);
+		assert_eq!(snapshot.get_time(), created);
+		let old_entry = snapshot.get_entry_by_id(&entry_id).unwrap();
+		assert_eq!(old_entry["password"], "first");
+		assert!(old_entry.get_tags().is_empty());
+		assert_eq!(snapshot.get_directory_by_id(&directory_id).unwrap().get_name(), Some("Work"));
+		assert_eq!(
+			snapshot.get_parent_directory(&entry_id).map(|directory| *directory.get_id()),
+			Some(ROOT_DIRECTORY_ID)
+		);
+		assert_eq!(snapshot.list_entries().count(), 1);
+		assert_eq!(snapshot.list_directories().count(), 2);
+
+		// The present is the same as the current database
+		let snapshot = db.snapshot_at(u64::MAX - 1);
+		assert_eq!(snapshot.get_entry_by_id(&entry_id), db.get_entry_by_id(&entry_id));
+		assert_eq!(snapshot.get_directory_by_id(&directory_id), db.get_directory_by_id(&directory_id));
+		assert_eq!(
+			snapshot.get_parent_directory(&entry_id).map(|directory| *directory.get_id()),
+			Some(directory_id)
+		);
+	}
+}
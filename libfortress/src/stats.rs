@@ -0,0 +1,111 @@
+// Summary numbers about a database, for showing in a frontend's settings screen and for keeping an eye on how large databases
+// get.  Everything is counted from the objects in memory, so it's quick enough to compute whenever it's shown.
+use crate::{unix_timestamp, Database, ID};
+use serde::Serialize;
+use std::collections::HashMap;
+
+
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct DatabaseStats {
+	/// All entries, including those in the trash.
+	pub entries: usize,
+	/// All directories, including the root directory and the trash.
+	pub directories: usize,
+	/// Edits in the history of all entries and directories.
+	pub history_records: usize,
+	/// Timestamp of the oldest edit to any entry or directory (nanoseconds).  None if there are no edits.
+	pub oldest_edit: Option<u64>,
+	/// Timestamp of the newest edit to any entry or directory (nanoseconds).  None if there are no edits.
+	pub newest_edit: Option<u64>,
+	/// Size of the payload that's encrypted when the database is saved (bytes).
+	pub payload_size: usize,
+	/// Number of entries directly in each directory (not in its subdirectories), by directory ID.
+	pub entries_per_directory: HashMap<ID, usize>,
+	/// How long ago, on average, the `password` field of entries that have one was last changed (nanoseconds).  None if no entry
+	/// has a password.
+	pub average_password_age: Option<u64>,
+}
+
+
+impl Database {
+	/// Counts of what's in the database.  See DatabaseStats.
+	pub fn stats(&self) -> DatabaseStats {
+		let now = unix_timestamp();
+		let mut stats = DatabaseStats {
+			payload_size: serde_json::to_vec(self).expect("internal error").len(),
+			..DatabaseStats::default()
+		};
+		let mut edit_times = Vec::new();
+		let mut password_ages = Vec::new();
+
+		for entry in self.list_entries() {
+			stats.entries += 1;
+			edit_times.extend(entry.get_history().iter().map(|item| item.time));
+
+			if entry.get("password").is_some_and(|password| !password.is_empty()) {
+				let changed = entry.get_history().iter().rev().find(|item| item.data.contains_key("password"));
+				password_ages.extend(changed.map(|item| now.saturating_sub(item.time)));
+			}
+		}
+
+		for directory in self.list_directories() {
+			stats.directories += 1;
+			edit_times.extend(directory.get_history().iter().map(|item| item.time));
+			stats.entries_per_directory.insert(*directory.get_id(), directory.list_entries(self).len());
+		}
+
+		stats.history_records = edit_times.len();
+		stats.oldest_edit = edit_times.iter().min().copied();
+		stats.newest_edit = edit_times.iter().max().copied();
+		if !password_ages.is_empty() {
+			let total: u128 = password_ages.iter().map(|age| *age as u128).sum();
+			stats.average_password_age = Some((total / password_ages.len() as u128) as u64);
+		}
+
+		stats
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{testing::fields, Database, Directory, Entry, EntryHistory};
+
+	#[test]
+	fn stats() {
+		let mut db = Database::new_with_password("username", "password");
+		let empty = db.stats();
+		assert_eq!(empty.entries, 0);
+		assert_eq!(empty.directories, 1);
+		assert_eq!(empty.history_records, 0);
+		assert_eq!(empty.oldest_edit, None);
+		assert_eq!(empty.average_password_age, None);
+		assert_eq!(empty.entries_per_directory[db.get_root().get_id()], 0);
+
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[("title", "Bank"), ("password", "hunter2")])));
+		entry.edit(entry.new_edit(fields(&[("username", "me")])));
+		let first_edit = entry.get_history()[0].time;
+		db.add_entry(entry);
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(fields(&[("title", "No password")])));
+		db.add_entry(entry);
+		let directory = Directory::new();
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+
+		let stats = db.stats();
+		assert_eq!(stats.entries, 2);
+		assert_eq!(stats.directories, 2);
+		assert_eq!(stats.entries_per_directory[db.get_root().get_id()], 2);
+		assert_eq!(stats.entries_per_directory[&directory_id], 0);
+		// Three entry edits, and adding the two entries and the directory to the root directory
+		assert_eq!(stats.history_records, 6);
+		assert_eq!(stats.oldest_edit, Some(first_edit));
+		assert!(stats.newest_edit.unwrap() > first_edit);
+		assert!(stats.payload_size > empty.payload_size);
+		// Only the entry with a password counts, from when its password was set rather than its latest edit
+		let age = stats.average_password_age.unwrap();
+		assert!(age > 0 && age <= super::unix_timestamp() - first_edit);
+	}
+}
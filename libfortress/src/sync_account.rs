@@ -0,0 +1,117 @@
+// Managing the account on the sync server, so frontends don't have to assume it already exists.  All of these use the `/user`
+// endpoint, authenticated with the login ID and login key like every other request:
+//
+// * `POST /user` creates an account for our credentials.  The server answers 409 Conflict if the login ID already has one.
+// * `GET /user` answers with the account's usage as JSON, such as
+//   `{"object_count": 12, "storage_used": 34567, "max_objects": 1000, "max_storage": 10485760}`.  The limits are left out if
+//   the server doesn't have any.
+// * `DELETE /user` deletes the account and every object in it.
+//
+// Servers without these endpoints answer 404 or 405, reported as FortressError::SyncAccountManagementUnsupported.  Credentials
+// the server doesn't know are reported as FortressError::SyncCredentialsRejected rather than an HTTP error, since that's how
+// a deleted or never registered account looks.
+use crate::{api_request, sync_endpoint, ApiError, Database, FortressError};
+use reqwest::{blocking::Response, Method};
+use serde::{Deserialize, Serialize};
+
+
+/// Passed to `Database::sync_delete_account` to confirm that deleting the account, and everything on the server, is intended.
+#[derive(Copy, Clone, Debug)]
+pub struct AccountDeletionConfirmed;
+
+
+/// Returned by `Database::sync_account_info`.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct SyncAccountInfo {
+	/// Number of objects stored on the server.
+	pub object_count: u64,
+	/// Bytes stored on the server, counting objects as uploaded.
+	pub storage_used: u64,
+	/// Most objects the account can hold, if the server limits it.
+	#[serde(default)]
+	pub max_objects: Option<u64>,
+	/// Most bytes the account can store, if the server limits it.
+	#[serde(default)]
+	pub max_storage: Option<u64>,
+}
+
+
+impl Database {
+	/// Creates an account for this database's username and password on its sync server.  Fails with
+	/// FortressError::SyncAccountExists if there already is one, or FortressError::SyncBadUrl if there's no sync URL.
+	pub fn sync_register_account(&self) -> Result<(), FortressError> {
+		let url = self.sync_url.as_ref().ok_or(FortressError::SyncBadUrl)?;
+
+		match api_request(
+			&self.sync_client()?,
+			self.sync_parameters.get_login_id(),
+			self.sync_parameters.get_login_key().expect("internal error"),
+			Method::POST,
+			sync_endpoint(url, "/user")?,
+			"",
+		) {
+			Ok(_) => Ok(()),
+			Err(FortressError::SyncApiError(ApiError::ApiError(409, _))) => Err(FortressError::SyncAccountExists),
+			Err(err) => Err(account_error(err)),
+		}
+	}
+
+	/// Deletes this database's account on its sync server, along with everything stored in it.  The database itself is left
+	/// alone, so syncing it again (after `sync_register_account`) uploads everything again.
+	/// Fails with FortressError::SyncBadUrl if there's no sync URL.
+	pub fn sync_delete_account(&mut self, _confirmation: AccountDeletionConfirmed) -> Result<(), FortressError> {
+		self.account_request(Method::DELETE)?;
+
+		// Nothing is left on the server to tell about a password change or to re-encrypt
+		self.old_sync_parameters = None;
+		self.key_rotation = None;
+
+		Ok(())
+	}
+
+	/// Asks the sync server how much this database's account is storing, and how much it's allowed to.  Fails with
+	/// FortressError::SyncBadUrl if there's no sync URL.
+	pub fn sync_account_info(&self) -> Result<SyncAccountInfo, FortressError> {
+		let response = self.account_request(Method::GET)?;
+
+		serde_json::from_slice(&response.bytes().map_err(ApiError::from)?).map_err(|_| FortressError::SyncMalformedResponse)
+	}
+
+	/// A request to `/user`.  The server may not have been told about a password change yet, in which case the old credentials
+	/// are tried too.
+	fn account_request(&self, method: Method) -> Result<Response, FortressError> {
+		let url = sync_endpoint(self.sync_url.as_ref().ok_or(FortressError::SyncBadUrl)?, "/user")?;
+		let client = self.sync_client()?;
+
+		let response = api_request(
+			&client,
+			self.sync_parameters.get_login_id(),
+			self.sync_parameters.get_login_key().expect("internal error"),
+			method.clone(),
+			url.clone(),
+			"",
+		);
+
+		match (response, &self.old_sync_parameters) {
+			(Err(FortressError::SyncApiError(ApiError::ApiError(401, _))), Some(old_sync_parameters)) => api_request(
+				&client,
+				old_sync_parameters.get_login_id(),
+				old_sync_parameters.get_login_key(),
+				method,
+				url,
+				"",
+			),
+			(response, _) => response,
+		}
+		.map_err(account_error)
+	}
+}
+
+
+fn account_error(err: FortressError) -> FortressError {
+	match err {
+		FortressError::SyncApiError(ApiError::ApiError(401, _)) => FortressError::SyncCredentialsRejected,
+		FortressError::SyncApiError(ApiError::ApiError(404 | 405, _)) => FortressError::SyncAccountManagementUnsupported,
+		err => err,
+	}
+}
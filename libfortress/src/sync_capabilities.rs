@@ -0,0 +1,115 @@
+// Limits the sync server imposes, fetched from GET /capabilities at the start of every sync.
+// Servers that don't have that endpoint (it answers 404) impose no limits we know of.
+//
+// Objects are checked against the limits before they're uploaded, so an oversized object fails the sync with
+// FortressError::SyncObjectTooLarge, naming the entry and the field taking up the most space, instead of an HTTP error from
+// the server.  A server that rejects an upload as too large (HTTP 413) without advertising a limit gets the same error.
+use crate::{database_object::DatabaseObject, FortressError};
+use fortresscrypto::EncryptedObject;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+
+#[derive(Deserialize, Eq, PartialEq, Debug, Clone, Default)]
+pub struct SyncCapabilities {
+	/// Largest object the server accepts, in bytes as uploaded (the encrypted object and its SIV).
+	#[serde(default)]
+	pub max_object_size: Option<usize>,
+}
+
+impl SyncCapabilities {
+	/// Checks that `encrypted`, the encryption of `object`, fits within the server's limits.
+	pub(crate) fn check_object_size(&self, object: &DatabaseObject, encrypted: &EncryptedObject) -> Result<(), FortressError> {
+		let size = encrypted.ciphertext.len() + encrypted.siv.as_ref().len();
+
+		match self.max_object_size {
+			Some(max) if size > max => Err(object_too_large(object, size, Some(max))),
+			_ => Ok(()),
+		}
+	}
+}
+
+
+/// The error for an object the server won't accept because of its size.
+pub(crate) fn object_too_large(object: &DatabaseObject, size: usize, max: Option<usize>) -> FortressError {
+	let (title, field) = match object {
+		DatabaseObject::Entry(entry) => (
+			entry.get_state().get("title").cloned(),
+			largest_field(entry.get_history().iter().map(|item| &item.data)),
+		),
+		_ => (None, None),
+	};
+
+	FortressError::SyncObjectTooLarge {
+		id: *object.get_id(),
+		title,
+		field,
+		size,
+		max,
+	}
+}
+
+
+/// The field whose values, over the whole history, take up the most space
+fn largest_field<'a>(history: impl Iterator<Item = &'a HashMap<String, String>>) -> Option<String> {
+	let mut sizes = HashMap::<&str, usize>::new();
+
+	for (key, value) in history.flatten() {
+		*sizes.entry(key).or_default() += value.len();
+	}
+
+	sizes
+		.into_iter()
+		.max_by_key(|(key, size)| (*size, std::cmp::Reverse(*key)))
+		.map(|(key, _)| key.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::SyncCapabilities;
+	use crate::{database_object::DatabaseObject, Entry, EntryHistory, FortressError};
+	use fortresscrypto::NetworkKeySuite;
+
+	#[test]
+	fn check_object_size() {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(
+			[("title".to_string(), "Scans".to_string()), ("notes".to_string(), "x".repeat(1000))]
+				.into_iter()
+				.collect(),
+		));
+		entry.edit(EntryHistory::new([("password".to_string(), "y".repeat(600))].into_iter().collect()));
+		entry.edit(EntryHistory::new([("password".to_string(), "z".repeat(600))].into_iter().collect()));
+		let id = *entry.get_id();
+		let object = DatabaseObject::Entry(entry);
+		let keys = NetworkKeySuite::derive(b"username", b"password");
+		let encrypted = keys.encrypt_object(&id[..], &serde_json::to_vec(&object).unwrap());
+		let size = encrypted.ciphertext.len() + 32;
+
+		assert!(SyncCapabilities::default().check_object_size(&object, &encrypted).is_ok());
+		let capabilities = SyncCapabilities { max_object_size: Some(size) };
+		assert!(capabilities.check_object_size(&object, &encrypted).is_ok());
+
+		let capabilities = SyncCapabilities {
+			max_object_size: Some(size - 1),
+		};
+		match capabilities.check_object_size(&object, &encrypted) {
+			Err(FortressError::SyncObjectTooLarge {
+				id: error_id,
+				title,
+				field,
+				size: error_size,
+				max,
+			}) => {
+				assert_eq!(error_id, id);
+				assert_eq!(title.as_deref(), Some("Scans"));
+				// Old values count too
+				assert_eq!(field.as_deref(), Some("password"));
+				assert_eq!(error_size, size);
+				assert_eq!(max, Some(size - 1));
+			},
+			result => panic!("{result:?}"),
+		}
+	}
+}
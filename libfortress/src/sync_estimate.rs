@@ -0,0 +1,66 @@
+// Estimating how much the next sync would transfer, for users on metered connections deciding whether to sync now.  Only the
+// server's object list is fetched; each of our objects is encrypted locally to see whether the server's copy matches it (the
+// SIV is deterministic), and those that don't are counted as uploads.  So the estimate errs on the high side: an object that
+// only changed on the server isn't uploaded after merging, since the merge matches the server's copy.  Objects with changes on
+// both sides are uploaded as merged, which can be a little larger.  The sizes of objects to be downloaded aren't in the list.
+// Like sync, the estimate refuses to go on if the database was last synced with a different account (see the account_change
+// module), and doesn't count tombstones the server has already dropped (see the gc module).
+use crate::{Database, FortressError};
+use serde::Serialize;
+use std::collections::HashMap;
+
+
+/// Returned by `Database::estimate_sync_size`.
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SyncSizeEstimate {
+	/// Bytes the next sync would upload, counting the objects below as they are now.
+	pub upload_bytes: u64,
+	/// Number of objects that are new or differ from the server's copies.
+	pub uploads: usize,
+	/// Number of objects on the server that we don't have or that differ from ours, which would be downloaded.
+	pub downloads: usize,
+	/// Bytes fetching the object list took.
+	pub listing_bytes: u64,
+}
+
+
+impl Database {
+	/// Predicts what the next sync would transfer, fetching only the server's object list (see the sync_estimate module).
+	/// Fails like `sync_preview` does, including with FortressError::SyncAccountChanged.
+	pub fn estimate_sync_size(&self) -> Result<SyncSizeEstimate, FortressError> {
+		let url = self.sync_url.as_ref().ok_or(FortressError::SyncBadUrl)?;
+		let client = self.sync_client()?;
+		let server_objects = self.sync_api_list_objects(&client, url)?.into_iter().collect::<HashMap<_, _>>();
+		self.check_account(server_objects.is_empty())?;
+		let mut estimate = SyncSizeEstimate {
+			listing_bytes: client.take_traffic().received,
+			downloads: server_objects.keys().filter(|id| self.objects.get(id).is_none()).count(),
+			..SyncSizeEstimate::default()
+		};
+
+		let local_only = self.list_local_only_objects();
+		let gc_horizon = self.get_gc_horizon();
+
+		for (id, object) in &self.objects {
+			let encrypted_object = self.encrypt_object(object);
+
+			match server_objects.get(id) {
+				Some(server_siv) if *server_siv == encrypted_object.siv => continue,
+				Some(_) => estimate.downloads += 1,
+				// The server dropped it already, so sync drops ours instead of uploading it
+				None if object.as_tombstone().is_some_and(|tombstone| tombstone.get_time() < gc_horizon) => continue,
+				None => (),
+			}
+
+			// Downloaded and merged, but not uploaded (see the selective_sync module)
+			if local_only.contains(id) {
+				continue;
+			}
+
+			estimate.uploads += 1;
+			estimate.upload_bytes += (encrypted_object.ciphertext.len() + encrypted_object.siv.as_ref().len()) as u64;
+		}
+
+		Ok(estimate)
+	}
+}
@@ -0,0 +1,248 @@
+// Retrying requests to the sync server, so a sync doesn't fail because of a server that's briefly overloaded or a network
+// blip.  Requests that fail in a way that might not happen again (a connection or timeout error, 429 Too Many Requests, or
+// 500, 502, 503 or 504) are retried up to `SyncOptions::max_retries` times.  Between attempts the client waits as long as the
+// server's Retry-After header asks, or otherwise backs off exponentially from `initial_retry_delay` up to `max_retry_delay`,
+// waiting a random amount between half and all of the backoff so that clients turned away together don't all come back
+// together.  A Retry-After longer than `max_retry_delay` isn't waited out; the request fails instead.
+//
+// Uploads name the SIV of the copy they replace, so retrying one that did reach the server can't overwrite anything; the
+// server answers 409 Conflict, since it already has our copy.  Sync treats that like any other conflict and goes round again,
+// finding the server's copy matches ours.
+//
+// `SyncOptions::timeout` bounds the whole sync, retries included, and a sync that runs past it fails with
+// FortressError::SyncTimedOut.
+//
+// For privacy, requests can go through a SOCKS5 proxy such as Tor's (`Database::set_sync_proxy`).  It applies to everything
+// that talks to the sync server, not just sync, and host names are always resolved by the proxy so they don't leak through
+// local DNS lookups.
+use crate::{cancellation, sync_report::SyncTraffic, ApiError, CancellationToken, Database, FortressError, ID};
+use fortresscrypto::{LoginId, LoginKey};
+use rand::{rngs::OsRng, Rng};
+use reqwest::{
+	blocking::{Client, Response},
+	header::RETRY_AFTER,
+	IntoUrl, Method, Proxy,
+};
+use std::{
+	cell::RefCell,
+	time::{Duration, Instant, SystemTime},
+};
+use url::Url;
+
+
+/// Options for `Database::sync_with_options`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyncOptions {
+	/// How many times a request that failed with a transient error is retried (see the sync_options module).
+	pub max_retries: u32,
+	/// How long to wait before the first retry, if the server doesn't say.  Doubles with each retry.
+	pub initial_retry_delay: Duration,
+	/// Longest wait between retries.
+	pub max_retry_delay: Duration,
+	/// How long the whole sync may take, if it's limited.
+	pub timeout: Option<Duration>,
+}
+
+impl Default for SyncOptions {
+	fn default() -> SyncOptions {
+		SyncOptions {
+			max_retries: 3,
+			initial_retry_delay: Duration::from_millis(250),
+			max_retry_delay: Duration::from_secs(30),
+			timeout: None,
+		}
+	}
+}
+
+
+/// The HTTP client requests to the sync server are made with, along with the retry policy and deadline of the operation making
+/// them.
+pub(crate) struct SyncClient {
+	client: Client,
+	options: SyncOptions,
+	deadline: Option<Instant>,
+	cancel: CancellationToken,
+	traffic: RefCell<SyncTraffic>,
+}
+
+impl SyncClient {
+	/// A client for an operation starting now, connecting through `proxy` if given.  Waits between retries stop with
+	/// FortressError::Cancelled if `cancel` is cancelled.
+	pub(crate) fn new(options: &SyncOptions, proxy: Option<&Url>, cancel: &CancellationToken) -> Result<SyncClient, FortressError> {
+		// Force SSL on release builds
+		let mut builder = Client::builder().https_only(!cfg!(debug_assertions));
+
+		if let Some(proxy) = proxy {
+			builder = builder.proxy(Proxy::all(proxy_url(proxy)?).map_err(|_| FortressError::SyncBadProxy)?);
+		}
+
+		Ok(SyncClient {
+			client: builder.build().expect("Failed to build HTTP client"),
+			options: options.clone(),
+			deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+			cancel: cancel.clone(),
+			traffic: RefCell::default(),
+		})
+	}
+
+	/// Counts `size` bytes of a response body read by the caller, which was object `id` if it's given.
+	pub(crate) fn record_download(&self, id: Option<&ID>, size: usize) {
+		let mut traffic = self.traffic.borrow_mut();
+		traffic.received += size as u64;
+
+		if let Some(id) = id {
+			*traffic.downloaded.entry(*id).or_default() += size as u64;
+		}
+	}
+
+	/// Notes that object `id` was uploaded as `size` bytes.  The bytes themselves are counted by `api_request`.
+	pub(crate) fn record_upload(&self, id: &ID, size: usize) {
+		*self.traffic.borrow_mut().uploaded.entry(*id).or_default() += size as u64;
+	}
+
+	/// Everything sent and received with this client so far.
+	pub(crate) fn take_traffic(&self) -> SyncTraffic {
+		self.traffic.take()
+	}
+
+	/// Time left before the deadline, or FortressError::SyncTimedOut if it has passed.  None if there's no deadline.
+	fn remaining(&self) -> Result<Option<Duration>, FortressError> {
+		match self.deadline {
+			Some(deadline) if Instant::now() >= deadline => Err(FortressError::SyncTimedOut),
+			Some(deadline) => Ok(Some(deadline - Instant::now())),
+			None => Ok(None),
+		}
+	}
+
+	/// How long to wait before retry number `retry` (counting from 0), if the server didn't say.
+	fn backoff(&self, retry: u32) -> Duration {
+		let backoff = self
+			.options
+			.initial_retry_delay
+			.saturating_mul(2u32.saturating_pow(retry))
+			.min(self.options.max_retry_delay);
+
+		OsRng.gen_range(backoff / 2..=backoff)
+	}
+}
+
+
+impl Database {
+	pub fn get_sync_proxy(&self) -> Option<&Url> {
+		self.sync_proxy.as_ref()
+	}
+
+	/// Makes sync, and everything else that talks to the sync server, connect through the SOCKS5 proxy at `proxy` (e.g.
+	/// `socks5://127.0.0.1:9050` for Tor), or directly if it's None.  Host names are resolved by the proxy, so they aren't
+	/// looked up locally either.  Fails with FortressError::SyncBadProxy, leaving the setting alone, unless it's a `socks5` or
+	/// `socks5h` URL with a host and port.  Local to this device.
+	pub fn set_sync_proxy(&mut self, proxy: Option<Url>) -> Result<(), FortressError> {
+		if let Some(proxy) = &proxy {
+			proxy_url(proxy)?;
+		}
+
+		self.sync_proxy = proxy;
+		Ok(())
+	}
+
+	/// A client with the default options, for requests made outside of a sync.
+	pub(crate) fn sync_client(&self) -> Result<SyncClient, FortressError> {
+		SyncClient::new(&SyncOptions::default(), self.sync_proxy.as_ref(), &CancellationToken::new())
+	}
+}
+
+
+/// The URL reqwest should use for `proxy`.  reqwest resolves host names locally for `socks5` URLs, so those are switched to
+/// `socks5h`, which has the proxy resolve them.
+pub(crate) fn proxy_url(proxy: &Url) -> Result<Url, FortressError> {
+	if !matches!(proxy.scheme(), "socks5" | "socks5h") || proxy.host().is_none() || proxy.port().is_none() {
+		return Err(FortressError::SyncBadProxy);
+	}
+
+	let mut url = proxy.clone();
+	url.set_scheme("socks5h").map_err(|_| FortressError::SyncBadProxy)?;
+	Ok(url)
+}
+
+
+/// Makes a request to the sync server, retrying transient failures (see the sync_options module).  Responses other than 2xx
+/// are returned as ApiError::ApiError.
+pub(crate) fn api_request<U, B>(
+	client: &SyncClient,
+	login_id: &LoginId,
+	login_key: &LoginKey,
+	method: Method,
+	url: U,
+	body: B,
+) -> Result<Response, FortressError>
+where
+	U: IntoUrl,
+	B: AsRef<[u8]>,
+{
+	let url = url.into_url().map_err(ApiError::from)?;
+	let auth_token = login_id.to_hex() + login_key.to_hex().as_str();
+	let mut retry = 0;
+
+	loop {
+		let mut request = client
+			.client
+			.request(method.clone(), url.clone())
+			.bearer_auth(&auth_token)
+			.body(body.as_ref().to_vec());
+		if let Some(remaining) = client.remaining()? {
+			request = request.timeout(remaining);
+		}
+		client.traffic.borrow_mut().sent += body.as_ref().len() as u64;
+
+		let (error, retry_after) = match request.send() {
+			Ok(response) if response.status().is_success() => return Ok(response),
+			Ok(response) => {
+				let retry_after = retry_after(&response);
+				let status = response.status();
+				let text = response.text().map_err(ApiError::from)?;
+				client.record_download(None, text.len());
+				(ApiError::ApiError(status.into(), text), retry_after)
+			},
+			Err(err) => {
+				// The timeout might have been the deadline
+				client.remaining()?;
+				(ApiError::from(err), None)
+			},
+		};
+
+		if retry >= client.options.max_retries || !is_transient(&error) {
+			return Err(error.into());
+		}
+
+		let delay = match retry_after {
+			Some(delay) if delay > client.options.max_retry_delay => return Err(error.into()),
+			Some(delay) => delay,
+			None => client.backoff(retry),
+		};
+		if client.remaining()?.is_some_and(|remaining| delay >= remaining) {
+			return Err(FortressError::SyncTimedOut);
+		}
+
+		cancellation::sleep_cancellable(&client.cancel, delay)?;
+		retry += 1;
+	}
+}
+
+
+fn is_transient(error: &ApiError) -> bool {
+	match error {
+		ApiError::ApiError(status, _) => matches!(status, 429 | 500 | 502 | 503 | 504),
+		ApiError::ReqwestError(err) => err.is_connect() || err.is_timeout(),
+	}
+}
+
+
+/// How long the server's Retry-After header asks us to wait.  It's either a number of seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+	let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+	match value.parse::<u64>() {
+		Ok(seconds) => Some(Duration::from_secs(seconds)),
+		Err(_) => Some(httpdate::parse_http_date(value).ok()?.duration_since(SystemTime::now()).unwrap_or_default()),
+	}
+}
@@ -1,18 +1,27 @@
-use fortresscrypto::{LoginId, LoginKey, NetworkKeySuite};
-use serde::{Deserialize, Serialize};
+use crate::{cancellation, progress, CancellationToken, DeriveProgress, FortressError};
+use fortresscrypto::{LoginId, LoginKey, NetworkKeySuite, Protected, Zeroizing};
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
+use std::{
+	fmt,
+	sync::{Arc, Condvar, Mutex, OnceLock},
+	thread,
+};
 
 
 // Encapsulate username, NetworkKeySuite, and all cached derivative data
 // to enforce invariants on them.
-#[derive(Serialize, Eq, PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct SyncParameters {
 	username: String,
 
 	// NetworkKeySuite is saved to the database's on-disk serialization since it is very expensive to calculate.
 	network_key_suite: Option<NetworkKeySuite>,
+	// Set instead of network_key_suite while the keys are derived in the background (see `derive_async`)
+	pending: Option<DerivationHandle>,
+	// The login key is the read-only one (see the readonly_sync module), so the server won't accept uploads
+	read_only: bool,
 
 	// Cache
-	#[serde(skip_serializing, skip_deserializing)]
 	login_id: LoginId, // Hashed username sent to server for authentication
 }
 
@@ -26,20 +35,118 @@ impl SyncParameters {
 		SyncParameters {
 			username: username.to_string(),
 			network_key_suite,
+			pending: None,
+			read_only: false,
 			login_id: fortresscrypto::hash_username_for_login(username.as_bytes()),
 		}
 	}
 
+	/// Sync parameters with keys derived earlier (see the derivation_cache module).
+	pub(crate) fn with_network_key_suite(username: &str, network_key_suite: NetworkKeySuite) -> SyncParameters {
+		SyncParameters {
+			username: username.to_string(),
+			network_key_suite: Some(network_key_suite),
+			pending: None,
+			read_only: false,
+			login_id: fortresscrypto::hash_username_for_login(username.as_bytes()),
+		}
+	}
+
+	/// Starts deriving sync parameters on a worker thread, returning immediately.  See `pending` to use them before the
+	/// derivation finishes.
+	pub fn derive_async<U: AsRef<str>, P: AsRef<str>>(username: U, password: P) -> DerivationHandle {
+		let username = username.as_ref().to_string();
+		let password = Zeroizing::new(password.as_ref().to_string());
+		let derivation = Arc::new(Derivation::default());
+
+		let worker_username = username.clone();
+		let worker_derivation = derivation.clone();
+		thread::spawn(move || {
+			let network_key_suite = NetworkKeySuite::derive(worker_username.as_bytes(), password.as_bytes());
+			worker_derivation.finish(network_key_suite);
+		});
+
+		DerivationHandle { username, derivation }
+	}
+
+	/// Sync parameters whose keys are still being derived by `handle`.  Everything that needs the keys blocks until they're
+	/// done; use `is_pending` to check first.
+	pub fn pending(handle: DerivationHandle) -> SyncParameters {
+		SyncParameters {
+			login_id: fortresscrypto::hash_username_for_login(handle.username.as_bytes()),
+			username: handle.username.clone(),
+			network_key_suite: None,
+			pending: Some(handle),
+			read_only: false,
+		}
+	}
+
+	/// True while the keys are still being derived in the background.
+	pub fn is_pending(&self) -> bool {
+		self.network_key_suite.is_none() && self.pending.as_ref().is_some_and(|handle| !handle.is_finished())
+	}
+
+	/// Waits for pending keys, returning FortressError::Cancelled as soon as `cancel` is cancelled.
+	pub(crate) fn wait_cancellable(&self, cancel: &CancellationToken) -> Result<(), FortressError> {
+		match self.pending.as_ref().filter(|_| self.is_pending()) {
+			Some(handle) => {
+				let handle = handle.clone();
+				cancellation::run_cancellable(cancel, move || {
+					handle.wait_for_keys();
+				})
+			},
+			None => Ok(()),
+		}
+	}
+
+	/// Same as `new`, reporting the progress of the key derivation to `progress`.
+	pub fn new_with_progress<U: AsRef<str>, P: AsRef<str>>(username: U, password: P, progress: &mut dyn FnMut(DeriveProgress)) -> SyncParameters {
+		let username = Zeroizing::new(username.as_ref().to_string());
+		let password = Zeroizing::new(password.as_ref().to_string());
+
+		progress::derive_with_progress(
+			&CancellationToken::new(),
+			NetworkKeySuite::estimate_derive_time(),
+			move || SyncParameters::new(username.as_str(), password.as_str()),
+			progress,
+		)
+		.expect("internal error")
+	}
+
 	pub fn derive<P: AsRef<str>>(&mut self, password: P) {
 		let password = password.as_ref();
 
 		self.network_key_suite = Some(NetworkKeySuite::derive(self.username.as_bytes(), password.as_bytes()));
+		self.pending = None;
+	}
+
+	/// Replaces the login key with `login_key`, which gives full access, keeping the encryption keys.  Blocks until the keys are
+	/// derived if they're still pending.
+	pub(crate) fn set_login_key(&mut self, login_key: LoginKey) {
+		let mut network_key_suite = self.get_network_key_suite().expect("internal error").clone();
+		network_key_suite.login_key = Protected::new(login_key);
+
+		self.network_key_suite = Some(network_key_suite);
+		self.pending = None;
+		self.read_only = false;
+	}
+
+	/// Switches to the read-only login key.  Blocks until the keys are derived if they're still pending.
+	pub(crate) fn make_read_only(&mut self) {
+		if !self.read_only {
+			self.set_login_key(self.get_login_key().expect("internal error").readonly_login_key());
+			self.read_only = true;
+		}
+	}
+
+	pub fn is_read_only(&self) -> bool {
+		self.read_only
 	}
 
 	pub fn freeze(&self) -> Option<FrozenSyncParameters> {
-		self.network_key_suite.as_ref().map(|network_key_suite| FrozenSyncParameters {
+		self.get_network_key_suite().map(|network_key_suite| FrozenSyncParameters {
 			login_id: self.login_id,
-			login_key: network_key_suite.login_key.clone(),
+			login_key: (*network_key_suite.login_key).clone(),
 		})
 	}
 
@@ -47,12 +154,16 @@ impl SyncParameters {
 		&self.username
 	}
 
+	/// Blocks until the keys are derived if they're still pending.
 	pub fn get_network_key_suite(&self) -> Option<&NetworkKeySuite> {
-		self.network_key_suite.as_ref()
+		self.network_key_suite
+			.as_ref()
+			.or_else(|| self.pending.as_ref().map(DerivationHandle::wait_for_keys))
 	}
 
+	/// Blocks until the keys are derived if they're still pending.
 	pub fn get_login_key(&self) -> Option<&LoginKey> {
-		self.network_key_suite.as_ref().map(|nks| &nks.login_key)
+		self.get_network_key_suite().map(|nks| &*nks.login_key)
 	}
 
 	pub fn get_login_id(&self) -> &LoginId {
@@ -60,6 +171,38 @@ impl SyncParameters {
 	}
 }
 
+impl PartialEq for SyncParameters {
+	fn eq(&self, other: &Self) -> bool {
+		self.username == other.username && self.get_network_key_suite() == other.get_network_key_suite() && self.read_only == other.read_only
+	}
+}
+
+impl Eq for SyncParameters {}
+
+impl Serialize for SyncParameters {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		// Keys that are still pending are saved as missing, and derived again when the database is next opened
+		let network_key_suite = self
+			.network_key_suite
+			.as_ref()
+			.or_else(|| self.pending.as_ref().and_then(DerivationHandle::get_keys));
+
+		let mut state = serializer.serialize_struct("SyncParameters", 3)?;
+		state.serialize_field("username", &self.username)?;
+		state.serialize_field("network_key_suite", &network_key_suite)?;
+		// Left out unless set, so the format stays the same for everyone else
+		if self.read_only {
+			state.serialize_field("read_only", &self.read_only)?;
+		} else {
+			state.skip_field("read_only")?;
+		}
+		state.end()
+	}
+}
+
 impl<'de> serde::Deserialize<'de> for SyncParameters {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
 	where
@@ -69,6 +212,8 @@ impl<'de> serde::Deserialize<'de> for SyncParameters {
 		struct DeserializableSyncParameters {
 			username: String,
 			network_key_suite: Option<NetworkKeySuite>,
+			#[serde(default)]
+			read_only: bool,
 		}
 
 		let params = DeserializableSyncParameters::deserialize(deserializer)?;
@@ -77,11 +222,90 @@ impl<'de> serde::Deserialize<'de> for SyncParameters {
 			login_id: fortresscrypto::hash_username_for_login(params.username.as_bytes()),
 			username: params.username,
 			network_key_suite: params.network_key_suite,
+			pending: None,
+			read_only: params.read_only,
 		})
 	}
 }
 
 
+/// A derivation of sync parameters running in the background, returned by `SyncParameters::derive_async`.  Clones refer to the
+/// same derivation.
+#[derive(Clone)]
+pub struct DerivationHandle {
+	username: String,
+	derivation: Arc<Derivation>,
+}
+
+impl DerivationHandle {
+	pub fn is_finished(&self) -> bool {
+		self.get_keys().is_some()
+	}
+
+	/// The derived sync parameters if the derivation has finished, without blocking.
+	pub fn poll(&self) -> Option<SyncParameters> {
+		self.get_keys().map(|network_key_suite| self.sync_parameters(network_key_suite))
+	}
+
+	/// Blocks until the derivation finishes, returning the derived sync parameters.
+	pub fn wait(&self) -> SyncParameters {
+		self.sync_parameters(self.wait_for_keys())
+	}
+
+	fn get_keys(&self) -> Option<&NetworkKeySuite> {
+		self.derivation.network_key_suite.get()
+	}
+
+	fn wait_for_keys(&self) -> &NetworkKeySuite {
+		let mut finished = self.derivation.finished.lock().unwrap();
+
+		loop {
+			if let Some(network_key_suite) = self.get_keys() {
+				return network_key_suite;
+			}
+
+			finished = self.derivation.condvar.wait(finished).unwrap();
+		}
+	}
+
+	fn sync_parameters(&self, network_key_suite: &NetworkKeySuite) -> SyncParameters {
+		SyncParameters {
+			login_id: fortresscrypto::hash_username_for_login(self.username.as_bytes()),
+			username: self.username.clone(),
+			network_key_suite: Some(network_key_suite.clone()),
+			pending: None,
+			read_only: false,
+		}
+	}
+}
+
+impl fmt::Debug for DerivationHandle {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("DerivationHandle")
+			.field("username", &self.username)
+			.field("finished", &self.is_finished())
+			.finish()
+	}
+}
+
+
+#[derive(Default)]
+struct Derivation {
+	network_key_suite: OnceLock<NetworkKeySuite>,
+	// Waiters hold this while checking network_key_suite, so they can't miss the notification
+	finished: Mutex<()>,
+	condvar: Condvar,
+}
+
+impl Derivation {
+	fn finish(&self, network_key_suite: NetworkKeySuite) {
+		let _ = self.network_key_suite.set(network_key_suite);
+		let _finished = self.finished.lock().unwrap();
+		self.condvar.notify_all();
+	}
+}
+
+
 /// This is used by Database to store old sync parameters during password change.
 /// The biggest difference is that network_key_suite is not optional.
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -99,3 +323,38 @@ impl FrozenSyncParameters {
 		&self.login_key
 	}
 }
+
+
+#[cfg(test)]
+mod tests {
+	use super::{Derivation, DerivationHandle, SyncParameters};
+	use fortresscrypto::NetworkKeySuite;
+	use std::sync::Arc;
+
+	#[test]
+	fn derive_async() {
+		let expected = SyncParameters::new("username", "password");
+		let handle = SyncParameters::derive_async("username", "password");
+		assert_eq!(handle.wait(), expected);
+		assert!(handle.is_finished());
+		assert_eq!(handle.poll(), Some(expected.clone()));
+		assert_eq!(SyncParameters::pending(handle), expected);
+
+		// Still pending
+		let handle = DerivationHandle {
+			username: "username".to_string(),
+			derivation: Arc::new(Derivation::default()),
+		};
+		let params = SyncParameters::pending(handle.clone());
+		assert!(params.is_pending());
+		assert_eq!(handle.poll(), None);
+		assert_eq!(params.get_login_id(), expected.get_login_id());
+		let saved = serde_json::to_value(&params).unwrap();
+		assert_eq!(saved["network_key_suite"], serde_json::Value::Null);
+
+		handle.derivation.finish(NetworkKeySuite::derive(b"username", b"password"));
+		assert!(!params.is_pending());
+		assert_eq!(params.get_login_key(), expected.get_login_key());
+		assert_eq!(serde_json::to_value(&params).unwrap(), serde_json::to_value(&expected).unwrap());
+	}
+}
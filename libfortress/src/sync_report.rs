@@ -0,0 +1,196 @@
+// What a sync changed, for showing to the user.
+use crate::{database_object::DatabaseObject, ID};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+
+/// The kinds of change a sync can make to an object.  Frontends may rely on these staying the same.
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncActivity {
+	/// An object we didn't have was downloaded from the server.
+	Added,
+	/// Changes from the server were merged into one of our objects.
+	Updated,
+	/// One of our objects was new or had changes the server didn't, and was uploaded.
+	Uploaded,
+}
+
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncObjectKind {
+	Entry,
+	Directory,
+	Settings,
+	/// What's left of an entry or directory that was deleted for good (see the gc module).
+	Tombstone,
+}
+
+impl SyncObjectKind {
+	pub(crate) fn of(object: &DatabaseObject) -> SyncObjectKind {
+		match object {
+			DatabaseObject::Entry(_) => SyncObjectKind::Entry,
+			DatabaseObject::Directory(_) => SyncObjectKind::Directory,
+			DatabaseObject::Settings(_) => SyncObjectKind::Settings,
+			DatabaseObject::Tombstone(_) => SyncObjectKind::Tombstone,
+		}
+	}
+}
+
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct SyncChange {
+	pub activity: SyncActivity,
+	pub kind: SyncObjectKind,
+	pub id: ID,
+}
+
+
+/// Something the sync couldn't decide on its own and the user should be asked about.
+/// Conflicts don't stop the sync; each describes the choice that was made, which the user can override.
+#[derive(Serialize, Clone, Eq, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncConflict {
+	/// A directory was renamed on two devices at around the same time (see `Directory::concurrent_rename`).
+	/// The later name was kept; renaming the directory to `discarded` picks the other one instead.
+	DirectoryRename { directory: ID, kept: String, discarded: String },
+}
+
+
+/// Returned by `Database::sync`.  Each object is listed at most once per activity, even if the sync took several rounds.
+#[derive(Serialize, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SyncReport {
+	pub changes: BTreeSet<SyncChange>,
+	pub conflicts: Vec<SyncConflict>,
+	/// Bytes sent to the server: request bodies, including retried requests, but not HTTP headers or TLS overhead.
+	pub bytes_uploaded: u64,
+	/// Bytes received from the server: response bodies, but not HTTP headers or TLS overhead.
+	pub bytes_downloaded: u64,
+	/// Encrypted size of each object uploaded.  An object uploaded in more than one round counts each time.
+	pub uploaded_sizes: BTreeMap<ID, u64>,
+	/// Encrypted size of each object downloaded, counted the same way.
+	pub downloaded_sizes: BTreeMap<ID, u64>,
+	/// Objects in local-only directories that the server has a copy of anyway, e.g. from before they were made local-only (see
+	/// the selective_sync module).  They're merged like any other object, but never uploaded.
+	pub local_only_on_server: BTreeSet<ID>,
+}
+
+impl SyncReport {
+	pub(crate) fn record(&mut self, activity: SyncActivity, object: &DatabaseObject) {
+		self.changes.insert(SyncChange {
+			activity,
+			kind: SyncObjectKind::of(object),
+			id: *object.get_id(),
+		});
+	}
+
+	pub(crate) fn add_traffic(&mut self, traffic: SyncTraffic) {
+		self.bytes_uploaded += traffic.sent;
+		self.bytes_downloaded += traffic.received;
+
+		for (id, size) in traffic.uploaded {
+			*self.uploaded_sizes.entry(id).or_default() += size;
+		}
+
+		for (id, size) in traffic.downloaded {
+			*self.downloaded_sizes.entry(id).or_default() += size;
+		}
+	}
+
+	/// True if nothing changed.  Says nothing about traffic; even a sync that changes nothing fetches the server's object list.
+	pub fn is_empty(&self) -> bool {
+		self.changes.is_empty() && self.conflicts.is_empty()
+	}
+
+	pub fn count(&self, activity: SyncActivity, kind: SyncObjectKind) -> usize {
+		self.changes.iter().filter(|change| change.activity == activity && change.kind == kind).count()
+	}
+
+	/// A short description of what changed, e.g. "3 entries updated from server, 1 entry uploaded, 1 conflict".
+	pub fn summary(&self) -> String {
+		if self.is_empty() {
+			return "Already up to date".to_string();
+		}
+
+		let activities = [
+			(SyncActivity::Added, "added from server"),
+			(SyncActivity::Updated, "updated from server"),
+			(SyncActivity::Uploaded, "uploaded"),
+		];
+
+		let conflicts = match self.conflicts.len() {
+			0 => None,
+			1 => Some("1 conflict".to_string()),
+			n => Some(format!("{n} conflicts")),
+		};
+
+		activities
+			.into_iter()
+			.filter_map(|(activity, description)| {
+				let counts = [
+					(SyncObjectKind::Entry, "entry", "entries"),
+					(SyncObjectKind::Directory, "directory", "directories"),
+					(SyncObjectKind::Settings, "settings object", "settings objects"),
+				]
+				.into_iter()
+				.map(|(kind, singular, plural)| (self.count(activity, kind), singular, plural))
+				.filter(|(count, _, _)| *count > 0)
+				.map(|(count, singular, plural)| format!("{} {}", count, if count == 1 { singular } else { plural }))
+				.collect::<Vec<_>>();
+
+				(!counts.is_empty()).then(|| format!("{} {}", counts.join(" and "), description))
+			})
+			.chain(conflicts)
+			.collect::<Vec<_>>()
+			.join(", ")
+	}
+}
+
+
+/// What a sync sent and received, collected by its client (see `SyncReport::bytes_uploaded` and friends).
+#[derive(Debug, Default)]
+pub(crate) struct SyncTraffic {
+	pub sent: u64,
+	pub received: u64,
+	pub uploaded: BTreeMap<ID, u64>,
+	pub downloaded: BTreeMap<ID, u64>,
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{SyncActivity, SyncChange, SyncConflict, SyncObjectKind, SyncReport};
+	use rand::{rngs::OsRng, Rng};
+
+	#[test]
+	fn summary() {
+		let change = |report: &mut SyncReport, activity, kind, id| report.changes.insert(SyncChange { activity, kind, id });
+		let mut report = SyncReport::default();
+		assert!(report.is_empty());
+		assert_eq!(report.summary(), "Already up to date");
+
+		for _ in 0..3 {
+			change(&mut report, SyncActivity::Updated, SyncObjectKind::Entry, OsRng.gen());
+		}
+		let id = OsRng.gen();
+		change(&mut report, SyncActivity::Uploaded, SyncObjectKind::Entry, id);
+		change(&mut report, SyncActivity::Uploaded, SyncObjectKind::Entry, id);
+		assert_eq!(report.summary(), "3 entries updated from server, 1 entry uploaded");
+
+		change(&mut report, SyncActivity::Added, SyncObjectKind::Directory, OsRng.gen());
+		change(&mut report, SyncActivity::Added, SyncObjectKind::Entry, OsRng.gen());
+		assert_eq!(report.count(SyncActivity::Added, SyncObjectKind::Entry), 1);
+		assert_eq!(
+			report.summary(),
+			"1 entry and 1 directory added from server, 3 entries updated from server, 1 entry uploaded"
+		);
+
+		let mut report = SyncReport::default();
+		report.conflicts.push(SyncConflict::DirectoryRename {
+			directory: OsRng.gen(),
+			kept: "Work".to_string(),
+			discarded: "Job".to_string(),
+		});
+		assert!(!report.is_empty());
+		assert_eq!(report.summary(), "1 conflict");
+	}
+}
@@ -0,0 +1,235 @@
+// Syncing with more than one server, e.g. a self-hosted one plus an off-site mirror for redundancy.  The server at `sync_url`
+// is the primary one, which `sync` and everything else uses.  Additional targets are added with `add_sync_target`, each with
+// its own URL and sync parameters (so it can be a different account, or even a different username and password), and are
+// only synced by `sync_all`.  Each target keeps its own state like the primary does: the login key the server hasn't been told
+// about yet (see `set_sync_target_parameters`), which account it belongs to (see the account_change module), and when it was
+// last synced.  The proxy and everything else about syncing is shared.
+//
+// `sync_all` syncs with the primary server and then each target in turn, so changes downloaded from a target reach the servers
+// before it on the next `sync_all`.  Network key rotation (see the key_rotation module) only applies to the primary server;
+// targets are encrypted under their own keys.
+//
+// NOTE: Histories compacted since the last sync (see the compaction module) are only compacted on the servers synced by the
+// same `sync` or `sync_all`, so a target that's left out may bring the old history back.
+use crate::{sync_parameters::FrozenSyncParameters, CancellationToken, Database, FortressError, SyncOptions, SyncParameters, SyncProgress, SyncReport};
+use fortresscrypto::LoginId;
+use serde::{Deserialize, Serialize};
+use std::{
+	mem,
+	time::{SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+
+/// A server the database syncs with besides the primary one (see the sync_targets module).
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct SyncTarget {
+	url: Url,
+	sync_parameters: SyncParameters,
+	/// Set to the old sync parameters until the server is told about a change, like `Database::old_sync_parameters`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	old_sync_parameters: Option<FrozenSyncParameters>,
+	/// The account the target was last synced with (see the account_change module).
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	account_login_id: Option<LoginId>,
+	/// When the last successful sync with the target finished, in seconds since the Unix epoch.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	last_sync: Option<u64>,
+}
+
+impl SyncTarget {
+	pub fn get_url(&self) -> &Url {
+		&self.url
+	}
+
+	pub fn get_username(&self) -> &str {
+		self.sync_parameters.get_username()
+	}
+
+	pub fn get_login_id(&self) -> &LoginId {
+		self.sync_parameters.get_login_id()
+	}
+
+	/// When the last successful sync with the target finished, in seconds since the Unix epoch.  None if it hasn't been synced.
+	pub fn get_last_sync(&self) -> Option<u64> {
+		self.last_sync
+	}
+
+	/// True if the target's server hasn't been told about a change of login key yet.  The next sync tells it.
+	pub fn login_key_change_pending(&self) -> bool {
+		self.old_sync_parameters.is_some()
+	}
+}
+
+
+impl Database {
+	/// Adds a server to sync with besides the primary one, logging in with `sync_parameters` (see the sync_targets module).
+	/// Fails with FortressError::SyncTargetExists if the database already syncs with `url`.  Blocks until the keys in
+	/// `sync_parameters` are derived if they're still pending, since the database has no password to derive them from later.
+	pub fn add_sync_target(&mut self, url: Url, sync_parameters: SyncParameters) -> Result<(), FortressError> {
+		if self.sync_url.as_ref() == Some(&url) || self.sync_targets.iter().any(|target| target.url == url) {
+			return Err(FortressError::SyncTargetExists);
+		}
+
+		sync_parameters.get_network_key_suite().expect("internal error");
+		self.sync_targets.push(SyncTarget {
+			url,
+			sync_parameters,
+			old_sync_parameters: None,
+			account_login_id: None,
+			last_sync: None,
+		});
+
+		Ok(())
+	}
+
+	/// Stops syncing with the target at `url`.  Returns false if there isn't one.  Nothing is deleted from its server.
+	pub fn remove_sync_target(&mut self, url: &Url) -> bool {
+		let count = self.sync_targets.len();
+		self.sync_targets.retain(|target| target.url != *url);
+		self.sync_targets.len() != count
+	}
+
+	/// The servers synced with besides the primary one, in the order `sync_all` syncs them.
+	pub fn list_sync_targets(&self) -> &[SyncTarget] {
+		&self.sync_targets
+	}
+
+	/// Logs in to the target at `url` with `sync_parameters` from now on, e.g. after its password was changed.  Like
+	/// `change_password`, if the username stays the same the next sync tells the server about the new login key.  Returns false
+	/// if there's no such target.  Blocks until the keys are derived if they're still pending.
+	pub fn set_sync_target_parameters(&mut self, url: &Url, sync_parameters: SyncParameters) -> bool {
+		let Some(target) = self.sync_targets.iter_mut().find(|target| target.url == *url) else {
+			return false;
+		};
+
+		sync_parameters.get_network_key_suite().expect("internal error");
+		// Keep the oldest key the server hasn't been told about, since that's the one it knows
+		if sync_parameters.get_username() == target.sync_parameters.get_username() && target.old_sync_parameters.is_none() {
+			target.old_sync_parameters = target.sync_parameters.freeze();
+		}
+		target.sync_parameters = sync_parameters;
+
+		true
+	}
+
+	/// Syncs with the primary server, if there's a sync URL, and then with each target (see the sync_targets module).  Every
+	/// server is tried even if syncing with an earlier one fails; the results are returned in order, along with each server's
+	/// URL.
+	pub fn sync_all(&mut self) -> Vec<(Url, Result<SyncReport, FortressError>)> {
+		self.sync_all_with_options(&SyncOptions::default(), &CancellationToken::new(), &mut |_| {})
+	}
+
+	/// Same as `sync_all`, with `options`, `cancel` and `progress` applying to each server's sync as in `sync_with_options`.
+	pub fn sync_all_with_options(
+		&mut self,
+		options: &SyncOptions,
+		cancel: &CancellationToken,
+		progress: &mut dyn FnMut(SyncProgress),
+	) -> Vec<(Url, Result<SyncReport, FortressError>)> {
+		// Each server's copy needs compacting the same way, so don't let the first successful sync forget about them
+		let compacted_objects = self.compacted_objects.clone();
+		let mut results = Vec::new();
+
+		if let Some(url) = self.sync_url.clone() {
+			results.push((url, self.sync_with_options(options, cancel, progress)));
+		}
+
+		for index in 0..self.sync_targets.len() {
+			self.compacted_objects.clone_from(&compacted_objects);
+			let url = self.sync_targets[index].url.clone();
+			results.push((url, self.sync_target(index, options, cancel, progress)));
+		}
+
+		if results.iter().any(|(_, result)| result.is_err()) {
+			self.compacted_objects = compacted_objects;
+		}
+
+		results
+	}
+
+	/// Syncs with `sync_targets[index]` by swapping its state in for the primary server's for the duration of the sync.
+	fn sync_target(
+		&mut self,
+		index: usize,
+		options: &SyncOptions,
+		cancel: &CancellationToken,
+		progress: &mut dyn FnMut(SyncProgress),
+	) -> Result<SyncReport, FortressError> {
+		let mut target = self.sync_targets.remove(index);
+		let sync_url = self.sync_url.replace(target.url.clone());
+		let key_rotation = self.key_rotation.take();
+		mem::swap(&mut self.sync_parameters, &mut target.sync_parameters);
+		mem::swap(&mut self.old_sync_parameters, &mut target.old_sync_parameters);
+		mem::swap(&mut self.account_login_id, &mut target.account_login_id);
+
+		let result = self.sync_with_options(options, cancel, progress);
+
+		mem::swap(&mut self.sync_parameters, &mut target.sync_parameters);
+		mem::swap(&mut self.old_sync_parameters, &mut target.old_sync_parameters);
+		mem::swap(&mut self.account_login_id, &mut target.account_login_id);
+		self.key_rotation = key_rotation;
+		self.sync_url = sync_url;
+
+		if result.is_ok() {
+			target.last_sync = Some(SystemTime::now().duration_since(UNIX_EPOCH).expect("internal error").as_secs());
+		}
+		self.sync_targets.insert(index, target);
+
+		result
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, FortressError, SyncParameters};
+	use url::Url;
+
+	#[test]
+	fn sync_targets() {
+		let mut db = Database::new_with_password("username", "password");
+		let primary = Url::parse("http://127.0.0.1:1/").unwrap();
+		let mirror = Url::parse("http://127.0.0.1:2/").unwrap();
+		db.set_sync_url(Some(primary.clone()));
+		assert!(matches!(
+			db.add_sync_target(primary.clone(), SyncParameters::new("username", "password")),
+			Err(FortressError::SyncTargetExists)
+		));
+		db.add_sync_target(mirror.clone(), SyncParameters::new("mirror", "password")).unwrap();
+		assert!(matches!(
+			db.add_sync_target(mirror.clone(), SyncParameters::new("mirror", "password")),
+			Err(FortressError::SyncTargetExists)
+		));
+		let target = &db.list_sync_targets()[0];
+		assert_eq!(target.get_url(), &mirror);
+		assert_eq!(target.get_username(), "mirror");
+		assert_eq!(target.get_last_sync(), None);
+
+		// A new login key for the same username is sent on the next sync
+		assert!(db.set_sync_target_parameters(&mirror, SyncParameters::new("mirror", "new password")));
+		assert!(db.list_sync_targets()[0].login_key_change_pending());
+		assert!(!db.set_sync_target_parameters(&primary, SyncParameters::new("username", "new password")));
+
+		// Every server is tried, and failing ones leave their state alone
+		let login_key = db.get_login_key().clone();
+		let results = db.sync_all();
+		assert_eq!(results.iter().map(|(url, _)| url).collect::<Vec<_>>(), [&primary, &mirror]);
+		assert!(results.iter().all(|(_, result)| result.is_err()));
+		assert_eq!(db.get_sync_url(), Some(&primary));
+		assert_eq!(db.get_login_key(), &login_key);
+		assert_eq!(db.list_sync_targets()[0].get_username(), "mirror");
+		assert!(db.list_sync_targets()[0].login_key_change_pending());
+
+		// Kept when saved
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("database.fortress");
+		db.save_to_path(&path).unwrap();
+		let saved = Database::load_from_path(&path, "password").unwrap();
+		assert_eq!(saved.list_sync_targets(), db.list_sync_targets());
+
+		assert!(db.remove_sync_target(&mirror));
+		assert!(!db.remove_sync_target(&mirror));
+		assert!(db.list_sync_targets().is_empty());
+	}
+}
@@ -0,0 +1,260 @@
+// Helpers for testing merge semantics, for this crate's own tests and for code built on libfortress that wants to check, after a
+// refactor, the invariants sync relies on.  Devices only end up with the same database, without losing anything, if merging
+// objects is commutative (it doesn't matter which side of a sync an object is on), idempotent (syncing again changes nothing),
+// and associative (it doesn't matter which devices sync first), and if a merge keeps the history of both sides.
+//
+// The generators make "branches": copies of one object with some shared history, that then diverge with random edits of their
+// own, as if made on different devices between syncs.  Every edit gets its own time, and branches never make edits that can't
+// be combined (such as two branches removing the same child of a directory), so the branches always merge.  The random number
+// generator is passed in and edit times come from a clock with a fixed start, so a seeded one (e.g. `StdRng::seed_from_u64`)
+// makes a failing case replayable.
+//
+// Built for this crate's tests, and for others with the `testing` feature.
+use crate::{Database, Directory, Entry, EntryHistory, SettingsObject, SteppingClock, TimeSource, ID};
+use rand::{seq::SliceRandom, Rng};
+use std::{collections::HashMap, fmt::Debug};
+
+
+const FIELDS: &[&str] = &["title", "username", "password", "url", "notes"];
+const TAGS: &[&str] = &["work", "personal", "shared"];
+// Branches are made on a clock that starts at a fixed time (2020-01-01) and ticks once per edit, so no two edits share a time and
+// a seed makes the same branches every run
+const START_TIME: u64 = 1_577_836_800_000_000_000;
+const EDIT_INTERVAL: u64 = 1_000_000;
+
+
+/// Objects that sync merges: entries, directories, and the settings object.
+pub trait Mergeable: Clone + Eq + Debug {
+	/// The object with the history of both, or None if they conflict.
+	fn merge(&self, other: &Self) -> Option<Self>;
+
+	/// True if replacing self with other loses nothing.
+	fn safe_to_replace_with(&self, other: &Self) -> bool;
+}
+
+impl Mergeable for Entry {
+	fn merge(&self, other: &Entry) -> Option<Entry> {
+		Entry::merge(self, other)
+	}
+
+	fn safe_to_replace_with(&self, other: &Entry) -> bool {
+		Entry::safe_to_replace_with(self, other)
+	}
+}
+
+impl Mergeable for Directory {
+	fn merge(&self, other: &Directory) -> Option<Directory> {
+		Directory::merge(self, other)
+	}
+
+	fn safe_to_replace_with(&self, other: &Directory) -> bool {
+		Directory::safe_to_replace_with(self, other)
+	}
+}
+
+impl Mergeable for SettingsObject {
+	fn merge(&self, other: &SettingsObject) -> Option<SettingsObject> {
+		SettingsObject::merge(self, other)
+	}
+
+	fn safe_to_replace_with(&self, other: &SettingsObject) -> bool {
+		SettingsObject::safe_to_replace_with(self, other)
+	}
+}
+
+
+/// `branches` copies of a new entry with up to `max_edits` shared edits, each followed by up to `max_edits` edits of its own.
+/// Edits set a few fields (from a small set of values, so they often overwrite each other) and sometimes add or remove tags.
+pub fn random_entry_branches<R: Rng>(rng: &mut R, branches: usize, max_edits: usize) -> Vec<Entry> {
+	let clock = SteppingClock::new(START_TIME, EDIT_INTERVAL);
+	let mut base = Entry::new_with(&clock, rng);
+
+	for _ in 0..rng.gen_range(0..=max_edits) {
+		base.edit(random_entry_edit(rng, &clock));
+	}
+
+	let mut branches = vec![base; branches];
+
+	// Interleaved, like devices editing at the same time
+	for _ in 0..rng.gen_range(0..=max_edits * branches.len()) {
+		let branch = branches.choose_mut(rng).expect("no branches");
+		branch.edit(random_entry_edit(rng, &clock));
+	}
+
+	branches
+}
+
+
+fn random_entry_edit<R: Rng>(rng: &mut R, clock: &SteppingClock) -> EntryHistory {
+	let mut data = HashMap::new();
+	for _ in 0..rng.gen_range(1..=2) {
+		data.insert(FIELDS.choose(rng).unwrap().to_string(), rng.gen_range(0..4).to_string());
+	}
+
+	let mut edit = EntryHistory::new_with(data, clock);
+	if rng.gen_ratio(1, 4) {
+		edit.tags.insert(TAGS.choose(rng).unwrap().to_string(), rng.gen());
+	}
+
+	edit
+}
+
+
+/// `branches` copies of a new directory with up to `max_edits` shared edits, each followed by up to `max_edits` edits of its own.
+/// Edits add and remove children and rename the directory.  Branches only remove children that they added themselves, since two
+/// branches removing the same child don't merge.
+pub fn random_directory_branches<R: Rng>(rng: &mut R, branches: usize, max_edits: usize) -> Vec<Directory> {
+	let clock = SteppingClock::new(START_TIME, EDIT_INTERVAL);
+	let mut base = Directory::new_with(rng);
+	let mut shared_children = Vec::new();
+
+	for _ in 0..rng.gen_range(0..=max_edits) {
+		random_directory_edit(rng, &clock, &mut base, &mut shared_children);
+	}
+
+	let mut branches = vec![(base, Vec::new()); branches];
+
+	for _ in 0..rng.gen_range(0..=max_edits * branches.len()) {
+		let (branch, own_children) = branches.choose_mut(rng).expect("no branches");
+		random_directory_edit(rng, &clock, branch, own_children);
+	}
+
+	branches.into_iter().map(|(branch, _)| branch).collect()
+}
+
+
+// Adds a new child (remembered in `children`), removes one of `children`, or renames the directory
+fn random_directory_edit<R: Rng>(rng: &mut R, clock: &SteppingClock, directory: &mut Directory, children: &mut Vec<ID>) {
+	let time = clock.now();
+
+	match rng.gen_range(0..3) {
+		0 => {
+			let id = rng.gen();
+			directory.add_with_time(id, time);
+			children.push(id);
+		},
+		1 if !children.is_empty() => {
+			let id = children.swap_remove(rng.gen_range(0..children.len()));
+			directory.remove_with_time(id, time);
+		},
+		_ => directory.rename_with_time(format!("Folder {}", rng.gen_range(0..4)), time),
+	}
+}
+
+
+/// `branches` copies of a new settings object with up to `max_edits` shared settings changes, each followed by up to
+/// `max_edits` changes of its own.
+pub fn random_settings_branches<R: Rng>(rng: &mut R, branches: usize, max_edits: usize) -> Vec<SettingsObject> {
+	let clock = SteppingClock::new(START_TIME, EDIT_INTERVAL);
+	let mut base = SettingsObject::new();
+
+	for _ in 0..rng.gen_range(0..=max_edits) {
+		base.set_with_time(format!("setting {}", rng.gen_range(0..3)), rng.gen_range(0..4).into(), clock.now())
+			.unwrap();
+	}
+
+	let mut branches = vec![base; branches];
+
+	for _ in 0..rng.gen_range(0..=max_edits * branches.len()) {
+		let branch = branches.choose_mut(rng).expect("no branches");
+		branch
+			.set_with_time(format!("setting {}", rng.gen_range(0..3)), rng.gen_range(0..4).into(), clock.now())
+			.unwrap();
+	}
+
+	branches
+}
+
+
+/// Panics unless `a` and `b` merge to the same object in either order, and that object keeps the history of both.  Returns the
+/// merged object.
+pub fn assert_merge_commutative<T: Mergeable>(a: &T, b: &T) -> T {
+	let ab = a.merge(b).unwrap_or_else(|| panic!("merge failed:\n{a:?}\n{b:?}"));
+	let ba = b.merge(a).unwrap_or_else(|| panic!("merge failed:\n{b:?}\n{a:?}"));
+
+	assert_eq!(ab, ba, "merge isn't commutative");
+	assert!(a.safe_to_replace_with(&ab), "merge lost history:\n{a:?}\n{ab:?}");
+	assert!(b.safe_to_replace_with(&ab), "merge lost history:\n{b:?}\n{ab:?}");
+
+	ab
+}
+
+
+/// Panics unless merging `a` with itself changes nothing.
+pub fn assert_merge_idempotent<T: Mergeable>(a: &T) {
+	assert_eq!(a.merge(a).as_ref(), Some(a), "merging with itself changed it");
+	assert!(a.safe_to_replace_with(a), "not safe to replace with itself");
+}
+
+
+/// Panics unless merging `a`, `b` and `c` gives the same object however they're grouped.
+pub fn assert_merge_associative<T: Mergeable>(a: &T, b: &T, c: &T) {
+	let ab_c = assert_merge_commutative(&assert_merge_commutative(a, b), c);
+	let a_bc = assert_merge_commutative(a, &assert_merge_commutative(b, c));
+
+	assert_eq!(ab_c, a_bc, "merge isn't associative");
+}
+
+
+/// Checks all of the above for every branch, pair and triple of `branches`, and that merging everything again into the result
+/// changes nothing.
+pub fn assert_merge_invariants<T: Mergeable>(branches: &[T]) {
+	for a in branches {
+		assert_merge_idempotent(a);
+
+		for b in branches {
+			let ab = assert_merge_commutative(a, b);
+			assert_merge_idempotent(&ab);
+			assert_eq!(ab.merge(a).as_ref(), Some(&ab), "merging again changed the result");
+
+			for c in branches {
+				assert_merge_associative(a, b, c);
+			}
+		}
+	}
+}
+
+
+/// Entry fields from `(name, value)` pairs, for building `EntryHistory`s in tests.
+pub fn fields(fields: &[(&str, &str)]) -> HashMap<String, String> {
+	fields.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+
+/// An entry with just a title, for tests that only need an entry to be there.
+pub fn new_entry(title: &str) -> Entry {
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new(fields(&[("title", title)])));
+	entry
+}
+
+
+/// Adds an entry with `data` as its fields to `db`, returning its ID.
+pub fn add_entry(db: &mut Database, data: &[(&str, &str)]) -> ID {
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new(fields(data)));
+	let id = *entry.get_id();
+	db.add_entry(entry);
+	id
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{assert_merge_invariants, random_directory_branches, random_entry_branches, random_settings_branches};
+	use crate::tests::for_each_seed;
+	use rand::{rngs::StdRng, SeedableRng};
+
+	// Each case is generated from a seed (see `for_each_seed`)
+	const CASES: u64 = 64;
+
+	#[test]
+	fn merge_invariants() {
+		for_each_seed("merge_invariants", CASES, |seed| {
+			let mut rng = StdRng::seed_from_u64(seed);
+			assert_merge_invariants(&random_entry_branches(&mut rng, 3, 8));
+			assert_merge_invariants(&random_directory_branches(&mut rng, 3, 8));
+			assert_merge_invariants(&random_settings_branches(&mut rng, 3, 8));
+		});
+	}
+}
@@ -0,0 +1,137 @@
+// Where entries and directories get the times of their edits from.  By default that's SystemClock, the system clock adjusted
+// so it never goes backwards or gives the same time twice within the process, so two edits made in quick succession are
+// always ordered, even on platforms with a coarse clock.  Changes made "now" (`Directory::add`, `Entry::new_edit`, ...) are also
+// timestamped just after the object's latest history item if that's ahead of the clock, as it can be when it was merged in
+// from a device whose clock is ahead, so clock skew between devices never puts history out of order.
+//
+// The `_with` variants of those (`Directory::add_with`, `Entry::new_edit_with`, ...) and of the constructors (`Entry::new_with`,
+// `Directory::new_with`, which also take a random number generator for the ID) take any TimeSource instead, so tests can use a
+// SteppingClock to make the same objects every run without sleeping between edits (see also the golden module).
+use std::{
+	cell::Cell,
+	sync::atomic::{AtomicU64, Ordering},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+
+// The last time SystemClock gave out
+static LAST_SYSTEM_TIME: AtomicU64 = AtomicU64::new(0);
+
+
+pub trait TimeSource {
+	/// The current time, in nanoseconds since the Unix epoch.
+	fn now(&self) -> u64;
+}
+
+
+/// The system clock, adjusted to always move forward (see the time_source module).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+	fn now(&self) -> u64 {
+		let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).expect("internal error");
+		let system_time = timestamp
+			.as_secs()
+			.checked_mul(1000000000)
+			.expect("internal error")
+			.checked_add(timestamp.subsec_nanos() as u64)
+			.expect("internal error");
+
+		let last = LAST_SYSTEM_TIME
+			.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(system_time.max(last + 1)))
+			.expect("internal error");
+		system_time.max(last + 1)
+	}
+}
+
+
+/// A clock that starts at a fixed time and moves forward by a fixed step every time it's read, for tests.
+#[derive(Clone, Debug)]
+pub struct SteppingClock {
+	next: Cell<u64>,
+	step: u64,
+}
+
+impl SteppingClock {
+	/// A clock that reads `start` first, then `start + step`, and so on (both in nanoseconds).
+	pub fn new(start: u64, step: u64) -> SteppingClock {
+		SteppingClock { next: Cell::new(start), step }
+	}
+}
+
+impl TimeSource for SteppingClock {
+	fn now(&self) -> u64 {
+		let now = self.next.get();
+		self.next.set(now.checked_add(self.step).expect("SteppingClock overflowed"));
+		now
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{SteppingClock, SystemClock, TimeSource};
+	use crate::{Directory, Entry, EntryHistory};
+	use rand::{rngs::StdRng, SeedableRng};
+	use std::collections::HashMap;
+
+	#[test]
+	fn time_sources() {
+		let clock = SteppingClock::new(10, 5);
+		assert_eq!([clock.now(), clock.now(), clock.now()], [10, 15, 20]);
+
+		// The system clock never repeats itself, even across threads
+		let times = std::thread::scope(|scope| {
+			let threads = (0..4)
+				.map(|_| scope.spawn(|| (0..1000).map(|_| SystemClock.now()).collect::<Vec<_>>()))
+				.collect::<Vec<_>>();
+			threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect::<Vec<_>>()
+		});
+		assert!(times[0] > 1_500_000_000_000_000_000);
+		let mut sorted = times.clone();
+		sorted.sort_unstable();
+		sorted.dedup();
+		assert_eq!(sorted.len(), times.len());
+
+		// The same clock and seed make the same objects
+		let make = || {
+			let (clock, mut rng) = (SteppingClock::new(1_000, 1), StdRng::seed_from_u64(42));
+			let mut entry = Entry::new_with(&clock, &mut rng);
+			entry.edit(EntryHistory::new_with(HashMap::from([("title".to_string(), "Email".to_string())]), &clock));
+			(entry, Directory::new_with(&mut rng))
+		};
+		let (entry, directory) = make();
+		assert_eq!((entry.clone(), directory.clone()), make());
+		assert_eq!(entry.get_time_created(), 1_000);
+		assert_eq!(entry.get_history()[0].time, 1_001);
+		assert_ne!(entry.get_id(), directory.get_id());
+	}
+
+	// A clock that's behind the history (e.g. merged in from a device whose clock is ahead) doesn't put it out of order
+	#[test]
+	fn clock_behind_history() {
+		let (clock, mut rng) = (SteppingClock::new(500, 1), StdRng::seed_from_u64(0));
+		let mut directory = Directory::new_with(&mut rng);
+		directory.rename_with_time("Ahead", 1_000);
+		directory.rename_with("Behind", &clock);
+		directory.add_with(*directory.get_id(), &clock);
+		directory.remove_with(*directory.get_id(), &clock);
+		directory.set_local_only_with(true, &clock);
+		assert_eq!(
+			directory.get_history().iter().map(|item| item.time).collect::<Vec<_>>(),
+			[1_000, 1_001, 1_002, 1_003, 1_004]
+		);
+
+		let mut entry = Entry::new_with(&clock, &mut rng);
+		entry.edit(EntryHistory::new_with(
+			HashMap::from([("title".to_string(), "Ahead".to_string())]),
+			&SteppingClock::new(1_000, 1),
+		));
+		entry.edit(entry.new_edit_with(HashMap::from([("title".to_string(), "Behind".to_string())]), &clock));
+		entry.record_use_with(&SteppingClock::new(7, 0));
+		entry.record_use_with(&SteppingClock::new(7, 0));
+		assert_eq!(entry.get_history()[1].time, 1_001);
+		assert_eq!(entry.get_use_count(), 2);
+	}
+}
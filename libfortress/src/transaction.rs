@@ -0,0 +1,91 @@
+// Applying many edits with a single save.  Saving serializes and encrypts the whole database, so frontends that save after every
+// edit would spend most of a bulk import (or any other batch of edits) saving.  `Database::transaction` applies a batch of edits
+// and saves once; if any edit fails, or saving does, the database is put back the way it was, so the file and the database in
+// memory never hold half a batch.
+use crate::{Database, Entry, FortressError};
+use std::path::Path;
+
+
+impl Database {
+	/// Applies `edits` to the database and then saves it to `path` (with `save_incremental`).  If `edits` returns an error, or
+	/// saving fails, the database is left as it was before and the file isn't changed.
+	pub fn transaction<P, T, E, F>(&mut self, path: P, edits: F) -> Result<T, E>
+	where
+		P: AsRef<Path>,
+		E: From<FortressError>,
+		F: FnOnce(&mut Database) -> Result<T, E>,
+	{
+		let before = self.clone();
+		let result = edits(self).and_then(|value| {
+			self.save_incremental(path)?;
+			Ok(value)
+		});
+
+		if result.is_err() {
+			*self = before;
+		}
+
+		result
+	}
+
+	/// Same as `add_entry` for each of `entries`.
+	pub fn add_entries<I: IntoIterator<Item = Entry>>(&mut self, entries: I) {
+		for entry in entries {
+			self.add_entry(entry);
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, Entry, EntryHistory, FortressError};
+	use std::collections::HashMap;
+	use tempfile::tempdir;
+
+	fn entry(title: &str) -> Entry {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(HashMap::from([("title".to_string(), title.to_string())])));
+		entry
+	}
+
+	#[test]
+	fn transaction() {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		let mut db = Database::new_with_password("username", "password");
+		db.save_to_path(&path).unwrap();
+
+		let ids = db
+			.transaction(&path, |db| {
+				let entries = (0..100).map(|i| entry(&format!("Entry {i}"))).collect::<Vec<_>>();
+				let ids = entries.iter().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+				db.add_entries(entries);
+				Ok::<_, FortressError>(ids)
+			})
+			.unwrap();
+		assert_eq!(ids.len(), 100);
+		assert_eq!(Database::load_from_path(&path, "password").unwrap(), db);
+
+		// A failed batch leaves both the database and the file alone
+		let before = db.clone();
+		let result = db.transaction(&path, |db| {
+			db.add_entry(entry("Half a batch"));
+			db.get_entry_by_id_mut(&ids[0])
+				.unwrap()
+				.edit(EntryHistory::new(HashMap::from([("title".to_string(), "Renamed".to_string())])));
+			Err::<(), _>(FortressError::Cancelled)
+		});
+		assert!(matches!(result, Err(FortressError::Cancelled)));
+		assert_eq!(db, before);
+		assert_eq!(Database::load_from_path(&path, "password").unwrap(), before);
+
+		// So does a failed save
+		let result = db.transaction(tmp_dir.path().join("missing").join("test.fortressdb"), |db| {
+			db.add_entry(entry("Unsaved"));
+			Ok::<_, FortressError>(())
+		});
+		assert!(result.is_err());
+		assert_eq!(db, before);
+	}
+}
@@ -0,0 +1,189 @@
+// The trash.
+// Deleting an entry or directory moves it into the trash directory, a second root that isn't part of the directory tree
+// frontends show.  A trashed directory takes everything in it along.  Like moves, trashing and restoring are directory edits,
+// so they sync.  Trashed objects keep their history, and stay until they're deleted for good (see the gc module), which leaves
+// tombstones in the trash in their place.
+use crate::{
+	database_object::{DatabaseObject, DirectoryHistoryAction},
+	Database, Directory, ID, ROOT_DIRECTORY_ID, SETTINGS_ID, TRASH_DIRECTORY_ID,
+};
+use serde::Serialize;
+use std::{cmp::Reverse, collections::HashSet};
+
+
+/// An entry or directory in the trash.
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct TrashedObject {
+	pub id: ID,
+	/// When it was trashed (nanoseconds).
+	pub time: u64,
+}
+
+
+impl Database {
+	/// None if nothing has ever been trashed.
+	pub fn get_trash(&self) -> Option<&Directory> {
+		self.get_directory_by_id(&TRASH_DIRECTORY_ID)
+	}
+
+	/// Moves the entry or directory `id` into the trash.  Returns false if it doesn't exist, is already in the trash, or is
+	/// the root directory.
+	pub fn trash_object(&mut self, id: &ID) -> bool {
+		if [ROOT_DIRECTORY_ID, TRASH_DIRECTORY_ID, SETTINGS_ID].contains(id) || self.objects.get(id).is_none() || self.is_in_trash(id) {
+			return false;
+		}
+
+		if self.get_trash().is_none() {
+			self.objects.update(DatabaseObject::Directory(Directory::new_trash()));
+		}
+
+		// Added to the trash first, so the object is never dangling.  Removed from every directory it's in, in case concurrent
+		// moves left it in more than one.
+		self.get_directory_by_id_mut(&TRASH_DIRECTORY_ID).expect("Internal error").add(*id);
+
+		for directory in self.list_directories_mut().filter(|directory| *directory.get_id() != TRASH_DIRECTORY_ID) {
+			if directory.contains(id) {
+				directory.remove(*id);
+			}
+		}
+
+		true
+	}
+
+	/// Moves the trashed entry or directory `id` back to the directory it was trashed from, or to the root directory if that
+	/// directory is gone or has been trashed too.  Returns false if `id` isn't directly in the trash, or was deleted for good.
+	pub fn restore_object(&mut self, id: &ID) -> bool {
+		if !self.get_trash().is_some_and(|trash| trash.contains(id)) || self.objects.get(id).is_some_and(|object| object.as_tombstone().is_some()) {
+			return false;
+		}
+
+		let parent = self.trashed_from(id).filter(|parent| !self.is_in_trash(parent)).unwrap_or(ROOT_DIRECTORY_ID);
+
+		self.move_object(id, &parent);
+		true
+	}
+
+	/// What's directly in the trash (not what's in trashed directories), most recently trashed first.  Objects deleted for good
+	/// aren't listed.
+	pub fn list_trash(&self) -> Vec<TrashedObject> {
+		let Some(trash) = self.get_trash() else {
+			return Vec::new();
+		};
+
+		let mut trashed = trash
+			.get_history()
+			.iter()
+			.filter_map(|item| match item.action {
+				DirectoryHistoryAction::Add(id) if trash.contains(&id) && self.objects.get(&id).is_some_and(|object| object.as_tombstone().is_none()) => {
+					Some(TrashedObject { id, time: item.time })
+				},
+				_ => None,
+			})
+			.collect::<Vec<_>>();
+
+		// An object trashed, restored, and trashed again was added more than once
+		trashed.sort_unstable_by_key(|object| Reverse(object.time));
+		let mut seen = HashSet::new();
+		trashed.retain(|object| seen.insert(object.id));
+		trashed
+	}
+
+	/// Every entry and directory in the trash, including what's in trashed directories.  Frontends should hide these.
+	pub fn list_trash_recursive(&self) -> HashSet<ID> {
+		let mut trashed = HashSet::new();
+		let mut pending = vec![TRASH_DIRECTORY_ID];
+
+		while let Some(id) = pending.pop() {
+			if let Some(directory) = self.get_directory_by_id(&id) {
+				pending.extend(directory.entries.iter().filter(|child| trashed.insert(**child)));
+			}
+		}
+
+		trashed.remove(&TRASH_DIRECTORY_ID);
+		trashed
+	}
+
+	/// True if `id` is in the trash, or in a trashed directory.
+	pub fn is_in_trash(&self, id: &ID) -> bool {
+		self.list_trash_recursive().contains(id)
+	}
+
+	/// The directory the entry or directory `id` was last removed from, other than the trash; for trashed objects, the one
+	/// they were trashed from.  None if it was never removed from one.
+	pub(crate) fn trashed_from(&self, id: &ID) -> Option<ID> {
+		self.list_directories()
+			.filter(|directory| *directory.get_id() != TRASH_DIRECTORY_ID)
+			.filter_map(|directory| last_removed(directory, id).map(|time| (time, *directory.get_id())))
+			.max()
+			.map(|(_, parent)| parent)
+	}
+}
+
+
+// When `id` was last removed from `directory`
+fn last_removed(directory: &Directory, id: &ID) -> Option<u64> {
+	directory
+		.get_history()
+		.iter()
+		.rev()
+		.find(|item| item.action == DirectoryHistoryAction::Remove(*id))
+		.map(|item| item.time)
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, Directory, Entry, ROOT_DIRECTORY_ID, TRASH_DIRECTORY_ID};
+
+	#[test]
+	fn trash_and_restore() {
+		let mut db = Database::new_with_password("username", "password");
+		let directory = Directory::new();
+		let directory_id = *directory.get_id();
+		db.add_directory(directory);
+		let (entry, nested) = (Entry::new(), Entry::new());
+		let (entry_id, nested_id) = (*entry.get_id(), *nested.get_id());
+		db.add_entry(entry);
+		db.add_entry(nested);
+		db.move_object(&nested_id, &directory_id);
+		assert!(db.get_trash().is_none());
+		assert!(db.list_trash().is_empty());
+
+		// The root, the trash, and objects that don't exist can't be trashed
+		assert!(!db.trash_object(&ROOT_DIRECTORY_ID));
+		assert!(!db.trash_object(&TRASH_DIRECTORY_ID));
+		assert!(!db.trash_object(Entry::new().get_id()));
+
+		assert!(db.trash_object(&entry_id));
+		assert!(!db.trash_object(&entry_id));
+		assert!(db.trash_object(&directory_id));
+		assert_eq!(db.list_trash().iter().map(|object| object.id).collect::<Vec<_>>(), [directory_id, entry_id]);
+		assert!(db.is_in_trash(&nested_id));
+		assert!(!db.trash_object(&nested_id));
+		assert!(!db.get_root().contains(&entry_id));
+		assert!(db.check_integrity().is_ok());
+
+		// Only what's directly in the trash can be restored
+		assert!(!db.restore_object(&nested_id));
+		assert!(db.restore_object(&directory_id));
+		assert!(!db.is_in_trash(&nested_id));
+		assert_eq!(db.get_parent_directory(&nested_id).map(Directory::get_id), Some(&directory_id));
+
+		// Restored into the directory it was trashed from, or the root if that's been trashed too
+		assert!(db.restore_object(&entry_id));
+		assert_eq!(db.get_parent_directory(&entry_id).map(Directory::get_id), Some(&ROOT_DIRECTORY_ID));
+		db.move_object(&entry_id, &directory_id);
+		assert!(db.trash_object(&entry_id));
+		assert!(db.restore_object(&entry_id));
+		assert_eq!(db.get_parent_directory(&entry_id).map(Directory::get_id), Some(&directory_id));
+		assert!(db.trash_object(&entry_id));
+		assert!(db.trash_object(&directory_id));
+		assert!(db.restore_object(&entry_id));
+		assert_eq!(db.get_parent_directory(&entry_id).map(Directory::get_id), Some(&ROOT_DIRECTORY_ID));
+
+		// Trashing again after restoring lists it once
+		assert!(db.trash_object(&entry_id));
+		assert_eq!(db.list_trash().iter().map(|object| object.id).collect::<Vec<_>>(), [entry_id, directory_id]);
+		assert!(db.check_integrity().is_ok());
+	}
+}
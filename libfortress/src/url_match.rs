@@ -0,0 +1,167 @@
+// Finding the entries that hold credentials for a URL, for autofill and the frontends' "open URL" features.
+// An entry matches a URL if any of its URL fields (see FieldKind::Url) is for the same site, or for a parent domain of it:
+// an entry for example.com matches login.example.com, but not the other way around.  URLs typed into entries are often missing
+// their scheme, so those are assumed to be https.  Entries for http sites also match the https version of the site, but
+// entries for https sites never match plain http pages, so credentials aren't handed to a downgraded connection.
+//
+// Entries can ask for stricter matching by setting their URL_MATCH_FIELD field to URL_MATCH_EXACT; they then only match
+// URLs with the same host and path, which is useful when several accounts live on different paths of one site.
+use std::cmp::Reverse;
+
+use url::Url;
+
+use crate::{Database, Entry, FieldKind};
+
+
+/// Entry field selecting how its URLs are matched.
+pub const URL_MATCH_FIELD: &str = "url_match";
+/// Value of URL_MATCH_FIELD that restricts an entry to URLs with the same host and path.
+pub const URL_MATCH_EXACT: &str = "exact";
+
+
+// How closely an entry's URL matches, worst first
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+enum MatchQuality {
+	ParentDomain,
+	Host,
+	Path,
+}
+
+
+impl Database {
+	/// Entries with credentials for `url`, best matches first.  Entries in the trash are left out, and nothing matches a URL
+	/// that can't be parsed.
+	pub fn find_entries_for_url(&self, url: &str) -> Vec<&Entry> {
+		let Some(url) = normalize_url(url) else {
+			return Vec::new();
+		};
+		let Some(host) = url.host_str() else {
+			return Vec::new();
+		};
+		let trashed = self.list_trash_recursive();
+
+		let mut matches = self
+			.objects
+			.get_index()
+			.host_candidates(host)
+			.iter()
+			.filter(|id| !trashed.contains(*id))
+			.filter_map(|id| self.get_entry_by_id(id))
+			.filter_map(|entry| Some((entry_match(entry, &url)?, entry)))
+			.collect::<Vec<_>>();
+
+		// Ties are ordered by title, so results don't depend on how entries happen to be stored
+		matches.sort_by_key(|(quality, entry)| (Reverse(*quality), entry.get("title").cloned().unwrap_or_default()));
+		matches.into_iter().map(|(_, entry)| entry).collect()
+	}
+}
+
+
+/// Parses a URL the way they're compared when matching entries: a missing scheme is taken to be https, the host is lowercased
+/// without any leading "www.", and default ports and fragments are dropped.  None if `url` isn't a URL with a host.
+pub fn normalize_url(url: &str) -> Option<Url> {
+	let url = url.trim();
+	let mut url = match Url::parse(url) {
+		Ok(parsed) if parsed.has_host() => parsed,
+		// Without a scheme, "example.com:8080" parses as a URL with the scheme "example.com" and the path "8080"
+		Ok(parsed) if parsed.path().starts_with(|c: char| c.is_ascii_digit()) => Url::parse(&format!("https://{url}")).ok()?,
+		Err(url::ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{url}")).ok()?,
+		_ => return None,
+	};
+
+	if !url.has_host() {
+		return None;
+	}
+
+	if let Some(host) = url.domain().and_then(|domain| domain.strip_prefix("www.")).map(str::to_owned) {
+		url.set_host(Some(&host)).ok()?;
+	}
+	url.set_fragment(None);
+
+	Some(url)
+}
+
+
+// The best match among the entry's URL fields, if any
+fn entry_match(entry: &Entry, url: &Url) -> Option<MatchQuality> {
+	let exact = entry
+		.get(URL_MATCH_FIELD)
+		.is_some_and(|value| value.trim().eq_ignore_ascii_case(URL_MATCH_EXACT));
+
+	entry
+		.get_fields()
+		.iter()
+		.filter(|field| field.kind == FieldKind::Url)
+		.filter_map(|field| normalize_url(field.value))
+		.filter_map(|entry_url| url_match(&entry_url, url))
+		.filter(|quality| !exact || *quality == MatchQuality::Path)
+		.max()
+}
+
+
+// How well `url` matches an entry's `entry_url`, both normalized
+fn url_match(entry_url: &Url, url: &Url) -> Option<MatchQuality> {
+	let scheme_matches = entry_url.scheme() == url.scheme() || (entry_url.scheme() == "http" && url.scheme() == "https");
+	if !scheme_matches || entry_url.port() != url.port() {
+		return None;
+	}
+
+	let (entry_host, host) = (entry_url.host_str()?, url.host_str()?);
+
+	if entry_host == host {
+		if entry_url.path().trim_end_matches('/') == url.path().trim_end_matches('/') {
+			Some(MatchQuality::Path)
+		} else {
+			Some(MatchQuality::Host)
+		}
+	} else if entry_url.domain().is_some() && url.domain().is_some() && host.ends_with(&format!(".{entry_host}")) {
+		Some(MatchQuality::ParentDomain)
+	} else {
+		None
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{normalize_url, URL_MATCH_EXACT, URL_MATCH_FIELD};
+	use crate::{testing::add_entry, Database};
+
+	#[test]
+	fn find_entries_for_url() {
+		let mut db = Database::new_with_password("username", "password");
+		let parent = add_entry(&mut db, &[("title", "parent"), ("url", "Example.com")]);
+		let login = add_entry(&mut db, &[("title", "login"), ("url", "https://www.login.example.com/")]);
+		let insecure = add_entry(&mut db, &[("title", "insecure"), ("url", "http://login.example.com/account")]);
+		let exact = add_entry(
+			&mut db,
+			&[("title", "exact"), ("url", "login.example.com/other"), (URL_MATCH_FIELD, URL_MATCH_EXACT)],
+		);
+		let port = add_entry(&mut db, &[("title", "port"), ("url", "login.example.com:8443")]);
+		let lookalike = add_entry(&mut db, &[("title", "lookalike"), ("url", "notexample.com")]);
+		let trashed = add_entry(&mut db, &[("title", "trashed"), ("url", "login.example.com")]);
+		// Only URL fields count
+		let note = add_entry(&mut db, &[("title", "note"), ("notes", "login.example.com")]);
+		db.trash_object(&trashed);
+
+		let find = |url: &str| db.find_entries_for_url(url).iter().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+
+		assert_eq!(find("https://login.example.com/account#top"), [insecure, login, parent]);
+		// The parent's URL has no scheme, so it's only for https
+		assert_eq!(find("http://login.example.com/account"), [insecure]);
+		assert_eq!(find("https://login.example.com/other/"), [exact, insecure, login, parent]);
+		assert_eq!(find("https://login.example.com:8443/"), [port]);
+		assert_eq!(find("https://example.com/"), [parent]);
+		assert_eq!(find("https://notexample.com/"), [lookalike]);
+		assert_eq!(find("https://example.org/"), []);
+		assert_eq!(find("not a url"), []);
+		assert!(!db
+			.find_entries_for_url("https://login.example.com/")
+			.iter()
+			.any(|entry| *entry.get_id() == note));
+
+		assert_eq!(normalize_url("WWW.Example.com:443/path#x").unwrap().as_str(), "https://example.com/path");
+		assert_eq!(normalize_url("localhost:3000").unwrap().as_str(), "https://localhost:3000/");
+		assert_eq!(normalize_url("mailto:someone@example.com"), None);
+	}
+}
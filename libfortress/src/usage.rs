@@ -0,0 +1,86 @@
+// How often and how recently entries are used, for "recently used" and "most used" views and for audits of passwords nobody
+// uses anymore.  Uses are reported by the frontend through `Database::record_use` (e.g. when a password is copied) and kept
+// on the entry itself (see `Entry::record_use`), so unlike `record_access` (the recent module) they're synced to every device.
+use crate::{Database, Entry, ID};
+use std::cmp::Reverse;
+
+
+impl Database {
+	/// Records a use of entry `id` (see `Entry::record_use`).  Returns false if `id` isn't an entry.
+	pub fn record_use(&mut self, id: &ID) -> bool {
+		match self.get_entry_by_id_mut(id) {
+			Some(entry) => {
+				entry.record_use();
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Up to `n` entries that have been used, most recently used first.  Trashed entries are left out.
+	pub fn recently_used(&self, n: usize) -> Vec<&Entry> {
+		let mut entries = self.used_entries();
+		entries.sort_by_key(|entry| Reverse(entry.get_last_used()));
+		entries.truncate(n);
+		entries
+	}
+
+	/// Up to `n` entries that have been used, most used first, and most recently used first among those used as often.
+	/// Trashed entries are left out.
+	pub fn most_used(&self, n: usize) -> Vec<&Entry> {
+		let mut entries = self.used_entries();
+		entries.sort_by_key(|entry| Reverse((entry.get_use_count(), entry.get_last_used())));
+		entries.truncate(n);
+		entries
+	}
+
+	fn used_entries(&self) -> Vec<&Entry> {
+		let trash = self.list_trash_recursive();
+
+		self.list_entries()
+			.filter(|entry| entry.get_use_count() > 0 && !trash.contains(entry.get_id()))
+			.collect()
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, Entry, ROOT_DIRECTORY_ID};
+
+	#[test]
+	fn usage() {
+		let mut db = Database::new_with_password("username", "password");
+		let ids = (0..4)
+			.map(|_| {
+				let entry = Entry::new();
+				let id = *entry.get_id();
+				db.add_entry(entry);
+				id
+			})
+			.collect::<Vec<_>>();
+		let ids_of = |entries: Vec<&Entry>| entries.into_iter().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+		assert_eq!(ids_of(db.recently_used(10)), []);
+		assert_eq!(ids_of(db.most_used(10)), []);
+
+		for id in [ids[0], ids[1], ids[0], ids[2], ids[1], ids[0]] {
+			assert!(db.record_use(&id));
+		}
+		assert!(!db.record_use(&ROOT_DIRECTORY_ID));
+
+		assert_eq!(ids_of(db.recently_used(10)), [ids[0], ids[1], ids[2]]);
+		assert_eq!(ids_of(db.recently_used(2)), [ids[0], ids[1]]);
+		assert_eq!(ids_of(db.most_used(10)), [ids[0], ids[1], ids[2]]);
+		assert_eq!(db.get_entry_by_id(&ids[0]).unwrap().get_use_count(), 3);
+		assert!(db.get_entry_by_id(&ids[3]).unwrap().get_last_used().is_none());
+
+		// Ties go to the most recently used
+		db.record_use(&ids[2]);
+		db.record_use(&ids[2]);
+		assert_eq!(ids_of(db.most_used(2)), [ids[2], ids[0]]);
+
+		assert!(db.trash_object(&ids[2]));
+		assert_eq!(ids_of(db.recently_used(10)), [ids[0], ids[1]]);
+		assert_eq!(ids_of(db.most_used(10)), [ids[0], ids[1]]);
+	}
+}
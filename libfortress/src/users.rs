@@ -0,0 +1,152 @@
+// Sharing one database file between people who each open it with their own password, e.g. a couple sharing a vault.  Each
+// user is a keyslot in the file (see fortresscrypto's keyslot module): the file is encrypted with random keys, which each
+// user's keyslot holds encrypted under keys derived from their password, with its own KDF parameters.  A database starts out
+// with one user, its own password, in keyslot 0; `add_user` adds the others.
+//
+// Only the file is shared this way.  The database still has one username and password for syncing, keyslot 0's, which only
+// user 0 can change (`change_password`).  The other users' keyslots can only be added and removed, so a user changes their
+// password by having it removed and added again.  Users are numbered by keyslot, and removing one moves the users after it
+// down by one.
+//
+// Keyslots are saved with the rest of the file, so they change on the next save.  The file's keys stay the same, though, since
+// new keys would have to be wrapped for every other user's password.  So a removed user who kept the keys (e.g. from an old
+// copy of the file and their password) can still decrypt it.  Databases that need a hardware key can't have more than one
+// user.
+use crate::{Database, FortressError};
+use fortresscrypto::FileKdfParameters;
+
+
+impl Database {
+	/// Lets `password` open the database file as well as the passwords that already can, from the next save on (see the users
+	/// module).  Returns the new user's number.
+	pub fn add_user<P: AsRef<str>>(&mut self, password: P) -> Result<usize, FortressError> {
+		self.add_user_with_params(password, &Default::default())
+	}
+
+	/// Same as `add_user`, but the new user's keys are derived with `params`.
+	pub fn add_user_with_params<P: AsRef<str>>(&mut self, password: P, params: &FileKdfParameters) -> Result<usize, FortressError> {
+		let (file_key_suite, user) = self.file_key_suite.add_keyslot(password.as_ref().as_bytes(), params)?;
+		self.file_key_suite = file_key_suite;
+
+		Ok(user)
+	}
+
+	/// Stops user `user`'s password from opening the database file from the next save on.  Users after it move down by one.
+	/// User 0 is the database's own password, which can't be removed (FortressError::CannotRemoveDatabasePassword).
+	/// NOTE: The file's keys aren't changed, so anyone who has opened it with the removed password can still decrypt later saves
+	/// with the keys they got then (see the users module).  Copy the entries to a new database to lock them out completely.
+	pub fn remove_user(&mut self, user: usize) -> Result<(), FortressError> {
+		if user == 0 {
+			return Err(FortressError::CannotRemoveDatabasePassword);
+		}
+
+		self.file_key_suite = self.file_key_suite.remove_keyslot(user).ok_or(FortressError::NoSuchUser(user))?;
+
+		Ok(())
+	}
+
+	/// The KDF parameters of each user's keys, by user number.  Databases only ever opened with their own password have the
+	/// one user.
+	pub fn get_users(&self) -> Vec<&FileKdfParameters> {
+		match self.file_key_suite.get_keyslots() {
+			[] => vec![self.file_key_suite.get_kdf_params()],
+			keyslots => keyslots.iter().map(|keyslot| keyslot.get_kdf_params()).collect(),
+		}
+	}
+
+	/// The number of the user whose password opened the database (see `get_users`).  0 if it wasn't opened with a password.
+	pub fn get_current_user(&self) -> usize {
+		self.file_key_suite.get_keyslot()
+	}
+
+	/// Fails with FortressError::NotDatabasePassword unless the database was opened with its own password.
+	pub(crate) fn check_database_password(&self) -> Result<(), FortressError> {
+		if self.get_current_user() == 0 {
+			Ok(())
+		} else {
+			Err(FortressError::NotDatabasePassword)
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use crate::{Database, Entry, EntryHistory, FortressError};
+	use fortresscrypto::{CryptoError, FileKdfParameters};
+	use std::{collections::HashMap, fs, io::Cursor};
+	use tempfile::tempdir;
+
+	fn save(db: &Database) -> Vec<u8> {
+		let tmp_dir = tempdir().unwrap();
+		let path = tmp_dir.path().join("test.fortressdb");
+		db.save_to_path(&path).unwrap();
+		fs::read(&path).unwrap()
+	}
+
+	#[test]
+	fn users() {
+		let mut db = Database::new_with_password("username", "alice");
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new(HashMap::from([("title".to_string(), "Shared".to_string())])));
+		let entry_id = *entry.get_id();
+		db.add_entry(entry);
+		assert_eq!(db.get_users().len(), 1);
+
+		let params = FileKdfParameters {
+			log_n: 9,
+			..Default::default()
+		};
+		assert_eq!(db.add_user_with_params("bob", &params).unwrap(), 1);
+		assert_eq!(db.add_user("carol").unwrap(), 2);
+		assert_eq!(db.get_users()[1], &params);
+
+		// Everyone opens the same database
+		let file = save(&db);
+		for (user, password) in ["alice", "bob", "carol"].into_iter().enumerate() {
+			let loaded = Database::load_from_reader(password, &mut Cursor::new(&file)).unwrap();
+			assert_eq!(loaded.get_current_user(), user);
+			assert_eq!(loaded.get_entry_by_id(&entry_id).unwrap()["title"], "Shared");
+			assert_eq!(loaded.get_username(), "username");
+		}
+		assert!(Database::load_from_reader("eve", &mut Cursor::new(&file)).is_err());
+
+		// Only Alice can change the database's password, which is hers
+		let mut bobs = Database::load_from_reader("bob", &mut Cursor::new(&file)).unwrap();
+		let before = bobs.get_login_key().clone();
+		assert!(matches!(bobs.change_password("username", "alice2"), Err(FortressError::NotDatabasePassword)));
+		assert_eq!(*bobs.get_login_key(), before);
+		let mut alices = Database::load_from_reader("alice", &mut Cursor::new(&file)).unwrap();
+		alices.change_password("username", "alice2").unwrap();
+		let file = save(&alices);
+		assert!(Database::load_from_reader("alice", &mut Cursor::new(&file)).is_err());
+		assert_eq!(Database::load_from_reader("alice2", &mut Cursor::new(&file)).unwrap().get_current_user(), 0);
+		assert_eq!(Database::load_from_reader("bob", &mut Cursor::new(&file)).unwrap().get_current_user(), 1);
+
+		// Removing Bob moves Carol down
+		assert!(matches!(alices.remove_user(0), Err(FortressError::CannotRemoveDatabasePassword)));
+		assert!(matches!(alices.remove_user(3), Err(FortressError::NoSuchUser(3))));
+		alices.remove_user(1).unwrap();
+		let file = save(&alices);
+		assert!(Database::load_from_reader("bob", &mut Cursor::new(&file)).is_err());
+		assert_eq!(Database::load_from_reader("carol", &mut Cursor::new(&file)).unwrap().get_current_user(), 1);
+	}
+
+	#[test]
+	fn users_and_hardware_keys() {
+		let response = |challenge: &[u8]| Some(challenge.to_vec().into());
+		let mut db = Database::new_with_password("username", "password");
+		db.set_hardware_key("password", Some(&response)).unwrap();
+		assert!(matches!(
+			db.add_user("bob"),
+			Err(FortressError::CryptoError(CryptoError::HardwareKeyWithKeyslots))
+		));
+
+		db.set_hardware_key("password", None).unwrap();
+		db.add_user("bob").unwrap();
+		assert!(matches!(
+			db.set_hardware_key("password", Some(&response)),
+			Err(FortressError::CryptoError(CryptoError::HardwareKeyWithKeyslots))
+		));
+	}
+}
@@ -1,52 +1,124 @@
 // A simplified in-memory Fortress server used for sync tests
+#![allow(clippy::iter_skip_next)]
 use data_encoding::HEXLOWER_PERMISSIVE;
 use fortresscrypto::{LoginKey, SIV};
 use libfortress::ID;
-use std::{collections::HashMap, thread};
+use std::{
+	collections::HashMap,
+	io::{Cursor, Read, Write},
+	net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+	sync::{Arc, Mutex},
+	thread,
+};
 use tiny_http::{Method, Response, Server};
 
 
 // Starts a server and returns the address it is listening on
-pub fn server(mut login_key: LoginKey) -> String {
+pub fn server(login_key: LoginKey) -> String {
+	server_with_max_object_size(login_key, None)
+}
+
+
+// Starts a server that, if `max_object_size` is given, advertises it and rejects larger objects.  Without it the server
+// behaves like one that predates the capabilities endpoint.
+pub fn server_with_max_object_size(login_key: LoginKey, max_object_size: Option<usize>) -> String {
+	start_server(Some(login_key), max_object_size, |_, _| None)
+}
+
+
+// Starts a server that has no account yet; one can be created with POST /user
+pub fn server_without_account() -> String {
+	start_server(None, None, |_, _| None)
+}
+
+
+// Starts a server that answers a request with `fault` (given the method and path segments) instead, if it returns a response.
+// Used to simulate servers that fail now and then.
+pub fn flaky_server<F>(login_key: LoginKey, fault: F) -> String
+where
+	F: FnMut(&Method, &[&str]) -> Option<Response<Cursor<Vec<u8>>>> + Send + 'static,
+{
+	start_server(Some(login_key), None, fault)
+}
+
+
+fn start_server<F>(mut login_key: Option<LoginKey>, max_object_size: Option<usize>, mut fault: F) -> String
+where
+	F: FnMut(&Method, &[&str]) -> Option<Response<Cursor<Vec<u8>>>> + Send + 'static,
+{
 	let mut db = HashMap::new();
 	let server = Server::http("127.0.0.1:0").unwrap();
 	let addr = server.server_addr().to_string();
 
-	let api = |method: Method, url: Vec<&str>, body: Vec<u8>, db: &mut HashMap<ID, Vec<u8>>, login_key: &mut LoginKey| match (method, url.as_slice()) {
-		(Method::Get, ["objects"]) => {
-			let response: Vec<_> = db
-				.iter()
-				.map(|(id, data)| (id, HEXLOWER_PERMISSIVE.encode(data[data.len() - 32..].as_ref())))
-				.collect();
-			Response::from_string(serde_json::to_string(&response).unwrap())
-		},
-		(Method::Get, ["object", id]) => {
-			let id = ID::from_slice(&HEXLOWER_PERMISSIVE.decode(id.as_bytes()).unwrap()).unwrap();
-			match db.get(&id) {
-				Some(data) => Response::from_data(data.clone()),
+	let api =
+		move |method: Method, url: Vec<&str>, body: Vec<u8>, db: &mut HashMap<ID, Vec<u8>>, login_key: &mut Option<LoginKey>| match (method, url.as_slice()) {
+			(Method::Get, ["objects"]) => {
+				let response: Vec<_> = db
+					.iter()
+					.map(|(id, data)| (id, HEXLOWER_PERMISSIVE.encode(data[data.len() - 32..].as_ref())))
+					.collect();
+				Response::from_string(serde_json::to_string(&response).unwrap())
+			},
+			(Method::Get, ["object", id]) => {
+				let id = ID::from_slice(&HEXLOWER_PERMISSIVE.decode(id.as_bytes()).unwrap()).unwrap();
+				match db.get(&id) {
+					Some(data) => Response::from_data(data.clone()),
+					None => Response::from_string("".to_string()).with_status_code(404),
+				}
+			},
+			(Method::Get, ["capabilities"]) => match max_object_size {
+				Some(max) => Response::from_string(format!("{{\"max_object_size\":{max}}}")),
 				None => Response::from_string("".to_string()).with_status_code(404),
-			}
-		},
-		(Method::Post, ["object", id, old_siv]) => {
-			let id = ID::from_slice(&HEXLOWER_PERMISSIVE.decode(id.as_bytes()).unwrap()).unwrap();
-			let old_siv = SIV::from_slice(&HEXLOWER_PERMISSIVE.decode(old_siv.as_bytes()).unwrap()).unwrap();
-			if let Some(data) = db.get(&id) {
-				if old_siv != SIV::from_slice(&data[data.len() - 32..]).unwrap() {
-					return Response::from_string("".to_string()).with_status_code(409);
+			},
+			(Method::Post, ["object", _, _]) if max_object_size.is_some_and(|max| body.len() > max) => {
+				Response::from_string("".to_string()).with_status_code(413)
+			},
+			(Method::Post, ["object", id, old_siv]) => {
+				let id = ID::from_slice(&HEXLOWER_PERMISSIVE.decode(id.as_bytes()).unwrap()).unwrap();
+				let old_siv = SIV::from_slice(&HEXLOWER_PERMISSIVE.decode(old_siv.as_bytes()).unwrap()).unwrap();
+				if let Some(data) = db.get(&id) {
+					if old_siv != SIV::from_slice(&data[data.len() - 32..]).unwrap() {
+						return Response::from_string("".to_string()).with_status_code(409);
+					}
 				}
-			}
-			db.insert(id, body);
-			Response::from_string("".to_string())
-		},
-		(Method::Post, ["user", "login_key"]) => {
-			let key = LoginKey::from_slice(body.as_slice()).unwrap();
+				db.insert(id, body);
+				Response::from_string("".to_string())
+			},
+			(Method::Post, ["gc"]) => {
+				let candidates: Vec<(String, String)> = serde_json::from_slice(&body).unwrap();
+				let mut dropped = Vec::new();
+				for (id, siv) in candidates {
+					let id = ID::from_slice(&HEXLOWER_PERMISSIVE.decode(id.as_bytes()).unwrap()).unwrap();
+					let siv = HEXLOWER_PERMISSIVE.decode(siv.as_bytes()).unwrap();
+					if db.get(&id).is_some_and(|data| data[data.len() - 32..] == siv) {
+						db.remove(&id);
+						dropped.push(id);
+					}
+				}
+				Response::from_string(serde_json::to_string(&dropped).unwrap())
+			},
+			(Method::Post, ["user", "login_key"]) => {
+				let key = LoginKey::from_slice(body.as_slice()).unwrap();
 
-			*login_key = key;
+				*login_key = Some(key);
 
-			Response::from_string("".to_string())
-		},
-		_ => panic!("404"),
-	};
+				Response::from_string("".to_string())
+			},
+			(Method::Get, ["user"]) => {
+				let storage_used: usize = db.values().map(Vec::len).sum();
+				Response::from_string(format!(
+					"{{\"object_count\":{},\"storage_used\":{storage_used},\"max_objects\":1000}}",
+					db.len()
+				))
+			},
+			(Method::Delete, ["user"]) => {
+				db.clear();
+				*login_key = None;
+
+				Response::from_string("".to_string())
+			},
+			_ => panic!("404"),
+		};
 
 	thread::spawn(move || {
 		for mut request in server.incoming_requests() {
@@ -56,6 +128,11 @@ pub fn server(mut login_key: LoginKey) -> String {
 			let mut body = Vec::new();
 			request.as_reader().read_to_end(&mut body).unwrap();
 
+			if let Some(response) = fault(&method, &url) {
+				request.respond(response).unwrap();
+				continue;
+			}
+
 			// Make sure auth header is present and the right format
 			let auth = request.headers().iter().find(|h| h.field.equiv("Authorization")).unwrap().value.to_string();
 			let auth = auth.split(' ').skip(1).next().unwrap();
@@ -68,7 +145,27 @@ pub fn server(mut login_key: LoginKey) -> String {
 			// Check login key
 			let auth = LoginKey::from_slice(&auth[32..]).unwrap();
 
-			if auth != login_key {
+			// Registration is the only request that doesn't need an existing account
+			if (&method, url.as_slice()) == (&Method::Post, ["user"].as_slice()) {
+				let response = match login_key {
+					Some(_) => Response::from_string("".to_string()).with_status_code(409),
+					None => {
+						login_key = Some(auth);
+						Response::from_string("".to_string())
+					},
+				};
+				request.respond(response).unwrap();
+				continue;
+			}
+
+			// The read-only key, derived from the login key, can only download
+			let read_only = login_key.as_ref().is_some_and(|key| key.readonly_login_key() == auth);
+			if read_only && method != Method::Get {
+				request.respond(Response::from_string("".to_string()).with_status_code(403)).unwrap();
+				continue;
+			}
+
+			if login_key != Some(auth) && !read_only {
 				request.respond(Response::from_string("".to_string()).with_status_code(401)).unwrap();
 				continue;
 			}
@@ -79,3 +176,99 @@ pub fn server(mut login_key: LoginKey) -> String {
 
 	"http://".to_string() + &addr
 }
+
+
+// Starts a server that answers every request using `handler` (given the method, path segments, and body), without checking
+// authentication.  Used to simulate broken and malicious servers.
+pub fn hostile_server<F>(mut handler: F) -> String
+where
+	F: FnMut(&Method, &[&str], &[u8]) -> Response<Cursor<Vec<u8>>> + Send + 'static,
+{
+	let server = Server::http("127.0.0.1:0").unwrap();
+	let addr = server.server_addr().to_string();
+
+	thread::spawn(move || {
+		for mut request in server.incoming_requests() {
+			let method = request.method().clone();
+			let url = request.url().to_owned();
+			let url = url.split('/').skip(1).collect::<Vec<_>>();
+			let mut body = Vec::new();
+			request.as_reader().read_to_end(&mut body).unwrap();
+
+			let response = handler(&method, &url, &body);
+			let _ = request.respond(response);
+		}
+	});
+
+	"http://".to_string() + &addr
+}
+
+
+// Starts a SOCKS5 proxy (no authentication, CONNECT only) and returns its address, along with the list of hosts it has been
+// asked to connect to, as given by the client.
+pub fn socks_proxy() -> (String, Arc<Mutex<Vec<String>>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap().to_string();
+	let hosts = Arc::new(Mutex::new(Vec::new()));
+	let requested = hosts.clone();
+
+	thread::spawn(move || {
+		for client in listener.incoming() {
+			let hosts = hosts.clone();
+			thread::spawn(move || socks_connection(client.unwrap(), &hosts));
+		}
+	});
+
+	(addr, requested)
+}
+
+
+fn socks_connection(mut client: TcpStream, hosts: &Mutex<Vec<String>>) -> std::io::Result<()> {
+	// Greeting: version, then the authentication methods offered
+	let mut header = [0u8; 2];
+	client.read_exact(&mut header)?;
+	let mut methods = vec![0u8; header[1] as usize];
+	client.read_exact(&mut methods)?;
+	assert_eq!(header[0], 5);
+	assert!(methods.contains(&0));
+	client.write_all(&[5, 0])?;
+
+	// Request: version, command, reserved, address type, address, port
+	let mut request = [0u8; 4];
+	client.read_exact(&mut request)?;
+	assert_eq!(request[..3], [5, 1, 0]);
+	let host = match request[3] {
+		1 => {
+			let mut ip = [0u8; 4];
+			client.read_exact(&mut ip)?;
+			Ipv4Addr::from(ip).to_string()
+		},
+		3 => {
+			let mut len = [0u8; 1];
+			client.read_exact(&mut len)?;
+			let mut name = vec![0u8; len[0] as usize];
+			client.read_exact(&mut name)?;
+			String::from_utf8(name).unwrap()
+		},
+		_ => panic!("unsupported address type"),
+	};
+	let mut port = [0u8; 2];
+	client.read_exact(&mut port)?;
+	hosts.lock().unwrap().push(host.clone());
+
+	let upstream = TcpStream::connect((host.as_str(), u16::from_be_bytes(port)))?;
+	client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+
+	// Relay in both directions until either side closes
+	let (mut client_reader, mut upstream_writer) = (client.try_clone()?, upstream.try_clone()?);
+	let relay = thread::spawn(move || {
+		let _ = std::io::copy(&mut client_reader, &mut upstream_writer);
+		let _ = upstream_writer.shutdown(Shutdown::Write);
+	});
+	let (mut upstream_reader, mut client_writer) = (upstream, client);
+	let _ = std::io::copy(&mut upstream_reader, &mut client_writer);
+	let _ = client_writer.shutdown(Shutdown::Write);
+	let _ = relay.join();
+
+	Ok(())
+}
@@ -1,8 +1,22 @@
+#![allow(clippy::clone_on_copy)]
+
 mod sync_server;
 
-use libfortress::{Database, Entry, EntryHistory, FortressError};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use libfortress::{
+	fortresscrypto::NetworkKeySuite, sync_parameters::SyncParameters, AccountChangeConfirmed, AccountDeletionConfirmed, CancellationToken, Database, Directory,
+	Entry, EntryHistory, FortressError, HistoryLossConfirmed, SyncActivity, SyncChange, SyncConflict, SyncObjectKind, SyncOptions, SyncPhase, SyncProgress,
+	SyncReport, GC_HORIZON_SETTING, ID,
+};
+use rand::{rngs::OsRng, Rng};
 use reqwest::Url;
-use std::collections::HashMap;
+use serde_json::json;
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant, SystemTime},
+};
+use tiny_http::{Header, Method, Response};
 
 
 #[test]
@@ -101,14 +115,23 @@ fn sync_integration_test() {
 	// Sync db
 	let db_before_sync = db.clone();
 
-	db.sync().unwrap();
+	let report = db.sync().unwrap();
+	assert_eq!(report.count(SyncActivity::Uploaded, SyncObjectKind::Entry), 4);
+	assert_eq!(report.count(SyncActivity::Uploaded, SyncObjectKind::Directory), 1);
+	assert_eq!(report.summary(), "4 entries and 1 directory uploaded");
 
 	// Syncing right now shouldn't change anything
-	db.sync().unwrap();
+	assert!(db.sync().unwrap().is_empty());
 	assert_eq!(db, db_before_sync);
 
 	// Syncing the older database should bring it up to speed
-	db_old.sync().unwrap();
+	let report = db_old.sync().unwrap();
+	assert_eq!(report.summary(), "1 entry added from server, 1 entry and 1 directory updated from server");
+	assert!(report.changes.contains(&SyncChange {
+		activity: SyncActivity::Added,
+		kind: SyncObjectKind::Entry,
+		id: *entry4.get_id(),
+	}));
 	assert_eq!(db_old, db);
 
 	// But still shouldn't affect db
@@ -116,7 +139,11 @@ fn sync_integration_test() {
 	assert_eq!(db, db_before_sync);
 
 	// Syncing parallel_db should pick up db's edits
-	parallel_db.sync().unwrap();
+	let report = parallel_db.sync().unwrap();
+	assert_eq!(
+		report.summary(),
+		"1 entry added from server, 1 entry and 1 directory updated from server, 2 entries and 1 directory uploaded"
+	);
 	assert_eq!(
 		parallel_db.get_entry_by_id(entry3.get_id()).unwrap(),
 		db.get_entry_by_id(entry3.get_id()).unwrap()
@@ -138,7 +165,7 @@ fn sync_integration_test() {
 
 	bootstrap_db.sync().unwrap();
 	// We compare the serialized forms, because things like the FileKeySuite won't be equal
-	assert_eq!(serde_json::to_string(&bootstrap_db).unwrap(), serde_json::to_string(&db).unwrap());
+	assert_eq!(synced_state(&bootstrap_db), synced_state(&db));
 
 	// Now test password change
 	let mut old_db = db.clone();
@@ -146,7 +173,7 @@ fn sync_integration_test() {
 	db.get_root_mut().rename("New Root Name".to_string());
 
 	// Change password on db
-	db.change_password("username", "barfoo");
+	db.change_password("username", "barfoo").unwrap();
 
 	// Sync so the server has the new password
 	db.sync().unwrap();
@@ -161,11 +188,1077 @@ fn sync_integration_test() {
 	}
 
 	// Now change the password on the old database
-	old_db.change_password("username", "barfoo");
+	old_db.change_password("username", "barfoo").unwrap();
 
 	// Syncing the old database should now work
 	old_db.sync().unwrap();
 
 	// And the databases should be equal (except for the FileKeySuite)
-	assert_eq!(serde_json::to_string(&db).unwrap(), serde_json::to_string(&old_db).unwrap());
+	assert_eq!(synced_state(&db), synced_state(&old_db));
+}
+
+
+// A database's serialized form, without the parts that are local to each device
+fn synced_state(db: &Database) -> serde_json::Value {
+	let mut state = serde_json::to_value(db).unwrap();
+	state.as_object_mut().unwrap().remove("derivation_cache");
+	state.as_object_mut().unwrap().remove("share_key");
+	state
+}
+
+
+// Renaming the same directory on two devices shouldn't silently lose one of the names
+#[test]
+fn rename_conflict_test() {
+	let mut db1 = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db1.get_login_key().clone())).unwrap();
+	db1.set_sync_url(Some(sync_url));
+	db1.get_root_mut().rename("Passwords");
+	db1.sync().unwrap();
+
+	let mut db2 = db1.clone();
+	db1.get_root_mut().rename("Mine");
+	db2.get_root_mut().rename("Theirs");
+
+	assert!(db1.sync().unwrap().conflicts.is_empty());
+	let report = db2.sync().unwrap();
+	let root = *db2.get_root().get_id();
+	assert_eq!(
+		report.conflicts,
+		[SyncConflict::DirectoryRename {
+			directory: root,
+			kept: "Theirs".to_string(),
+			discarded: "Mine".to_string(),
+		}]
+	);
+	assert!(report.summary().ends_with(", 1 conflict"));
+	assert_eq!(db2.get_root().get_name(), Some("Theirs"));
+
+	// The user picks the other name, and that reaches the other device without another conflict
+	db2.get_root_mut().rename("Mine");
+	assert!(db2.sync().unwrap().conflicts.is_empty());
+	assert!(db1.sync().unwrap().conflicts.is_empty());
+	assert_eq!(db1.get_root().get_name(), Some("Mine"));
+	assert_eq!(db1, db2);
+}
+
+
+// Frontend settings follow the user across devices, with the most recent value of each setting winning
+#[test]
+fn settings_sync_test() {
+	let mut db1 = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db1.get_login_key().clone())).unwrap();
+	db1.set_sync_url(Some(sync_url.clone()));
+	let mut db2 = Database::new_with_password("username", "foobar");
+	db2.set_sync_url(Some(sync_url));
+	assert!(db1.get_settings().is_none());
+
+	db1.set_setting("theme", json!("dark"));
+	db1.set_setting("generator", json!({ "length": 20 }));
+	db1.sync().unwrap();
+
+	// Settings created separately on another device merge into the same object
+	db2.set_setting("font_size", json!(14));
+	let report = db2.sync().unwrap();
+	assert_eq!(report.count(SyncActivity::Updated, SyncObjectKind::Settings), 1);
+	let settings = db2.get_settings().unwrap();
+	assert_eq!(settings.get("theme"), Some(&json!("dark")));
+	assert_eq!(settings.get("font_size"), Some(&json!(14)));
+
+	db2.set_setting("theme", json!("light"));
+	db2.remove_setting("generator");
+	db2.sync().unwrap();
+	db1.sync().unwrap();
+	let settings = db1.get_settings().unwrap();
+	assert_eq!(settings.get("theme"), Some(&json!("light")));
+	assert_eq!(settings.get("generator"), None);
+	assert_eq!(db1.get_settings(), db2.get_settings());
+}
+
+
+// Compacting history replaces the server's copy, keeping edits other devices made after the cutoff
+#[test]
+fn compaction_sync_test() {
+	let edit = |time, title: &str| {
+		let mut edit = EntryHistory::new([("title".to_string(), title.to_string())].into_iter().collect());
+		edit.time = time;
+		edit
+	};
+
+	let mut db1 = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db1.get_login_key().clone())).unwrap();
+	db1.set_sync_url(Some(sync_url.clone()));
+	let mut entry = Entry::new();
+	for (time, title) in [(1, "One"), (2, "Two"), (3, "Three")] {
+		entry.edit(edit(time, title));
+	}
+	let id = *entry.get_id();
+	db1.add_entry(entry);
+	db1.sync().unwrap();
+
+	// Another device edits the entry after the cutoff
+	let mut db2 = db1.clone();
+	db2.get_entry_by_id_mut(&id).unwrap().edit(edit(20, "Twenty"));
+	db2.sync().unwrap();
+
+	let report = db1.compact_history(10, HistoryLossConfirmed);
+	assert_eq!(report.entries_compacted, 1);
+	assert_eq!(report.history_items_removed, 2);
+	let report = db1.sync().unwrap();
+	assert_eq!(report.count(SyncActivity::Uploaded, SyncObjectKind::Entry), 1);
+	let entry = db1.get_entry_by_id(&id).unwrap();
+	assert_eq!(entry.get_history().iter().map(|item| item.time).collect::<Vec<_>>(), [3, 20]);
+	assert_eq!(entry["title"], "Twenty");
+
+	// A new device gets the compacted history, and later syncs don't compact again
+	let mut db3 = Database::new_with_password("username", "foobar");
+	db3.set_sync_url(Some(sync_url));
+	db3.sync().unwrap();
+	assert_eq!(db3.get_entry_by_id(&id), db1.get_entry_by_id(&id));
+	assert!(db1.sync().unwrap().is_empty());
+}
+
+
+// Deleting for good reaches other devices, loses to later edits, and is dropped from the server by sync_gc
+#[test]
+fn gc_sync_test() {
+	let mut db1 = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db1.get_login_key().clone())).unwrap();
+	db1.set_sync_url(Some(sync_url.clone()));
+	let (deleted, revived) = (Entry::new(), Entry::new());
+	let (deleted_id, revived_id) = (*deleted.get_id(), *revived.get_id());
+	db1.add_entry(deleted);
+	db1.add_entry(revived);
+	db1.trash_object(&deleted_id);
+	db1.trash_object(&revived_id);
+	db1.sync().unwrap();
+	let mut db2 = db1.clone();
+
+	assert_eq!(db1.empty_trash(HistoryLossConfirmed), 2);
+	let report = db1.sync().unwrap();
+	assert_eq!(report.count(SyncActivity::Uploaded, SyncObjectKind::Tombstone), 2);
+
+	// A device that hadn't heard of the deletion edits one of the entries
+	db2.get_entry_by_id_mut(&revived_id)
+		.unwrap()
+		.edit(EntryHistory::new([("title".to_string(), "Revived".to_string())].into_iter().collect()));
+	db2.sync().unwrap();
+	assert!(db2.get_entry_by_id(&deleted_id).is_none());
+	assert_eq!(db2.get_entry_by_id(&revived_id).unwrap()["title"], "Revived");
+	db1.sync().unwrap();
+	assert_eq!(db1.list_trash().iter().map(|object| object.id).collect::<Vec<_>>(), [revived_id]);
+	assert_eq!(db1.list_tombstones().map(|tombstone| *tombstone.get_id()).collect::<Vec<_>>(), [deleted_id]);
+
+	assert_eq!(db1.sync_gc(0).unwrap(), 1);
+	assert_eq!(db1.list_tombstones().count(), 0);
+	assert!(!db1.get_trash().unwrap().contains(&deleted_id));
+
+	// The other device drops its copy instead of uploading it again
+	assert_eq!(db2.list_tombstones().count(), 1);
+	db2.sync().unwrap();
+	assert_eq!(db2.list_tombstones().count(), 0);
+	assert_eq!(db2.get_gc_horizon(), db1.get_gc_horizon());
+	let mut db3 = Database::new_with_password("username", "foobar");
+	db3.set_sync_url(Some(sync_url));
+	db3.sync().unwrap();
+	assert_eq!(db3.list_tombstones().count(), 0);
+	assert_eq!(db3.get_entry_by_id(&revived_id).unwrap()["title"], "Revived");
+}
+
+
+// A sync cancelled part way through leaves the local database as it was
+#[test]
+fn cancellation_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let original = db.clone();
+	let root_id = *db.get_root().get_id();
+
+	// The server has a changed root directory, and the sync is cancelled while it's being downloaded
+	let server_root = {
+		let mut root = db.get_root().clone();
+		root.add(OsRng.gen());
+		let mut object = serde_json::to_value(root).unwrap();
+		object["type"] = "Directory".into();
+		let encrypted = NetworkKeySuite::derive(b"username", b"foobar").encrypt_object(&root_id[..], &serde_json::to_vec(&object).unwrap());
+		[&encrypted.ciphertext, encrypted.siv.as_ref()].concat()
+	};
+	let cancel = CancellationToken::new();
+	let canceller = cancel.clone();
+	let handler = move |method: &Method, url: &[&str], _: &[u8]| match (method, url) {
+		(Method::Get, ["objects"]) => {
+			let listing = [(root_id.to_hex(), HEXLOWER_PERMISSIVE.encode(&OsRng.gen::<[u8; 32]>()))];
+			Response::from_string(serde_json::to_string(&listing).unwrap())
+		},
+		(Method::Get, ["object", _]) => {
+			canceller.cancel();
+			Response::from_data(server_root.clone())
+		},
+		(Method::Get, ["capabilities"]) => empty_response(404),
+		_ => empty_response(200),
+	};
+	db.set_sync_url(Some(Url::parse(&sync_server::hostile_server(handler)).unwrap()));
+
+	let result = db.sync_cancellable(&cancel);
+	assert!(matches!(result, Err(FortressError::Cancelled)), "{result:?}");
+	assert_eq!(db.get_root(), original.get_root());
+
+	// Cancelled tokens stay cancelled
+	assert!(matches!(db.sync_cancellable(&cancel), Err(FortressError::Cancelled)));
+	assert_eq!(db.get_root(), original.get_root());
+}
+
+
+// Switching a database to a different account that already has objects on the server needs confirmation before they're merged
+#[test]
+fn account_change_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	db.set_sync_url(Some(Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap()));
+	db.sync().unwrap();
+	assert!(!db.account_changed());
+
+	let mut other = Database::new_with_password("other", "foobar");
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Other".to_string())].into_iter().collect()));
+	let entry_id = *entry.get_id();
+	other.add_entry(entry);
+	let other_url = Url::parse(&sync_server::server(other.get_login_key().clone())).unwrap();
+	other.set_sync_url(Some(other_url.clone()));
+	other.sync().unwrap();
+
+	db.change_password("other", "foobar").unwrap();
+	db.set_sync_url(Some(other_url));
+	assert!(db.account_changed());
+	let original = db.clone();
+	assert!(matches!(db.sync(), Err(FortressError::SyncAccountChanged)));
+	assert_eq!(db, original);
+
+	db.confirm_account_change(AccountChangeConfirmed);
+	assert!(!db.account_changed());
+	db.sync().unwrap();
+	assert!(db.get_entry_by_id(&entry_id).is_some());
+
+	// An account with nothing on the server yet doesn't need confirmation
+	db.change_password("third", "foobar").unwrap();
+	db.set_sync_url(Some(Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap()));
+	assert!(db.account_changed());
+	db.sync().unwrap();
+	assert!(!db.account_changed());
+}
+
+
+// Rotating the network keys re-encrypts everything on the server, merging edits other devices uploaded under the old keys
+#[test]
+fn key_rotation_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap();
+	db.set_sync_url(Some(sync_url.clone()));
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Email".to_string())].into_iter().collect()));
+	let entry_id = *entry.get_id();
+	db.add_entry(entry);
+	db.sync().unwrap();
+
+	// Another device edits the entry under the old keys
+	let mut phone = db.clone();
+	phone
+		.get_entry_by_id_mut(&entry_id)
+		.unwrap()
+		.edit(EntryHistory::new([("password".to_string(), "From phone".to_string())].into_iter().collect()));
+	phone.sync().unwrap();
+	db.get_entry_by_id_mut(&entry_id)
+		.unwrap()
+		.edit(EntryHistory::new([("username".to_string(), "From laptop".to_string())].into_iter().collect()));
+
+	// Interrupted before the server heard about it; carries on with the next sync
+	db.set_sync_url(Some(Url::parse("http://127.0.0.1:1").unwrap()));
+	assert!(db.rotate_network_keys("barfoo").is_err());
+	assert!(db.key_rotation_pending());
+	let serialized = serde_json::to_value(&db).unwrap();
+	assert!(serialized.get("key_rotation").is_some());
+	db.set_sync_url(Some(sync_url));
+	let report = db.sync().unwrap();
+	assert!(!db.key_rotation_pending());
+	assert_eq!(report.count(SyncActivity::Updated, SyncObjectKind::Entry), 1);
+	let entry = db.get_entry_by_id(&entry_id).unwrap();
+	assert_eq!(entry["password"], "From phone");
+	assert_eq!(entry["username"], "From laptop");
+
+	// Everything on the server is under the new keys, so a device with the new password gets all of it
+	assert!(matches!(
+		phone.sync(),
+		Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(401, _)))
+	));
+	phone.change_password("username", "barfoo").unwrap();
+	phone.sync().unwrap();
+	assert_eq!(synced_state(&phone), synced_state(&db));
+
+	// Rotating again works in one go
+	db.rotate_network_keys("foobar").unwrap();
+	assert!(!db.key_rotation_pending());
+	phone.change_password("username", "foobar").unwrap();
+	phone.sync().unwrap();
+	assert_eq!(synced_state(&phone), synced_state(&db));
+}
+
+
+#[test]
+fn login_key_rotation_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap();
+	db.set_sync_url(Some(sync_url.clone()));
+	db.add_entry(Entry::new());
+	db.sync().unwrap();
+	let mut phone = db.clone();
+
+	db.rotate_login_key().unwrap();
+	db.add_entry(Entry::new());
+	db.sync().unwrap();
+
+	// The password alone isn't enough anymore
+	assert!(matches!(
+		phone.sync(),
+		Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(401, _)))
+	));
+	phone.set_login_key(db.get_login_key().clone());
+	phone.sync().unwrap();
+	assert_eq!(synced_state(&phone), synced_state(&db));
+
+	// A password change afterwards is sent with the rotated key
+	db.change_password("username", "barfoo").unwrap();
+	db.sync().unwrap();
+	assert!(db.test_sync_connection(&sync_url).unwrap().authenticated);
+}
+
+
+#[test]
+fn readonly_sync_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap();
+	db.set_sync_url(Some(sync_url.clone()));
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Email".to_string())].into_iter().collect()));
+	let entry_id = *entry.get_id();
+	db.add_entry(entry);
+	db.sync().unwrap();
+
+	// A backup agent gets every object without the encryption keys
+	let keys = db.get_readonly_sync_keys();
+	let objects = keys.download_objects(&sync_url).unwrap();
+	assert_eq!(objects.len() as u64, db.sync_account_info().unwrap().object_count);
+	assert!(objects.iter().any(|(id, _)| *id == entry_id));
+
+	// A read-only copy follows along, but can't upload
+	let mut mirror = db.clone();
+	mirror.make_sync_read_only();
+	db.get_entry_by_id_mut(&entry_id)
+		.unwrap()
+		.edit(EntryHistory::new([("password".to_string(), "hunter2".to_string())].into_iter().collect()));
+	db.sync().unwrap();
+	mirror.sync().unwrap();
+	assert_eq!(mirror.get_entry_by_id(&entry_id).unwrap()["password"], "hunter2");
+	mirror.add_entry(Entry::new());
+	assert!(matches!(mirror.sync(), Err(FortressError::SyncReadOnly)));
+	assert!(matches!(
+		mirror.sync_delete_account(AccountDeletionConfirmed),
+		Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(403, _)))
+	));
+	assert_eq!(db.sync().unwrap().count(SyncActivity::Added, SyncObjectKind::Entry), 0);
+
+	// Changing the password revokes it
+	db.change_password("username", "barfoo").unwrap();
+	db.sync().unwrap();
+	assert!(matches!(
+		keys.download_objects(&sync_url),
+		Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(401, _)))
+	));
+}
+
+
+#[test]
+fn selective_sync_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap();
+	db.set_sync_url(Some(sync_url.clone()));
+	let directory = Directory::new();
+	let directory_id = *directory.get_id();
+	db.add_directory(directory);
+	let uploaded = Entry::new();
+	let uploaded_id = *uploaded.get_id();
+	db.add_entry(uploaded);
+	db.move_object(&uploaded_id, &directory_id);
+	db.sync().unwrap();
+
+	// Only the directory and what was uploaded before reach the server
+	db.get_directory_by_id_mut(&directory_id).unwrap().set_local_only(true);
+	let kept = Entry::new();
+	let kept_id = *kept.get_id();
+	db.add_entry(kept);
+	db.move_object(&kept_id, &directory_id);
+	let report = db.sync().unwrap();
+	assert_eq!(report.local_only_on_server, [uploaded_id].into_iter().collect());
+	let on_server = db.get_readonly_sync_keys().download_objects(&sync_url).unwrap();
+	assert!(on_server.iter().any(|(id, _)| *id == directory_id));
+	assert!(on_server.iter().any(|(id, _)| *id == uploaded_id));
+	assert!(!on_server.iter().any(|(id, _)| *id == kept_id));
+
+	// Other devices learn the flag, and don't upload their copies either
+	let mut other = Database::new_with_password("username", "foobar");
+	other.set_sync_url(Some(sync_url.clone()));
+	other.sync().unwrap();
+	assert!(other.get_directory_by_id(&directory_id).unwrap().is_local_only());
+	assert!(other.get_entry_by_id(&kept_id).is_none());
+	other
+		.get_entry_by_id_mut(&uploaded_id)
+		.unwrap()
+		.edit(EntryHistory::new([("password".to_string(), "hunter2".to_string())].into_iter().collect()));
+	other.sync().unwrap();
+	assert_eq!(db.sync().unwrap().count(SyncActivity::Updated, SyncObjectKind::Entry), 0);
+
+	// Moving an entry out lets it sync again
+	let root_id = *db.get_root().get_id();
+	db.move_object(&kept_id, &root_id);
+	db.sync().unwrap();
+	other.sync().unwrap();
+	assert!(other.get_entry_by_id(&kept_id).is_some());
+}
+
+
+#[test]
+fn sync_targets_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let login_id = *db.get_login_id();
+	let primary_url = Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap();
+	let mirror_parameters = SyncParameters::new("mirror", "barfoo");
+	let mirror_url = Url::parse(&sync_server::server(mirror_parameters.get_login_key().unwrap().clone())).unwrap();
+	db.set_sync_url(Some(primary_url.clone()));
+	db.add_sync_target(mirror_url.clone(), mirror_parameters).unwrap();
+	let entry = Entry::new();
+	let entry_id = *entry.get_id();
+	db.add_entry(entry);
+
+	let results = db.sync_all();
+	assert_eq!(results.len(), 2);
+	assert!(results
+		.iter()
+		.all(|(_, result)| result.as_ref().unwrap().count(SyncActivity::Uploaded, SyncObjectKind::Entry) == 1));
+	assert!(db.list_sync_targets()[0].get_last_sync().is_some());
+
+	// The mirror is a separate account with its own keys
+	let mut mirror = Database::new_with_password("mirror", "barfoo");
+	mirror.set_sync_url(Some(mirror_url.clone()));
+	mirror.sync().unwrap();
+	mirror
+		.get_entry_by_id_mut(&entry_id)
+		.unwrap()
+		.edit(EntryHistory::new([("password".to_string(), "hunter2".to_string())].into_iter().collect()));
+	mirror.sync().unwrap();
+
+	// Changes from the mirror reach the primary server on the next sync_all
+	let results = db.sync_all();
+	assert_eq!(results[0].1.as_ref().unwrap().count(SyncActivity::Updated, SyncObjectKind::Entry), 0);
+	assert_eq!(results[1].1.as_ref().unwrap().count(SyncActivity::Updated, SyncObjectKind::Entry), 1);
+	assert_eq!(db.get_entry_by_id(&entry_id).unwrap()["password"], "hunter2");
+	let results = db.sync_all();
+	assert_eq!(results[0].1.as_ref().unwrap().count(SyncActivity::Uploaded, SyncObjectKind::Entry), 1);
+	assert!(results[1].1.as_ref().unwrap().is_empty());
+	assert_eq!(db.get_login_id(), &login_id);
+
+	// A new password for the mirror is sent to its server on the next sync
+	assert!(db.set_sync_target_parameters(&mirror_url, SyncParameters::new("mirror", "foobaz")));
+	assert!(db.sync_all().into_iter().all(|(_, result)| result.is_ok()));
+	assert!(!db.list_sync_targets()[0].login_key_change_pending());
+	mirror.change_password("mirror", "foobaz").unwrap();
+	mirror.sync().unwrap();
+}
+
+
+#[test]
+fn connection_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let sync_url = Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap();
+
+	let status = db.test_sync_connection(&sync_url).unwrap();
+	assert!(status.authenticated);
+	assert!(status.latency > Duration::ZERO);
+
+	// Wrong credentials
+	let other = Database::new_with_password("username", "wrong");
+	assert!(!other.test_sync_connection(&sync_url).unwrap().authenticated);
+
+	// A password change the server hasn't been told about yet is fine, since the next sync tells it
+	db.change_password("username", "barfoo").unwrap();
+	assert!(db.test_sync_connection(&sync_url).unwrap().authenticated);
+	db.set_sync_url(Some(sync_url.clone()));
+	db.sync().unwrap();
+	assert!(db.test_sync_connection(&sync_url).unwrap().authenticated);
+
+	// Not a Fortress server
+	let url = Url::parse(&sync_server::hostile_server(|_, _, _| Response::from_string("<html></html>"))).unwrap();
+	let result = db.test_sync_connection(&url);
+	assert!(matches!(result, Err(FortressError::SyncMalformedResponse)), "{result:?}");
+	let url = Url::parse(&sync_server::hostile_server(|_, _, _| empty_response(500))).unwrap();
+	let result = db.test_sync_connection(&url);
+	assert!(matches!(result, Err(FortressError::SyncApiError(_))), "{result:?}");
+}
+
+
+#[test]
+fn account_management_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	assert!(matches!(db.sync_register_account(), Err(FortressError::SyncBadUrl)));
+	db.set_sync_url(Some(Url::parse(&sync_server::server_without_account()).unwrap()));
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Email".to_string())].into_iter().collect()));
+	db.add_entry(entry);
+
+	// Nothing works until the account is registered
+	assert!(matches!(db.sync_account_info(), Err(FortressError::SyncCredentialsRejected)));
+	db.sync_register_account().unwrap();
+	assert!(matches!(db.sync_register_account(), Err(FortressError::SyncAccountExists)));
+	let info = db.sync_account_info().unwrap();
+	assert_eq!((info.object_count, info.storage_used), (0, 0));
+	assert_eq!(info.max_objects, Some(1000));
+	assert_eq!(info.max_storage, None);
+
+	db.sync().unwrap();
+	let info = db.sync_account_info().unwrap();
+	assert!(info.object_count > 0);
+	assert!(info.storage_used > 0);
+
+	// Works before the server has been told about a password change too
+	db.change_password("username", "barfoo").unwrap();
+	assert_eq!(db.sync_account_info().unwrap(), info);
+	db.sync_delete_account(AccountDeletionConfirmed).unwrap();
+	assert!(matches!(db.sync_account_info(), Err(FortressError::SyncCredentialsRejected)));
+	assert!(matches!(db.sync(), Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(401, _)))));
+
+	// Everything is uploaded again to a new account
+	db.sync_register_account().unwrap();
+	db.sync().unwrap();
+	assert_eq!(db.sync_account_info().unwrap().object_count, info.object_count);
+
+	// Servers that predate account management
+	db.set_sync_url(Some(Url::parse(&sync_server::hostile_server(|_, _, _| empty_response(404))).unwrap()));
+	assert!(matches!(db.sync_register_account(), Err(FortressError::SyncAccountManagementUnsupported)));
+	assert!(matches!(db.sync_account_info(), Err(FortressError::SyncAccountManagementUnsupported)));
+}
+
+// Objects too large for the server fail the sync with an error naming the entry, before they're uploaded
+#[test]
+fn object_size_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new(
+		[("title".to_string(), "Scans".to_string()), ("notes".to_string(), "x".repeat(5000))]
+			.into_iter()
+			.collect(),
+	));
+	let id = *entry.get_id();
+	db.add_entry(entry);
+	db.set_sync_url(Some(
+		Url::parse(&sync_server::server_with_max_object_size(db.get_login_key().clone(), Some(4096))).unwrap(),
+	));
+
+	let original = db.clone();
+	let too_large = |result: Result<SyncReport, FortressError>| match result {
+		Err(FortressError::SyncObjectTooLarge {
+			id: error_id,
+			title,
+			field,
+			max,
+			..
+		}) => {
+			assert_eq!(error_id, id);
+			assert_eq!(title.as_deref(), Some("Scans"));
+			assert_eq!(field.as_deref(), Some("notes"));
+			assert_eq!(max, Some(4096));
+		},
+		result => panic!("{result:?}"),
+	};
+	too_large(db.sync_preview());
+	too_large(db.sync());
+	assert_eq!(db, original);
+
+	// A server that doesn't advertise its limit.  It rejects the first upload, which may be the root directory instead.
+	let result = sync_with(&mut db, |method, url, _| match (method, url) {
+		(Method::Get, ["objects"]) => Response::from_string("[]"),
+		_ => empty_response(413),
+	});
+	assert!(matches!(result, Err(FortressError::SyncObjectTooLarge { max: None, .. })), "{result:?}");
+}
+
+
+// A preview lists exactly what the sync then does, without changing anything
+#[test]
+fn sync_preview_test() {
+	let edit = |db: &mut Database, id: &ID, key: &str, value: &str| {
+		db.get_entry_by_id_mut(id)
+			.unwrap()
+			.edit(EntryHistory::new([(key.to_string(), value.to_string())].into_iter().collect()));
+	};
+	let add = |db: &mut Database| {
+		let entry = Entry::new();
+		let id = *entry.get_id();
+		db.add_entry(entry);
+		id
+	};
+
+	let mut db1 = Database::new_with_password("username", "foobar");
+	db1.set_sync_url(Some(Url::parse(&sync_server::server(db1.get_login_key().clone())).unwrap()));
+	let shared = add(&mut db1);
+	db1.sync().unwrap();
+	assert!(db1.sync_preview().unwrap().is_empty());
+
+	let mut db2 = db1.clone();
+	edit(&mut db1, &shared, "title", "Shared");
+	let from_db1 = add(&mut db1);
+	db1.sync().unwrap();
+	edit(&mut db2, &shared, "username", "Alice");
+	let from_db2 = add(&mut db2);
+
+	let original = db2.clone();
+	let preview = db2.sync_preview().unwrap();
+	assert_eq!(db2, original);
+	assert!(db1.sync_preview().unwrap().is_empty());
+
+	let root = *db2.get_root().get_id();
+	let change = |activity, kind, id| SyncChange { activity, kind, id };
+	assert_eq!(
+		preview.changes,
+		[
+			change(SyncActivity::Added, SyncObjectKind::Entry, from_db1),
+			change(SyncActivity::Updated, SyncObjectKind::Entry, shared),
+			change(SyncActivity::Updated, SyncObjectKind::Directory, root),
+			change(SyncActivity::Uploaded, SyncObjectKind::Entry, shared),
+			change(SyncActivity::Uploaded, SyncObjectKind::Entry, from_db2),
+			change(SyncActivity::Uploaded, SyncObjectKind::Directory, root),
+		]
+		.into_iter()
+		.collect()
+	);
+	// Same changes, though not the same traffic
+	let report = db2.sync().unwrap();
+	assert_eq!((report.changes, report.conflicts), (preview.changes, preview.conflicts));
+	assert!(db2.sync_preview().unwrap().is_empty());
+}
+
+
+// Sync reports each object it goes through, in each phase of each round
+#[test]
+fn sync_progress_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	db.set_sync_url(Some(Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap()));
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Email".to_string())].into_iter().collect()));
+	db.add_entry(entry);
+
+	let phase = |round, phase, total| (0..=total).map(move |done| SyncProgress { round, phase, done, total });
+	let mut reports = Vec::new();
+	db.sync_with_progress(&CancellationToken::new(), &mut |progress| reports.push(progress))
+		.unwrap();
+	let total = reports.last().unwrap().total;
+	assert!(total >= 2);
+	assert_eq!(
+		reports,
+		phase(1, SyncPhase::Downloading, 0)
+			.chain(phase(1, SyncPhase::Uploading, total))
+			.collect::<Vec<_>>()
+	);
+
+	// Uploading a change to an object the server has takes another round, to check that it settled
+	let mut other = db.clone();
+	other.get_root_mut().rename("Passwords");
+	let mut reports = Vec::new();
+	other
+		.sync_with_progress(&CancellationToken::new(), &mut |progress| reports.push(progress))
+		.unwrap();
+	assert_eq!(
+		reports,
+		phase(1, SyncPhase::Downloading, total)
+			.chain(phase(1, SyncPhase::Uploading, total))
+			.chain(phase(2, SyncPhase::Downloading, total))
+			.chain(phase(2, SyncPhase::Uploading, total))
+			.collect::<Vec<_>>()
+	);
+}
+
+
+fn empty_response(status: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+	Response::from_data(Vec::new()).with_status_code(status)
+}
+
+// Syncs with a hostile server that, like an older server, doesn't have the capabilities endpoint
+fn sync_with<F>(db: &mut Database, mut handler: F) -> Result<SyncReport, FortressError>
+where
+	F: FnMut(&Method, &[&str], &[u8]) -> Response<std::io::Cursor<Vec<u8>>> + Send + 'static,
+{
+	let handler = move |method: &Method, url: &[&str], body: &[u8]| match (method, url) {
+		(Method::Get, ["capabilities"]) => empty_response(404),
+		_ => handler(method, url, body),
+	};
+	db.set_sync_url(Some(Url::parse(&sync_server::hostile_server(handler)).unwrap()));
+	db.sync()
+}
+
+
+// A malicious or broken server must never be able to crash the client, leave it syncing forever, or corrupt the local database
+#[test]
+fn hostile_server_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Test".to_string())].iter().cloned().collect()));
+	let entry_id = *entry.get_id();
+	db.add_entry(entry);
+	let original = db.clone();
+	let root_id = *db.get_root().get_id();
+	let network_key_suite = NetworkKeySuite::derive(b"username", b"foobar");
+	let listing = move |ids: &[ID]| {
+		let listing = ids
+			.iter()
+			.map(|id| (id.to_hex(), HEXLOWER_PERMISSIVE.encode(&OsRng.gen::<[u8; 32]>())))
+			.collect::<Vec<_>>();
+		Response::from_string(serde_json::to_string(&listing).unwrap())
+	};
+
+	// Garbage capabilities
+	for body in ["", "not json", "\"text\"", "{\"max_object_size\":-1}"] {
+		db.set_sync_url(Some(
+			Url::parse(&sync_server::hostile_server(move |_, _, _| Response::from_string(body))).unwrap(),
+		));
+		let result = db.sync();
+		assert!(matches!(result, Err(FortressError::SyncMalformedResponse)), "{result:?}");
+	}
+
+	// Garbage object listings
+	for body in ["", "not json", "{}", "[[\"zz\",\"zz\"]]", "[[\"00\",\"00\"]]"] {
+		let result = sync_with(&mut db, move |_, _, _| Response::from_string(body));
+		assert!(matches!(result, Err(FortressError::SyncMalformedResponse)), "{result:?}");
+	}
+
+	// Object bodies too short to contain a SIV
+	for len in [0, 1, 31] {
+		let result = sync_with(&mut db, move |method, url, _| match (method, url) {
+			(Method::Get, ["objects"]) => listing(&[root_id]),
+			_ => Response::from_data(vec![0; len]),
+		});
+		assert!(matches!(result, Err(FortressError::SyncMalformedResponse)), "{result:?}");
+	}
+
+	// An object we don't have that can't be decrypted
+	let result = sync_with(&mut db, move |method, url, _| match (method, url) {
+		(Method::Get, ["objects"]) => listing(&[OsRng.gen()]),
+		_ => Response::from_data(vec![0; 100]),
+	});
+	assert!(matches!(result, Err(FortressError::SyncInconsistentServer)), "{result:?}");
+
+	// Validly encrypted objects of the wrong type (this requires the server to know our keys): an entry claiming the root
+	// directory's reserved ID, and a directory with one of our entries' IDs
+	let encrypt_as = |object: serde_json::Value, kind: &str, id: ID| {
+		let mut object = object;
+		object["type"] = kind.into();
+		object["id"] = id.to_hex().into();
+		let encrypted = network_key_suite.encrypt_object(&id[..], &serde_json::to_vec(&object).unwrap());
+		[&encrypted.ciphertext, encrypted.siv.as_ref()].concat()
+	};
+	let reserved = encrypt_as(serde_json::to_value(Entry::new()).unwrap(), "Entry", root_id);
+	let result = sync_with(&mut db, move |method, url, _| match (method, url) {
+		(Method::Get, ["objects"]) => listing(&[root_id]),
+		_ => Response::from_data(reserved.clone()),
+	});
+	assert!(matches!(result, Err(FortressError::ReservedIdViolation(id)) if id == root_id), "{result:?}");
+
+	let mismatched = encrypt_as(serde_json::to_value(Directory::new()).unwrap(), "Directory", entry_id);
+	let result = sync_with(&mut db, move |method, url, _| match (method, url) {
+		(Method::Get, ["objects"]) => listing(&[entry_id]),
+		_ => Response::from_data(mismatched.clone()),
+	});
+	assert!(matches!(result, Err(FortressError::SyncObjectTypeMismatch(id)) if id == entry_id), "{result:?}");
+
+	// A server that accepts uploads but keeps reporting different versions of them
+	let stored = Arc::new(Mutex::new(HashMap::<String, Vec<u8>>::new()));
+	let handler = move |method: &Method, url: &[&str], body: &[u8]| match (method, url) {
+		(Method::Get, ["objects"]) => {
+			let ids = stored
+				.lock()
+				.unwrap()
+				.keys()
+				.map(|id| ID::from_slice(&HEXLOWER_PERMISSIVE.decode(id.as_bytes()).unwrap()).unwrap())
+				.collect::<Vec<_>>();
+			listing(&ids)
+		},
+		(Method::Get, ["object", id]) => Response::from_data(stored.lock().unwrap()[*id].clone()),
+		(Method::Post, ["object", id, _]) => {
+			stored.lock().unwrap().insert(id.to_string(), body.to_vec());
+			empty_response(200)
+		},
+		_ => empty_response(404),
+	};
+	db.set_sync_url(Some(Url::parse(&sync_server::hostile_server(handler)).unwrap()));
+	db.sync().unwrap();
+	assert!(matches!(db.sync(), Err(FortressError::SyncNotConverging)));
+
+	// Error responses are passed through
+	let result = sync_with(&mut db, |_, _, _| empty_response(500));
+	assert!(
+		matches!(result, Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(500, _)))),
+		"{result:?}"
+	);
+
+	// None of that should have touched our data
+	for entry in original.list_entries() {
+		assert_eq!(db.get_entry_by_id(entry.get_id()), Some(entry));
+	}
+	assert_eq!(db.get_root(), original.get_root());
+}
+
+
+// Transient failures are retried, waiting as long as the server asks
+#[test]
+fn retry_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Test".to_string())].into_iter().collect()));
+	db.add_entry(entry);
+
+	let retry_after = |status, value: String| empty_response(status).with_header(Header::from_bytes("Retry-After", value).unwrap());
+	let mut requests = 0;
+	let url = sync_server::flaky_server(db.get_login_key().clone(), move |_, _| {
+		requests += 1;
+		match requests {
+			1 => Some(retry_after(503, "0".to_string())),
+			2 => Some(retry_after(429, httpdate::fmt_http_date(SystemTime::now()))),
+			3 => Some(empty_response(502)),
+			_ => None,
+		}
+	});
+	db.set_sync_url(Some(Url::parse(&url).unwrap()));
+	let report = db.sync().unwrap();
+	assert_eq!(report.count(SyncActivity::Uploaded, SyncObjectKind::Entry), 1);
+
+	// Counts requests to a server that always answers with `response`, and returns the sync's result along with the count
+	let attempts = |db: &mut Database, options: &SyncOptions, response: fn() -> Response<std::io::Cursor<Vec<u8>>>| {
+		let count = Arc::new(Mutex::new(0));
+		let counter = count.clone();
+		db.set_sync_url(Some(
+			Url::parse(&sync_server::hostile_server(move |_, _, _| {
+				*counter.lock().unwrap() += 1;
+				response()
+			}))
+			.unwrap(),
+		));
+		let result = db.sync_with_options(options, &CancellationToken::new(), &mut |_| {});
+		let count = *count.lock().unwrap();
+		(result, count)
+	};
+	let options = SyncOptions {
+		max_retries: 2,
+		initial_retry_delay: Duration::from_millis(10),
+		..SyncOptions::default()
+	};
+
+	// Giving up eventually
+	let (result, count) = attempts(&mut db, &options, || empty_response(503));
+	assert!(
+		matches!(result, Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(503, _)))),
+		"{result:?}"
+	);
+	assert_eq!(count, 3);
+
+	// Errors that would just happen again aren't retried
+	let (result, count) = attempts(&mut db, &options, || empty_response(400));
+	assert!(
+		matches!(result, Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(400, _)))),
+		"{result:?}"
+	);
+	assert_eq!(count, 1);
+
+	// Nor are ones the server wants us to wait too long for
+	let (result, count) = attempts(&mut db, &options, || {
+		empty_response(429).with_header(Header::from_bytes("Retry-After", "3600").unwrap())
+	});
+	assert!(
+		matches!(result, Err(FortressError::SyncApiError(libfortress::ApiError::ApiError(429, _)))),
+		"{result:?}"
+	);
+	assert_eq!(count, 1);
+
+	// The timeout covers waiting between retries
+	let options = SyncOptions {
+		max_retries: 100,
+		timeout: Some(Duration::from_millis(500)),
+		..SyncOptions::default()
+	};
+	let start = Instant::now();
+	let (result, _) = attempts(&mut db, &options, || {
+		empty_response(503).with_header(Header::from_bytes("Retry-After", "1").unwrap())
+	});
+	assert!(matches!(result, Err(FortressError::SyncTimedOut)), "{result:?}");
+	assert!(start.elapsed() < Duration::from_secs(1));
+
+	// And slow responses
+	let (result, _) = attempts(&mut db, &options, || {
+		std::thread::sleep(Duration::from_secs(2));
+		empty_response(200)
+	});
+	assert!(matches!(result, Err(FortressError::SyncTimedOut)), "{result:?}");
+}
+
+
+// An upload that reached the server but whose response was lost is retried, gets 409 Conflict since the server already has
+// our copy, and the sync carries on
+#[test]
+fn lost_upload_response_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Test".to_string())].into_iter().collect()));
+	let entry_id = *entry.get_id();
+	db.add_entry(entry);
+
+	let stored = Arc::new(Mutex::new(HashMap::<String, Vec<u8>>::new()));
+	let server = stored.clone();
+	let mut lost = false;
+	let url = sync_server::hostile_server(move |method, url, body| {
+		let mut stored = server.lock().unwrap();
+		match (method, url) {
+			(Method::Get, ["objects"]) => {
+				let listing = stored
+					.iter()
+					.map(|(id, data)| (id.clone(), HEXLOWER_PERMISSIVE.encode(&data[data.len() - 32..])))
+					.collect::<Vec<_>>();
+				Response::from_string(serde_json::to_string(&listing).unwrap())
+			},
+			(Method::Get, ["object", id]) => Response::from_data(stored[*id].clone()),
+			(Method::Post, ["object", id, old_siv]) => {
+				let current = stored
+					.get(*id)
+					.map_or("0".repeat(64), |data| HEXLOWER_PERMISSIVE.encode(&data[data.len() - 32..]));
+				if current != *old_siv {
+					return empty_response(409);
+				}
+				stored.insert(id.to_string(), body.to_vec());
+				// The first upload of the entry is stored, but the client only sees a gateway error
+				if *id == entry_id.to_hex() && !lost {
+					lost = true;
+					return empty_response(502);
+				}
+				empty_response(200)
+			},
+			_ => empty_response(404),
+		}
+	});
+	db.set_sync_url(Some(Url::parse(&url).unwrap()));
+
+	let report = db.sync().unwrap();
+	assert_eq!(report.count(SyncActivity::Uploaded, SyncObjectKind::Entry), 1);
+	assert!(stored.lock().unwrap().contains_key(&entry_id.to_hex()));
+	assert_eq!(db.sync().unwrap().count(SyncActivity::Uploaded, SyncObjectKind::Entry), 0);
+}
+
+
+// Everything that talks to the sync server goes through the proxy, which resolves the server's name itself
+#[test]
+fn proxy_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new([("title".to_string(), "Test".to_string())].into_iter().collect()));
+	db.add_entry(entry);
+
+	let server = Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap();
+	let sync_url = Url::parse(&format!("http://localhost:{}", server.port().unwrap())).unwrap();
+	let (proxy, hosts) = sync_server::socks_proxy();
+	db.set_sync_url(Some(sync_url.clone()));
+	db.set_sync_proxy(Some(Url::parse(&format!("socks5://{proxy}")).unwrap())).unwrap();
+
+	db.sync().unwrap();
+	assert!(db.test_sync_connection(&sync_url).unwrap().authenticated);
+	let requested = hosts.lock().unwrap().clone();
+	assert!(!requested.is_empty());
+	assert!(requested.iter().all(|host| host == "localhost"), "{requested:?}");
+
+	// Saved with the database
+	let tmp_dir = tempfile::tempdir().unwrap();
+	let path = tmp_dir.path().join("test.fortressdb");
+	db.save_to_path(&path).unwrap();
+	let mut loaded = Database::load_from_path(&path, "foobar").unwrap();
+	assert_eq!(loaded.get_sync_proxy(), db.get_sync_proxy());
+	hosts.lock().unwrap().clear();
+	loaded.sync().unwrap();
+	assert!(!hosts.lock().unwrap().is_empty());
+
+	// Only SOCKS5 proxies
+	for proxy in ["http://127.0.0.1:8080", "socks4://127.0.0.1:9050", "socks5://127.0.0.1"] {
+		assert!(matches!(db.set_sync_proxy(Some(Url::parse(proxy).unwrap())), Err(FortressError::SyncBadProxy)));
+	}
+	assert!(db.get_sync_proxy().is_some());
+	db.set_sync_proxy(None).unwrap();
+	assert_eq!(db.get_sync_proxy(), None);
+}
+
+
+#[test]
+fn traffic_test() {
+	let mut db = Database::new_with_password("username", "foobar");
+	db.add_entries((0..5).map(|i| {
+		let mut entry = Entry::new();
+		entry.edit(EntryHistory::new([("title".to_string(), format!("Entry {i}"))].into_iter().collect()));
+		entry
+	}));
+	db.set_sync_url(Some(Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap()));
+
+	// Everything is new to the server
+	let estimate = db.estimate_sync_size().unwrap();
+	assert_eq!(estimate.uploads, 6);
+	assert_eq!(estimate.downloads, 0);
+	assert_eq!(estimate.listing_bytes, 2);
+
+	let report = db.sync().unwrap();
+	assert_eq!(report.uploaded_sizes.len(), estimate.uploads);
+	assert_eq!(report.uploaded_sizes.values().sum::<u64>(), estimate.upload_bytes);
+	assert_eq!(report.bytes_uploaded, estimate.upload_bytes);
+	assert!(report.downloaded_sizes.is_empty());
+	assert!(report.bytes_downloaded >= estimate.listing_bytes);
+	assert_eq!(db.estimate_sync_size().unwrap().uploads, 0);
+
+	// A change from another device
+	let mut phone = db.clone();
+	let id = *phone.list_entries().next().unwrap().get_id();
+	phone
+		.get_entry_by_id_mut(&id)
+		.unwrap()
+		.edit(EntryHistory::new([("password".to_string(), "x".repeat(1000))].into_iter().collect()));
+	let phone_report = phone.sync().unwrap();
+	assert_eq!(phone_report.uploaded_sizes.keys().collect::<Vec<_>>(), [&id]);
+	assert!(phone_report.uploaded_sizes[&id] > 1000);
+
+	let estimate = db.estimate_sync_size().unwrap();
+	assert_eq!((estimate.uploads, estimate.downloads), (1, 1));
+	let report = db.sync().unwrap();
+	assert_eq!(report.downloaded_sizes.get(&id), phone_report.uploaded_sizes.get(&id));
+	assert!(report.bytes_downloaded > report.downloaded_sizes[&id]);
+	// Only the server's copy changed, so there's nothing to upload after all
+	assert!(report.uploaded_sizes.is_empty());
+
+	// Tombstones from before the gc horizon aren't uploaded
+	let mut db = Database::new_with_password("username", "foobar");
+	let entry = Entry::new();
+	let id = *entry.get_id();
+	db.add_entry(entry);
+	db.trash_object(&id);
+	db.empty_trash(HistoryLossConfirmed);
+	db.set_setting(GC_HORIZON_SETTING, u64::MAX.into());
+	db.set_sync_url(Some(Url::parse(&sync_server::server(db.get_login_key().clone())).unwrap()));
+	let estimate = db.estimate_sync_size().unwrap();
+	let report = db.sync().unwrap();
+	assert_eq!(report.count(SyncActivity::Uploaded, SyncObjectKind::Tombstone), 0);
+	assert_eq!(report.uploaded_sizes.len(), estimate.uploads);
+
+	// Nor is anything estimated for an account the database hasn't synced with before
+	let mut other = Database::new_with_password("other", "foobar");
+	other.add_entry(Entry::new());
+	let other_url = Url::parse(&sync_server::server(other.get_login_key().clone())).unwrap();
+	other.set_sync_url(Some(other_url.clone()));
+	other.sync().unwrap();
+	db.change_password("other", "foobar").unwrap();
+	db.set_sync_url(Some(other_url));
+	assert!(matches!(db.estimate_sync_size(), Err(FortressError::SyncAccountChanged)));
 }
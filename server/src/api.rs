@@ -0,0 +1,222 @@
+// The endpoints libfortress's sync client uses (see SYNCING.md).  Every request is authenticated with
+// `Authorization: Bearer <login ID><login key>`, both in hex; credentials for an account that doesn't exist, or the wrong
+// login key, get 401 Unauthorized.
+//
+// * `GET /capabilities` answers with the server's limits, e.g. `{"max_object_size": 33554432}`.
+// * `GET /objects` lists the account's objects as JSON `[id, siv]` pairs in hex.
+// * `GET /object/<id>` answers with the object as uploaded (its ciphertext followed by its SIV), or 404 if there's no such
+//   object.
+// * `POST /object/<id>/<old siv>` stores the body as object `id`, if the copy it replaces has SIV `old siv` (all zeroes for a
+//   new object); 409 Conflict otherwise.  Objects over the size limit get 413 Payload Too Large, and objects that would take
+//   the account over its quota 507 Insufficient Storage.
+// * `POST /user/login_key` replaces the account's login key with the body (32 bytes), e.g. after a password change.
+// * `POST /user` creates an account for the credentials, if registration is open (403 Forbidden otherwise); 409 Conflict if
+//   the login ID already has one.  `GET /user` answers with the account's usage and quota, and `DELETE /user` deletes the
+//   account and everything in it.
+// * `POST /gc` drops objects whose SIV still matches, given as a JSON list of `[id, siv]` pairs in hex, and answers with a
+//   JSON list of the IDs it dropped (see libfortress's gc module).
+//
+// Requests made with the account's read-only login key can only GET; anything else gets 403 Forbidden.
+use crate::{
+	storage::{parse_id, Access, Storage, Upload},
+	Config,
+};
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+use fortresscrypto::{LoginId, LoginKey, SIV};
+use serde::Serialize;
+use std::io::{self, Cursor, Read};
+use tiny_http::{Header, Method, Request, Response};
+
+
+/// Largest request body read, in bytes, except for object uploads, which are only held to `Config::max_object_size`.
+const MAX_BODY_SIZE: u64 = 64 << 20;
+
+
+#[derive(Serialize)]
+struct Capabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_object_size: Option<usize>,
+}
+
+
+#[derive(Serialize)]
+struct AccountInfo {
+	object_count: u64,
+	storage_used: u64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_objects: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	max_storage: Option<u64>,
+}
+
+
+pub(crate) struct Api {
+	storage: Storage,
+	config: Config,
+}
+
+impl Api {
+	pub fn new(storage: Storage, config: Config) -> Api {
+		Api { storage, config }
+	}
+
+	pub fn handle(&self, mut request: Request) {
+		let response = self.respond(&mut request).unwrap_or_else(|err| {
+			eprintln!("Error handling {} {}: {err}", request.method(), request.url());
+			status(500)
+		});
+
+		if let Err(err) = request.respond(response) {
+			eprintln!("Error responding: {err}");
+		}
+	}
+
+	fn respond(&self, request: &mut Request) -> io::Result<Response<Cursor<Vec<u8>>>> {
+		let method = request.method().clone();
+		let url = request.url().split('?').next().unwrap_or_default().to_owned();
+		let path = url.split('/').skip(1).collect::<Vec<_>>();
+
+		let Some((login_id, login_key)) = credentials(request) else {
+			return Ok(status(401));
+		};
+
+		// Registration is the only request that doesn't need an existing account
+		if (&method, path.as_slice()) == (&Method::Post, ["user"].as_slice()) {
+			return Ok(if !self.config.allow_registration {
+				status(403)
+			} else if self.storage.create_account(&login_id, &login_key)? {
+				status(200)
+			} else {
+				status(409)
+			});
+		}
+
+		let access = match self.storage.authenticate(&login_id, &login_key)? {
+			Some(access) => access,
+			None => return Ok(status(401)),
+		};
+		if access == Access::ReadOnly && method != Method::Get {
+			return Ok(status(403));
+		}
+
+		// The body is only read once we know who's asking, so strangers can't have us hold on to one
+		let max_body_size = match (&method, path.as_slice()) {
+			(Method::Post, ["object", ..]) => self.config.max_object_size.map(|max| max as u64),
+			_ => Some(MAX_BODY_SIZE),
+		};
+		let Some(body) = read_body(request, max_body_size)? else {
+			return Ok(status(413));
+		};
+
+		match (&method, path.as_slice()) {
+			(Method::Get, ["capabilities"]) => Ok(json(&Capabilities {
+				max_object_size: self.config.max_object_size,
+			})),
+			(Method::Get, ["objects"]) => {
+				let objects = self.storage.list_objects(&login_id)?;
+				Ok(json(&objects.iter().map(|(id, siv)| (HEXLOWER.encode(id), siv)).collect::<Vec<_>>()))
+			},
+			(Method::Get, ["object", id]) => {
+				let Some(id) = parse_id(id) else {
+					return Ok(status(400));
+				};
+
+				Ok(match self.storage.get_object(&login_id, &id)? {
+					Some(data) => Response::from_data(data),
+					None => status(404),
+				})
+			},
+			(Method::Post, ["object", id, old_siv]) => {
+				let (Some(id), Some(old_siv)) = (parse_id(id), parse_id(old_siv).and_then(|siv| SIV::from_slice(&siv))) else {
+					return Ok(status(400));
+				};
+				if body.len() <= 32 {
+					return Ok(status(400));
+				}
+
+				Ok(match self.storage.put_object(&login_id, &id, &old_siv, &body, &self.config.quota)? {
+					Upload::Stored => status(200),
+					Upload::Conflict => status(409),
+					Upload::OverQuota => status(507),
+				})
+			},
+			(Method::Post, ["user", "login_key"]) => {
+				let Some(new_key) = LoginKey::from_slice(&body) else {
+					return Ok(status(400));
+				};
+
+				self.storage.set_login_key(&login_id, &new_key)?;
+				Ok(status(200))
+			},
+			(Method::Get, ["user"]) => {
+				let usage = self.storage.usage(&login_id)?;
+				Ok(json(&AccountInfo {
+					object_count: usage.object_count,
+					storage_used: usage.storage_used,
+					max_objects: self.config.quota.max_objects,
+					max_storage: self.config.quota.max_storage,
+				}))
+			},
+			(Method::Delete, ["user"]) => {
+				self.storage.delete_account(&login_id)?;
+				Ok(status(200))
+			},
+			(Method::Post, ["gc"]) => {
+				let Ok(objects) = serde_json::from_slice::<Vec<(String, SIV)>>(&body) else {
+					return Ok(status(400));
+				};
+				let Some(objects) = objects.into_iter().map(|(id, siv)| Some((parse_id(&id)?, siv))).collect::<Option<Vec<_>>>() else {
+					return Ok(status(400));
+				};
+
+				let dropped = self.storage.drop_objects(&login_id, &objects)?;
+				Ok(json(&dropped.iter().map(|id| HEXLOWER.encode(id)).collect::<Vec<_>>()))
+			},
+			(_, ["capabilities" | "objects" | "user" | "gc"] | ["object", ..] | ["user", "login_key"]) => Ok(status(405)),
+			_ => Ok(status(404)),
+		}
+	}
+}
+
+
+// The request's body, or None if it's over `max_size` bytes (None for no limit)
+fn read_body(request: &mut Request, max_size: Option<u64>) -> io::Result<Option<Vec<u8>>> {
+	let Some(max_size) = max_size else {
+		let mut body = Vec::new();
+		request.as_reader().read_to_end(&mut body)?;
+		return Ok(Some(body));
+	};
+
+	// Bodies that say up front they're too large aren't read at all
+	if request.body_length().is_some_and(|length| length as u64 > max_size) {
+		return Ok(None);
+	}
+
+	let mut body = Vec::new();
+	request.as_reader().take(max_size + 1).read_to_end(&mut body)?;
+	Ok(Some(body).filter(|body| body.len() as u64 <= max_size))
+}
+
+
+// The login ID and login key from the request's Authorization header
+fn credentials(request: &Request) -> Option<(LoginId, LoginKey)> {
+	let header = request.headers().iter().find(|header| header.field.equiv("Authorization"))?;
+	let token = header.value.as_str().strip_prefix("Bearer ")?;
+	let token = HEXLOWER_PERMISSIVE.decode(token.trim().as_bytes()).ok()?;
+	if token.len() != 64 {
+		return None;
+	}
+
+	Some((LoginId::from_slice(&token[..32])?, LoginKey::from_slice(&token[32..])?))
+}
+
+
+fn status(code: u16) -> Response<Cursor<Vec<u8>>> {
+	Response::from_data(Vec::new()).with_status_code(code)
+}
+
+
+fn json<T: Serialize>(value: &T) -> Response<Cursor<Vec<u8>>> {
+	Response::from_data(serde_json::to_vec(value).expect("internal error"))
+		.with_header(Header::from_bytes("Content-Type", "application/json").expect("internal error"))
+}
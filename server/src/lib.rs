@@ -0,0 +1,98 @@
+// A sync server for Fortress, so it can be self-hosted.  It answers the requests libfortress's sync client makes (see SYNCING.md
+// and the api module) over plain HTTP, and keeps every account's objects as flat files in a data directory (see the storage
+// module).  It only ever sees the login ID and login key of each account and the encrypted objects; usernames, passwords, and
+// the encryption keys never leave the client.
+//
+// Any number of users can share a server, each with their own account, keyed by login ID.  Registration (`POST /user`) is
+// closed unless `Config::allow_registration` is set, so a private server can be opened up just long enough for its users to
+// register.
+//
+// TLS is left to a reverse proxy in front of the server.  The client asks for `/objects`, `/user`, and so on at the root of the
+// sync URL's host, whatever path the URL has, so the proxy has to forward the whole host to the server.
+mod api;
+mod storage;
+
+use api::Api;
+use std::{
+	error::Error,
+	io,
+	net::{SocketAddr, ToSocketAddrs},
+	path::PathBuf,
+	sync::Arc,
+	thread,
+};
+pub use storage::{Quota, Storage};
+
+
+/// Largest object accepted by default, the most the client will download (libfortress's MAX_OBJECT_SIZE).
+pub const DEFAULT_MAX_OBJECT_SIZE: usize = 32 << 20;
+
+
+#[derive(Clone, Debug)]
+pub struct Config {
+	/// Directory accounts and their objects are kept in (see the storage module).
+	pub data_dir: PathBuf,
+	/// Whether anyone can create an account.
+	pub allow_registration: bool,
+	/// Largest object accepted, in bytes as uploaded (the encrypted object and its SIV); None for no limit.  Advertised by
+	/// `GET /capabilities`.
+	pub max_object_size: Option<usize>,
+	/// Limits on each account.
+	pub quota: Quota,
+}
+
+impl Config {
+	/// A private server keeping its data in `data_dir`, with the default object size limit and no quota.
+	pub fn new<P: Into<PathBuf>>(data_dir: P) -> Config {
+		Config {
+			data_dir: data_dir.into(),
+			allow_registration: false,
+			max_object_size: Some(DEFAULT_MAX_OBJECT_SIZE),
+			quota: Quota::default(),
+		}
+	}
+}
+
+
+pub struct FortressServer {
+	http: Arc<tiny_http::Server>,
+	api: Arc<Api>,
+}
+
+impl FortressServer {
+	/// Opens the data directory (creating it if needed) and starts listening on `addr`.
+	pub fn bind<A: ToSocketAddrs>(addr: A, config: Config) -> Result<FortressServer, Box<dyn Error + Send + Sync>> {
+		let storage = Storage::open(&config.data_dir)?;
+		let http = tiny_http::Server::http(addr)?;
+
+		Ok(FortressServer {
+			http: Arc::new(http),
+			api: Arc::new(Api::new(storage, config)),
+		})
+	}
+
+	/// The address the server is listening on, e.g. to find the port when bound to port 0.
+	pub fn local_addr(&self) -> SocketAddr {
+		self.http.server_addr().to_ip().expect("internal error")
+	}
+
+	/// Handles requests on `threads` threads, until listening fails.
+	pub fn run(self, threads: usize) -> io::Result<()> {
+		let workers = (0..threads.max(1))
+			.map(|_| {
+				let (http, api) = (Arc::clone(&self.http), Arc::clone(&self.api));
+				thread::spawn(move || -> io::Result<()> {
+					loop {
+						api.handle(http.recv()?);
+					}
+				})
+			})
+			.collect::<Vec<_>>();
+
+		for worker in workers {
+			worker.join().expect("worker panicked")?;
+		}
+
+		Ok(())
+	}
+}
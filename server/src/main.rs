@@ -0,0 +1,65 @@
+use clap::Parser;
+use fortress_server::{Config, FortressServer, Quota, DEFAULT_MAX_OBJECT_SIZE};
+use std::{path::PathBuf, process};
+
+
+/// Sync server for Fortress.  Speaks plain HTTP, so put it behind a reverse proxy that terminates TLS.
+#[derive(Parser, Debug)]
+#[clap(version, about, long_about = None)]
+struct Args {
+	/// Directory to keep accounts and their objects in.  Created if it doesn't exist.
+	#[clap(long)]
+	data_dir: PathBuf,
+
+	/// Address to listen on
+	#[clap(long, default_value = "127.0.0.1:8000")]
+	listen: String,
+
+	/// Let anyone with the server's address create an account.  Without this, accounts can't be created, so a private
+	/// server can be run with it just long enough for its users to register.
+	#[clap(long)]
+	allow_registration: bool,
+
+	/// Largest object to accept, in bytes (0 for no limit)
+	#[clap(long, default_value_t = DEFAULT_MAX_OBJECT_SIZE)]
+	max_object_size: usize,
+
+	/// Most objects an account can store
+	#[clap(long)]
+	max_objects: Option<u64>,
+
+	/// Most bytes an account can store
+	#[clap(long)]
+	max_storage: Option<u64>,
+
+	/// Number of requests to handle at once
+	#[clap(long, default_value_t = 4)]
+	threads: usize,
+}
+
+
+fn main() {
+	let args = Args::parse();
+
+	let config = Config {
+		allow_registration: args.allow_registration,
+		max_object_size: Some(args.max_object_size).filter(|max| *max > 0),
+		quota: Quota {
+			max_objects: args.max_objects,
+			max_storage: args.max_storage,
+		},
+		..Config::new(args.data_dir)
+	};
+
+	let server = FortressServer::bind(args.listen.as_str(), config).unwrap_or_else(|err| {
+		eprintln!("Failed to start server on {}: {err}", args.listen);
+		process::exit(1);
+	});
+
+	eprintln!("Listening on {}", server.local_addr());
+
+	if let Err(err) = server.run(args.threads) {
+		eprintln!("Server failed: {err}");
+		process::exit(1);
+	}
+}
@@ -0,0 +1,468 @@
+// Accounts and their objects, kept as flat files in the data directory:
+//
+//   accounts/<login ID>/account.json           hashes of the account's login key and read-only login key
+//   accounts/<login ID>/objects/<object ID>    the object as uploaded: its ciphertext followed by its SIV
+//
+// IDs are in lowercase hex.  Files are written to a temporary file and renamed into place, so readers never see one half
+// written, and a crash leaves either the old version or the new one.  Changes are made under one lock, so an upload that
+// names the SIV it replaces can't race another.  The lock also guards each account's usage, which is counted from its files
+// the first time it's needed and kept up to date as objects are stored and dropped, so quotas don't cost a scan per upload.
+//
+// Only SHA-256 hashes of the login keys are stored, so a copy of the data directory isn't enough to log in to its accounts.
+// The read-only login key's hash is stored alongside, since it's derived from the login key (see SYNCING.md).
+use data_encoding::HEXLOWER;
+use fortresscrypto::{LoginId, LoginKey, SIV};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::{hash_map, HashMap},
+	fs::{self, File},
+	io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+	sync::{Mutex, MutexGuard},
+};
+use subtle::ConstantTimeEq;
+use tempfile::NamedTempFile;
+
+
+/// What credentials give access to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Access {
+	Full,
+	/// Only GET requests (see SYNCING.md).
+	ReadOnly,
+}
+
+
+/// How an upload went.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Upload {
+	Stored,
+	/// The object's SIV isn't the one the upload said it replaces.
+	Conflict,
+	/// Storing it would take the account over `max_objects` or `max_storage`.
+	OverQuota,
+}
+
+
+/// An account's object count and bytes stored.
+#[derive(Serialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Usage {
+	pub object_count: u64,
+	pub storage_used: u64,
+}
+
+
+/// Per-account limits; None is unlimited.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Quota {
+	pub max_objects: Option<u64>,
+	pub max_storage: Option<u64>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+	login_key_hash: String,
+	readonly_login_key_hash: String,
+}
+
+impl Account {
+	fn new(login_key: &LoginKey) -> Account {
+		Account {
+			login_key_hash: hash(&login_key[..]),
+			readonly_login_key_hash: hash(&login_key.readonly_login_key()[..]),
+		}
+	}
+}
+
+
+pub struct Storage {
+	accounts: PathBuf,
+	// Usage of the accounts counted so far, by login ID
+	lock: Mutex<HashMap<[u8; 32], Usage>>,
+}
+
+impl Storage {
+	/// Opens the data directory at `path`, creating it if it doesn't exist.
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Storage> {
+		let accounts = path.as_ref().join("accounts");
+		fs::create_dir_all(&accounts)?;
+
+		Ok(Storage {
+			accounts,
+			lock: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// What `login_key` gives access to in account `login_id`.  None if there's no such account, or the key is wrong.
+	pub fn authenticate(&self, login_id: &LoginId, login_key: &LoginKey) -> io::Result<Option<Access>> {
+		let Some(account) = self.read_account(login_id)? else {
+			return Ok(None);
+		};
+		let key_hash = hash(&login_key[..]);
+
+		if bool::from(key_hash.as_bytes().ct_eq(account.login_key_hash.as_bytes())) {
+			Ok(Some(Access::Full))
+		} else if bool::from(key_hash.as_bytes().ct_eq(account.readonly_login_key_hash.as_bytes())) {
+			Ok(Some(Access::ReadOnly))
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Creates account `login_id`, logged in to with `login_key`.  Returns false if it already exists.
+	pub fn create_account(&self, login_id: &LoginId, login_key: &LoginKey) -> io::Result<bool> {
+		let mut usage = self.lock();
+		let dir = self.account_dir(login_id);
+
+		if dir.join("account.json").exists() {
+			return Ok(false);
+		}
+
+		// Counted again when it's next needed, in case objects were left behind by a deletion that failed partway
+		usage.remove(&login_id[..]);
+		fs::create_dir_all(dir.join("objects"))?;
+		write_atomically(&dir.join("account.json"), &serde_json::to_vec(&Account::new(login_key))?)?;
+		Ok(true)
+	}
+
+	/// Replaces account `login_id`'s login key, which revokes the read-only login key derived from the old one.
+	pub fn set_login_key(&self, login_id: &LoginId, login_key: &LoginKey) -> io::Result<()> {
+		let _guard = self.lock();
+
+		write_atomically(&self.account_dir(login_id).join("account.json"), &serde_json::to_vec(&Account::new(login_key))?)
+	}
+
+	/// Deletes account `login_id` and everything in it.
+	pub fn delete_account(&self, login_id: &LoginId) -> io::Result<()> {
+		let mut usage = self.lock();
+		let dir = self.account_dir(login_id);
+		usage.remove(&login_id[..]);
+
+		// The account file goes first, so an account that's only partly deleted can't be logged in to
+		fs::remove_file(dir.join("account.json"))?;
+		fs::remove_dir_all(dir)
+	}
+
+	/// The ID and SIV of every object in account `login_id`.
+	pub fn list_objects(&self, login_id: &LoginId) -> io::Result<Vec<([u8; 32], SIV)>> {
+		let mut objects = Vec::new();
+
+		for (id, path) in self.object_files(login_id)? {
+			let mut file = match File::open(&path) {
+				Ok(file) => file,
+				// Dropped since it was listed
+				Err(err) if err.kind() == ErrorKind::NotFound => continue,
+				Err(err) => return Err(err),
+			};
+			let mut siv = [0; 32];
+			file.seek(SeekFrom::End(-32))?;
+			file.read_exact(&mut siv)?;
+			objects.push((id, SIV::from_slice(&siv).expect("internal error")));
+		}
+
+		Ok(objects)
+	}
+
+	/// Object `id` in account `login_id`, as it was uploaded.
+	pub fn get_object(&self, login_id: &LoginId, id: &[u8; 32]) -> io::Result<Option<Vec<u8>>> {
+		match fs::read(self.object_path(login_id, id)) {
+			Ok(data) => Ok(Some(data)),
+			Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Stores `data` (ciphertext followed by SIV) as object `id` in account `login_id`, replacing the copy with SIV `old_siv`.
+	/// An all-zero `old_siv` is for objects the server doesn't have yet.
+	pub fn put_object(&self, login_id: &LoginId, id: &[u8; 32], old_siv: &SIV, data: &[u8], quota: &Quota) -> io::Result<Upload> {
+		let mut usage = self.lock();
+		let path = self.object_path(login_id, id);
+
+		let current = self.get_object(login_id, id)?;
+		let current_siv = current.as_deref().map(siv_of).unwrap_or(SIV::from_slice(&[0; 32]).expect("internal error"));
+		if current_siv != *old_siv {
+			return Ok(Upload::Conflict);
+		}
+
+		let usage = self.account_usage(&mut usage, login_id)?;
+		let new_usage = Usage {
+			object_count: usage.object_count + u64::from(current.is_none()),
+			storage_used: usage.storage_used - current.map_or(0, |current| current.len() as u64) + data.len() as u64,
+		};
+		if quota.max_objects.is_some_and(|max| new_usage.object_count > max) || quota.max_storage.is_some_and(|max| new_usage.storage_used > max) {
+			return Ok(Upload::OverQuota);
+		}
+
+		write_atomically(&path, data)?;
+		*usage = new_usage;
+		Ok(Upload::Stored)
+	}
+
+	/// Deletes each of `objects` from account `login_id` whose SIV still matches.  Returns the IDs of those deleted.
+	pub fn drop_objects(&self, login_id: &LoginId, objects: &[([u8; 32], SIV)]) -> io::Result<Vec<[u8; 32]>> {
+		let mut usage = self.lock();
+		let usage = self.account_usage(&mut usage, login_id)?;
+		let mut dropped = Vec::new();
+
+		for (id, siv) in objects {
+			let Some(data) = self.get_object(login_id, id)?.filter(|data| siv_of(data) == *siv) else {
+				continue;
+			};
+
+			fs::remove_file(self.object_path(login_id, id))?;
+			usage.object_count -= 1;
+			usage.storage_used -= data.len() as u64;
+			dropped.push(*id);
+		}
+
+		Ok(dropped)
+	}
+
+	pub fn usage(&self, login_id: &LoginId) -> io::Result<Usage> {
+		let mut usage = self.lock();
+		self.account_usage(&mut usage, login_id).map(|usage| *usage)
+	}
+
+	// Account `login_id`'s entry in `usage`, counting it if it isn't there yet
+	fn account_usage<'a>(&self, usage: &'a mut HashMap<[u8; 32], Usage>, login_id: &LoginId) -> io::Result<&'a mut Usage> {
+		let key = login_id[..].try_into().expect("internal error");
+
+		match usage.entry(key) {
+			hash_map::Entry::Occupied(entry) => Ok(entry.into_mut()),
+			hash_map::Entry::Vacant(entry) => Ok(entry.insert(self.count_usage(login_id)?)),
+		}
+	}
+
+	fn count_usage(&self, login_id: &LoginId) -> io::Result<Usage> {
+		let mut usage = Usage::default();
+
+		for (_, path) in self.object_files(login_id)? {
+			match fs::metadata(path) {
+				Ok(metadata) => {
+					usage.object_count += 1;
+					usage.storage_used += metadata.len();
+				},
+				Err(err) if err.kind() == ErrorKind::NotFound => (),
+				Err(err) => return Err(err),
+			}
+		}
+
+		Ok(usage)
+	}
+
+	fn read_account(&self, login_id: &LoginId) -> io::Result<Option<Account>> {
+		match fs::read(self.account_dir(login_id).join("account.json")) {
+			Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+			Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+			Err(err) => Err(err),
+		}
+	}
+
+	// Every object file in the account, skipping temporary files
+	fn object_files(&self, login_id: &LoginId) -> io::Result<Vec<([u8; 32], PathBuf)>> {
+		let mut files = Vec::new();
+
+		for dir_entry in fs::read_dir(self.account_dir(login_id).join("objects"))? {
+			let dir_entry = dir_entry?;
+			if let Some(id) = dir_entry.file_name().to_str().and_then(parse_id) {
+				files.push((id, dir_entry.path()));
+			}
+		}
+
+		Ok(files)
+	}
+
+	fn account_dir(&self, login_id: &LoginId) -> PathBuf {
+		self.accounts.join(HEXLOWER.encode(&login_id[..]))
+	}
+
+	fn object_path(&self, login_id: &LoginId, id: &[u8; 32]) -> PathBuf {
+		self.account_dir(login_id).join("objects").join(HEXLOWER.encode(id))
+	}
+
+	fn lock(&self) -> MutexGuard<'_, HashMap<[u8; 32], Usage>> {
+		// Usage is only updated once the change it counts has been made, so a panic can't leave it inconsistent
+		self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+}
+
+
+/// Parses a 32 byte ID in hex.
+pub fn parse_id(hex: &str) -> Option<[u8; 32]> {
+	data_encoding::HEXLOWER_PERMISSIVE.decode(hex.as_bytes()).ok()?.try_into().ok()
+}
+
+
+fn siv_of(data: &[u8]) -> SIV {
+	SIV::from_slice(&data[data.len().saturating_sub(32)..]).unwrap_or(SIV::from_slice(&[0; 32]).expect("internal error"))
+}
+
+
+fn hash(data: &[u8]) -> String {
+	HEXLOWER.encode(&Sha256::digest(data))
+}
+
+
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+	let mut file = NamedTempFile::new_in(path.parent().expect("internal error"))?;
+	file.write_all(data)?;
+	file.as_file().sync_all()?;
+	file.persist(path)?;
+	Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{Access, Quota, Storage, Upload, Usage};
+	use fortresscrypto::{LoginId, LoginKey, SIV};
+	use rand::{rngs::OsRng, Rng};
+	use tempfile::tempdir;
+
+	fn object(siv: u8) -> Vec<u8> {
+		[b"ciphertext".as_slice(), &[siv; 32]].concat()
+	}
+
+	fn siv(siv: u8) -> SIV {
+		SIV::from_slice(&[siv; 32]).unwrap()
+	}
+
+	#[test]
+	fn accounts() {
+		let dir = tempdir().unwrap();
+		let storage = Storage::open(dir.path()).unwrap();
+		let (login_id, login_key): (LoginId, LoginKey) = (OsRng.gen(), OsRng.gen());
+
+		assert_eq!(storage.authenticate(&login_id, &login_key).unwrap(), None);
+		assert!(storage.create_account(&login_id, &login_key).unwrap());
+		assert!(!storage.create_account(&login_id, &OsRng.gen()).unwrap());
+		assert_eq!(storage.authenticate(&login_id, &login_key).unwrap(), Some(Access::Full));
+		assert_eq!(
+			storage.authenticate(&login_id, &login_key.readonly_login_key()).unwrap(),
+			Some(Access::ReadOnly)
+		);
+		assert_eq!(storage.authenticate(&login_id, &OsRng.gen()).unwrap(), None);
+
+		// A new login key revokes the old one, and its read-only key
+		let new_key: LoginKey = OsRng.gen();
+		storage.set_login_key(&login_id, &new_key).unwrap();
+		assert_eq!(storage.authenticate(&login_id, &login_key).unwrap(), None);
+		assert_eq!(storage.authenticate(&login_id, &login_key.readonly_login_key()).unwrap(), None);
+		assert_eq!(storage.authenticate(&login_id, &new_key).unwrap(), Some(Access::Full));
+
+		// Reopening the directory keeps everything
+		let id = [1; 32];
+		storage.put_object(&login_id, &id, &siv(0), &object(1), &Quota::default()).unwrap();
+		let storage = Storage::open(dir.path()).unwrap();
+		assert_eq!(storage.authenticate(&login_id, &new_key).unwrap(), Some(Access::Full));
+		assert_eq!(storage.get_object(&login_id, &id).unwrap(), Some(object(1)));
+
+		storage.delete_account(&login_id).unwrap();
+		assert_eq!(storage.authenticate(&login_id, &new_key).unwrap(), None);
+		assert!(storage.create_account(&login_id, &login_key).unwrap());
+		assert!(storage.list_objects(&login_id).unwrap().is_empty());
+	}
+
+	#[test]
+	fn objects() {
+		let dir = tempdir().unwrap();
+		let storage = Storage::open(dir.path()).unwrap();
+		let (login_id, other_id): (LoginId, LoginId) = (OsRng.gen(), OsRng.gen());
+		storage.create_account(&login_id, &OsRng.gen()).unwrap();
+		storage.create_account(&other_id, &OsRng.gen()).unwrap();
+		let quota = Quota::default();
+		let (id1, id2) = ([1; 32], [2; 32]);
+
+		// Uploads have to name the SIV they replace
+		assert_eq!(storage.put_object(&login_id, &id1, &siv(0), &object(1), &quota).unwrap(), Upload::Stored);
+		assert_eq!(storage.put_object(&login_id, &id1, &siv(0), &object(2), &quota).unwrap(), Upload::Conflict);
+		assert_eq!(storage.put_object(&login_id, &id1, &siv(1), &object(2), &quota).unwrap(), Upload::Stored);
+		assert_eq!(storage.put_object(&login_id, &id2, &siv(0), &object(3), &quota).unwrap(), Upload::Stored);
+		assert_eq!(storage.get_object(&login_id, &id1).unwrap(), Some(object(2)));
+		let mut objects = storage.list_objects(&login_id).unwrap();
+		objects.sort_unstable_by_key(|(id, _)| *id);
+		assert_eq!(objects, [(id1, siv(2)), (id2, siv(3))]);
+		assert_eq!(storage.usage(&login_id).unwrap().object_count, 2);
+		assert_eq!(storage.usage(&login_id).unwrap().storage_used, 2 * object(1).len() as u64);
+
+		// Accounts don't see each other's objects
+		assert!(storage.list_objects(&other_id).unwrap().is_empty());
+		assert_eq!(storage.get_object(&other_id, &id1).unwrap(), None);
+
+		// Only objects that haven't changed are dropped
+		assert_eq!(storage.drop_objects(&login_id, &[(id1, siv(1)), (id2, siv(3))]).unwrap(), [id2]);
+		assert_eq!(storage.get_object(&login_id, &id2).unwrap(), None);
+		assert_eq!(storage.list_objects(&login_id).unwrap(), [(id1, siv(2))]);
+	}
+
+	#[test]
+	fn usage() {
+		let dir = tempdir().unwrap();
+		let storage = Storage::open(dir.path()).unwrap();
+		let login_id: LoginId = OsRng.gen();
+		storage.create_account(&login_id, &OsRng.gen()).unwrap();
+		let quota = Quota::default();
+		let long_object = [&[0; 10], object(2).as_slice()].concat();
+
+		// Kept up to date as objects come and go, matching what's counted from the files
+		let counted = || Storage::open(dir.path()).unwrap().usage(&login_id).unwrap();
+		storage.put_object(&login_id, &[1; 32], &siv(0), &object(1), &quota).unwrap();
+		storage.put_object(&login_id, &[1; 32], &siv(1), &long_object, &quota).unwrap();
+		storage.put_object(&login_id, &[2; 32], &siv(0), &object(3), &quota).unwrap();
+		assert_eq!(storage.put_object(&login_id, &[2; 32], &siv(0), &object(4), &quota).unwrap(), Upload::Conflict);
+		assert_eq!(
+			storage.usage(&login_id).unwrap(),
+			Usage {
+				object_count: 2,
+				storage_used: (long_object.len() + object(3).len()) as u64,
+			}
+		);
+		assert_eq!(storage.usage(&login_id).unwrap(), counted());
+
+		storage.drop_objects(&login_id, &[([1; 32], siv(2)), ([2; 32], siv(2))]).unwrap();
+		assert_eq!(
+			storage.usage(&login_id).unwrap(),
+			Usage {
+				object_count: 1,
+				storage_used: object(3).len() as u64,
+			}
+		);
+		assert_eq!(storage.usage(&login_id).unwrap(), counted());
+
+		// A deleted account starts again from nothing
+		storage.delete_account(&login_id).unwrap();
+		storage.create_account(&login_id, &OsRng.gen()).unwrap();
+		assert_eq!(storage.usage(&login_id).unwrap(), Usage::default());
+	}
+
+	#[test]
+	fn quotas() {
+		let dir = tempdir().unwrap();
+		let storage = Storage::open(dir.path()).unwrap();
+		let login_id: LoginId = OsRng.gen();
+		storage.create_account(&login_id, &OsRng.gen()).unwrap();
+
+		let quota = Quota {
+			max_objects: Some(1),
+			max_storage: None,
+		};
+		assert_eq!(storage.put_object(&login_id, &[1; 32], &siv(0), &object(1), &quota).unwrap(), Upload::Stored);
+		assert_eq!(storage.put_object(&login_id, &[2; 32], &siv(0), &object(2), &quota).unwrap(), Upload::OverQuota);
+		// Replacing an object doesn't count it twice
+		assert_eq!(storage.put_object(&login_id, &[1; 32], &siv(1), &object(2), &quota).unwrap(), Upload::Stored);
+
+		let quota = Quota {
+			max_objects: None,
+			max_storage: Some(object(1).len() as u64 + 10),
+		};
+		assert_eq!(
+			storage
+				.put_object(&login_id, &[1; 32], &siv(2), &[&[0; 10], object(3).as_slice()].concat(), &quota)
+				.unwrap(),
+			Upload::Stored
+		);
+		assert_eq!(storage.put_object(&login_id, &[2; 32], &siv(0), &object(4), &quota).unwrap(), Upload::OverQuota);
+	}
+}
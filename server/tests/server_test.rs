@@ -0,0 +1,245 @@
+use fortress_server::{Config, FortressServer, Quota};
+use libfortress::{AccountDeletionConfirmed, Database, Entry, EntryHistory, FortressError, HistoryLossConfirmed, SyncAccountInfo};
+use std::{
+	collections::HashMap,
+	io::{BufRead, BufReader, Write},
+	net::TcpStream,
+	path::Path,
+	thread,
+	time::Duration,
+};
+use tempfile::tempdir;
+use url::Url;
+
+
+fn start(config: Config) -> Url {
+	let server = FortressServer::bind("127.0.0.1:0", config).unwrap();
+	let url = Url::parse(&format!("http://{}", server.local_addr())).unwrap();
+	thread::spawn(move || server.run(2));
+	url
+}
+
+
+fn open_server(data_dir: &Path) -> Url {
+	start(Config {
+		allow_registration: true,
+		..Config::new(data_dir)
+	})
+}
+
+
+fn device(username: &str, password: &str, url: &Url) -> Database {
+	let mut db = Database::new_with_password(username, password);
+	db.set_sync_url(Some(url.clone()));
+	db
+}
+
+
+// Sends a request the sync client wouldn't, and returns the response's status code.  `head` is the request line and any
+// headers.
+fn raw_request(url: &Url, head: &str, body: &[u8]) -> u16 {
+	let mut stream = TcpStream::connect(url.socket_addrs(|| None).unwrap()[0]).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+	stream.write_all(format!("{head}\r\nConnection: close\r\n\r\n").as_bytes()).unwrap();
+	stream.write_all(body).unwrap();
+
+	let mut status_line = String::new();
+	BufReader::new(stream).read_line(&mut status_line).unwrap();
+	status_line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+
+fn entry(title: &str) -> Entry {
+	let mut entry = Entry::new();
+	entry.edit(EntryHistory::new(HashMap::from([("title".to_string(), title.to_string())])));
+	entry
+}
+
+
+#[test]
+fn sync_test() {
+	let data_dir = tempdir().unwrap();
+	let url = open_server(data_dir.path());
+
+	// Nothing works before registering
+	let mut db1 = device("username", "foobar", &url);
+	assert!(matches!(db1.sync(), Err(FortressError::SyncApiError(_))));
+	db1.sync_register_account().unwrap();
+	assert!(matches!(db1.sync_register_account(), Err(FortressError::SyncAccountExists)));
+
+	let entry = entry("Bank");
+	let entry_id = *entry.get_id();
+	db1.add_entry(entry);
+	db1.sync().unwrap();
+
+	let mut db2 = device("username", "foobar", &url);
+	db2.sync().unwrap();
+	assert_eq!(db2.get_entry_by_id(&entry_id).unwrap()["title"], "Bank");
+	db2.get_entry_by_id_mut(&entry_id)
+		.unwrap()
+		.edit(EntryHistory::new(HashMap::from([("title".to_string(), "My Bank".to_string())])));
+	db2.sync().unwrap();
+	db1.sync().unwrap();
+	assert_eq!(db1.get_entry_by_id(&entry_id).unwrap()["title"], "My Bank");
+
+	let info = db1.sync_account_info().unwrap();
+	assert_eq!(info.object_count, db1.list_entries().count() as u64 + db1.list_directories().count() as u64);
+	assert!(info.storage_used > 0);
+	assert_eq!((info.max_objects, info.max_storage), (None, None));
+
+	// Read-only credentials can download but not upload
+	let objects = db1.get_readonly_sync_keys().download_objects(&url).unwrap();
+	assert_eq!(objects.len() as u64, info.object_count);
+	let mut readonly = db1.clone();
+	readonly.make_sync_read_only();
+	readonly.sync().unwrap();
+
+	// A password change reaches the server with the next sync, and locks out the old password
+	db1.change_password("username", "barfoo").unwrap();
+	db1.sync().unwrap();
+	assert!(db2.sync().is_err());
+	assert!(db1.get_readonly_sync_keys().download_objects(&url).is_ok());
+	let mut db3 = device("username", "barfoo", &url);
+	db3.sync().unwrap();
+	assert_eq!(db3.get_entry_by_id(&entry_id).unwrap()["title"], "My Bank");
+
+	// Everything is on disk, so another server on the same data directory has it all
+	let url = open_server(data_dir.path());
+	let mut db4 = device("username", "barfoo", &url);
+	db4.sync().unwrap();
+	assert_eq!(db4.get_entry_by_id(&entry_id).unwrap()["title"], "My Bank");
+
+	db4.sync_delete_account(AccountDeletionConfirmed).unwrap();
+	assert!(matches!(db4.sync_account_info(), Err(FortressError::SyncCredentialsRejected)));
+}
+
+
+#[test]
+fn multiple_users_test() {
+	let data_dir = tempdir().unwrap();
+	let url = open_server(data_dir.path());
+
+	let mut alice = device("alice", "foobar", &url);
+	let mut bob = device("bob", "foobar", &url);
+	alice.sync_register_account().unwrap();
+	bob.sync_register_account().unwrap();
+	let entry = entry("Alice's");
+	let entry_id = *entry.get_id();
+	alice.add_entry(entry);
+	alice.sync().unwrap();
+	bob.sync().unwrap();
+
+	assert!(bob.get_entry_by_id(&entry_id).is_none());
+	assert!(bob.sync_account_info().unwrap().object_count < alice.sync_account_info().unwrap().object_count);
+	let mut eve = device("alice", "wrong", &url);
+	assert!(eve.sync().is_err());
+}
+
+
+#[test]
+fn limits_test() {
+	let data_dir = tempdir().unwrap();
+
+	// Registration is closed by default
+	let url = start(Config::new(data_dir.path()));
+	assert!(matches!(
+		device("username", "foobar", &url).sync_register_account(),
+		Err(FortressError::SyncApiError(_))
+	));
+
+	let url = start(Config {
+		allow_registration: true,
+		max_object_size: Some(1024),
+		quota: Quota {
+			max_objects: Some(100),
+			max_storage: None,
+		},
+		..Config::new(data_dir.path())
+	});
+	let mut db = device("username", "foobar", &url);
+	db.sync_register_account().unwrap();
+	db.sync().unwrap();
+	assert!(matches!(
+		db.sync_account_info().unwrap(),
+		SyncAccountInfo {
+			max_objects: Some(100),
+			max_storage: None,
+			..
+		}
+	));
+
+	db.add_entry(entry(&"x".repeat(2048)));
+	assert!(matches!(db.sync(), Err(FortressError::SyncObjectTooLarge { .. })));
+}
+
+
+#[test]
+fn request_body_test() {
+	let data_dir = tempdir().unwrap();
+	let url = start(Config {
+		allow_registration: true,
+		max_object_size: None,
+		..Config::new(data_dir.path())
+	});
+	let auth = format!("Authorization: Bearer {}", "01".repeat(64));
+	let upload = format!("POST /object/{}/{} HTTP/1.1\r\n{auth}", "02".repeat(32), "00".repeat(32));
+
+	// Bodies aren't waited for until the request is authenticated, so these are answered without ever being sent
+	assert_eq!(
+		raw_request(
+			&url,
+			&format!("{upload}\r\nAuthorization: Bearer {}\r\nContent-Length: 1000000", "03".repeat(64)),
+			b""
+		),
+		401
+	);
+	assert_eq!(raw_request(&url, &format!("{upload}\r\nContent-Length: 1000000"), b""), 401);
+	assert_eq!(
+		raw_request(&url, &format!("POST /user HTTP/1.1\r\n{auth}\r\nContent-Length: 1000000"), b""),
+		200
+	);
+
+	// Other requests are still held to a limit, but with no object size limit uploads can be any size
+	assert_eq!(
+		raw_request(&url, &format!("POST /gc HTTP/1.1\r\n{auth}\r\nContent-Length: {}", 65 << 20), b""),
+		413
+	);
+	let object = vec![0x55; 65 << 20];
+	assert_eq!(raw_request(&url, &format!("{upload}\r\nContent-Length: {}", object.len()), &object), 200);
+
+	let url = start(Config {
+		max_object_size: Some(1024),
+		..Config::new(data_dir.path())
+	});
+	let upload = format!("POST /object/{}/{} HTTP/1.1\r\n{auth}", "04".repeat(32), "00".repeat(32));
+	assert_eq!(raw_request(&url, &format!("{upload}\r\nContent-Length: 1025"), b""), 413);
+	assert_eq!(raw_request(&url, &format!("{upload}\r\nContent-Length: 1024"), &[0x55; 1024]), 200);
+}
+
+
+#[test]
+fn gc_test() {
+	let data_dir = tempdir().unwrap();
+	let url = open_server(data_dir.path());
+	let mut db = device("username", "foobar", &url);
+	db.sync_register_account().unwrap();
+
+	let entry = entry("Old");
+	let entry_id = *entry.get_id();
+	db.add_entry(entry);
+	db.trash_object(&entry_id);
+	db.sync().unwrap();
+	assert_eq!(db.empty_trash(HistoryLossConfirmed), 1);
+	db.sync().unwrap();
+
+	let on_server = |db: &Database| {
+		db.get_readonly_sync_keys()
+			.download_objects(&url)
+			.unwrap()
+			.iter()
+			.any(|(id, _)| *id == entry_id)
+	};
+	assert!(on_server(&db));
+	assert_eq!(db.sync_gc(0).unwrap(), 1);
+	assert!(!on_server(&db));
+}
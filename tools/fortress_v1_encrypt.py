@@ -0,0 +1,76 @@
+#!/usr/bin/env python3
+# The inverse of decrypting and decompressing a Fortress v1 file: compresses and encrypts Fortress v1 JSON (the input of
+# fortress_v1_to_fortress_v2.py) into a fortress1-scrypt-chacha20 file, as described in ENCRYPTION.md.
+# Used to make libfortress/tests/data/legacy.fortress1 from legacy.fortress1.json.  The scrypt parameters are far weaker than
+# Fortress v1's own, so only use this for test data.
+import gzip
+import hashlib
+import hmac
+import os
+import struct
+import sys
+
+
+HEADER = b'fortress1-scrypt-chacha20\0'
+SCRYPT_LOG_N = 8
+SCRYPT_R = 8
+SCRYPT_P = 1
+
+
+def main():
+	if len(sys.argv) != 4:
+		print("Usage: %s <Fortress v1 JSON file> <password> <output file>" % sys.argv[0])
+		sys.exit(1)
+
+	with open(sys.argv[1], 'rb') as f:
+		payload = gzip.compress(f.read(), mtime=0)
+
+	with open(sys.argv[3], 'wb') as f:
+		f.write(encrypt(payload, sys.argv[2].encode('utf-8')))
+
+
+def encrypt(payload, password):
+	salt = os.urandom(32)
+	keys = hashlib.scrypt(password, salt=salt, n=1 << SCRYPT_LOG_N, r=SCRYPT_R, p=SCRYPT_P, dklen=64)
+	cipher_key, mac_key = keys[:32], keys[32:]
+
+	data = HEADER + struct.pack('<BII', SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P) + salt + chacha20(cipher_key, payload)
+	return data + hmac.new(mac_key, data, hashlib.sha256).digest()
+
+
+# The original ChaCha20, with a 64-bit counter and a 64-bit nonce, here always zero
+def chacha20(key, data):
+	def rotl(x, n):
+		return ((x << n) & 0xffffffff) | (x >> (32 - n))
+
+	def quarter_round(s, a, b, c, d):
+		s[a] = (s[a] + s[b]) & 0xffffffff; s[d] = rotl(s[d] ^ s[a], 16)
+		s[c] = (s[c] + s[d]) & 0xffffffff; s[b] = rotl(s[b] ^ s[c], 12)
+		s[a] = (s[a] + s[b]) & 0xffffffff; s[d] = rotl(s[d] ^ s[a], 8)
+		s[c] = (s[c] + s[d]) & 0xffffffff; s[b] = rotl(s[b] ^ s[c], 7)
+
+	output = bytearray()
+
+	for counter, offset in enumerate(range(0, len(data), 64)):
+		state = list(struct.unpack('<4I', b'expand 32-byte k') + struct.unpack('<8I', key) + (counter & 0xffffffff, counter >> 32, 0, 0))
+		working = state[:]
+
+		for _ in range(10):
+			quarter_round(working, 0, 4, 8, 12)
+			quarter_round(working, 1, 5, 9, 13)
+			quarter_round(working, 2, 6, 10, 14)
+			quarter_round(working, 3, 7, 11, 15)
+			quarter_round(working, 0, 5, 10, 15)
+			quarter_round(working, 1, 6, 11, 12)
+			quarter_round(working, 2, 7, 8, 13)
+			quarter_round(working, 3, 4, 9, 14)
+
+		keystream = struct.pack('<16I', *((w + s) & 0xffffffff for w, s in zip(working, state)))
+		block = data[offset:offset + 64]
+		output.extend(b ^ k for b, k in zip(block, keystream))
+
+	return bytes(output)
+
+
+if __name__ == '__main__':
+	main()
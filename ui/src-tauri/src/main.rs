@@ -1,16 +1,39 @@
 #![cfg_attr(all(not(debug_assertions), target_os = "windows"), windows_subsystem = "windows")]
 
+#[cfg(unix)]
+mod agent;
+mod native_host;
+#[cfg(unix)]
+mod serve;
+#[cfg(unix)]
+mod unix_socket;
+mod watched_database;
+
 use std::{
-	collections::HashMap,
+	collections::{BTreeMap, BTreeSet, HashMap},
 	fs::{self, File},
-	io::{self, BufReader, Read, Write},
+	io::{self, BufRead, BufReader, Read, Write},
 	path::{Path, PathBuf},
-	sync::Mutex,
+	process::{Command, Stdio},
+	sync::{Mutex, MutexGuard},
+	thread,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use clap::{Parser, Subcommand};
-use libfortress::{fortresscrypto::CryptoError, Database, Directory, Entry, EntryHistory, FortressError, ID};
+use libfortress::{
+	clipboard::{Clipboard, SystemClipboard, DEFAULT_CLIPBOARD_TIMEOUT},
+	fortresscrypto::{CryptoError, FileKdfParameters, SharePublicKey, Zeroizing},
+	keychain::SystemKeychain,
+	AccountChangeConfirmed, AccountDeletionConfirmed, AuditOptions, BackupPolicy, CancellationToken, ChangeLogFormat, Database, DatabaseStats, DeriveProgress,
+	Directory, Entry, EntryHistory, ExportOptions, FortressError, GeneratorOptions, HistoryLossConfirmed, ImportPlan, IntegrityReport, LocalSettings,
+	PaperBackupEntries, PaperBackupOptions, SearchHit, SelectionFormat, SyncAccountInfo, SyncConflict, SyncOptions, SyncProgress, SyncReport, SyncSizeEstimate,
+	TrashedObject, ID,
+};
+use serde::Serialize;
+use tauri::Manager;
 use url::Url;
+use watched_database::WatchedDatabase;
 
 
 #[derive(Parser, Debug)]
@@ -28,15 +51,420 @@ struct Args {
 	#[cfg(not(debug_assertions))]
 	#[clap(long, value_parser)]
 	dir: Option<PathBuf>,
+
+	/// Read the password from this file descriptor instead of prompting for it (Unix only)
+	#[clap(long, global = true)]
+	password_fd: Option<i32>,
+
+	/// Read the password from this environment variable instead of prompting for it
+	#[clap(long, global = true, conflicts_with = "password_fd")]
+	password_env: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-	/// Just encrypt the specified payload, writing to stdout
-	Encrypt { path: PathBuf },
+	/// Just encrypt the specified payload (- for stdin), writing to stdout
+	Encrypt {
+		path: PathBuf,
+
+		/// Write to this file instead of stdout
+		#[clap(long, short, default_value = "-")]
+		output: PathBuf,
+	},
+
+	/// Just decrypt the specified payload (- for stdin), writing to stdout
+	Decrypt {
+		path: PathBuf,
+
+		/// Write to this file instead of stdout
+		#[clap(long, short, default_value = "-")]
+		output: PathBuf,
+	},
+
+	/// Export a database as JSON (see EXPORT.md), writing to stdout
+	Export {
+		path: PathBuf,
+
+		/// Only export the current state of each object, not its history
+		#[clap(long)]
+		no_history: bool,
+
+		/// Leave out protected fields such as passwords
+		#[clap(long)]
+		no_secrets: bool,
+	},
+
+	/// Write a log of every change made to a database's entries and directories to stdout, as JSON (or CSV).  Protected fields are
+	/// redacted.
+	ChangeLog {
+		path: PathBuf,
+
+		/// Only include changes made at or after this Unix time (in seconds)
+		#[clap(long, default_value_t = 0)]
+		since: u64,
+
+		/// Write CSV instead of JSON
+		#[clap(long)]
+		csv: bool,
+	},
+
+	/// Print a health report of a database's passwords to stdout: weak, reused and old passwords, entries without a URL, and
+	/// entries that have never been used.  The database isn't changed.
+	Audit {
+		path: PathBuf,
+
+		/// Passwords that haven't been changed for this many days count as old
+		#[clap(long, default_value_t = 365)]
+		max_age_days: u64,
+
+		/// Write JSON instead, listing entries by ID
+		#[clap(long)]
+		json: bool,
+	},
+
+	/// Import a 1Password export (.1pux or .csv) into a database.  Each vault becomes a directory; CSV exports go in the root
+	/// directory.
+	#[clap(name = "import-1password")]
+	Import1password {
+		path: PathBuf,
+
+		/// The export to import
+		export: PathBuf,
+
+		/// Only print what would be imported, without changing the database
+		#[clap(long)]
+		dry_run: bool,
+	},
+
+	/// Import passwords exported from Chrome, Edge, or Firefox (.csv) into a database's root directory.  Logins for the same site
+	/// and username become one entry; if their passwords differ, the entry is tagged `import-conflict` for review.
+	ImportBrowser {
+		path: PathBuf,
+
+		/// The exported CSV file
+		csv: PathBuf,
+
+		/// Only print what would be imported, without changing the database
+		#[clap(long)]
+		dry_run: bool,
+	},
+
+	/// Re-encrypt a database file with new KDF parameters, keeping a backup of the original
+	Reencrypt {
+		path: PathBuf,
+
+		/// Scrypt log2(N); defaults to the current recommended value
+		#[clap(long)]
+		log_n: Option<u8>,
+
+		/// Scrypt r; defaults to the current recommended value
+		#[clap(long)]
+		r: Option<u32>,
+
+		/// Scrypt p; defaults to the current recommended value
+		#[clap(long)]
+		p: Option<u32>,
+	},
+
+	/// Convert a legacy fortress1 database to the current format, keeping a backup of the original
+	UpgradeLegacy {
+		path: PathBuf,
+
+		/// Username to use for syncing (fortress1 databases don't have one)
+		#[clap(long)]
+		username: String,
+	},
+
+	/// Write a printable, encrypted paper backup of a database to stdout, for recovering it if every device is lost
+	PaperBackup {
+		path: PathBuf,
+
+		/// Include entries with this tag (by default, entries tagged "paper-backup")
+		#[clap(long)]
+		tag: Option<String>,
+
+		/// Include every entry
+		#[clap(long, conflicts_with = "tag")]
+		all: bool,
+
+		/// Only include the username and sync URL; entries can be recovered by syncing
+		#[clap(long, conflicts_with_all = ["tag", "all"])]
+		no_entries: bool,
+	},
+
+	/// Restore a database from a paper backup, saving it to PATH (which must not exist yet)
+	RestorePaperBackup { backup: PathBuf, path: PathBuf },
+
+	/// Add an entry to a database, printing its ID to stdout
+	Add {
+		path: PathBuf,
+
+		/// Field to set, as NAME=VALUE (e.g. title=Email); can be repeated.  Values on the command line may be visible to
+		/// other users of this machine, so prefer --generate for passwords.
+		#[clap(long = "field", short, value_parser = parse_field, required_unless_present = "generate")]
+		fields: Vec<(String, String)>,
+
+		/// Set the password field to a newly generated password
+		#[clap(long)]
+		generate: bool,
+
+		#[command(flatten)]
+		generator: GeneratorArgs,
+
+		/// Directory to add the entry to (ID prefix or name); defaults to the root
+		#[clap(long)]
+		parent: Option<String>,
+	},
+
+	/// Print an entry's fields to stdout, or copy one of them to the clipboard.  Protected fields are hidden unless --reveal or
+	/// --field is given.
+	Show {
+		path: PathBuf,
+
+		/// Entry to show (ID prefix or title)
+		entry: String,
+
+		/// Show protected fields such as passwords
+		#[clap(long)]
+		reveal: bool,
+
+		/// Only print the value of this field
+		#[clap(long)]
+		field: Option<String>,
+
+		/// Copy the field given by --field (the password by default) to the clipboard instead of printing it, and clear it from
+		/// the clipboard again after --clear-after seconds
+		#[clap(long)]
+		copy: bool,
+
+		/// Seconds before a copied field is cleared from the clipboard
+		#[clap(long, default_value_t = DEFAULT_CLIPBOARD_TIMEOUT.as_secs(), requires = "copy")]
+		clear_after: u64,
+	},
+
+	/// Edit an entry's fields
+	Edit {
+		path: PathBuf,
+
+		/// Entry to edit (ID prefix or title)
+		entry: String,
+
+		/// Field to set, as NAME=VALUE; can be repeated
+		#[clap(long = "field", short, value_parser = parse_field, required_unless_present = "generate")]
+		fields: Vec<(String, String)>,
+
+		/// Set the password field to a newly generated password
+		#[clap(long)]
+		generate: bool,
+
+		#[command(flatten)]
+		generator: GeneratorArgs,
+	},
+
+	/// List the contents of a directory
+	Ls {
+		path: PathBuf,
+
+		/// Directory to list (ID prefix or name); defaults to the root
+		directory: Option<String>,
+
+		/// Also list the contents of subdirectories
+		#[clap(long, short)]
+		recursive: bool,
+	},
+
+	/// Move an entry or directory into another directory
+	Mv {
+		path: PathBuf,
+
+		/// Entry or directory to move (ID prefix, title, or name)
+		object: String,
+
+		/// Directory to move it to (ID prefix or name)
+		directory: String,
+	},
+
+	/// Print the public key others need to share entries with a database
+	ShareKey { path: PathBuf },
+
+	/// Encrypt entries for another Fortress user, writing the bundle to OUTPUT for them to import with import-share.  Only the
+	/// entries' current fields are shared, not their history.
+	Share {
+		path: PathBuf,
+
+		/// The recipient's public key, as printed by their share-key
+		#[clap(long)]
+		recipient: String,
+
+		/// Entries to share (ID prefix or title)
+		#[clap(required = true)]
+		entries: Vec<String>,
+
+		#[clap(long, short)]
+		output: PathBuf,
+	},
+
+	/// Add the entries in a bundle from share to a database, or update the ones it already has
+	ImportShare { path: PathBuf, bundle: PathBuf },
+
+	/// Make a database need the YubiKey plugged in (HMAC-SHA1 challenge-response in slot 2, via ykchalresp) as well as its
+	/// password to open.  There is no way to open the database without the YubiKey afterwards.
+	HardwareKey {
+		path: PathBuf,
+
+		/// Go back to only needing the password
+		#[clap(long)]
+		disable: bool,
+	},
+
+	/// Double-lock an entry: hide every field but its title and URL behind a PIN, read from stdin, even while the database is
+	/// open (see the unlock command)
+	DoubleLock {
+		path: PathBuf,
+
+		/// Entry to lock (ID prefix or title)
+		entry: String,
+
+		/// Put the entry's fields back instead, so it only needs the database's password again
+		#[clap(long)]
+		remove: bool,
+	},
+
+	/// Print a double-locked entry's fields to stdout, including protected ones, given its PIN on stdin
+	Unlock {
+		path: PathBuf,
+
+		/// Entry to unlock (ID prefix or title)
+		entry: String,
+	},
+
+	/// Let another password open a database file too, e.g. to share it with a partner.  The new user's password is read from
+	/// stdin.  Only the database's own password is used for syncing.
+	AddUser { path: PathBuf },
+
+	/// Stop a user's password from opening a database file (see the users command).  The users after it move down by one.
+	RemoveUser { path: PathBuf, user: usize },
+
+	/// List the users whose passwords open a database file, by number; user 0 is the database's own password
+	Users { path: PathBuf },
+
+	/// Permanently delete everything in a database's trash, history and all.  The deletion syncs to other devices, unless
+	/// they edit the deleted entries before hearing of it.
+	EmptyTrash { path: PathBuf },
+
+	/// Sync a database, then have its sync server drop what was permanently deleted (see the empty-trash command) long enough
+	/// ago.  Devices that haven't synced since then will bring it back.
+	Gc {
+		path: PathBuf,
+
+		/// Only drop deletions made more than this many days ago
+		#[clap(long, default_value_t = 30)]
+		days: u64,
+	},
+
+	/// Generate a random password, writing it to stdout
+	Generate {
+		#[command(flatten)]
+		generator: GeneratorArgs,
+	},
+
+	/// Sync a database with its sync server
+	Sync {
+		path: PathBuf,
+
+		/// Merge in the entries of the account the database was switched to, even though it was last synced with a different
+		/// account.  The merge can't be undone.
+		#[clap(long)]
+		merge_accounts: bool,
+
+		/// Only print what a sync would change, without changing anything
+		#[clap(long, conflicts_with = "merge_accounts")]
+		dry_run: bool,
+	},
+
+	/// Serve a browser extension over the native messaging protocol on stdin and stdout.  Browsers run this themselves, as
+	/// set up by the extension's native messaging host manifest.
+	NativeHost {
+		/// Database to use; defaults to the one the app last opened
+		#[clap(long)]
+		database: Option<PathBuf>,
+
+		/// Browsers pass the extension's origin (and on Windows, the parent window), which aren't needed
+		#[clap(hide = true, trailing_var_arg = true, allow_hyphen_values = true)]
+		browser_args: Vec<String>,
+	},
+
+	/// Unlock a database and answer requests from scripts on a Unix socket until stopped (Unix only).  See serve.rs for the
+	/// protocol.
+	Serve {
+		path: PathBuf,
+
+		/// Socket to listen on, e.g. $XDG_RUNTIME_DIR/fortress.sock
+		#[clap(long)]
+		socket: PathBuf,
+	},
+
+	/// Unlock a database and act as an SSH agent for the OpenSSH private keys stored in its entries until stopped (Unix only).
+	/// Only unencrypted Ed25519 and ECDSA P-256 keys are supported.
+	Agent {
+		path: PathBuf,
+
+		/// Socket to listen on, e.g. $XDG_RUNTIME_DIR/fortress-agent.sock; point SSH_AUTH_SOCK at it
+		#[clap(long)]
+		socket: PathBuf,
+	},
+}
+
+#[derive(clap::Args, Debug)]
+struct GeneratorArgs {
+	/// Length of generated passwords
+	#[clap(long, default_value_t = 20)]
+	length: usize,
+
+	/// Leave uppercase letters out of generated passwords
+	#[clap(long)]
+	no_uppercase: bool,
+
+	/// Leave lowercase letters out of generated passwords
+	#[clap(long)]
+	no_lowercase: bool,
+
+	/// Leave numbers out of generated passwords
+	#[clap(long)]
+	no_numbers: bool,
+
+	/// Other characters to use in generated passwords
+	#[clap(long, default_value = "")]
+	others: String,
+
+	/// Leave easily confused characters (0, O, 1, l, I) out of generated passwords
+	#[clap(long)]
+	exclude_ambiguous: bool,
+
+	/// Use at least one character of each kind in generated passwords
+	#[clap(long)]
+	require_each_class: bool,
+
+	/// Use each character at most once in generated passwords
+	#[clap(long)]
+	no_repeats: bool,
+}
 
-	/// Just decrypt the specified payload, writing to stdout
-	Decrypt { path: PathBuf },
+impl GeneratorArgs {
+	fn generate(&self) -> String {
+		let options = GeneratorOptions {
+			length: self.length,
+			uppercase: !self.no_uppercase,
+			lowercase: !self.no_lowercase,
+			numbers: !self.no_numbers,
+			others: self.others.clone(),
+			exclude_ambiguous: self.exclude_ambiguous,
+			require_each_selected_class: self.require_each_class,
+			no_repeated_characters: self.no_repeats,
+		};
+
+		options.generate().unwrap_or_else(|err| panic!("{}", format_fortress_error(err)))
+	}
 }
 
 
@@ -45,31 +473,246 @@ fn main() {
 
 	// Handle encrypt/decrypt commands
 	match &args.command {
-		Some(Commands::Encrypt { path }) => {
-			let password = read_password();
+		Some(Commands::Encrypt { path, output }) => {
+			let password = read_payload_password(&args, path);
+
+			do_encrypt(path, output, &password);
+			return;
+		},
+		Some(Commands::Decrypt { path, output }) => {
+			let password = read_payload_password(&args, path);
+
+			do_decrypt(path, output, &password);
+			return;
+		},
+		Some(Commands::Export { path, no_history, no_secrets }) => {
+			let password = read_password(&args);
+			let options = ExportOptions {
+				include_history: !no_history,
+				include_secrets: !no_secrets,
+			};
+
+			do_export(path, &password, &options);
+			return;
+		},
+		Some(Commands::ChangeLog { path, since, csv }) => {
+			let password = read_password(&args);
+			let format = if *csv { ChangeLogFormat::Csv } else { ChangeLogFormat::Json };
+
+			do_change_log(path, &password, since.saturating_mul(1_000_000_000), format);
+			return;
+		},
+		Some(Commands::Audit { path, max_age_days, json }) => {
+			let password = read_password(&args);
+			let options = AuditOptions {
+				max_password_age: max_age_days.saturating_mul(24 * 60 * 60 * 1_000_000_000),
+				..AuditOptions::default()
+			};
+
+			do_audit(path, &password, &options, *json);
+			return;
+		},
+		Some(Commands::Import1password { path, export, dry_run }) => {
+			let password = read_password(&args);
+
+			do_import_1password(path, &password, export, *dry_run);
+			return;
+		},
+		Some(Commands::ImportBrowser { path, csv, dry_run }) => {
+			let password = read_password(&args);
+
+			do_import_browser(path, &password, csv, *dry_run);
+			return;
+		},
+		Some(Commands::Reencrypt { path, log_n, r, p }) => {
+			let password = read_password(&args);
+
+			do_reencrypt(path, &password, *log_n, *r, *p);
+			return;
+		},
+		Some(Commands::UpgradeLegacy { path, username }) => {
+			let password = read_password(&args);
+
+			do_upgrade_legacy(path, username, &password);
+			return;
+		},
+		Some(Commands::PaperBackup { path, tag, all, no_entries }) => {
+			let password = read_password(&args);
+			let entries = match (tag, all, no_entries) {
+				(_, true, _) => PaperBackupEntries::All,
+				(_, _, true) => PaperBackupEntries::None,
+				(Some(tag), _, _) => PaperBackupEntries::Tagged(tag.clone()),
+				(None, _, _) => PaperBackupOptions::default().entries,
+			};
+
+			do_paper_backup(path, &password, &PaperBackupOptions { entries });
+			return;
+		},
+		Some(Commands::RestorePaperBackup { backup, path }) => {
+			let password = read_password(&args);
+
+			do_restore_paper_backup(backup, path, &password);
+			return;
+		},
+		Some(Commands::Add {
+			path,
+			fields,
+			generate,
+			generator,
+			parent,
+		}) => {
+			let password = read_password(&args);
+			let generated = generate.then(|| generator.generate());
+
+			do_add(path, &password, fields, generated, parent.as_deref());
+			return;
+		},
+		Some(Commands::Show {
+			path,
+			entry,
+			reveal,
+			field,
+			copy,
+			clear_after,
+		}) => {
+			let password = read_password(&args);
+			let copy = copy.then(|| Duration::from_secs(*clear_after));
+
+			do_show(path, &password, entry, *reveal, field.as_deref(), copy);
+			return;
+		},
+		Some(Commands::Edit {
+			path,
+			entry,
+			fields,
+			generate,
+			generator,
+		}) => {
+			let password = read_password(&args);
+			let generated = generate.then(|| generator.generate());
+
+			do_edit(path, &password, entry, fields, generated);
+			return;
+		},
+		Some(Commands::Ls { path, directory, recursive }) => {
+			let password = read_password(&args);
+
+			do_ls(path, &password, directory.as_deref(), *recursive);
+			return;
+		},
+		Some(Commands::Mv { path, object, directory }) => {
+			let password = read_password(&args);
+
+			do_mv(path, &password, object, directory);
+			return;
+		},
+		Some(Commands::ShareKey { path }) => {
+			let password = read_password(&args);
+
+			println!("{}", load_database(path, &password).get_share_public_key().to_hex());
+			return;
+		},
+		Some(Commands::Share {
+			path,
+			recipient,
+			entries,
+			output,
+		}) => {
+			let password = read_password(&args);
+
+			do_share(path, &password, recipient, entries, output);
+			return;
+		},
+		Some(Commands::ImportShare { path, bundle }) => {
+			let password = read_password(&args);
+
+			do_import_share(path, &password, bundle);
+			return;
+		},
+		Some(Commands::HardwareKey { path, disable }) => {
+			let password = read_password(&args);
+
+			do_hardware_key(path, &password, *disable);
+			return;
+		},
+		Some(Commands::DoubleLock { path, entry, remove }) => {
+			let password = read_password(&args);
+
+			do_double_lock(path, &password, entry, *remove);
+			return;
+		},
+		Some(Commands::Unlock { path, entry }) => {
+			let password = read_password(&args);
+
+			do_unlock(path, &password, entry);
+			return;
+		},
+		Some(Commands::AddUser { path }) => {
+			let password = read_password(&args);
+
+			do_add_user(path, &password);
+			return;
+		},
+		Some(Commands::RemoveUser { path, user }) => {
+			let password = read_password(&args);
+
+			do_remove_user(path, &password, *user);
+			return;
+		},
+		Some(Commands::Users { path }) => {
+			let password = read_password(&args);
+
+			do_users(path, &password);
+			return;
+		},
+		Some(Commands::EmptyTrash { path }) => {
+			let password = read_password(&args);
+
+			do_empty_trash(path, &password);
+			return;
+		},
+		Some(Commands::Gc { path, days }) => {
+			let password = read_password(&args);
+
+			do_gc(path, &password, *days);
+			return;
+		},
+		Some(Commands::Generate { generator }) => {
+			println!("{}", generator.generate());
+			return;
+		},
+		Some(Commands::Sync { path, merge_accounts, dry_run }) => {
+			let password = read_password(&args);
+
+			do_sync(path, &password, *merge_accounts, *dry_run);
+			return;
+		},
+		Some(Commands::NativeHost { database, .. }) => {
+			let path = database.clone().unwrap_or_else(|| last_database_path(&data_dir(args.dir.clone())));
+
+			// stdout is the browser's, so errors can only go to stderr
+			if let Err(err) = native_host::run(path) {
+				eprintln!("Native messaging failed: {err}");
+			}
+			return;
+		},
+		Some(Commands::Serve { path, socket }) => {
+			let password = read_password(&args);
 
-			do_encrypt(path, &password);
+			do_serve(path, socket, password);
 			return;
 		},
-		Some(Commands::Decrypt { path }) => {
-			let password = read_password();
+		Some(Commands::Agent { path, socket }) => {
+			let password = read_password(&args);
 
-			do_decrypt(path, &password);
+			do_agent(path, socket, password);
 			return;
 		},
 		None => {},
 	}
 
 	// Handle normal operation
-	#[cfg(not(debug_assertions))]
-	let data_dir = args.dir.unwrap_or_else(|| {
-		directories::ProjectDirs::from("", "", "Fortress")
-			.expect("Unable to find data dir")
-			.data_dir()
-			.to_owned()
-	});
-	#[cfg(debug_assertions)]
-	let data_dir = args.dir.expect("Data dir is required in debug mode");
+	let data_dir = data_dir(args.dir);
 
 	fs::create_dir_all(&data_dir).expect("Failed to create data directory");
 
@@ -78,53 +721,297 @@ fn main() {
 		return;
 	}
 
-	let database_path = data_dir.join("database.fortress");
+	let database_path = last_database_path(&data_dir);
 	let appstate = AppState {
-		database_path,
+		database_path: Mutex::new(database_path),
+		data_dir,
 		database: Mutex::new(None),
+		last_activity: Mutex::new(Instant::now()),
+		cancellation: Mutex::new(CancellationToken::new()),
 	};
 
 	tauri::Builder::default()
 		.manage(appstate)
+		.setup(|app| {
+			spawn_autolock_timer(app.handle());
+			Ok(())
+		})
 		.invoke_handler(tauri::generate_handler![
 			database_exists,
+			get_database_path,
+			open_database_at,
+			list_recent_databases,
+			copy_entries_to_database,
 			create_database,
 			unlock_database,
+			unlock_with_keychain,
+			set_keychain_unlock,
+			set_hardware_key,
 			list_entries,
 			list_directories,
+			search_entries,
+			find_entries_for_url,
+			get_resolved_fields,
+			record_entry_access,
+			list_recent_entries,
+			export_selection,
+			get_share_public_key,
+			export_share,
+			import_share,
 			error_dialog,
 			move_object,
+			export_database,
+			delete_entry,
+			delete_directory,
+			list_trash,
+			restore,
 			rename_directory,
+			set_directory_local_only,
 			new_directory,
-			random_string,
+			generate_password,
+			estimate_password_entropy,
 			edit_entry,
+			create_entries,
+			get_entry_history,
+			revert_entry_field,
 			get_username,
 			get_sync_keys,
+			get_readonly_sync_keys,
 			get_sync_url,
 			set_sync_url,
+			get_sync_proxy,
+			set_sync_proxy,
 			change_password,
-			sync_database
+			sync_database,
+			database_changed_on_disk,
+			reload_database,
+			preview_sync,
+			estimate_sync_size,
+			check_integrity,
+			get_database_stats,
+			repair_database,
+			test_sync_connection,
+			confirm_account_change,
+			register_sync_account,
+			get_sync_account_info,
+			delete_sync_account,
+			cancel_operation,
+			lock_database,
+			record_activity,
+			get_autolock_minutes,
+			set_autolock_minutes,
+			get_backup_count,
+			set_backup_count,
+			copy_secret,
+			get_clipboard_clear_seconds,
+			set_clipboard_clear_seconds,
+			get_settings,
+			set_setting,
+			get_local_settings,
+			set_local_settings
 		])
 		.run(tauri::generate_context!())
 		.expect("error while running tauri application");
 }
 
 
+// In debug mode we won't auto-fill dir with the user's data dir (see Args::dir).
+fn data_dir(dir: Option<PathBuf>) -> PathBuf {
+	#[cfg(not(debug_assertions))]
+	let data_dir = dir.unwrap_or_else(|| {
+		directories::ProjectDirs::from("", "", "Fortress")
+			.expect("Unable to find data dir")
+			.data_dir()
+			.to_owned()
+	});
+	#[cfg(debug_assertions)]
+	let data_dir = dir.expect("Data dir is required in debug mode");
+
+	data_dir
+}
+
+
 struct AppState {
-	database_path: PathBuf,
+	/// The database the commands work on, which may not exist yet (see `open_database_at`).  Only changed while `database` is locked.
+	database_path: Mutex<PathBuf>,
+	data_dir: PathBuf,
 	database: Mutex<Option<Database>>,
+	/// When the user last did anything, for auto-lock (see `spawn_autolock_timer`).
+	last_activity: Mutex<Instant>,
+	/// Token for the long-running operation in progress, if any (see `cancel_operation`).
+	cancellation: Mutex<CancellationToken>,
+}
+
+impl AppState {
+	/// Starts a new cancellable operation, returning its token.
+	fn begin_operation(&self) -> CancellationToken {
+		let token = CancellationToken::new();
+		*self.cancellation.lock().unwrap() = token.clone();
+		token
+	}
+
+	/// Locks the database for a command, which counts as user activity.
+	fn use_database(&self) -> MutexGuard<'_, Option<Database>> {
+		self.record_activity();
+		self.database.lock().unwrap()
+	}
+
+	fn record_activity(&self) {
+		*self.last_activity.lock().unwrap() = Instant::now();
+	}
+
+	fn database_path(&self) -> PathBuf {
+		self.database_path.lock().unwrap().clone()
+	}
+
+	/// Makes `database` the unlocked database, unless another database was opened since `path` was current.
+	fn set_database(&self, path: &Path, database: Database) -> Result<(), String> {
+		let mut current = self.use_database();
+
+		if self.database_path() != path {
+			return Err("Another database was opened.".to_owned());
+		}
+
+		*current = Some(database);
+
+		// Not being able to remember the database isn't worth failing over
+		let _ = write_recent_database(&self.data_dir, path.to_owned());
+
+		Ok(())
+	}
+}
+
+
+// The unlocked database is dropped after this long without user activity, unless the user set their own time.  The setting is
+// synced, like the frontend's settings.
+const AUTOLOCK_SETTING: &str = "autolock_minutes";
+const DEFAULT_AUTOLOCK_MINUTES: u64 = 15;
+const AUTOLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// Sent to every window when the database is locked, so they can go back to the unlock screen.
+const DATABASE_LOCKED_EVENT: &str = "database-locked";
+
+/// None if auto-lock is off
+fn autolock_timeout(database: &Database) -> Option<Duration> {
+	let minutes = database
+		.get_settings()
+		.and_then(|settings| settings.get(AUTOLOCK_SETTING))
+		.and_then(serde_json::Value::as_u64)
+		.unwrap_or(DEFAULT_AUTOLOCK_MINUTES);
+
+	(minutes > 0).then(|| Duration::from_secs(minutes.saturating_mul(60)))
+}
+
+fn spawn_autolock_timer(app: tauri::AppHandle) {
+	thread::spawn(move || loop {
+		thread::sleep(AUTOLOCK_CHECK_INTERVAL);
+		let state = app.state::<AppState>();
+
+		// A command using the database is activity, so it's fine to skip this check
+		let Ok(mut database) = state.database.try_lock() else {
+			continue;
+		};
+		let idle = state.last_activity.lock().unwrap().elapsed();
+
+		if database.as_ref().and_then(autolock_timeout).is_some_and(|timeout| idle >= timeout) {
+			*database = None;
+			let _ = app.emit_all(DATABASE_LOCKED_EVENT, ());
+		}
+	});
+}
+
+
+// Secrets copied with copy_secret are cleared from the clipboard after this long, unless the user set their own time.  Synced,
+// like AUTOLOCK_SETTING.
+const CLIPBOARD_CLEAR_SETTING: &str = "clipboard_clear_seconds";
+// Sent to every window when copy_secret clears the clipboard.
+const CLIPBOARD_CLEARED_EVENT: &str = "clipboard-cleared";
+
+/// None if copied secrets are left on the clipboard
+fn clipboard_timeout(database: &Database) -> Option<Duration> {
+	let seconds = database
+		.get_settings()
+		.and_then(|settings| settings.get(CLIPBOARD_CLEAR_SETTING))
+		.and_then(serde_json::Value::as_u64)
+		.unwrap_or(DEFAULT_CLIPBOARD_TIMEOUT.as_secs());
+
+	(seconds > 0).then(|| Duration::from_secs(seconds))
+}
+
+
+// The recently opened databases are remembered in the data dir, most recent first
+const RECENT_DATABASES_FILE: &str = "recent_databases.json";
+const RECENT_DATABASES_LIMIT: usize = 10;
+
+// The database that was open last, if it's still there
+fn last_database_path(data_dir: &Path) -> PathBuf {
+	read_recent_databases(data_dir)
+		.into_iter()
+		.find(|path| path.exists())
+		.unwrap_or_else(|| data_dir.join("database.fortress"))
+}
+
+fn read_recent_databases(data_dir: &Path) -> Vec<PathBuf> {
+	fs::read(data_dir.join(RECENT_DATABASES_FILE))
+		.ok()
+		.and_then(|json| serde_json::from_slice(&json).ok())
+		.unwrap_or_default()
+}
+
+fn write_recent_database(data_dir: &Path, path: PathBuf) -> io::Result<()> {
+	let mut recent = read_recent_databases(data_dir);
+	recent.retain(|recent_path| *recent_path != path);
+	recent.insert(0, path);
+	recent.truncate(RECENT_DATABASES_LIMIT);
+
+	fs::write(data_dir.join(RECENT_DATABASES_FILE), serde_json::to_vec(&recent)?)
 }
 
+// Returned for cancelled operations, so the frontend can tell them apart from failures.
+const CANCELLED_MESSAGE: &str = "Cancelled";
+// Returned when syncing would merge in another account's entries, so the frontend can ask the user (see confirm_account_change).
+const ACCOUNT_CHANGED_MESSAGE: &str = "AccountChanged";
+
 
 fn format_fortress_error(err: FortressError) -> String {
 	match err {
 		FortressError::CryptoError(CryptoError::DecryptionError) => "Incorrect password.".to_owned(),
 		FortressError::CryptoError(CryptoError::BadChecksum) => "File is corrupted.".to_owned(),
+		FortressError::CryptoError(CryptoError::HardwareKeyRequired) => "Database needs its YubiKey; plug it in and try again.".to_owned(),
+		FortressError::LegacyDatabase => "Database is in the old fortress1 format. Upgrade it using `fortress upgrade-legacy`.".to_owned(),
+		FortressError::Cancelled => CANCELLED_MESSAGE.to_owned(),
+		FortressError::SyncAccountChanged => ACCOUNT_CHANGED_MESSAGE.to_owned(),
 		err => format!("{err}"),
 	}
 }
 
 
+// Progress of long-running commands is sent to the frontend as events on the calling window, so it can show a progress bar.
+const DERIVE_PROGRESS_EVENT: &str = "derive-progress";
+const SYNC_PROGRESS_EVENT: &str = "sync-progress";
+
+#[derive(Serialize, Clone)]
+struct DeriveProgressPayload {
+	fraction: f64,
+	elapsed_ms: u64,
+	remaining_ms: u64,
+}
+
+fn emit_derive_progress(window: &tauri::Window, progress: DeriveProgress) {
+	let payload = DeriveProgressPayload {
+		fraction: progress.fraction(),
+		elapsed_ms: progress.elapsed.as_millis() as u64,
+		remaining_ms: progress.remaining().as_millis() as u64,
+	};
+
+	// Progress is only informational, so a window that's gone away isn't an error
+	let _ = window.emit(DERIVE_PROGRESS_EVENT, payload);
+}
+
+fn emit_sync_progress(window: &tauri::Window, progress: SyncProgress) {
+	let _ = window.emit(SYNC_PROGRESS_EVENT, progress);
+}
+
+
 #[tauri::command]
 fn error_dialog(message: String, window: tauri::Window) {
 	tauri::api::dialog::message(Some(&window), "Error", message)
@@ -132,111 +1019,481 @@ fn error_dialog(message: String, window: tauri::Window) {
 
 
 #[tauri::command]
-fn random_string(length: usize, uppercase: bool, lowercase: bool, numbers: bool, others: String) -> String {
-	libfortress::random_string(length, uppercase, lowercase, numbers, &others)
+fn generate_password(options: GeneratorOptions) -> Result<String, String> {
+	options.generate().map_err(format_fortress_error)
+}
+
+
+/// Bits of entropy in passwords generated with `options`
+#[tauri::command]
+fn estimate_password_entropy(options: GeneratorOptions) -> Result<f64, String> {
+	options.estimate_entropy().map_err(format_fortress_error)
 }
 
 
 #[tauri::command]
 fn database_exists(state: tauri::State<AppState>) -> bool {
-	state.database_path.exists()
+	state.database_path().exists()
 }
 
 
 #[tauri::command]
-fn create_database(username: String, password: String, state: tauri::State<AppState>) -> Result<(), String> {
-	let mut database = Database::new_with_password(username, password);
+fn get_database_path(state: tauri::State<AppState>) -> PathBuf {
+	state.database_path()
+}
 
-	database.get_root_mut().rename("My Passwords");
 
-	database.save_to_path(&state.database_path).map_err(format_fortress_error)?;
+/// Switches to the database at `path`, locking the current one.  The database doesn't have to exist yet; create_database will
+/// create it.
+#[tauri::command]
+fn open_database_at(path: PathBuf, state: tauri::State<AppState>) -> Result<(), String> {
+	if path.is_dir() {
+		return Err(format!("'{}' is a directory.", path.display()));
+	}
 
-	*state.database.lock().unwrap() = Some(database);
+	let mut database = state.use_database();
+	*database = None;
+	*state.database_path.lock().unwrap() = path;
 
 	Ok(())
 }
 
 
+/// Databases that were recently unlocked or created, most recent first
 #[tauri::command]
+fn list_recent_databases(state: tauri::State<AppState>) -> Vec<PathBuf> {
+	read_recent_databases(&state.data_dir)
+}
+
+
+/// Copies the entries in `entry_ids` into the root directory of the database at `path`, unlocking it with `password`.  Entries
+/// the other database already has are merged instead, so copying again brings them up to date.
+/// Entries can't be deleted (sync would bring them back), so moving entries means copying them and filing the originals away.
+#[tauri::command(async)]
+fn copy_entries_to_database(path: PathBuf, password: String, entry_ids: Vec<ID>, state: tauri::State<AppState>) -> Result<(), String> {
+	let password = Zeroizing::new(password);
+	let cancel = state.begin_operation();
+
+	if path == state.database_path() {
+		return Err("Entries can't be copied to the same database.".to_owned());
+	}
+
+	let entries = {
+		let database = state.use_database();
+		let database = database.as_ref().ok_or("Database is not unlocked.")?;
+
+		entry_ids
+			.iter()
+			.map(|id| database.get_entry_by_id(id).cloned().ok_or("Entry not found."))
+			.collect::<Result<Vec<_>, _>>()?
+	};
+
+	let mut target = Database::load_from_path_cancellable(&path, &password, &cancel).map_err(format_fortress_error)?;
+
+	for entry in entries {
+		match target.get_entry_by_id_mut(entry.get_id()) {
+			Some(existing) => *existing = existing.merge(&entry).ok_or("Entry conflicts with the other database's copy.")?,
+			None => target.add_entry(entry),
+		}
+	}
+
+	target.save_to_path(&path).map_err(format_fortress_error)
+}
+
+
+// Long-running commands are async so they run off the main thread, leaving it free to handle cancel_operation.
+#[tauri::command(async)]
+fn create_database(username: String, password: String, window: tauri::Window, state: tauri::State<AppState>) -> Result<(), String> {
+	let password = Zeroizing::new(password);
+	let cancel = state.begin_operation();
+	let path = state.database_path();
+	let mut database = Database::new_with_password_and_progress(username, &password, &cancel, &mut |progress| emit_derive_progress(&window, progress))
+		.map_err(format_fortress_error)?;
+
+	database.get_root_mut().rename("My Passwords");
+
+	database.save_to_path(&path).map_err(format_fortress_error)?;
+
+	state.set_database(&path, database)
+}
+
+
+#[tauri::command(async)]
 fn unlock_database(password: String, state: tauri::State<AppState>) -> Result<(), String> {
-	match Database::load_from_path(&state.database_path, password) {
+	let password = Zeroizing::new(password);
+	let cancel = state.begin_operation();
+	let path = state.database_path();
+
+	// Databases that need a YubiKey fail before the slow key derivation, and are loaded again asking the YubiKey
+	let loaded = match Database::load_from_path_cancellable(&path, &password, &cancel) {
+		Err(FortressError::CryptoError(CryptoError::HardwareKeyRequired)) => Database::load_from_path_with_hardware_key(&path, &password, &yubikey_response),
+		loaded => loaded,
+	};
+
+	match loaded {
 		Ok(database) => {
-			*state.database.lock().unwrap() = Some(database);
-			Ok(())
+			// Persist any format upgrade right away
+			if database.needs_resave() {
+				database.save_to_path(&path).map_err(format_fortress_error)?;
+			}
+
+			state.set_database(&path, database)
 		},
 		Err(err) => Err(format_fortress_error(err)),
 	}
 }
 
 
+/// Unlocks the current database with keys kept in the OS keychain by set_keychain_unlock.  Returns false if it can't be
+/// unlocked that way, and the password has to be used.
+#[tauri::command(async)]
+fn unlock_with_keychain(state: tauri::State<AppState>) -> Result<bool, String> {
+	let path = state.database_path();
+
+	match Database::unlock_with_keychain(&mut SystemKeychain, &path).map_err(format_fortress_error)? {
+		Some(database) => state.set_database(&path, database).map(|()| true),
+		None => Ok(false),
+	}
+}
+
+
+/// Turns unlocking the current database with the OS keychain on or off.  Has to be turned on again after the password is
+/// changed.
+#[tauri::command]
+fn set_keychain_unlock(enabled: bool, state: tauri::State<AppState>) -> Result<(), String> {
+	let path = state.database_path();
+
+	if enabled {
+		let database = state.use_database();
+		let database = database.as_ref().ok_or("Database is not unlocked.")?;
+		database.remember_in_keychain(&mut SystemKeychain, &path).map_err(format_fortress_error)
+	} else {
+		Database::forget_in_keychain(&mut SystemKeychain, &path).map_err(format_fortress_error)
+	}
+}
+
+
+/// Makes the current database need the YubiKey as well as its password to open, or only its password again.  `password` must be
+/// the database's current password.
+#[tauri::command(async)]
+fn set_hardware_key(password: String, enabled: bool, state: tauri::State<AppState>) -> Result<(), String> {
+	let password = Zeroizing::new(password);
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	database
+		.set_hardware_key(&password, enabled.then_some(&yubikey_response))
+		.map_err(format_fortress_error)?;
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
 #[tauri::command]
 fn list_entries(state: tauri::State<AppState>) -> Result<Vec<Entry>, ()> {
-	let database = state.database.lock().unwrap();
+	let database = state.use_database();
+
+	let database = database.as_ref().ok_or(())?;
+	let trashed = database.list_trash_recursive();
 
-	database.as_ref().ok_or(()).map(|d| d.list_entries().cloned().collect())
+	Ok(database.list_entries().filter(|entry| !trashed.contains(entry.get_id())).cloned().collect())
 }
 
 
 #[tauri::command]
-fn list_directories(state: tauri::State<AppState>) -> Result<Vec<Directory>, ()> {
-	let database = state.database.lock().unwrap();
+fn search_entries(query: String, state: tauri::State<AppState>) -> Result<Vec<SearchHit>, ()> {
+	let database = state.use_database();
+
+	let database = database.as_ref().ok_or(())?;
+	let trashed = database.list_trash_recursive();
 
-	database.as_ref().ok_or(()).map(|d| d.list_directories().cloned().collect())
+	Ok(database.search(&query).into_iter().filter(|hit| !trashed.contains(&hit.id)).collect())
 }
 
 
+/// Entries with credentials for `url`, best matches first (see `Database::find_entries_for_url`)
 #[tauri::command]
-fn move_object(object_id: ID, new_parent_id: ID, state: tauri::State<AppState>) -> Result<(), String> {
-	let mut database = state.database.lock().unwrap();
+fn find_entries_for_url(url: String, state: tauri::State<AppState>) -> Result<Vec<ID>, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
 
-	if let Some(database) = database.as_mut() {
-		database.move_object(&object_id, &new_parent_id);
+	Ok(database.find_entries_for_url(&url).into_iter().map(|entry| *entry.get_id()).collect())
+}
 
-		// Save the database
-		if let Err(err) = database.save_to_path(&state.database_path) {
-			Err(format_fortress_error(err))
-		} else {
-			Ok(())
-		}
-	} else {
-		Err("Database is not unlocked.".to_owned())
-	}
+
+/// The entry's fields with its derived fields, such as TOTP codes, computed for the current time (see `Entry::resolved_state`)
+#[tauri::command]
+fn get_resolved_fields(entry_id: ID, state: tauri::State<AppState>) -> Result<HashMap<String, String>, ()> {
+	let database = state.use_database();
+	let entry = database.as_ref().and_then(|database| database.get_entry_by_id(&entry_id)).ok_or(())?;
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_err(|_| ())?
+		.as_nanos()
+		.try_into()
+		.map_err(|_| ())?;
+
+	Ok(entry.resolved_state(now))
 }
 
 
+/// Called when the user opens an entry or copies one of its fields.
 #[tauri::command]
-fn rename_directory(directory_id: ID, new_name: String, state: tauri::State<AppState>) -> Result<(), String> {
-	let mut database = state.database.lock().unwrap();
+fn record_entry_access(entry_id: ID, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
 
 	if let Some(database) = database.as_mut() {
-		let directory = database.get_directory_by_id_mut(&directory_id).ok_or("Directory not found.")?;
-		directory.rename(new_name);
+		database.record_access(&entry_id);
 
-		// Save the database
-		if let Err(err) = database.save_to_path(&state.database_path) {
-			Err(format_fortress_error(err))
-		} else {
-			Ok(())
+		// The list is only saved if the user asked for that, so there's usually nothing to save
+		if database.get_persist_recent() {
+			database.save_to_path(state.database_path()).map_err(format_fortress_error)?;
 		}
+
+		Ok(())
 	} else {
 		Err("Database is not unlocked.".to_owned())
 	}
 }
 
 
+/// Most recently accessed first
 #[tauri::command]
-fn new_directory(name: String, state: tauri::State<AppState>) -> Result<ID, String> {
-	let mut database = state.database.lock().unwrap();
+fn list_recent_entries(state: tauri::State<AppState>) -> Result<Vec<ID>, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
 
-	if let Some(database) = database.as_mut() {
-		let mut directory = Directory::new();
-		let id = *directory.get_id();
-		directory.rename(name);
-		database.add_directory(directory);
+	let trashed = database.list_trash_recursive();
 
-		// Save the database
-		if let Err(err) = database.save_to_path(&state.database_path) {
-			Err(format_fortress_error(err))
+	Ok(database.recent().map(|entry| *entry.get_id()).filter(|id| !trashed.contains(id)).collect())
+}
+
+
+/// Non-protected `fields` of the entries in `entry_ids` as text for the clipboard (see `Database::export_selection`)
+#[tauri::command]
+fn export_selection(entry_ids: Vec<ID>, fields: Vec<String>, format: SelectionFormat, state: tauri::State<AppState>) -> Result<String, ()> {
+	let database = state.use_database();
+
+	database.as_ref().ok_or(()).map(|d| d.export_selection(&entry_ids, &fields, format))
+}
+
+
+/// This database's share public key, in hex, for other users to share entries with (see the share module in libfortress)
+#[tauri::command]
+fn get_share_public_key(state: tauri::State<AppState>) -> Result<String, ()> {
+	let database = state.use_database();
+
+	database.as_ref().ok_or(()).map(|d| d.get_share_public_key().to_hex())
+}
+
+
+/// Writes the entries in `entry_ids`, encrypted for the user whose share public key is `recipient`, to `path`
+#[tauri::command]
+fn export_share(entry_ids: Vec<ID>, recipient: String, path: PathBuf, state: tauri::State<AppState>) -> Result<(), String> {
+	let recipient = SharePublicKey::from_hex(recipient.trim()).ok_or("Not a valid share key.")?;
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+
+	fs::write(&path, database.export_share(&entry_ids, &recipient)).map_err(|err| err.to_string())
+}
+
+
+/// Imports a share bundle from `path` and saves the database.  Returns the IDs of the shared entries.
+#[tauri::command]
+fn import_share(path: PathBuf, state: tauri::State<AppState>) -> Result<Vec<ID>, String> {
+	let bundle = fs::read(&path).map_err(|err| err.to_string())?;
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	let imported = database.import_share(&bundle).map_err(format_fortress_error)?;
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)?;
+
+	Ok(imported)
+}
+
+
+#[tauri::command]
+fn list_directories(state: tauri::State<AppState>) -> Result<Vec<Directory>, ()> {
+	let database = state.use_database();
+
+	let database = database.as_ref().ok_or(())?;
+	let trash = database.get_trash().map(|trash| *trash.get_id());
+	let trashed = database.list_trash_recursive();
+
+	Ok(database
+		.list_directories()
+		.filter(|directory| Some(*directory.get_id()) != trash && !trashed.contains(directory.get_id()))
+		.cloned()
+		.collect())
+}
+
+
+/// Writes a copy of the database encrypted under `export_password` to `path` (see `Database::export_encrypted_copy`), once the
+/// user confirms it in a dialog.  Returns false if they didn't.
+#[tauri::command(async)]
+fn export_database(path: PathBuf, export_password: String, window: tauri::Window, state: tauri::State<AppState>) -> Result<bool, String> {
+	let export_password = Zeroizing::new(export_password);
+
+	if path == state.database_path() {
+		return Err("Can't export over the open database.".to_owned());
+	}
+
+	let message = format!(
+		"Anyone with the export password will be able to read every entry in this database.\n\nExport to '{}'?",
+		path.display()
+	);
+	if !tauri::api::dialog::blocking::ask(Some(&window), "Export Database", message) {
+		return Ok(false);
+	}
+
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+	database.export_encrypted_copy(&path, &export_password).map_err(format_fortress_error)?;
+
+	Ok(true)
+}
+
+
+/// Moves an entry to the trash (see `Database::trash_object`)
+#[tauri::command]
+fn delete_entry(entry_id: ID, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	if database.get_entry_by_id(&entry_id).is_none() || !database.trash_object(&entry_id) {
+		return Err("Entry not found.".to_owned());
+	}
+
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
+/// Moves a directory, and everything in it, to the trash
+#[tauri::command]
+fn delete_directory(directory_id: ID, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	if database.get_directory_by_id(&directory_id).is_none() || !database.trash_object(&directory_id) {
+		return Err("Directory not found.".to_owned());
+	}
+
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
+#[derive(Serialize)]
+struct TrashContents {
+	/// What's directly in the trash, most recently trashed first
+	objects: Vec<TrashedObject>,
+	/// Everything in the trash, including what's in trashed directories
+	entries: Vec<Entry>,
+	directories: Vec<Directory>,
+}
+
+#[tauri::command]
+fn list_trash(state: tauri::State<AppState>) -> Result<TrashContents, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
+	let trashed = database.list_trash_recursive();
+
+	Ok(TrashContents {
+		objects: database.list_trash(),
+		entries: database.list_entries().filter(|entry| trashed.contains(entry.get_id())).cloned().collect(),
+		directories: database
+			.list_directories()
+			.filter(|directory| trashed.contains(directory.get_id()))
+			.cloned()
+			.collect(),
+	})
+}
+
+
+/// Moves an entry or directory out of the trash, back to where it was
+#[tauri::command]
+fn restore(object_id: ID, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	if !database.restore_object(&object_id) {
+		return Err("Not in the trash.".to_owned());
+	}
+
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
+#[tauri::command]
+fn move_object(object_id: ID, new_parent_id: ID, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+
+	if let Some(database) = database.as_mut() {
+		database.move_object(&object_id, &new_parent_id);
+
+		// Save the database
+		if let Err(err) = database.save_to_path(state.database_path()) {
+			Err(format_fortress_error(err))
+		} else {
+			Ok(())
+		}
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+
+#[tauri::command]
+fn rename_directory(directory_id: ID, new_name: String, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+
+	if let Some(database) = database.as_mut() {
+		let directory = database.get_directory_by_id_mut(&directory_id).ok_or("Directory not found.")?;
+		directory.rename(new_name);
+
+		// Save the database
+		if let Err(err) = database.save_to_path(state.database_path()) {
+			Err(format_fortress_error(err))
+		} else {
+			Ok(())
+		}
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+
+#[tauri::command]
+fn set_directory_local_only(directory_id: ID, local_only: bool, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+
+	if let Some(database) = database.as_mut() {
+		let directory = database.get_directory_by_id_mut(&directory_id).ok_or("Directory not found.")?;
+		directory.set_local_only(local_only);
+
+		// Save the database
+		if let Err(err) = database.save_to_path(state.database_path()) {
+			Err(format_fortress_error(err))
+		} else {
+			Ok(())
+		}
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+
+#[tauri::command]
+fn new_directory(name: String, state: tauri::State<AppState>) -> Result<ID, String> {
+	let mut database = state.use_database();
+
+	if let Some(database) = database.as_mut() {
+		let mut directory = Directory::new();
+		let id = *directory.get_id();
+		directory.rename(name);
+		database.add_directory(directory);
+
+		// Save the database
+		if let Err(err) = database.save_to_path(state.database_path()) {
+			Err(format_fortress_error(err))
 		} else {
 			Ok(id)
 		}
@@ -248,25 +1505,23 @@ fn new_directory(name: String, state: tauri::State<AppState>) -> Result<ID, Stri
 
 #[tauri::command]
 fn edit_entry(entry_id: Option<ID>, data: HashMap<String, String>, parent_id: ID, state: tauri::State<AppState>) -> Result<(), String> {
-	let mut database = state.database.lock().unwrap();
-
-	let data = EntryHistory::new(data);
+	let mut database = state.use_database();
 
 	if let Some(database) = database.as_mut() {
 		if let Some(id) = entry_id {
 			// Edit entry
 			let entry = database.get_entry_by_id_mut(&id).ok_or("Entry not found.")?;
-			entry.edit(data);
+			entry.try_edit(entry.new_edit(data)).map_err(|err| format_fortress_error(err.into()))?;
 		} else {
 			// New entry
 			let mut entry = libfortress::Entry::new();
 			let entry_id = *entry.get_id();
-			entry.edit(data);
+			entry.try_edit(entry.new_edit(data)).map_err(|err| format_fortress_error(err.into()))?;
 			database.add_entry(entry);
 			database.move_object(&entry_id, &parent_id);
 		}
 
-		if let Err(err) = database.save_to_path(&state.database_path) {
+		if let Err(err) = database.save_incremental(state.database_path()) {
 			Err(format_fortress_error(err))
 		} else {
 			Ok(())
@@ -277,9 +1532,79 @@ fn edit_entry(entry_id: Option<ID>, data: HashMap<String, String>, parent_id: ID
 }
 
 
+/// Creates an entry in `parent_id` for each of `entries`, saving once at the end (see `Database::transaction`).  Returns the new
+/// entries' IDs.
+#[tauri::command(async)]
+fn create_entries(entries: Vec<HashMap<String, String>>, parent_id: ID, state: tauri::State<AppState>) -> Result<Vec<ID>, String> {
+	let path = state.database_path();
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	database
+		.transaction(&path, |database| {
+			let entries = entries
+				.into_iter()
+				.map(|data| {
+					let mut entry = Entry::new();
+					entry.try_edit(entry.new_edit(data))?;
+					Ok(entry)
+				})
+				.collect::<Result<Vec<_>, FortressError>>()?;
+			let entry_ids = entries.iter().map(|entry| *entry.get_id()).collect::<Vec<_>>();
+
+			database.add_entries(entries);
+			for entry_id in &entry_ids {
+				database.move_object(entry_id, &parent_id);
+			}
+
+			Ok::<_, FortressError>(entry_ids)
+		})
+		.map_err(format_fortress_error)
+}
+
+
+/// Every edit made to the entry, oldest first, with the fields its double lock holds redacted while it's locked
+#[tauri::command]
+fn get_entry_history(entry_id: ID, state: tauri::State<AppState>) -> Result<Vec<EntryHistory>, String> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+	let entry = database.get_entry_by_id(&entry_id).ok_or("Entry not found.")?;
+
+	Ok(entry.get_visible_history())
+}
+
+
+/// Sets an entry's field back to the value it had at `to_time` (nanoseconds), as a new edit (see `Entry::revert_field`)
+#[tauri::command]
+fn revert_entry_field(entry_id: ID, field: String, to_time: u64, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+	let entry = database.get_entry_by_id_mut(&entry_id).ok_or("Entry not found.")?;
+
+	// JavaScript numbers can't hold nanosecond timestamps exactly, so to_time is taken to mean the closest edit
+	let to_time = entry
+		.get_history()
+		.iter()
+		.map(|item| item.time)
+		.min_by_key(|time| time.abs_diff(to_time))
+		.unwrap_or(to_time);
+
+	if entry.is_field_double_locked(&field) {
+		return Err("Remove the entry's double lock to revert this field.".to_owned());
+	}
+
+	let Some(edit) = entry.revert_field(&field, to_time) else {
+		return Ok(());
+	};
+	entry.try_edit(edit).map_err(|err| format_fortress_error(err.into()))?;
+
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
 #[tauri::command]
 fn get_username(state: tauri::State<AppState>) -> Result<String, ()> {
-	let database = state.database.lock().unwrap();
+	let database = state.use_database();
 
 	database.as_ref().ok_or(()).map(|d| d.get_username().to_owned())
 }
@@ -287,7 +1612,7 @@ fn get_username(state: tauri::State<AppState>) -> Result<String, ()> {
 
 #[tauri::command]
 fn get_sync_keys(state: tauri::State<AppState>) -> Result<String, ()> {
-	let database = state.database.lock().unwrap();
+	let database = state.use_database();
 
 	database
 		.as_ref()
@@ -296,9 +1621,20 @@ fn get_sync_keys(state: tauri::State<AppState>) -> Result<String, ()> {
 }
 
 
+#[tauri::command]
+fn get_readonly_sync_keys(state: tauri::State<AppState>) -> Result<String, ()> {
+	let database = state.use_database();
+
+	database.as_ref().ok_or(()).map(|d| {
+		let keys = d.get_readonly_sync_keys();
+		format!("{}:{}", keys.get_login_id().to_hex(), keys.get_login_key().to_hex())
+	})
+}
+
+
 #[tauri::command]
 fn get_sync_url(state: tauri::State<AppState>) -> Result<Option<Url>, ()> {
-	let database = state.database.lock().unwrap();
+	let database = state.use_database();
 
 	database.as_ref().ok_or(()).map(|d| d.get_sync_url().cloned())
 }
@@ -306,12 +1642,12 @@ fn get_sync_url(state: tauri::State<AppState>) -> Result<Option<Url>, ()> {
 
 #[tauri::command]
 fn set_sync_url(url: String, state: tauri::State<AppState>) -> Result<(), String> {
-	let mut database = state.database.lock().unwrap();
+	let mut database = state.use_database();
 
 	if let Some(database) = database.as_mut() {
 		database.set_sync_url(Some(url.parse().map_err(|_| "Invalid URL.")?));
 
-		if let Err(err) = database.save_to_path(&state.database_path) {
+		if let Err(err) = database.save_to_path(state.database_path()) {
 			Err(format_fortress_error(err))
 		} else {
 			Ok(())
@@ -323,13 +1659,47 @@ fn set_sync_url(url: String, state: tauri::State<AppState>) -> Result<(), String
 
 
 #[tauri::command]
-fn change_password(username: String, password: String, state: tauri::State<AppState>) -> Result<(), String> {
-	let mut database = state.database.lock().unwrap();
+fn get_sync_proxy(state: tauri::State<AppState>) -> Result<Option<Url>, ()> {
+	let database = state.use_database();
+
+	database.as_ref().ok_or(()).map(|d| d.get_sync_proxy().cloned())
+}
+
+
+/// Sets the SOCKS5 proxy to sync through, or clears it if `proxy` is empty.
+#[tauri::command]
+fn set_sync_proxy(proxy: String, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+
+	if let Some(database) = database.as_mut() {
+		let proxy = match proxy.trim() {
+			"" => None,
+			proxy => Some(proxy.parse().map_err(|_| "Invalid URL.")?),
+		};
+		database.set_sync_proxy(proxy).map_err(format_fortress_error)?;
+
+		if let Err(err) = database.save_to_path(state.database_path()) {
+			Err(format_fortress_error(err))
+		} else {
+			Ok(())
+		}
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+#[tauri::command(async)]
+fn change_password(username: String, password: String, window: tauri::Window, state: tauri::State<AppState>) -> Result<(), String> {
+	let password = Zeroizing::new(password);
+	let cancel = state.begin_operation();
+	let mut database = state.use_database();
 
 	if let Some(database) = database.as_mut() {
-		database.change_password(&username, &password);
+		database
+			.change_password_with_progress(&username, &password, &cancel, &mut |progress| emit_derive_progress(&window, progress))
+			.map_err(format_fortress_error)?;
 
-		if let Err(err) = database.save_to_path(&state.database_path) {
+		if let Err(err) = database.save_to_path(state.database_path()) {
 			Err(format_fortress_error(err))
 		} else {
 			Ok(())
@@ -341,11 +1711,44 @@ fn change_password(username: String, password: String, state: tauri::State<AppSt
 
 
 #[tauri::command]
-fn sync_database(state: tauri::State<AppState>) -> Result<(), String> {
-	let mut database = state.database.lock().unwrap();
+fn get_settings(state: tauri::State<AppState>) -> Result<HashMap<String, serde_json::Value>, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
+
+	Ok(database
+		.get_settings()
+		.map(|settings| settings.iter().map(|(key, value)| (key.to_owned(), value.clone())).collect())
+		.unwrap_or_default())
+}
+
+
+/// This device's settings for the open database (see `LocalSettings`), which don't need it to be unlocked
+#[tauri::command]
+fn get_local_settings(state: tauri::State<AppState>) -> Result<LocalSettings, String> {
+	LocalSettings::load(state.database_path()).map_err(format_fortress_error)
+}
+
+
+#[tauri::command]
+fn set_local_settings(settings: LocalSettings, state: tauri::State<AppState>) -> Result<(), String> {
+	settings.save(state.database_path()).map_err(format_fortress_error)
+}
+
+
+/// Setting a value to null removes it.
+#[tauri::command]
+fn set_setting(key: String, value: serde_json::Value, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
 
 	if let Some(database) = database.as_mut() {
-		if let Err(err) = database.sync() {
+		if value.is_null() {
+			database.remove_setting(&key);
+		} else {
+			database.set_setting(key, value);
+		}
+
+		// Save the database
+		if let Err(err) = database.save_to_path(state.database_path()) {
 			Err(format_fortress_error(err))
 		} else {
 			Ok(())
@@ -356,45 +1759,1065 @@ fn sync_database(state: tauri::State<AppState>) -> Result<(), String> {
 }
 
 
-fn read_password() -> String {
-	// NOTE: We could use something like the rpassword crate to read this without showing the password
-	// on screen, but that adds another dependency and the decrypt/encrypt commands are generally only
-	// used during development or exotic scenarios.
-	let mut password = String::new();
-	eprint!("Password: ");
-	io::stderr().flush().unwrap();
-	io::stdin().read_line(&mut password).expect("Failed to read password from stdin");
-	password.trim_end().to_owned()
+#[derive(Serialize)]
+struct SyncResult {
+	/// A short summary of what the sync changed
+	summary: String,
+	/// Choices the user should be offered
+	conflicts: Vec<SyncConflict>,
+	bytes_uploaded: u64,
+	bytes_downloaded: u64,
+	/// Encrypted size of each object transferred, by ID
+	uploaded_sizes: BTreeMap<ID, u64>,
+	downloaded_sizes: BTreeMap<ID, u64>,
+	/// Objects in local-only directories that the server has anyway
+	local_only_on_server: BTreeSet<ID>,
 }
 
+impl From<SyncReport> for SyncResult {
+	fn from(report: SyncReport) -> SyncResult {
+		SyncResult {
+			summary: report.summary(),
+			conflicts: report.conflicts,
+			bytes_uploaded: report.bytes_uploaded,
+			bytes_downloaded: report.bytes_downloaded,
+			uploaded_sizes: report.uploaded_sizes,
+			downloaded_sizes: report.downloaded_sizes,
+			local_only_on_server: report.local_only_on_server,
+		}
+	}
+}
 
-/// Read file and decrypt
-fn do_decrypt<P: AsRef<Path>>(path: P, password: &str) {
-	let (payload, _) = {
-		let file = File::open(path).expect("Failed to open file");
-		let mut reader = BufReader::new(file);
+// Syncs that take longer than this fail, rather than leaving the app stuck syncing with a server that's gone quiet
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 
-		libfortress::fortresscrypto::decrypt_from_file(&mut reader, password.as_bytes()).expect("Failed to decrypt file")
-	};
+#[tauri::command(async)]
+fn sync_database(window: tauri::Window, state: tauri::State<AppState>) -> Result<SyncResult, String> {
+	let cancel = state.begin_operation();
+	let mut database = state.use_database();
 
-	io::stdout().write_all(&payload).expect("Failed to write to stdout");
+	if let Some(database) = database.as_mut() {
+		let options = SyncOptions {
+			timeout: Some(SYNC_TIMEOUT),
+			..SyncOptions::default()
+		};
+
+		match database.sync_with_options(&options, &cancel, &mut |progress| emit_sync_progress(&window, progress)) {
+			Ok(report) => Ok(report.into()),
+			Err(err) => Err(format_fortress_error(err)),
+		}
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
 }
 
 
-/// Read file and encrypt
-fn do_encrypt<P: AsRef<Path>>(path: P, password: &str) {
-	let payload = {
-		let mut data = Vec::new();
-		File::open(path)
-			.expect("Failed to open file")
-			.read_to_end(&mut data)
-			.expect("Failed to read file");
-		data
-	};
+/// True if something else (such as a file syncing service) changed the database file since we last loaded or saved it
+#[tauri::command]
+fn database_changed_on_disk(state: tauri::State<AppState>) -> Result<bool, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
 
-	let encryption_parameters = Default::default();
-	let file_key_suite =
-		libfortress::fortresscrypto::FileKeySuite::derive(password.as_bytes(), &encryption_parameters).expect("Failed to derive file key suite");
+	Ok(database.file_changed_on_disk(state.database_path()))
+}
+
+
+/// Merges the database file's changes into ours and saves the result, returning the choices the user should be offered
+#[tauri::command(async)]
+fn reload_database(password: String, state: tauri::State<AppState>) -> Result<Vec<SyncConflict>, String> {
+	let password = Zeroizing::new(password);
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	let report = database.reload_and_merge(state.database_path(), &password).map_err(format_fortress_error)?;
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)?;
+
+	Ok(report.conflicts)
+}
+
+
+/// Same as sync_database, but only reports what a sync would change (see `Database::sync_preview`)
+#[tauri::command(async)]
+fn preview_sync(state: tauri::State<AppState>) -> Result<SyncResult, String> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+	let report = database.sync_preview().map_err(format_fortress_error)?;
+
+	Ok(report.into())
+}
+
+
+/// Predicts what the next sync would transfer, fetching only the server's object list (see `Database::estimate_sync_size`)
+#[tauri::command(async)]
+fn estimate_sync_size(state: tauri::State<AppState>) -> Result<SyncSizeEstimate, String> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+
+	database.estimate_sync_size().map_err(format_fortress_error)
+}
+
+
+/// Looks for entries the frontend can't show and other damage to the directory tree (see `Database::check_integrity`)
+#[tauri::command(async)]
+fn check_integrity(state: tauri::State<AppState>) -> Result<IntegrityReport, String> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+
+	Ok(database.check_integrity())
+}
+
+
+/// Counts of what's in the database, for the settings screen (see `Database::stats`)
+#[tauri::command(async)]
+fn get_database_stats(state: tauri::State<AppState>) -> Result<DatabaseStats, String> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+
+	Ok(database.stats())
+}
+
+
+/// Repairs what check_integrity finds and saves the database, returning what was repaired
+#[tauri::command(async)]
+fn repair_database(state: tauri::State<AppState>) -> Result<IntegrityReport, String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+	let report = database.repair();
+
+	if !report.is_ok() {
+		database.save_to_path(state.database_path()).map_err(format_fortress_error)?;
+	}
+
+	Ok(report)
+}
+
+
+#[derive(Serialize)]
+struct SyncConnectionResult {
+	latency_ms: u64,
+	/// False if the server rejected our credentials
+	authenticated: bool,
+}
+
+/// Checks a sync URL (which doesn't have to be saved yet) by making a harmless request to the server
+#[tauri::command(async)]
+fn test_sync_connection(url: String, state: tauri::State<AppState>) -> Result<SyncConnectionResult, String> {
+	let url = url.parse().map_err(|_| "Invalid URL.")?;
+	let database = state.use_database();
+
+	if let Some(database) = database.as_ref() {
+		match database.test_sync_connection(&url) {
+			Ok(status) => Ok(SyncConnectionResult {
+				latency_ms: status.latency.as_millis().try_into().unwrap_or(u64::MAX),
+				authenticated: status.authenticated,
+			}),
+			Err(err) => Err(format_fortress_error(err)),
+		}
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+
+/// Lets the next sync merge in the entries of the account the database was switched to (after it failed with
+/// ACCOUNT_CHANGED_MESSAGE).  Only call this once the user has confirmed it.
+#[tauri::command]
+fn confirm_account_change(state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+
+	if let Some(database) = database.as_mut() {
+		database.confirm_account_change(AccountChangeConfirmed);
+
+		if let Err(err) = database.save_to_path(state.database_path()) {
+			Err(format_fortress_error(err))
+		} else {
+			Ok(())
+		}
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
 
-	libfortress::fortresscrypto::encrypt_to_file(&mut io::stdout(), &payload, &file_key_suite).expect("Failed to encrypt file");
+
+/// Creates an account on the sync server for the database's username and password.
+#[tauri::command]
+fn register_sync_account(state: tauri::State<AppState>) -> Result<(), String> {
+	let database = state.use_database();
+
+	if let Some(database) = database.as_ref() {
+		database.sync_register_account().map_err(format_fortress_error)
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+
+/// How much the database's account on the sync server is storing, and its limits.
+#[tauri::command]
+fn get_sync_account_info(state: tauri::State<AppState>) -> Result<SyncAccountInfo, String> {
+	let database = state.use_database();
+
+	if let Some(database) = database.as_ref() {
+		database.sync_account_info().map_err(format_fortress_error)
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+
+/// Deletes the database's account on the sync server and everything stored in it.  The local database is kept.  Only call this
+/// once the user has confirmed it.
+#[tauri::command]
+fn delete_sync_account(state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+
+	if let Some(database) = database.as_mut() {
+		database.sync_delete_account(AccountDeletionConfirmed).map_err(format_fortress_error)?;
+		database.save_to_path(state.database_path()).map_err(format_fortress_error)
+	} else {
+		Err("Database is not unlocked.".to_owned())
+	}
+}
+
+/// Cancels the long-running operation in progress (creating, unlocking, changing the password, or syncing), which then fails
+/// with CANCELLED_MESSAGE.  Does nothing if there isn't one.
+#[tauri::command]
+fn cancel_operation(state: tauri::State<AppState>) {
+	state.cancellation.lock().unwrap().cancel();
+}
+
+
+/// Drops the unlocked database, so it has to be unlocked again
+#[tauri::command]
+fn lock_database(app: tauri::AppHandle, state: tauri::State<AppState>) {
+	if state.use_database().take().is_some() {
+		let _ = app.emit_all(DATABASE_LOCKED_EVENT, ());
+	}
+}
+
+
+/// Called by the frontend when the user does something that doesn't need the backend, so it doesn't auto-lock while they're
+/// using it
+#[tauri::command]
+fn record_activity(state: tauri::State<AppState>) {
+	state.record_activity();
+}
+
+
+/// 0 means auto-lock is off
+#[tauri::command]
+fn get_autolock_minutes(state: tauri::State<AppState>) -> Result<u64, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
+
+	Ok(autolock_timeout(database).map_or(0, |timeout| timeout.as_secs() / 60))
+}
+
+
+/// Sets how long the database stays unlocked without user activity; 0 turns auto-lock off
+#[tauri::command]
+fn set_autolock_minutes(minutes: u64, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	database.set_setting(AUTOLOCK_SETTING, minutes.into());
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
+/// How many previous versions of the database file are kept; 0 means backups are off
+#[tauri::command]
+fn get_backup_count(state: tauri::State<AppState>) -> Result<usize, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
+
+	Ok(database.get_backup_policy().keep)
+}
+
+
+/// Takes effect on this save, which also prunes backups past the new count
+#[tauri::command]
+fn set_backup_count(count: usize, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	database.set_backup_policy(BackupPolicy::new(count));
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
+/// Copies an entry's field to the system clipboard, so the frontend never handles it, and clears it again after the user's
+/// timeout (see `libfortress::clipboard::copy_secret`), emitting CLIPBOARD_CLEARED_EVENT
+#[tauri::command(async)]
+fn copy_secret(entry_id: ID, field: String, app: tauri::AppHandle, state: tauri::State<AppState>) -> Result<(), String> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or("Database is not unlocked.")?;
+	let entry = database.get_entry_by_id(&entry_id).ok_or("Entry not found.")?;
+	let value = entry.get(&field).ok_or("The entry has no such field.")?;
+
+	if let Some(timeout) = clipboard_timeout(database) {
+		let pending = libfortress::clipboard::copy_secret(SystemClipboard, value, timeout).map_err(format_fortress_error)?;
+
+		thread::spawn(move || {
+			if pending.wait().is_ok() {
+				let _ = app.emit_all(CLIPBOARD_CLEARED_EVENT, ());
+			}
+		});
+	} else {
+		SystemClipboard.set(value).map_err(format_fortress_error)?;
+	}
+
+	Ok(())
+}
+
+
+/// 0 means copied secrets aren't cleared
+#[tauri::command]
+fn get_clipboard_clear_seconds(state: tauri::State<AppState>) -> Result<u64, ()> {
+	let database = state.use_database();
+	let database = database.as_ref().ok_or(())?;
+
+	Ok(clipboard_timeout(database).map_or(0, |timeout| timeout.as_secs()))
+}
+
+
+/// Sets how long secrets copied with copy_secret stay on the clipboard; 0 leaves them there
+#[tauri::command]
+fn set_clipboard_clear_seconds(seconds: u64, state: tauri::State<AppState>) -> Result<(), String> {
+	let mut database = state.use_database();
+	let database = database.as_mut().ok_or("Database is not unlocked.")?;
+
+	database.set_setting(CLIPBOARD_CLEAR_SETTING, seconds.into());
+	database.save_to_path(state.database_path()).map_err(format_fortress_error)
+}
+
+
+fn read_password(args: &Args) -> Zeroizing<String> {
+	let mut password = Zeroizing::new(String::new());
+
+	if let Some(var) = &args.password_env {
+		let value = Zeroizing::new(std::env::var(var).unwrap_or_else(|_| panic!("--password-env: ${var} is not set")));
+		password.push_str(&value);
+		return password;
+	}
+
+	if let Some(fd) = args.password_fd {
+		BufReader::new(open_fd(fd))
+			.read_line(&mut password)
+			.expect("Failed to read password from --password-fd");
+	} else {
+		// NOTE: We could use something like the rpassword crate to read this without showing the password
+		// on screen, but that adds another dependency.  Scripts and shared machines should use --password-fd.
+		eprint!("Password: ");
+		io::stderr().flush().unwrap();
+		io::stdin().read_line(&mut password).expect("Failed to read password from stdin");
+	}
+
+	strip_line_ending(&mut password);
+	password
+}
+
+
+/// Asks for a secret other than the database's password on stdin, e.g. a new password.
+fn prompt_secret(prompt: &str) -> Zeroizing<String> {
+	let mut secret = Zeroizing::new(String::new());
+
+	eprint!("{prompt}: ");
+	io::stderr().flush().unwrap();
+	io::stdin()
+		.read_line(&mut secret)
+		.unwrap_or_else(|_| panic!("Failed to read {} from stdin", prompt.to_lowercase()));
+
+	strip_line_ending(&mut secret);
+	secret
+}
+
+
+/// Removes the line ending `read_line` leaves on `line` (`\n` or `\r\n`).  Other trailing whitespace is kept, since it may be
+/// part of a password.
+fn strip_line_ending(line: &mut String) {
+	if line.ends_with('\n') {
+		line.pop();
+		if line.ends_with('\r') {
+			line.pop();
+		}
+	}
+}
+
+
+/// Like `read_password`, but for commands that read their payload from `path`, which is stdin if it's `-`.  The password can't
+/// be prompted for on stdin then.
+fn read_payload_password(args: &Args, path: &Path) -> Zeroizing<String> {
+	if is_stdio(path) && args.password_fd.is_none() && args.password_env.is_none() {
+		panic!("Reading the payload from stdin requires --password-fd or --password-env");
+	}
+
+	read_password(args)
+}
+
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> File {
+	use std::os::unix::io::FromRawFd;
+
+	// The File closes its descriptor when it's dropped, which would take stdin, stdout or stderr away from the rest of the command
+	if fd <= 2 {
+		panic!("--password-fd must be a file descriptor other than stdin, stdout and stderr; leave it out to be asked for the password");
+	}
+
+	// SAFETY: The user handed us this file descriptor for reading the password, and nothing else in the process uses it.
+	unsafe { File::from_raw_fd(fd) }
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> File {
+	panic!("--password-fd is only supported on Unix");
+}
+
+
+fn parse_field(s: &str) -> Result<(String, String), String> {
+	s.split_once('=')
+		.map(|(name, value)| (name.to_owned(), value.to_owned()))
+		.ok_or_else(|| format!("expected NAME=VALUE, got '{s}'"))
+}
+
+
+fn load_database<P: AsRef<Path>>(path: P, password: &str) -> Database {
+	Database::load_from_path_with_hardware_key(path, password, &yubikey_response)
+		.unwrap_or_else(|err| panic!("Failed to open database: {}", format_fortress_error(err)))
+}
+
+
+/// The response of the YubiKey plugged in to `challenge`, using the HMAC-SHA1 challenge-response configured in its second slot.
+/// None if ykchalresp (from yubikey-personalization) isn't installed or there's no YubiKey.
+fn yubikey_response(challenge: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+	eprintln!("Touch your YubiKey if it's flashing...");
+	let output = Command::new("ykchalresp")
+		.args(["-2", "-x", &data_encoding::HEXLOWER.encode(challenge)])
+		.stderr(Stdio::inherit())
+		.output()
+		.ok()
+		.filter(|output| output.status.success())?;
+	let output = Zeroizing::new(output.stdout);
+
+	data_encoding::HEXLOWER_PERMISSIVE.decode(output.trim_ascii()).ok().map(Zeroizing::new)
+}
+
+
+fn save_database<P: AsRef<Path>>(database: &Database, path: P) {
+	database
+		.save_to_path(path)
+		.unwrap_or_else(|err| panic!("Failed to save database: {}", format_fortress_error(err)));
+}
+
+
+/// Looks up the single entry matching `query` (see `Database::find_entries`)
+fn find_entry(database: &Database, query: &str) -> ID {
+	match database.find_entries(query).as_slice() {
+		[entry] => *entry.get_id(),
+		[] => panic!("No entry matches '{query}'"),
+		entries => panic!("'{query}' matches {} entries; use more of the ID to pick one", entries.len()),
+	}
+}
+
+
+/// Looks up the single directory matching `query` (see `Database::find_directories`)
+fn find_directory(database: &Database, query: &str) -> ID {
+	match database.find_directories(query).as_slice() {
+		[directory] => *directory.get_id(),
+		[] => panic!("No directory matches '{query}'"),
+		directories => panic!("'{query}' matches {} directories; use more of the ID to pick one", directories.len()),
+	}
+}
+
+
+fn entry_data(fields: &[(String, String)], generated: Option<String>) -> HashMap<String, String> {
+	fields
+		.iter()
+		.cloned()
+		.chain(generated.map(|password| ("password".to_owned(), password)))
+		.collect()
+}
+
+
+/// `-` stands for stdin or stdout in the encrypt and decrypt commands
+fn is_stdio(path: &Path) -> bool {
+	path == Path::new("-")
+}
+
+
+fn open_input(path: &Path) -> Box<dyn Read> {
+	if is_stdio(path) {
+		Box::new(io::stdin().lock())
+	} else {
+		Box::new(BufReader::new(File::open(path).expect("Failed to open file")))
+	}
+}
+
+
+// Written in place, rather than through a temporary file, so plaintext never lands anywhere the user didn't ask for
+fn open_output(path: &Path) -> Box<dyn Write> {
+	if is_stdio(path) {
+		Box::new(io::stdout().lock())
+	} else {
+		Box::new(File::create(path).expect("Failed to create output file"))
+	}
+}
+
+
+/// Read file and decrypt
+fn do_decrypt(path: &Path, output: &Path, password: &str) {
+	let (payload, _) = libfortress::fortresscrypto::decrypt_from_file(&mut open_input(path), password.as_bytes()).expect("Failed to decrypt file");
+
+	let mut output = open_output(output);
+	output.write_all(&payload).expect("Failed to write output");
+	output.flush().expect("Failed to write output");
+}
+
+
+/// Read file and encrypt
+fn do_encrypt(path: &Path, output: &Path, password: &str) {
+	let payload = {
+		let mut data = Zeroizing::new(Vec::new());
+		open_input(path).read_to_end(&mut data).expect("Failed to read file");
+		data
+	};
+
+	let encryption_parameters = Default::default();
+	let file_key_suite =
+		libfortress::fortresscrypto::FileKeySuite::derive(password.as_bytes(), &encryption_parameters).expect("Failed to derive file key suite");
+
+	let mut output = open_output(output);
+	libfortress::fortresscrypto::encrypt_to_file(&mut output, &payload, &file_key_suite).expect("Failed to encrypt file");
+	output.flush().expect("Failed to write output");
+}
+
+
+/// Load database and export it as JSON
+fn do_export<P: AsRef<Path>>(path: P, password: &str, options: &ExportOptions) {
+	let database = Database::load_from_path(path, password).unwrap_or_else(|err| panic!("Failed to open database: {}", format_fortress_error(err)));
+
+	database.export_json(io::stdout().lock(), options).expect("Failed to export database");
+}
+
+
+fn do_change_log<P: AsRef<Path>>(path: P, password: &str, since: u64, format: ChangeLogFormat) {
+	let database = Database::load_from_path(path, password).unwrap_or_else(|err| panic!("Failed to open database: {}", format_fortress_error(err)));
+
+	database
+		.export_change_log(io::stdout().lock(), since, format)
+		.expect("Failed to export change log");
+}
+
+
+/// Load database and print an audit of its passwords, without saving it
+fn do_audit<P: AsRef<Path>>(path: P, password: &str, options: &AuditOptions, json: bool) {
+	let database = load_database(path, password);
+	let report = database.audit(options);
+
+	if json {
+		serde_json::to_writer_pretty(io::stdout().lock(), &report).expect("Failed to write audit");
+		println!();
+		return;
+	}
+
+	let print_entries = |heading: &str, ids: &[ID]| {
+		println!("{heading} ({}):", ids.len());
+		for id in ids {
+			let title = database.get_entry_by_id(id).and_then(|entry| entry.get("title").cloned());
+			println!("  {}  {}", &id.to_hex()[..12], title.as_deref().unwrap_or("(untitled)"));
+		}
+	};
+
+	println!(
+		"Health score: {}% ({} entries, {} with a password)",
+		report.health_score, report.entries, report.entries_with_password
+	);
+	print_entries("Weak passwords", &report.weak_passwords);
+	for (i, group) in report.reused_passwords.iter().enumerate() {
+		print_entries(&format!("Reused password {}", i + 1), group);
+	}
+	print_entries("Old passwords", &report.old_passwords);
+	print_entries("No URL", &report.missing_urls);
+	print_entries("Never used", &report.never_used);
+}
+
+
+/// Import a 1Password export into a database, unless `dry_run`
+fn do_import_1password<P: AsRef<Path>>(path: P, password: &str, export: &Path, dry_run: bool) {
+	let data = fs::read(export).unwrap_or_else(|err| panic!("Failed to read '{}': {}", export.display(), err));
+	// 1PUX exports are zip files
+	let plan = if data.starts_with(b"PK\x03\x04") {
+		ImportPlan::from_1pux(&data)
+	} else {
+		ImportPlan::from_1password_csv(&data)
+	}
+	.unwrap_or_else(|err| panic!("Failed to read '{}': {}", export.display(), format_fortress_error(err)));
+
+	import_plan(path, password, &plan, dry_run);
+}
+
+
+/// Import passwords exported from a browser into a database, unless `dry_run`
+fn do_import_browser<P: AsRef<Path>>(path: P, password: &str, csv: &Path, dry_run: bool) {
+	let data = fs::read(csv).unwrap_or_else(|err| panic!("Failed to read '{}': {}", csv.display(), err));
+	let plan = ImportPlan::from_browser_csv(&data).unwrap_or_else(|err| panic!("Failed to read '{}': {}", csv.display(), format_fortress_error(err)));
+
+	import_plan(path, password, &plan, dry_run);
+}
+
+
+/// Load database, add everything in `plan` to it, and save it.  Only prints what would be imported if `dry_run`.
+fn import_plan<P: AsRef<Path>>(path: P, password: &str, plan: &ImportPlan, dry_run: bool) {
+	for skipped in &plan.skipped {
+		eprintln!("Skipped: {skipped}");
+	}
+	for conflict in &plan.conflicts {
+		eprintln!("Needs review: {conflict}");
+	}
+
+	if dry_run {
+		eprintln!("Would import {}.", plan.summary());
+		return;
+	}
+
+	let mut database = load_database(&path, password);
+	database
+		.import(plan)
+		.unwrap_or_else(|err| panic!("Failed to import: {}", format_fortress_error(err)));
+	save_database(&database, &path);
+
+	eprintln!("Imported {}.", plan.summary());
+}
+
+
+/// Re-encrypt a database file in place with new KDF parameters
+fn do_reencrypt<P: AsRef<Path>>(path: P, password: &str, log_n: Option<u8>, r: Option<u32>, p: Option<u32>) {
+	let defaults = FileKdfParameters::default();
+	let params = FileKdfParameters {
+		log_n: log_n.unwrap_or(defaults.log_n),
+		r: r.unwrap_or(defaults.r),
+		p: p.unwrap_or(defaults.p),
+		..defaults
+	};
+
+	let backup_path =
+		Database::reencrypt(path, password, &params).unwrap_or_else(|err| panic!("Failed to re-encrypt database: {}", format_fortress_error(err)));
+
+	eprintln!("Database re-encrypted. The original was saved to '{}'.", backup_path.display());
+}
+
+
+/// Convert a legacy fortress1 database file in place to the current format
+fn do_upgrade_legacy<P: AsRef<Path>>(path: P, username: &str, password: &str) {
+	let backup_path = Database::upgrade_legacy_file(path, username, password)
+		.unwrap_or_else(|err| panic!("Failed to upgrade legacy database: {}", format_fortress_error(err)));
+
+	eprintln!("Database upgraded. The original was saved to '{}'.", backup_path.display());
+}
+
+
+/// Load database and write a paper backup of it
+fn do_paper_backup<P: AsRef<Path>>(path: P, password: &str, options: &PaperBackupOptions) {
+	let database = Database::load_from_path(path, password).unwrap_or_else(|err| panic!("Failed to open database: {}", format_fortress_error(err)));
+
+	let blocks = database
+		.export_paper_backup(io::stdout().lock(), password, options)
+		.unwrap_or_else(|err| panic!("Failed to write paper backup: {}", format_fortress_error(err)));
+
+	eprintln!("Paper backup written ({blocks} blocks). Print it, or encode each block as a QR code.");
+}
+
+
+/// Restore a database from a paper backup and save it to a new file
+fn do_restore_paper_backup<P: AsRef<Path>, Q: AsRef<Path>>(backup: P, path: Q, password: &str) {
+	let path = path.as_ref();
+
+	if path.exists() {
+		panic!("'{}' already exists; refusing to overwrite it", path.display());
+	}
+
+	let file = File::open(backup).expect("Failed to open paper backup");
+	let database = Database::import_paper_backup(&mut BufReader::new(file), password)
+		.unwrap_or_else(|err| panic!("Failed to restore paper backup: {}", format_fortress_error(err)));
+	database
+		.save_to_path(path)
+		.unwrap_or_else(|err| panic!("Failed to save database: {}", format_fortress_error(err)));
+
+	eprintln!("Database restored to '{}'. Sync it to recover the rest of your data.", path.display());
+}
+
+
+/// Load database, add an entry to it, and save it
+fn do_add<P: AsRef<Path>>(path: P, password: &str, fields: &[(String, String)], generated: Option<String>, parent: Option<&str>) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+	let parent_id = match parent {
+		Some(parent) => find_directory(&database, parent),
+		None => *database.get_root().get_id(),
+	};
+
+	let mut entry = Entry::new();
+	let entry_id = *entry.get_id();
+	entry
+		.try_edit(EntryHistory::new(entry_data(fields, generated)))
+		.unwrap_or_else(|err| panic!("Failed to add the entry: {err}"));
+	database.add_entry(entry);
+	database.move_object(&entry_id, &parent_id);
+	save_database(&database, path);
+
+	println!("{}", entry_id.to_hex());
+}
+
+
+/// Load database and print an entry
+fn do_show<P: AsRef<Path>>(path: P, password: &str, query: &str, reveal: bool, field: Option<&str>, copy: Option<Duration>) {
+	let database = load_database(path, password);
+	let entry = database.get_entry_by_id(&find_entry(&database, query)).expect("Entry not found");
+
+	if let Some(timeout) = copy {
+		let field = field.unwrap_or("password");
+		let value = entry.get(field).unwrap_or_else(|| panic!("The entry has no '{field}' field"));
+		let pending = libfortress::clipboard::copy_secret(SystemClipboard, value, timeout)
+			.unwrap_or_else(|err| panic!("Failed to copy to the clipboard: {}", format_fortress_error(err)));
+		drop(database);
+
+		// The clipboard is only cleared if we're still running, so wait for it
+		eprintln!("Copied {field} to the clipboard. It will be cleared in {} seconds.", timeout.as_secs());
+		pending
+			.wait()
+			.unwrap_or_else(|err| panic!("Failed to clear the clipboard: {}", format_fortress_error(err)));
+		return;
+	}
+
+	if let Some(field) = field {
+		let value = entry.get(field).unwrap_or_else(|| panic!("The entry has no '{field}' field"));
+		println!("{value}");
+		return;
+	}
+
+	println!("id: {}", entry.get_id().to_hex());
+	for field in entry.get_fields() {
+		let value = if field.protected && !reveal { "********" } else { field.value };
+		println!("{}: {}", field.name, value);
+	}
+	if !entry.get_tags().is_empty() {
+		println!("tags: {}", entry.get_tags().iter().cloned().collect::<Vec<_>>().join(", "));
+	}
+}
+
+
+/// Load database, edit an entry, and save it
+fn do_edit<P: AsRef<Path>>(path: P, password: &str, query: &str, fields: &[(String, String)], generated: Option<String>) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+	let entry_id = find_entry(&database, query);
+
+	let entry = database.get_entry_by_id_mut(&entry_id).expect("Entry not found");
+	entry
+		.try_edit(entry.new_edit(entry_data(fields, generated)))
+		.unwrap_or_else(|err| panic!("Failed to edit the entry: {err}"));
+	save_database(&database, path);
+}
+
+
+/// Load database and list a directory's subdirectories and entries, one per line: the start of the ID, then the name
+fn do_ls<P: AsRef<Path>>(path: P, password: &str, directory: Option<&str>, recursive: bool) {
+	let database = load_database(path, password);
+	let directory_id = match directory {
+		Some(directory) => find_directory(&database, directory),
+		None => *database.get_root().get_id(),
+	};
+
+	print_directory(&database, &directory_id, recursive, 0);
+}
+
+
+fn print_directory(database: &Database, directory_id: &ID, recursive: bool, depth: usize) {
+	let Some(directory) = database.get_directory_by_id(directory_id) else {
+		return;
+	};
+	let indent = "  ".repeat(depth);
+	let short_id = |id: &ID| id.to_hex()[..12].to_owned();
+
+	let mut directories = directory
+		.list_directories(database)
+		.filter_map(|id| database.get_directory_by_id(id))
+		.collect::<Vec<_>>();
+	directories.sort_by_key(|directory| directory.get_name().unwrap_or_default().to_lowercase());
+	for subdirectory in directories {
+		println!(
+			"{}  {}{}/",
+			short_id(subdirectory.get_id()),
+			indent,
+			subdirectory.get_name().unwrap_or_default()
+		);
+
+		// Directories can't contain themselves, but don't loop forever on a malformed tree
+		if recursive && depth < 64 {
+			print_directory(database, subdirectory.get_id(), recursive, depth + 1);
+		}
+	}
+
+	let mut entries = directory
+		.list_entries(database)
+		.into_iter()
+		.filter_map(|id| database.get_entry_by_id(id))
+		.collect::<Vec<_>>();
+	entries.sort_by_key(|entry| entry.get("title").map(|title| title.to_lowercase()).unwrap_or_default());
+	for entry in entries {
+		println!(
+			"{}  {}{}",
+			short_id(entry.get_id()),
+			indent,
+			entry.get("title").map(String::as_str).unwrap_or("(untitled)")
+		);
+	}
+}
+
+
+/// Load database, move an entry or directory, and save it
+fn do_mv<P: AsRef<Path>>(path: P, password: &str, object: &str, directory: &str) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+	let directory_id = find_directory(&database, directory);
+	let object_id = match (database.find_entries(object).as_slice(), database.find_directories(object).as_slice()) {
+		([], [_]) => find_directory(&database, object),
+		(_, []) => find_entry(&database, object),
+		_ => panic!("'{object}' matches both entries and directories; use more of the ID to pick one"),
+	};
+
+	// Moving a directory into itself or one of its subdirectories would cut it off from the root.  (The bound is in case the
+	// tree is malformed and has a cycle.)
+	let mut ancestors = std::iter::successors(Some(directory_id), |id| database.get_parent_directory(id).map(|parent| *parent.get_id()));
+	if ancestors.by_ref().take(1024).any(|id| id == object_id) {
+		panic!("Can't move a directory into itself");
+	}
+
+	database.move_object(&object_id, &directory_id);
+	save_database(&database, path);
+}
+
+
+/// Load database and write a share bundle of the matching entries to `output`
+fn do_share<P: AsRef<Path>>(path: P, password: &str, recipient: &str, queries: &[String], output: &Path) {
+	let database = load_database(path, password);
+	let recipient = SharePublicKey::from_hex(recipient.trim()).expect("Not a valid share key");
+	let entry_ids = queries.iter().map(|query| find_entry(&database, query)).collect::<Vec<_>>();
+
+	fs::write(output, database.export_share(&entry_ids, &recipient)).unwrap_or_else(|err| panic!("Failed to write {}: {err}", output.display()));
+	eprintln!("Shared {} entries.", entry_ids.len());
+}
+
+
+/// Load database, import a share bundle, and save it
+fn do_import_share<P: AsRef<Path>>(path: P, password: &str, bundle: &Path) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+	let bundle = fs::read(bundle).unwrap_or_else(|err| panic!("Failed to read {}: {err}", bundle.display()));
+
+	let imported = database
+		.import_share(&bundle)
+		.unwrap_or_else(|err| panic!("Failed to import share: {}", format_fortress_error(err)));
+	save_database(&database, path);
+	eprintln!("Imported {} entries.", imported.len());
+}
+
+
+/// Load database, turn needing the YubiKey on or off, and save it
+fn do_hardware_key<P: AsRef<Path>>(path: P, password: &str, disable: bool) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+
+	database
+		.set_hardware_key(password, (!disable).then_some(&yubikey_response))
+		.unwrap_or_else(|err| panic!("Failed to set hardware key: {}", format_fortress_error(err)));
+	save_database(&database, path);
+
+	if disable {
+		eprintln!("The database now only needs its password.");
+	} else {
+		eprintln!("The database now needs the YubiKey as well as its password. It can't be opened without the YubiKey.");
+	}
+}
+
+
+/// Load database, double-lock an entry (or remove its double lock), and save
+fn do_double_lock<P: AsRef<Path>>(path: P, password: &str, query: &str, remove: bool) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+	let entry_id = find_entry(&database, query);
+	let entry = database.get_entry_by_id_mut(&entry_id).expect("Entry not found");
+	if !remove && entry.is_double_locked() {
+		panic!("The entry is already double-locked");
+	}
+
+	let pin = prompt_secret("PIN");
+	let edit = if remove {
+		entry.remove_double_lock_edit(&pin)
+	} else {
+		entry.double_lock_edit(&pin)
+	};
+	entry.edit(edit.unwrap_or_else(|err| panic!("Failed to change the entry's double lock: {}", format_fortress_error(err))));
+	save_database(&database, path);
+
+	if remove {
+		eprintln!("The entry only needs the database's password again.");
+	} else {
+		eprintln!("The entry is double-locked. Use 'unlock' to see its fields.");
+	}
+}
+
+
+/// Load database and print a double-locked entry's fields
+fn do_unlock<P: AsRef<Path>>(path: P, password: &str, query: &str) {
+	let database = load_database(path, password);
+	let entry = database.get_entry_by_id(&find_entry(&database, query)).expect("Entry not found");
+
+	let pin = prompt_secret("PIN");
+	let mut fields = entry
+		.unlock_fields(&pin)
+		.unwrap_or_else(|err| panic!("Failed to unlock the entry: {}", format_fortress_error(err)))
+		.into_iter()
+		.filter(|(_, value)| !value.is_empty())
+		.collect::<Vec<_>>();
+	fields.sort_unstable();
+
+	println!("id: {}", entry.get_id().to_hex());
+	for (name, value) in fields {
+		println!("{name}: {value}");
+	}
+}
+
+
+/// Load database, let another password open it, and save
+fn do_add_user<P: AsRef<Path>>(path: P, password: &str) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+
+	let new_password = prompt_secret("New user's password");
+	if new_password.is_empty() {
+		panic!("The new user's password can't be empty");
+	}
+
+	let user = database
+		.add_user(&*new_password)
+		.unwrap_or_else(|err| panic!("Failed to add user: {}", format_fortress_error(err)));
+	save_database(&database, path);
+
+	eprintln!("Added user {user}.");
+}
+
+
+/// Load database, stop a user's password from opening it, and save
+fn do_remove_user<P: AsRef<Path>>(path: P, password: &str, user: usize) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+
+	database
+		.remove_user(user)
+		.unwrap_or_else(|err| panic!("Failed to remove user: {}", format_fortress_error(err)));
+	save_database(&database, path);
+
+	eprintln!("Removed user {user}.");
+}
+
+
+/// Load database and list its users
+fn do_users<P: AsRef<Path>>(path: P, password: &str) {
+	let database = load_database(path, password);
+
+	for (user, params) in database.get_users().into_iter().enumerate() {
+		let current = if user == database.get_current_user() { " (you)" } else { "" };
+		println!("{user}: scrypt log_n={} r={} p={}{current}", params.log_n, params.r, params.p);
+	}
+}
+
+
+/// Load database, permanently delete what's in its trash, and save
+fn do_empty_trash<P: AsRef<Path>>(path: P, password: &str) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+
+	let deleted = database.empty_trash(HistoryLossConfirmed);
+	save_database(&database, path);
+
+	eprintln!("Deleted {deleted} objects from the trash.");
+}
+
+
+/// Load database, sync it, have its sync server drop old deletions, and save
+fn do_gc<P: AsRef<Path>>(path: P, password: &str, days: u64) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+
+	let dropped = database
+		.sync_gc(days)
+		.unwrap_or_else(|err| panic!("Failed to collect garbage: {}", format_fortress_error(err)));
+	save_database(&database, path);
+
+	eprintln!("Dropped {dropped} deleted objects from the sync server.");
+}
+
+
+#[cfg(unix)]
+fn do_serve(path: &Path, socket: &Path, password: Zeroizing<String>) {
+	let database = WatchedDatabase::load(path.to_owned(), password).unwrap_or_else(|err| panic!("Failed to open database: {}", format_fortress_error(err)));
+
+	serve::run(database, socket).unwrap_or_else(|err| panic!("Failed to serve on {}: {err}", socket.display()));
+}
+
+#[cfg(not(unix))]
+fn do_serve(_path: &Path, _socket: &Path, _password: Zeroizing<String>) {
+	panic!("serve is only supported on Unix");
+}
+
+
+#[cfg(unix)]
+fn do_agent(path: &Path, socket: &Path, password: Zeroizing<String>) {
+	let database = WatchedDatabase::load(path.to_owned(), password).unwrap_or_else(|err| panic!("Failed to open database: {}", format_fortress_error(err)));
+
+	agent::run(database, socket).unwrap_or_else(|err| panic!("Failed to serve on {}: {err}", socket.display()));
+}
+
+#[cfg(not(unix))]
+fn do_agent(_path: &Path, _socket: &Path, _password: Zeroizing<String>) {
+	panic!("agent is only supported on Unix");
+}
+
+
+/// Load database, sync it, and save it
+fn do_sync<P: AsRef<Path>>(path: P, password: &str, merge_accounts: bool, dry_run: bool) {
+	let path = path.as_ref();
+	let mut database = load_database(path, password);
+
+	if merge_accounts {
+		database.confirm_account_change(AccountChangeConfirmed);
+	}
+
+	let result = if dry_run { database.sync_preview() } else { database.sync() };
+	let report = result.unwrap_or_else(|err| match err {
+		FortressError::SyncAccountChanged => {
+			panic!("The database was last synced with a different account, and syncing would merge in that account's entries. Use --merge-accounts if that's intended.")
+		},
+		err => panic!("Failed to sync: {}", format_fortress_error(err)),
+	});
+
+	if dry_run {
+		eprint!("Dry run: ");
+	} else {
+		save_database(&database, path);
+	}
+
+	eprintln!("{}", report.summary());
+	for conflict in report.conflicts {
+		match conflict {
+			SyncConflict::DirectoryRename { directory, kept, discarded } => {
+				eprintln!(
+					"Directory {} was renamed to both '{kept}' and '{discarded}'; kept '{kept}'",
+					&directory.to_hex()[..12]
+				);
+			},
+		}
+	}
 }
@@ -0,0 +1,301 @@
+// Native messaging host for browser extensions (`fortress native-host`).
+// Chrome and Firefox start the host when an extension connects to it, and exchange JSON messages with it over stdin and stdout,
+// each prefixed with its length as a native-endian u32.  Every request has a "command" field; every response is either
+// {"ok": true, "result": ...} or {"ok": false, "error": "..."}.
+//
+// The database starts locked, and only "status" and "unlock" work until the extension sends the user's password.  Credentials
+// are only handed out for entries whose URL matches the page they're for (see `Database::find_entries_for_url`).  The app
+// may have the same database open (see WatchedDatabase).
+use std::{
+	collections::HashMap,
+	io::{self, Read, Write},
+	path::PathBuf,
+};
+
+use libfortress::{fortresscrypto::Zeroizing, Database, Entry, ID};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{format_fortress_error, watched_database::WatchedDatabase};
+
+
+// Largest message we'll accept from the browser.  Chrome limits messages to the host to 4 GiB, which is far more than any request needs.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Request {
+	Status,
+	Unlock {
+		password: String,
+	},
+	Lock,
+	/// Entries for the page at `url`, without their passwords
+	Query {
+		url: String,
+	},
+	/// The username and password of entry `id`, for filling in on the page at `url`
+	Fill {
+		id: ID,
+		url: String,
+	},
+	/// Adds a new entry for a login the user made on the page at `url`
+	Save {
+		url: String,
+		username: String,
+		password: String,
+		title: Option<String>,
+	},
+}
+
+
+struct NativeHost {
+	path: PathBuf,
+	database: Option<WatchedDatabase>,
+}
+
+impl NativeHost {
+	fn handle(&mut self, request: Request) -> Result<serde_json::Value, String> {
+		self.reload_if_modified()?;
+
+		match request {
+			Request::Status => Ok(json!({ "unlocked": self.database.is_some(), "database": self.path })),
+			Request::Unlock { password } => {
+				let database = WatchedDatabase::load(self.path.clone(), Zeroizing::new(password)).map_err(format_fortress_error)?;
+				self.database = Some(database);
+				Ok(json!({}))
+			},
+			Request::Lock => {
+				self.database = None;
+				Ok(json!({}))
+			},
+			Request::Query { url } => {
+				let database = self.get_database()?;
+				libfortress::normalize_url(&url).ok_or("Invalid URL.")?;
+
+				let entries = database
+					.find_entries_for_url(&url)
+					.into_iter()
+					.map(|entry| json!({ "id": entry.get_id(), "title": entry.get("title"), "username": entry.get("username") }))
+					.collect::<Vec<_>>();
+
+				Ok(json!({ "entries": entries }))
+			},
+			Request::Fill { id, url } => {
+				let database = self.get_database()?;
+				let entry = database
+					.find_entries_for_url(&url)
+					.into_iter()
+					.find(|entry| *entry.get_id() == id)
+					.ok_or("No matching entry.")?;
+
+				Ok(json!({ "username": entry.get("username"), "password": entry.get("password") }))
+			},
+			Request::Save {
+				url,
+				username,
+				password,
+				title,
+			} => {
+				let password = Zeroizing::new(password);
+				let database = self.database.as_mut().ok_or("Database is locked.")?;
+				let host = libfortress::normalize_url(&url)
+					.and_then(|url| url.host_str().map(str::to_owned))
+					.ok_or("Invalid URL.")?;
+
+				let mut entry = Entry::new();
+				let id = *entry.get_id();
+				let data = HashMap::from([
+					("title".to_owned(), title.unwrap_or(host)),
+					("url".to_owned(), url),
+					("username".to_owned(), username),
+					("password".to_owned(), password.to_string()),
+				]);
+				entry.try_edit(entry.new_edit(data)).map_err(|err| format_fortress_error(err.into()))?;
+				database.get_mut().add_entry(entry);
+				database.save().map_err(format_fortress_error)?;
+
+				Ok(json!({ "id": id }))
+			},
+		}
+	}
+
+	fn get_database(&self) -> Result<&Database, String> {
+		Ok(self.database.as_ref().ok_or("Database is locked.")?.get())
+	}
+
+	// Locks the database if it can't be loaded again
+	fn reload_if_modified(&mut self) -> Result<(), String> {
+		if let Some(Err(err)) = self.database.as_mut().map(WatchedDatabase::reload_if_modified) {
+			self.database = None;
+			return Err(format_fortress_error(err));
+		}
+
+		Ok(())
+	}
+}
+
+
+/// Serves requests from the browser until it disconnects.
+pub fn run(path: PathBuf) -> io::Result<()> {
+	let mut host = NativeHost { path, database: None };
+	let mut stdin = io::stdin().lock();
+	let mut stdout = io::stdout().lock();
+
+	while let Some(message) = read_message(&mut stdin)? {
+		let response = match serde_json::from_slice(&message) {
+			Ok(request) => host.handle(request),
+			Err(err) => Err(format!("Invalid request: {err}")),
+		};
+		let response = match response {
+			Ok(result) => json!({ "ok": true, "result": result }),
+			Err(error) => json!({ "ok": false, "error": error }),
+		};
+
+		write_message(&mut stdout, &Zeroizing::new(serde_json::to_vec(&response)?))?;
+	}
+
+	Ok(())
+}
+
+
+// None once the browser closes stdin
+fn read_message<R: Read>(reader: &mut R) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+	let mut length = [0u8; 4];
+
+	match reader.read_exact(&mut length) {
+		Ok(()) => {},
+		Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(err) => return Err(err),
+	}
+
+	let length = u32::from_ne_bytes(length) as usize;
+	if length > MAX_MESSAGE_SIZE {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Message too large"));
+	}
+
+	let mut message = Zeroizing::new(vec![0; length]);
+	reader.read_exact(&mut message)?;
+	Ok(Some(message))
+}
+
+
+fn write_message<W: Write>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+	let length = u32::try_from(message.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Message too large"))?;
+
+	writer.write_all(&length.to_ne_bytes())?;
+	writer.write_all(message)?;
+	writer.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+	use crate::watched_database::tests::{saved_database, PASSWORD};
+
+	fn request(request: serde_json::Value) -> Request {
+		serde_json::from_value(request).unwrap()
+	}
+
+	#[test]
+	fn message_framing() {
+		let mut stream = Vec::new();
+		write_message(&mut stream, br#"{"command":"status"}"#).unwrap();
+		write_message(&mut stream, b"").unwrap();
+		assert_eq!(stream[..4], 20u32.to_ne_bytes());
+
+		// Messages are read one at a time, and the browser closing stdin between them isn't an error
+		let mut reader = &stream[..];
+		assert_eq!(read_message(&mut reader).unwrap().unwrap().as_slice(), br#"{"command":"status"}"#);
+		assert_eq!(read_message(&mut reader).unwrap().unwrap().as_slice(), b"");
+		assert!(read_message(&mut reader).unwrap().is_none());
+
+		// Closing it partway through a message is
+		let mut reader = &stream[..10];
+		assert_eq!(read_message(&mut reader).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+		// Oversized messages are refused without reading them
+		let oversized = [&(MAX_MESSAGE_SIZE as u32 + 1).to_ne_bytes()[..], &[b' '; 16]].concat();
+		let mut reader = &oversized[..];
+		assert_eq!(read_message(&mut reader).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(reader.len(), 16);
+
+		let mut largest = Vec::new();
+		write_message(&mut largest, &vec![b' '; MAX_MESSAGE_SIZE]).unwrap();
+		let mut reader = &largest[..];
+		assert_eq!(read_message(&mut reader).unwrap().unwrap().len(), MAX_MESSAGE_SIZE);
+	}
+
+	#[test]
+	fn locked() {
+		let mut host = NativeHost {
+			path: PathBuf::from("missing.fortressdb"),
+			database: None,
+		};
+		let id = *Entry::new().get_id();
+		let locked = Err("Database is locked.".to_owned());
+
+		assert_eq!(host.handle(request(json!({ "command": "status" }))).unwrap()["unlocked"], false);
+		assert_eq!(host.handle(request(json!({ "command": "query", "url": "https://example.com" }))), locked);
+		assert_eq!(
+			host.handle(request(json!({ "command": "fill", "id": id, "url": "https://example.com" }))),
+			locked
+		);
+		assert_eq!(
+			host.handle(request(
+				json!({ "command": "save", "url": "https://example.com", "username": "alice", "password": "hunter2" })
+			)),
+			locked
+		);
+	}
+
+	#[test]
+	fn fill_only_for_matching_url() {
+		let (_dir, path, ids) = saved_database(&[&[
+			("title", "Bank"),
+			("url", "https://bank.example.com"),
+			("username", "alice"),
+			("password", "hunter2"),
+		]]);
+		let mut host = NativeHost { path, database: None };
+
+		assert_eq!(
+			host.handle(request(json!({ "command": "unlock", "password": "wrong" }))),
+			Err("Incorrect password.".to_owned())
+		);
+		host.handle(request(json!({ "command": "unlock", "password": PASSWORD }))).unwrap();
+		assert_eq!(host.handle(request(json!({ "command": "status" }))).unwrap()["unlocked"], true);
+
+		// Queries don't give out passwords
+		let result = host
+			.handle(request(json!({ "command": "query", "url": "https://bank.example.com/login" })))
+			.unwrap();
+		assert_eq!(result, json!({ "entries": [{ "id": ids[0], "title": "Bank", "username": "alice" }] }));
+		let result = host.handle(request(json!({ "command": "query", "url": "https://example.net" }))).unwrap();
+		assert_eq!(result, json!({ "entries": [] }));
+
+		let fill = |host: &mut NativeHost, url: &str| host.handle(request(json!({ "command": "fill", "id": ids[0], "url": url })));
+		assert_eq!(
+			fill(&mut host, "https://bank.example.com/login").unwrap(),
+			json!({ "username": "alice", "password": "hunter2" })
+		);
+
+		// The entry's credentials are only handed to its own site, however the page's URL is dressed up
+		for url in [
+			"https://example.net/login",
+			"https://bank.example.com.example.net/",
+			"https://example.com/",
+			"http://bank.example.com/login",
+			"not a url",
+		] {
+			assert_eq!(fill(&mut host, url), Err("No matching entry.".to_owned()), "{url}");
+		}
+
+		host.handle(request(json!({ "command": "lock" }))).unwrap();
+		assert_eq!(fill(&mut host, "https://bank.example.com/login"), Err("Database is locked.".to_owned()));
+	}
+}
@@ -0,0 +1,294 @@
+// Local API for scripts and other tools (`fortress serve`), such as rofi scripts and shell helpers.
+// Listens on a Unix domain socket that only processes running as the same user can use (see unix_socket).  Each line a client
+// sends is a JSON request with a "command" field, answered with one line: {"ok": true, "result": ...} or {"ok": false, "error": "..."}.
+// Clients sending a line longer than MAX_REQUEST_SIZE are disconnected.
+//
+// The database is unlocked when the server starts, and stays unlocked until it's stopped.  The app may have the same database
+// open (see WatchedDatabase).
+use std::{
+	io::{self, BufRead, BufReader, Read, Write},
+	os::unix::net::UnixStream,
+	path::Path,
+	time::Duration,
+};
+
+use libfortress::{fortresscrypto::Zeroizing, Entry, GeneratorOptions, ID, MAX_GENERATED_LENGTH};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{format_fortress_error, unix_socket, watched_database::WatchedDatabase};
+
+
+// Clients that go quiet for this long are disconnected, so they can't keep others waiting
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Longest request line we'll accept, including its newline.  The same limit as native_host's messages, and far more than any
+// request needs.
+const MAX_REQUEST_SIZE: usize = 1 << 20;
+
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Request {
+	/// Every entry, without secrets
+	List,
+	/// Entries matching `query` (see `Database::search`), best first, without secrets
+	Search { query: String },
+	/// The value of one of an entry's fields; the password by default
+	GetSecret { id: ID, field: Option<String> },
+	/// A new random password, using the same defaults as `fortress generate`, up to MAX_GENERATED_LENGTH characters long
+	Generate {
+		length: Option<usize>,
+		uppercase: Option<bool>,
+		lowercase: Option<bool>,
+		numbers: Option<bool>,
+		others: Option<String>,
+		exclude_ambiguous: Option<bool>,
+		require_each_selected_class: Option<bool>,
+		no_repeated_characters: Option<bool>,
+	},
+}
+
+
+/// Serves requests on `socket` until the process is stopped.
+pub fn run(mut database: WatchedDatabase, socket: &Path) -> io::Result<()> {
+	let listener = unix_socket::bind_private(socket)?;
+	eprintln!("Listening on {}", socket.display());
+
+	for stream in listener.incoming() {
+		let stream = match stream {
+			Ok(stream) => stream,
+			Err(err) => {
+				eprintln!("WARNING: Failed to accept connection: {err}");
+				continue;
+			},
+		};
+
+		// One bad client shouldn't stop the server
+		if let Err(err) = serve_client(&mut database, stream) {
+			eprintln!("WARNING: Connection failed: {err}");
+		}
+	}
+
+	Ok(())
+}
+
+
+fn serve_client(database: &mut WatchedDatabase, stream: UnixStream) -> io::Result<()> {
+	unix_socket::check_peer(&stream)?;
+	stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+	let mut writer = stream.try_clone()?;
+	let mut reader = BufReader::new(stream);
+
+	while let Some(line) = read_request(&mut reader)? {
+		writer.write_all(&respond(database, &line)?)?;
+	}
+
+	Ok(())
+}
+
+
+// None once the client disconnects
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Zeroizing<String>>> {
+	let mut line = Zeroizing::new(String::new());
+	let length = reader.take(MAX_REQUEST_SIZE as u64).read_line(&mut line)?;
+
+	if length == 0 {
+		return Ok(None);
+	}
+	if length == MAX_REQUEST_SIZE && !line.ends_with('\n') {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Request too large"));
+	}
+
+	Ok(Some(line))
+}
+
+
+// The response line to a request line
+fn respond(database: &mut WatchedDatabase, line: &str) -> io::Result<Zeroizing<Vec<u8>>> {
+	let response = match serde_json::from_str(line) {
+		Ok(request) => handle(database, request),
+		Err(err) => Err(format!("Invalid request: {err}")),
+	};
+	let response = match response {
+		Ok(result) => json!({ "ok": true, "result": result }),
+		Err(error) => json!({ "ok": false, "error": error }),
+	};
+
+	let mut response = Zeroizing::new(serde_json::to_vec(&response)?);
+	response.push(b'\n');
+	Ok(response)
+}
+
+
+fn handle(database: &mut WatchedDatabase, request: Request) -> Result<serde_json::Value, String> {
+	database.reload_if_modified().map_err(format_fortress_error)?;
+	let database = database.get();
+	let trashed = database.list_trash_recursive();
+
+	match request {
+		Request::List => {
+			let entries = database
+				.list_entries()
+				.filter(|entry| !trashed.contains(entry.get_id()))
+				.map(entry_summary)
+				.collect::<Vec<_>>();
+
+			Ok(json!({ "entries": entries }))
+		},
+		Request::Search { query } => {
+			let entries = database
+				.search(&query)
+				.iter()
+				.filter(|hit| !trashed.contains(&hit.id))
+				.filter_map(|hit| database.get_entry_by_id(&hit.id))
+				.map(entry_summary)
+				.collect::<Vec<_>>();
+
+			Ok(json!({ "entries": entries }))
+		},
+		Request::GetSecret { id, field } => {
+			let entry = database
+				.get_entry_by_id(&id)
+				.filter(|entry| !trashed.contains(entry.get_id()))
+				.ok_or("Entry not found.")?;
+			let field = field.as_deref().unwrap_or("password");
+
+			Ok(json!({ "value": entry.get(field).ok_or_else(|| format!("Entry has no {field} field."))? }))
+		},
+		Request::Generate {
+			length,
+			uppercase,
+			lowercase,
+			numbers,
+			others,
+			exclude_ambiguous,
+			require_each_selected_class,
+			no_repeated_characters,
+		} => {
+			let length = length.unwrap_or(20);
+			if length > MAX_GENERATED_LENGTH {
+				return Err(format!("Passwords can be at most {MAX_GENERATED_LENGTH} characters long."));
+			}
+
+			let options = GeneratorOptions {
+				length,
+				uppercase: uppercase.unwrap_or(true),
+				lowercase: lowercase.unwrap_or(true),
+				numbers: numbers.unwrap_or(true),
+				others: others.unwrap_or_default(),
+				exclude_ambiguous: exclude_ambiguous.unwrap_or(false),
+				require_each_selected_class: require_each_selected_class.unwrap_or(false),
+				no_repeated_characters: no_repeated_characters.unwrap_or(false),
+			};
+			let password = options.generate().map_err(format_fortress_error)?;
+
+			Ok(json!({ "password": password }))
+		},
+	}
+}
+
+
+fn entry_summary(entry: &Entry) -> serde_json::Value {
+	json!({
+		"id": entry.get_id(),
+		"title": entry.get("title"),
+		"username": entry.get("username"),
+		"url": entry.get("url"),
+	})
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::watched_database::tests::{saved_database, PASSWORD};
+
+	fn request(database: &mut WatchedDatabase, request: serde_json::Value) -> serde_json::Value {
+		let response = respond(database, &format!("{request}\n")).unwrap();
+		assert!(response.ends_with(b"\n"));
+		serde_json::from_slice(&response).unwrap()
+	}
+
+	#[test]
+	fn request_lines() {
+		let mut reader = "{\"command\":\"list\"}\n\n{\"command\":\"list\"}".as_bytes();
+		assert_eq!(read_request(&mut reader).unwrap().unwrap().as_str(), "{\"command\":\"list\"}\n");
+		assert_eq!(read_request(&mut reader).unwrap().unwrap().as_str(), "\n");
+		// A last line without a newline is still a request
+		assert_eq!(read_request(&mut reader).unwrap().unwrap().as_str(), "{\"command\":\"list\"}");
+		assert!(read_request(&mut reader).unwrap().is_none());
+
+		// Lines are only read up to the limit
+		let longest = format!("{}\n", " ".repeat(MAX_REQUEST_SIZE - 1));
+		let mut reader = longest.as_bytes();
+		assert_eq!(read_request(&mut reader).unwrap().unwrap().len(), MAX_REQUEST_SIZE);
+		let too_long = format!("{}\n", " ".repeat(MAX_REQUEST_SIZE));
+		let mut reader = too_long.as_bytes();
+		assert_eq!(read_request(&mut reader).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(reader.len(), 1);
+	}
+
+	#[test]
+	fn requests() {
+		let (_dir, path, ids) = saved_database(&[
+			&[("title", "Bank"), ("username", "alice"), ("password", "hunter2"), ("pin", "1234")],
+			&[("title", "Old bank"), ("password", "letmein")],
+		]);
+		let mut database = WatchedDatabase::load(path, Zeroizing::new(PASSWORD.to_owned())).unwrap();
+		database.get_mut().trash_object(&ids[1]);
+		database.save().unwrap();
+
+		let response = respond(&mut database, "not json\n").unwrap();
+		let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+		assert_eq!(response["ok"], false);
+		assert!(response["error"].as_str().unwrap().starts_with("Invalid request: "));
+		assert_eq!(request(&mut database, json!({ "command": "unknown" }))["ok"], false);
+
+		// Trashed entries are left out of listings, and their secrets aren't given out
+		let entries = json!([{ "id": ids[0], "title": "Bank", "username": "alice", "url": null }]);
+		assert_eq!(
+			request(&mut database, json!({ "command": "list" })),
+			json!({ "ok": true, "result": { "entries": entries } })
+		);
+		assert_eq!(
+			request(&mut database, json!({ "command": "get_secret", "id": ids[0] })),
+			json!({ "ok": true, "result": { "value": "hunter2" } })
+		);
+		assert_eq!(
+			request(&mut database, json!({ "command": "get_secret", "id": ids[0], "field": "pin" })),
+			json!({ "ok": true, "result": { "value": "1234" } })
+		);
+		assert_eq!(
+			request(&mut database, json!({ "command": "get_secret", "id": ids[0], "field": "notes" })),
+			json!({ "ok": false, "error": "Entry has no notes field." })
+		);
+		assert_eq!(
+			request(&mut database, json!({ "command": "get_secret", "id": ids[1] })),
+			json!({ "ok": false, "error": "Entry not found." })
+		);
+	}
+
+	#[test]
+	fn generate() {
+		let (_dir, path, _) = saved_database(&[]);
+		let mut database = WatchedDatabase::load(path, Zeroizing::new(PASSWORD.to_owned())).unwrap();
+		let password = |response: serde_json::Value| response["result"]["password"].as_str().unwrap().to_owned();
+
+		assert_eq!(password(request(&mut database, json!({ "command": "generate" }))).len(), 20);
+		let generated = password(request(
+			&mut database,
+			json!({ "command": "generate", "length": 8, "uppercase": false, "lowercase": false }),
+		));
+		assert!(generated.len() == 8 && generated.chars().all(|c| c.is_ascii_digit()));
+		assert_eq!(
+			password(request(&mut database, json!({ "command": "generate", "length": MAX_GENERATED_LENGTH }))).len(),
+			MAX_GENERATED_LENGTH
+		);
+
+		assert_eq!(
+			request(&mut database, json!({ "command": "generate", "length": MAX_GENERATED_LENGTH + 1 })),
+			json!({ "ok": false, "error": format!("Passwords can be at most {MAX_GENERATED_LENGTH} characters long.") })
+		);
+	}
+}
@@ -0,0 +1,75 @@
+// Unix domain sockets for the long-running modes that answer other processes (serve and agent).  Only processes running as
+// the same user may connect: the socket is only accessible to us, and every connection's peer credentials are checked as well.
+use std::{
+	fs, io,
+	os::unix::{
+		fs::{FileTypeExt, PermissionsExt},
+		io::AsRawFd,
+		net::{UnixListener, UnixStream},
+	},
+	path::Path,
+};
+
+
+/// Binds a socket only we can connect to, replacing one left behind by a server that's no longer running.
+pub fn bind_private(socket: &Path) -> io::Result<UnixListener> {
+	if fs::symlink_metadata(socket).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+		if UnixStream::connect(socket).is_ok() {
+			return Err(io::Error::new(io::ErrorKind::AddrInUse, "Another server is already listening on this socket"));
+		}
+
+		fs::remove_file(socket)?;
+	}
+
+	let listener = UnixListener::bind(socket)?;
+	fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+	Ok(listener)
+}
+
+
+/// Fails unless the other end of `stream` is running as the same user as us.
+pub fn check_peer(stream: &UnixStream) -> io::Result<()> {
+	// SAFETY: geteuid can't fail and has no side effects
+	if peer_uid(stream)? != unsafe { libc::geteuid() } {
+		return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Connection from another user"));
+	}
+
+	Ok(())
+}
+
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+	let mut credentials = libc::ucred { pid: 0, uid: 0, gid: 0 };
+	let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+	// SAFETY: credentials and length are valid for writes of the sizes given, and the socket stays open for the call.
+	let result = unsafe {
+		libc::getsockopt(
+			stream.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_PEERCRED,
+			&mut credentials as *mut libc::ucred as *mut libc::c_void,
+			&mut length,
+		)
+	};
+
+	if result != 0 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(credentials.uid)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+	let mut uid = 0;
+	let mut gid = 0;
+
+	// SAFETY: uid and gid are valid for writes, and the socket stays open for the call.
+	if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(uid)
+}
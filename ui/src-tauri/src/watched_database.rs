@@ -0,0 +1,98 @@
+// An unlocked database for the long-running modes (native-host, serve and agent), which may share the database with the app.  It's
+// loaded again whenever the file changes on disk, so they see the app's edits.  The app only sees their edits once it's
+// unlocked again (and saving from the app before then loses them).
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	time::SystemTime,
+};
+
+use libfortress::{fortresscrypto::Zeroizing, Database, FortressError};
+
+
+pub struct WatchedDatabase {
+	path: PathBuf,
+	database: Database,
+	// Kept to load the database again when it changes
+	password: Zeroizing<String>,
+	modified: Option<SystemTime>,
+}
+
+impl WatchedDatabase {
+	pub fn load(path: PathBuf, password: Zeroizing<String>) -> Result<WatchedDatabase, FortressError> {
+		let modified = modified_time(&path);
+		let database = Database::load_from_path(&path, &password)?;
+
+		Ok(WatchedDatabase {
+			path,
+			database,
+			password,
+			modified,
+		})
+	}
+
+	/// Loads the database again if the file changed since it was loaded or saved.  Fails if it can't be loaded any more, for
+	/// example because the password was changed in the app.
+	pub fn reload_if_modified(&mut self) -> Result<(), FortressError> {
+		if modified_time(&self.path) != self.modified {
+			*self = WatchedDatabase::load(self.path.clone(), self.password.clone())?;
+		}
+
+		Ok(())
+	}
+
+	pub fn get(&self) -> &Database {
+		&self.database
+	}
+
+	pub fn get_mut(&mut self) -> &mut Database {
+		&mut self.database
+	}
+
+	pub fn save(&mut self) -> Result<(), FortressError> {
+		self.database.save_to_path(&self.path)?;
+		self.modified = modified_time(&self.path);
+		Ok(())
+	}
+}
+
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use std::{collections::HashMap, path::PathBuf};
+
+	use libfortress::{Database, Entry, ID};
+	use tempfile::TempDir;
+
+	pub(crate) const PASSWORD: &str = "password";
+
+	/// A database holding `entries`, each given as its fields, saved with PASSWORD in a new temporary directory.  Returns the
+	/// directory, which is removed when it's dropped, the database's path, and the entries' IDs.
+	pub(crate) fn saved_database(entries: &[&[(&str, &str)]]) -> (TempDir, PathBuf, Vec<ID>) {
+		let mut database = Database::new_with_password("username", PASSWORD);
+		let ids = entries
+			.iter()
+			.map(|fields| {
+				let mut entry = Entry::new();
+				let data = fields
+					.iter()
+					.map(|(key, value)| (key.to_string(), value.to_string()))
+					.collect::<HashMap<_, _>>();
+				entry.edit(entry.new_edit(data));
+				let id = *entry.get_id();
+				database.add_entry(entry);
+				id
+			})
+			.collect();
+
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("test.fortressdb");
+		database.save_to_path(&path).unwrap();
+		(dir, path, ids)
+	}
+}
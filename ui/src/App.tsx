@@ -3,6 +3,7 @@ import OpenDatabase from "./OpenDatabase";
 import ViewDatabase from "./ViewDatabase";
 import EntryEditor from "./EntryEditor";
 import Settings from "./Settings";
+import { useEffect } from "react";
 import { atom, useRecoilState } from "recoil";
 import { Entry } from "./state";
 import * as ffi from "./ffi";
@@ -21,7 +22,8 @@ export type AppState =
 	| { variant: AppStateVariant.CreateDatabase }
 	| { variant: AppStateVariant.OpenDatabase }
 	| { variant: AppStateVariant.ViewDatabase }
-	| { variant: AppStateVariant.EditEntry; entry: Entry | null }
+	// note is for new entries, which are notes if it's true
+	| { variant: AppStateVariant.EditEntry; entry: Entry | null; note?: boolean }
 	| { variant: AppStateVariant.Settings };
 
 function app_state_to_component(state: AppState) {
@@ -35,7 +37,7 @@ function app_state_to_component(state: AppState) {
 		case AppStateVariant.ViewDatabase:
 			return <ViewDatabase />;
 		case AppStateVariant.EditEntry:
-			return <EntryEditor entry={state.entry} />;
+			return <EntryEditor entry={state.entry} note={state.note ?? false} />;
 		case AppStateVariant.Settings:
 			return <Settings />;
 	}
@@ -55,6 +57,15 @@ function App() {
 		});
 	}
 
+	// Back to the unlock screen whenever the database is locked, including by auto-lock
+	useEffect(() => {
+		const unlisten = ffi.onDatabaseLocked(() => setAppState({ variant: AppStateVariant.OpenDatabase }));
+
+		return () => {
+			void unlisten.then((unlisten) => unlisten());
+		};
+	}, [setAppState]);
+
 	return <div className="app">{app_state_to_component(app)}</div>;
 }
 
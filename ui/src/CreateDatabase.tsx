@@ -15,6 +15,7 @@ function CreateDatabase() {
 	const setAppState = useSetRecoilState(appState);
 	const setDatabase = useSetRecoilState(databaseState);
 	const [creating, setCreating] = useState(false);
+	const [progress, setProgress] = useState<ffi.DeriveProgress | null>(null);
 
 	function createClicked(event: React.FormEvent) {
 		event.preventDefault();
@@ -31,14 +32,17 @@ function CreateDatabase() {
 		setCreating(true);
 
 		try {
-			await ffi.createDatabase(username, password);
+			await ffi.createDatabase(username, password, setProgress);
 			await refreshDatabase(setDatabase);
 
 			setAppState({ variant: AppStateVariant.ViewDatabase });
 		} catch (e) {
-			await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			if (!ffi.isCancelled(e)) {
+				await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			}
 		} finally {
 			setCreating(false);
+			setProgress(null);
 		}
 	}
 
@@ -60,8 +64,19 @@ function CreateDatabase() {
 					<button type="submit" disabled={creating}>
 						{creating ? <Icon icon={arrowSyncCircle24Filled} className="icon spinner" width="18" /> : "Create"}
 					</button>
+					{creating && (
+						<button type="button" onClick={() => void ffi.cancelOperation()}>
+							Cancel
+						</button>
+					)}
 				</form>
 			</div>
+			{creating && progress !== null && (
+				<div className="row">
+					<progress value={progress.fraction} />
+					<p>{ffi.describeDeriveProgress(progress)}</p>
+				</div>
+			)}
 		</div>
 	);
 }
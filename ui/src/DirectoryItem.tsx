@@ -12,7 +12,7 @@ export enum DirectoryItemVariant {
 }
 
 type DirectoryItemProps =
-	| { variant: DirectoryItemVariant.Directory; directory: Directory }
+	| { variant: DirectoryItemVariant.Directory; directory: Directory; onContextMenu?: (event: React.MouseEvent) => void }
 	| { variant: DirectoryItemVariant.All }
 	| { variant: DirectoryItemVariant.New; onCancel: () => void };
 
@@ -134,7 +134,14 @@ function DirectoryItem(props: DirectoryItemProps) {
 			: "";
 
 	return (
-		<div className={className} onDrop={onDrop} onDragOver={allowDrop} onClick={onClick} onDoubleClick={onDoubleClick}>
+		<div
+			className={className}
+			onDrop={onDrop}
+			onDragOver={allowDrop}
+			onClick={onClick}
+			onDoubleClick={onDoubleClick}
+			onContextMenu={props.variant === DirectoryItemVariant.Directory ? props.onContextMenu : undefined}
+		>
 			<Icon icon={folder24Filled} width="24" className="icon" />
 			{renaming ? (
 				<input type="text" defaultValue={name} onBlur={onBlur} onKeyUp={onKeyUp} autoFocus />
@@ -1,4 +1,4 @@
-import { useEffect, useRef, useState } from "react";
+import { useEffect, useMemo, useRef, useState } from "react";
 import "./EntryEditor.css";
 import { useRecoilValue, useSetRecoilState } from "recoil";
 import { appState, AppStateVariant } from "./App";
@@ -8,27 +8,48 @@ import dismiss24Filled from "@iconify/icons-fluent/dismiss-24-filled";
 import save24Filled from "@iconify/icons-fluent/save-24-filled";
 import eye24Filled from "@iconify/icons-fluent/eye-24-filled";
 import eyeOff24Filled from "@iconify/icons-fluent/eye-off-24-filled";
+import history24Filled from "@iconify/icons-fluent/history-24-filled";
 import { DirectoryItemVariant } from "./DirectoryItem";
 import { databaseState, Entry, refreshDatabase, ROOT_DIR_ID, selectedDirectoryState } from "./state";
 import * as ffi from "./ffi";
 
-function EntryEditor({ entry }: { entry: Entry | null }) {
+// Notes (see ffi.NOTE_ENTRY_TYPE) only have a title and their text.  `note` makes new entries notes.
+function EntryEditor({ entry, note }: { entry: Entry | null; note: boolean }) {
+	const isNote = entry === null ? note : entry.state.entry_type == ffi.NOTE_ENTRY_TYPE;
 	const [title, setTitle] = useState(entry?.state.title ?? "");
 	const [username, setUsername] = useState(entry?.state.username ?? "");
-	const [password, setPassword] = useState(entry?.state.password ?? null);
+	const [password, setPassword] = useState(entry?.state.password ?? (isNote ? "" : null));
 	const [showPassword, setShowPassword] = useState(false);
 	const [url, setURL] = useState(entry?.state.url ?? "");
 	const [notes, setNotes] = useState(entry?.state.notes ?? "");
+	const [markdown, setMarkdown] = useState(entry?.state.note_format == "markdown");
 	const setAppState = useSetRecoilState(appState);
 	const selectedDirectory = useRecoilValue(selectedDirectoryState);
 	const setDatabase = useSetRecoilState(databaseState);
 	const [menu, setMenu] = useState({ x: 0, y: 0, open: false });
+	const [showHistory, setShowHistory] = useState(false);
 	const generateBtnRef = useRef<HTMLButtonElement>(null);
 
 	function onBackClicked() {
 		setAppState({ variant: AppStateVariant.ViewDatabase });
 	}
 
+	// Restoring saves a new edit straight away, so like saving it goes back to the list
+	async function onRestore(field: string, time: number) {
+		if (entry === null) {
+			return;
+		}
+
+		try {
+			await ffi.revertEntryField(entry.id, field, time);
+		} catch (e) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(e));
+		}
+
+		setAppState({ variant: AppStateVariant.ViewDatabase });
+		await refreshDatabase(setDatabase);
+	}
+
 	async function onDiscardClicked() {
 		// At least on macOS confirm was returning a Promise<boolean> instead of a boolean, so we use Promise.resolve to handle both cases.
 		const result = Promise.resolve(confirm("Are you sure you want to discard your changes?") as unknown);
@@ -45,17 +66,22 @@ function EntryEditor({ entry }: { entry: Entry | null }) {
 		const parentId = selectedDirectory === DirectoryItemVariant.All ? ROOT_DIR_ID : selectedDirectory;
 
 		try {
-			await ffi.editEntry(
-				entry?.id ?? null,
-				{
-					title: title,
-					username: username,
-					password: password,
-					url: url,
-					notes: notes,
-				},
-				parentId
-			);
+			const data = isNote
+				? {
+						entry_type: ffi.NOTE_ENTRY_TYPE,
+						title: title,
+						notes: notes,
+						note_format: markdown ? "markdown" : "",
+				  }
+				: {
+						title: title,
+						username: username,
+						password: password,
+						url: url,
+						notes: notes,
+				  };
+
+			await ffi.editEntry(entry?.id ?? null, data, parentId);
 		} catch (e) {
 			// TODO: This is a fatal error.  We should use a different dialog that allows the user to try and save again, or quit the application.
 			await ffi.showErrorDialog(ffi.getErrorMessage(e));
@@ -100,7 +126,32 @@ function EntryEditor({ entry }: { entry: Entry | null }) {
 		username != (entry?.state.username ?? "") ||
 		password != (entry?.state.password ?? "") ||
 		url != (entry?.state.url ?? "") ||
-		notes != (entry?.state.notes ?? "");
+		notes != (entry?.state.notes ?? "") ||
+		markdown != (entry?.state.note_format == "markdown");
+
+	// Escape closes the generate menu if it's open, and otherwise the editor, asking first if there are changes
+	function onKeyDown(event: KeyboardEvent) {
+		if (event.key != "Escape") {
+			return;
+		}
+
+		event.preventDefault();
+
+		if (menu.open) {
+			onCloseGenerateContextMenu();
+		} else if (modified) {
+			void onDiscardClicked();
+		} else {
+			onBackClicked();
+		}
+	}
+
+	useEffect(() => {
+		window.addEventListener("keydown", onKeyDown);
+		return () => {
+			window.removeEventListener("keydown", onKeyDown);
+		};
+	});
 
 	return (
 		<div className="entry-editor container">
@@ -120,66 +171,163 @@ function EntryEditor({ entry }: { entry: Entry | null }) {
 						<Icon icon={save24Filled} className="icon" width="24" />
 					</button>
 				) : null}
+				{modified || entry === null ? null : (
+					<button
+						type="button"
+						title={showHistory ? "Hide History" : "Show History"}
+						className={showHistory ? "selected" : ""}
+						onClick={() => setShowHistory(!showHistory)}
+					>
+						<Icon icon={history24Filled} className="icon" width="24" />
+					</button>
+				)}
 			</div>
-			<div className="entry-editor-main">
-				<div className="field">
-					<div className="label">Title</div>
-					<input type="text" id="title" value={title} onChange={(e) => setTitle(e.currentTarget.value)} />
-				</div>
-				<div className="field">
-					<div className="label">Username</div>
-					<input
-						type="text"
-						id="username"
-						autoComplete="off"
-						spellCheck="false"
-						autoCorrect="off"
-						value={username}
-						onChange={(e) => setUsername(e.currentTarget.value)}
-					/>
-				</div>
-				<div className="field">
-					<div className="label">Password</div>
-					<div className="password-input">
-						<input
-							type={showPassword ? "text" : "password"}
-							id="password"
-							value={password ?? ""}
-							onChange={(e) => setPassword(e.currentTarget.value)}
-						/>
-						<button className="show-password" title="Show password" onClick={() => setShowPassword(!showPassword)}>
-							<Icon icon={showPassword ? eyeOff24Filled : eye24Filled} className="icon" width="24" />
-						</button>
-						<button type="button" title="Generate" onClick={onGenerateClicked} ref={generateBtnRef}>
-							Generate
-						</button>
+			{showHistory && entry !== null ? (
+				<HistoryPanel entryId={entry.id} onRestore={(field, time) => void onRestore(field, time)} />
+			) : (
+				<div className="entry-editor-main">
+					<div className="field">
+						<div className="label">Title</div>
+						<input type="text" id="title" value={title} onChange={(e) => setTitle(e.currentTarget.value)} />
+					</div>
+					{isNote ? null : (
+						<>
+							<div className="field">
+								<div className="label">Username</div>
+								<input
+									type="text"
+									id="username"
+									autoComplete="off"
+									spellCheck="false"
+									autoCorrect="off"
+									value={username}
+									onChange={(e) => setUsername(e.currentTarget.value)}
+								/>
+							</div>
+							<div className="field">
+								<div className="label">Password</div>
+								<div className="password-input">
+									<input
+										type={showPassword ? "text" : "password"}
+										id="password"
+										value={password ?? ""}
+										onChange={(e) => setPassword(e.currentTarget.value)}
+									/>
+									<button
+										className="show-password"
+										title="Show password"
+										onClick={() => setShowPassword(!showPassword)}
+									>
+										<Icon icon={showPassword ? eyeOff24Filled : eye24Filled} className="icon" width="24" />
+									</button>
+									<button type="button" title="Generate" onClick={onGenerateClicked} ref={generateBtnRef}>
+										Generate
+									</button>
+								</div>
+							</div>
+							<div className="field">
+								<div className="label">URL</div>
+								<input
+									type="url"
+									id="url"
+									autoComplete="off"
+									spellCheck="false"
+									autoCorrect="off"
+									value={url}
+									onChange={(e) => setURL(e.currentTarget.value)}
+								/>
+							</div>
+						</>
+					)}
+					{isNote ? (
+						<div className="note-format">
+							<input
+								type="checkbox"
+								id="markdown"
+								checked={markdown}
+								onChange={(e) => setMarkdown(e.currentTarget.checked)}
+							/>
+							<label htmlFor="markdown">Markdown</label>
+						</div>
+					) : null}
+					<div className="field">
+						<div className="label">{isNote ? "Text" : "Notes"}</div>
+						<textarea id="notes" value={notes} onChange={(e) => setNotes(e.currentTarget.value)} />
 					</div>
 				</div>
-				<div className="field">
-					<div className="label">URL</div>
-					<input
-						type="url"
-						id="url"
-						autoComplete="off"
-						spellCheck="false"
-						autoCorrect="off"
-						value={url}
-						onChange={(e) => setURL(e.currentTarget.value)}
-					/>
-				</div>
-				<div className="field">
-					<div className="label">Notes</div>
-					<textarea id="notes" value={notes} onChange={(e) => setNotes(e.currentTarget.value)} />
-				</div>
-			</div>
+			)}
 			<GenerateMenu state={menu} onClose={onCloseGenerateContextMenu} onGenerate={onGenerate} />
 		</div>
 	);
 }
 
-// TODO: Should this reflect the current state of the generate dialog? Or maybe some kind of setting? Or at least break this out to global constants.
+// Every revision of the entry, newest first, with the fields it changed.  Values are hidden until asked for, since they're
+// often old passwords.
+function HistoryPanel({ entryId, onRestore }: { entryId: string; onRestore: (field: string, time: number) => void }) {
+	const [history, setHistory] = useState<ffi.EntryHistory[] | null>(null);
+	const [shown, setShown] = useState<string[]>([]);
+
+	useEffect(() => {
+		let cancelled = false;
+
+		ffi
+			.getEntryHistory(entryId)
+			.then((history) => {
+				if (!cancelled) setHistory(history.slice().reverse());
+			})
+			.catch((e) => void ffi.showErrorDialog(ffi.getErrorMessage(e)));
+
+		return () => {
+			cancelled = true;
+		};
+	}, [entryId]);
+
+	if (history === null) {
+		return <div className="history-panel">Loading...</div>;
+	}
+
+	function toggleShown(key: string) {
+		setShown(shown.includes(key) ? shown.filter((k) => k !== key) : [...shown, key]);
+	}
+
+	return (
+		<div className="history-panel">
+			{history.map((revision, index) => (
+				<div className="revision" key={revision.time}>
+					<div className="revision-time">{new Date(revision.time / 1e6).toLocaleString()}</div>
+					{Object.entries(revision.data).map(([field, value]) => {
+						const key = `${revision.time}:${field}`;
+
+						return (
+							<div className="revision-field" key={field}>
+								<div className="label">{field}</div>
+								<div className="value">{shown.includes(key) ? value || "(empty)" : "••••••"}</div>
+								<button type="button" onClick={() => toggleShown(key)}>
+									{shown.includes(key) ? "Hide" : "View"}
+								</button>
+								{index === 0 ? null : (
+									<button type="button" onClick={() => onRestore(field, revision.time)}>
+										Restore
+									</button>
+								)}
+							</div>
+						);
+					})}
+				</div>
+			))}
+		</div>
+	);
+}
+
+// New entries get a password generated with the options last used on this device
 async function defaultGeneratePassword(setPassword: (password: string) => void) {
-	setPassword(await ffi.randomString(20, true, true, true, ""));
+	const settings = await ffi.getLocalSettings().catch(() => null);
+
+	try {
+		setPassword(await ffi.generatePassword(settings?.generator ?? ffi.DEFAULT_GENERATOR_OPTIONS));
+	} catch {
+		setPassword(await ffi.generatePassword(ffi.DEFAULT_GENERATOR_OPTIONS));
+	}
 }
 
 function GenerateMenu({
@@ -197,6 +345,9 @@ function GenerateMenu({
 	const [lowercase, setLowercase] = useState(true);
 	const [numbers, setNumbers] = useState(true);
 	const [others, setOthers] = useState("");
+	const [excludeAmbiguous, setExcludeAmbiguous] = useState(false);
+	const [requireEachClass, setRequireEachClass] = useState(false);
+	const [noRepeats, setNoRepeats] = useState(false);
 
 	function onMouseDownOutside(event: MouseEvent) {
 		if (menu.current !== null && event.target instanceof Element && !menu.current.contains(event.target)) {
@@ -206,8 +357,69 @@ function GenerateMenu({
 		}
 	}
 
+	// Starts with the options last used on this device
+	useEffect(() => {
+		void ffi.getLocalSettings().then((settings) => {
+			const generator = settings.generator;
+
+			if (generator !== undefined) {
+				setLength(generator.length);
+				setUppercase(generator.uppercase);
+				setLowercase(generator.lowercase);
+				setNumbers(generator.numbers);
+				setOthers(generator.others);
+				setExcludeAmbiguous(generator.exclude_ambiguous);
+				setRequireEachClass(generator.require_each_selected_class);
+				setNoRepeats(generator.no_repeated_characters);
+			}
+		});
+	}, []);
+
+	const [entropy, setEntropy] = useState<number | null>(null);
+	const options = useMemo(
+		() => ({
+			length,
+			uppercase,
+			lowercase,
+			numbers,
+			others,
+			exclude_ambiguous: excludeAmbiguous,
+			require_each_selected_class: requireEachClass,
+			no_repeated_characters: noRepeats,
+		}),
+		[length, uppercase, lowercase, numbers, others, excludeAmbiguous, requireEachClass, noRepeats]
+	);
+
+	useEffect(() => {
+		let cancelled = false;
+
+		ffi
+			.estimatePasswordEntropy(options)
+			.then((bits) => {
+				if (!cancelled) setEntropy(bits);
+			})
+			.catch(() => {
+				if (!cancelled) setEntropy(null);
+			});
+
+		return () => {
+			cancelled = true;
+		};
+	}, [options]);
+
 	async function onGenerateClicked() {
-		onGenerate(await ffi.randomString(length, uppercase, lowercase, numbers, others));
+		try {
+			onGenerate(await ffi.generatePassword(options));
+		} catch (e) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			return;
+		}
+
+		// Remembering the options isn't worth bothering the user about if it fails
+		await ffi
+			.getLocalSettings()
+			.then((settings) => ffi.setLocalSettings({ ...settings, generator: options }))
+			.catch(() => undefined);
 	}
 
 	useEffect(() => {
@@ -256,6 +468,40 @@ function GenerateMenu({
 			</div>
 			<label htmlFor="others">Other characters</label>
 			<input type="text" id="others" value={others} onChange={(e) => setOthers(e.currentTarget.value)} />
+			<div>
+				<input
+					type="checkbox"
+					id="exclude-ambiguous"
+					checked={excludeAmbiguous}
+					onChange={(e) => setExcludeAmbiguous(e.currentTarget.checked)}
+				/>
+				<label htmlFor="exclude-ambiguous">Avoid look-alikes (0, O, 1, l, I)</label>
+			</div>
+			<div>
+				<input
+					type="checkbox"
+					id="require-each-class"
+					checked={requireEachClass}
+					onChange={(e) => setRequireEachClass(e.currentTarget.checked)}
+				/>
+				<label htmlFor="require-each-class">At least one of each kind</label>
+			</div>
+			<div>
+				<input
+					type="checkbox"
+					id="no-repeats"
+					checked={noRepeats}
+					onChange={(e) => setNoRepeats(e.currentTarget.checked)}
+				/>
+				<label htmlFor="no-repeats">No repeated characters</label>
+			</div>
+			{entropy === null ? (
+				<div className="entropy weak">No password fits these settings</div>
+			) : (
+				<div className={entropy < ffi.WEAK_ENTROPY_BITS ? "entropy weak" : "entropy"}>
+					≈ {Math.floor(entropy)} bits{entropy < ffi.WEAK_ENTROPY_BITS && " (weak)"}
+				</div>
+			)}
 			<button type="button" onClick={onGenerateClicked}>
 				Generate
 			</button>
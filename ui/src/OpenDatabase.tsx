@@ -8,10 +8,13 @@ import * as ffi from "./ffi";
 
 function OpenDatabase() {
 	const [password, setPassword] = useState("");
+	const [unlocking, setUnlocking] = useState(false);
 	const setAppState = useSetRecoilState(appState);
 	const setDatabase = useSetRecoilState(databaseState);
 
 	async function unlockClicked() {
+		setUnlocking(true);
+
 		try {
 			await ffi.unlockDatabase(password);
 
@@ -19,7 +22,11 @@ function OpenDatabase() {
 
 			await refreshDatabase(setDatabase);
 		} catch (e) {
-			await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			if (!ffi.isCancelled(e)) {
+				await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			}
+		} finally {
+			setUnlocking(false);
 		}
 	}
 
@@ -43,7 +50,13 @@ function OpenDatabase() {
 						placeholder="Enter your password..."
 						autoFocus
 					/>
-					<button type="submit">Unlock</button>
+					{unlocking ? (
+						<button type="button" onClick={() => void ffi.cancelOperation()}>
+							Cancel
+						</button>
+					) : (
+						<button type="submit">Unlock</button>
+					)}
 				</form>
 			</div>
 		</div>
@@ -15,23 +15,43 @@ function Settings() {
 	const setAppState = useSetRecoilState(appState);
 	const setDatabase = useSetRecoilState(databaseState);
 	const [syncUrl, setSyncUrlState] = useState<string | null>(null);
+	const [syncProxy, setSyncProxyState] = useState<string | null>(null);
 	const [username, setUsername] = useState<string | null>(null);
 	const [password, setPassword] = useState("");
 	const [repeatPassword, setRepeatPassword] = useState("");
 	const [syncKeys, setSyncKeys] = useState<string | null>(null);
+	const [readonlySyncKeys, setReadonlySyncKeys] = useState<string | null>(null);
 	const [showPassword, setShowPassword] = useState(false);
 	const [showRepeatPassword, setShowRepeatPassword] = useState(false);
 	const [showSyncKeys, setShowSyncKeys] = useState(false);
+	const [showReadonlySyncKeys, setShowReadonlySyncKeys] = useState(false);
 	const [syncing, setSyncing] = useState(0);
+	const [syncSummary, setSyncSummary] = useState<string | null>(null);
+	const [testingConnection, setTestingConnection] = useState(false);
+	const [previewing, setPreviewing] = useState(false);
+	const [accountBusy, setAccountBusy] = useState(false);
+	const [accountSummary, setAccountSummary] = useState<string | null>(null);
 	const [changingPassword, setChangingPassword] = useState(0);
+	const [syncProgress, setSyncProgress] = useState<ffi.SyncProgress | null>(null);
+	const [deriveProgress, setDeriveProgress] = useState<ffi.DeriveProgress | null>(null);
+	const [trash, setTrash] = useState<ffi.TrashContents | null>(null);
 
-	async function onBackClicked() {
+	// Saves the sync URL and proxy being edited
+	async function applySyncSettings() {
 		if (syncUrl !== null) {
-			try {
-				await ffi.setSyncUrl(syncUrl);
-			} catch (e) {
-				await ffi.showErrorDialog(ffi.getErrorMessage(e));
-			}
+			await ffi.setSyncUrl(syncUrl);
+		}
+
+		if (syncProxy !== null) {
+			await ffi.setSyncProxy(syncProxy);
+		}
+	}
+
+	async function onBackClicked() {
+		try {
+			await applySyncSettings();
+		} catch (e) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(e));
 		}
 
 		setAppState({ variant: AppStateVariant.ViewDatabase });
@@ -39,24 +59,166 @@ function Settings() {
 
 	async function onSyncClicked() {
 		setSyncing(1);
+		setSyncSummary(null);
 
 		try {
-			if (syncUrl !== null) {
-				await ffi.setSyncUrl(syncUrl);
+			await applySyncSettings();
+
+			let result;
+			try {
+				result = await ffi.syncDatabase(setSyncProgress);
+			} catch (e) {
+				if (!ffi.isAccountChanged(e)) {
+					throw e;
+				}
+
+				const merge = Promise.resolve(
+					confirm(
+						"This database was last synced with a different account, and the account for your current username already has entries. Merge them into this database? This can't be undone."
+					) as unknown
+				);
+				if (!(await merge)) {
+					setSyncSummary("Sync cancelled");
+					return;
+				}
+
+				await ffi.confirmAccountChange();
+				result = await ffi.syncDatabase(setSyncProgress);
 			}
+			const traffic = `${formatBytes(result.bytes_uploaded)} sent, ${formatBytes(result.bytes_downloaded)} received`;
+			const onServer = result.local_only_on_server.length;
+			setSyncSummary(
+				onServer > 0
+					? `${result.summary} (${traffic}; ${onServer} kept off the server are still on it)`
+					: `${result.summary} (${traffic})`
+			);
 
-			await ffi.syncDatabase();
+			for (const conflict of result.conflicts) {
+				if (conflict.type == "directory_rename") {
+					const keep = Promise.resolve(
+						confirm(
+							`A folder was renamed to "${conflict.kept}" on another device at about the same time it was renamed to "${conflict.discarded}". Keep "${conflict.kept}"? (Cancel to use "${conflict.discarded}" instead.)`
+						) as unknown
+					);
+					if (!(await keep)) {
+						await ffi.renameDirectory(conflict.directory, conflict.discarded);
+						await ffi.syncDatabase();
+					}
+				}
+			}
 
 			setSyncing(2);
 			await refreshDatabase(setDatabase);
 			await sleep(1000);
 		} catch (e) {
-			await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			if (ffi.isCancelled(e)) {
+				setSyncSummary("Sync cancelled");
+			} else {
+				await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			}
 		} finally {
 			setSyncing(0);
+			setSyncProgress(null);
+		}
+	}
+
+	async function onPreviewClicked() {
+		setPreviewing(true);
+		setSyncSummary(null);
+
+		try {
+			await applySyncSettings();
+
+			const result = await ffi.previewSync();
+			setSyncSummary(`Preview: ${result.summary}`);
+		} catch (e) {
+			if (ffi.isAccountChanged(e)) {
+				setSyncSummary("Preview: entries from a different account would be merged in");
+			} else {
+				await ffi.showErrorDialog(ffi.getErrorMessage(e));
+			}
+		} finally {
+			setPreviewing(false);
+		}
+	}
+
+	async function onTestConnectionClicked() {
+		if (syncUrl === null) {
+			return;
+		}
+
+		setTestingConnection(true);
+		setSyncSummary(null);
+
+		try {
+			if (syncProxy !== null) {
+				await ffi.setSyncProxy(syncProxy);
+			}
+
+			const result = await ffi.testSyncConnection(syncUrl);
+			setSyncSummary(
+				result.authenticated
+					? `Connected (${result.latency_ms} ms)`
+					: "The server rejected your username or password"
+			);
+		} catch (e) {
+			setSyncSummary(`Could not connect: ${ffi.getErrorMessage(e)}`);
+		} finally {
+			setTestingConnection(false);
+		}
+	}
+
+	// Runs an account operation against the sync URL being edited, showing its outcome under the Account section
+	async function runAccountOperation(operation: () => Promise<string>) {
+		setAccountBusy(true);
+		setAccountSummary(null);
+
+		try {
+			await applySyncSettings();
+
+			setAccountSummary(await operation());
+		} catch (e) {
+			setAccountSummary(ffi.getErrorMessage(e));
+		} finally {
+			setAccountBusy(false);
 		}
 	}
 
+	async function onRegisterAccountClicked() {
+		await runAccountOperation(async () => {
+			await ffi.registerSyncAccount();
+			return "Account created; sync to upload this database";
+		});
+	}
+
+	async function onAccountUsageClicked() {
+		await runAccountOperation(async () => {
+			const info = await ffi.getSyncAccountInfo();
+			const objects = info.max_objects === null ? `${info.object_count}` : `${info.object_count} of ${info.max_objects}`;
+			const storage =
+				info.max_storage === null
+					? formatBytes(info.storage_used)
+					: `${formatBytes(info.storage_used)} of ${formatBytes(info.max_storage)}`;
+			return `${objects} objects, ${storage}`;
+		});
+	}
+
+	async function onDeleteAccountClicked() {
+		const result = Promise.resolve(
+			confirm(
+				"Delete your account on the sync server, and everything stored there? This database is kept, but other devices won't be able to sync until the account is created again."
+			) as unknown
+		);
+		if (!(await result)) {
+			return;
+		}
+
+		await runAccountOperation(async () => {
+			await ffi.deleteSyncAccount();
+			return "Account deleted";
+		});
+	}
+
 	async function onLoginChangeClicked() {
 		if (password != repeatPassword) {
 			alert("Passwords do not match");
@@ -75,7 +237,7 @@ function Settings() {
 		setChangingPassword(1);
 
 		try {
-			await ffi.changePassword(username, password);
+			await ffi.changePassword(username, password, setDeriveProgress);
 
 			setPassword("");
 			setRepeatPassword("");
@@ -85,6 +247,7 @@ function Settings() {
 			await ffi.showErrorDialog(ffi.getErrorMessage(e));
 		} finally {
 			setChangingPassword(0);
+			setDeriveProgress(null);
 		}
 	}
 
@@ -96,10 +259,33 @@ function Settings() {
 		void ffi.getSyncKeys().then((x) => setSyncKeys(x));
 	}
 
+	if (readonlySyncKeys === null) {
+		void ffi.getReadonlySyncKeys().then((x) => setReadonlySyncKeys(x));
+	}
+
 	if (syncUrl === null) {
 		void ffi.getSyncUrl().then((x) => setSyncUrlState(x));
 	}
 
+	if (syncProxy === null) {
+		void ffi.getSyncProxy().then((x) => setSyncProxyState(x ?? ""));
+	}
+
+	if (trash === null) {
+		void ffi.listTrash().then((x) => setTrash(x));
+	}
+
+	async function onRestoreClicked(id: string) {
+		try {
+			await ffi.restore(id);
+		} catch (e) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(e));
+		}
+
+		setTrash(await ffi.listTrash());
+		await refreshDatabase(setDatabase);
+	}
+
 	return (
 		<div className="settings container">
 			<div className="settings-header">
@@ -123,6 +309,14 @@ function Settings() {
 						value={syncUrl ?? ""}
 						onChange={(e) => setSyncUrlState(e.currentTarget.value)}
 					/>
+					<label htmlFor="sync_proxy">SOCKS5 Proxy (optional)</label>
+					<input
+						type="text"
+						id="sync_proxy"
+						placeholder="socks5://127.0.0.1:9050"
+						value={syncProxy ?? ""}
+						onChange={(e) => setSyncProxyState(e.currentTarget.value)}
+					/>
 					<button type="button" onClick={onSyncClicked} className="settings-btn" disabled={syncing > 0}>
 						{syncing == 1 ? (
 							<Icon icon={arrowSyncCircle24Filled} className="icon spinner" width="18" />
@@ -132,6 +326,39 @@ function Settings() {
 							"Sync"
 						)}
 					</button>
+					<button
+						type="button"
+						onClick={onTestConnectionClicked}
+						className="settings-btn"
+						disabled={syncing > 0 || testingConnection}
+					>
+						{testingConnection ? <Icon icon={arrowSyncCircle24Filled} className="icon spinner" width="18" /> : "Test"}
+					</button>
+					<button type="button" onClick={onPreviewClicked} className="settings-btn" disabled={syncing > 0 || previewing}>
+						{previewing ? <Icon icon={arrowSyncCircle24Filled} className="icon spinner" width="18" /> : "Preview"}
+					</button>
+					{syncing == 1 && (
+						<button type="button" onClick={() => void ffi.cancelOperation()} className="settings-btn">
+							Cancel
+						</button>
+					)}
+					{syncing == 1 && syncProgress !== null && (
+						<p className="settings-sync-summary">{ffi.describeSyncProgress(syncProgress)}</p>
+					)}
+					{syncSummary !== null && <p className="settings-sync-summary">{syncSummary}</p>}
+				</div>
+				<div className="settings-section">
+					<h2>Account</h2>
+					<button type="button" onClick={onRegisterAccountClicked} className="settings-btn" disabled={accountBusy}>
+						Create
+					</button>
+					<button type="button" onClick={onAccountUsageClicked} className="settings-btn" disabled={accountBusy}>
+						Usage
+					</button>
+					<button type="button" onClick={onDeleteAccountClicked} className="settings-btn" disabled={accountBusy}>
+						Delete
+					</button>
+					{accountSummary !== null && <p className="settings-sync-summary">{accountSummary}</p>}
 				</div>
 				<div className="settings-section">
 					<h2>Username and Password</h2>
@@ -179,6 +406,27 @@ function Settings() {
 							"Change"
 						)}
 					</button>
+					{changingPassword == 1 && deriveProgress !== null && (
+						<div>
+							<progress value={deriveProgress.fraction} />
+							<p className="settings-sync-summary">{ffi.describeDeriveProgress(deriveProgress)}</p>
+						</div>
+					)}
+				</div>
+				<div className="settings-section">
+					<h2>Trash</h2>
+					{trash === null || trash.objects.length == 0 ? (
+						<p className="settings-sync-summary">The trash is empty</p>
+					) : (
+						trash.objects.map((object) => (
+							<div className="settings-trash-item" key={object.id}>
+								<span>{describeTrashedObject(trash, object.id)}</span>
+								<button type="button" onClick={() => void onRestoreClicked(object.id)}>
+									Restore
+								</button>
+							</div>
+						))
+					)}
 				</div>
 				<div className="settings-section">
 					<h2>Sync Keys</h2>
@@ -188,12 +436,54 @@ function Settings() {
 							<Icon icon={showSyncKeys ? eyeOff24Filled : eye24Filled} className="icon" width="24" />
 						</button>
 					</div>
+					<label htmlFor="readonly_sync_keys">Read-only (for backups)</label>
+					<div>
+						<input
+							type={showReadonlySyncKeys ? "text" : "password"}
+							id="readonly_sync_keys"
+							value={readonlySyncKeys ?? ""}
+							readOnly
+						/>
+						<button
+							className="show-password"
+							title="Show read-only sync keys"
+							onClick={() => setShowReadonlySyncKeys(!showReadonlySyncKeys)}
+						>
+							<Icon icon={showReadonlySyncKeys ? eyeOff24Filled : eye24Filled} className="icon" width="24" />
+						</button>
+					</div>
 				</div>
 			</div>
 		</div>
 	);
 }
 
+// The title of a trashed entry, or the name of a trashed folder
+function describeTrashedObject(trash: ffi.TrashContents, id: string): string {
+	const entry = trash.entries.find((entry) => entry.id === id);
+	if (entry !== undefined) {
+		const title = entry.history.reduce<string | null>((title, item) => item.data.title ?? title, null);
+		return title || "Untitled entry";
+	}
+
+	const directory = trash.directories.find((directory) => directory.id === id);
+	const name = directory?.history.reduce<string | null>(
+		(name, item) => ("Rename" in item.action ? item.action.Rename : name),
+		null
+	);
+	return `Folder: ${name ?? "Unnamed"}`;
+}
+
+function formatBytes(bytes: number): string {
+	if (bytes < 1024) {
+		return `${bytes} B`;
+	} else if (bytes < 1024 * 1024) {
+		return `${(bytes / 1024).toFixed(1)} KiB`;
+	} else {
+		return `${(bytes / 1024 / 1024).toFixed(1)} MiB`;
+	}
+}
+
 function sleep(ms: number): Promise<void> {
 	return new Promise((resolve) => setTimeout(resolve, ms));
 }
@@ -1,13 +1,25 @@
 import React, { useEffect, useRef, useState } from "react";
 import "./ViewDatabase.css";
-import { useRecoilState, useRecoilValue, useSetRecoilState } from "recoil";
+import { useRecoilState, useSetRecoilState } from "recoil";
 import { Icon } from "@iconify/react";
 import settings24Filled from "@iconify/icons-fluent/settings-24-filled";
 import add24Filled from "@iconify/icons-fluent/add-24-filled";
 import folderAdd24Filled from "@iconify/icons-fluent/folder-add-24-filled";
+import noteAdd24Filled from "@iconify/icons-fluent/note-add-24-filled";
+import key24Filled from "@iconify/icons-fluent/key-24-filled";
+import person24Filled from "@iconify/icons-fluent/person-24-filled";
 import { appState, AppStateVariant } from "./App";
 import DirectoryItem, { DirectoryItemVariant } from "./DirectoryItem";
-import { databaseState, Directory, Entry, getRootDirectory, ROOT_DIR_ID, selectedDirectoryState } from "./state";
+import {
+	databaseState,
+	Directory,
+	Entry,
+	getRootDirectory,
+	refreshDatabase,
+	ROOT_DIR_ID,
+	selectedDirectoryState,
+} from "./state";
+import * as ffi from "./ffi";
 
 function sortDirectories(directories: Directory[]) {
 	const directory_name = (directory: Directory) => directory.name ?? "Unnamed";
@@ -75,14 +87,20 @@ function sortEntries(entries: Entry[]) {
 	return result;
 }
 
+// The columns shown in the entry list, which are also what "Copy Selection" copies
+const SELECTION_FIELDS = ["title", "username", "url"];
+
 function ViewDatabase() {
 	const [search, setSearch] = useState("");
 	const [contextMenuEntry, setContextMenuEntry] = useState("");
+	const [selectedEntries, setSelectedEntries] = useState<string[]>([]);
 	const [menu, setMenu] = useState({ x: 0, y: 0, open: false });
-	const database = useRecoilValue(databaseState);
+	const [directoryMenu, setDirectoryMenu] = useState({ x: 0, y: 0, open: false, id: "" });
+	const [database, setDatabase] = useRecoilState(databaseState);
 	const [selectedDirectory, setSelectedDirectory] = useRecoilState(selectedDirectoryState);
 	const setAppState = useSetRecoilState(appState);
 	const [newDirectory, setNewDirectory] = useState(false);
+	const searchRef = useRef<HTMLInputElement>(null);
 
 	function handleContextMenu(event: React.MouseEvent, entry_id: string) {
 		event.preventDefault();
@@ -93,46 +111,174 @@ function ViewDatabase() {
 
 		setMenu({ x: x, y: y, open: true });
 		setContextMenuEntry(entry_id);
+
+		// Right clicking outside the selection acts on just that entry
+		if (!selectedEntries.includes(entry_id)) {
+			setSelectedEntries([entry_id]);
+		}
+	}
+
+	// Ctrl/Cmd+click adds or removes an entry from the selection
+	function onEntryClick(event: React.MouseEvent, entry_id: string) {
+		if (event.ctrlKey || event.metaKey) {
+			setSelectedEntries(
+				selectedEntries.includes(entry_id)
+					? selectedEntries.filter((id) => id !== entry_id)
+					: [...selectedEntries, entry_id]
+			);
+		} else {
+			setSelectedEntries([entry_id]);
+		}
 	}
 
 	function onCloseContextMenu() {
 		setMenu({ x: 0, y: 0, open: false });
 	}
 
-	function onCopyUsername() {
-		const entry = database.entries.find((entry) => entry.id === contextMenuEntry);
+	// Passwords are copied by the backend, which also clears them from the clipboard again
+	function copyField(entry_id: string, field: string) {
+		const entry = database.entries.find((entry) => entry.id === entry_id);
 
 		if (entry) {
-			void navigator.clipboard.writeText(entry.state.username ?? "");
+			if (field == "password") {
+				ffi.copySecret(entry.id, field).catch((e) => void ffi.showErrorDialog(ffi.getErrorMessage(e)));
+			} else {
+				void navigator.clipboard.writeText(entry.state[field] ?? "");
+			}
+			void ffi.recordEntryAccess(entry.id);
 		}
+	}
 
+	function onCopyField(field: string) {
+		copyField(contextMenuEntry, field);
 		onCloseContextMenu();
 	}
 
-	function onCopyPassword() {
-		const entry = database.entries.find((entry) => entry.id === contextMenuEntry);
+	// Deleted entries go to the trash, where they can be restored from Settings
+	async function onDeleteSelection() {
+		onCloseContextMenu();
 
-		if (entry) {
-			void navigator.clipboard.writeText(entry.state.password ?? "");
+		const count = selectedEntries.length;
+		const result = Promise.resolve(
+			confirm(count == 1 ? "Move this entry to the trash?" : `Move these ${count} entries to the trash?`) as unknown
+		);
+		if (!(await result)) {
+			return;
 		}
 
-		onCloseContextMenu();
+		try {
+			for (const id of selectedEntries) {
+				await ffi.deleteEntry(id);
+			}
+		} catch (error) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(error));
+		}
+
+		setSelectedEntries([]);
+		await refreshDatabase(setDatabase);
 	}
 
-	function onCopyUrl() {
-		const entry = database.entries.find((entry) => entry.id === contextMenuEntry);
+	function onDirectoryContextMenu(event: React.MouseEvent, directory_id: string) {
+		event.preventDefault();
+		event.stopPropagation();
+
+		setDirectoryMenu({ x: event.pageX, y: event.pageY, open: true, id: directory_id });
+	}
 
-		if (entry) {
-			void navigator.clipboard.writeText(entry.state.url ?? "");
+	async function onDeleteDirectory() {
+		const directory = database.directories.find((directory) => directory.id === directoryMenu.id);
+		setDirectoryMenu({ x: 0, y: 0, open: false, id: "" });
+
+		if (directory === undefined) {
+			return;
+		}
+
+		const result = Promise.resolve(
+			confirm(`Move the folder "${directory.name ?? "Unnamed"}" and everything in it to the trash?`) as unknown
+		);
+		if (!(await result)) {
+			return;
+		}
+
+		try {
+			await ffi.deleteDirectory(directory.id);
+		} catch (error) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(error));
 		}
 
+		if (selectedDirectory === directory.id) {
+			setSelectedDirectory(ROOT_DIR_ID);
+		}
+
+		await refreshDatabase(setDatabase);
+	}
+
+	async function onToggleLocalOnly() {
+		const directory = database.directories.find((directory) => directory.id === directoryMenu.id);
+		setDirectoryMenu({ x: 0, y: 0, open: false, id: "" });
+
+		if (directory === undefined) {
+			return;
+		}
+
+		try {
+			await ffi.setDirectoryLocalOnly(directory.id, !directory.localOnly);
+		} catch (error) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(error));
+		}
+
+		await refreshDatabase(setDatabase);
+	}
+
+	async function onCopySelection(format: ffi.SelectionFormat) {
 		onCloseContextMenu();
+
+		try {
+			const text = await ffi.exportSelection(selectedEntries, SELECTION_FIELDS, format);
+			await navigator.clipboard.writeText(text);
+		} catch (error) {
+			await ffi.showErrorDialog(ffi.getErrorMessage(error));
+		}
+	}
+
+	// Ctrl+F searches, Ctrl+N adds an entry, and Ctrl+C and Ctrl+Shift+C copy the selected entry's password and username.
+	// Copying is left alone while typing, so copying text still works.
+	function onKeyDown(event: KeyboardEvent) {
+		if (!(event.ctrlKey || event.metaKey)) {
+			return;
+		}
+
+		const typing = event.target instanceof HTMLInputElement || event.target instanceof HTMLTextAreaElement;
+		const key = event.key.toLowerCase();
+
+		if (key == "f") {
+			event.preventDefault();
+			searchRef.current?.focus();
+			searchRef.current?.select();
+		} else if (key == "n") {
+			event.preventDefault();
+			onAddEntryClicked();
+		} else if (key == "c" && !typing && selectedEntries.length == 1) {
+			event.preventDefault();
+			copyField(selectedEntries[0], event.shiftKey ? "username" : "password");
+		}
 	}
 
+	useEffect(() => {
+		window.addEventListener("keydown", onKeyDown);
+		return () => {
+			window.removeEventListener("keydown", onKeyDown);
+		};
+	});
+
 	function onAddEntryClicked() {
 		setAppState({ variant: AppStateVariant.EditEntry, entry: null });
 	}
 
+	function onAddNoteClicked() {
+		setAppState({ variant: AppStateVariant.EditEntry, entry: null, note: true });
+	}
+
 	function onAddDirectoryClicked() {
 		setNewDirectory(true);
 	}
@@ -154,7 +300,12 @@ function ViewDatabase() {
 	}
 
 	const dirs = sortDirectories(database.directories).map((dir) => (
-		<DirectoryItem key={dir.id} variant={DirectoryItemVariant.Directory} directory={dir} />
+		<DirectoryItem
+			key={dir.id}
+			variant={DirectoryItemVariant.Directory}
+			directory={dir}
+			onContextMenu={dir.id === ROOT_DIR_ID ? undefined : (e: React.MouseEvent) => onDirectoryContextMenu(e, dir.id)}
+		/>
 	));
 
 	// The "All" category
@@ -170,7 +321,14 @@ function ViewDatabase() {
 	);
 
 	const ents = entries_filtered.map((ent) => (
-		<EntryItem key={ent.id} entry={ent} onContextMenu={(e: React.MouseEvent) => handleContextMenu(e, ent.id)} />
+		<EntryItem
+			key={ent.id}
+			entry={ent}
+			selected={selectedEntries.includes(ent.id)}
+			onClick={(e: React.MouseEvent) => onEntryClick(e, ent.id)}
+			onContextMenu={(e: React.MouseEvent) => handleContextMenu(e, ent.id)}
+			onCopy={(field: string) => copyField(ent.id, field)}
+		/>
 	));
 
 	return (
@@ -180,6 +338,7 @@ function ViewDatabase() {
 					type="search"
 					placeholder="Search..."
 					onChange={onSearchChange}
+					ref={searchRef}
 					autoFocus
 					autoComplete="off"
 					spellCheck="false"
@@ -188,6 +347,9 @@ function ViewDatabase() {
 				<button type="button" title="Add Entry" onClick={onAddEntryClicked}>
 					<Icon icon={add24Filled} className="icon" width="24" />
 				</button>
+				<button type="button" title="Add Note" onClick={onAddNoteClicked}>
+					<Icon icon={noteAdd24Filled} className="icon" width="24" />
+				</button>
 				<button type="button" title="Add Directory" onClick={onAddDirectoryClicked}>
 					<Icon icon={folderAdd24Filled} className="icon" width="24" />
 				</button>
@@ -196,7 +358,16 @@ function ViewDatabase() {
 				</button>
 			</div>
 			<div className="view-database-main">
-				<div className="directories">{dirs}</div>
+				<div className="directories">
+					{dirs}
+					<DirectoryContextMenu
+						state={directoryMenu}
+						onClose={() => setDirectoryMenu({ x: 0, y: 0, open: false, id: "" })}
+						localOnly={database.directories.find((directory) => directory.id === directoryMenu.id)?.localOnly ?? false}
+						onDelete={() => void onDeleteDirectory()}
+						onToggleLocalOnly={() => void onToggleLocalOnly()}
+					/>
+				</div>
 				<div className="entries">
 					<table>
 						<thead>
@@ -210,6 +381,7 @@ function ViewDatabase() {
 								<th>
 									<div>URL</div>
 								</th>
+								<th></th>
 							</tr>
 						</thead>
 						<tbody>{ents}</tbody>
@@ -217,9 +389,11 @@ function ViewDatabase() {
 					<ContextMenu
 						state={menu}
 						onClose={() => onCloseContextMenu()}
-						onCopyUsername={onCopyUsername}
-						onCopyPassword={onCopyPassword}
-						onCopyUrl={onCopyUrl}
+						onCopyUsername={() => onCopyField("username")}
+						onCopyPassword={() => onCopyField("password")}
+						onCopyUrl={() => onCopyField("url")}
+						onCopySelection={(format) => void onCopySelection(format)}
+						onDelete={() => void onDeleteSelection()}
 					/>
 				</div>
 			</div>
@@ -227,7 +401,19 @@ function ViewDatabase() {
 	);
 }
 
-function EntryItem({ entry, onContextMenu }: { entry: Entry; onContextMenu: (event: React.MouseEvent) => void }) {
+function EntryItem({
+	entry,
+	selected,
+	onClick,
+	onContextMenu,
+	onCopy,
+}: {
+	entry: Entry;
+	selected: boolean;
+	onClick: (event: React.MouseEvent) => void;
+	onContextMenu: (event: React.MouseEvent) => void;
+	onCopy: (field: string) => void;
+}) {
 	const setAppState = useSetRecoilState(appState);
 	const title = entry.state.title || "-";
 	const username = entry.state.username || "-";
@@ -241,12 +427,14 @@ function EntryItem({ entry, onContextMenu }: { entry: Entry; onContextMenu: (eve
 	function onDoubleClick(event: React.MouseEvent) {
 		event.preventDefault();
 
+		void ffi.recordEntryAccess(entry.id);
 		setAppState({ variant: AppStateVariant.EditEntry, entry: entry });
 	}
 
 	return (
 		<tr
-			className="entry-item"
+			className={"entry-item" + (selected ? " selected" : "")}
+			onClick={onClick}
 			onContextMenu={onContextMenu}
 			draggable={true}
 			onDragStart={(e) => onDragStart(e)}
@@ -261,6 +449,18 @@ function EntryItem({ entry, onContextMenu }: { entry: Entry; onContextMenu: (eve
 			<td>
 				<div className="entry-item-url">{url}</div>
 			</td>
+			<td>
+				{entry.state.entry_type == ffi.NOTE_ENTRY_TYPE ? null : (
+					<div className="entry-item-actions">
+						<button type="button" title="Copy Username" onClick={() => onCopy("username")}>
+							<Icon icon={person24Filled} className="icon" width="16" />
+						</button>
+						<button type="button" title="Copy Password" onClick={() => onCopy("password")}>
+							<Icon icon={key24Filled} className="icon" width="16" />
+						</button>
+					</div>
+				)}
+			</td>
 		</tr>
 	);
 }
@@ -271,12 +471,16 @@ function ContextMenu({
 	onCopyUsername,
 	onCopyPassword,
 	onCopyUrl,
+	onCopySelection,
+	onDelete,
 }: {
 	state: { x: number; y: number; open: boolean };
 	onClose: () => void;
 	onCopyUsername: () => void;
 	onCopyPassword: () => void;
 	onCopyUrl: () => void;
+	onCopySelection: (format: ffi.SelectionFormat) => void;
+	onDelete: () => void;
 }) {
 	const menu = useRef<HTMLElement>(null);
 
@@ -308,6 +512,62 @@ function ContextMenu({
 			<div className="context-menu-item" role="menuitem" tabIndex={-1} onClick={onCopyUrl}>
 				Copy URL
 			</div>
+			<div className="context-menu-item" role="menuitem" tabIndex={-1} onClick={() => onCopySelection("markdown")}>
+				Copy Selection as Markdown Table
+			</div>
+			<div className="context-menu-item" role="menuitem" tabIndex={-1} onClick={() => onCopySelection("csv")}>
+				Copy Selection as CSV
+			</div>
+			<div className="context-menu-item" role="menuitem" tabIndex={-1} onClick={() => onCopySelection("json")}>
+				Copy Selection as JSON
+			</div>
+			<div className="context-menu-item" role="menuitem" tabIndex={-1} onClick={onDelete}>
+				Delete
+			</div>
+		</nav>
+	);
+}
+
+function DirectoryContextMenu({
+	state,
+	localOnly,
+	onClose,
+	onDelete,
+	onToggleLocalOnly,
+}: {
+	state: { x: number; y: number; open: boolean };
+	localOnly: boolean;
+	onClose: () => void;
+	onDelete: () => void;
+	onToggleLocalOnly: () => void;
+}) {
+	const menu = useRef<HTMLElement>(null);
+
+	function onMouseDownOutside(event: MouseEvent) {
+		if (menu.current !== null && event.target instanceof Element && !menu.current.contains(event.target)) {
+			onClose();
+		}
+	}
+
+	useEffect(() => {
+		window.addEventListener("click", onMouseDownOutside);
+		return () => {
+			window.removeEventListener("click", onMouseDownOutside);
+		};
+	});
+
+	if (!state.open) {
+		return null;
+	}
+
+	return (
+		<nav role="menu" tabIndex={-1} className="context-menu" style={{ left: state.x, top: state.y }} ref={menu}>
+			<div className="context-menu-item" role="menuitem" tabIndex={-1} onClick={onDelete}>
+				Delete Folder
+			</div>
+			<div className="context-menu-item" role="menuitem" tabIndex={-1} onClick={onToggleLocalOnly}>
+				{localOnly ? "Sync Folder" : "Keep Folder Off Server"}
+			</div>
 		</nav>
 	);
 }
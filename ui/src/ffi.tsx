@@ -1,8 +1,9 @@
 import { invoke } from "@tauri-apps/api/tauri";
+import { listen } from "@tauri-apps/api/event";
 
 export interface DirectoryHistory {
 	time: number;
-	action: { Rename: string } | { Add: string } | { Remove: string };
+	action: { Rename: string } | { Add: string } | { Remove: string } | { SetLocalOnly: boolean };
 }
 
 export interface Directory {
@@ -21,6 +22,31 @@ export interface Entry {
 	time_created: number;
 }
 
+export interface SearchMatch {
+	target: { field: string } | { tag: string };
+	positions: number[];
+}
+
+export interface SearchHit {
+	id: string;
+	score: number;
+	matches: SearchMatch[];
+}
+
+// Cancelled operations fail with this message; see cancelOperation
+const CANCELLED_MESSAGE = "Cancelled";
+
+export function isCancelled(error: unknown) {
+	return getErrorMessage(error) === CANCELLED_MESSAGE;
+}
+
+// Syncing fails with this message if it would merge in the entries of a different account; see confirmAccountChange
+const ACCOUNT_CHANGED_MESSAGE = "AccountChanged";
+
+export function isAccountChanged(error: unknown) {
+	return getErrorMessage(error) === ACCOUNT_CHANGED_MESSAGE;
+}
+
 export function getErrorMessage(error: unknown) {
 	if (error instanceof Error) {
 		return error.message;
@@ -29,6 +55,58 @@ export function getErrorMessage(error: unknown) {
 	return String(error);
 }
 
+// Reported while keys are derived from a password (createDatabase, changePassword), which can take minutes.  The time it
+// will take is estimated, so fraction stays just below 1 if it takes longer.
+export interface DeriveProgress {
+	fraction: number;
+	elapsed_ms: number;
+	remaining_ms: number;
+}
+
+// Reported by syncDatabase for each object it compares with the server's copy
+export interface SyncProgress {
+	round: number;
+	phase: "downloading" | "uploading";
+	done: number;
+	total: number;
+}
+
+// Runs a command, passing the progress events it sends to onProgress
+async function withProgress<T, P>(
+	event: string,
+	onProgress: ((progress: P) => void) | undefined,
+	command: () => Promise<T>
+): Promise<T> {
+	if (onProgress === undefined) {
+		return await command();
+	}
+
+	const unlisten = await listen<P>(event, (e) => onProgress(e.payload));
+	try {
+		return await command();
+	} finally {
+		unlisten();
+	}
+}
+
+export function describeDeriveProgress(progress: DeriveProgress) {
+	const percent = Math.floor(progress.fraction * 100);
+	const remaining = Math.ceil(progress.remaining_ms / 1000);
+
+	if (remaining <= 0) {
+		return `Deriving keys... ${percent}%, almost done`;
+	} else if (remaining < 60) {
+		return `Deriving keys... ${percent}%, about ${remaining} s left`;
+	} else {
+		return `Deriving keys... ${percent}%, about ${Math.ceil(remaining / 60)} min left`;
+	}
+}
+
+export function describeSyncProgress(progress: SyncProgress) {
+	const phase = progress.phase == "downloading" ? "Checking" : "Uploading";
+	return `${phase} ${progress.done} of ${progress.total}`;
+}
+
 export async function showErrorDialog(message: string) {
 	await invoke("error_dialog", { message });
 }
@@ -41,42 +119,211 @@ export async function listEntries(): Promise<Entry[]> {
 	return await invoke("list_entries");
 }
 
+export async function searchEntries(query: string): Promise<SearchHit[]> {
+	return await invoke("search_entries", { query });
+}
+
+// IDs of the entries with credentials for the URL, best matches first
+export async function findEntriesForUrl(url: string): Promise<string[]> {
+	return await invoke("find_entries_for_url", { url });
+}
+
+// The entry's fields, with derived fields such as TOTP codes computed for the current time
+export async function getResolvedFields(entryId: string): Promise<Record<string, string>> {
+	return await invoke("get_resolved_fields", { entryId });
+}
+
+// Call when the user opens an entry or copies one of its fields
+export async function recordEntryAccess(entryId: string): Promise<void> {
+	await invoke("record_entry_access", { entryId });
+}
+
+// IDs of recently accessed entries, most recent first
+export async function listRecentEntries(): Promise<string[]> {
+	return await invoke("list_recent_entries");
+}
+
+export type SelectionFormat = "markdown" | "csv" | "json";
+
+// The given fields of the entries as text, leaving out protected fields such as passwords
+export async function exportSelection(entryIds: string[], fields: string[], format: SelectionFormat): Promise<string> {
+	return await invoke("export_selection", { entryIds, fields, format });
+}
+
+// Other users need this key to share entries with this database
+export async function getSharePublicKey(): Promise<string> {
+	return await invoke("get_share_public_key");
+}
+
+// Writes the entries, encrypted so only the user whose share key is recipient can import them, to path
+export async function exportShare(entryIds: string[], recipient: string, path: string): Promise<void> {
+	await invoke("export_share", { entryIds, recipient, path });
+}
+
+// Resolves to the IDs of the entries that were added or updated
+export async function importShare(path: string): Promise<string[]> {
+	return await invoke("import_share", { path });
+}
+
 export async function renameDirectory(directory_id: string, new_name: string): Promise<void> {
 	await invoke("rename_directory", { directoryId: directory_id, newName: new_name });
 }
 
+// Local-only directories keep their contents off the sync server
+export async function setDirectoryLocalOnly(directory_id: string, local_only: boolean): Promise<void> {
+	await invoke("set_directory_local_only", { directoryId: directory_id, localOnly: local_only });
+}
+
 export async function moveObject(id: string, new_parent: string): Promise<void> {
 	await invoke("move_object", { objectId: id, newParentId: new_parent });
 }
 
+// Writes a copy of the database that opens with exportPassword instead, after the user confirms it in a dialog.  Resolves to
+// false if they cancelled.
+export async function exportDatabase(path: string, exportPassword: string): Promise<boolean> {
+	return await invoke("export_database", { path, exportPassword });
+}
+
+// Deleted entries and directories go to the trash, and are left out of listEntries, listDirectories, and searches
+export async function deleteEntry(id: string): Promise<void> {
+	await invoke("delete_entry", { entryId: id });
+}
+
+// Deletes everything in the directory too
+export async function deleteDirectory(id: string): Promise<void> {
+	await invoke("delete_directory", { directoryId: id });
+}
+
+export interface TrashContents {
+	// What's directly in the trash, most recently deleted first
+	objects: { id: string; time: number }[];
+	// Everything in the trash, including what's in deleted directories
+	entries: Entry[];
+	directories: Directory[];
+}
+
+export async function listTrash(): Promise<TrashContents> {
+	return await invoke("list_trash");
+}
+
+// Moves something that's directly in the trash back to where it was deleted from
+export async function restore(id: string): Promise<void> {
+	await invoke("restore", { objectId: id });
+}
+
 export async function newDirectory(name: string): Promise<void> {
 	await invoke("new_directory", { name });
 }
 
-export async function randomString(
-	length: number,
-	uppercase: boolean,
-	lowercase: boolean,
-	numbers: boolean,
-	others: string
-): Promise<string> {
-	return await invoke("random_string", { length, uppercase, lowercase, numbers, others });
+export interface GeneratorOptions {
+	length: number;
+	uppercase: boolean;
+	lowercase: boolean;
+	numbers: boolean;
+	others: string;
+	exclude_ambiguous: boolean;
+	require_each_selected_class: boolean;
+	no_repeated_characters: boolean;
+}
+
+export const DEFAULT_GENERATOR_OPTIONS: GeneratorOptions = {
+	length: 20,
+	uppercase: true,
+	lowercase: true,
+	numbers: true,
+	others: "",
+	exclude_ambiguous: false,
+	require_each_selected_class: false,
+	no_repeated_characters: false,
+};
+
+// Fails if no password can meet the options
+export async function generatePassword(options: GeneratorOptions): Promise<string> {
+	return await invoke("generate_password", { options });
 }
 
+// Generated passwords with fewer bits of entropy than this are worth warning about (libfortress's WEAK_ENTROPY_BITS)
+export const WEAK_ENTROPY_BITS = 64;
+
+// Bits of entropy in passwords generated with the options; fails like generatePassword
+export async function estimatePasswordEntropy(options: GeneratorOptions): Promise<number> {
+	return await invoke("estimate_password_entropy", { options });
+}
+
+// Notes are entries with entry_type set to this, their text in notes, and note_format "markdown" or "" (libfortress's
+// secure_note module)
+export const NOTE_ENTRY_TYPE = "note";
+
 export async function editEntry(entryId: string | null, data: Record<string, string>, parentId: string): Promise<void> {
 	await invoke("edit_entry", { entryId, data, parentId });
 }
 
+// Every edit made to the entry, oldest first
+// Creates an entry for each of entries in parentId, saving once, and resolves to their IDs.  For bulk imports, which would be
+// slow with one editEntry per entry.
+export async function createEntries(entries: Record<string, string>[], parentId: string): Promise<string[]> {
+	return await invoke("create_entries", { entries, parentId });
+}
+
+export async function getEntryHistory(entryId: string): Promise<EntryHistory[]> {
+	return await invoke("get_entry_history", { entryId });
+}
+
+// Sets field back to the value it had at toTime (the time of one of its edits), as a new edit
+export async function revertEntryField(entryId: string, field: string, toTime: number): Promise<void> {
+	await invoke("revert_entry_field", { entryId, field, toTime });
+}
+
 export async function unlockDatabase(password: string): Promise<void> {
 	await invoke("unlock_database", { password });
 }
 
+// Resolves to false if the database can't be unlocked with the OS keychain, and the password has to be used
+export async function unlockWithKeychain(): Promise<boolean> {
+	return await invoke("unlock_with_keychain");
+}
+
+// Keeps the current database's keys in the OS keychain so unlockWithKeychain works, or removes them
+export async function setKeychainUnlock(enabled: boolean): Promise<void> {
+	await invoke("set_keychain_unlock", { enabled });
+}
+
+// Makes the current database need its YubiKey as well as the password to open, or only the password.  password must be the
+// current password.
+export async function setHardwareKey(password: string, enabled: boolean): Promise<void> {
+	await invoke("set_hardware_key", { password, enabled });
+}
+
 export async function databaseExists(): Promise<boolean> {
 	return await invoke("database_exists");
 }
 
-export async function createDatabase(username: string, password: string): Promise<void> {
-	await invoke("create_database", { username, password });
+// The database the other functions work on
+export async function getDatabasePath(): Promise<string> {
+	return await invoke("get_database_path");
+}
+
+// Locks the current database and switches to the one at path, which is created by createDatabase if it doesn't exist
+export async function openDatabaseAt(path: string): Promise<void> {
+	await invoke("open_database_at", { path });
+}
+
+// Most recently unlocked or created first
+export async function listRecentDatabases(): Promise<string[]> {
+	return await invoke("list_recent_databases");
+}
+
+// Copies entries into the root directory of another database, merging any the other database already has
+export async function copyEntriesToDatabase(path: string, password: string, entryIds: string[]): Promise<void> {
+	await invoke("copy_entries_to_database", { path, password, entryIds });
+}
+
+export async function createDatabase(
+	username: string,
+	password: string,
+	onProgress?: (progress: DeriveProgress) => void
+): Promise<void> {
+	await withProgress("derive-progress", onProgress, () => invoke("create_database", { username, password }));
 }
 
 export async function getUsername(): Promise<string> {
@@ -87,6 +334,10 @@ export async function getSyncKeys(): Promise<string> {
 	return await invoke("get_sync_keys");
 }
 
+export async function getReadonlySyncKeys(): Promise<string> {
+	return await invoke("get_readonly_sync_keys");
+}
+
 export async function getSyncUrl(): Promise<string> {
 	return await invoke("get_sync_url");
 }
@@ -96,10 +347,228 @@ export async function setSyncUrl(url: string): Promise<void> {
 	await invoke("set_sync_url", { url });
 }
 
-export async function changePassword(username: string, password: string): Promise<void> {
-	await invoke("change_password", { username, password });
+// SOCKS5 proxy sync connects through (e.g. socks5://127.0.0.1:9050 for Tor), or null for none
+export async function getSyncProxy(): Promise<string | null> {
+	return await invoke("get_sync_proxy");
+}
+
+// An empty proxy means connecting directly
+export async function setSyncProxy(proxy: string): Promise<void> {
+	await invoke("set_sync_proxy", { proxy });
+}
+
+export async function changePassword(
+	username: string,
+	password: string,
+	onProgress?: (progress: DeriveProgress) => void
+): Promise<void> {
+	await withProgress("derive-progress", onProgress, () => invoke("change_password", { username, password }));
+}
+
+// Settings synced along with the database
+export async function getSettings(): Promise<Record<string, unknown>> {
+	return await invoke("get_settings");
+}
+
+// Setting a value to null removes it
+export async function setSetting(key: string, value: unknown): Promise<void> {
+	await invoke("set_setting", { key, value });
+}
+
+// Settings kept on this device, next to the database, which can be read before it's unlocked (libfortress's LocalSettings).
+// Keys other than these are the frontend's own.
+export interface LocalSettings {
+	version: number;
+	window_size?: { width: number; height: number };
+	generator?: GeneratorOptions;
+	[key: string]: unknown;
+}
+
+export async function getLocalSettings(): Promise<LocalSettings> {
+	return await invoke("get_local_settings");
+}
+
+export async function setLocalSettings(settings: LocalSettings): Promise<void> {
+	await invoke("set_local_settings", { settings });
+}
+
+export type SyncConflict = { type: "directory_rename"; directory: string; kept: string; discarded: string };
+
+export interface SyncResult {
+	// A short summary of what changed, e.g. "1 entry updated from server"
+	summary: string;
+	conflicts: SyncConflict[];
+	// Bytes transferred, not counting HTTP headers
+	bytes_uploaded: number;
+	bytes_downloaded: number;
+	// Encrypted size of each object transferred, by ID
+	uploaded_sizes: Record<string, number>;
+	downloaded_sizes: Record<string, number>;
+	// Objects in local-only directories that the server has anyway, e.g. from before they were made local-only
+	local_only_on_server: string[];
+}
+
+export async function syncDatabase(onProgress?: (progress: SyncProgress) => void): Promise<SyncResult> {
+	return await withProgress("sync-progress", onProgress, () => invoke<SyncResult>("sync_database"));
+}
+
+// True if something else (such as Dropbox or Syncthing) changed the database file since it was last loaded or saved
+export async function databaseChangedOnDisk(): Promise<boolean> {
+	return await invoke("database_changed_on_disk");
+}
+
+// Merges the changed database file into the open database and saves the result
+export async function reloadDatabase(password: string): Promise<SyncConflict[]> {
+	return await invoke("reload_database", { password });
+}
+
+// What syncDatabase would do, without changing anything
+export async function previewSync(): Promise<SyncResult> {
+	return await invoke("preview_sync");
+}
+
+export interface SyncSizeEstimate {
+	// Bytes the next sync would upload; an overestimate if the server's copies changed
+	upload_bytes: number;
+	uploads: number;
+	downloads: number;
+	listing_bytes: number;
+}
+
+// Predicts what the next sync would transfer, fetching only the server's object list
+export async function estimateSyncSize(): Promise<SyncSizeEstimate> {
+	return await invoke("estimate_sync_size");
+}
+
+// Damage to the directory tree, such as entries that aren't in any directory and so can't be shown
+export interface IntegrityReport {
+	missing_root: boolean;
+	orphans: string[];
+	missing_references: { directory: string; missing: string }[];
+	duplicate_references: { object: string; directories: string[] }[];
+	future_history: { object: string; time: number }[];
+}
+
+export async function checkIntegrity(): Promise<IntegrityReport> {
+	return await invoke("check_integrity");
+}
+
+// Repairs what checkIntegrity finds and saves the database, returning what was found
+export async function repairDatabase(): Promise<IntegrityReport> {
+	return await invoke("repair_database");
+}
+
+// Times and ages are in nanoseconds
+export interface DatabaseStats {
+	entries: number;
+	directories: number;
+	history_records: number;
+	oldest_edit: number | null;
+	newest_edit: number | null;
+	payload_size: number;
+	// Number of entries directly in each directory, by directory ID
+	entries_per_directory: Record<string, number>;
+	average_password_age: number | null;
+}
+
+export async function getDatabaseStats(): Promise<DatabaseStats> {
+	return await invoke("get_database_stats");
+}
+
+export interface SyncConnectionResult {
+	latency_ms: number;
+	// False if the server rejected our credentials
+	authenticated: boolean;
+}
+
+// Checks that the sync server at url is reachable and accepts our credentials, without syncing
+export async function testSyncConnection(url: string): Promise<SyncConnectionResult> {
+	return await invoke("test_sync_connection", { url });
+}
+
+// Lets the next sync merge in the entries of the account the database was switched to.  Only call this once the user has
+// confirmed it, since the merge can't be undone.
+export async function confirmAccountChange(): Promise<void> {
+	await invoke("confirm_account_change");
+}
+
+export interface SyncAccountInfo {
+	object_count: number;
+	// Bytes stored on the server
+	storage_used: number;
+	// Null if the server doesn't limit it
+	max_objects: number | null;
+	max_storage: number | null;
+}
+
+// Creates an account on the sync server for the database's username and password
+export async function registerSyncAccount(): Promise<void> {
+	await invoke("register_sync_account");
+}
+
+export async function getSyncAccountInfo(): Promise<SyncAccountInfo> {
+	return await invoke("get_sync_account_info");
+}
+
+// Deletes the account on the sync server and everything stored in it; the local database is kept.  Only call this once the
+// user has confirmed it.
+export async function deleteSyncAccount(): Promise<void> {
+	await invoke("delete_sync_account");
+}
+
+// Cancels the create, unlock, password change, or sync in progress
+export async function cancelOperation(): Promise<void> {
+	await invoke("cancel_operation");
+}
+
+// Drops the unlocked database, so it has to be unlocked again
+export async function lockDatabase(): Promise<void> {
+	await invoke("lock_database");
+}
+
+// Call on user input that doesn't otherwise reach the backend, so the database isn't auto-locked while it's being used
+export async function recordActivity(): Promise<void> {
+	await invoke("record_activity");
+}
+
+// 0 means auto-lock is off
+export async function getAutolockMinutes(): Promise<number> {
+	return await invoke("get_autolock_minutes");
+}
+
+export async function setAutolockMinutes(minutes: number): Promise<void> {
+	await invoke("set_autolock_minutes", { minutes });
+}
+
+export async function getBackupCount(): Promise<number> {
+	return await invoke("get_backup_count");
+}
+
+export async function setBackupCount(count: number): Promise<void> {
+	await invoke("set_backup_count", { count });
+}
+
+// Copies an entry's field to the clipboard from the backend, so secrets don't pass through the frontend.  It's cleared again
+// after getClipboardClearSeconds, and onClipboardCleared is called.
+export async function copySecret(entryId: string, field: string): Promise<void> {
+	await invoke("copy_secret", { entryId, field });
+}
+
+// 0 means copied secrets aren't cleared
+export async function getClipboardClearSeconds(): Promise<number> {
+	return await invoke("get_clipboard_clear_seconds");
+}
+
+export async function setClipboardClearSeconds(seconds: number): Promise<void> {
+	await invoke("set_clipboard_clear_seconds", { seconds });
+}
+
+// Returns a function that stops listening
+export async function onClipboardCleared(onCleared: () => void): Promise<() => void> {
+	return await listen("clipboard-cleared", () => onCleared());
 }
 
-export async function syncDatabase(): Promise<void> {
-	await invoke("sync_database");
+// Calls onLocked whenever the database is locked, by lockDatabase or after being idle.  Returns a function that stops listening.
+export async function onDatabaseLocked(onLocked: () => void): Promise<() => void> {
+	return await listen("database-locked", () => onLocked());
 }
@@ -25,6 +25,8 @@ export const selectedDirectoryState = atom<string | DirectoryItemVariant.All>({
 export interface Directory {
 	id: string;
 	name: string | null;
+	// Kept off the sync server, along with everything in it
+	localOnly: boolean;
 	history: ffi.DirectoryHistory[];
 	children: string[];
 }
@@ -39,6 +41,7 @@ export interface Entry {
 export async function refreshDatabase(setDatabase: (state: DatabaseState) => void) {
 	const directories = (await ffi.listDirectories()).map((directory) => {
 		let name = null;
+		let localOnly = false;
 		let children: string[] = [];
 
 		for (const history of directory.history) {
@@ -49,12 +52,15 @@ export async function refreshDatabase(setDatabase: (state: DatabaseState) => voi
 			} else if ("Remove" in history.action) {
 				const id = history.action.Remove;
 				children = children.filter((child: string) => child !== id);
+			} else if ("SetLocalOnly" in history.action) {
+				localOnly = history.action.SetLocalOnly;
 			}
 		}
 
 		return {
 			id: directory.id,
 			name,
+			localOnly,
 			history: directory.history,
 			children,
 		};